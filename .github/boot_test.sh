@@ -0,0 +1,22 @@
+#! /bin/bash
+# Run `boot-test` with the CI BIOS, or generate the golden hashes if
+# `expected.toml` doesn't have them yet, to be committed from the artifact
+
+set -e
+
+EXPECTED=./tools/boot-test/expected.toml
+OUTPUT=./boot-test-expected.toml
+
+BIOS=$(find ./test_roms -iname '*.bin' -size 512k | head -n 1)
+if [ -z "$BIOS" ]; then
+    echo "no BIOS found in test_roms"
+    exit 1
+fi
+
+if grep -q '^\[titles\.bios\]' "$EXPECTED"; then
+    cargo run -p boot-test --release -- --bios "$BIOS"
+else
+    cp "$EXPECTED" "$OUTPUT"
+    cargo run -p boot-test --release -- --bios "$BIOS" --expected "$OUTPUT" --regenerate
+    echo "::warning::$EXPECTED has no golden hashes, commit $OUTPUT from the boot-test artifact"
+fi
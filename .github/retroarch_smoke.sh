@@ -0,0 +1,31 @@
+#! /bin/bash
+# Load the libretro core in RetroArch with the null drivers, and run an EXE for
+# a few frames, fails if the core or the content doesn't load
+
+set -e
+set -o pipefail
+
+CORE=./target/debug/libtrapezoid_libretro.so
+SYSTEM_DIR=$(mktemp -d)
+
+# the core looks for the BIOS by file name, any of the extracted ones works
+BIOS=$(find ./test_roms -iname '*.bin' -size 512k | head -n 1)
+if [ -z "$BIOS" ]; then
+    echo "no BIOS found in test_roms"
+    exit 1
+fi
+cp "$BIOS" "$SYSTEM_DIR/scph1001.bin"
+
+cat > "$SYSTEM_DIR/retroarch.cfg" << EOF
+video_driver = "null"
+audio_driver = "null"
+input_driver = "null"
+system_directory = "$SYSTEM_DIR"
+EOF
+
+timeout 300 retroarch --verbose --max-frames=600 --config="$SYSTEM_DIR/retroarch.cfg" \
+    -L "$CORE" ./tools/disasm/tests/data/hello.exe 2>&1 | tee retroarch.log
+
+if grep -q -e "Failed to load content" -e "Failed to open libretro core" -e "No BIOS found" retroarch.log; then
+    exit 1
+fi
@@ -0,0 +1,370 @@
+use std::{
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+
+use crate::audio_output::AudioOutput;
+
+pub const SAMPLE_RATE: u32 = 44100;
+/// Must match the `BufferSize` given to the player
+pub const BUFFER_SECONDS: f64 = 0.25;
+/// How often to check if the device was disconnected, and try to open it again
+const DEVICE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The maximum speed/pitch change applied to keep the buffer filled
+const MAX_RATE_DEVIATION: f64 = 0.005;
+/// The buffer fill we try to stay around, leaves room for jitter on both sides
+const TARGET_FILL: f64 = 0.5;
+/// How fast the rate follows the buffer fill changes, lower is smoother
+const RATE_SMOOTHING: f64 = 0.05;
+
+/// How the emulation is kept in sync with the audio output
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncMode {
+    /// Speed up or slow down the emulation slightly to keep the audio buffer filled
+    Audio,
+    /// Run at exactly 60 FPS, and resample the audio slightly instead
+    Video,
+    /// Run at 60 FPS without any adjustments
+    Off,
+}
+
+/// Estimates how much of the audio player buffer is filled, by counting what we queue
+/// and what the device should have consumed since then
+struct AudioBufferFill {
+    /// in samples (both channels)
+    capacity: f64,
+    queued: f64,
+    last_update: Instant,
+}
+
+impl AudioBufferFill {
+    fn new(capacity_secs: f64) -> Self {
+        Self {
+            capacity: capacity_secs * SAMPLE_RATE as f64 * 2.,
+            queued: 0.,
+            last_update: Instant::now(),
+        }
+    }
+
+    fn drain(&mut self) {
+        let now = Instant::now();
+        let consumed = now.duration_since(self.last_update).as_secs_f64() * SAMPLE_RATE as f64 * 2.;
+        self.last_update = now;
+        self.queued = (self.queued - consumed).max(0.);
+    }
+
+    fn queue(&mut self, samples: usize) {
+        self.drain();
+        // the player drops what doesn't fit
+        self.queued = (self.queued + samples as f64).min(self.capacity);
+    }
+
+    /// The fill ratio in `0.0..=1.0`
+    fn fill(&mut self) -> f64 {
+        self.drain();
+        self.queued / self.capacity
+    }
+}
+
+/// Dynamic rate control, computes the rate to produce samples at, relative
+/// to the nominal rate, so that the buffer stays around [`TARGET_FILL`]
+struct RateControl {
+    rate: f64,
+}
+
+impl RateControl {
+    fn new() -> Self {
+        Self { rate: 1. }
+    }
+
+    fn update(&mut self, fill: f64) -> f64 {
+        // produce more when the buffer is draining, and less when it's filling up
+        let error = ((TARGET_FILL - fill) / TARGET_FILL).clamp(-1., 1.);
+        let target_rate = 1. + error * MAX_RATE_DEVIATION;
+        self.rate += (target_rate - self.rate) * RATE_SMOOTHING;
+        self.rate
+    }
+}
+
+/// Linear interpolation resampler for interleaved stereo buffers,
+/// keeps its position between buffers so there are no clicks
+pub struct Resampler {
+    /// Position of the next output frame, `0.0` is the last frame of the previous buffer
+    position: f64,
+    last_frame: [f32; 2],
+}
+
+impl Resampler {
+    pub fn new() -> Self {
+        Self {
+            position: 0.,
+            last_frame: [0.; 2],
+        }
+    }
+
+    /// Produce `ratio` output frames for every input frame
+    pub fn process(&mut self, input: &[f32], ratio: f64) -> Vec<f32> {
+        let frames = input.len() / 2;
+        if frames == 0 {
+            return Vec::new();
+        }
+        let frame = |i: usize| -> [f32; 2] {
+            if i == 0 {
+                self.last_frame
+            } else {
+                [input[(i - 1) * 2], input[(i - 1) * 2 + 1]]
+            }
+        };
+
+        let step = 1. / ratio;
+        let mut output = Vec::with_capacity(((frames as f64 * ratio) as usize + 1) * 2);
+        while self.position < frames as f64 {
+            let i = self.position as usize;
+            let fraction = (self.position - i as f64) as f32;
+            let [l0, r0] = frame(i);
+            let [l1, r1] = frame(i + 1);
+            output.push(l0 + (l1 - l0) * fraction);
+            output.push(r0 + (r1 - r0) * fraction);
+            self.position += step;
+        }
+        self.position -= frames as f64;
+        let last_frame = frame(frames);
+        self.last_frame = last_frame;
+
+        output
+    }
+}
+
+/// Audio output state, shared between the audio thread, the emulation thread and the OSD
+pub struct AudioSync {
+    mode: SyncMode,
+    fill: AudioBufferFill,
+    rate_control: RateControl,
+    /// The player is paused, and the emulation thread drops the audio
+    muted: bool,
+    /// The emulation is paused, the player is paused once the queued audio has played
+    paused: bool,
+}
+
+impl AudioSync {
+    fn new(mode: SyncMode) -> Self {
+        Self {
+            mode,
+            fill: AudioBufferFill::new(BUFFER_SECONDS),
+            rate_control: RateControl::new(),
+            muted: false,
+            paused: false,
+        }
+    }
+
+    pub fn muted(&self) -> bool {
+        self.muted
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// The estimated fill of the audio buffer in `0.0..=1.0`
+    pub fn fill(&mut self) -> f64 {
+        self.fill.fill()
+    }
+
+    /// The current adjustment applied to the emulation speed or the audio,
+    /// `0.001` means 0.1% faster
+    pub fn rate_adjustment(&self) -> f64 {
+        self.rate_control.rate - 1.
+    }
+
+    /// The emulation speed relative to the video frame rate, should be called once per frame
+    pub fn emulation_speed(&mut self) -> f64 {
+        // the buffer is not filled when muted
+        if self.mode == SyncMode::Audio && !self.muted {
+            let fill = self.fill.fill();
+            self.rate_control.update(fill)
+        } else {
+            1.
+        }
+    }
+
+    /// The resampling ratio for the next buffer, should be called once per buffer
+    fn resample_ratio(&mut self) -> f64 {
+        if self.mode == SyncMode::Video {
+            let fill = self.fill.fill();
+            self.rate_control.update(fill)
+        } else {
+            1.
+        }
+    }
+}
+
+/// Play the audio buffers received from the emulation thread on a separate thread,
+/// so that audio keeps playing even if the event loop is blocked
+///
+/// The buffer fill is estimated from what was queued and the nominal sample rate,
+/// so it doesn't account for the drift of the device clock.
+///
+/// `device` is the name of the output device, or `None` for the default one. If the
+/// device is disconnected, it's opened again when it comes back.
+pub fn spawn_audio_thread(
+    mode: SyncMode,
+    device: Option<String>,
+) -> Option<(mpsc::Sender<Vec<f32>>, Arc<Mutex<AudioSync>>)> {
+    let (sender, receiver) = mpsc::channel::<Vec<f32>>();
+    let (init_sender, init_receiver) = mpsc::sync_channel(1);
+    let sync = Arc::new(Mutex::new(AudioSync::new(mode)));
+    let thread_sync = sync.clone();
+
+    thread::Builder::new()
+        .name("audio".to_string())
+        .spawn(move || {
+            let open = || -> Result<AudioOutput, String> {
+                let output = AudioOutput::open(device.as_deref())?;
+                output.play()?;
+                Ok(output)
+            };
+
+            // the output is created here, since it may not be `Send` on all platforms
+            let mut output = match open() {
+                Ok(output) => {
+                    init_sender.send(true).unwrap();
+                    Some(output)
+                }
+                Err(e) => {
+                    log::error!("Failed to initialize audio player: {}", e);
+                    init_sender.send(false).unwrap();
+                    return;
+                }
+            };
+
+            let mut resampler = Resampler::new();
+            let mut paused = false;
+            let mut last_device_check = Instant::now();
+            loop {
+                let buffer = match receiver.recv_timeout(DEVICE_CHECK_INTERVAL) {
+                    Ok(buffer) => Some(buffer),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+
+                if last_device_check.elapsed() >= DEVICE_CHECK_INTERVAL {
+                    last_device_check = Instant::now();
+                    let lost = match &output {
+                        Some(output) => output.is_lost(),
+                        None => true,
+                    };
+                    if lost {
+                        if output.take().is_some() {
+                            log::warn!("Audio device disconnected, trying to reconnect");
+                        }
+                        match open() {
+                            Ok(new_output) => {
+                                log::info!("Audio device reconnected");
+                                output = Some(new_output);
+                                paused = false;
+                            }
+                            Err(e) => log::debug!("Failed to reconnect the audio device: {}", e),
+                        }
+                    }
+                }
+
+                let Some(output) = &mut output else {
+                    continue;
+                };
+
+                let should_pause = {
+                    let mut sync = thread_sync.lock().unwrap();
+                    // when the emulation is paused, the queued audio plays out first,
+                    // so the sound isn't cut in the middle and doesn't pop on resume
+                    sync.muted || (sync.paused && buffer.is_none() && sync.fill.fill() == 0.)
+                };
+                if should_pause != paused {
+                    paused = should_pause;
+                    let result = if should_pause {
+                        output.pause()
+                    } else {
+                        output.play()
+                    };
+                    if let Err(e) = result {
+                        log::error!("Failed to pause/play the audio: {}", e);
+                    }
+                }
+
+                if let Some(buffer) = buffer {
+                    let ratio = thread_sync.lock().unwrap().resample_ratio();
+                    let buffer = if mode == SyncMode::Video {
+                        resampler.process(&buffer, ratio)
+                    } else {
+                        buffer
+                    };
+                    output.queue(&buffer);
+                    thread_sync.lock().unwrap().fill.queue(buffer.len());
+                }
+            }
+        })
+        .expect("failed to spawn the audio thread");
+
+    init_receiver
+        .recv()
+        .unwrap_or(false)
+        .then_some((sender, sync))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_control_direction() {
+        let mut rate = RateControl::new();
+        for _ in 0..1000 {
+            rate.update(0.);
+        }
+        assert!((rate.rate - (1. + MAX_RATE_DEVIATION)).abs() < 1e-6);
+
+        let mut rate = RateControl::new();
+        for _ in 0..1000 {
+            rate.update(1.);
+        }
+        assert!((rate.rate - (1. - MAX_RATE_DEVIATION)).abs() < 1e-6);
+
+        let mut rate = RateControl::new();
+        assert_eq!(rate.update(TARGET_FILL), 1.);
+    }
+
+    #[test]
+    fn resampler_identity() {
+        let mut resampler = Resampler::new();
+        let input = [1., -1., 2., -2., 3., -3.];
+        // delayed by one frame
+        assert_eq!(resampler.process(&input, 1.), [0., 0., 1., -1., 2., -2.]);
+        assert_eq!(resampler.process(&[4., -4.], 1.), [3., -3.]);
+    }
+
+    #[test]
+    fn resampler_ratio() {
+        let mut resampler = Resampler::new();
+        let input = vec![0.5; 735 * 2];
+
+        let mut total = 0;
+        for _ in 0..100 {
+            total += resampler.process(&input, 1.005).len() / 2;
+        }
+        // the position is kept between buffers, so the count is exact over time
+        assert!((total as i64 - 73867).abs() <= 1);
+
+        let output = resampler.process(&input, 0.995);
+        assert!(output.iter().all(|&s| s == 0.5));
+    }
+}
@@ -0,0 +1,200 @@
+//! The audio devices, the default device is played with `dynwave` (which resamples
+//! to the device rate), devices chosen by name are opened with `cpal` directly.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use dynwave::{AudioPlayer, BufferSize};
+
+use crate::audio::{Resampler, BUFFER_SECONDS, SAMPLE_RATE};
+
+/// The names of the output devices, for `--audio-device`
+pub fn list_devices() -> Vec<String> {
+    match cpal::default_host().output_devices() {
+        Ok(devices) => devices.filter_map(|d| d.name().ok()).collect(),
+        Err(e) => {
+            log::error!("Failed to list the audio devices: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+fn default_device_name() -> Option<String> {
+    cpal::default_host()
+        .default_output_device()
+        .and_then(|d| d.name().ok())
+}
+
+pub enum AudioOutput {
+    /// `name` is used to notice when the default device changes or disappears
+    Default {
+        player: AudioPlayer<f32>,
+        name: Option<String>,
+    },
+    Device(DeviceOutput),
+}
+
+impl AudioOutput {
+    /// Open the device called `device`, or the default device
+    pub fn open(device: Option<&str>) -> Result<Self, String> {
+        match device {
+            None => {
+                let player = AudioPlayer::<f32>::new(SAMPLE_RATE, BufferSize::QuarterSecond)
+                    .map_err(|e| format!("{:?}", e))?;
+                Ok(Self::Default {
+                    player,
+                    name: default_device_name(),
+                })
+            }
+            Some(name) => DeviceOutput::open(name).map(Self::Device),
+        }
+    }
+
+    pub fn play(&self) -> Result<(), String> {
+        match self {
+            Self::Default { player, .. } => player.play().map_err(|e| format!("{:?}", e)),
+            Self::Device(output) => output.stream.play().map_err(|e| e.to_string()),
+        }
+    }
+
+    pub fn pause(&self) -> Result<(), String> {
+        match self {
+            Self::Default { player, .. } => player.pause().map_err(|e| format!("{:?}", e)),
+            Self::Device(output) => output.stream.pause().map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Queue interleaved stereo samples at [`SAMPLE_RATE`], what doesn't fit is dropped
+    pub fn queue(&mut self, samples: &[f32]) {
+        match self {
+            Self::Default { player, .. } => player.queue(samples),
+            Self::Device(output) => output.queue(samples),
+        }
+    }
+
+    /// `true` if the device is gone, and the output should be opened again
+    pub fn is_lost(&self) -> bool {
+        match self {
+            Self::Default { name, .. } => default_device_name() != *name,
+            Self::Device(output) => output.lost.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A device playing `f32` samples with `cpal`, the samples are resampled
+/// if the device doesn't support [`SAMPLE_RATE`]
+pub struct DeviceOutput {
+    stream: cpal::Stream,
+    /// Interleaved stereo samples at `sample_rate`
+    buffer: Arc<Mutex<VecDeque<f32>>>,
+    capacity: usize,
+    sample_rate: u32,
+    resampler: Resampler,
+    /// Set from the stream error callback
+    lost: Arc<AtomicBool>,
+}
+
+impl DeviceOutput {
+    fn open(name: &str) -> Result<Self, String> {
+        let device = cpal::default_host()
+            .output_devices()
+            .map_err(|e| e.to_string())?
+            .find(|d| d.name().is_ok_and(|n| n == name))
+            .ok_or_else(|| {
+                format!(
+                    "No audio device named {:?}, see `--list-audio-devices`",
+                    name
+                )
+            })?;
+
+        // prefer configs that don't need resampling, and stereo ones
+        let range = device
+            .supported_output_configs()
+            .map_err(|e| e.to_string())?
+            .filter(|c| c.sample_format() == cpal::SampleFormat::F32 && c.channels() > 0)
+            .max_by_key(|c| {
+                (
+                    (c.min_sample_rate().0..=c.max_sample_rate().0).contains(&SAMPLE_RATE),
+                    c.channels() >= 2,
+                )
+            })
+            .ok_or_else(|| format!("The audio device {:?} doesn't support f32 samples", name))?;
+        let sample_rate = SAMPLE_RATE.clamp(range.min_sample_rate().0, range.max_sample_rate().0);
+        let config = range
+            .with_sample_rate(cpal::SampleRate(sample_rate))
+            .config();
+        let channels = config.channels as usize;
+        log::info!(
+            "Audio device {:?}: {}Hz, {} channels",
+            name,
+            sample_rate,
+            channels
+        );
+
+        let capacity = (sample_rate as f64 * BUFFER_SECONDS) as usize * 2;
+        let buffer = Arc::new(Mutex::new(VecDeque::with_capacity(capacity)));
+        let lost = Arc::new(AtomicBool::new(false));
+
+        let stream_buffer = buffer.clone();
+        let stream_lost = lost.clone();
+        let stream = device
+            .build_output_stream(
+                &config,
+                move |data: &mut [f32], _| {
+                    let mut buffer = stream_buffer.lock().unwrap();
+                    for frame in data.chunks_mut(channels) {
+                        // silence if we are late
+                        let left = buffer.pop_front().unwrap_or(0.);
+                        let right = buffer.pop_front().unwrap_or(0.);
+                        if channels == 1 {
+                            frame[0] = (left + right) / 2.;
+                        } else {
+                            frame[0] = left;
+                            frame[1] = right;
+                            frame[2..].fill(0.);
+                        }
+                    }
+                },
+                move |e| {
+                    log::error!("Audio device error: {}", e);
+                    if matches!(e, cpal::StreamError::DeviceNotAvailable) {
+                        stream_lost.store(true, Ordering::Relaxed);
+                    }
+                },
+                None,
+            )
+            .map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            stream,
+            buffer,
+            capacity,
+            sample_rate,
+            resampler: Resampler::new(),
+            lost,
+        })
+    }
+
+    fn queue(&mut self, samples: &[f32]) {
+        let resampled;
+        let samples = if self.sample_rate == SAMPLE_RATE {
+            samples
+        } else {
+            resampled = self
+                .resampler
+                .process(samples, self.sample_rate as f64 / SAMPLE_RATE as f64);
+            &resampled
+        };
+
+        let mut buffer = self.buffer.lock().unwrap();
+        // both are even, so the channels stay in order
+        let free = self.capacity.saturating_sub(buffer.len());
+        buffer.extend(samples.iter().take(free));
+    }
+}
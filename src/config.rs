@@ -0,0 +1,332 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{de::IntoDeserializer, Deserialize};
+use trapezoid_core::DigitalControllerKey;
+use winit::keyboard::KeyCode;
+
+/// The config file format, everything is optional, missing entries use the defaults.
+///
+/// ```toml
+/// [controller]
+/// Up = "ArrowUp"
+/// X = "KeyZ"
+///
+/// [hotkeys]
+/// toggle_vram = "F2"
+/// ```
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    controller: HashMap<String, String>,
+    #[serde(default)]
+    hotkeys: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    ToggleFullVram,
+    ToggleShellOpen,
+    /// Pause the audio and drop what the emulator produces
+    ToggleMute,
+    /// Insert the next disk of the list, the shell must be open
+    NextDisk,
+    /// Pause the CPU and enable the debugger
+    DebuggerBreak,
+    /// Resume the CPU if paused
+    DebuggerContinue,
+    /// Select the next file of the recent files list
+    NextRecent,
+    /// Load the selected recent file
+    LoadRecent,
+    /// Stop advancing the emulation, the display keeps updating
+    TogglePause,
+    /// Run exactly one video frame while paused
+    FrameAdvance,
+    /// Switch to the next output shader
+    NextShader,
+}
+
+impl Hotkey {
+    const ALL: [Hotkey; 11] = [
+        Hotkey::ToggleFullVram,
+        Hotkey::ToggleShellOpen,
+        Hotkey::ToggleMute,
+        Hotkey::NextDisk,
+        Hotkey::DebuggerBreak,
+        Hotkey::DebuggerContinue,
+        Hotkey::NextRecent,
+        Hotkey::LoadRecent,
+        Hotkey::TogglePause,
+        Hotkey::FrameAdvance,
+        Hotkey::NextShader,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Hotkey::ToggleFullVram => "toggle_vram",
+            Hotkey::ToggleShellOpen => "toggle_shell_open",
+            Hotkey::ToggleMute => "toggle_mute",
+            Hotkey::NextDisk => "next_disk",
+            Hotkey::DebuggerBreak => "debugger_break",
+            Hotkey::DebuggerContinue => "debugger_continue",
+            Hotkey::NextRecent => "next_recent",
+            Hotkey::LoadRecent => "load_recent",
+            Hotkey::TogglePause => "toggle_pause",
+            Hotkey::FrameAdvance => "frame_advance",
+            Hotkey::NextShader => "next_shader",
+        }
+    }
+
+    fn default_key(&self) -> KeyCode {
+        match self {
+            Hotkey::ToggleFullVram => KeyCode::KeyV,
+            Hotkey::ToggleShellOpen => KeyCode::BracketRight,
+            Hotkey::ToggleMute => KeyCode::KeyM,
+            Hotkey::NextDisk => KeyCode::BracketLeft,
+            Hotkey::DebuggerBreak => KeyCode::Slash,
+            Hotkey::DebuggerContinue => KeyCode::KeyC,
+            Hotkey::NextRecent => KeyCode::F5,
+            Hotkey::LoadRecent => KeyCode::F6,
+            Hotkey::TogglePause => KeyCode::Space,
+            Hotkey::FrameAdvance => KeyCode::Period,
+            Hotkey::NextShader => KeyCode::F7,
+        }
+    }
+}
+
+/// The PSX controller keys, with their names in the config file and default bindings
+const CONTROLLER_KEYS: [(&str, DigitalControllerKey, KeyCode); 16] = [
+    ("Start", DigitalControllerKey::Start, KeyCode::Enter),
+    ("Select", DigitalControllerKey::Select, KeyCode::Backspace),
+    ("L1", DigitalControllerKey::L1, KeyCode::Digit1),
+    ("L2", DigitalControllerKey::L2, KeyCode::Digit2),
+    ("L3", DigitalControllerKey::L3, KeyCode::Digit3),
+    ("R1", DigitalControllerKey::R1, KeyCode::Digit0),
+    ("R2", DigitalControllerKey::R2, KeyCode::Digit9),
+    ("R3", DigitalControllerKey::R3, KeyCode::Digit8),
+    ("Up", DigitalControllerKey::Up, KeyCode::KeyW),
+    ("Down", DigitalControllerKey::Down, KeyCode::KeyS),
+    ("Right", DigitalControllerKey::Right, KeyCode::KeyD),
+    ("Left", DigitalControllerKey::Left, KeyCode::KeyA),
+    ("Triangle", DigitalControllerKey::Triangle, KeyCode::KeyI),
+    ("X", DigitalControllerKey::X, KeyCode::KeyK),
+    ("Circle", DigitalControllerKey::Circle, KeyCode::KeyL),
+    ("Square", DigitalControllerKey::Square, KeyCode::KeyJ),
+];
+
+pub fn parse_digital_key(name: &str) -> Option<DigitalControllerKey> {
+    CONTROLLER_KEYS
+        .iter()
+        .find(|(n, _, _)| *n == name)
+        .map(|(_, key, _)| *key)
+}
+
+/// Parse winit `KeyCode` names, e.g. `KeyW`, `Digit1`, `ArrowUp`
+fn parse_key_code(name: &str) -> Option<KeyCode> {
+    let deserializer: serde::de::value::StrDeserializer<serde::de::value::Error> =
+        name.into_deserializer();
+    KeyCode::deserialize(deserializer).ok()
+}
+
+/// What a keyboard key is bound to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    Controller(DigitalControllerKey),
+    Hotkey(Hotkey),
+}
+
+pub struct Config {
+    bindings: HashMap<KeyCode, Binding>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::from_file_content(ConfigFile::default()).unwrap()
+    }
+}
+
+impl Config {
+    /// `~/.config/trapezoid/config.toml` on linux, and similar locations on other platforms
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|d| d.join("trapezoid").join("config.toml"))
+    }
+
+    /// Load the config from `path`, if `path` is `None`, the default path is used,
+    /// and the default config is returned if it doesn't exist.
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match Self::default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Self::default()),
+            },
+        };
+
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Could not read config file {:?}: {}", path, e))?;
+        Self::parse(&content).map_err(|e| format!("Invalid config file {:?}: {}", path, e))
+    }
+
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let file: ConfigFile = toml::from_str(content).map_err(|e| e.to_string())?;
+        Self::from_file_content(file)
+    }
+
+    fn from_file_content(mut file: ConfigFile) -> Result<Self, String> {
+        let mut bindings = HashMap::new();
+        // the name of the action each key is bound to, for errors
+        let mut bound_names = HashMap::new();
+
+        let mut bind = |name: &str,
+                        entries: &mut HashMap<String, String>,
+                        default_key: KeyCode,
+                        binding: Binding|
+         -> Result<(), String> {
+            let key = match entries.remove(name) {
+                Some(key_name) => parse_key_code(&key_name)
+                    .ok_or_else(|| format!("unknown key name `{}` for `{}`", key_name, name))?,
+                None => default_key,
+            };
+
+            if let Some(other) = bound_names.insert(key, name.to_string()) {
+                return Err(format!(
+                    "key `{:?}` is bound to both `{}` and `{}`",
+                    key, other, name
+                ));
+            }
+            bindings.insert(key, binding);
+            Ok(())
+        };
+
+        for (name, controller_key, default_key) in CONTROLLER_KEYS {
+            bind(
+                name,
+                &mut file.controller,
+                default_key,
+                Binding::Controller(controller_key),
+            )?;
+        }
+        for hotkey in Hotkey::ALL {
+            bind(
+                hotkey.name(),
+                &mut file.hotkeys,
+                hotkey.default_key(),
+                Binding::Hotkey(hotkey),
+            )?;
+        }
+
+        // anything left is not known
+        if let Some(name) = file.controller.keys().next() {
+            return Err(format!("unknown controller key `{}`", name));
+        }
+        if let Some(name) = file.hotkeys.keys().next() {
+            return Err(format!("unknown hotkey `{}`", name));
+        }
+
+        Ok(Self { bindings })
+    }
+
+    pub fn binding(&self, key: KeyCode) -> Option<Binding> {
+        self.bindings.get(&key).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_bindings() {
+        let config = Config::default();
+
+        assert_eq!(
+            config.binding(KeyCode::KeyW),
+            Some(Binding::Controller(DigitalControllerKey::Up))
+        );
+        assert_eq!(
+            config.binding(KeyCode::KeyV),
+            Some(Binding::Hotkey(Hotkey::ToggleFullVram))
+        );
+        assert_eq!(
+            config.binding(KeyCode::Space),
+            Some(Binding::Hotkey(Hotkey::TogglePause))
+        );
+        assert_eq!(config.binding(KeyCode::KeyQ), None);
+    }
+
+    #[test]
+    fn parse_overrides() {
+        let config = Config::parse(
+            r#"
+            [controller]
+            Up = "ArrowUp"
+            X = "KeyZ"
+
+            [hotkeys]
+            toggle_vram = "F2"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.binding(KeyCode::ArrowUp),
+            Some(Binding::Controller(DigitalControllerKey::Up))
+        );
+        assert_eq!(
+            config.binding(KeyCode::KeyZ),
+            Some(Binding::Controller(DigitalControllerKey::X))
+        );
+        // the old bindings are not used anymore
+        assert_eq!(config.binding(KeyCode::KeyW), None);
+        assert_eq!(config.binding(KeyCode::KeyK), None);
+        assert_eq!(config.binding(KeyCode::KeyV), None);
+        assert_eq!(
+            config.binding(KeyCode::F2),
+            Some(Binding::Hotkey(Hotkey::ToggleFullVram))
+        );
+        // not changed
+        assert_eq!(
+            config.binding(KeyCode::KeyS),
+            Some(Binding::Controller(DigitalControllerKey::Down))
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        let err = Config::parse("[controller]\nUp = \"KeyWW\"").err().unwrap();
+        assert_eq!(err, "unknown key name `KeyWW` for `Up`");
+
+        let err = Config::parse("[controller]\nTurbo = \"KeyQ\"")
+            .err()
+            .unwrap();
+        assert_eq!(err, "unknown controller key `Turbo`");
+
+        let err = Config::parse("[hotkeys]\nquit = \"KeyQ\"").err().unwrap();
+        assert_eq!(err, "unknown hotkey `quit`");
+
+        assert!(Config::parse("[something]").is_err());
+    }
+
+    #[test]
+    fn conflicts() {
+        // two controller keys
+        let err = Config::parse("[controller]\nUp = \"KeyQ\"\nDown = \"KeyQ\"")
+            .err()
+            .unwrap();
+        assert_eq!(err, "key `KeyQ` is bound to both `Up` and `Down`");
+
+        // with a default binding
+        let err = Config::parse("[controller]\nUp = \"KeyK\"").err().unwrap();
+        assert_eq!(err, "key `KeyK` is bound to both `Up` and `X`");
+
+        // controller key and hotkey
+        let err = Config::parse("[hotkeys]\ntoggle_vram = \"KeyW\"")
+            .err()
+            .unwrap();
+        assert_eq!(err, "key `KeyW` is bound to both `Up` and `toggle_vram`");
+    }
+}
@@ -0,0 +1,73 @@
+//! Crash logs for panics in the emulation thread, so that a crash leaves more
+//! than the panic message on the terminal
+
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use trapezoid_core::{cpu::RegisterType, Psx};
+
+use crate::log_ring;
+
+/// The message and location of the last panic, set by the hook
+static LAST_PANIC: Mutex<Option<String>> = Mutex::new(None);
+
+/// Keep the message of panics for the crash log, they are still printed as usual
+pub fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        *LAST_PANIC.lock().unwrap_or_else(|e| e.into_inner()) = Some(info.to_string());
+        default_hook(info);
+    }));
+}
+
+/// The message of the last panic, with its location
+pub fn take_panic_message() -> String {
+    LAST_PANIC
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take()
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+fn crash_log_path() -> PathBuf {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    // next to the working directory if there is no data directory
+    dirs::data_local_dir()
+        .map(|d| d.join("trapezoid"))
+        .unwrap_or_default()
+        .join(format!("crash-{}.log", time))
+}
+
+/// Write the panic message, the CPU registers and the last log lines to a new
+/// crash log, returns its path
+pub fn write_crash_log(message: &str, psx: &mut Psx) -> io::Result<PathBuf> {
+    let path = crash_log_path();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = BufWriter::new(File::create(&path)?);
+
+    writeln!(file, "trapezoid {} crashed", env!("CARGO_PKG_VERSION"))?;
+    writeln!(file, "{}", message)?;
+    writeln!(file)?;
+    writeln!(file, "Disk: {}", psx.disk_id().unwrap_or("-"))?;
+    let registers = psx.cpu().registers();
+    writeln!(file, "PC: {:08X}", registers.read(RegisterType::Pc))?;
+    writeln!(file, "{:?}", registers)?;
+    writeln!(file)?;
+    writeln!(file, "Last log lines:")?;
+    for line in log_ring::last_lines() {
+        writeln!(file, "{}", line)?;
+    }
+    file.flush()?;
+
+    Ok(path)
+}
@@ -1,4 +1,10 @@
-use std::{io::Write, process, sync::mpsc, thread};
+mod expr;
+mod symbols;
+
+use std::{collections::HashMap, io::Write, process, sync::mpsc, thread};
+
+use expr::{EvalContext, Expr};
+use symbols::Symbols;
 
 use rustyline::{
     completion::Completer, error::ReadlineError, highlight::Highlighter, hint::Hinter,
@@ -6,7 +12,7 @@ use rustyline::{
     Config, Editor,
 };
 use trapezoid_core::{
-    cpu::{CpuState, Instruction, RegisterType, CPU_REGISTERS},
+    cpu::{unwind_stack, CpuState, Instruction, RegisterType, TraceConfig, CPU_REGISTERS},
     Psx, HW_REGISTERS,
 };
 
@@ -118,6 +124,70 @@ fn create_editor() -> Editor<EditorHelper, MemHistory> {
     editor
 }
 
+/// Gives the expressions access to the emulator
+struct PsxEvalContext<'a>(&'a mut Psx);
+
+impl EvalContext for PsxEvalContext<'_> {
+    fn register(&mut self, reg: RegisterType) -> u32 {
+        self.0.cpu().registers().read(reg)
+    }
+
+    fn read_memory(&mut self, addr: u32, bits: u8) -> Option<u32> {
+        match bits {
+            8 => self.0.bus_read_u8(addr).ok().map(u32::from),
+            16 => self.0.bus_read_u16(addr).ok().map(u32::from),
+            32 => self.0.bus_read_u32(addr).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// A watched memory location, with the last value seen
+struct Watch {
+    bits: u8,
+    value: Option<u32>,
+}
+
+const MAIN_RAM_START: u32 = 0x8000_0000;
+const MAIN_RAM_SIZE: u32 = 0x20_0000;
+/// Default number of frames for `backtrace`
+const BACKTRACE_MAX_DEPTH: usize = 64;
+/// Stop printing `find` results after this many matches
+const MAX_FIND_RESULTS: usize = 32;
+
+/// Parse the `find` modifier: `8`, `16`, `32` or `b` (bytes),
+/// optionally followed by `:<alignment>`. Returns the value size and alignment.
+fn parse_find_modifier(modifier: Option<&str>) -> Option<(Option<u8>, u32)> {
+    let (kind, align) = match modifier {
+        Some(m) => match m.split_once(':') {
+            Some((kind, align)) => (kind, Some(align.parse::<u32>().ok()?)),
+            None => (m, None),
+        },
+        None => ("32", None),
+    };
+    let bits = match kind {
+        "b" => None,
+        "8" => Some(8),
+        "16" => Some(16),
+        "32" => Some(32),
+        _ => return None,
+    };
+    let align = align.unwrap_or(bits.map(|b| b as u32 / 8).unwrap_or(1));
+    (align != 0).then_some((bits, align))
+}
+
+/// Offsets of `pattern` in `data`, only at multiples of `align`
+fn find_pattern(data: &[u8], pattern: &[u8], align: u32) -> Vec<u32> {
+    if pattern.is_empty() || pattern.len() > data.len() {
+        return Vec::new();
+    }
+    (0..=data.len() - pattern.len())
+        .step_by(align as usize)
+        .filter(|&i| data[i..].starts_with(pattern))
+        .map(|i| i as u32)
+        .collect()
+}
+
 struct RunHookSettings {
     step: bool,
     step_over: bool,
@@ -133,6 +203,9 @@ pub struct Debugger {
     enabled: bool,
     breakpoint_hooks: Vec<String>,
     run_hook_settings: RunHookSettings,
+    breakpoint_conditions: HashMap<u32, Expr>,
+    watches: HashMap<u32, Watch>,
+    symbols: Symbols,
 }
 
 impl Debugger {
@@ -185,6 +258,9 @@ impl Debugger {
             editor_tx,
             enabled: false,
             breakpoint_hooks: Vec::new(),
+            breakpoint_conditions: HashMap::new(),
+            watches: HashMap::new(),
+            symbols: Symbols::default(),
             run_hook_settings: RunHookSettings {
                 step: false,
                 step_over: false,
@@ -240,7 +316,7 @@ impl Debugger {
             })
         }
 
-        fn parse_address(a: &str, psx: &mut Psx) -> Option<u32> {
+        fn parse_address(a: &str, psx: &mut Psx, symbols: &Symbols) -> Option<u32> {
             if let Some(register_name) = a.strip_prefix('$') {
                 let reg_ty = parse_register_name(register_name);
                 reg_ty.map(|r| psx.cpu().registers().read(r))
@@ -249,6 +325,8 @@ impl Debugger {
                     println!("Invalid hardware register name: {}", hw_register_name);
                     None
                 })
+            } else if let Some(addr) = symbols.address_of(a) {
+                Some(addr)
             } else {
                 let value = u32::from_str_radix(a.trim_start_matches("0x"), 16);
                 match value {
@@ -266,9 +344,17 @@ impl Debugger {
             cmd = s1;
             s2
         });
+        // `b <addr> if <condition>`
+        let (arg, condition) = match arg.and_then(|a| a.split_once(" if ")) {
+            Some((a, c)) if cmd == "b" => (Some(a.trim()), Some(c)),
+            _ => (arg, None),
+        };
         let addr = arg.and_then(|a| {
-            if cmd != "set" {
-                parse_address(a, psx)
+            if !matches!(
+                cmd,
+                "set" | "find" | "eval" | "loadsyms" | "tlog" | "mute" | "solo" | "warnings"
+            ) {
+                parse_address(a, psx, &self.symbols)
             } else {
                 None
             }
@@ -285,21 +371,37 @@ impl Debugger {
                 println!("su - step-out");
                 println!("tt - enable trace");
                 println!("tf - disbale trace");
+                println!("tlog <path> [max] - write the executed instructions and registers changes to a file [stop after `max` instructions]");
+                println!("tlog off - stop writing the trace file");
                 println!("stack [0xn] - print stack [n entries in hex]");
                 println!("bt/[limit] - print backtrace [top `limit` entries]");
-                println!("b <addr> - set breakpoint");
+                println!("backtrace/[limit] - print backtrace recovered from the stack [up to `limit` frames]");
+                println!("trace-calls <on/off> - print the target of every function call");
+                println!("b <addr> [if <cond>] - set breakpoint [only break if `cond` is true]");
                 println!("rb <addr> - remove breakpoint");
                 println!("bw <addr> - set write breakpoint");
                 println!("rbw <addr> - remove write breakpoint");
                 println!("br <addr> - set read breakpoint");
                 println!("rbr <addr> - remove read breakpoint");
                 println!("lb - list breakpoints");
+                println!("watch[/8/16/32] <addr> - break on writes and print the old/new value (default u32)");
+                println!("rwatch <addr> - remove watch");
+                println!("find[/b/8/16/32][:align] <value> - search RAM for a value or hex bytes (default u32)");
+                println!("eval <expr> - evaluate an expression");
                 println!("m[32/16/8] <addr> - print content of memory (default u32)");
                 println!("md/[n] <addr> - memory dump ([n] argument will print the next multiple of 16 after n)");
                 println!("p <addr>/<$reg> - print address or register value");
                 println!("set <$reg> <value> - set register value (if it can be modified)");
                 println!("i/[n] [addr] - disassemble instructions");
                 println!("spu - print SPU state");
+                println!("mute <voice/cd> <on/off> - mute a SPU voice (0-23) or the CD audio in the output");
+                println!("solo <voice/off> - only output one SPU voice (0-23)");
+                println!("irq - print pending interrupts (I_STAT & I_MASK)");
+                println!("timing - print the elapsed CPU cycles, vblanks and hblanks");
+                println!(
+                    "warnings [clear] - list the unsupported behavior seen so far [and clear it]"
+                );
+                println!("loadsyms <path> - load symbols from a nocash .sym or a map file");
                 println!("hook_add <cmd[;cmd]> - add hook/s commands");
                 println!("hook_clear - clear all hooks");
                 println!("hook_list - list all hooks");
@@ -307,10 +409,10 @@ impl Debugger {
                     "hook_setting [<break_type>[=true/false]] - change when the hooks are executed"
                 );
             }
-            "reset" => {
-                psx.reset();
-                println!("Reset");
-            }
+            "reset" => match psx.reset() {
+                Ok(()) => println!("Reset"),
+                Err(e) => println!("Reset, but the disk could not be loaded: {}", e),
+            },
             "r" => println!("{:?}", psx.cpu().registers()),
             "c" => {
                 self.set_enabled(false);
@@ -346,6 +448,37 @@ impl Debugger {
                 psx.cpu().debugger().set_instruction_trace_handler(None);
                 println!("Instruction trace: false");
             }
+            "tlog" => match arg
+                .map(|a| a.split_whitespace().collect::<Vec<_>>())
+                .as_deref()
+            {
+                Some(["off"]) => {
+                    psx.cpu().set_trace(None).ok();
+                    println!("Trace file: false");
+                }
+                Some([path, rest @ ..]) if rest.len() <= 1 => {
+                    let max_instructions = match rest.first().map(|m| m.parse::<u64>()) {
+                        Some(Ok(m)) => Some(m),
+                        Some(Err(_)) => {
+                            println!("Invalid max instructions: {}", rest[0]);
+                            return;
+                        }
+                        None => None,
+                    };
+                    let config = TraceConfig {
+                        path: path.into(),
+                        start_pc: None,
+                        end_pc: None,
+                        max_instructions,
+                        include_registers: true,
+                    };
+                    match psx.cpu().set_trace(Some(config)) {
+                        Ok(()) => println!("Trace file: {}", path),
+                        Err(e) => println!("Failed to create {}: {}", path, e),
+                    }
+                }
+                _ => println!("Usage: tlog <path> [max] / tlog off"),
+            },
             "stack" => {
                 let n = addr.unwrap_or(10);
                 let sp = psx.cpu().registers().read(RegisterType::Sp);
@@ -367,19 +500,88 @@ impl Debugger {
                     .unwrap_or(call_stack.len());
 
                 for (i, frame) in call_stack.iter().enumerate().rev().take(limit) {
-                    println!("#{:02}:      {:08X}", i, frame);
+                    match self.symbols.describe(*frame) {
+                        Some(symbol) => println!("#{:02}:      {:08X} <{}>", i, frame, symbol),
+                        None => println!("#{:02}:      {:08X}", i, frame),
+                    }
+                }
+            }
+            "backtrace" => {
+                let limit = modifier
+                    .and_then(|m| m.parse::<usize>().ok())
+                    .unwrap_or(BACKTRACE_MAX_DEPTH);
+                let regs = psx.cpu().registers();
+                let (pc, sp, ra) = (
+                    regs.read(RegisterType::Pc),
+                    regs.read(RegisterType::Sp),
+                    regs.read(RegisterType::Ra),
+                );
+
+                let frames = unwind_stack(pc, sp, ra, limit, |addr| psx.bus_read_u32(addr).ok());
+                for (i, frame) in frames.iter().enumerate() {
+                    let function = frame
+                        .function
+                        .map(|f| {
+                            self.symbols
+                                .describe(f)
+                                .unwrap_or_else(|| format!("{:08X}", f))
+                        })
+                        .or_else(|| self.symbols.describe(frame.pc))
+                        .unwrap_or_else(|| "????????".to_string());
+                    println!(
+                        "#{:02}: {:08X} in {} (sp={:08X})",
+                        i, frame.pc, function, frame.sp
+                    );
                 }
             }
+            "trace-calls" => match arg {
+                Some("on") => {
+                    // a snapshot, symbols loaded later are used after enabling again
+                    let symbols = self.symbols.clone();
+                    psx.cpu().debugger().set_call_trace_handler(Some(Box::new(
+                        move |pc, target, depth| {
+                            println!(
+                                "{:indent$}{:08X}: call {}",
+                                "",
+                                pc,
+                                symbols.format_address(target),
+                                indent = depth * 2
+                            );
+                        },
+                    )));
+                    println!("Call trace: true");
+                }
+                Some("off") => {
+                    psx.cpu().debugger().set_call_trace_handler(None);
+                    println!("Call trace: false");
+                }
+                _ => println!("Usage: trace-calls <on/off>"),
+            },
             "b" => {
                 if let Some(addr) = addr {
+                    let condition = match condition.map(Expr::parse).transpose() {
+                        Ok(condition) => condition,
+                        Err(e) => {
+                            println!("Invalid condition: {}", e);
+                            return;
+                        }
+                    };
                     psx.cpu().debugger().add_breakpoint(addr);
-                    println!("Breakpoint added: 0x{:08X}", addr);
+                    let addr_str = self.symbols.format_address(addr);
+                    if let Some(condition) = condition {
+                        println!("Breakpoint added: {} if {}", addr_str, condition);
+                        self.breakpoint_conditions.insert(addr, condition);
+                    } else {
+                        println!("Breakpoint added: {}", addr_str);
+                        self.breakpoint_conditions.remove(&addr);
+                    }
                 } else {
-                    println!("Usage: b <address>");
+                    println!("Usage: b <address> [if <condition>]");
                 }
             }
             "rb" => {
                 if let Some(addr) = addr {
+                    self.breakpoint_conditions.remove(&addr);
                     if psx.cpu().debugger().remove_breakpoint(addr) {
                         println!("Breakpoint removed: 0x{:08X}", addr);
                     } else {
@@ -429,7 +631,15 @@ impl Debugger {
             }
             "lb" => {
                 for bp in psx.cpu().debugger().instruction_breakpoints().iter() {
-                    println!("Breakpoint: 0x{:08X}", bp);
+                    let addr_str = self.symbols.format_address(*bp);
+                    if let Some(condition) = self.breakpoint_conditions.get(bp) {
+                        println!("Breakpoint: {} if {}", addr_str, condition);
+                    } else {
+                        println!("Breakpoint: {}", addr_str);
+                    }
+                }
+                for (addr, watch) in self.watches.iter() {
+                    println!("Watch: 0x{:08X} u{}", addr, watch.bits);
                 }
                 for bp in psx.cpu().debugger().write_breakpoints().iter() {
                     println!("Write Breakpoint: 0x{:08X}", bp);
@@ -438,6 +648,103 @@ impl Debugger {
                     println!("Read Breakpoint: 0x{:08X}", bp);
                 }
             }
+            "watch" => {
+                let bits = match modifier {
+                    None | Some("32") => 32,
+                    Some("16") => 16,
+                    Some("8") => 8,
+                    Some(m) => {
+                        println!("Invalid watch size: {}", m);
+                        return;
+                    }
+                };
+                if let Some(addr) = addr {
+                    let value = PsxEvalContext(psx).read_memory(addr, bits);
+                    psx.cpu().debugger().add_write_breakpoint(addr);
+                    self.watches.insert(addr, Watch { bits, value });
+                    match value {
+                        Some(value) => println!("Watch added: 0x{:08X} = 0x{:X}", addr, value),
+                        None => println!("Watch added: 0x{:08X}", addr),
+                    }
+                } else {
+                    println!("Usage: watch[/8/16/32] <address>");
+                }
+            }
+            "rwatch" => {
+                if let Some(addr) = addr {
+                    if self.watches.remove(&addr).is_some() {
+                        psx.cpu().debugger().remove_write_breakpoint(addr);
+                        println!("Watch removed: 0x{:08X}", addr);
+                    } else {
+                        println!("Watch not found: 0x{:08X}", addr);
+                    }
+                } else {
+                    println!("Usage: rwatch <address>");
+                }
+            }
+            "find" => {
+                let usage = "Usage: find[/b/8/16/32][:align] <value/hex bytes>";
+                let (Some(arg), Some((bits, align))) = (arg, parse_find_modifier(modifier)) else {
+                    println!("{}", usage);
+                    return;
+                };
+                let arg = arg.trim().trim_start_matches("0x");
+                let pattern = match bits {
+                    Some(bits) => {
+                        let Ok(value) = u32::from_str_radix(arg, 16) else {
+                            println!("{}", usage);
+                            return;
+                        };
+                        value.to_le_bytes()[..bits as usize / 8].to_vec()
+                    }
+                    None => {
+                        let hex = arg.replace(' ', "");
+                        let bytes = (0..hex.len())
+                            .step_by(2)
+                            .map(|i| {
+                                hex.get(i..i + 2)
+                                    .and_then(|b| u8::from_str_radix(b, 16).ok())
+                            })
+                            .collect::<Option<Vec<u8>>>();
+                        match bytes {
+                            Some(bytes) if !bytes.is_empty() => bytes,
+                            _ => {
+                                println!("{}", usage);
+                                return;
+                            }
+                        }
+                    }
+                };
+
+                let mut ram = Vec::with_capacity(MAIN_RAM_SIZE as usize);
+                for addr in (MAIN_RAM_START..MAIN_RAM_START + MAIN_RAM_SIZE).step_by(4) {
+                    let Ok(word) = psx.bus_read_u32(addr) else {
+                        println!("Error reading u32 {:08X}", addr);
+                        return;
+                    };
+                    ram.extend_from_slice(&word.to_le_bytes());
+                }
+
+                let matches = find_pattern(&ram, &pattern, align);
+                for offset in matches.iter().take(MAX_FIND_RESULTS) {
+                    println!("0x{:08X}", MAIN_RAM_START + offset);
+                }
+                if matches.len() > MAX_FIND_RESULTS {
+                    println!("... and {} more", matches.len() - MAX_FIND_RESULTS);
+                } else if matches.is_empty() {
+                    println!("Not found");
+                }
+            }
+            "eval" => {
+                let Some(arg) = arg else {
+                    println!("Usage: eval <expression>");
+                    return;
+                };
+                match Expr::parse(arg).and_then(|e| e.eval(&mut PsxEvalContext(psx))) {
+                    Ok(value) => println!("0x{:08X} ({})", value, value),
+                    Err(e) => println!("{}", e),
+                }
+            }
             "md" => {
                 let count = modifier.and_then(|m| m.parse::<u32>().ok()).unwrap_or(1);
                 let addr = addr.unwrap_or(psx.cpu().registers().read(RegisterType::Pc));
@@ -529,7 +836,7 @@ impl Debugger {
                     println!("Invalid register name: {}", register_name);
                     return;
                 };
-                let Some(value) = parse_address(value, psx) else {
+                let Some(value) = parse_address(value, psx, &self.symbols) else {
                     println!("Invalid value: {}", value);
                     return;
                 };
@@ -550,11 +857,22 @@ impl Debugger {
                         // will always be aligned
                         let val = psx.bus_read_u32(addr).unwrap();
                         let instr = Instruction::from_u32(val, addr);
+                        // label the start of functions
+                        if let Some(name) = self.symbols.describe(addr).filter(|s| !s.contains('+'))
+                        {
+                            println!("<{}>:", name);
+                        }
+                        let target = instr
+                            .jump_target()
+                            .and_then(|t| self.symbols.describe(t))
+                            .map(|s| format!(" <{}>", s))
+                            .unwrap_or_default();
                         println!(
-                            "0x{:08X}: {}{}",
+                            "0x{:08X}: {}{}{}",
                             addr,
                             if previous_instr.is_branch() { "_" } else { "" },
-                            instr
+                            instr,
+                            target
                         );
                         previous_instr = instr;
                     }
@@ -565,6 +883,108 @@ impl Debugger {
             "spu" => {
                 psx.print_spu_state();
             }
+            "mute" => {
+                let mut args = arg.unwrap_or("").split_whitespace();
+                let target = args.next();
+                let muted = match args.next() {
+                    Some("on") => Some(true),
+                    Some("off") => Some(false),
+                    _ => None,
+                };
+                match (target, muted) {
+                    (Some("cd"), Some(muted)) => {
+                        psx.set_cd_audio_muted(muted);
+                        println!("CD audio muted: {}", muted);
+                    }
+                    (Some(voice), Some(muted)) => match voice.parse::<usize>() {
+                        Ok(voice) if voice < 24 => {
+                            psx.set_voice_muted(voice, muted);
+                            println!("Voice {} muted: {}", voice, muted);
+                        }
+                        _ => println!("Invalid voice: {}", voice),
+                    },
+                    _ => println!("Usage: mute <voice/cd> <on/off>"),
+                }
+            }
+            "solo" => match arg.map(str::trim) {
+                Some("off") => {
+                    psx.solo_voice(None);
+                    println!("Solo: off");
+                }
+                Some(voice) => match voice.parse::<usize>() {
+                    Ok(voice) if voice < 24 => {
+                        psx.solo_voice(Some(voice));
+                        println!("Solo: voice {}", voice);
+                    }
+                    _ => println!("Invalid voice: {}", voice),
+                },
+                None => println!("Usage: solo <voice/off>"),
+            },
+            "irq" => {
+                const IRQ_NAMES: [&str; 11] = [
+                    "VBLANK",
+                    "GPU",
+                    "CDROM",
+                    "DMA",
+                    "TIMER0",
+                    "TIMER1",
+                    "TIMER2",
+                    "CONTROLLER_AND_MEMCARD",
+                    "SIO",
+                    "SPU",
+                    "CONTROLLER",
+                ];
+                let pending = psx.pending_interrupts();
+                let names = IRQ_NAMES
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| pending & (1 << i) != 0)
+                    .map(|(_, name)| *name)
+                    .collect::<Vec<_>>();
+                println!(
+                    "Pending interrupts: 0x{:04X} [{}]",
+                    pending,
+                    names.join(", ")
+                );
+            }
+            "timing" => {
+                println!("CPU cycles: {}", psx.elapsed_cycles());
+                println!("vblanks: {}", psx.vblank_count());
+                println!("hblanks: {}", psx.hblank_count());
+            }
+            "warnings" => match arg {
+                Some("clear") => {
+                    psx.clear_warnings();
+                    println!("Warnings cleared");
+                }
+                Some(_) => println!("Usage: warnings [clear]"),
+                None => {
+                    let warnings = psx.emulation_warnings();
+                    if warnings.is_empty() {
+                        println!("No warnings");
+                    }
+                    for warning in &warnings {
+                        println!(
+                            "[{}] {} (x{})",
+                            warning.device, warning.message, warning.count
+                        );
+                    }
+                    println!("Total: {}", psx.warning_count());
+                }
+            },
+            "loadsyms" => {
+                let Some(path) = arg else {
+                    println!("Usage: loadsyms <path>");
+                    return;
+                };
+                match Symbols::load(path.trim()) {
+                    Ok(symbols) => {
+                        println!("Loaded {} symbols from {}", symbols.len(), path.trim());
+                        self.symbols.extend(symbols);
+                    }
+                    Err(e) => println!("{}", e),
+                }
+            }
             "hook_add" => {
                 if let Some(arg) = arg {
                     for split in arg.split(';') {
@@ -656,13 +1076,64 @@ impl Debugger {
         match cpu_state {
             CpuState::Normal => {}
             CpuState::InstructionBreakpoint(addr) => {
-                println!("Instruction breakpoint at {:#x}", addr);
+                if let Some(condition) = self.breakpoint_conditions.get(&addr) {
+                    let mut operands = Vec::new();
+                    match condition.eval_with_operands(&mut PsxEvalContext(psx), &mut operands) {
+                        // keep running
+                        Ok(0) => return,
+                        Ok(_) => {
+                            println!(
+                                "Instruction breakpoint at {}, `{}` is true",
+                                self.symbols.format_address(addr),
+                                condition
+                            );
+                            for (operand, value) in operands {
+                                println!("    {} = 0x{:08X}", operand, value);
+                            }
+                        }
+                        Err(e) => {
+                            println!(
+                                "Instruction breakpoint at {}, failed to evaluate `{}`: {}",
+                                self.symbols.format_address(addr),
+                                condition,
+                                e
+                            );
+                        }
+                    }
+                } else {
+                    println!(
+                        "Instruction breakpoint at {}",
+                        self.symbols.format_address(addr)
+                    );
+                }
                 self.set_enabled(true);
                 if self.run_hook_settings.instruction_breakpoint {
                     self.run_hooks(psx);
                 }
             }
             CpuState::WriteBreakpoint { addr, bits } => {
+                if let Some(watch) = self.watches.get(&addr) {
+                    let new_value = PsxEvalContext(psx).read_memory(addr, watch.bits);
+                    let fmt_value = |v: Option<u32>| {
+                        v.map(|v| format!("0x{:X}", v))
+                            .unwrap_or_else(|| "??".to_string())
+                    };
+                    println!(
+                        "Watch 0x{:08X}: {} -> {} at {:08x}",
+                        addr,
+                        fmt_value(watch.value),
+                        fmt_value(new_value),
+                        psx.cpu().registers().read(RegisterType::Pc)
+                    );
+                    self.watches.get_mut(&addr).unwrap().value = new_value;
+
+                    self.set_enabled(true);
+                    if self.run_hook_settings.write_breakpoint {
+                        self.run_hooks(psx);
+                    }
+                    return;
+                }
+
                 let hw_reg_name = HW_REGISTERS
                     .entries()
                     .find(|(_, &v)| v == addr)
@@ -720,6 +1191,32 @@ impl Debugger {
                     self.run_hooks(psx);
                 }
             }
+            // reported by the emulation thread
+            CpuState::GpuError { .. } => {}
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_modifier() {
+        assert_eq!(parse_find_modifier(None), Some((Some(32), 4)));
+        assert_eq!(parse_find_modifier(Some("16")), Some((Some(16), 2)));
+        assert_eq!(parse_find_modifier(Some("b")), Some((None, 1)));
+        assert_eq!(parse_find_modifier(Some("8:4")), Some((Some(8), 4)));
+        assert_eq!(parse_find_modifier(Some("32:0")), None);
+        assert_eq!(parse_find_modifier(Some("64")), None);
+    }
+
+    #[test]
+    fn find_aligned() {
+        let data = [0xAA, 0xBB, 0xAA, 0xBB, 0x00, 0xAA, 0xBB, 0x00];
+        assert_eq!(find_pattern(&data, &[0xAA, 0xBB], 1), [0, 2, 5]);
+        assert_eq!(find_pattern(&data, &[0xAA, 0xBB], 2), [0, 2]);
+        assert_eq!(find_pattern(&data, &[0xAA, 0xBB], 4), [0]);
+        assert!(find_pattern(&data, &[], 1).is_empty());
+    }
+}
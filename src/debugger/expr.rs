@@ -0,0 +1,496 @@
+//! A small expression language for debugger conditions.
+//!
+//! Values are `u32` with wrapping arithmetic, comparisons are unsigned and
+//! return `0` or `1`, the precedence follows C.
+//!
+//! - numbers: `16`, `0x10`
+//! - registers: `a0`, `$a0`, `r4`, `pc`, `hi`...
+//! - memory reads: `[addr]` (u32), `u8[addr]`, `u16[addr]`, `u32[addr]`
+//! - operators: `|| && | ^ & == != < <= > >= << >> + - * / %` and unary `- ! ~`
+
+use std::fmt;
+
+use trapezoid_core::cpu::{RegisterType, CPU_REGISTERS};
+
+/// Access to the emulator state needed to evaluate expressions
+pub trait EvalContext {
+    fn register(&mut self, reg: RegisterType) -> u32;
+    /// Read `bits` (8, 16 or 32) from `addr`
+    fn read_memory(&mut self, addr: u32, bits: u8) -> Option<u32>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    BitNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl BinaryOp {
+    /// Binding power, higher binds tighter
+    fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::BitOr => 3,
+            BinaryOp::BitXor => 4,
+            BinaryOp::BitAnd => 5,
+            BinaryOp::Eq | BinaryOp::Ne => 6,
+            BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => 7,
+            BinaryOp::Shl | BinaryOp::Shr => 8,
+            BinaryOp::Add | BinaryOp::Sub => 9,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 10,
+        }
+    }
+
+    fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Or => "||",
+            BinaryOp::And => "&&",
+            BinaryOp::BitOr => "|",
+            BinaryOp::BitXor => "^",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::Shl => "<<",
+            BinaryOp::Shr => ">>",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(u32),
+    Register(RegisterType),
+    Memory { bits: u8, addr: Box<Expr> },
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Number(n) => write!(f, "0x{:X}", n),
+            Expr::Register(r) => write!(f, "${}", r),
+            Expr::Memory { bits, addr } => write!(f, "u{}[{}]", bits, addr),
+            Expr::Unary(op, e) => {
+                let op = match op {
+                    UnaryOp::Neg => "-",
+                    UnaryOp::Not => "!",
+                    UnaryOp::BitNot => "~",
+                };
+                write!(f, "{}{}", op, e)
+            }
+            Expr::Binary(op, l, r) => write!(f, "({} {} {})", l, op.symbol(), r),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(u32),
+    Ident(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    // longer operators first
+    const OPS: [&str; 21] = [
+        "||", "&&", "==", "!=", "<=", ">=", "<<", ">>", "|", "^", "&", "<", ">", "+", "-", "*",
+        "/", "%", "!", "~", "$",
+    ];
+
+    let mut tokens = Vec::new();
+    let mut rest = input.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            let literal = &rest[..end];
+            let value = if let Some(hex) = literal
+                .strip_prefix("0x")
+                .or_else(|| literal.strip_prefix("0X"))
+            {
+                u32::from_str_radix(hex, 16)
+            } else {
+                literal.parse::<u32>()
+            }
+            .map_err(|_| format!("invalid number `{}`", literal))?;
+            tokens.push(Token::Number(value));
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else {
+            let token = match c {
+                '(' => Token::LParen,
+                ')' => Token::RParen,
+                '[' => Token::LBracket,
+                ']' => Token::RBracket,
+                _ => {
+                    let op = OPS
+                        .iter()
+                        .find(|op| rest.starts_with(**op))
+                        .ok_or_else(|| format!("unexpected character `{}`", c))?;
+                    Token::Op(*op)
+                }
+            };
+            let len = match &token {
+                Token::Op(op) => op.len(),
+                _ => 1,
+            };
+            tokens.push(token);
+            rest = &rest[len..];
+        }
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+/// Resolve `a0`, `r4`, `pc`... to a register
+pub fn parse_register(name: &str) -> Option<RegisterType> {
+    let name = name.to_ascii_lowercase();
+    if let Some(reg) = CPU_REGISTERS.get(name.as_str()) {
+        return Some(*reg);
+    }
+    let n = name.strip_prefix('r')?.parse::<u8>().ok()?;
+    (n < 32).then(|| RegisterType::from(n))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), String> {
+        match self.next() {
+            Some(t) if t == token => Ok(()),
+            Some(t) => Err(format!("expected {:?}, found {:?}", token, t)),
+            None => Err(format!("expected {:?}", token)),
+        }
+    }
+
+    fn peek_binary_op(&self) -> Option<BinaryOp> {
+        let Some(Token::Op(op)) = self.peek() else {
+            return None;
+        };
+        let op = match *op {
+            "||" => BinaryOp::Or,
+            "&&" => BinaryOp::And,
+            "|" => BinaryOp::BitOr,
+            "^" => BinaryOp::BitXor,
+            "&" => BinaryOp::BitAnd,
+            "==" => BinaryOp::Eq,
+            "!=" => BinaryOp::Ne,
+            "<" => BinaryOp::Lt,
+            "<=" => BinaryOp::Le,
+            ">" => BinaryOp::Gt,
+            ">=" => BinaryOp::Ge,
+            "<<" => BinaryOp::Shl,
+            ">>" => BinaryOp::Shr,
+            "+" => BinaryOp::Add,
+            "-" => BinaryOp::Sub,
+            "*" => BinaryOp::Mul,
+            "/" => BinaryOp::Div,
+            "%" => BinaryOp::Rem,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    /// Precedence climbing, parse operators binding tighter than `min_precedence`
+    fn binary(&mut self, min_precedence: u8) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        while let Some(op) = self.peek_binary_op() {
+            if op.precedence() <= min_precedence {
+                break;
+            }
+            self.pos += 1;
+            let rhs = self.binary(op.precedence())?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let op = match self.peek() {
+            Some(Token::Op("-")) => UnaryOp::Neg,
+            Some(Token::Op("!")) => UnaryOp::Not,
+            Some(Token::Op("~")) => UnaryOp::BitNot,
+            _ => return self.primary(),
+        };
+        self.pos += 1;
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn memory(&mut self, bits: u8) -> Result<Expr, String> {
+        self.expect(Token::LBracket)?;
+        let addr = self.binary(0)?;
+        self.expect(Token::RBracket)?;
+        Ok(Expr::Memory {
+            bits,
+            addr: Box::new(addr),
+        })
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::LParen) => {
+                let e = self.binary(0)?;
+                self.expect(Token::RParen)?;
+                Ok(e)
+            }
+            Some(Token::LBracket) => {
+                self.pos -= 1;
+                self.memory(32)
+            }
+            Some(Token::Op("$")) => match self.next() {
+                Some(Token::Ident(name)) => parse_register(&name)
+                    .map(Expr::Register)
+                    .ok_or_else(|| format!("unknown register `{}`", name)),
+                _ => Err("expected a register name after `$`".to_string()),
+            },
+            Some(Token::Ident(name)) => match name.as_str() {
+                "u8" => self.memory(8),
+                "u16" => self.memory(16),
+                "u32" => self.memory(32),
+                _ => parse_register(&name)
+                    .map(Expr::Register)
+                    .ok_or_else(|| format!("unknown register `{}`", name)),
+            },
+            Some(t) => Err(format!("unexpected {:?}", t)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+impl Expr {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            pos: 0,
+        };
+        let expr = parser.binary(0)?;
+        if let Some(t) = parser.peek() {
+            return Err(format!("unexpected {:?}", t));
+        }
+        Ok(expr)
+    }
+
+    pub fn eval<C: EvalContext>(&self, ctx: &mut C) -> Result<u32, String> {
+        self.eval_with_operands(ctx, &mut Vec::new())
+    }
+
+    /// Evaluate, and record the value of every register and memory read into `operands`,
+    /// used to show why a condition matched
+    pub fn eval_with_operands<C: EvalContext>(
+        &self,
+        ctx: &mut C,
+        operands: &mut Vec<(String, u32)>,
+    ) -> Result<u32, String> {
+        let value = match self {
+            Expr::Number(n) => *n,
+            Expr::Register(reg) => {
+                let value = ctx.register(*reg);
+                operands.push((self.to_string(), value));
+                value
+            }
+            Expr::Memory { bits, addr } => {
+                let addr = addr.eval_with_operands(ctx, operands)?;
+                let value = ctx
+                    .read_memory(addr, *bits)
+                    .ok_or_else(|| format!("could not read u{} at 0x{:08X}", bits, addr))?;
+                operands.push((format!("u{}[0x{:08X}]", bits, addr), value));
+                value
+            }
+            Expr::Unary(op, e) => {
+                let v = e.eval_with_operands(ctx, operands)?;
+                match op {
+                    UnaryOp::Neg => v.wrapping_neg(),
+                    UnaryOp::Not => (v == 0) as u32,
+                    UnaryOp::BitNot => !v,
+                }
+            }
+            Expr::Binary(op, l, r) => {
+                let l = l.eval_with_operands(ctx, operands)?;
+                // short circuit, so that memory reads behind a check are not performed
+                match op {
+                    BinaryOp::And if l == 0 => return Ok(0),
+                    BinaryOp::Or if l != 0 => return Ok(1),
+                    _ => {}
+                }
+                let r = r.eval_with_operands(ctx, operands)?;
+                match op {
+                    BinaryOp::Or | BinaryOp::And => (r != 0) as u32,
+                    BinaryOp::BitOr => l | r,
+                    BinaryOp::BitXor => l ^ r,
+                    BinaryOp::BitAnd => l & r,
+                    BinaryOp::Eq => (l == r) as u32,
+                    BinaryOp::Ne => (l != r) as u32,
+                    BinaryOp::Lt => (l < r) as u32,
+                    BinaryOp::Le => (l <= r) as u32,
+                    BinaryOp::Gt => (l > r) as u32,
+                    BinaryOp::Ge => (l >= r) as u32,
+                    BinaryOp::Shl => l.wrapping_shl(r),
+                    BinaryOp::Shr => l.wrapping_shr(r),
+                    BinaryOp::Add => l.wrapping_add(r),
+                    BinaryOp::Sub => l.wrapping_sub(r),
+                    BinaryOp::Mul => l.wrapping_mul(r),
+                    BinaryOp::Div => l.checked_div(r).ok_or("division by zero")?,
+                    BinaryOp::Rem => l.checked_rem(r).ok_or("division by zero")?,
+                }
+            }
+        };
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestContext;
+
+    impl EvalContext for TestContext {
+        fn register(&mut self, reg: RegisterType) -> u32 {
+            match reg {
+                RegisterType::A0 => 5,
+                RegisterType::Sp => 0x801F_FF00,
+                _ => 0,
+            }
+        }
+
+        fn read_memory(&mut self, addr: u32, bits: u8) -> Option<u32> {
+            match (addr, bits) {
+                (0x801F_FF00, 32) => Some(0x1234_5678),
+                (0x801F_FF00, 16) => Some(0x5678),
+                (0x801F_FF00, 8) => Some(0x78),
+                _ => None,
+            }
+        }
+    }
+
+    fn eval(input: &str) -> Result<u32, String> {
+        Expr::parse(input)?.eval(&mut TestContext)
+    }
+
+    #[test]
+    fn parse_numbers_and_registers() {
+        assert_eq!(Expr::parse("0x10").unwrap(), Expr::Number(16));
+        assert_eq!(Expr::parse("10").unwrap(), Expr::Number(10));
+        assert_eq!(Expr::parse("a0").unwrap(), Expr::Register(RegisterType::A0));
+        assert_eq!(
+            Expr::parse("$A0").unwrap(),
+            Expr::Register(RegisterType::A0)
+        );
+        assert_eq!(Expr::parse("r4").unwrap(), Expr::Register(RegisterType::A0));
+        assert_eq!(parse_register("r31"), Some(RegisterType::Ra));
+        assert_eq!(parse_register("r32"), None);
+
+        assert!(Expr::parse("0xZZ").is_err());
+        assert!(Expr::parse("foo").is_err());
+        assert!(Expr::parse("1 +").is_err());
+        assert!(Expr::parse("(1").is_err());
+        assert!(Expr::parse("1 2").is_err());
+        assert!(Expr::parse("1 @ 2").is_err());
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("10 - 4 - 3"), Ok(3));
+        assert_eq!(eval("1 << 2 + 1"), Ok(8));
+        assert_eq!(eval("1 | 2 & 3 == 3"), Ok(1));
+        assert_eq!(eval("r4 == 0x5 && sp > 0x80000000"), Ok(1));
+        assert_eq!(eval("r4 == 4 || r4 == 5"), Ok(1));
+        assert_eq!(eval("!r4"), Ok(0));
+        assert_eq!(eval("-1"), Ok(u32::MAX));
+        assert_eq!(eval("~0 == -1"), Ok(1));
+        assert_eq!(
+            Expr::parse("1 + 2 * 3 == 7").unwrap().to_string(),
+            "((0x1 + (0x2 * 0x3)) == 0x7)"
+        );
+    }
+
+    #[test]
+    fn memory_reads() {
+        assert_eq!(eval("[sp]"), Ok(0x1234_5678));
+        assert_eq!(eval("u16[sp] == 0x5678"), Ok(1));
+        assert_eq!(eval("u8[sp + 0]"), Ok(0x78));
+        assert!(eval("[0]").is_err());
+        // not evaluated because of the short circuit
+        assert_eq!(eval("0 && [0]"), Ok(0));
+        assert!(eval("1 / 0").is_err());
+    }
+
+    #[test]
+    fn operands() {
+        let expr = Expr::parse("a0 == 5 && u8[sp] == 0x78").unwrap();
+        let mut operands = Vec::new();
+        assert_eq!(
+            expr.eval_with_operands(&mut TestContext, &mut operands),
+            Ok(1)
+        );
+        assert_eq!(
+            operands,
+            [
+                ("$a0".to_string(), 5),
+                ("$sp".to_string(), 0x801F_FF00),
+                ("u8[0x801FFF00]".to_string(), 0x78),
+            ]
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! Symbol files, used to show and accept function names in the debugger.
+//!
+//! Supported formats:
+//! - nocash `.sym`: one `<hex address> <name>` per line, `;` starts a comment.
+//! - map files: GNU ld (`0x0000000080010000    main` lines in the memory map),
+//!   and PSYQ (`  80010000 main` lines in the symbol tables).
+//!
+//! When more than one symbol has the same address, the first one in the file is
+//! used for display, the others can still be used by name. If a name is duplicated,
+//! the first address is kept.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+};
+
+/// A symbol further than this from an address is not used to describe it,
+/// otherwise data after the last function would show as `func+0x12345`
+const MAX_SYMBOL_OFFSET: u32 = 0x10000;
+
+#[derive(Debug, Default, Clone)]
+pub struct Symbols {
+    by_address: BTreeMap<u32, String>,
+    by_name: HashMap<String, u32>,
+}
+
+fn is_symbol_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$'))
+}
+
+fn parse_hex(s: &str) -> Option<u32> {
+    let s = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+    // GNU ld prints 64-bit addresses
+    u64::from_str_radix(s, 16)
+        .ok()
+        .and_then(|v| u32::try_from(v).ok())
+}
+
+impl Symbols {
+    /// Parse a nocash `.sym` file
+    pub fn parse_sym(content: &str) -> Self {
+        let mut symbols = Self::default();
+        for line in content.lines() {
+            let line = line.split(';').next().unwrap();
+            let mut parts = line.split_whitespace();
+            let (Some(addr), Some(name), None) = (parts.next(), parts.next(), parts.next()) else {
+                continue;
+            };
+            // `.word`, `.byte`... describe data, not names
+            if let (Some(addr), true) = (parse_hex(addr), is_symbol_name(name)) {
+                symbols.insert(addr, name);
+            }
+        }
+        symbols
+    }
+
+    /// Parse a GNU ld or PSYQ map file
+    pub fn parse_map(content: &str) -> Self {
+        let mut symbols = Self::default();
+        for line in content.lines() {
+            // symbols are indented, sections and headers are not
+            if !line.starts_with(char::is_whitespace) {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            // section lines have the size and the object file after the address,
+            // and assignments (`. = ALIGN (0x4)`, `_end = .`) have more parts too
+            let (Some(addr), Some(name), None) = (parts.next(), parts.next(), parts.next()) else {
+                continue;
+            };
+            let is_gnu = addr.starts_with("0x");
+            // PSYQ addresses are always 8 digits, which skips the size columns
+            if !is_gnu && addr.len() != 8 {
+                continue;
+            }
+            if let (Some(addr), true) = (parse_hex(addr), is_symbol_name(name)) {
+                symbols.insert(addr, name);
+            }
+        }
+        symbols
+    }
+
+    /// Load a symbol file, the format is selected by the extension,
+    /// `.sym` for nocash, and map otherwise
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let is_sym = path
+            .extension()
+            .map(|e| e.eq_ignore_ascii_case("sym"))
+            .unwrap_or(false);
+
+        let symbols = if is_sym {
+            Self::parse_sym(&content)
+        } else {
+            Self::parse_map(&content)
+        };
+        if symbols.is_empty() {
+            return Err(format!("No symbols found in {}", path.display()));
+        }
+        Ok(symbols)
+    }
+
+    fn insert(&mut self, addr: u32, name: &str) {
+        self.by_address
+            .entry(addr)
+            .or_insert_with(|| name.to_string());
+        self.by_name.entry(name.to_string()).or_insert(addr);
+    }
+
+    /// Add all the symbols of `other`, the existing ones take priority
+    pub fn extend(&mut self, other: Symbols) {
+        for (addr, name) in other.by_address {
+            self.insert(addr, &name);
+        }
+        for (name, addr) in other.by_name {
+            self.by_name.entry(name).or_insert(addr);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    pub fn address_of(&self, name: &str) -> Option<u32> {
+        self.by_name.get(name).copied()
+    }
+
+    /// The symbol at `addr` or before it, as `name` or `name+0x10`
+    pub fn describe(&self, addr: u32) -> Option<String> {
+        let (&start, name) = self.by_address.range(..=addr).next_back()?;
+        match addr - start {
+            0 => Some(name.clone()),
+            offset if offset < MAX_SYMBOL_OFFSET => Some(format!("{}+0x{:X}", name, offset)),
+            _ => None,
+        }
+    }
+
+    /// `addr` followed by its symbol if any, like `0x80010010 <main+0x10>`
+    pub fn format_address(&self, addr: u32) -> String {
+        match self.describe(addr) {
+            Some(symbol) => format!("0x{:08X} <{}>", addr, symbol),
+            None => format!("0x{:08X}", addr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nocash_sym() {
+        let symbols = Symbols::parse_sym(
+            "; generated by the linker\n\
+             80010000 main\n\
+             80010040 DrawSync ; libgpu\n\
+             80010040 DrawSync_alias\n\
+             80020000 .word:0004\n\
+             garbage line here\n",
+        );
+        assert_eq!(symbols.len(), 3);
+        assert_eq!(symbols.address_of("main"), Some(0x80010000));
+        assert_eq!(symbols.address_of("DrawSync_alias"), Some(0x80010040));
+        // the first symbol is used for display
+        assert_eq!(symbols.describe(0x80010040).unwrap(), "DrawSync");
+        assert_eq!(symbols.describe(0x80010050).unwrap(), "DrawSync+0x10");
+        assert_eq!(symbols.describe(0x8000FFFC), None);
+        assert_eq!(symbols.describe(0x80030000), None);
+    }
+
+    #[test]
+    fn gnu_ld_map() {
+        let symbols = Symbols::parse_map(
+            "Memory Configuration\n\
+             \n\
+             .text           0x0000000080010000     0x1234\n \
+             .text          0x0000000080010000      0x100 main.o\n                \
+             0x0000000080010000                main\n                \
+             0x0000000080010080                update\n                \
+             0x0000000080010100                . = ALIGN (0x4)\n                \
+             0x0000000080011234                _etext = .\n",
+        );
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols.address_of("main"), Some(0x80010000));
+        assert_eq!(symbols.address_of("update"), Some(0x80010080));
+        assert_eq!(
+            symbols.format_address(0x80010084),
+            "0x80010084 <update+0x4>"
+        );
+    }
+
+    #[test]
+    fn psyq_map() {
+        let symbols = Symbols::parse_map(
+            "  Start     Stop   Length      Obj Group            Section name\n  \
+             80010000 8001FFFF 0000FFFF 80010000 text             .text\n\
+             \n  \
+             Address  Names alphabetically\n  \
+             80010040 DrawSync\n  \
+             80010000 main\n\
+             \n  \
+             Address  Names in address order\n  \
+             80010000 main\n  \
+             80010040 DrawSync\n",
+        );
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols.address_of("DrawSync"), Some(0x80010040));
+        assert_eq!(symbols.describe(0x80010000).unwrap(), "main");
+    }
+}
@@ -0,0 +1,418 @@
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+use clap::ValueEnum;
+use vulkano::swapchain::PresentMode;
+use winit::monitor::VideoMode;
+
+/// How far the display refresh rate can be from the emulated frame rate, relative,
+/// for the emulation to be paced by the display instead of sleeping.
+/// Covers the `~59.82Hz` NTSC and `~49.75Hz` PAL of the console on `60Hz` and `50Hz`
+/// displays, with the adjustment of the audio sync
+const REFRESH_TOLERANCE: f64 = 0.01;
+/// How far a video mode refresh rate can be from the one asked for, in Hz
+const VIDEO_MODE_REFRESH_TOLERANCE: f64 = 0.5;
+
+/// The exclusive fullscreen mode from `--display-mode`, `WxH` or `WxH@Hz`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplayModeArg {
+    pub width: u32,
+    pub height: u32,
+    /// The highest one of the resolution without it
+    pub refresh_hz: Option<f64>,
+}
+
+impl FromStr for DisplayModeArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid display mode {:?}, expected WxH@Hz", s);
+
+        let (size, refresh) = match s.split_once('@') {
+            Some((size, refresh)) => (size, Some(refresh)),
+            None => (s, None),
+        };
+        let (width, height) = size.split_once(['x', 'X']).ok_or_else(invalid)?;
+        let width = width.trim().parse().map_err(|_| invalid())?;
+        let height = height.trim().parse().map_err(|_| invalid())?;
+        let refresh_hz = match refresh {
+            Some(refresh) => {
+                let refresh = refresh.trim().trim_end_matches("Hz");
+                let hz: f64 = refresh.parse().map_err(|_| invalid())?;
+                if hz <= 0.0 || !hz.is_finite() {
+                    return Err(invalid());
+                }
+                Some(hz)
+            }
+            None => None,
+        };
+        if width == 0 || height == 0 {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            width,
+            height,
+            refresh_hz,
+        })
+    }
+}
+
+impl fmt::Display for DisplayModeArg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)?;
+        match self.refresh_hz {
+            Some(refresh_hz) => write!(f, "@{}", refresh_hz),
+            None => Ok(()),
+        }
+    }
+}
+
+/// What is needed to choose a [`VideoMode`], which can't be created outside winit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VideoModeInfo {
+    pub width: u32,
+    pub height: u32,
+    pub refresh_millihertz: u32,
+    pub bit_depth: u16,
+}
+
+impl From<&VideoMode> for VideoModeInfo {
+    fn from(mode: &VideoMode) -> Self {
+        Self {
+            width: mode.size().width,
+            height: mode.size().height,
+            refresh_millihertz: mode.refresh_rate_millihertz(),
+            bit_depth: mode.bit_depth(),
+        }
+    }
+}
+
+impl VideoModeInfo {
+    pub fn refresh_hz(&self) -> f64 {
+        self.refresh_millihertz as f64 / 1000.0
+    }
+}
+
+/// The index of the mode with the size of `wanted`, and the closest refresh rate
+/// to it, or the highest one if it doesn't have one. The higher bit depth wins
+/// between modes with the same refresh rate.
+///
+/// `None` if no mode has the size, or the refresh rate is not close to any of them
+pub fn best_video_mode(modes: &[VideoModeInfo], wanted: &DisplayModeArg) -> Option<usize> {
+    let same_size = modes
+        .iter()
+        .enumerate()
+        .filter(|(_, mode)| mode.width == wanted.width && mode.height == wanted.height);
+
+    match wanted.refresh_hz {
+        Some(refresh_hz) => same_size
+            .filter(|(_, mode)| {
+                (mode.refresh_hz() - refresh_hz).abs() <= VIDEO_MODE_REFRESH_TOLERANCE
+            })
+            .min_by(|(_, a), (_, b)| {
+                let distance = |mode: &VideoModeInfo| (mode.refresh_hz() - refresh_hz).abs();
+                distance(a)
+                    .total_cmp(&distance(b))
+                    .then(b.bit_depth.cmp(&a.bit_depth))
+            })
+            .map(|(i, _)| i),
+        None => same_size
+            .max_by_key(|(_, mode)| (mode.refresh_millihertz, mode.bit_depth))
+            .map(|(i, _)| i),
+    }
+}
+
+/// The present mode from `--present-mode`
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresentModeArg {
+    /// `mailbox` if supported, or `relaxed` with `--vrr`
+    Auto,
+    /// Wait for vsync, the emulation is paced by the display if its refresh rate
+    /// is the same as the emulated one
+    Fifo,
+    /// Replace the queued frame, no tearing but the frame may wait for vsync
+    Mailbox,
+    /// Present right away, with tearing
+    Immediate,
+    /// Like `fifo`, but a late frame is presented right away, with tearing
+    Relaxed,
+}
+
+/// The present mode to use from the `available` ones, `vrr` is set when the
+/// display has a variable refresh rate.
+///
+/// With VRR, the display follows the presents, so `relaxed` and `fifo` don't
+/// add latency, while `mailbox` still waits for the next refresh. A mode that
+/// is not available falls back to `fifo`, which all drivers support
+pub fn choose_present_mode(
+    available: &[PresentMode],
+    arg: PresentModeArg,
+    vrr: bool,
+) -> PresentMode {
+    let preference = |mode: PresentMode| -> u32 {
+        match (mode, vrr) {
+            (PresentMode::FifoRelaxed, true) => 0,
+            (PresentMode::Fifo, true) => 1,
+            (PresentMode::Mailbox, _) => 2,
+            (PresentMode::Immediate, _) => 3,
+            (PresentMode::Fifo, false) => 4,
+            (PresentMode::FifoRelaxed, false) => 5,
+            _ => 6,
+        }
+    };
+
+    let wanted = match arg {
+        PresentModeArg::Auto => {
+            return available
+                .iter()
+                .copied()
+                .min_by_key(|&mode| preference(mode))
+                .unwrap_or(PresentMode::Fifo);
+        }
+        PresentModeArg::Fifo => PresentMode::Fifo,
+        PresentModeArg::Mailbox => PresentMode::Mailbox,
+        PresentModeArg::Immediate => PresentMode::Immediate,
+        PresentModeArg::Relaxed => PresentMode::FifoRelaxed,
+    };
+    if available.contains(&wanted) {
+        wanted
+    } else {
+        log::warn!(
+            "The present mode {:?} is not supported, using Fifo instead",
+            wanted
+        );
+        PresentMode::Fifo
+    }
+}
+
+/// If the emulation running at `target_fps` can be paced by the display refreshing
+/// at `refresh_hz`
+pub fn refresh_matches(refresh_hz: f64, target_fps: f64) -> bool {
+    ((refresh_hz - target_fps) / target_fps).abs() <= REFRESH_TOLERANCE
+}
+
+/// Counts the frames presented by the UI thread, so the emulation thread can
+/// wait for the display instead of sleeping
+#[derive(Clone, Default)]
+pub struct PresentSignal {
+    count: Arc<(Mutex<u64>, Condvar)>,
+}
+
+impl PresentSignal {
+    /// Called after a frame is queued for presentation
+    pub fn presented(&self) {
+        let (count, condvar) = &*self.count;
+        *count.lock().unwrap() += 1;
+        condvar.notify_all();
+    }
+
+    /// Wait until more than `seen` frames were presented, or `timeout` passes if
+    /// the window is not presenting (e.g. minimized). Returns the frames presented
+    pub fn wait_after(&self, seen: u64, timeout: Duration) -> u64 {
+        let (count, condvar) = &*self.count;
+        let count = condvar
+            .wait_timeout_while(count.lock().unwrap(), timeout, |count| *count <= seen)
+            .unwrap()
+            .0;
+        *count
+    }
+}
+
+/// Pace the emulation by the presents of the UI thread, when the present mode
+/// waits for vsync, without VRR
+#[derive(Clone)]
+pub struct VsyncPacing {
+    pub signal: PresentSignal,
+    pub refresh_hz: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mode(width: u32, height: u32, refresh_millihertz: u32, bit_depth: u16) -> VideoModeInfo {
+        VideoModeInfo {
+            width,
+            height,
+            refresh_millihertz,
+            bit_depth,
+        }
+    }
+
+    #[test]
+    fn parse_display_mode() {
+        assert_eq!(
+            "1920x1080@59.94".parse(),
+            Ok(DisplayModeArg {
+                width: 1920,
+                height: 1080,
+                refresh_hz: Some(59.94)
+            })
+        );
+        assert_eq!(
+            "640X480@60Hz".parse(),
+            Ok(DisplayModeArg {
+                width: 640,
+                height: 480,
+                refresh_hz: Some(60.0)
+            })
+        );
+        assert_eq!(
+            "2560x1440".parse(),
+            Ok(DisplayModeArg {
+                width: 2560,
+                height: 1440,
+                refresh_hz: None
+            })
+        );
+        assert_eq!(
+            "1920x1080@59.94"
+                .parse::<DisplayModeArg>()
+                .unwrap()
+                .to_string(),
+            "1920x1080@59.94"
+        );
+        for invalid in [
+            "",
+            "1920",
+            "1920x",
+            "x1080",
+            "0x480",
+            "1920x1080@",
+            "1920x1080@-60",
+        ] {
+            assert!(invalid.parse::<DisplayModeArg>().is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn video_mode_matching() {
+        let modes = [
+            mode(1920, 1080, 60000, 24),
+            mode(1920, 1080, 59940, 24),
+            mode(1920, 1080, 59940, 32),
+            mode(1920, 1080, 144000, 32),
+            mode(1280, 720, 60000, 32),
+        ];
+        let wanted = |width, height, refresh_hz| DisplayModeArg {
+            width,
+            height,
+            refresh_hz,
+        };
+
+        assert_eq!(
+            best_video_mode(&modes, &wanted(1920, 1080, Some(60.0))),
+            Some(0)
+        );
+        // the closest, with the higher bit depth
+        assert_eq!(
+            best_video_mode(&modes, &wanted(1920, 1080, Some(59.94))),
+            Some(2)
+        );
+        assert_eq!(
+            best_video_mode(&modes, &wanted(1920, 1080, Some(143.9))),
+            Some(3)
+        );
+        // 59.94 and 60 are both close, 60 is closer
+        assert_eq!(
+            best_video_mode(&modes, &wanted(1920, 1080, Some(59.98))),
+            Some(0)
+        );
+        assert_eq!(best_video_mode(&modes, &wanted(1920, 1080, None)), Some(3));
+        assert_eq!(
+            best_video_mode(&modes, &wanted(1280, 720, Some(59.94))),
+            Some(4)
+        );
+
+        assert_eq!(
+            best_video_mode(&modes, &wanted(1920, 1080, Some(75.0))),
+            None
+        );
+        assert_eq!(
+            best_video_mode(&modes, &wanted(1280, 720, Some(50.0))),
+            None
+        );
+        assert_eq!(best_video_mode(&modes, &wanted(800, 600, None)), None);
+        assert_eq!(best_video_mode(&[], &wanted(1920, 1080, None)), None);
+    }
+
+    #[test]
+    fn present_mode_selection() {
+        let all = [
+            PresentMode::Immediate,
+            PresentMode::Mailbox,
+            PresentMode::Fifo,
+            PresentMode::FifoRelaxed,
+        ];
+        let fifo_only = [PresentMode::Fifo];
+
+        assert_eq!(
+            choose_present_mode(&all, PresentModeArg::Auto, false),
+            PresentMode::Mailbox
+        );
+        assert_eq!(
+            choose_present_mode(&all, PresentModeArg::Auto, true),
+            PresentMode::FifoRelaxed
+        );
+        assert_eq!(
+            choose_present_mode(&all[..3], PresentModeArg::Auto, true),
+            PresentMode::Fifo
+        );
+        assert_eq!(
+            choose_present_mode(&all[..1], PresentModeArg::Auto, false),
+            PresentMode::Immediate
+        );
+        assert_eq!(
+            choose_present_mode(&fifo_only, PresentModeArg::Auto, false),
+            PresentMode::Fifo
+        );
+
+        assert_eq!(
+            choose_present_mode(&all, PresentModeArg::Immediate, true),
+            PresentMode::Immediate
+        );
+        assert_eq!(
+            choose_present_mode(&all, PresentModeArg::Relaxed, false),
+            PresentMode::FifoRelaxed
+        );
+        assert_eq!(
+            choose_present_mode(&fifo_only, PresentModeArg::Mailbox, false),
+            PresentMode::Fifo
+        );
+    }
+
+    #[test]
+    fn refresh_rate_matching() {
+        // NTSC and PAL of the console
+        assert!(refresh_matches(60.0, 59.817));
+        assert!(refresh_matches(59.94, 59.817));
+        assert!(refresh_matches(50.0, 49.747));
+        assert!(!refresh_matches(60.0, 50.0));
+        assert!(!refresh_matches(120.0, 59.94));
+        assert!(!refresh_matches(75.0, 59.94));
+        // the audio sync adjusts the speed by up to 0.5%
+        assert!(refresh_matches(60.0, 59.817 * 0.995));
+        assert!(!refresh_matches(60.0, 59.817 * 0.98));
+    }
+
+    #[test]
+    fn present_signal_wait() {
+        let signal = PresentSignal::default();
+        assert_eq!(signal.wait_after(0, Duration::from_millis(1)), 0);
+
+        let presenter = signal.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            presenter.presented();
+        });
+        assert_eq!(signal.wait_after(0, Duration::from_secs(10)), 1);
+        thread.join().unwrap();
+        // already presented
+        assert_eq!(signal.wait_after(0, Duration::from_secs(10)), 1);
+    }
+}
@@ -0,0 +1,538 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender, TryRecvError, TrySendError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use trapezoid_core::{cpu::CpuState, DigitalControllerKey, PerfFrameReport, Psx};
+use vulkano::{
+    device::{Device, Queue},
+    image::Image,
+};
+use winit::event_loop::EventLoopProxy;
+
+use crate::{audio::AudioSync, crash, display_mode::VsyncPacing, video_record::VideoRecorder, Fps};
+
+#[cfg(feature = "debugger")]
+use crate::debugger::Debugger;
+
+#[cfg(not(feature = "debugger"))]
+struct Debugger;
+
+#[cfg(not(feature = "debugger"))]
+impl Debugger {
+    fn new() -> Self {
+        Self {}
+    }
+
+    fn set_enabled(&mut self, _enabled: bool) {}
+
+    fn enabled(&self) -> bool {
+        false
+    }
+
+    fn run(&mut self, _psx: &mut Psx) {}
+
+    fn handle_cpu_state(&mut self, _psx: &mut Psx, _cpu_state: CpuState) {}
+}
+
+/// How often the event loop is woken while paused, so the display and the OSD
+/// keep updating without new frames
+const PAUSED_REDRAW_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Whether the emulation thread advances the emulation, the debugger stops it
+/// on its own regardless of this
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunState {
+    Running,
+    Paused,
+    /// Run one video frame, then go back to [`RunState::Paused`]
+    StepOneFrame,
+}
+
+/// Commands from the UI thread to the emulation thread
+pub enum EmuCommand {
+    /// `player` is the port, or the multitap pad with `multitap`
+    ControllerKey {
+        player: usize,
+        key: DigitalControllerKey,
+        pressed: bool,
+    },
+    ControllerConnected {
+        player: usize,
+        connected: bool,
+    },
+    ShellOpen(bool),
+    /// Replace the disk, the shell must be open
+    SwapDisk(PathBuf),
+    /// Restart with a new emulator running this `.cue` or `.exe`
+    Load(PathBuf),
+    /// Reset the emulator and run this `.exe` again, after it was rebuilt
+    ReloadExe(PathBuf),
+    FullVramDisplay(bool),
+    /// Stop or resume advancing the emulation, without the debugger
+    SetPaused(bool),
+    /// Run exactly one video frame, only while paused
+    FrameAdvance,
+    /// Pause the emulation and start the debugger
+    DebuggerBreak,
+    DebuggerContinue,
+    Quit,
+}
+
+/// Results of the commands, from the emulation thread to the UI thread
+pub enum EmuEvent {
+    /// `swapped` is `true` if the disk was swapped, `false` if the emulator
+    /// was restarted. `disk_id` is [`Psx::disk_id`] of the new disk
+    DiskLoaded {
+        path: PathBuf,
+        swapped: bool,
+        disk_id: Option<String>,
+    },
+    /// The `.exe` was reloaded by [`EmuCommand::ReloadExe`]
+    ExeReloaded(PathBuf),
+    Error(String),
+    /// The emulation can't continue, the thread has stopped
+    Fatal(String),
+}
+
+pub struct EmuThreadOptions {
+    /// Used to create a new emulator on [`EmuCommand::Load`]
+    pub bios: Option<PathBuf>,
+    pub device: Arc<Device>,
+    pub queue: Arc<Queue>,
+    /// Send the front images to the UI thread, not needed in headless mode
+    pub produce_frames: bool,
+    pub full_vram_display: bool,
+    /// The players are the pads A-D of a multitap in the first port,
+    /// instead of the two ports
+    pub multitap: bool,
+    /// Where to send the audio buffers, if audio is enabled
+    pub audio_sender: Option<Sender<Vec<f32>>>,
+    /// Used to adjust the emulation speed to the audio output
+    pub audio_sync: Option<Arc<Mutex<AudioSync>>>,
+    /// Set to [`Psx::warning_count`] after every frame
+    pub warning_count: Arc<AtomicU64>,
+    /// Set to [`Psx::perf_frame_report`] after every frame
+    pub perf_report: Arc<Mutex<PerfFrameReport>>,
+    /// Gets every frame and its audio, finalized when the thread stops
+    pub video_recorder: Option<VideoRecorder>,
+    /// Used to wake the event loop when a new frame is ready
+    pub event_loop_proxy: Option<EventLoopProxy<()>>,
+    /// Pace the emulation by the presents of the window, instead of sleeping
+    pub vsync_pacing: Option<VsyncPacing>,
+    /// Panic after the first frame, to test the crash log
+    pub debug_panic: bool,
+}
+
+/// State owned by the emulation thread
+struct Emulator {
+    psx: Psx,
+    bios: Option<PathBuf>,
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    debugger: Debugger,
+    run_state: RunState,
+    fps: Fps,
+    full_vram_display: bool,
+    produce_frames: bool,
+    multitap: bool,
+    commands: Receiver<EmuCommand>,
+    events: Sender<EmuEvent>,
+    frames: SyncSender<Arc<Image>>,
+    audio_sender: Option<Sender<Vec<f32>>>,
+    audio_sync: Option<Arc<Mutex<AudioSync>>>,
+    warning_count: Arc<AtomicU64>,
+    perf_report: Arc<Mutex<PerfFrameReport>>,
+    video_recorder: Option<VideoRecorder>,
+    event_loop_proxy: Option<EventLoopProxy<()>>,
+    debug_panic: bool,
+}
+
+impl Emulator {
+    /// Returns `false` if the thread should stop
+    fn handle_command(&mut self, cmd: EmuCommand) -> bool {
+        match cmd {
+            EmuCommand::ControllerKey {
+                player,
+                key,
+                pressed,
+            } => {
+                let (port, pad) = self.player_address(player);
+                self.psx
+                    .change_multitap_controller_key_state(port, pad, key, pressed);
+            }
+            EmuCommand::ControllerConnected { player, connected } => {
+                let (port, pad) = self.player_address(player);
+                self.psx
+                    .set_multitap_controller_connected(port, pad, connected);
+            }
+            EmuCommand::ShellOpen(open) => self.psx.change_cdrom_shell_open_state(open),
+            EmuCommand::SwapDisk(path) => match self.psx.swap_disk(&path) {
+                Ok(()) => self.send_event(EmuEvent::DiskLoaded {
+                    path,
+                    swapped: true,
+                    disk_id: self.psx.disk_id().map(str::to_string),
+                }),
+                Err(e) => {
+                    log::error!("Could not swap the disk to {:?}: {}", path, e);
+                    self.send_event(EmuEvent::Error(format!("Could not swap the disk: {}", e)));
+                }
+            },
+            EmuCommand::Load(path) => self.reload(path),
+            EmuCommand::ReloadExe(path) => match self.psx.reload_exe(&path) {
+                Ok(()) => self.send_event(EmuEvent::ExeReloaded(path)),
+                // the old EXE is still running
+                Err(e) => {
+                    log::error!("Could not reload {:?}: {}", path, e);
+                    self.send_event(EmuEvent::Error(format!("Could not reload the EXE: {}", e)));
+                }
+            },
+            EmuCommand::FullVramDisplay(full_vram) => self.full_vram_display = full_vram,
+            EmuCommand::SetPaused(paused) => {
+                if paused {
+                    self.run_state = RunState::Paused;
+                } else {
+                    self.run_state = RunState::Running;
+                    // the time spent paused is not a slow frame
+                    self.fps.restart();
+                }
+                if let Some(audio_sync) = &self.audio_sync {
+                    audio_sync.lock().unwrap().set_paused(paused);
+                }
+            }
+            EmuCommand::FrameAdvance => {
+                if self.run_state == RunState::Paused {
+                    self.run_state = RunState::StepOneFrame;
+                }
+            }
+            EmuCommand::DebuggerBreak => {
+                if cfg!(feature = "debugger") {
+                    println!("{:?}", self.psx.cpu().registers());
+                }
+                self.debugger.set_enabled(true);
+            }
+            EmuCommand::DebuggerContinue => self.debugger.set_enabled(false),
+            EmuCommand::Quit => return false,
+        }
+        true
+    }
+
+    fn send_event(&self, event: EmuEvent) {
+        self.events.send(event).ok();
+        if let Some(proxy) = &self.event_loop_proxy {
+            proxy.send_event(()).ok();
+        }
+    }
+
+    /// Replace the emulator with a new one running `path`, with the same BIOS,
+    /// config and options.
+    ///
+    /// The new emulator is created first, so the current game keeps running if
+    /// it fails. The front images already sent hold their own reference to the
+    /// image, so the UI can keep presenting the last one until the new emulator
+    /// renders, and the old GPU thread stops by itself when it is dropped.
+    fn reload(&mut self, path: PathBuf) {
+        let mut psx = match Psx::new(
+            self.bios.as_deref(),
+            Some(&path),
+            *self.psx.config(),
+            self.device.clone(),
+            self.queue.clone(),
+        ) {
+            Ok(psx) => psx,
+            Err(e) => {
+                log::error!("Could not load {:?}: {}", path, e);
+                self.send_event(EmuEvent::Error(format!("Could not load the file: {}", e)));
+                return;
+            }
+        };
+
+        psx.set_gpu_render_options(self.psx.gpu_render_options());
+        psx.set_multitap(0, self.multitap);
+        #[cfg(feature = "scripting")]
+        if let Some(script) = self.psx.detach_script_engine() {
+            psx.attach_script_engine(script);
+        }
+        self.psx = psx;
+
+        self.send_event(EmuEvent::DiskLoaded {
+            path,
+            swapped: false,
+            disk_id: self.psx.disk_id().map(str::to_string),
+        });
+    }
+
+    /// The port and the multitap pad of `player`
+    fn player_address(&self, player: usize) -> (usize, usize) {
+        if self.multitap {
+            (0, player)
+        } else {
+            (player, 0)
+        }
+    }
+
+    /// Decide if this iteration should run a frame of the emulation
+    fn should_emulate(&mut self) -> bool {
+        if self.debugger.enabled() {
+            return false;
+        }
+        match self.run_state {
+            RunState::Running => true,
+            RunState::Paused => false,
+            RunState::StepOneFrame => {
+                self.run_state = RunState::Paused;
+                true
+            }
+        }
+    }
+
+    /// Wait for the next command while paused, and wake the event loop
+    /// periodically so that the display keeps updating.
+    ///
+    /// Returns `false` if the thread should stop
+    fn wait_while_paused(&mut self) -> bool {
+        match self.commands.recv_timeout(PAUSED_REDRAW_INTERVAL) {
+            Ok(cmd) => self.handle_command(cmd),
+            Err(RecvTimeoutError::Timeout) => {
+                if let Some(proxy) = &self.event_loop_proxy {
+                    proxy.send_event(()).ok();
+                }
+                true
+            }
+            // the UI is gone
+            Err(RecvTimeoutError::Disconnected) => false,
+        }
+    }
+
+    /// After a panic in [`Emulator::run`], write the crash log and stop the UI
+    fn report_panic(&mut self) {
+        let message = crash::take_panic_message();
+        let report = match crash::write_crash_log(&message, &mut self.psx) {
+            Ok(path) => format!("crash log written to {}", path.display()),
+            Err(e) => format!("could not write the crash log: {}", e),
+        };
+        self.send_event(EmuEvent::Fatal(format!(
+            "The emulation thread panicked, {}",
+            report
+        )));
+    }
+
+    fn run(&mut self) {
+        loop {
+            loop {
+                match self.commands.try_recv() {
+                    Ok(cmd) => {
+                        if !self.handle_command(cmd) {
+                            return;
+                        }
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    // the UI is gone
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            }
+
+            if self.run_state == RunState::Paused && !self.debugger.enabled() {
+                if !self.wait_while_paused() {
+                    return;
+                }
+                continue;
+            }
+
+            let emulate = self.should_emulate();
+            // frame advances are not paced, and don't count for the fps
+            if self.run_state == RunState::Running {
+                // one video frame at a time, so the emulated and real time match in
+                // both video modes, and the audio buffer neither drains nor grows
+                let speed = match &self.audio_sync {
+                    Some(audio_sync) => audio_sync.lock().unwrap().emulation_speed(),
+                    None => 1.,
+                };
+                self.fps.set_target_fps(self.psx.frame_rate() * speed);
+                self.fps.lock();
+                self.fps.tick();
+            }
+
+            // if the debugger is enabled, we don't run the emulation
+            if emulate {
+                // breakpoints with a false condition don't stop the debugger,
+                // so keep going until the frame is done
+                loop {
+                    let (frame_done, cpu_state) = self.psx.clock_based_on_video(u32::MAX);
+                    if let CpuState::GpuError { error, recovered } = cpu_state {
+                        if !recovered {
+                            self.send_event(EmuEvent::Fatal(format!("GPU error: {}", error)));
+                            return;
+                        }
+                        self.send_event(EmuEvent::Error(format!(
+                            "GPU error: {}, the VRAM was reset",
+                            error
+                        )));
+                    }
+                    self.debugger.handle_cpu_state(&mut self.psx, cpu_state);
+                    if frame_done || self.debugger.enabled() {
+                        break;
+                    }
+                }
+                self.warning_count
+                    .store(self.psx.warning_count(), Ordering::Relaxed);
+                *self.perf_report.lock().unwrap() = self.psx.perf_frame_report();
+
+                // taken even when muted, so that it doesn't grow
+                let mut audio_buffer = Vec::new();
+                self.psx.take_audio_buffer_into(&mut audio_buffer);
+                if let Some(recorder) = &mut self.video_recorder {
+                    recorder.push(
+                        self.psx.emulated_time(),
+                        self.psx.read_display_rgba(),
+                        audio_buffer.clone(),
+                    );
+                }
+                let muted = self
+                    .audio_sync
+                    .as_ref()
+                    .is_some_and(|audio_sync| audio_sync.lock().unwrap().muted());
+                if let Some(audio_sender) = &self.audio_sender {
+                    if !muted {
+                        audio_sender.send(audio_buffer).ok();
+                    }
+                }
+
+                if self.debug_panic {
+                    panic!("forced by --debug-panic");
+                }
+            }
+
+            // keep sending frames even when the debugger is running so that
+            // we don't hang the display
+            if self.produce_frames {
+                if let Some(image) = self.psx.take_front_image(self.full_vram_display) {
+                    match self.frames.try_send(image) {
+                        Ok(()) => {
+                            if let Some(proxy) = &self.event_loop_proxy {
+                                proxy.send_event(()).ok();
+                            }
+                        }
+                        // the UI thread is busy, drop the frame
+                        Err(TrySendError::Full(_)) => {}
+                        Err(TrySendError::Disconnected(_)) => return,
+                    }
+                }
+            }
+
+            if self.debugger.enabled() {
+                self.debugger.run(&mut self.psx);
+            }
+        }
+    }
+}
+
+/// Runs the emulation on its own thread, so that the emulation and audio
+/// don't stall when the event loop is blocked (window drags, OS dialogs...).
+///
+/// The UI thread sends input with [`EmuThread::send`], and receives the rendered
+/// front images with [`EmuThread::latest_frame`].
+pub struct EmuThread {
+    commands: Sender<EmuCommand>,
+    events: Receiver<EmuEvent>,
+    frames: Receiver<Arc<Image>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl EmuThread {
+    pub fn spawn(psx: Psx, options: EmuThreadOptions) -> Self {
+        let (commands_sender, commands) = mpsc::channel();
+        let (events_sender, events) = mpsc::channel();
+        // a small buffer, if the UI can't keep up, the frames are dropped
+        let (frames_sender, frames) = mpsc::sync_channel(2);
+
+        let handle = thread::Builder::new()
+            .name("emulation".to_string())
+            .spawn(move || {
+                let mut fps = Fps::new();
+                fps.set_vsync_pacing(options.vsync_pacing);
+                let mut emulator = Emulator {
+                    psx,
+                    bios: options.bios,
+                    device: options.device,
+                    queue: options.queue,
+                    // created here, since it spawns its own editor thread
+                    debugger: Debugger::new(),
+                    run_state: RunState::Running,
+                    fps,
+                    full_vram_display: options.full_vram_display,
+                    produce_frames: options.produce_frames,
+                    multitap: options.multitap,
+                    commands,
+                    events: events_sender,
+                    frames: frames_sender,
+                    audio_sender: options.audio_sender,
+                    audio_sync: options.audio_sync,
+                    warning_count: options.warning_count,
+                    perf_report: options.perf_report,
+                    video_recorder: options.video_recorder,
+                    event_loop_proxy: options.event_loop_proxy,
+                    debug_panic: options.debug_panic,
+                };
+                // the psx is only read for the crash log after this
+                if panic::catch_unwind(AssertUnwindSafe(|| emulator.run())).is_err() {
+                    emulator.report_panic();
+                }
+            })
+            .expect("failed to spawn the emulation thread");
+
+        Self {
+            commands: commands_sender,
+            events,
+            frames,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn send(&self, cmd: EmuCommand) {
+        // the thread only stops on `Quit`, or on an error, which is reported
+        // with `EmuEvent::Fatal`
+        self.commands.send(cmd).ok();
+    }
+
+    pub fn set_controller_key(&self, player: usize, key: DigitalControllerKey, pressed: bool) {
+        self.send(EmuCommand::ControllerKey {
+            player,
+            key,
+            pressed,
+        });
+    }
+
+    /// The results of the commands since the last call
+    pub fn events(&self) -> impl Iterator<Item = EmuEvent> + '_ {
+        self.events.try_iter()
+    }
+
+    /// The most recent front image, if any new one was rendered since the last call
+    pub fn latest_frame(&self) -> Option<Arc<Image>> {
+        self.frames.try_iter().last()
+    }
+
+    /// Stop the emulation thread and wait for it
+    pub fn stop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            self.send(EmuCommand::Quit);
+            if handle.join().is_err() {
+                log::error!("The emulation thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for EmuThread {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
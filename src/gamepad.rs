@@ -0,0 +1,285 @@
+use std::{collections::HashMap, path::Path};
+
+use gilrs::{Axis, Button, EventType, GamepadId, Gilrs};
+use serde::Deserialize;
+use trapezoid_core::DigitalControllerKey;
+
+use crate::{
+    config::parse_digital_key,
+    emu_thread::{EmuCommand, EmuThread},
+};
+
+const DEFAULT_STICK_DPAD_THRESHOLD: f32 = 0.5;
+
+/// Mapping file format, all fields are optional and override the defaults.
+///
+/// ```toml
+/// stick_dpad_threshold = 0.6
+///
+/// [buttons]
+/// South = "Circle"
+/// East = "X"
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ControllerMapFile {
+    stick_dpad_threshold: Option<f32>,
+    #[serde(default)]
+    buttons: HashMap<String, String>,
+}
+
+pub struct ControllerMap {
+    buttons: HashMap<Button, DigitalControllerKey>,
+    /// How far the left stick should be pushed to emulate a D-pad press
+    stick_dpad_threshold: f32,
+}
+
+impl Default for ControllerMap {
+    fn default() -> Self {
+        let buttons = [
+            (Button::South, DigitalControllerKey::X),
+            (Button::East, DigitalControllerKey::Circle),
+            (Button::North, DigitalControllerKey::Triangle),
+            (Button::West, DigitalControllerKey::Square),
+            (Button::LeftTrigger, DigitalControllerKey::L1),
+            (Button::LeftTrigger2, DigitalControllerKey::L2),
+            (Button::RightTrigger, DigitalControllerKey::R1),
+            (Button::RightTrigger2, DigitalControllerKey::R2),
+            (Button::LeftThumb, DigitalControllerKey::L3),
+            (Button::RightThumb, DigitalControllerKey::R3),
+            (Button::Select, DigitalControllerKey::Select),
+            (Button::Start, DigitalControllerKey::Start),
+            (Button::DPadUp, DigitalControllerKey::Up),
+            (Button::DPadDown, DigitalControllerKey::Down),
+            (Button::DPadLeft, DigitalControllerKey::Left),
+            (Button::DPadRight, DigitalControllerKey::Right),
+        ]
+        .into_iter()
+        .collect();
+
+        Self {
+            buttons,
+            stick_dpad_threshold: DEFAULT_STICK_DPAD_THRESHOLD,
+        }
+    }
+}
+
+impl ControllerMap {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let file: ControllerMapFile = toml::from_str(&content).map_err(|e| e.to_string())?;
+
+        let mut map = Self::default();
+        if let Some(threshold) = file.stick_dpad_threshold {
+            if !(0.0..=1.0).contains(&threshold) {
+                return Err(format!(
+                    "stick_dpad_threshold must be between 0 and 1, got {}",
+                    threshold
+                ));
+            }
+            map.stick_dpad_threshold = threshold;
+        }
+        for (button, key) in file.buttons {
+            let button = parse_button(&button)
+                .ok_or_else(|| format!("unknown gamepad button `{}`", button))?;
+            let key = parse_digital_key(&key)
+                .ok_or_else(|| format!("unknown controller key `{}`", key))?;
+            map.buttons.insert(button, key);
+        }
+
+        Ok(map)
+    }
+}
+
+fn parse_button(name: &str) -> Option<Button> {
+    let button = match name {
+        "South" => Button::South,
+        "East" => Button::East,
+        "North" => Button::North,
+        "West" => Button::West,
+        "C" => Button::C,
+        "Z" => Button::Z,
+        "LeftTrigger" => Button::LeftTrigger,
+        "LeftTrigger2" => Button::LeftTrigger2,
+        "RightTrigger" => Button::RightTrigger,
+        "RightTrigger2" => Button::RightTrigger2,
+        "Select" => Button::Select,
+        "Start" => Button::Start,
+        "Mode" => Button::Mode,
+        "LeftThumb" => Button::LeftThumb,
+        "RightThumb" => Button::RightThumb,
+        "DPadUp" => Button::DPadUp,
+        "DPadDown" => Button::DPadDown,
+        "DPadLeft" => Button::DPadLeft,
+        "DPadRight" => Button::DPadRight,
+        _ => return None,
+    };
+    Some(button)
+}
+
+/// A gamepad assigned to one of the players
+struct ConnectedPad {
+    id: GamepadId,
+    /// The D-pad state emulated from the left stick `[up, down, left, right]`
+    stick_dpad: [bool; 4],
+}
+
+/// Handle gamepads and forward their input to the emulator.
+///
+/// Gamepads are assigned to the players in connection order, these are the two
+/// controller ports, or the 4 pads of the multitap. The first player is also
+/// controlled by the keyboard.
+pub struct Gamepads {
+    gilrs: Gilrs,
+    map: ControllerMap,
+    players: Vec<Option<ConnectedPad>>,
+}
+
+impl Gamepads {
+    pub fn new(map: ControllerMap, emu: &EmuThread, players: usize) -> Option<Self> {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => gilrs,
+            Err(gilrs::Error::NotImplemented(gilrs)) => {
+                log::warn!("Gamepads are not supported on this platform");
+                gilrs
+            }
+            Err(e) => {
+                log::error!("Failed to initialize gamepads: {}", e);
+                return None;
+            }
+        };
+
+        let mut s = Self {
+            gilrs,
+            map,
+            players: (0..players).map(|_| None).collect(),
+        };
+
+        // gamepads connected before startup don't produce `Connected` events
+        let ids = s.gilrs.gamepads().map(|(id, _)| id).collect::<Vec<_>>();
+        for id in ids {
+            s.connect(id, emu);
+        }
+
+        Some(s)
+    }
+
+    fn player_of(&self, id: GamepadId) -> Option<usize> {
+        self.players
+            .iter()
+            .position(|p| p.as_ref().map(|p| p.id) == Some(id))
+    }
+
+    /// Returns the message to show, if the gamepad was assigned to a player
+    fn connect(&mut self, id: GamepadId, emu: &EmuThread) -> Option<String> {
+        if self.player_of(id).is_some() {
+            return None;
+        }
+        let Some(player) = self.players.iter().position(|p| p.is_none()) else {
+            log::warn!("Gamepad {} ignored, all players are in use", id);
+            return None;
+        };
+
+        let message = format!(
+            "Gamepad {} ({}) connected to player {}",
+            id,
+            self.gilrs.gamepad(id).name(),
+            player + 1
+        );
+        log::info!("{}", message);
+        self.players[player] = Some(ConnectedPad {
+            id,
+            stick_dpad: [false; 4],
+        });
+        // the first player is always connected (keyboard)
+        if player != 0 {
+            emu.send(EmuCommand::ControllerConnected {
+                player,
+                connected: true,
+            });
+        }
+        Some(message)
+    }
+
+    /// Returns the message to show, if the gamepad was assigned to a player
+    fn disconnect(&mut self, id: GamepadId, emu: &EmuThread) -> Option<String> {
+        let player = self.player_of(id)?;
+        let message = format!("Gamepad {} disconnected from player {}", id, player + 1);
+        log::info!("{}", message);
+        self.players[player] = None;
+
+        if player == 0 {
+            // keep the player connected for the keyboard, but release all the keys
+            // that could have been held by the gamepad
+            for &key in self.map.buttons.values() {
+                emu.set_controller_key(0, key, false);
+            }
+        } else {
+            emu.send(EmuCommand::ControllerConnected {
+                player,
+                connected: false,
+            });
+        }
+        Some(message)
+    }
+
+    /// Process all pending gamepad events, should be called at least
+    /// once every emulated frame. The hot-plug messages are given to `show_message`
+    pub fn poll(&mut self, emu: &EmuThread, mut show_message: impl FnMut(&str)) {
+        while let Some(gilrs::Event { id, event, .. }) = self.gilrs.next_event() {
+            match event {
+                EventType::Connected => {
+                    if let Some(message) = self.connect(id, emu) {
+                        show_message(&message);
+                    }
+                }
+                EventType::Disconnected => {
+                    if let Some(message) = self.disconnect(id, emu) {
+                        show_message(&message);
+                    }
+                }
+                EventType::ButtonPressed(button, _) | EventType::ButtonReleased(button, _) => {
+                    let pressed = matches!(event, EventType::ButtonPressed(..));
+                    if let (Some(player), Some(&key)) =
+                        (self.player_of(id), self.map.buttons.get(&button))
+                    {
+                        emu.set_controller_key(player, key, pressed);
+                    }
+                }
+                EventType::AxisChanged(axis @ (Axis::LeftStickX | Axis::LeftStickY), value, _) => {
+                    if let Some(player) = self.player_of(id) {
+                        self.handle_stick(player, axis, value, emu);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Emulate the D-pad with the left stick, until analog controllers are supported
+    fn handle_stick(&mut self, player: usize, axis: Axis, value: f32, emu: &EmuThread) {
+        let threshold = self.map.stick_dpad_threshold;
+        let pad = self.players[player].as_mut().unwrap();
+
+        // `Y` is positive upwards
+        let (negative, positive, indices) = match axis {
+            Axis::LeftStickX => (
+                DigitalControllerKey::Left,
+                DigitalControllerKey::Right,
+                (2, 3),
+            ),
+            Axis::LeftStickY => (DigitalControllerKey::Down, DigitalControllerKey::Up, (1, 0)),
+            _ => unreachable!(),
+        };
+
+        for (key, index, pressed) in [
+            (negative, indices.0, value < -threshold),
+            (positive, indices.1, value > threshold),
+        ] {
+            if pad.stick_dpad[index] != pressed {
+                pad.stick_dpad[index] = pressed;
+                emu.set_controller_key(player, key, pressed);
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! A logger that prints with `env_logger`, and keeps the last lines in memory
+//! so they can be written to the crash log
+
+use std::{collections::VecDeque, sync::Mutex};
+
+use log::{Log, Metadata, Record};
+
+const MAX_LINES: usize = 100;
+
+static LINES: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+struct RingLogger {
+    inner: env_logger::Logger,
+}
+
+impl Log for RingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.inner.matches(record) {
+            return;
+        }
+        self.inner.log(record);
+
+        let line = format!("[{} {}] {}", record.level(), record.target(), record.args());
+        // a panic while holding the lock shouldn't lose the log
+        let mut lines = LINES.lock().unwrap_or_else(|e| e.into_inner());
+        if lines.len() == MAX_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Install the logger, only errors are logged by default
+pub fn init() {
+    let inner = env_logger::builder()
+        .format_timestamp(None)
+        .filter_level(log::LevelFilter::Error)
+        .build();
+    log::set_max_level(inner.filter());
+    log::set_boxed_logger(Box::new(RingLogger { inner })).expect("logger already installed");
+}
+
+/// The last lines logged, oldest first
+pub fn last_lines() -> Vec<String> {
+    let lines = LINES.lock().unwrap_or_else(|e| e.into_inner());
+    lines.iter().cloned().collect()
+}
@@ -1,16 +1,49 @@
+mod audio;
+mod audio_output;
+mod config;
+mod crash;
 #[cfg(feature = "debugger")]
 mod debugger;
+mod display_mode;
+mod emu_thread;
+mod gamepad;
+mod log_ring;
+mod osd;
+mod post_process;
+mod recent;
+mod save_state;
+#[cfg(feature = "scripting")]
+mod script;
+mod video_record;
+mod watch;
 
 use std::{
-    path::PathBuf,
-    sync::Arc,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
     time::{Duration, Instant},
 };
 
-use dynwave::{AudioPlayer, BufferSize};
-use trapezoid_core::{DigitalControllerKey, Psx, PsxConfig};
+use audio::{AudioSync, SyncMode};
+use config::{Binding, Config, Hotkey};
+use display_mode::{
+    best_video_mode, choose_present_mode, refresh_matches, DisplayModeArg, PresentModeArg,
+    PresentSignal, VideoModeInfo, VsyncPacing,
+};
+use emu_thread::{EmuCommand, EmuEvent, EmuThread, EmuThreadOptions};
+use gamepad::{ControllerMap, Gamepads};
+use osd::Osd;
+use post_process::{PostProcess, Shader};
+use recent::RecentFiles;
+use trapezoid_core::{
+    CdromSeekTiming, DitherMode, IdleSkip, PerfFrameReport, Psx, PsxConfig, RamSize, RegionOverride,
+};
+use video_record::VideoRecorder;
+use watch::ExeWatcher;
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use vulkano::{
     device::{
         physical::PhysicalDeviceType, Device, DeviceCreateInfo, DeviceExtensions, Queue,
@@ -27,30 +60,55 @@ use vulkano::{
 };
 use winit::{
     event::{ElementState, Event, WindowEvent},
-    event_loop::{ControlFlow, EventLoop},
-    keyboard::{KeyCode, PhysicalKey},
-    window::{Window, WindowBuilder, WindowId},
+    event_loop::{ControlFlow, EventLoop, EventLoopProxy},
+    keyboard::PhysicalKey,
+    monitor::VideoMode,
+    window::{Fullscreen, Icon, Window, WindowBuilder, WindowId},
 };
 
-#[cfg(feature = "debugger")]
-use debugger::Debugger;
-
-#[cfg(not(feature = "debugger"))]
-struct Debugger;
+const ICON: &[u8] = include_bytes!("../assets/icon.png");
 
-#[cfg(not(feature = "debugger"))]
-impl Debugger {
-    fn new() -> Self {
-        Self {}
+/// Decode the embedded icon, it's RGBA8
+fn window_icon() -> Result<Icon, String> {
+    let decoder = png::Decoder::new(ICON);
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+    let mut rgba = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut rgba).map_err(|e| e.to_string())?;
+    if info.color_type != png::ColorType::Rgba || info.bit_depth != png::BitDepth::Eight {
+        return Err(format!("unsupported format {:?}", info.color_type));
     }
+    rgba.truncate(info.buffer_size());
+    Icon::from_rgba(rgba, info.width, info.height).map_err(|e| e.to_string())
+}
 
-    fn enabled(&self) -> bool {
-        false
+/// The video mode of the primary monitor for `--display-mode`, exits if it
+/// doesn't have it
+fn exclusive_video_mode(event_loop: &EventLoop<()>, display_mode: &DisplayModeArg) -> VideoMode {
+    let monitor = event_loop
+        .primary_monitor()
+        .or_else(|| event_loop.available_monitors().next());
+    let modes: Vec<VideoMode> = monitor.iter().flat_map(|m| m.video_modes()).collect();
+    let infos: Vec<VideoModeInfo> = modes.iter().map(VideoModeInfo::from).collect();
+
+    match best_video_mode(&infos, display_mode) {
+        Some(i) => modes[i].clone(),
+        None => {
+            eprintln!(
+                "The display doesn't support {}, its modes are:",
+                display_mode
+            );
+            for info in &infos {
+                eprintln!(
+                    "  {}x{}@{} ({} bits)",
+                    info.width,
+                    info.height,
+                    info.refresh_hz(),
+                    info.bit_depth
+                );
+            }
+            std::process::exit(1);
+        }
     }
-
-    fn run(&mut self, _psx: &mut Psx) {}
-
-    fn handle_cpu_state(&mut self, _psx: &mut Psx, _cpu_state: trapezoid_core::cpu::CpuState) {}
 }
 
 struct MovingAverage {
@@ -85,17 +143,29 @@ struct Fps {
     moving_average: MovingAverage,
     last_frame: Instant,
     target_fps: f64,
+    vsync_pacing: Option<VsyncPacing>,
+    /// The frames presented the last time [`Fps::lock`] waited for the display
+    presents_seen: u64,
 }
 
 impl Fps {
-    fn new(target_fps: f64) -> Self {
+    /// Doesn't lock until a target is set with [`Fps::set_target_fps`]
+    fn new() -> Self {
         Self {
             moving_average: MovingAverage::new(),
             last_frame: Instant::now(),
-            target_fps,
+            target_fps: f64::INFINITY,
+            vsync_pacing: None,
+            presents_seen: 0,
         }
     }
 
+    /// Wait for the presents in [`Fps::lock`] instead of sleeping, when the
+    /// refresh rate matches the target
+    fn set_vsync_pacing(&mut self, vsync_pacing: Option<VsyncPacing>) {
+        self.vsync_pacing = vsync_pacing;
+    }
+
     fn tick(&mut self) {
         let now = Instant::now();
         let delta = now.duration_since(self.last_frame).as_secs_f64();
@@ -104,15 +174,35 @@ impl Fps {
         self.moving_average.add(delta);
     }
 
+    /// Start measuring from now, so the time spent paused is not counted as a frame
+    fn restart(&mut self) {
+        self.last_frame = Instant::now();
+    }
+
     fn fps(&self) -> f64 {
         1.0 / self.moving_average.average()
     }
 
+    fn set_target_fps(&mut self, target_fps: f64) {
+        self.target_fps = target_fps;
+    }
+
     /// Locks the current thread to the target FPS
     /// This is useful when running on a higher FPS than 60
     fn lock(&mut self) {
         let duration_per_frame = Duration::from_secs_f64(1.0 / self.target_fps);
 
+        if let Some(vsync_pacing) = &self.vsync_pacing {
+            if refresh_matches(vsync_pacing.refresh_hz, self.target_fps) {
+                // presenting the last frame waits for vsync, so the display
+                // keeps the pace, sleeping too would drift from it
+                self.presents_seen = vsync_pacing
+                    .signal
+                    .wait_after(self.presents_seen, duration_per_frame * 2);
+                return;
+            }
+        }
+
         let elapsed = self.last_frame.elapsed();
 
         if elapsed >= duration_per_frame {
@@ -143,24 +233,44 @@ enum DisplayType {
         images: Vec<Arc<Image>>,
         future: Option<Box<dyn GpuFuture>>,
         full_vram_display: bool,
+        post_process: PostProcess,
+        osd: Option<Osd>,
     },
     Headless,
 }
 
-// Locked FPS for audio (more important than video)
-// 60 FPS result in popping sound because of emulation speed of the SPU
-const FPS: f64 = 59.5;
+/// How the window is shown and presented
+struct WindowOptions {
+    full_vram_display: bool,
+    show_osd: bool,
+    shader: Shader,
+    /// Exclusive fullscreen in this mode of the primary monitor
+    display_mode: Option<DisplayModeArg>,
+    present_mode: PresentModeArg,
+    /// The display has a variable refresh rate
+    vrr: bool,
+}
 
 struct VkDisplay {
     device: Arc<Device>,
     queue: Arc<Queue>,
     display_type: DisplayType,
     fps: Fps,
+    /// Set if the presents wait for vsync, for the emulation thread to be paced by them
+    vsync_pacing: Option<VsyncPacing>,
     render_time_average: MovingAverage,
+    /// Shown in the OSD if audio is playing
+    audio_sync: Option<Arc<Mutex<AudioSync>>>,
+    /// Updated by the emulation thread, see [`trapezoid_core::Psx::warning_count`]
+    warning_count: Arc<AtomicU64>,
+    /// Updated by the emulation thread, see [`trapezoid_core::Psx::perf_frame_report`]
+    perf_report: Arc<Mutex<PerfFrameReport>>,
+    /// The disk ID or the file name, shown in the title
+    game_name: Option<String>,
 }
 
 impl VkDisplay {
-    fn windowed(full_vram_display: bool) -> Self {
+    fn windowed(options: WindowOptions) -> Self {
         let event_loop = EventLoop::new().unwrap();
 
         let vulkan_library = VulkanLibrary::new().unwrap();
@@ -176,7 +286,19 @@ impl VkDisplay {
         )
         .unwrap();
 
-        let window = Arc::new(WindowBuilder::new().build(&event_loop).unwrap());
+        let icon = window_icon()
+            .map_err(|e| log::error!("Could not load the window icon: {}", e))
+            .ok();
+        let fullscreen_mode = options
+            .display_mode
+            .map(|display_mode| exclusive_video_mode(&event_loop, &display_mode));
+        let window = Arc::new(
+            WindowBuilder::new()
+                .with_window_icon(icon)
+                .with_fullscreen(fullscreen_mode.clone().map(Fullscreen::Exclusive))
+                .build(&event_loop)
+                .unwrap(),
+        );
         let surface = Surface::from_window(instance.clone(), window.clone()).unwrap();
 
         let device_extensions = DeviceExtensions {
@@ -230,31 +352,29 @@ impl VkDisplay {
 
         let queue = queues.next().unwrap();
 
+        let format = device
+            .physical_device()
+            .surface_formats(&surface, Default::default())
+            .unwrap()[0]
+            .0;
+
+        let present_mode;
         let (swapchain, images) = {
             let caps = device
                 .physical_device()
                 .surface_capabilities(&surface, Default::default())
                 .unwrap();
 
-            let format = device
-                .physical_device()
-                .surface_formats(&surface, Default::default())
-                .unwrap()[0]
-                .0;
             let window = surface.object().unwrap().downcast_ref::<Window>().unwrap();
 
-            let present_mode = device
+            let available_present_modes: Vec<_> = device
                 .physical_device()
                 .surface_present_modes(&surface, Default::default())
                 .unwrap()
-                .min_by_key(|&m| match m {
-                    PresentMode::Mailbox => 0,
-                    PresentMode::Immediate => 1,
-                    PresentMode::Fifo => 2,
-                    PresentMode::FifoRelaxed => 3,
-                    _ => 4,
-                })
-                .unwrap();
+                .collect();
+            present_mode =
+                choose_present_mode(&available_present_modes, options.present_mode, options.vrr);
+            println!("Using present mode: {:?}", present_mode);
 
             let dimensions: [u32; 2] = window.inner_size().into();
             Swapchain::new(
@@ -264,7 +384,8 @@ impl VkDisplay {
                     min_image_count: caps.min_image_count,
                     image_format: format,
                     image_extent: dimensions,
-                    image_usage: ImageUsage::TRANSFER_DST,
+                    // the frame and the OSD are rendered directly into the swapchain images
+                    image_usage: ImageUsage::COLOR_ATTACHMENT,
                     composite_alpha: CompositeAlpha::Opaque,
                     present_mode,
                     ..Default::default()
@@ -273,19 +394,48 @@ impl VkDisplay {
             .unwrap()
         };
 
+        // with VRR, the display follows the emulation instead
+        let presents_wait_for_vsync =
+            matches!(present_mode, PresentMode::Fifo | PresentMode::FifoRelaxed) && !options.vrr;
+        let refresh_millihertz = match &fullscreen_mode {
+            Some(mode) => Some(mode.refresh_rate_millihertz()),
+            None => window
+                .current_monitor()
+                .and_then(|monitor| monitor.refresh_rate_millihertz()),
+        };
+        let vsync_pacing =
+            refresh_millihertz
+                .filter(|_| presents_wait_for_vsync)
+                .map(|refresh_millihertz| VsyncPacing {
+                    signal: PresentSignal::default(),
+                    refresh_hz: refresh_millihertz as f64 / 1000.0,
+                });
+
+        let post_process = PostProcess::new(device.clone(), queue.clone(), format, options.shader);
+        let osd = options
+            .show_osd
+            .then(|| Osd::new(device.clone(), queue.clone(), format));
+
         Self {
             device: device.clone(),
             queue,
-            fps: Fps::new(FPS),
+            fps: Fps::new(),
+            vsync_pacing,
             render_time_average: MovingAverage::new(),
+            audio_sync: None,
+            warning_count: Arc::default(),
+            perf_report: Arc::default(),
+            game_name: None,
             display_type: DisplayType::Windowed {
                 event_loop: Some(event_loop),
                 window,
                 surface,
                 swapchain,
                 images,
-                full_vram_display,
+                full_vram_display: options.full_vram_display,
                 future: Some(sync::now(device).boxed()),
+                post_process,
+                osd,
             },
         }
     }
@@ -347,8 +497,13 @@ impl VkDisplay {
         Self {
             device,
             queue,
-            fps: Fps::new(FPS),
+            fps: Fps::new(),
+            vsync_pacing: None,
             render_time_average: MovingAverage::new(),
+            audio_sync: None,
+            warning_count: Arc::default(),
+            perf_report: Arc::default(),
+            game_name: None,
             display_type: DisplayType::Headless,
         }
     }
@@ -377,15 +532,28 @@ impl VkDisplay {
         }
     }
 
-    fn render_frame(&mut self, psx: &mut Psx) {
+    /// Can be used to wake up the event loop from other threads
+    fn event_loop_proxy(&self) -> Option<EventLoopProxy<()>> {
+        match &self.display_type {
+            DisplayType::Windowed {
+                event_loop: Some(event_loop),
+                ..
+            } => Some(event_loop.create_proxy()),
+            _ => None,
+        }
+    }
+
+    /// Present `front_image`, which is the frame rendered by the emulator
+    fn render_frame(&mut self, front_image: Arc<Image>) {
         let mut recreate_swapchain = false;
         match &mut self.display_type {
             DisplayType::Windowed {
                 swapchain,
                 images,
-                full_vram_display,
                 surface,
                 future,
+                post_process,
+                osd,
                 ..
             } => {
                 let t = Instant::now();
@@ -393,8 +561,13 @@ impl VkDisplay {
                 current_future.cleanup_finished();
 
                 let window = surface.object().unwrap().downcast_ref::<Window>().unwrap();
+                let game_name = match &self.game_name {
+                    Some(name) => format!("{} - ", name),
+                    None => String::new(),
+                };
                 window.set_title(&format!(
-                    "PSX - FPS: {:.1} - Render time: {:.1}us",
+                    "PSX - {}FPS: {:.1} - Render time: {:.1}us",
+                    game_name,
                     (self.fps.fps() * 10.).round() / 10.,
                     (self.render_time_average.average() * 10.).round() / 10.
                 ));
@@ -416,12 +589,40 @@ impl VkDisplay {
 
                 let current_image = images[image_num as usize].clone();
 
-                let current_future = psx.blit_to_front(
-                    current_image,
-                    *full_vram_display,
+                let mut current_future = post_process.draw(
+                    front_image,
+                    current_image.clone(),
                     current_future.join(acquire_future).boxed(),
                 );
 
+                if let Some(osd) = osd {
+                    let mut status = vec![format!("FPS: {:.1}", self.fps.fps())];
+                    if let Some(audio_sync) = &self.audio_sync {
+                        let mut audio_sync = audio_sync.lock().unwrap();
+                        status.push(if audio_sync.muted() {
+                            "AUDIO: muted".to_string()
+                        } else {
+                            format!(
+                                "AUDIO: {:.0}% ({:+.2}%)",
+                                audio_sync.fill() * 100.,
+                                audio_sync.rate_adjustment() * 100.
+                            )
+                        });
+                    }
+                    let perf_report = *self.perf_report.lock().unwrap();
+                    status.push(format!(
+                        "CPU: {:.2}M INSTR, {} EXC, {} DMA",
+                        perf_report.instructions_retired as f64 / 1_000_000.,
+                        perf_report.total_exceptions(),
+                        perf_report.total_dma_cycles()
+                    ));
+                    let warning_count = self.warning_count.load(Ordering::Relaxed);
+                    if warning_count > 0 {
+                        status.push(format!("WARNINGS: {}", warning_count));
+                    }
+                    current_future = osd.draw(current_image, &status, current_future);
+                }
+
                 *future = Some(
                     current_future
                         .then_swapchain_present(
@@ -435,6 +636,9 @@ impl VkDisplay {
                         .unwrap()
                         .boxed(),
                 );
+                if let Some(vsync_pacing) = &self.vsync_pacing {
+                    vsync_pacing.signal.presented();
+                }
 
                 let elapsed = t.elapsed();
                 self.render_time_average.add(elapsed.as_micros() as f64);
@@ -448,15 +652,40 @@ impl VkDisplay {
         }
     }
 
-    fn toggle_full_vram_display(&mut self) {
+    /// Returns the new state
+    fn toggle_full_vram_display(&mut self) -> bool {
         match self.display_type {
             DisplayType::Windowed {
                 ref mut full_vram_display,
                 ..
             } => {
                 *full_vram_display = !*full_vram_display;
+                let message = if *full_vram_display {
+                    "Full VRAM display on"
+                } else {
+                    "Full VRAM display off"
+                };
+                let full_vram_display = *full_vram_display;
+                self.show_message(message);
+                full_vram_display
             }
-            DisplayType::Headless => {}
+            DisplayType::Headless => false,
+        }
+    }
+
+    /// Switch to the next output shader
+    fn next_shader(&mut self) {
+        if let DisplayType::Windowed { post_process, .. } = &mut self.display_type {
+            let shader = post_process.shader().next();
+            post_process.set_shader(shader);
+            self.show_message(&format!("Shader: {}", shader.name()));
+        }
+    }
+
+    /// Show a transient message on the OSD, if enabled
+    fn show_message(&mut self, message: &str) {
+        if let DisplayType::Windowed { osd: Some(osd), .. } = &mut self.display_type {
+            osd.show_message(message);
         }
     }
 
@@ -473,24 +702,25 @@ impl VkDisplay {
                 let event_loop = event_loop.take().unwrap();
                 let window = window.clone();
                 event_loop
-                    .run(|event, target| match event {
-                        Event::AboutToWait => {
+                    .run(|event, target| {
+                        // a new frame is ready
+                        if let Event::UserEvent(()) = event {
                             window.request_redraw();
                         }
-                        _ => {
-                            let r = f(&mut self, event);
-                            if let Some(r) = r {
-                                target.set_control_flow(r);
-                            } else {
-                                target.exit();
-                            }
+                        let r = f(&mut self, event);
+                        if let Some(r) = r {
+                            target.set_control_flow(r);
+                        } else {
+                            target.exit();
                         }
                     })
                     .unwrap();
             }
             DisplayType::Headless => loop {
                 // TODO: support keyboard input and such
-                // NOTE: MainEventCleared is used here to run the emulator
+                if f(&mut self, Event::AboutToWait).is_none() {
+                    break;
+                }
                 let r = f(
                     &mut self,
                     Event::WindowEvent {
@@ -507,13 +737,89 @@ impl VkDisplay {
     }
 }
 
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum SeekTiming {
+    Instant,
+    Fast,
+    Accurate,
+}
+
+impl From<SeekTiming> for CdromSeekTiming {
+    fn from(timing: SeekTiming) -> Self {
+        match timing {
+            SeekTiming::Instant => CdromSeekTiming::Instant,
+            SeekTiming::Fast => CdromSeekTiming::Fast,
+            SeekTiming::Accurate => CdromSeekTiming::Accurate,
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum IdleSkipMode {
+    Off,
+    On,
+    Verify,
+}
+
+impl From<IdleSkipMode> for IdleSkip {
+    fn from(mode: IdleSkipMode) -> Self {
+        match mode {
+            IdleSkipMode::Off => IdleSkip::Off,
+            IdleSkipMode::On => IdleSkip::On,
+            IdleSkipMode::Verify => IdleSkip::Verify,
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Dithering {
+    Hardware,
+    Off,
+    ForceOn,
+}
+
+impl From<Dithering> for DitherMode {
+    fn from(dithering: Dithering) -> Self {
+        match dithering {
+            Dithering::Hardware => DitherMode::Hardware,
+            Dithering::Off => DitherMode::Off,
+            Dithering::ForceOn => DitherMode::ForceOn,
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum VideoRegion {
+    Auto,
+    Ntsc,
+    Pal,
+}
+
+impl From<VideoRegion> for RegionOverride {
+    fn from(region: VideoRegion) -> Self {
+        match region {
+            VideoRegion::Auto => RegionOverride::Auto,
+            VideoRegion::Ntsc => RegionOverride::Ntsc,
+            VideoRegion::Pal => RegionOverride::Pal,
+        }
+    }
+}
+
 #[derive(Parser, Debug)]
 #[command(version, author, about = "PSX emulator")]
 struct PsxEmuArgs {
-    /// The bios file to run
-    bios: PathBuf,
+    /// The bios file to run, with `--hle-bios` this is the exe file to run instead
+    #[arg(required_unless_present = "list_audio_devices")]
+    bios: Option<PathBuf>,
     /// The disk/exe file to run, without this, it will run the bios only
     disk_file: Option<PathBuf>,
+    /// Another `.cue` file for multi-disc games, can be repeated. With the shell open,
+    /// the [ key cycles through the disks
+    #[arg(long = "disk", value_name = "FILE")]
+    extra_disks: Vec<PathBuf>,
+    /// Reset and reload the exe file when it changes, e.g. after rebuilding it
+    #[arg(long)]
+    watch: bool,
     /// Turn off window display and run in headless mode
     #[arg(short = 'e', long)]
     headless: bool,
@@ -523,147 +829,533 @@ struct PsxEmuArgs {
     /// Play audio
     #[arg(short, long)]
     audio: bool,
+    /// The audio output device with `--audio`, instead of the default device
+    #[arg(long, value_name = "NAME", requires = "audio")]
+    audio_device: Option<String>,
+    /// Print the names of the audio output devices and exit
+    #[arg(long, exclusive = true)]
+    list_audio_devices: bool,
+    /// How to keep the audio buffer filled with `--audio`: by adjusting the emulation
+    /// speed (`audio`), by resampling (`video`), or not at all (`off`)
+    #[arg(long, value_enum, default_value_t = SyncMode::Audio)]
+    sync: SyncMode,
+    /// Disable the on-screen display (FPS, audio buffer and messages)
+    #[arg(long)]
+    no_osd: bool,
+    /// The filter used to draw the frame to the window, can be changed later with [F7] key
+    #[arg(long, value_enum, default_value_t = Shader::Nearest)]
+    shader: Shader,
+    /// Exclusive fullscreen with this mode of the display, e.g. `1920x1080@60`
+    #[arg(long, value_name = "WxH@Hz", conflicts_with = "headless")]
+    display_mode: Option<DisplayModeArg>,
+    /// How frames are presented to the window
+    #[arg(long, value_enum, default_value_t = PresentModeArg::Auto)]
+    present_mode: PresentModeArg,
+    /// The display has a variable refresh rate (FreeSync/G-Sync), `auto` present mode
+    /// uses `relaxed` then
+    #[arg(long)]
+    vrr: bool,
     /// Print tty debug output to the console
     #[arg(short, long)]
     debug: bool,
     /// Skips the shell
     #[arg(short, long)]
     fast_boot: bool,
+    /// Run an exe file without a BIOS, only simple homebrew will work
+    #[arg(long)]
+    hle_bios: bool,
+    /// How long the CD-ROM seeks take, some games need `accurate` to work correctly
+    #[arg(long, value_enum, default_value_t = SeekTiming::Instant)]
+    seek_timing: SeekTiming,
+    /// Don't execute loops that only wait (e.g. for vblank) to use less CPU,
+    /// `verify` keeps executing them and panics if the detection was wrong
+    #[arg(long, value_enum, default_value_t = IdleSkipMode::Off)]
+    idle_skip: IdleSkipMode,
+    /// Install 8MB of RAM like development consoles, for homebrew that needs it
+    #[arg(long)]
+    ram_8mb: bool,
+    /// When to dither shaded and texture-blended draws, `hardware` follows the game
+    #[arg(long, value_enum, default_value_t = Dithering::Hardware)]
+    dithering: Dithering,
+    /// Force the video timing, `auto` uses the mode the BIOS and the game set
+    #[arg(long, value_enum, default_value_t = VideoRegion::Auto)]
+    region: VideoRegion,
+    /// Run the CPU faster than the rest of the console (`1.0` to `4.0`), to reduce
+    /// slowdown, some games don't work with it
+    #[arg(long, value_name = "FACTOR", default_value_t = 1.0)]
+    cpu_overclock: f32,
+    /// The config file for key bindings, (default: `<config_dir>/trapezoid/config.toml`)
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Plug a multitap in the first port, the gamepads are assigned to its 4 pads
+    #[arg(long)]
+    multitap: bool,
+    /// A TOML file to rebind the gamepad buttons
+    #[arg(long, value_name = "FILE")]
+    controller_map: Option<PathBuf>,
+    /// Start from a save state, a file or the slot number (`1` to `4`) of the disk
+    #[arg(long, value_name = "FILE|SLOT")]
+    load_state: Option<PathBuf>,
+    /// Record the display and the audio to a Matroska (`.mkv`) file, timed by the emulated time
+    #[arg(long, value_name = "FILE")]
+    record_video: Option<PathBuf>,
+    /// A Rhai script to run with the emulator, see the README for its functions
+    #[cfg(feature = "scripting")]
+    #[arg(long, value_name = "FILE")]
+    script: Option<PathBuf>,
+    /// Panic in the emulation thread after the first frame, to test the crash log
+    #[arg(long, hide = true)]
+    debug_panic: bool,
+}
+
+/// What to do with a file dropped on the window or picked from the recent files
+enum OpenFile {
+    Disk(PathBuf),
+    Exe(PathBuf),
+}
+
+impl OpenFile {
+    fn from_path(path: &Path) -> Result<Self, String> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("cue") => Ok(Self::Disk(path.to_path_buf())),
+            Some("exe") => Ok(Self::Exe(path.to_path_buf())),
+            // the disk is loaded through its cue file
+            Some("bin") => {
+                let cue = path.with_extension("cue");
+                if cue.exists() {
+                    Ok(Self::Disk(cue))
+                } else {
+                    Err(format!("No cue file found for {}", file_name(path)))
+                }
+            }
+            Some("chd") => Err("CHD images are not supported, use a .cue/.bin".to_string()),
+            _ => Err(format!("Unsupported file type: {}", file_name(path))),
+        }
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// The state of the disks in the UI thread
+struct Disks {
+    /// the disks that can be swapped while running, starting with the inserted one
+    list: Vec<PathBuf>,
+    current: usize,
+    shell_open: bool,
+}
+
+impl Disks {
+    /// Swap the disk if the shell is open, otherwise restart the emulator with
+    /// the file, the result comes back as an [`emu_thread::EmuEvent`]
+    fn open(&mut self, path: &Path, hle_bios: bool, emu: &EmuThread, display: &mut VkDisplay) {
+        match OpenFile::from_path(path) {
+            Ok(OpenFile::Disk(_)) if hle_bios => {
+                display.show_message("Disks need a BIOS, run without --hle-bios");
+            }
+            Ok(OpenFile::Disk(cue)) if self.shell_open => {
+                display.show_message(&format!("Swapping disk: {}", file_name(&cue)));
+                emu.send(EmuCommand::SwapDisk(cue.clone()));
+                // so the disk can be swapped back with the next disk hotkey
+                self.current = match self.list.iter().position(|d| *d == cue) {
+                    Some(i) => i,
+                    None => {
+                        self.list.push(cue);
+                        self.list.len() - 1
+                    }
+                };
+            }
+            Ok(OpenFile::Disk(cue)) => {
+                self.restart(&cue, emu, display);
+                self.list = vec![cue];
+            }
+            Ok(OpenFile::Exe(exe)) => {
+                self.restart(&exe, emu, display);
+                self.list = Vec::new();
+            }
+            Err(e) => {
+                log::error!("Could not open {:?}: {}", path, e);
+                display.show_message(&e);
+            }
+        }
+    }
+
+    fn restart(&mut self, path: &Path, emu: &EmuThread, display: &mut VkDisplay) {
+        display.show_message(&format!("Loading {}", file_name(path)));
+        emu.send(EmuCommand::Load(path.to_path_buf()));
+        // the new emulator starts with the shell closed
+        self.shell_open = false;
+        self.current = 0;
+    }
+}
+
+/// The disk ID if it's a game disk, otherwise the file name
+fn game_name(path: &Path, disk_id: Option<&str>) -> String {
+    disk_id.map_or_else(|| file_name(path), str::to_string)
+}
+
+/// Only EXEs can be reloaded in place, for `--watch`
+fn watch_exe(path: &Path) -> Option<ExeWatcher> {
+    matches!(OpenFile::from_path(path), Ok(OpenFile::Exe(_))).then(|| ExeWatcher::new(path))
 }
 
 fn main() {
-    env_logger::builder()
-        .format_timestamp(None)
-        .filter_level(log::LevelFilter::Error)
-        .init();
+    log_ring::init();
+    crash::install_panic_hook();
 
     let args = PsxEmuArgs::parse();
 
-    let display = if args.headless {
+    if args.list_audio_devices {
+        for name in audio_output::list_devices() {
+            println!("{}", name);
+        }
+        return;
+    }
+    let bios_arg = args.bios.expect("required by clap");
+
+    let config = match Config::load(args.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut display = if args.headless {
         VkDisplay::headless()
     } else {
-        VkDisplay::windowed(args.vram)
+        VkDisplay::windowed(WindowOptions {
+            full_vram_display: args.vram,
+            show_osd: !args.no_osd,
+            shader: args.shader,
+            display_mode: args.display_mode,
+            present_mode: args.present_mode,
+            vrr: args.vrr,
+        })
+    };
+
+    // in HLE mode, there is no BIOS file, so the first file is the exe
+    let (bios, disk_file) = if args.hle_bios {
+        (None, Some(bios_arg))
+    } else {
+        (Some(bios_arg), args.disk_file)
     };
+    let mut disks = Disks {
+        list: disk_file
+            .iter()
+            .cloned()
+            .chain(args.extra_disks.iter().cloned())
+            .collect(),
+        current: 0,
+        shell_open: false,
+    };
+    let mut exe_watcher = None;
+    if args.watch {
+        exe_watcher = disk_file.as_deref().and_then(watch_exe);
+        if exe_watcher.is_none() {
+            log::warn!("--watch only works with exe files, ignoring it");
+        }
+    }
+    let mut recent = RecentFiles::load();
+    if let Some(file) = &disk_file {
+        recent.add(file);
+    }
 
     let mut psx = Psx::new(
-        &args.bios,
-        args.disk_file,
-        PsxConfig {
-            stdout_debug: args.debug,
-            fast_boot: args.fast_boot,
-        },
+        bios.as_deref(),
+        disk_file.as_deref(),
+        PsxConfig::builder()
+            .stdout_debug(args.debug)
+            .fast_boot(args.fast_boot)
+            .hle_bios(args.hle_bios)
+            .cdrom_seek_timing(args.seek_timing.into())
+            .idle_skip(args.idle_skip.into())
+            .ram_size(if args.ram_8mb {
+                RamSize::Dev8MB
+            } else {
+                RamSize::Std2MB
+            })
+            .region_override(args.region.into())
+            .cpu_overclock(args.cpu_overclock)
+            .build(),
         display.device.clone(),
         display.queue.clone(),
     )
     .unwrap();
+    let mut render_options = psx.gpu_render_options();
+    render_options.dithering = args.dithering.into();
+    psx.set_gpu_render_options(render_options);
+    psx.set_multitap(0, args.multitap);
+    display.game_name = disk_file
+        .as_deref()
+        .map(|path| game_name(path, psx.disk_id()));
+
+    if let Some(arg) = &args.load_state {
+        let result = save_state::resolve_arg(arg, disk_file.as_deref(), psx.disk_id())
+            .and_then(|path| save_state::load(&path));
+        if let Err(e) = result {
+            eprintln!("Failed to load state {:?}: {}", arg, e);
+            std::process::exit(1);
+        }
+    }
 
-    let mut shell_state_open = false;
+    #[cfg(feature = "scripting")]
+    if let Some(path) = &args.script {
+        match script::RhaiScript::from_file(path) {
+            Ok(script) => psx.attach_script_engine(Box::new(script)),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
 
-    let mut debugger = Debugger::new();
+    let controller_map = match &args.controller_map {
+        Some(path) => match ControllerMap::from_file(path) {
+            Ok(map) => map,
+            Err(e) => {
+                eprintln!("Failed to load controller map {:?}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        None => ControllerMap::default(),
+    };
 
-    let mut audio_player = if args.audio {
-        let audio_player = AudioPlayer::<f32>::new(44100, BufferSize::QuarterSecond);
+    let (audio_sender, audio_sync) = match args
+        .audio
+        .then(|| audio::spawn_audio_thread(args.sync, args.audio_device))
+    {
+        Some(Some((sender, sync))) => (Some(sender), Some(sync)),
+        _ => (None, None),
+    };
+    display.audio_sync = audio_sync.clone();
 
-        match audio_player {
-            Ok(p) => {
-                p.play().expect("Audio device to play");
-                Some(p)
-            }
+    let video_recorder = match &args.record_video {
+        Some(path) => match VideoRecorder::start(path) {
+            Ok(recorder) => Some(recorder),
             Err(e) => {
-                log::error!("Failed to initialize audio player: {:?}", e);
-                None
+                eprintln!("Failed to record to {:?}: {}", path, e);
+                std::process::exit(1);
             }
-        }
-    } else {
-        None
+        },
+        None => None,
     };
 
+    let mut emu = EmuThread::spawn(
+        psx,
+        EmuThreadOptions {
+            bios,
+            device: display.device.clone(),
+            queue: display.queue.clone(),
+            produce_frames: !args.headless,
+            full_vram_display: args.vram,
+            multitap: args.multitap,
+            audio_sender,
+            audio_sync,
+            warning_count: display.warning_count.clone(),
+            perf_report: display.perf_report.clone(),
+            video_recorder,
+            event_loop_proxy: display.event_loop_proxy(),
+            vsync_pacing: display.vsync_pacing.clone(),
+            debug_panic: args.debug_panic,
+        },
+    );
+
+    let players = if args.multitap { 4 } else { 2 };
+    let mut gamepads = Gamepads::new(controller_map, &emu, players);
+
+    let hle_bios = args.hle_bios;
+    let watch = args.watch;
+    let mut last_frame = None;
+    let mut paused = false;
+
     display.run(move |display, event| {
+        // poll after every batch of events, new frames wake the event loop
+        // so this is done at least once per frame
+        if let Event::AboutToWait = event {
+            if let Some(gamepads) = &mut gamepads {
+                gamepads.poll(&emu, |message| display.show_message(message));
+            }
+            if let Some(watcher) = &mut exe_watcher {
+                if watcher.poll() {
+                    emu.send(EmuCommand::ReloadExe(watcher.path().to_path_buf()));
+                }
+            }
+            let mut fatal = None;
+            for event in emu.events() {
+                match event {
+                    EmuEvent::DiskLoaded {
+                        path,
+                        swapped,
+                        disk_id,
+                    } => {
+                        if !swapped {
+                            display.show_message(&format!("Loaded {}", file_name(&path)));
+                        }
+                        display.game_name = Some(game_name(&path, disk_id.as_deref()));
+                        recent.add(&path);
+                        // follow the new file, unless it only replaced the disk
+                        if watch && !swapped {
+                            exe_watcher = watch_exe(&path);
+                        }
+                    }
+                    EmuEvent::ExeReloaded(path) => {
+                        display.show_message(&format!("Reloaded {}", file_name(&path)));
+                    }
+                    EmuEvent::Error(e) => display.show_message(&e),
+                    EmuEvent::Fatal(e) => fatal = Some(e),
+                }
+            }
+            if let Some(e) = fatal {
+                log::error!("{}", e);
+                emu.stop();
+                return None;
+            }
+        }
+
         if let Event::WindowEvent { event, .. } = event {
             match event {
                 WindowEvent::CloseRequested => {
+                    emu.stop();
                     return None;
                 }
                 WindowEvent::Resized(_) => {
                     display.window_resize();
                 }
+                WindowEvent::DroppedFile(path) => {
+                    disks.open(&path, hle_bios, &emu, display);
+                }
                 WindowEvent::KeyboardInput { event: input, .. } => {
                     let pressed = input.state == ElementState::Pressed;
 
-                    let digital_key = match input.physical_key {
-                        PhysicalKey::Code(KeyCode::Enter) => Some(DigitalControllerKey::Start),
-                        PhysicalKey::Code(KeyCode::Backspace) => Some(DigitalControllerKey::Select),
-
-                        PhysicalKey::Code(KeyCode::Digit1) => Some(DigitalControllerKey::L1),
-                        PhysicalKey::Code(KeyCode::Digit2) => Some(DigitalControllerKey::L2),
-                        PhysicalKey::Code(KeyCode::Digit3) => Some(DigitalControllerKey::L3),
-                        PhysicalKey::Code(KeyCode::Digit0) => Some(DigitalControllerKey::R1),
-                        PhysicalKey::Code(KeyCode::Digit9) => Some(DigitalControllerKey::R2),
-                        PhysicalKey::Code(KeyCode::Digit8) => Some(DigitalControllerKey::R3),
-
-                        PhysicalKey::Code(KeyCode::KeyW) => Some(DigitalControllerKey::Up),
-                        PhysicalKey::Code(KeyCode::KeyS) => Some(DigitalControllerKey::Down),
-                        PhysicalKey::Code(KeyCode::KeyD) => Some(DigitalControllerKey::Right),
-                        PhysicalKey::Code(KeyCode::KeyA) => Some(DigitalControllerKey::Left),
-
-                        PhysicalKey::Code(KeyCode::KeyI) => Some(DigitalControllerKey::Triangle),
-                        PhysicalKey::Code(KeyCode::KeyK) => Some(DigitalControllerKey::X),
-                        PhysicalKey::Code(KeyCode::KeyL) => Some(DigitalControllerKey::Circle),
-                        PhysicalKey::Code(KeyCode::KeyJ) => Some(DigitalControllerKey::Square),
+                    let binding = match input.physical_key {
+                        PhysicalKey::Code(key_code) => config.binding(key_code),
                         _ => None,
                     };
-                    if let Some(k) = digital_key {
-                        psx.change_controller_key_state(k, pressed);
-                    } else if pressed {
-                        match input.physical_key {
-                            #[cfg(feature = "debugger")]
-                            // Pause CPU and enable debug
-                            PhysicalKey::Code(KeyCode::Slash) => {
-                                println!("{:?}", psx.cpu().registers());
-                                debugger.set_enabled(true);
+
+                    match binding {
+                        Some(Binding::Controller(k)) => {
+                            emu.set_controller_key(0, k, pressed);
+                        }
+                        Some(Binding::Hotkey(hotkey)) if pressed => match hotkey {
+                            Hotkey::ToggleFullVram => {
+                                let full_vram = display.toggle_full_vram_display();
+                                emu.send(EmuCommand::FullVramDisplay(full_vram));
                             }
-                            #[cfg(feature = "debugger")]
-                            // Resume CPU if paused
-                            PhysicalKey::Code(KeyCode::KeyC) => {
-                                debugger.set_enabled(false);
+                            Hotkey::ToggleShellOpen => {
+                                disks.shell_open = !disks.shell_open;
+                                emu.send(EmuCommand::ShellOpen(disks.shell_open));
+                                display.show_message(if disks.shell_open {
+                                    "Shell opened"
+                                } else {
+                                    "Shell closed"
+                                });
                             }
-                            PhysicalKey::Code(KeyCode::KeyV) => display.toggle_full_vram_display(),
-                            PhysicalKey::Code(KeyCode::BracketRight) => {
-                                shell_state_open = !shell_state_open;
-                                psx.change_cdrom_shell_open_state(shell_state_open);
+                            Hotkey::ToggleMute => {
+                                let muted = display.audio_sync.as_ref().map(|audio_sync| {
+                                    let mut audio_sync = audio_sync.lock().unwrap();
+                                    let muted = !audio_sync.muted();
+                                    audio_sync.set_muted(muted);
+                                    muted
+                                });
+                                display.show_message(match muted {
+                                    Some(true) => "Audio muted",
+                                    Some(false) => "Audio unmuted",
+                                    None => "Audio is not enabled, use --audio",
+                                });
                             }
-                            _ => {}
-                        }
+                            Hotkey::NextDisk => {
+                                if disks.list.len() < 2 {
+                                    display.show_message("No other disks, add them with --disk");
+                                } else if !disks.shell_open {
+                                    display.show_message("Open the shell to swap the disk");
+                                } else {
+                                    disks.current = (disks.current + 1) % disks.list.len();
+                                    emu.send(EmuCommand::SwapDisk(
+                                        disks.list[disks.current].clone(),
+                                    ));
+                                    display.show_message(&format!(
+                                        "Disk {}/{} inserted",
+                                        disks.current + 1,
+                                        disks.list.len()
+                                    ));
+                                }
+                            }
+                            Hotkey::NextRecent => {
+                                let len = recent.len();
+                                match recent.select_next() {
+                                    Some((i, path)) => display.show_message(&format!(
+                                        "Recent {}/{}: {}",
+                                        i + 1,
+                                        len,
+                                        file_name(path)
+                                    )),
+                                    None => display.show_message("No recent files"),
+                                }
+                            }
+                            Hotkey::LoadRecent => match recent.selected() {
+                                Some(path) => {
+                                    let path = path.to_path_buf();
+                                    disks.open(&path, hle_bios, &emu, display);
+                                }
+                                None => display.show_message("No recent file selected"),
+                            },
+                            Hotkey::DebuggerBreak => {
+                                emu.send(EmuCommand::DebuggerBreak);
+                                if cfg!(feature = "debugger") {
+                                    display.show_message("Paused");
+                                }
+                            }
+                            Hotkey::DebuggerContinue => {
+                                emu.send(EmuCommand::DebuggerContinue);
+                            }
+                            Hotkey::TogglePause => {
+                                paused = !paused;
+                                emu.send(EmuCommand::SetPaused(paused));
+                                if paused {
+                                    display.show_message("Paused");
+                                } else {
+                                    display.fps.restart();
+                                    display.show_message("Resumed");
+                                }
+                            }
+                            Hotkey::FrameAdvance => {
+                                if paused {
+                                    emu.send(EmuCommand::FrameAdvance);
+                                } else {
+                                    display.show_message("Pause first to advance frames");
+                                }
+                            }
+                            Hotkey::NextShader => display.next_shader(),
+                        },
+                        _ => {}
                     }
                 }
                 WindowEvent::RedrawRequested => {
-                    // limit the frame rate to the target fps if the display support more than that
-                    display.fps.lock();
-                    display.fps.tick();
-
-                    // if the debugger is enabled, we don't run the emulation
-                    if !debugger.enabled() {
-                        let cpu_state = psx.clock_full_video_frame();
-                        debugger.handle_cpu_state(&mut psx, cpu_state);
-
-                        let audio_buffer = psx.take_audio_buffer();
-                        if let Some(audio_player) = &mut audio_player {
-                            audio_player.queue(&audio_buffer);
+                    if let Some(frame) = emu.latest_frame() {
+                        // the frames advanced while paused don't count
+                        if !paused {
+                            display.fps.tick();
                         }
+                        last_frame = Some(frame);
+                    }
+                    // the window may need to be redrawn without a new frame
+                    if let Some(frame) = &last_frame {
+                        display.render_frame(frame.clone());
                     }
-                    // keep rendering even when debugger is  running so that
-                    // we don't hang the display
-                    display.render_frame(&mut psx);
                 }
                 _ => {}
             }
         }
-        // this is placed outside the emulation event, so that it reacts faster
-        // to user input
-        if debugger.enabled() {
-            debugger.run(&mut psx);
-        }
 
-        Some(ControlFlow::Poll)
+        Some(ControlFlow::Wait)
     });
 }
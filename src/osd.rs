@@ -0,0 +1,497 @@
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use vulkano::{
+    buffer::{
+        allocator::{SubbufferAllocator, SubbufferAllocatorCreateInfo},
+        BufferContents, BufferUsage,
+    },
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, CommandBufferUsage,
+        PrimaryAutoCommandBuffer, RenderPassBeginInfo,
+    },
+    device::{Device, Queue},
+    format::Format,
+    image::{view::ImageView, Image},
+    memory::allocator::{MemoryTypeFilter, StandardMemoryAllocator},
+    pipeline::{
+        graphics::{
+            color_blend::{AttachmentBlend, ColorBlendAttachmentState, ColorBlendState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            vertex_input::{Vertex as VertexTrait, VertexDefinition},
+            viewport::{Viewport, ViewportState},
+            GraphicsPipelineCreateInfo,
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+        DynamicState, GraphicsPipeline, PipelineLayout, PipelineShaderStageCreateInfo,
+    },
+    render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass},
+    sync::GpuFuture,
+};
+
+/// How long a message is shown before it starts fading
+const MESSAGE_DURATION: Duration = Duration::from_secs(2);
+const MESSAGE_FADE_DURATION: Duration = Duration::from_millis(500);
+const MAX_MESSAGES: usize = 4;
+
+// all in font pixels
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+const CELL_WIDTH: u32 = GLYPH_WIDTH + 1;
+const LINE_HEIGHT: u32 = GLYPH_HEIGHT + 3;
+const MARGIN: u32 = 4;
+
+/// The number of screen rows the font should span at least, used to scale the font
+/// with the window size
+const MIN_VIRTUAL_HEIGHT: u32 = 240;
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+#version 450
+
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec4 color;
+layout(location = 0) out vec4 v_color;
+
+void main() {
+    gl_Position = vec4(position, 0.0, 1.0);
+    v_color = color;
+}",
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+#version 450
+
+layout(location = 0) in vec4 v_color;
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    f_color = v_color;
+}"
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy, VertexTrait, BufferContents)]
+#[repr(C)]
+struct Vertex {
+    #[format(R32G32_SFLOAT)]
+    position: [f32; 2],
+    #[format(R32G32B32A32_SFLOAT)]
+    color: [f32; 4],
+}
+
+/// 5x7 glyphs, every row is 5 bits, the MSB is the leftmost pixel.
+///
+/// Only upper case letters are available, lower case are drawn as upper case,
+/// and unknown characters are drawn as `?`.
+#[rustfmt::skip]
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        ' ' => [0; 7],
+        '0' => [0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110],
+        '1' => [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+        '2' => [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111],
+        '3' => [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110],
+        '4' => [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010],
+        '5' => [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110],
+        '6' => [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110],
+        '7' => [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000],
+        '8' => [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110],
+        '9' => [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100],
+        'A' => [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        'B' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110],
+        'C' => [0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110],
+        'D' => [0b11100, 0b10010, 0b10001, 0b10001, 0b10001, 0b10010, 0b11100],
+        'E' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111],
+        'F' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000],
+        'G' => [0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111],
+        'H' => [0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        'I' => [0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+        'J' => [0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100],
+        'K' => [0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001],
+        'L' => [0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111],
+        'M' => [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001],
+        'N' => [0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001],
+        'O' => [0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        'P' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000],
+        'Q' => [0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101],
+        'R' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001],
+        'S' => [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110],
+        'T' => [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100],
+        'U' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        'V' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100],
+        'W' => [0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010],
+        'X' => [0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001],
+        'Y' => [0b10001, 0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100],
+        'Z' => [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111],
+        '.' => [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b01100],
+        ',' => [0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b00100, 0b01000],
+        ':' => [0b00000, 0b01100, 0b01100, 0b00000, 0b01100, 0b01100, 0b00000],
+        '%' => [0b11000, 0b11001, 0b00010, 0b00100, 0b01000, 0b10011, 0b00011],
+        '/' => [0b00000, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b00000],
+        '-' => [0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000],
+        '+' => [0b00000, 0b00100, 0b00100, 0b11111, 0b00100, 0b00100, 0b00000],
+        '=' => [0b00000, 0b00000, 0b11111, 0b00000, 0b11111, 0b00000, 0b00000],
+        '_' => [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b11111],
+        '(' => [0b00010, 0b00100, 0b01000, 0b01000, 0b01000, 0b00100, 0b00010],
+        ')' => [0b01000, 0b00100, 0b00010, 0b00010, 0b00010, 0b00100, 0b01000],
+        '!' => [0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00000, 0b00100],
+        _ => [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b00000, 0b00100],
+    }
+}
+
+/// The opacity of a message that was shown `elapsed` ago, `None` if it should be removed
+fn message_alpha(elapsed: Duration) -> Option<f32> {
+    if elapsed < MESSAGE_DURATION {
+        Some(1.0)
+    } else if elapsed < MESSAGE_DURATION + MESSAGE_FADE_DURATION {
+        let fade = (elapsed - MESSAGE_DURATION).as_secs_f32();
+        Some(1.0 - fade / MESSAGE_FADE_DURATION.as_secs_f32())
+    } else {
+        None
+    }
+}
+
+/// Builds the vertices of text lines, positions are in font pixels
+struct TextBatch {
+    /// size of a font pixel in normalized device coordinates
+    pixel_size: [f32; 2],
+    vertices: Vec<Vertex>,
+}
+
+impl TextBatch {
+    fn new(width: u32, height: u32, scale: u32) -> Self {
+        Self {
+            pixel_size: [
+                2.0 * scale as f32 / width as f32,
+                2.0 * scale as f32 / height as f32,
+            ],
+            vertices: Vec::new(),
+        }
+    }
+
+    fn rect(&mut self, x: u32, y: u32, w: u32, h: u32, color: [f32; 4]) {
+        let [pw, ph] = self.pixel_size;
+        let left = x as f32 * pw - 1.0;
+        let top = y as f32 * ph - 1.0;
+        let right = (x + w) as f32 * pw - 1.0;
+        let bottom = (y + h) as f32 * ph - 1.0;
+
+        for position in [
+            [left, top],
+            [right, top],
+            [left, bottom],
+            [right, top],
+            [right, bottom],
+            [left, bottom],
+        ] {
+            self.vertices.push(Vertex { position, color });
+        }
+    }
+
+    /// Draws `text` with a dark backdrop behind it
+    fn line(&mut self, x: u32, y: u32, text: &str, alpha: f32) {
+        let len = text.chars().count() as u32;
+        if len == 0 {
+            return;
+        }
+        self.rect(
+            x - 1,
+            y - 1,
+            len * CELL_WIDTH + 1,
+            GLYPH_HEIGHT + 2,
+            [0.0, 0.0, 0.0, 0.6 * alpha],
+        );
+
+        for (i, c) in text.chars().enumerate() {
+            let glyph_x = x + i as u32 * CELL_WIDTH;
+            for (row, bits) in glyph(c).into_iter().enumerate() {
+                for column in 0..GLYPH_WIDTH {
+                    if bits & (1 << (GLYPH_WIDTH - 1 - column)) != 0 {
+                        self.rect(
+                            glyph_x + column,
+                            y + row as u32,
+                            1,
+                            1,
+                            [1.0, 1.0, 1.0, alpha],
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct Message {
+    text: String,
+    shown_at: Instant,
+}
+
+/// A minimal on-screen display, drawn on top of the swapchain image after the
+/// emulator has blitted its frame.
+///
+/// Status lines (FPS, audio...) are drawn in the top left corner, and transient
+/// messages in the bottom left corner, they fade out after a couple of seconds.
+pub struct Osd {
+    queue: Arc<Queue>,
+    command_buffer_allocator: StandardCommandBufferAllocator,
+    vertex_buffer_allocator: SubbufferAllocator,
+    render_pass: Arc<RenderPass>,
+    pipeline: Arc<GraphicsPipeline>,
+    messages: VecDeque<Message>,
+}
+
+impl Osd {
+    /// `format` is the format of the images that will be drawn on,
+    /// they must have the `COLOR_ATTACHMENT` usage
+    pub fn new(device: Arc<Device>, queue: Arc<Queue>, format: Format) -> Self {
+        let vs = vs::load(device.clone())
+            .unwrap()
+            .entry_point("main")
+            .unwrap();
+        let fs = fs::load(device.clone())
+            .unwrap()
+            .entry_point("main")
+            .unwrap();
+
+        let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
+        let command_buffer_allocator =
+            StandardCommandBufferAllocator::new(device.clone(), Default::default());
+        let vertex_buffer_allocator = SubbufferAllocator::new(
+            memory_allocator,
+            SubbufferAllocatorCreateInfo {
+                buffer_usage: BufferUsage::VERTEX_BUFFER,
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+        );
+
+        // keep the emulator frame, and draw on top of it
+        let render_pass = vulkano::single_pass_renderpass!(
+            device.clone(),
+            attachments: {
+                color: {
+                    format: format,
+                    samples: 1,
+                    load_op: Load,
+                    store_op: Store,
+                },
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {},
+            },
+        )
+        .unwrap();
+
+        let vertex_input_state = Vertex::per_vertex()
+            .definition(&vs.info().input_interface)
+            .unwrap();
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vs),
+            PipelineShaderStageCreateInfo::new(fs),
+        ];
+
+        let layout = PipelineLayout::new(
+            device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(device.clone())
+                .unwrap(),
+        )
+        .unwrap();
+
+        let subpass = Subpass::from(render_pass.clone(), 0).unwrap();
+        let pipeline = GraphicsPipeline::new(
+            device.clone(),
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.iter().cloned().collect(),
+                vertex_input_state: Some(vertex_input_state),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                multisample_state: Some(MultisampleState::default()),
+                viewport_state: Some(ViewportState::default()),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    1,
+                    ColorBlendAttachmentState {
+                        blend: Some(AttachmentBlend::alpha()),
+                        ..Default::default()
+                    },
+                )),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )
+        .unwrap();
+
+        Self {
+            queue,
+            command_buffer_allocator,
+            vertex_buffer_allocator,
+            render_pass,
+            pipeline,
+            messages: VecDeque::new(),
+        }
+    }
+
+    /// Show a message that fades after a couple of seconds
+    pub fn show_message<S: Into<String>>(&mut self, text: S) {
+        if self.messages.len() == MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(Message {
+            text: text.into(),
+            shown_at: Instant::now(),
+        });
+    }
+
+    /// Draw the `status` lines and the active messages on top of `dest_image`
+    pub fn draw(
+        &mut self,
+        dest_image: Arc<Image>,
+        status: &[String],
+        in_future: Box<dyn GpuFuture>,
+    ) -> Box<dyn GpuFuture> {
+        let now = Instant::now();
+        self.messages
+            .retain(|m| message_alpha(now.duration_since(m.shown_at)).is_some());
+
+        let [width, height, _] = dest_image.extent();
+        let scale = (height / MIN_VIRTUAL_HEIGHT).max(1);
+        let virtual_height = height / scale;
+
+        let mut batch = TextBatch::new(width, height, scale);
+        for (i, line) in status.iter().enumerate() {
+            batch.line(MARGIN, MARGIN + i as u32 * LINE_HEIGHT, line, 1.0);
+        }
+        let messages_top =
+            virtual_height.saturating_sub(MARGIN + self.messages.len() as u32 * LINE_HEIGHT);
+        for (i, message) in self.messages.iter().enumerate() {
+            let alpha = message_alpha(now.duration_since(message.shown_at)).unwrap_or(0.0);
+            batch.line(
+                MARGIN,
+                messages_top + i as u32 * LINE_HEIGHT,
+                &message.text,
+                alpha,
+            );
+        }
+
+        if batch.vertices.is_empty() {
+            return in_future;
+        }
+
+        let vertex_buffer = self
+            .vertex_buffer_allocator
+            .allocate_slice(batch.vertices.len() as u64)
+            .unwrap();
+        vertex_buffer
+            .write()
+            .unwrap()
+            .copy_from_slice(&batch.vertices);
+
+        let framebuffer = Framebuffer::new(
+            self.render_pass.clone(),
+            FramebufferCreateInfo {
+                attachments: vec![ImageView::new_default(dest_image).unwrap()],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let mut builder: AutoCommandBufferBuilder<PrimaryAutoCommandBuffer> =
+            AutoCommandBufferBuilder::primary(
+                &self.command_buffer_allocator,
+                self.queue.queue_family_index(),
+                CommandBufferUsage::OneTimeSubmit,
+            )
+            .unwrap();
+
+        builder
+            .begin_render_pass(
+                RenderPassBeginInfo {
+                    clear_values: vec![None],
+                    ..RenderPassBeginInfo::framebuffer(framebuffer)
+                },
+                Default::default(),
+            )
+            .unwrap()
+            .set_viewport(
+                0,
+                [Viewport {
+                    offset: [0.0, 0.0],
+                    extent: [width as f32, height as f32],
+                    depth_range: 0.0..=1.0,
+                }]
+                .into_iter()
+                .collect(),
+            )
+            .unwrap()
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .unwrap()
+            .bind_vertex_buffers(0, vertex_buffer)
+            .unwrap()
+            .draw(batch.vertices.len() as u32, 1, 0, 0)
+            .unwrap()
+            .end_render_pass(Default::default())
+            .unwrap();
+
+        let command_buffer = builder.build().unwrap();
+
+        in_future
+            .then_execute(self.queue.clone(), command_buffer)
+            .unwrap()
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_fade() {
+        assert_eq!(message_alpha(Duration::ZERO), Some(1.0));
+        assert_eq!(message_alpha(MESSAGE_DURATION / 2), Some(1.0));
+
+        let alpha = message_alpha(MESSAGE_DURATION + MESSAGE_FADE_DURATION / 2).unwrap();
+        assert!((alpha - 0.5).abs() < 0.01);
+
+        assert_eq!(
+            message_alpha(MESSAGE_DURATION + MESSAGE_FADE_DURATION),
+            None
+        );
+    }
+
+    #[test]
+    fn text_layout() {
+        let mut batch = TextBatch::new(640, 480, 2);
+        batch.line(MARGIN, MARGIN, "", 1.0);
+        assert!(batch.vertices.is_empty());
+
+        // backdrop + 10 pixels of `1`
+        batch.line(MARGIN, MARGIN, "1", 1.0);
+        assert_eq!(batch.vertices.len(), (1 + 10) * 6);
+
+        // the backdrop starts one pixel before the text (2 screen pixels)
+        let [x, y] = batch.vertices[0].position;
+        assert!((x - (-1.0 + 2.0 * 6.0 / 640.0)).abs() < 1e-6);
+        assert!((y - (-1.0 + 2.0 * 6.0 / 480.0)).abs() < 1e-6);
+
+        // lower case is drawn as upper case, unknown characters as `?`
+        assert_eq!(glyph('a'), glyph('A'));
+        assert_eq!(glyph('~'), glyph('?'));
+    }
+}
@@ -0,0 +1,472 @@
+use std::sync::Arc;
+
+use clap::ValueEnum;
+use vulkano::{
+    buffer::BufferContents,
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, CommandBufferUsage,
+        PrimaryAutoCommandBuffer, RenderPassBeginInfo,
+    },
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, PersistentDescriptorSet, WriteDescriptorSet,
+    },
+    device::{Device, Queue},
+    format::Format,
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+        view::ImageView,
+        Image,
+    },
+    pipeline::{
+        graphics::{
+            color_blend::{ColorBlendAttachmentState, ColorBlendState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            vertex_input::VertexInputState,
+            viewport::{Viewport, ViewportState},
+            GraphicsPipelineCreateInfo,
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+        DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+    render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass},
+    shader::EntryPoint,
+    sync::GpuFuture,
+};
+
+/// A triangle covering the whole output, no vertex buffer needed
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+#version 450
+
+layout(location = 0) out vec2 v_uv;
+
+void main() {
+    v_uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(v_uv * 2.0 - 1.0, 0.0, 1.0);
+}",
+    }
+}
+
+// All the fragment shaders have the same interface, so they share the pipeline layout
+
+mod fs_nearest {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+#version 450
+
+layout(location = 0) in vec2 v_uv;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D tex;
+
+layout(push_constant) uniform PushConstantData {
+    vec2 source_size;
+    vec2 output_size;
+} pc;
+
+void main() {
+    ivec2 texel = ivec2(v_uv * pc.source_size);
+    f_color = texelFetch(tex, clamp(texel, ivec2(0), ivec2(pc.source_size) - 1), 0);
+}"
+    }
+}
+
+mod fs_sharp_bilinear {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+#version 450
+
+layout(location = 0) in vec2 v_uv;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D tex;
+
+layout(push_constant) uniform PushConstantData {
+    vec2 source_size;
+    vec2 output_size;
+} pc;
+
+// nearest inside the texels, and only blend the output pixels on their borders,
+// so that non-integer scales are not uneven
+void main() {
+    vec2 texel = v_uv * pc.source_size;
+    vec2 scale = max(floor(pc.output_size / pc.source_size), vec2(1.0));
+    vec2 region_range = 0.5 - 0.5 / scale;
+    vec2 center_dist = fract(texel) - 0.5;
+    vec2 f = (center_dist - clamp(center_dist, -region_range, region_range)) * scale + 0.5;
+    f_color = texture(tex, (floor(texel) + f) / pc.source_size);
+}"
+    }
+}
+
+mod fs_scanlines {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+#version 450
+
+layout(location = 0) in vec2 v_uv;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D tex;
+
+layout(push_constant) uniform PushConstantData {
+    vec2 source_size;
+    vec2 output_size;
+} pc;
+
+void main() {
+    vec3 color = texture(tex, v_uv).rgb;
+    // darkest between the source lines, only when there are enough output lines
+    float row = fract(v_uv.y * pc.source_size.y);
+    float strength = clamp(pc.output_size.y / pc.source_size.y - 1.0, 0.0, 1.0) * 0.5;
+    float scanline = 1.0 - strength * (1.0 - sin(row * 3.14159265));
+    f_color = vec4(color * scanline, 1.0);
+}"
+    }
+}
+
+mod fs_crt {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+#version 450
+
+layout(location = 0) in vec2 v_uv;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D tex;
+
+layout(push_constant) uniform PushConstantData {
+    vec2 source_size;
+    vec2 output_size;
+} pc;
+
+const vec2 CURVATURE = vec2(0.03, 0.04);
+
+void main() {
+    vec2 centered = v_uv * 2.0 - 1.0;
+    centered *= 1.0 + centered.yx * centered.yx * CURVATURE;
+    vec2 uv = centered * 0.5 + 0.5;
+    if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0)))) {
+        f_color = vec4(0.0, 0.0, 0.0, 1.0);
+        return;
+    }
+
+    vec3 color = texture(tex, uv).rgb;
+    float row = fract(uv.y * pc.source_size.y);
+    color *= 0.65 + 0.35 * sin(row * 3.14159265);
+
+    // aperture grille, one output column per color
+    vec3 mask = vec3(0.75);
+    mask[int(gl_FragCoord.x) % 3] = 1.0;
+    // make up for the lost brightness
+    f_color = vec4(min(color * mask * 1.25, vec3(1.0)), 1.0);
+}"
+    }
+}
+
+/// The filter used to draw the emulator frame to the window
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shader {
+    /// Plain pixels, like the blit without a shader
+    Nearest,
+    /// Sharp pixels, that are blended only on their edges to hide uneven scaling
+    SharpBilinear,
+    /// Dark lines between the rows of the frame
+    Scanlines,
+    /// A curved screen, scanlines and a color mask
+    Crt,
+}
+
+impl Shader {
+    const ALL: [Shader; 4] = [
+        Shader::Nearest,
+        Shader::SharpBilinear,
+        Shader::Scanlines,
+        Shader::Crt,
+    ];
+
+    /// The next shader, cycles back to the first one
+    pub fn next(self) -> Self {
+        let i = Self::ALL.iter().position(|&s| s == self).unwrap();
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+
+    /// The name used for `--shader`
+    pub fn name(self) -> &'static str {
+        match self {
+            Shader::Nearest => "nearest",
+            Shader::SharpBilinear => "sharp-bilinear",
+            Shader::Scanlines => "scanlines",
+            Shader::Crt => "crt",
+        }
+    }
+
+    fn load(self, device: Arc<Device>) -> EntryPoint {
+        match self {
+            Shader::Nearest => fs_nearest::load(device),
+            Shader::SharpBilinear => fs_sharp_bilinear::load(device),
+            Shader::Scanlines => fs_scanlines::load(device),
+            Shader::Crt => fs_crt::load(device),
+        }
+        .unwrap()
+        .entry_point("main")
+        .unwrap()
+    }
+}
+
+#[derive(BufferContents)]
+#[repr(C)]
+struct PushConstantData {
+    source_size: [f32; 2],
+    output_size: [f32; 2],
+}
+
+/// Draws the emulator frame to the swapchain image with one of the [`Shader`]s,
+/// replacing a plain blit, it covers the whole image.
+pub struct PostProcess {
+    queue: Arc<Queue>,
+    command_buffer_allocator: StandardCommandBufferAllocator,
+    descriptor_set_allocator: StandardDescriptorSetAllocator,
+    render_pass: Arc<RenderPass>,
+    sampler: Arc<Sampler>,
+    /// One for every shader, in the order of [`Shader::ALL`]
+    pipelines: Vec<Arc<GraphicsPipeline>>,
+    shader: Shader,
+}
+
+impl PostProcess {
+    /// `format` is the format of the images that will be drawn on,
+    /// they must have the `COLOR_ATTACHMENT` usage
+    pub fn new(device: Arc<Device>, queue: Arc<Queue>, format: Format, shader: Shader) -> Self {
+        let vs = vs::load(device.clone())
+            .unwrap()
+            .entry_point("main")
+            .unwrap();
+
+        let command_buffer_allocator =
+            StandardCommandBufferAllocator::new(device.clone(), Default::default());
+        let descriptor_set_allocator =
+            StandardDescriptorSetAllocator::new(device.clone(), Default::default());
+
+        // the whole image is drawn, nothing to keep
+        let render_pass = vulkano::single_pass_renderpass!(
+            device.clone(),
+            attachments: {
+                color: {
+                    format: format,
+                    samples: 1,
+                    load_op: DontCare,
+                    store_op: Store,
+                },
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {},
+            },
+        )
+        .unwrap();
+
+        // `nearest` uses `texelFetch`, so it's not affected by the filter
+        let sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let layout = PipelineLayout::new(
+            device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&[
+                PipelineShaderStageCreateInfo::new(vs.clone()),
+                PipelineShaderStageCreateInfo::new(Shader::Nearest.load(device.clone())),
+            ])
+            .into_pipeline_layout_create_info(device.clone())
+            .unwrap(),
+        )
+        .unwrap();
+
+        let subpass = Subpass::from(render_pass.clone(), 0).unwrap();
+        let pipelines = Shader::ALL
+            .iter()
+            .map(|shader| {
+                let stages = [
+                    PipelineShaderStageCreateInfo::new(vs.clone()),
+                    PipelineShaderStageCreateInfo::new(shader.load(device.clone())),
+                ];
+                GraphicsPipeline::new(
+                    device.clone(),
+                    None,
+                    GraphicsPipelineCreateInfo {
+                        stages: stages.into_iter().collect(),
+                        vertex_input_state: Some(VertexInputState::default()),
+                        input_assembly_state: Some(InputAssemblyState::default()),
+                        rasterization_state: Some(RasterizationState::default()),
+                        multisample_state: Some(MultisampleState::default()),
+                        viewport_state: Some(ViewportState::default()),
+                        dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                        color_blend_state: Some(ColorBlendState::with_attachment_states(
+                            1,
+                            ColorBlendAttachmentState::default(),
+                        )),
+                        subpass: Some(subpass.clone().into()),
+                        ..GraphicsPipelineCreateInfo::layout(layout.clone())
+                    },
+                )
+                .unwrap()
+            })
+            .collect();
+
+        Self {
+            queue,
+            command_buffer_allocator,
+            descriptor_set_allocator,
+            render_pass,
+            sampler,
+            pipelines,
+            shader,
+        }
+    }
+
+    pub fn shader(&self) -> Shader {
+        self.shader
+    }
+
+    pub fn set_shader(&mut self, shader: Shader) {
+        self.shader = shader;
+    }
+
+    /// Draw `front_image` stretched over the whole `dest_image`
+    pub fn draw(
+        &mut self,
+        front_image: Arc<Image>,
+        dest_image: Arc<Image>,
+        in_future: Box<dyn GpuFuture>,
+    ) -> Box<dyn GpuFuture> {
+        let [source_width, source_height, _] = front_image.extent();
+        let [width, height, _] = dest_image.extent();
+
+        let index = Shader::ALL.iter().position(|&s| s == self.shader).unwrap();
+        let pipeline = self.pipelines[index].clone();
+
+        let set = PersistentDescriptorSet::new(
+            &self.descriptor_set_allocator,
+            pipeline.layout().set_layouts()[0].clone(),
+            [WriteDescriptorSet::image_view_sampler(
+                0,
+                ImageView::new_default(front_image).unwrap(),
+                self.sampler.clone(),
+            )],
+            [],
+        )
+        .unwrap();
+
+        let framebuffer = Framebuffer::new(
+            self.render_pass.clone(),
+            FramebufferCreateInfo {
+                attachments: vec![ImageView::new_default(dest_image).unwrap()],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let push_constants = PushConstantData {
+            source_size: [source_width as f32, source_height as f32],
+            output_size: [width as f32, height as f32],
+        };
+
+        let mut builder: AutoCommandBufferBuilder<PrimaryAutoCommandBuffer> =
+            AutoCommandBufferBuilder::primary(
+                &self.command_buffer_allocator,
+                self.queue.queue_family_index(),
+                CommandBufferUsage::OneTimeSubmit,
+            )
+            .unwrap();
+
+        builder
+            .begin_render_pass(
+                RenderPassBeginInfo {
+                    clear_values: vec![None],
+                    ..RenderPassBeginInfo::framebuffer(framebuffer)
+                },
+                Default::default(),
+            )
+            .unwrap()
+            .set_viewport(
+                0,
+                [Viewport {
+                    offset: [0.0, 0.0],
+                    extent: [width as f32, height as f32],
+                    depth_range: 0.0..=1.0,
+                }]
+                .into_iter()
+                .collect(),
+            )
+            .unwrap()
+            .bind_pipeline_graphics(pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                pipeline.layout().clone(),
+                0,
+                set,
+            )
+            .unwrap()
+            .push_constants(pipeline.layout().clone(), 0, push_constants)
+            .unwrap()
+            .draw(3, 1, 0, 0)
+            .unwrap()
+            .end_render_pass(Default::default())
+            .unwrap();
+
+        let command_buffer = builder.build().unwrap();
+
+        in_future
+            .then_execute(self.queue.clone(), command_buffer)
+            .unwrap()
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shader_cycle() {
+        let mut shader = Shader::Nearest;
+        for expected in [
+            Shader::SharpBilinear,
+            Shader::Scanlines,
+            Shader::Crt,
+            Shader::Nearest,
+        ] {
+            shader = shader.next();
+            assert_eq!(shader, expected);
+        }
+    }
+
+    #[test]
+    fn shader_names_match_clap() {
+        for shader in Shader::ALL {
+            assert_eq!(
+                Shader::from_str(shader.name(), false).unwrap(),
+                shader,
+                "{:?}",
+                shader
+            );
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// The most files kept in the list
+const MAX_RECENT_FILES: usize = 10;
+
+/// The file format, the most recent file is first
+///
+/// ```toml
+/// files = ["/games/game1.cue", "/homebrew/demo.exe"]
+/// ```
+#[derive(Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RecentFilesFile {
+    #[serde(default)]
+    files: Vec<PathBuf>,
+}
+
+/// The disks and EXEs loaded before, saved in the config directory after every change.
+///
+/// [`RecentFiles::select_next`] moves a cursor through the list, so loading the
+/// selected file (which moves it to the front) doesn't restart the cycle.
+pub struct RecentFiles {
+    path: Option<PathBuf>,
+    files: Vec<PathBuf>,
+    selected: Option<usize>,
+}
+
+impl RecentFiles {
+    /// `~/.config/trapezoid/recent.toml` on linux, and similar locations on other platforms
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|d| d.join("trapezoid").join("recent.toml"))
+    }
+
+    /// Load the list from the default path, a missing or broken file is an empty list
+    pub fn load() -> Self {
+        let path = Self::default_path();
+        let files = match path.as_ref().map(std::fs::read_to_string) {
+            Some(Ok(content)) => Self::parse(&content).unwrap_or_else(|e| {
+                log::error!("Invalid recent files list {:?}: {}", path, e);
+                Vec::new()
+            }),
+            _ => Vec::new(),
+        };
+        Self {
+            path,
+            files,
+            selected: None,
+        }
+    }
+
+    fn parse(content: &str) -> Result<Vec<PathBuf>, String> {
+        let file: RecentFilesFile = toml::from_str(content).map_err(|e| e.to_string())?;
+        let mut files = file.files;
+        files.truncate(MAX_RECENT_FILES);
+        Ok(files)
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let content = toml::to_string(&RecentFilesFile {
+            files: self.files.clone(),
+        })
+        .expect("the list is always serializable");
+
+        let result = path
+            .parent()
+            .map_or(Ok(()), |p| std::fs::create_dir_all(p))
+            .and_then(|_| std::fs::write(path, content));
+        if let Err(e) = result {
+            log::error!("Could not save the recent files list {:?}: {}", path, e);
+        }
+    }
+
+    /// Move `file` to the front of the list, and save it
+    pub fn add(&mut self, file: &Path) {
+        let file = file.canonicalize().unwrap_or_else(|_| file.to_path_buf());
+        // keep the cursor on the same file
+        let selected = self.selected.map(|i| self.files[i].clone());
+
+        self.files.retain(|f| *f != file);
+        self.files.insert(0, file);
+        self.files.truncate(MAX_RECENT_FILES);
+
+        self.selected = selected.and_then(|s| self.files.iter().position(|f| *f == s));
+        self.save();
+    }
+
+    /// Select the next (older) file, wrapping around, returns the selected
+    /// file and its position
+    pub fn select_next(&mut self) -> Option<(usize, &Path)> {
+        if self.files.is_empty() {
+            return None;
+        }
+        let next = self.selected.map_or(0, |i| (i + 1) % self.files.len());
+        self.selected = Some(next);
+        Some((next, &self.files[next]))
+    }
+
+    pub fn selected(&self) -> Option<&Path> {
+        self.selected.map(|i| self.files[i].as_path())
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn in_memory(files: &[&str]) -> RecentFiles {
+        RecentFiles {
+            path: None,
+            files: files.iter().map(PathBuf::from).collect(),
+            selected: None,
+        }
+    }
+
+    #[test]
+    fn parse_limit() {
+        let content = format!(
+            "files = [{}]",
+            (0..15)
+                .map(|i| format!("\"game{}.cue\"", i))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let files = RecentFiles::parse(&content).unwrap();
+        assert_eq!(files.len(), MAX_RECENT_FILES);
+        assert_eq!(files[0], PathBuf::from("game0.cue"));
+
+        assert!(RecentFiles::parse("files = 1").is_err());
+        assert!(RecentFiles::parse("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn add_moves_to_front() {
+        let mut recent = in_memory(&["/a.cue", "/b.cue", "/c.exe"]);
+        recent.add(Path::new("/c.exe"));
+        assert_eq!(
+            recent.files,
+            [
+                Path::new("/c.exe"),
+                Path::new("/a.cue"),
+                Path::new("/b.cue")
+            ]
+        );
+        recent.add(Path::new("/d.cue"));
+        assert_eq!(recent.files[0], Path::new("/d.cue"));
+        assert_eq!(recent.len(), 4);
+    }
+
+    #[test]
+    fn cycle_selection() {
+        let mut recent = in_memory(&[]);
+        assert_eq!(recent.select_next(), None);
+
+        let mut recent = in_memory(&["/a.cue", "/b.cue", "/c.exe"]);
+        assert_eq!(recent.select_next(), Some((0, Path::new("/a.cue"))));
+        assert_eq!(recent.select_next(), Some((1, Path::new("/b.cue"))));
+
+        // loading the selected file keeps the cursor on it
+        recent.add(Path::new("/b.cue"));
+        assert_eq!(recent.selected(), Some(Path::new("/b.cue")));
+        assert_eq!(recent.select_next(), Some((1, Path::new("/a.cue"))));
+        assert_eq!(recent.select_next(), Some((2, Path::new("/c.exe"))));
+        assert_eq!(recent.select_next(), Some((0, Path::new("/b.cue"))));
+    }
+}
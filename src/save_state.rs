@@ -0,0 +1,95 @@
+//! Save state files, each disk has [`SLOTS`] of them next to its image.
+//!
+//! `trapezoid-core` can't save or load its state yet, so loading a state
+//! fails with an error until it does.
+
+use std::path::{Path, PathBuf};
+
+/// The number of save slots of a disk
+pub const SLOTS: u8 = 4;
+
+/// The file of `slot` (`1` to [`SLOTS`]) for the disk at `disk_path`, next to it and
+/// named by the disk ID, e.g. `games/SLUS_007.71.ss1`.
+///
+/// EXEs and disks without an ID use the file name of the disk instead.
+pub fn slot_path(disk_path: &Path, disk_id: Option<&str>, slot: u8) -> PathBuf {
+    let name = match disk_id {
+        Some(id) => id.to_string(),
+        None => disk_path
+            .file_stem()
+            .unwrap_or(disk_path.as_os_str())
+            .to_string_lossy()
+            .into_owned(),
+    };
+    disk_path.with_file_name(format!("{}.ss{}", name, slot))
+}
+
+/// The file of `--load-state`, which is either a path or a slot number of the disk
+pub fn resolve_arg(
+    arg: &Path,
+    disk_path: Option<&Path>,
+    disk_id: Option<&str>,
+) -> Result<PathBuf, String> {
+    let Some(slot) = arg.to_str().and_then(|s| s.parse::<u8>().ok()) else {
+        return Ok(arg.to_path_buf());
+    };
+
+    if !(1..=SLOTS).contains(&slot) {
+        return Err(format!("the slot must be 1 to {}", SLOTS));
+    }
+    let disk_path = disk_path.ok_or("a slot needs a disk or exe file")?;
+    Ok(slot_path(disk_path, disk_id, slot))
+}
+
+/// Load the state file at `path`
+pub fn load(path: &Path) -> Result<(), String> {
+    std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Err("save states are not supported by trapezoid-core yet".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slot_named_by_disk_id() {
+        assert_eq!(
+            slot_path(Path::new("games/game.cue"), Some("SLUS_007.71"), 1),
+            Path::new("games/SLUS_007.71.ss1")
+        );
+        assert_eq!(
+            slot_path(Path::new("homebrew/demo.exe"), None, 4),
+            Path::new("homebrew/demo.ss4")
+        );
+    }
+
+    #[test]
+    fn load_state_arg() {
+        let disk = Some(Path::new("games/game.cue"));
+
+        assert_eq!(
+            resolve_arg(Path::new("states/game.ss1"), disk, None),
+            Ok(PathBuf::from("states/game.ss1"))
+        );
+        assert_eq!(
+            resolve_arg(Path::new("2"), disk, Some("SLUS_007.71")),
+            Ok(PathBuf::from("games/SLUS_007.71.ss2"))
+        );
+        assert!(resolve_arg(Path::new("0"), disk, None).is_err());
+        assert!(resolve_arg(Path::new("5"), disk, None).is_err());
+        assert!(resolve_arg(Path::new("1"), None, None).is_err());
+    }
+
+    #[test]
+    fn load_fails_cleanly() {
+        let missing = std::env::temp_dir().join("trapezoid_missing_state.ss1");
+        let e = load(&missing).unwrap_err();
+        assert!(e.contains("trapezoid_missing_state.ss1"), "{}", e);
+
+        let path = std::env::temp_dir().join("trapezoid_state_test.ss1");
+        std::fs::write(&path, [0; 16]).unwrap();
+        let result = load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,219 @@
+//! Rhai scripts for `--script`, see the `Scripting` section of the README
+//! for the functions available to them.
+
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicPtr, Ordering},
+        Arc,
+    },
+};
+
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, Scope, AST, INT};
+use trapezoid_core::{
+    cpu::CPU_REGISTERS,
+    scripting::{ScriptEngine, ScriptHandle},
+    BusError,
+};
+
+use crate::config::parse_digital_key;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// The handle of the running callback, null outside of the callbacks
+#[derive(Clone, Default)]
+struct CurrentHandle(Arc<AtomicPtr<ScriptHandle<'static>>>);
+
+impl CurrentHandle {
+    fn with<T>(&self, f: impl FnOnce(&mut ScriptHandle) -> T) -> ScriptResult<T> {
+        let handle = self.0.load(Ordering::Relaxed);
+        if handle.is_null() {
+            return Err("the emulator can only be used inside the callbacks".into());
+        }
+        // SAFETY: the pointer is only set by `RhaiScript::call` while it borrows
+        // the handle, and the script runs on the same thread inside that call
+        Ok(f(unsafe { &mut *handle }))
+    }
+}
+
+pub struct RhaiScript {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    /// `this` in the callbacks, a map kept between calls for the script state
+    state: Dynamic,
+    current: CurrentHandle,
+}
+
+impl RhaiScript {
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let current = CurrentHandle::default();
+        let engine = create_engine(&current);
+
+        let ast = engine
+            .compile_file(path.to_path_buf())
+            .map_err(|e| format!("Could not load the script {:?}: {}", path, e))?;
+        let mut scope = Scope::new();
+        // top level statements, e.g. constants
+        engine
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(|e| format!("Script error: {}", e))?;
+
+        Ok(Self {
+            engine,
+            ast,
+            scope,
+            state: Dynamic::from_map(Default::default()),
+            current,
+        })
+    }
+
+    /// Call `name` if the script defines it, the errors are printed since the
+    /// emulation continues anyway
+    fn call(&mut self, psx: &mut ScriptHandle, name: &str, args: impl rhai::FuncArgs) {
+        if !self.ast.iter_functions().any(|f| f.name == name) {
+            return;
+        }
+
+        self.current
+            .0
+            .store((psx as *mut ScriptHandle).cast(), Ordering::Relaxed);
+        let result = self.engine.call_fn_with_options::<Dynamic>(
+            CallFnOptions::new()
+                .eval_ast(false)
+                .bind_this_ptr(&mut self.state),
+            &mut self.scope,
+            &self.ast,
+            name,
+            args,
+        );
+        self.current
+            .0
+            .store(std::ptr::null_mut(), Ordering::Relaxed);
+
+        if let Err(e) = result {
+            log::error!("Script error in `{}`: {}", name, e);
+        }
+    }
+}
+
+impl ScriptEngine for RhaiScript {
+    fn on_attach(&mut self, psx: &mut ScriptHandle) {
+        self.call(psx, "on_start", ());
+    }
+
+    fn on_frame(&mut self, psx: &mut ScriptHandle) {
+        self.call(psx, "on_frame", ());
+    }
+
+    fn on_breakpoint(&mut self, psx: &mut ScriptHandle, addr: u32) {
+        self.call(psx, "on_breakpoint", (addr as INT,));
+    }
+}
+
+fn bus_error(error: BusError) -> Box<EvalAltResult> {
+    error.to_string().into()
+}
+
+fn create_engine(current: &CurrentHandle) -> Engine {
+    let mut engine = Engine::new();
+
+    macro_rules! register_bus {
+        ($read_name:literal, $read:ident, $write_name:literal, $write:ident, $ty:ty) => {
+            let c = current.clone();
+            engine.register_fn($read_name, move |addr: INT| -> ScriptResult<INT> {
+                c.with(|psx| psx.$read(addr as u32))?
+                    .map(|v| v as INT)
+                    .map_err(bus_error)
+            });
+            let c = current.clone();
+            engine.register_fn(
+                $write_name,
+                move |addr: INT, value: INT| -> ScriptResult<()> {
+                    c.with(|psx| psx.$write(addr as u32, value as $ty))?
+                        .map_err(bus_error)
+                },
+            );
+        };
+    }
+    register_bus!("read_u32", bus_read_u32, "write_u32", bus_write_u32, u32);
+    register_bus!("read_u16", bus_read_u16, "write_u16", bus_write_u16, u16);
+    register_bus!("read_u8", bus_read_u8, "write_u8", bus_write_u8, u8);
+
+    let c = current.clone();
+    engine.register_fn("register", move |name: &str| -> ScriptResult<INT> {
+        let ty = *CPU_REGISTERS
+            .get(name)
+            .ok_or_else(|| format!("Invalid CPU register name: {}", name))?;
+        c.with(|psx| psx.registers().read(ty) as INT)
+    });
+    let c = current.clone();
+    engine.register_fn(
+        "set_register",
+        move |name: &str, value: INT| -> ScriptResult<()> {
+            let ty = *CPU_REGISTERS
+                .get(name)
+                .ok_or_else(|| format!("Invalid CPU register name: {}", name))?;
+            c.with(|psx| psx.set_register(ty, value as u32))
+        },
+    );
+
+    let c = current.clone();
+    engine.register_fn(
+        "set_key",
+        move |port: INT, key: &str, pressed: bool| -> ScriptResult<()> {
+            let key = parse_digital_key(key).ok_or_else(|| format!("Invalid key: {}", key))?;
+            if !(0..2).contains(&port) {
+                return Err(format!("Invalid controller port: {}", port).into());
+            }
+            c.with(|psx| psx.set_key_state(port as usize, key, pressed))
+        },
+    );
+
+    let c = current.clone();
+    engine.register_fn("frame", move || -> ScriptResult<INT> {
+        c.with(|psx| psx.frame() as INT)
+    });
+    let c = current.clone();
+    engine.register_fn("screenshot", move |path: &str| -> ScriptResult<()> {
+        c.with(|psx| psx.capture_screenshot(path))?
+            .map_err(|e| format!("Could not save the screenshot {:?}: {}", path, e).into())
+    });
+    let c = current.clone();
+    engine.register_fn("add_breakpoint", move |addr: INT| -> ScriptResult<()> {
+        c.with(|psx| psx.add_breakpoint(addr as u32))
+    });
+    let c = current.clone();
+    engine.register_fn("remove_breakpoint", move |addr: INT| -> ScriptResult<()> {
+        c.with(|psx| psx.remove_breakpoint(addr as u32))
+    });
+
+    engine
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(name: &str, content: &str) -> Result<RhaiScript, String> {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, content).unwrap();
+        let script = RhaiScript::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        script
+    }
+
+    #[test]
+    fn emulator_outside_callbacks() {
+        assert!(load(
+            "trapezoid_script_ok.rhai",
+            "fn on_frame() { write_u32(0, 1); }"
+        )
+        .is_ok());
+
+        let Err(err) = load("trapezoid_script_top.rhai", "read_u32(0x80000000);") else {
+            panic!("the emulator is not running yet");
+        };
+        assert!(err.contains("only be used inside the callbacks"), "{}", err);
+    }
+}
@@ -0,0 +1,569 @@
+//! `--record-video`, writes the display frames and the audio to a Matroska (`.mkv`) file.
+//!
+//! The video is MJPEG and the audio is 16bit PCM, both are encoded without any
+//! external libraries, and every frame is a keyframe, so the file can be cut
+//! anywhere. The files are a lot bigger than with AV1 or H.264, but encoding
+//! a frame takes a few milliseconds, so it doesn't slow down the emulation.
+//! Re-encode them with another tool if the size matters.
+//!
+//! The frames are timed by the emulated time, not the wall clock, so the video
+//! plays at the right speed even if the emulation was slower or faster.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+    sync::mpsc::{self, Receiver, SyncSender, TrySendError},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// The output sample rate of the emulator
+const AUDIO_SAMPLE_RATE: u32 = 44100;
+/// The frames waiting for the encoder, about half a second
+const QUEUE_SIZE: usize = 30;
+const JPEG_QUALITY: u8 = 90;
+/// A new cluster is started every second, the blocks timestamps are relative
+/// to the cluster, and can't be more than `i16::MAX` milliseconds apart
+const CLUSTER_DURATION_MS: u64 = 1000;
+
+/// Element IDs, from the Matroska specification
+mod id {
+    pub const EBML: u32 = 0x1A45DFA3;
+    pub const EBML_VERSION: u32 = 0x4286;
+    pub const EBML_READ_VERSION: u32 = 0x42F7;
+    pub const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+    pub const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+    pub const DOC_TYPE: u32 = 0x4282;
+    pub const DOC_TYPE_VERSION: u32 = 0x4287;
+    pub const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+
+    pub const SEGMENT: u32 = 0x18538067;
+    pub const INFO: u32 = 0x1549A966;
+    pub const TIMESTAMP_SCALE: u32 = 0x2AD7B1;
+    pub const MUXING_APP: u32 = 0x4D80;
+    pub const WRITING_APP: u32 = 0x5741;
+    pub const DURATION: u32 = 0x4489;
+
+    pub const TRACKS: u32 = 0x1654AE6B;
+    pub const TRACK_ENTRY: u32 = 0xAE;
+    pub const TRACK_NUMBER: u32 = 0xD7;
+    pub const TRACK_UID: u32 = 0x73C5;
+    pub const TRACK_TYPE: u32 = 0x83;
+    pub const FLAG_LACING: u32 = 0x9C;
+    pub const CODEC_ID: u32 = 0x86;
+    pub const VIDEO: u32 = 0xE0;
+    pub const PIXEL_WIDTH: u32 = 0xB0;
+    pub const PIXEL_HEIGHT: u32 = 0xBA;
+    pub const AUDIO: u32 = 0xE1;
+    pub const SAMPLING_FREQUENCY: u32 = 0xB5;
+    pub const CHANNELS: u32 = 0x9F;
+    pub const BIT_DEPTH: u32 = 0x6264;
+
+    pub const CLUSTER: u32 = 0x1F43B675;
+    pub const TIMESTAMP: u32 = 0xE7;
+    pub const SIMPLE_BLOCK: u32 = 0xA3;
+}
+
+const VIDEO_TRACK: u8 = 1;
+const AUDIO_TRACK: u8 = 2;
+
+/// Builds EBML elements in memory
+#[derive(Default)]
+struct Ebml(Vec<u8>);
+
+impl Ebml {
+    fn id(&mut self, id: u32) {
+        let bytes = id.to_be_bytes();
+        let start = bytes.iter().position(|&b| b != 0).unwrap_or(3);
+        self.0.extend_from_slice(&bytes[start..]);
+    }
+
+    /// Sizes are always 8 bytes, so they can be patched later
+    fn size(&mut self, size: u64) {
+        self.0.extend_from_slice(&((1 << 56) | size).to_be_bytes());
+    }
+
+    fn bytes(&mut self, id: u32, data: &[u8]) {
+        self.id(id);
+        self.size(data.len() as u64);
+        self.0.extend_from_slice(data);
+    }
+
+    fn uint(&mut self, id: u32, value: u64) {
+        let bytes = value.to_be_bytes();
+        let start = bytes.iter().position(|&b| b != 0).unwrap_or(7);
+        self.bytes(id, &bytes[start..]);
+    }
+
+    fn float(&mut self, id: u32, value: f64) {
+        self.bytes(id, &value.to_be_bytes());
+    }
+
+    fn string(&mut self, id: u32, value: &str) {
+        self.bytes(id, value.as_bytes());
+    }
+
+    fn master(&mut self, id: u32, f: impl FnOnce(&mut Ebml)) {
+        let mut children = Ebml::default();
+        f(&mut children);
+        self.bytes(id, &children.0);
+    }
+}
+
+/// A frame of the emulation sent to the encoder thread
+struct RecordFrame {
+    timestamp: Duration,
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+    /// Interleaved stereo samples
+    audio: Vec<f32>,
+}
+
+/// Writes the Matroska file, the video size is set by the first frame, and
+/// the next frames are scaled to it
+struct MkvWriter<W: Write + Seek> {
+    out: W,
+    width: u32,
+    height: u32,
+    /// Where the segment size and the duration are written when finishing
+    segment_size_offset: u64,
+    segment_data_offset: u64,
+    duration_offset: u64,
+    cluster: Ebml,
+    cluster_timestamp_ms: u64,
+    audio_samples: u64,
+    last_timestamp_ms: u64,
+    scaled: Vec<u8>,
+}
+
+impl<W: Write + Seek> MkvWriter<W> {
+    fn new(mut out: W, width: u32, height: u32) -> io::Result<Self> {
+        let mut header = Ebml::default();
+        header.master(id::EBML, |e| {
+            e.uint(id::EBML_VERSION, 1);
+            e.uint(id::EBML_READ_VERSION, 1);
+            e.uint(id::EBML_MAX_ID_LENGTH, 4);
+            e.uint(id::EBML_MAX_SIZE_LENGTH, 8);
+            e.string(id::DOC_TYPE, "matroska");
+            e.uint(id::DOC_TYPE_VERSION, 4);
+            e.uint(id::DOC_TYPE_READ_VERSION, 2);
+        });
+        header.id(id::SEGMENT);
+        let segment_size_offset = header.0.len() as u64;
+        // unknown, until `finish`
+        header.size(0xFFFFFFFFFFFFFF);
+        let segment_data_offset = header.0.len() as u64;
+
+        let mut info = Ebml::default();
+        info.uint(id::TIMESTAMP_SCALE, 1_000_000);
+        info.string(id::MUXING_APP, "trapezoid");
+        info.string(id::WRITING_APP, "trapezoid");
+        info.float(id::DURATION, 0.);
+        header.bytes(id::INFO, &info.0);
+        // the duration is the last element, so its value is the last 8 bytes
+        let duration_offset = header.0.len() as u64 - 8;
+
+        header.master(id::TRACKS, |e| {
+            e.master(id::TRACK_ENTRY, |e| {
+                e.uint(id::TRACK_NUMBER, VIDEO_TRACK as u64);
+                e.uint(id::TRACK_UID, VIDEO_TRACK as u64);
+                e.uint(id::TRACK_TYPE, 1);
+                e.uint(id::FLAG_LACING, 0);
+                e.string(id::CODEC_ID, "V_MJPEG");
+                e.master(id::VIDEO, |e| {
+                    e.uint(id::PIXEL_WIDTH, width as u64);
+                    e.uint(id::PIXEL_HEIGHT, height as u64);
+                });
+            });
+            e.master(id::TRACK_ENTRY, |e| {
+                e.uint(id::TRACK_NUMBER, AUDIO_TRACK as u64);
+                e.uint(id::TRACK_UID, AUDIO_TRACK as u64);
+                e.uint(id::TRACK_TYPE, 2);
+                e.uint(id::FLAG_LACING, 0);
+                e.string(id::CODEC_ID, "A_PCM/INT/LIT");
+                e.master(id::AUDIO, |e| {
+                    e.float(id::SAMPLING_FREQUENCY, AUDIO_SAMPLE_RATE as f64);
+                    e.uint(id::CHANNELS, 2);
+                    e.uint(id::BIT_DEPTH, 16);
+                });
+            });
+        });
+        out.write_all(&header.0)?;
+
+        Ok(Self {
+            out,
+            width,
+            height,
+            segment_size_offset,
+            segment_data_offset,
+            duration_offset,
+            cluster: Ebml::default(),
+            cluster_timestamp_ms: 0,
+            audio_samples: 0,
+            last_timestamp_ms: 0,
+            scaled: Vec::new(),
+        })
+    }
+
+    fn simple_block(&mut self, track: u8, timestamp_ms: u64, data: &[u8]) -> io::Result<()> {
+        if timestamp_ms >= self.cluster_timestamp_ms + CLUSTER_DURATION_MS {
+            self.flush_cluster()?;
+            self.cluster_timestamp_ms = timestamp_ms;
+        }
+        let relative = (timestamp_ms as i64 - self.cluster_timestamp_ms as i64) as i16;
+
+        self.cluster.id(id::SIMPLE_BLOCK);
+        self.cluster.size(4 + data.len() as u64);
+        // the track number as a 1 byte vint
+        self.cluster.0.push(0x80 | track);
+        self.cluster.0.extend_from_slice(&relative.to_be_bytes());
+        // keyframe
+        self.cluster.0.push(0x80);
+        self.cluster.0.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn flush_cluster(&mut self) -> io::Result<()> {
+        if self.cluster.0.is_empty() {
+            return Ok(());
+        }
+        let mut timestamp = Ebml::default();
+        timestamp.uint(id::TIMESTAMP, self.cluster_timestamp_ms);
+        let blocks = std::mem::take(&mut self.cluster);
+
+        let mut cluster = Ebml::default();
+        cluster.id(id::CLUSTER);
+        cluster.size((timestamp.0.len() + blocks.0.len()) as u64);
+        self.out.write_all(&cluster.0)?;
+        self.out.write_all(&timestamp.0)?;
+        self.out.write_all(&blocks.0)
+    }
+
+    fn write_frame(&mut self, frame: &RecordFrame) -> io::Result<()> {
+        let mut rgba = frame.rgba.as_slice();
+        if (frame.width, frame.height) != (self.width, self.height) {
+            scale_nearest(
+                &frame.rgba,
+                [frame.width, frame.height],
+                &mut self.scaled,
+                [self.width, self.height],
+            );
+            rgba = &self.scaled;
+        }
+
+        let mut jpeg = Vec::new();
+        jpeg_encoder::Encoder::new(&mut jpeg, JPEG_QUALITY)
+            .encode(
+                rgba,
+                self.width as u16,
+                self.height as u16,
+                jpeg_encoder::ColorType::Rgba,
+            )
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let video_timestamp_ms = frame.timestamp.as_millis() as u64;
+        self.simple_block(VIDEO_TRACK, video_timestamp_ms, &jpeg)?;
+
+        if !frame.audio.is_empty() {
+            let audio_timestamp_ms = self.audio_samples * 1000 / AUDIO_SAMPLE_RATE as u64;
+            let pcm: Vec<u8> = frame
+                .audio
+                .iter()
+                .flat_map(|s| ((s.clamp(-1., 1.) * i16::MAX as f32) as i16).to_le_bytes())
+                .collect();
+            self.simple_block(AUDIO_TRACK, audio_timestamp_ms, &pcm)?;
+            self.audio_samples += frame.audio.len() as u64 / 2;
+        }
+
+        self.last_timestamp_ms = video_timestamp_ms;
+        Ok(())
+    }
+
+    /// Write the last cluster, and the sizes that were unknown until now
+    fn finish(mut self) -> io::Result<W> {
+        self.flush_cluster()?;
+
+        let audio_duration_ms = self.audio_samples * 1000 / AUDIO_SAMPLE_RATE as u64;
+        let duration_ms = self.last_timestamp_ms.max(audio_duration_ms);
+
+        let end = self.out.stream_position()?;
+        self.out.seek(SeekFrom::Start(self.segment_size_offset))?;
+        let mut size = Ebml::default();
+        size.size(end - self.segment_data_offset);
+        self.out.write_all(&size.0)?;
+        self.out.seek(SeekFrom::Start(self.duration_offset))?;
+        self.out.write_all(&(duration_ms as f64).to_be_bytes())?;
+        self.out.seek(SeekFrom::Start(end))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+fn scale_nearest(
+    src: &[u8],
+    [src_w, src_h]: [u32; 2],
+    dst: &mut Vec<u8>,
+    [dst_w, dst_h]: [u32; 2],
+) {
+    dst.clear();
+    for y in 0..dst_h {
+        let src_y = y * src_h / dst_h;
+        for x in 0..dst_w {
+            let src_x = x * src_w / dst_w;
+            let i = ((src_y * src_w + src_x) * 4) as usize;
+            dst.extend_from_slice(&src[i..i + 4]);
+        }
+    }
+}
+
+fn encoder_thread(file: File, frames: Receiver<RecordFrame>) -> io::Result<()> {
+    // the size is known from the first frame
+    let Ok(first) = frames.recv() else {
+        return Ok(());
+    };
+    let mut writer = MkvWriter::new(BufWriter::new(file), first.width, first.height)?;
+    writer.write_frame(&first)?;
+    for frame in frames {
+        writer.write_frame(&frame)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// Encodes the frames in its own thread, the file is finalized when dropped
+pub struct VideoRecorder {
+    sender: Option<SyncSender<RecordFrame>>,
+    thread: Option<JoinHandle<io::Result<()>>>,
+    /// The time of the first frame is `0`
+    timestamp: Duration,
+    last_emulated_time: Option<Duration>,
+    warned: bool,
+}
+
+impl VideoRecorder {
+    pub fn start(path: &Path) -> io::Result<Self> {
+        let is_mkv = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("mkv"));
+        if !is_mkv {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "only .mkv files can be recorded",
+            ));
+        }
+
+        let file = File::create(path)?;
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_SIZE);
+        let thread = thread::Builder::new()
+            .name("video_record".to_string())
+            .spawn(move || encoder_thread(file, receiver))?;
+
+        Ok(Self {
+            sender: Some(sender),
+            thread: Some(thread),
+            timestamp: Duration::ZERO,
+            last_emulated_time: None,
+            warned: false,
+        })
+    }
+
+    /// Record a frame, and the audio produced with it. `emulated_time` is the
+    /// time of the emulator when the frame was done, it can go back when
+    /// the emulator is restarted.
+    ///
+    /// If the encoder falls behind, this waits for it, so no frames are lost.
+    pub fn push(
+        &mut self,
+        emulated_time: Duration,
+        (width, height, rgba): (u32, u32, Vec<u8>),
+        audio: Vec<f32>,
+    ) {
+        if let Some(last) = self.last_emulated_time {
+            self.timestamp += emulated_time.saturating_sub(last);
+        }
+        self.last_emulated_time = Some(emulated_time);
+
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        let frame = RecordFrame {
+            timestamp: self.timestamp,
+            width,
+            height,
+            rgba,
+            audio,
+        };
+        let result = match sender.try_send(frame) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(frame)) => {
+                if !self.warned {
+                    log::warn!("the video encoder can't keep up, the emulation will slow down");
+                    self.warned = true;
+                }
+                sender.send(frame).map_err(|_| ())
+            }
+            Err(TrySendError::Disconnected(_)) => Err(()),
+        };
+        // the encoder failed, the error is reported by `finish`
+        if result.is_err() {
+            self.sender = None;
+        }
+    }
+
+    /// Wait for the encoder to write the remaining frames and finalize the file
+    pub fn finish(mut self) -> io::Result<()> {
+        self.finish_inner()
+    }
+
+    fn finish_inner(&mut self) -> io::Result<()> {
+        self.sender = None;
+        match self.thread.take() {
+            Some(thread) => thread
+                .join()
+                .map_err(|_| io::Error::new(io::ErrorKind::Other, "the encoder panicked"))?,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for VideoRecorder {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish_inner() {
+            log::error!("Failed to record the video: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    /// `(id, data)` of the elements in `data`, the IDs are kept with their marker bits
+    fn elements(mut data: &[u8]) -> Vec<(u32, &[u8])> {
+        fn vint(data: &[u8]) -> (u64, usize) {
+            let len = data[0].leading_zeros() as usize + 1;
+            let mut value = (data[0] as u64) & (0xFF >> len);
+            for &b in &data[1..len] {
+                value = (value << 8) | b as u64;
+            }
+            (value, len)
+        }
+
+        let mut out = Vec::new();
+        while !data.is_empty() {
+            let id_len = data[0].leading_zeros() as usize + 1;
+            let id = data[..id_len]
+                .iter()
+                .fold(0u32, |id, &b| (id << 8) | b as u32);
+            let (size, size_len) = vint(&data[id_len..]);
+            let start = id_len + size_len;
+            let end = start + size as usize;
+            out.push((id, &data[start..end]));
+            data = &data[end..];
+        }
+        out
+    }
+
+    fn child(data: &[u8], id: u32) -> &[u8] {
+        elements(data)
+            .into_iter()
+            .find(|(i, _)| *i == id)
+            .unwrap()
+            .1
+    }
+
+    fn uint(data: &[u8]) -> u64 {
+        data.iter().fold(0, |v, &b| (v << 8) | b as u64)
+    }
+
+    #[test]
+    fn record_frames() {
+        let mut writer = MkvWriter::new(Cursor::new(Vec::new()), 320, 240).unwrap();
+        for i in 0..60u32 {
+            // the second half is in another resolution
+            let (width, height) = if i < 30 { (320, 240) } else { (640, 480) };
+            let frame = RecordFrame {
+                timestamp: Duration::from_secs(i as u64) / 60,
+                width,
+                height,
+                rgba: vec![i as u8; (width * height * 4) as usize],
+                audio: vec![0.5; 735 * 2],
+            };
+            writer.write_frame(&frame).unwrap();
+        }
+        let file = writer.finish().unwrap().into_inner();
+
+        let top = elements(&file);
+        assert_eq!(top.len(), 2);
+        assert_eq!(top[0].0, id::EBML);
+        assert_eq!(child(top[0].1, id::DOC_TYPE), b"matroska");
+        assert_eq!(top[1].0, id::SEGMENT);
+        let segment = elements(top[1].1);
+
+        let info = child(top[1].1, id::INFO);
+        let duration = f64::from_be_bytes(child(info, id::DURATION).try_into().unwrap());
+        assert_eq!(duration, 1000.);
+
+        let tracks = elements(child(top[1].1, id::TRACKS));
+        assert_eq!(tracks.len(), 2);
+        assert_eq!(child(tracks[0].1, id::CODEC_ID), b"V_MJPEG");
+        assert_eq!(
+            uint(child(child(tracks[0].1, id::VIDEO), id::PIXEL_WIDTH)),
+            320
+        );
+        assert_eq!(child(tracks[1].1, id::CODEC_ID), b"A_PCM/INT/LIT");
+
+        let mut video_frames = 0;
+        let mut audio_bytes = 0;
+        let mut last_video_ms = 0;
+        for (_, cluster) in segment.iter().filter(|(i, _)| *i == id::CLUSTER) {
+            let cluster_ms = uint(child(cluster, id::TIMESTAMP));
+            for (_, block) in elements(cluster)
+                .into_iter()
+                .filter(|(i, _)| *i == id::SIMPLE_BLOCK)
+            {
+                let relative = i16::from_be_bytes([block[1], block[2]]) as i64;
+                match block[0] & 0x7F {
+                    VIDEO_TRACK => {
+                        // a JPEG
+                        assert_eq!(&block[4..6], [0xFF, 0xD8]);
+                        video_frames += 1;
+                        last_video_ms = cluster_ms as i64 + relative;
+                    }
+                    AUDIO_TRACK => audio_bytes += block.len() - 4,
+                    track => panic!("unknown track {}", track),
+                }
+            }
+        }
+        assert_eq!(video_frames, 60);
+        assert_eq!(last_video_ms, 59 * 1000 / 60);
+        assert_eq!(audio_bytes, 60 * 735 * 4);
+    }
+
+    #[test]
+    fn timestamps_follow_emulated_time() {
+        let path =
+            std::env::temp_dir().join(format!("trapezoid-record-{}.mkv", std::process::id()));
+        let mut recorder = VideoRecorder::start(&path).unwrap();
+        let frame = || (2, 2, vec![0; 16]);
+        recorder.push(Duration::from_millis(500), frame(), Vec::new());
+        recorder.push(Duration::from_millis(600), frame(), Vec::new());
+        // restarted
+        recorder.push(Duration::from_millis(100), frame(), Vec::new());
+        recorder.push(Duration::from_millis(200), frame(), Vec::new());
+        assert_eq!(recorder.timestamp, Duration::from_millis(200));
+        recorder.finish().unwrap();
+
+        let file = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let segment = elements(&file)[1].1;
+        let info = child(segment, id::INFO);
+        let duration = f64::from_be_bytes(child(info, id::DURATION).try_into().unwrap());
+        assert_eq!(duration, 200.);
+
+        assert!(VideoRecorder::start(Path::new("out.mp4")).is_err());
+    }
+}
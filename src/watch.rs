@@ -0,0 +1,82 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// How often the file is checked, a build usually takes longer than this anyway
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Polls the modification time of the running EXE, for `--watch`
+pub struct ExeWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    last_poll: Instant,
+}
+
+impl ExeWatcher {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            modified: modified_time(path),
+            last_poll: Instant::now(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the file was modified since the last call, it's checked at most
+    /// every [`POLL_INTERVAL`].
+    ///
+    /// The file can be caught in the middle of being written, then it fails to load,
+    /// and the end of the write is another change.
+    pub fn poll(&mut self) -> bool {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = Instant::now();
+
+        // a missing file is being replaced, wait for the new one
+        let Some(modified) = modified_time(&self.path) else {
+            return false;
+        };
+        if self.modified == Some(modified) {
+            return false;
+        }
+        self.modified = Some(modified);
+        true
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_modification() {
+        let path = std::env::temp_dir().join(format!("trapezoid_watch_{}.exe", std::process::id()));
+        std::fs::write(&path, b"old").unwrap();
+        let mut watcher = ExeWatcher::new(&path);
+        std::thread::sleep(POLL_INTERVAL);
+        assert!(!watcher.poll());
+
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        // not checked again yet
+        assert!(!watcher.poll());
+        std::thread::sleep(POLL_INTERVAL);
+        assert!(watcher.poll());
+        std::thread::sleep(POLL_INTERVAL);
+        assert!(!watcher.poll());
+
+        std::fs::remove_file(&path).unwrap();
+        std::thread::sleep(POLL_INTERVAL);
+        assert!(!watcher.poll());
+    }
+}
@@ -0,0 +1,216 @@
+use std::{collections::BTreeMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+/// The hashes of one title at one of the checkpoint frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hashes {
+    pub state: u64,
+    pub vram: u64,
+}
+
+/// The hashes are saved as hex strings, TOML integers are signed 64-bit
+#[derive(Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct TitleFile {
+    state: Vec<String>,
+    vram: Vec<String>,
+}
+
+/// The file format
+///
+/// ```toml
+/// bios = "8b2e4c3f6a0d1e57"
+/// frames = [60, 300, 600]
+///
+/// [titles.bios]
+/// state = ["...", "...", "..."]
+/// vram = ["...", "...", "..."]
+/// ```
+#[derive(Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ExpectedFile {
+    /// The hash of the BIOS file the hashes were generated with
+    #[serde(default)]
+    bios: Option<String>,
+    frames: Vec<u64>,
+    #[serde(default)]
+    titles: BTreeMap<String, TitleFile>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Expected {
+    pub bios: Option<u64>,
+    pub frames: Vec<u64>,
+    /// The hashes at each of `frames`, by title name
+    pub titles: BTreeMap<String, Vec<Hashes>>,
+}
+
+/// Written at the start of the file on `--regenerate`, since the comments are lost
+const HEADER: &str = "\
+# The expected hashes of `boot-test`, regenerate them with:
+#   cargo run -p boot-test --release -- --bios <BIOS> --regenerate [IMAGES...]
+#
+# The hashes depend on the BIOS, so they are only compared when `bios` matches
+# the hash of the BIOS file used. The BIOS can't be distributed, so this file
+# starts without any title, CI generates the BIOS ones with the BIOS from
+# `.github/extract_bios.sh` (in `test_roms`) and uploads them as an artifact.
+";
+
+fn parse_hash(hash: &str) -> Result<u64, String> {
+    u64::from_str_radix(hash, 16).map_err(|_| format!("invalid hash {:?}", hash))
+}
+
+pub fn format_hash(hash: u64) -> String {
+    format!("{:016x}", hash)
+}
+
+impl Expected {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {:?}: {}", path, e))?;
+        Self::parse(&content).map_err(|e| format!("Invalid expected hashes {:?}: {}", path, e))
+    }
+
+    fn parse(content: &str) -> Result<Self, String> {
+        let file: ExpectedFile = toml::from_str(content).map_err(|e| e.to_string())?;
+        if file.frames.is_empty() || !file.frames.windows(2).all(|w| w[0] < w[1]) {
+            return Err("`frames` must be increasing and not empty".to_string());
+        }
+
+        let mut titles = BTreeMap::new();
+        for (name, title) in file.titles {
+            if title.state.len() != file.frames.len() || title.vram.len() != file.frames.len() {
+                return Err(format!(
+                    "title {:?} must have a hash for each of the {} frames",
+                    name,
+                    file.frames.len()
+                ));
+            }
+            let hashes = title
+                .state
+                .iter()
+                .zip(&title.vram)
+                .map(|(state, vram)| {
+                    Ok(Hashes {
+                        state: parse_hash(state)?,
+                        vram: parse_hash(vram)?,
+                    })
+                })
+                .collect::<Result<_, String>>()?;
+            titles.insert(name, hashes);
+        }
+
+        Ok(Self {
+            bios: file.bios.as_deref().map(parse_hash).transpose()?,
+            frames: file.frames,
+            titles,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let file = ExpectedFile {
+            bios: self.bios.map(format_hash),
+            frames: self.frames.clone(),
+            titles: self
+                .titles
+                .iter()
+                .map(|(name, hashes)| {
+                    let title = TitleFile {
+                        state: hashes.iter().map(|h| format_hash(h.state)).collect(),
+                        vram: hashes.iter().map(|h| format_hash(h.vram)).collect(),
+                    };
+                    (name.clone(), title)
+                })
+                .collect(),
+        };
+        let content = format!(
+            "{}{}",
+            HEADER,
+            toml::to_string(&file).expect("the hashes are always serializable")
+        );
+        std::fs::write(path, content).map_err(|e| format!("Could not write {:?}: {}", path, e))
+    }
+}
+
+/// The differences between `got` and `expected`, one line for each
+/// hash that doesn't match
+pub fn diff(frames: &[u64], got: &[Hashes], expected: &[Hashes]) -> Vec<String> {
+    let mut lines = Vec::new();
+    for ((frame, got), expected) in frames.iter().zip(got).zip(expected) {
+        if got.state != expected.state {
+            lines.push(format!(
+                "frame {}: state {} != expected {}",
+                frame,
+                format_hash(got.state),
+                format_hash(expected.state)
+            ));
+        }
+        if got.vram != expected.vram {
+            lines.push(format!(
+                "frame {}: vram  {} != expected {}",
+                frame,
+                format_hash(got.vram),
+                format_hash(expected.vram)
+            ));
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut expected = Expected {
+            bios: Some(0x1234),
+            frames: vec![60, 300],
+            titles: BTreeMap::new(),
+        };
+        expected.titles.insert(
+            "game.cue".to_string(),
+            vec![
+                Hashes {
+                    state: u64::MAX,
+                    vram: 0,
+                },
+                Hashes {
+                    state: 1,
+                    vram: 0xABCD,
+                },
+            ],
+        );
+        let path = std::env::temp_dir().join("boot-test-round-trip.toml");
+        expected.save(&path).unwrap();
+        assert_eq!(Expected::load(&path).unwrap(), expected);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn invalid_files() {
+        assert!(Expected::parse("frames = []").is_err());
+        assert!(Expected::parse("frames = [300, 60]").is_err());
+        // missing a hash for the second frame
+        assert!(Expected::parse(
+            "frames = [60, 300]\n[titles.bios]\nstate = [\"01\"]\nvram = [\"01\", \"02\"]"
+        )
+        .is_err());
+        assert!(
+            Expected::parse("frames = [60]\n[titles.bios]\nstate = [\"xy\"]\nvram = [\"01\"]")
+                .is_err()
+        );
+        assert!(Expected::parse(include_str!("../expected.toml")).is_ok());
+    }
+
+    #[test]
+    fn diff_lines() {
+        let a = Hashes { state: 1, vram: 2 };
+        let b = Hashes { state: 1, vram: 3 };
+        assert!(diff(&[60, 300], &[a, a], &[a, a]).is_empty());
+        let lines = diff(&[60, 300], &[a, a], &[a, b]);
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("frame 300: vram"));
+    }
+}
@@ -0,0 +1,388 @@
+//! Boot the BIOS alone, and each of the given disks and EXEs, for a few frames,
+//! and compare [`Psx::state_hash`] and a hash of the VRAM at some of the frames
+//! with the expected hashes in a TOML file.
+//!
+//! Each title runs in its own process (the same binary with `--child`), so a panic
+//! or a hang in one title only fails that title.
+
+mod expected;
+
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use expected::{format_hash, Expected, Hashes};
+use trapezoid_core::{cpu::CpuState, Psx, PsxConfig};
+
+const DEFAULT_EXPECTED: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/expected.toml");
+/// The title name of the BIOS alone, the others are named by their file name
+const BIOS_TITLE: &str = "bios";
+/// The start of the hash lines of `--child`, the core prints some messages
+/// to stdout as well, e.g. when loading an EXE
+const HASH_PREFIX: &str = "HASH";
+
+#[derive(Parser)]
+#[command(about = "Boot the BIOS and games, and compare their hashes to the expected ones")]
+struct Args {
+    /// The BIOS file, all the titles boot through it
+    #[arg(long)]
+    bios: PathBuf,
+    /// The disks (`.cue`) and EXEs to boot, after the BIOS alone
+    images: Vec<PathBuf>,
+    /// The TOML file of the expected hashes
+    #[arg(long, default_value = DEFAULT_EXPECTED)]
+    expected: PathBuf,
+    /// Write the hashes of this run to the expected file, instead of comparing them
+    #[arg(long)]
+    regenerate: bool,
+    /// The frames to hash at, defaults to the ones in the expected file,
+    /// changing them needs `--regenerate`
+    #[arg(long, value_delimiter = ',')]
+    frames: Option<Vec<u64>>,
+    /// Seconds before a title is stopped and failed
+    #[arg(long, default_value_t = 300)]
+    timeout: u64,
+    /// Run the only image (or the BIOS alone) in this process, and print the hashes
+    #[arg(long, hide = true)]
+    child: bool,
+}
+
+enum Outcome {
+    Passed,
+    /// The differences from the expected hashes
+    Mismatch(Vec<String>),
+    /// There are no expected hashes for this title
+    Missing,
+    /// With the reason, e.g. the panic message
+    Failed(String),
+    TimedOut,
+    /// The hashes were written with `--regenerate`
+    Regenerated,
+}
+
+impl Outcome {
+    fn short(&self) -> String {
+        match self {
+            Outcome::Passed => "ok".to_string(),
+            Outcome::Mismatch(diff) => format!("MISMATCH ({})", diff[0]),
+            Outcome::Missing => "MISSING (run with --regenerate)".to_string(),
+            Outcome::Failed(reason) => {
+                format!("FAILED ({})", reason.lines().next().unwrap_or_default())
+            }
+            Outcome::TimedOut => "TIMEOUT".to_string(),
+            Outcome::Regenerated => "regenerated".to_string(),
+        }
+    }
+
+    fn is_ok(&self) -> bool {
+        matches!(self, Outcome::Passed | Outcome::Regenerated)
+    }
+}
+
+/// FNV-1a, it only needs to be stable, these are not kept secret
+fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes.into_iter().fold(0xCBF2_9CE4_8422_2325, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01B3)
+    })
+}
+
+fn vram_hash(psx: &mut Psx) -> u64 {
+    let vram = psx.read_vram_block(0, 0, 1024, 512);
+    fnv1a(vram.iter().flat_map(|w| w.to_le_bytes()))
+}
+
+/// The body of `--child`, prints a `HASH frame state vram` line for each of `frames`
+fn run_child(bios: &Path, image: Option<&Path>, frames: &[u64]) -> Result<(), String> {
+    let mut psx =
+        Psx::new_headless(Some(bios), image, PsxConfig::default()).map_err(|e| e.to_string())?;
+
+    let last = *frames.last().expect("checked when parsing");
+    for frame in 1..=last {
+        let cpu_state = psx.clock_full_video_frame();
+        if cpu_state != CpuState::Normal {
+            return Err(format!("stopped at frame {}: {:?}", frame, cpu_state));
+        }
+        if frames.contains(&frame) {
+            println!(
+                "{} {} {} {}",
+                HASH_PREFIX,
+                frame,
+                format_hash(psx.state_hash()),
+                format_hash(vram_hash(&mut psx))
+            );
+        }
+    }
+    Ok(())
+}
+
+fn parse_child_output(output: &str, frames: &[u64]) -> Result<Vec<Hashes>, String> {
+    let hashes = output
+        .lines()
+        .filter_map(|line| line.strip_prefix(HASH_PREFIX)?.strip_prefix(' '))
+        .map(|line| {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            match parts[..] {
+                [_, state, vram] => Ok(Hashes {
+                    state: u64::from_str_radix(state, 16).map_err(|e| e.to_string())?,
+                    vram: u64::from_str_radix(vram, 16).map_err(|e| e.to_string())?,
+                }),
+                _ => Err(format!("unexpected hash line {:?}", line)),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if hashes.len() != frames.len() {
+        return Err(format!(
+            "got {} hashes out of {}",
+            hashes.len(),
+            frames.len()
+        ));
+    }
+    Ok(hashes)
+}
+
+fn read_pipe(mut pipe: impl Read + Send + 'static) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut output = String::new();
+        pipe.read_to_string(&mut output).ok();
+        output
+    })
+}
+
+/// Run one title in a new process, the error is the reason it failed
+/// (`None` for a timeout)
+fn run_title(
+    args: &Args,
+    image: Option<&Path>,
+    frames: &[u64],
+) -> Result<Vec<Hashes>, Option<String>> {
+    let mut command = Command::new(std::env::current_exe().map_err(|e| Some(e.to_string()))?);
+    command
+        .arg("--child")
+        .arg("--bios")
+        .arg(&args.bios)
+        .arg("--frames")
+        .arg(
+            frames
+                .iter()
+                .map(|f| f.to_string())
+                .collect::<Vec<_>>()
+                .join(","),
+        )
+        .args(image)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut child = command.spawn().map_err(|e| Some(e.to_string()))?;
+    // read while running, so the child doesn't block on a full pipe
+    let stdout = read_pipe(child.stdout.take().unwrap());
+    let stderr = read_pipe(child.stderr.take().unwrap());
+
+    let deadline = Instant::now() + Duration::from_secs(args.timeout);
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(|e| Some(e.to_string()))? {
+            break status;
+        }
+        if Instant::now() > deadline {
+            child.kill().ok();
+            child.wait().ok();
+            return Err(None);
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    if !status.success() {
+        let reason = stderr.trim();
+        return Err(Some(if reason.is_empty() {
+            format!("exited with {}", status)
+        } else {
+            reason.to_string()
+        }));
+    }
+    parse_child_output(&stdout, frames).map_err(Some)
+}
+
+fn title_name(image: Option<&Path>) -> String {
+    match image {
+        Some(image) => image
+            .file_name()
+            .unwrap_or(image.as_os_str())
+            .to_string_lossy()
+            .into_owned(),
+        None => BIOS_TITLE.to_string(),
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+
+    if args.child {
+        let frames = args.frames.as_deref().unwrap_or_default();
+        if args.images.len() > 1 || frames.is_empty() {
+            eprintln!("--child runs one title, with --frames");
+            std::process::exit(2);
+        }
+        if let Err(e) = run_child(&args.bios, args.images.first().map(|p| p.as_path()), frames) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let mut expected = match Expected::load(&args.expected) {
+        Ok(expected) => expected,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    let bios_hash = match std::fs::read(&args.bios) {
+        Ok(bios) => fnv1a(bios),
+        Err(e) => {
+            eprintln!("Could not read the BIOS {:?}: {}", args.bios, e);
+            std::process::exit(2);
+        }
+    };
+
+    if let Some(frames) = &args.frames {
+        if *frames != expected.frames && !args.regenerate {
+            eprintln!(
+                "The expected hashes are for the frames {:?}, use --regenerate to change them",
+                expected.frames
+            );
+            std::process::exit(2);
+        }
+        if frames.is_empty() || !frames.windows(2).all(|w| w[0] < w[1]) {
+            eprintln!("--frames must be increasing and not empty");
+            std::process::exit(2);
+        }
+        // the old hashes are for other frames, they can't be kept
+        if *frames != expected.frames {
+            expected.titles.clear();
+            expected.frames = frames.clone();
+        }
+    }
+    if args.regenerate {
+        if expected.bios != Some(bios_hash) {
+            expected.titles.clear();
+        }
+        expected.bios = Some(bios_hash);
+    } else if expected.bios.is_some_and(|b| b != bios_hash) {
+        eprintln!(
+            "The expected hashes were generated with another BIOS ({}, this one is {}), \
+             use --regenerate to replace them",
+            format_hash(expected.bios.unwrap()),
+            format_hash(bios_hash)
+        );
+        std::process::exit(2);
+    }
+    let frames = expected.frames.clone();
+
+    let titles = std::iter::once(None).chain(args.images.iter().map(|p| Some(p.as_path())));
+    let mut results = Vec::new();
+    for image in titles {
+        let name = title_name(image);
+        eprintln!("Running {}...", name);
+        let start = Instant::now();
+        let outcome = match run_title(&args, image, &frames) {
+            Ok(hashes) if args.regenerate => {
+                expected.titles.insert(name.clone(), hashes);
+                Outcome::Regenerated
+            }
+            Ok(hashes) => match expected.titles.get(&name) {
+                Some(expected) => {
+                    let diff = expected::diff(&frames, &hashes, expected);
+                    if diff.is_empty() {
+                        Outcome::Passed
+                    } else {
+                        Outcome::Mismatch(diff)
+                    }
+                }
+                None => Outcome::Missing,
+            },
+            Err(Some(reason)) => Outcome::Failed(reason),
+            Err(None) => Outcome::TimedOut,
+        };
+
+        match &outcome {
+            Outcome::Mismatch(diff) => {
+                for line in diff {
+                    eprintln!("  {}", line);
+                }
+            }
+            Outcome::Failed(reason) => {
+                for line in reason.lines() {
+                    eprintln!("  {}", line);
+                }
+            }
+            _ => {}
+        }
+        results.push((name, outcome, start.elapsed()));
+    }
+
+    if args.regenerate {
+        if let Err(e) = expected.save(&args.expected) {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    }
+
+    let name_width = results
+        .iter()
+        .map(|(name, ..)| name.len())
+        .max()
+        .unwrap_or(0)
+        .max("title".len());
+    println!();
+    println!("{:<name_width$}  {:>8}  result", "title", "time");
+    for (name, outcome, time) in &results {
+        println!(
+            "{:<name_width$}  {:>7.1}s  {}",
+            name,
+            time.as_secs_f64(),
+            outcome.short()
+        );
+    }
+    let failed = results.iter().filter(|(_, o, _)| !o.is_ok()).count();
+    println!();
+    println!("{} passed, {} failed", results.len() - failed, failed);
+
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn child_output_ignores_other_lines() {
+        let output = "Loaded EXE into pc: 80010000\n\
+                      HASH 60 00000000000000ff 0000000000000001\n\
+                      Loaded memory card 0\n\
+                      HASH 300 0000000000000100 0000000000000002\n";
+        assert_eq!(
+            parse_child_output(output, &[60, 300]).unwrap(),
+            [
+                Hashes {
+                    state: 0xFF,
+                    vram: 1
+                },
+                Hashes {
+                    state: 0x100,
+                    vram: 2
+                },
+            ]
+        );
+        // a missing frame is still an error
+        assert!(parse_child_output(output, &[60, 300, 600]).is_err());
+        assert!(parse_child_output("HASH 60 xyz 01\n", &[60]).is_err());
+    }
+}
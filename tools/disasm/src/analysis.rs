@@ -0,0 +1,540 @@
+//! Finding the functions, the BIOS calls and the strings of an image, this is
+//! all static, nothing is executed.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde::Serialize;
+use trapezoid_core::cpu::{bios_function_name, Instruction, Opcode, RegisterType};
+
+/// Shorter strings are too likely to be data or code that happens to be printable
+const MIN_STRING_LEN: usize = 4;
+/// A `sw ra` this far after a stack allocation makes it a prologue
+const PROLOGUE_SAVE_DISTANCE: u32 = 8;
+/// Runs of zero words at least this long are shown as one item
+const MIN_ZERO_RUN: u32 = 4;
+/// Strings longer than this are cut in the comments of the instructions using them
+const MAX_COMMENT_STRING_LEN: usize = 40;
+
+/// The content of the file, loaded at `base`
+pub struct Image {
+    pub base: u32,
+    pub data: Vec<u8>,
+    /// Where the execution starts, if known
+    pub entry: Option<u32>,
+}
+
+impl Image {
+    fn contains(&self, addr: u32) -> bool {
+        addr.wrapping_sub(self.base) < self.data.len() as u32
+    }
+
+    fn word(&self, addr: u32) -> Option<u32> {
+        let offset = addr.wrapping_sub(self.base) as usize;
+        let bytes = self.data.get(offset..offset + 4)?;
+        Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn instruction(&self, addr: u32) -> Option<Instruction> {
+        self.word(addr)
+            .map(|word| Instruction::from_u32(word, addr))
+    }
+
+    /// The addresses of the words of the image, the bytes after the last full word are ignored
+    fn word_addresses(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..self.data.len() as u32 / 4).map(|i| self.base.wrapping_add(i * 4))
+    }
+}
+
+/// One line of the listing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Item {
+    Instruction {
+        addr: u32,
+        word: u32,
+        text: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        comment: Option<String>,
+    },
+    /// A NUL terminated string, it covers the words up to the terminator
+    String { addr: u32, text: String },
+    /// `count` zero words
+    Zeros { addr: u32, count: u32 },
+}
+
+impl Item {
+    pub fn addr(&self) -> u32 {
+        match self {
+            Item::Instruction { addr, .. }
+            | Item::String { addr, .. }
+            | Item::Zeros { addr, .. } => *addr,
+        }
+    }
+}
+
+pub struct Analysis {
+    /// The start of the functions, with their names
+    pub functions: BTreeMap<u32, String>,
+    /// The strings, without the NUL terminator
+    pub strings: BTreeMap<u32, String>,
+    pub items: Vec<Item>,
+}
+
+impl Analysis {
+    pub fn new(image: &Image) -> Self {
+        let strings = find_strings(image);
+        let functions = find_functions(image, &strings);
+        let comments = annotate(image, &functions, &strings);
+        let items = list_items(image, &strings, comments);
+        Self {
+            functions,
+            strings,
+            items,
+        }
+    }
+}
+
+fn is_string_char(byte: u8) -> bool {
+    byte.is_ascii_graphic() || matches!(byte, b' ' | b'\n' | b'\t' | b'\r')
+}
+
+/// Word aligned, NUL terminated runs of printable characters
+fn find_strings(image: &Image) -> BTreeMap<u32, String> {
+    let mut strings = BTreeMap::new();
+    let mut offset = 0;
+    while offset < image.data.len() {
+        let len = image.data[offset..]
+            .iter()
+            .position(|&b| !is_string_char(b))
+            .unwrap_or(image.data.len() - offset);
+        let terminated = image.data.get(offset + len) == Some(&0);
+        if len >= MIN_STRING_LEN && terminated {
+            let text = String::from_utf8(image.data[offset..offset + len].to_vec()).unwrap();
+            strings.insert(image.base.wrapping_add(offset as u32), text);
+            // the next one starts at the word after the terminator
+            offset += len + 1;
+        } else {
+            offset += 1;
+        }
+        offset = (offset + 3) & !3;
+    }
+    strings
+}
+
+/// The number of words covered by `text`, with its terminator
+fn string_words(text: &str) -> u32 {
+    (text.len() as u32 + 1).div_ceil(4)
+}
+
+fn in_string(strings: &BTreeMap<u32, String>, addr: u32) -> bool {
+    strings
+        .range(..=addr)
+        .next_back()
+        .is_some_and(|(&start, text)| addr < start + string_words(text) * 4)
+}
+
+/// `addiu rt, zero, imm` and `ori rt, zero, imm`, which load a constant in `rt`
+fn load_immediate(instr: &Instruction) -> Option<(RegisterType, u32)> {
+    match instr.opcode {
+        Opcode::Addiu | Opcode::Ori if instr.rs() == RegisterType::Zero => {
+            let imm = match instr.opcode {
+                Opcode::Addiu => instr.imm16() as i16 as u32,
+                _ => instr.imm16() as u32,
+            };
+            Some((instr.rt(), imm))
+        }
+        _ => None,
+    }
+}
+
+/// The register written by `instr`, for the ones not followed by [`annotate`]
+fn written_register(instr: &Instruction) -> Option<RegisterType> {
+    match instr.opcode {
+        Opcode::Lb
+        | Opcode::Lbu
+        | Opcode::Lh
+        | Opcode::Lhu
+        | Opcode::Lw
+        | Opcode::Lwl
+        | Opcode::Lwr
+        | Opcode::Slti
+        | Opcode::Sltiu
+        | Opcode::Addi
+        | Opcode::Andi
+        | Opcode::Xori
+        | Opcode::Mfc(_)
+        | Opcode::Cfc(_) => Some(instr.rt()),
+        Opcode::Slt
+        | Opcode::Sltu
+        | Opcode::Addu
+        | Opcode::Add
+        | Opcode::Subu
+        | Opcode::Sub
+        | Opcode::And
+        | Opcode::Or
+        | Opcode::Xor
+        | Opcode::Nor
+        | Opcode::Sllv
+        | Opcode::Srlv
+        | Opcode::Srav
+        | Opcode::Sll
+        | Opcode::Srl
+        | Opcode::Sra
+        | Opcode::Mfhi
+        | Opcode::Mflo => Some(instr.rd()),
+        _ => None,
+    }
+}
+
+/// `addiu sp, sp, -size`
+fn is_stack_allocation(instr: &Instruction) -> bool {
+    matches!(instr.opcode, Opcode::Addiu)
+        && instr.rs() == RegisterType::Sp
+        && instr.rt() == RegisterType::Sp
+        && (instr.imm16() as i16) < 0
+}
+
+/// A stack allocation followed by saving `ra` on the stack
+fn is_prologue(image: &Image, addr: u32) -> bool {
+    let Some(instr) = image.instruction(addr) else {
+        return false;
+    };
+    is_stack_allocation(&instr)
+        && (1..=PROLOGUE_SAVE_DISTANCE).any(|i| {
+            image.instruction(addr + i * 4).is_some_and(|next| {
+                matches!(next.opcode, Opcode::Sw)
+                    && next.rs() == RegisterType::Sp
+                    && next.rt() == RegisterType::Ra
+            })
+        })
+}
+
+/// The table and the function number of the BIOS call stub at `addr`, which loads
+/// the table address in `t2` and jumps to it, with the function in `t1`
+fn bios_stub(image: &Image, addr: u32) -> Option<(u32, u32)> {
+    let mut table = None;
+    let mut function = None;
+    for i in 0..3 {
+        let instr = image.instruction(addr + i * 4)?;
+        match load_immediate(&instr) {
+            Some((RegisterType::T2, value)) => table = Some(value),
+            Some((RegisterType::T1, value)) => function = Some(value),
+            _ if matches!(instr.opcode, Opcode::Jr) && instr.rs() == RegisterType::T2 => {
+                // `t1` is usually set in the delay slot
+                if let Some((RegisterType::T1, value)) = image
+                    .instruction(addr + (i + 1) * 4)
+                    .as_ref()
+                    .and_then(load_immediate)
+                {
+                    function = Some(value);
+                }
+                let table = table.filter(|t| matches!(t, 0xA0 | 0xB0 | 0xC0))?;
+                return Some((table, function?));
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+fn bios_call_name(table: u32, function: u32) -> String {
+    match bios_function_name(table, function) {
+        Some(name) => name.to_string(),
+        None => format!("{:02X}_{:02X}", table, function),
+    }
+}
+
+/// The entry, the targets of `jal`, and the functions that start with a prologue.
+///
+/// The BIOS call stubs are named after the function they call, the others after
+/// their address.
+fn find_functions(image: &Image, strings: &BTreeMap<u32, String>) -> BTreeMap<u32, String> {
+    let mut starts = Vec::new();
+    starts.extend(image.entry.filter(|&entry| image.contains(entry)));
+    for addr in image.word_addresses() {
+        if in_string(strings, addr) {
+            continue;
+        }
+        let instr = image.instruction(addr).unwrap();
+        if let (Opcode::Jal, Some(target)) = (instr.opcode, instr.jump_target()) {
+            if image.contains(target) && !in_string(strings, target) {
+                starts.push(target);
+            }
+        }
+        if is_prologue(image, addr) {
+            starts.push(addr);
+        }
+    }
+
+    let mut functions = BTreeMap::new();
+    let mut used_names = HashSet::new();
+    for addr in starts {
+        if functions.contains_key(&addr) {
+            continue;
+        }
+        let name = if Some(addr) == image.entry {
+            "start".to_string()
+        } else if let Some((table, function)) = bios_stub(image, addr) {
+            let name = bios_call_name(table, function);
+            // some programs have more than one stub for the same function
+            if used_names.contains(&name) {
+                format!("{}_{:08X}", name, addr)
+            } else {
+                name
+            }
+        } else {
+            format!("func_{:08X}", addr)
+        };
+        used_names.insert(name.clone());
+        functions.insert(addr, name);
+    }
+    functions
+}
+
+/// `text` as a C string literal
+pub fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn short_quote(text: &str) -> String {
+    if text.len() > MAX_COMMENT_STRING_LEN {
+        format!("{}...", quote(&text[..MAX_COMMENT_STRING_LEN]))
+    } else {
+        quote(text)
+    }
+}
+
+/// The comments of the instructions: the functions they call or jump to, the
+/// BIOS calls, and the strings they load the address of.
+///
+/// The registers are followed linearly, and forgotten at the start of each
+/// function and after each jump, which is enough for the code compilers generate.
+fn annotate(
+    image: &Image,
+    functions: &BTreeMap<u32, String>,
+    strings: &BTreeMap<u32, String>,
+) -> HashMap<u32, String> {
+    let mut comments = HashMap::new();
+    // the known values of the registers, indexed by their number
+    let mut values = [None; 32];
+    let mut forget_after = None;
+
+    for addr in image.word_addresses() {
+        if functions.contains_key(&addr) || forget_after == Some(addr) {
+            values = [None; 32];
+        }
+        if in_string(strings, addr) {
+            continue;
+        }
+        let instr = image.instruction(addr).unwrap();
+
+        let mut comment = None;
+        match instr.opcode {
+            Opcode::Lui => {
+                values[instr.rt() as usize] = Some((instr.imm16() as u32) << 16);
+            }
+            Opcode::Addiu | Opcode::Ori => {
+                let imm = match instr.opcode {
+                    Opcode::Addiu => instr.imm16() as i16 as u32,
+                    _ => instr.imm16() as u32,
+                };
+                let base = match instr.rs() {
+                    RegisterType::Zero => Some(0),
+                    rs => values[rs as usize],
+                };
+                match base {
+                    Some(base) => {
+                        let value = match instr.opcode {
+                            Opcode::Addiu => base.wrapping_add(imm),
+                            _ => base | imm,
+                        };
+                        if let Some(text) = strings.get(&value) {
+                            comment = Some(short_quote(text));
+                        }
+                        values[instr.rt() as usize] = Some(value);
+                    }
+                    None => values[instr.rt() as usize] = None,
+                }
+            }
+            Opcode::Jr | Opcode::Jalr => {
+                // a BIOS call with the function number set in the delay slot
+                let delay_slot = image.instruction(addr + 4);
+                let function = match delay_slot.as_ref().and_then(load_immediate) {
+                    Some((RegisterType::T1, value)) => Some(value),
+                    _ => values[RegisterType::T1 as usize],
+                };
+                if let (Some(table @ (0xA0 | 0xB0 | 0xC0)), Some(function)) =
+                    (values[instr.rs() as usize], function)
+                {
+                    comment = Some(format!(
+                        "{:02X}({:02X}h) {}",
+                        table,
+                        function,
+                        bios_function_name(table, function).unwrap_or("?")
+                    ));
+                }
+            }
+            _ => {
+                if let Some(name) = instr.jump_target().and_then(|t| functions.get(&t)) {
+                    comment = Some(name.clone());
+                }
+                if let Some(register) = written_register(&instr) {
+                    values[register as usize] = None;
+                }
+            }
+        }
+        if instr.is_branch() {
+            forget_after = Some(addr + 8);
+        }
+
+        if let Some(comment) = comment {
+            comments.insert(addr, comment);
+        }
+    }
+    comments
+}
+
+fn list_items(
+    image: &Image,
+    strings: &BTreeMap<u32, String>,
+    mut comments: HashMap<u32, String>,
+) -> Vec<Item> {
+    let mut items = Vec::new();
+    let words = image.data.len() as u32 / 4;
+    let mut i = 0;
+    while i < words {
+        let addr = image.base.wrapping_add(i * 4);
+        if let Some(text) = strings.get(&addr) {
+            items.push(Item::String {
+                addr,
+                text: text.clone(),
+            });
+            i += string_words(text);
+            continue;
+        }
+
+        let zeros = (i..words)
+            .map(|i| image.base.wrapping_add(i * 4))
+            .take_while(|a| image.word(*a) == Some(0) && !strings.contains_key(a))
+            .count() as u32;
+        if zeros >= MIN_ZERO_RUN {
+            items.push(Item::Zeros { addr, count: zeros });
+            i += zeros;
+            continue;
+        }
+
+        let instr = image.instruction(addr).unwrap();
+        items.push(Item::Instruction {
+            addr,
+            word: instr.instruction,
+            text: instr.to_string(),
+            comment: comments.remove(&addr),
+        });
+        i += 1;
+    }
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(words: &[u32]) -> Image {
+        Image {
+            base: 0x80010000,
+            data: words.iter().flat_map(|w| w.to_le_bytes()).collect(),
+            entry: Some(0x80010000),
+        }
+    }
+
+    #[test]
+    fn prologue_functions() {
+        let image = image(&[
+            0x03E00008, // jr    ra
+            0x00000000, // nop
+            0x27BDFFE0, // addiu sp, sp, -0x20
+            0x00000000, // nop
+            0xAFBF0018, // sw    ra, 0x18(sp)
+            0x27BD0020, // addiu sp, sp, 0x20
+            0x27BDFFF0, // addiu sp, sp, -0x10, without saving ra
+            0x03E00008, // jr    ra
+            0x27BD0010, // addiu sp, sp, 0x10
+        ]);
+        let functions = find_functions(&image, &BTreeMap::new());
+        assert_eq!(
+            functions.into_iter().collect::<Vec<_>>(),
+            [
+                (0x80010000, "start".to_string()),
+                (0x80010008, "func_80010008".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn strings_are_aligned_and_terminated() {
+        let mut data = b"abc\0".to_vec(); // too short
+        data.extend(b"long enough\0");
+        data.extend(b"not terminated..");
+        data.extend(b"\x01xy\0after\n\0\0\0");
+        let image = Image {
+            base: 0x1000,
+            data,
+            entry: None,
+        };
+        let strings = find_strings(&image);
+        assert_eq!(
+            strings.into_iter().collect::<Vec<_>>(),
+            [
+                (0x1004, "long enough".to_string()),
+                (0x1024, "after\n".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn bios_stubs() {
+        let image = image(&[
+            0x0C004004, // jal   0x80010010
+            0x00000000, // nop
+            0x0C004008, // jal   0x80010020
+            0x00000000, // nop
+            0x240A00B0, // addiu t2, zero, 0xB0
+            0x01400008, // jr    t2
+            0x24090017, // addiu t1, zero, 0x17
+            0x00000000, // nop
+            0x240A00C0, // addiu t2, zero, 0xC0
+            0x24090050, // addiu t1, zero, 0x50, unused
+            0x01400008, // jr    t2
+            0x00000000, // nop
+        ]);
+        let analysis = Analysis::new(&image);
+        assert_eq!(analysis.functions[&0x80010010], "ReturnFromException");
+        assert_eq!(analysis.functions[&0x80010020], "C0_50");
+        let comment = |addr| {
+            analysis.items.iter().find_map(|item| match item {
+                Item::Instruction {
+                    addr: a, comment, ..
+                } if *a == addr => comment.clone(),
+                _ => None,
+            })
+        };
+        assert_eq!(comment(0x80010000).as_deref(), Some("ReturnFromException"));
+        assert_eq!(
+            comment(0x80010014).as_deref(),
+            Some("B0(17h) ReturnFromException")
+        );
+        assert_eq!(comment(0x80010028).as_deref(), Some("C0(50h) ?"));
+    }
+}
@@ -0,0 +1,195 @@
+//! Disassemble a `PS-X EXE`, a BIOS image, or a raw RAM dump, and annotate the
+//! listing with the functions, the BIOS calls and the strings found in it.
+//!
+//! The functions are found from the entry, the targets of `jal`, and the stack
+//! allocations followed by saving `ra`. The BIOS call stubs are named after the
+//! kernel function they call.
+
+mod analysis;
+
+use std::path::{Path, PathBuf};
+
+use analysis::{quote, Analysis, Image, Item};
+use clap::Parser;
+use serde::Serialize;
+use trapezoid_core::{BiosInfo, PsxExe, RamSize, BIOS_SIZE};
+
+const BIOS_BASE: u32 = 0xBFC00000;
+/// The comments start at this column of the instruction text
+const COMMENT_COLUMN: usize = 40;
+
+#[derive(Parser)]
+#[command(
+    about = "Disassemble a PSX EXE, BIOS or RAM dump, with its functions, BIOS calls and strings"
+)]
+struct Args {
+    /// A `PS-X EXE`, a BIOS image, or a raw dump with `--base`
+    file: PathBuf,
+    /// Load the file as a raw dump at this address (hex), e.g. `80000000` for a RAM dump
+    #[arg(long, value_parser = parse_hex)]
+    base: Option<u32>,
+    /// Where the execution starts in a raw dump (hex)
+    #[arg(long, value_parser = parse_hex, requires = "base")]
+    entry: Option<u32>,
+    /// Print the analysis as JSON, instead of the listing
+    #[arg(long)]
+    json: bool,
+}
+
+fn parse_hex(s: &str) -> Result<u32, String> {
+    let digits = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+    u32::from_str_radix(digits, 16).map_err(|e| format!("invalid hex address {:?}: {}", s, e))
+}
+
+/// The image in the file, with a description of what it is
+fn load(path: &Path, base: Option<u32>, entry: Option<u32>) -> Result<(Image, String), String> {
+    let data =
+        std::fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+
+    if let Some(base) = base {
+        let image = Image { base, data, entry };
+        return Ok((image, format!("raw dump at {:08X}", base)));
+    }
+
+    if data.starts_with(b"PS-X EXE") {
+        // the 8MB of the development consoles accept all EXEs
+        let exe = PsxExe::parse(&data, RamSize::Dev8MB).map_err(|e| e.to_string())?;
+        let description = format!("PS-X EXE at {:08X}, gp {:08X}", exe.text_addr, exe.gp);
+        let image = Image {
+            base: exe.text_addr,
+            data: exe.text,
+            entry: Some(exe.pc),
+        };
+        Ok((image, description))
+    } else if data.len() == BIOS_SIZE {
+        let info = BiosInfo::from_data(&data);
+        let description = format!(
+            "BIOS version {}, region {}",
+            info.version.as_deref().unwrap_or("?"),
+            info.region
+                .map_or("?".to_string(), |region| format!("{:?}", region))
+        );
+        let image = Image {
+            base: BIOS_BASE,
+            data,
+            entry: Some(BIOS_BASE),
+        };
+        Ok((image, description))
+    } else {
+        Err(format!(
+            "{} is not an EXE or a BIOS image, use --base to load a raw dump",
+            path.display()
+        ))
+    }
+}
+
+fn print_listing(name: &str, description: &str, image: &Image, analysis: &Analysis) {
+    println!("; {}: {}", name, description);
+    if let Some(entry) = image.entry {
+        println!("; entry {:08X}", entry);
+    }
+    println!(
+        "; {} functions, {} strings",
+        analysis.functions.len(),
+        analysis.strings.len()
+    );
+
+    for item in &analysis.items {
+        if let Some(name) = analysis.functions.get(&item.addr()) {
+            println!();
+            println!("{}:", name);
+        }
+
+        match item {
+            Item::Instruction {
+                addr,
+                word,
+                text,
+                comment,
+            } => match comment {
+                Some(comment) => println!(
+                    "    {:08X}  {:08X}  {:<width$}  ; {}",
+                    addr,
+                    word,
+                    text,
+                    comment,
+                    width = COMMENT_COLUMN
+                ),
+                None => println!("    {:08X}  {:08X}  {}", addr, word, text),
+            },
+            Item::String { addr, text } => println!("    {:08X}  {}", addr, quote(text)),
+            Item::Zeros { addr, count } => {
+                println!("    {:08X}  ... {} zero words", addr, count)
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonFunction<'a> {
+    addr: u32,
+    name: &'a str,
+}
+
+#[derive(Serialize)]
+struct JsonString<'a> {
+    addr: u32,
+    text: &'a str,
+}
+
+#[derive(Serialize)]
+struct JsonOutput<'a> {
+    description: &'a str,
+    base: u32,
+    entry: Option<u32>,
+    functions: Vec<JsonFunction<'a>>,
+    strings: Vec<JsonString<'a>>,
+    items: &'a [Item],
+}
+
+fn print_json(description: &str, image: &Image, analysis: &Analysis) {
+    let output = JsonOutput {
+        description,
+        base: image.base,
+        entry: image.entry,
+        functions: analysis
+            .functions
+            .iter()
+            .map(|(&addr, name)| JsonFunction { addr, name })
+            .collect(),
+        strings: analysis
+            .strings
+            .iter()
+            .map(|(&addr, text)| JsonString { addr, text })
+            .collect(),
+        items: &analysis.items,
+    };
+    println!("{}", serde_json::to_string_pretty(&output).unwrap());
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let (image, description) = match load(&args.file, args.base, args.entry) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let analysis = Analysis::new(&image);
+
+    if args.json {
+        print_json(&description, &image, &analysis);
+    } else {
+        let name = args
+            .file
+            .file_name()
+            .unwrap_or(args.file.as_os_str())
+            .to_string_lossy();
+        print_listing(&name, &description, &image, &analysis);
+    }
+}
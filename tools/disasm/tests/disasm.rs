@@ -0,0 +1,141 @@
+//! Run the binary on `data/hello.exe`, which is:
+//!
+//! ```text
+//! 80010000  start:  addiu sp, sp, -0x18
+//!                   sw    ra, 0x10(sp)
+//!                   lui   a0, 0x8001
+//!                   jal   printf
+//!                   addiu a0, a0, 0x80      ; "Hello, world!\n"
+//!                   jal   0x80010030
+//!                   nop
+//!                   lw    ra, 0x10(sp)
+//!                   addiu sp, sp, 0x18
+//!                   jr    ra
+//!                   nop
+//! 80010030          jr    ra                ; a leaf, only found from the `jal`
+//!                   addiu v0, zero, 1
+//! 80010040  printf: addiu t2, zero, 0xA0
+//!                   jr    t2
+//!                   addiu t1, zero, 0x3F
+//! 80010050          addiu sp, sp, -0x20     ; never called, found from the prologue
+//!                   sw    ra, 0x18(sp)
+//!                   lw    ra, 0x18(sp)
+//!                   addiu sp, sp, 0x20
+//!                   jr    ra
+//!                   nop
+//! 80010080          "Hello, world!\n"
+//! ```
+
+use std::{path::PathBuf, process::Command};
+
+fn hello_exe() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/hello.exe")
+}
+
+fn run(args: &[&str]) -> (bool, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_trapezoid-disasm"))
+        .args(args)
+        .output()
+        .unwrap();
+    (
+        output.status.success(),
+        String::from_utf8(output.stdout).unwrap(),
+    )
+}
+
+/// The line of the listing at `addr`
+fn line_at<'a>(listing: &'a str, addr: &str) -> &'a str {
+    listing
+        .lines()
+        .find(|line| line.trim_start().starts_with(addr))
+        .unwrap_or_else(|| panic!("no line at {} in:\n{}", addr, listing))
+}
+
+#[test]
+fn listing() {
+    let (success, listing) = run(&[hello_exe().to_str().unwrap()]);
+    assert!(success);
+
+    assert!(listing.starts_with("; hello.exe: PS-X EXE at 80010000"));
+    assert!(listing.contains("; 4 functions, 1 strings"));
+    for label in ["start:", "func_80010030:", "printf:", "func_80010050:"] {
+        assert!(listing.lines().any(|line| line == label), "{}", label);
+    }
+
+    assert!(line_at(&listing, "8001000C")
+        .starts_with("    8001000C  0C004010  jal 0x0004010 => 0x80010040"));
+    assert!(line_at(&listing, "8001000C").ends_with("; printf"));
+    assert!(line_at(&listing, "80010010").ends_with(r#"; "Hello, world!\n""#));
+    assert!(line_at(&listing, "80010014").ends_with("; func_80010030"));
+    assert!(line_at(&listing, "80010044").contains("jr t2"));
+    assert!(line_at(&listing, "80010044").ends_with("; A0(3Fh) printf"));
+    assert_eq!(
+        line_at(&listing, "80010080"),
+        r#"    80010080  "Hello, world!\n""#
+    );
+    assert_eq!(
+        line_at(&listing, "80010090"),
+        "    80010090  ... 476 zero words"
+    );
+}
+
+#[test]
+fn json() {
+    let (success, output) = run(&[hello_exe().to_str().unwrap(), "--json"]);
+    assert!(success);
+    let json: serde_json::Value = serde_json::from_str(&output).unwrap();
+
+    assert_eq!(json["entry"], 0x80010000u32);
+    let functions: Vec<_> = json["functions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|f| (f["addr"].as_u64().unwrap(), f["name"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        functions,
+        [
+            (0x80010000, "start"),
+            (0x80010030, "func_80010030"),
+            (0x80010040, "printf"),
+            (0x80010050, "func_80010050"),
+        ]
+    );
+    assert_eq!(json["strings"][0]["addr"], 0x80010080u32);
+    assert_eq!(json["strings"][0]["text"], "Hello, world!\n");
+
+    let bios_call = json["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|item| item["addr"] == 0x80010044u32)
+        .unwrap();
+    assert_eq!(bios_call["type"], "instruction");
+    assert_eq!(bios_call["word"], 0x01400008u32);
+    assert_eq!(bios_call["comment"], "A0(3Fh) printf");
+}
+
+#[test]
+fn raw_dump() {
+    let exe = std::fs::read(hello_exe()).unwrap();
+    let path = std::env::temp_dir().join(format!("trapezoid_disasm_{}.bin", std::process::id()));
+    // the EXE without its header
+    std::fs::write(&path, &exe[0x800..]).unwrap();
+
+    let (success, listing) = run(&[path.to_str().unwrap()]);
+    assert!(!success, "not detected as an EXE or a BIOS");
+    assert!(listing.is_empty());
+
+    let (success, listing) = run(&[
+        path.to_str().unwrap(),
+        "--base",
+        "80010000",
+        "--entry",
+        "0x80010000",
+    ]);
+    assert!(success);
+    assert!(line_at(&listing, "80010044").ends_with("; A0(3Fh) printf"));
+    assert!(listing.lines().any(|line| line == "start:"));
+
+    std::fs::remove_file(path).ok();
+}
@@ -0,0 +1,209 @@
+#ifndef TRAPEZOID_H
+#define TRAPEZOID_H
+
+/* Generated with cbindgen, do not edit manually, see `cbindgen.toml` */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The result of all the functions that can fail
+ */
+typedef enum TrapezoidResult {
+  TRAPEZOID_RESULT_OK = 0,
+  /**
+   * A `NULL` pointer, or an invalid value
+   */
+  TRAPEZOID_RESULT_INVALID_ARGUMENT = 1,
+  /**
+   * The BIOS is invalid (e.g. wrong size)
+   */
+  TRAPEZOID_RESULT_BIOS_ERROR = 2,
+  /**
+   * The disk couldn't be loaded or is not supported
+   */
+  TRAPEZOID_RESULT_DISK_ERROR = 3,
+  /**
+   * Couldn't create the Vulkan device used for rendering
+   */
+  TRAPEZOID_RESULT_VULKAN_ERROR = 4,
+  /**
+   * The buffer is too small, nothing was written
+   */
+  TRAPEZOID_RESULT_BUFFER_TOO_SMALL = 5,
+  /**
+   * The emulator doesn't support this yet
+   */
+  TRAPEZOID_RESULT_UNSUPPORTED = 6,
+  /**
+   * The emulator panicked, the handle should not be used anymore, other
+   * than destroying it
+   */
+  TRAPEZOID_RESULT_PANIC = 7,
+} TrapezoidResult;
+
+/**
+ * The keys of the digital controller
+ */
+typedef enum TrapezoidKey {
+  TRAPEZOID_KEY_SELECT = 0,
+  TRAPEZOID_KEY_L3 = 1,
+  TRAPEZOID_KEY_R3 = 2,
+  TRAPEZOID_KEY_START = 3,
+  TRAPEZOID_KEY_UP = 4,
+  TRAPEZOID_KEY_RIGHT = 5,
+  TRAPEZOID_KEY_DOWN = 6,
+  TRAPEZOID_KEY_LEFT = 7,
+  TRAPEZOID_KEY_L2 = 8,
+  TRAPEZOID_KEY_R2 = 9,
+  TRAPEZOID_KEY_L1 = 10,
+  TRAPEZOID_KEY_R1 = 11,
+  TRAPEZOID_KEY_TRIANGLE = 12,
+  TRAPEZOID_KEY_CIRCLE = 13,
+  TRAPEZOID_KEY_X = 14,
+  TRAPEZOID_KEY_SQUARE = 15,
+} TrapezoidKey;
+
+/**
+ * The emulator, only used through pointers
+ */
+typedef struct TrapezoidPsx TrapezoidPsx;
+
+/**
+ * Options for [`trapezoid_create`], get the defaults with [`trapezoid_default_config`]
+ */
+typedef struct TrapezoidConfig {
+  /**
+   * Skip the BIOS shell and boot the disk directly
+   */
+  bool fast_boot;
+  /**
+   * Print the BIOS/game tty output to `stdout`
+   */
+  bool stdout_debug;
+  /**
+   * Don't execute loops that only wait, to use less CPU, see `PsxConfig::idle_skip`
+   */
+  bool idle_skip;
+} TrapezoidConfig;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * The description of the last error on this thread, valid until the next error
+ * on the same thread. It's an empty string if there was no error.
+ */
+const char *trapezoid_last_error(void);
+
+TrapezoidConfig trapezoid_default_config(void);
+
+/**
+ * Create an emulator, choosing the first Vulkan device for rendering.
+ *
+ * `bios_data` is the BIOS image (`bios_len` must be 512KB), which is copied.
+ * `disk_path` is a `.cue` or `.exe` file (UTF-8), or `NULL` to boot to the BIOS shell.
+ * `config` can be `NULL` for the defaults.
+ *
+ * On success, the handle is written to `out_psx`.
+ *
+ * # Safety
+ * The pointers must be `NULL` or valid, `bios_data` for `bios_len` bytes, and
+ * `disk_path` a nul terminated string.
+ */
+TrapezoidResult trapezoid_create(const uint8_t *bios_data,
+                                 size_t bios_len,
+                                 const char *disk_path,
+                                 const TrapezoidConfig *config,
+                                 TrapezoidPsx **out_psx);
+
+/**
+ * Free the emulator, `psx` can be `NULL`
+ *
+ * # Safety
+ * `psx` must be `NULL` or a valid handle, which is not used after this
+ */
+void trapezoid_destroy(TrapezoidPsx *psx);
+
+/**
+ * Run the emulator until the next vblank (one video frame)
+ *
+ * # Safety
+ * `psx` must be a valid handle
+ */
+TrapezoidResult trapezoid_run_frame(TrapezoidPsx *psx);
+
+/**
+ * Change the state of a key of the digital controller in `port` (`0` or `1`)
+ *
+ * # Safety
+ * `psx` must be a valid handle
+ */
+TrapezoidResult trapezoid_set_key_state(TrapezoidPsx *psx,
+                                        uint32_t port,
+                                        TrapezoidKey key,
+                                        bool pressed);
+
+/**
+ * Copy the audio produced since the last call into `buffer`, as interleaved stereo
+ * `f32` samples at 44100Hz. `capacity` is the number of `f32`s that fit in `buffer`,
+ * and the number written is stored in `out_len`.
+ *
+ * If there are more samples than `capacity`, the rest are kept for the next call.
+ *
+ * # Safety
+ * `psx` must be a valid handle, `buffer` valid for `capacity` `f32`s
+ */
+TrapezoidResult trapezoid_get_audio_samples(TrapezoidPsx *psx,
+                                            float *buffer,
+                                            size_t capacity,
+                                            size_t *out_len);
+
+/**
+ * Copy the current display area into `buffer` as `RGBA8888` pixels, row by row.
+ *
+ * The size is always written to `out_width` and `out_height`, if `capacity` (in bytes)
+ * is less than `width * height * 4`, nothing is copied and
+ * `TRAPEZOID_RESULT_BUFFER_TOO_SMALL` is returned, so this can be called with a `NULL`
+ * `buffer` to get the size. The size changes with the game video mode.
+ *
+ * # Safety
+ * `psx` must be a valid handle, `buffer` `NULL` or valid for `capacity` bytes
+ */
+TrapezoidResult trapezoid_get_framebuffer(TrapezoidPsx *psx,
+                                          uint8_t *buffer,
+                                          size_t capacity,
+                                          uint32_t *out_width,
+                                          uint32_t *out_height);
+
+/**
+ * Save the emulator state into `buffer`.
+ *
+ * Save states are not supported by the emulator yet, this always returns
+ * `TRAPEZOID_RESULT_UNSUPPORTED`, it's here so that the API won't change when they are.
+ *
+ * # Safety
+ * `psx` must be a valid handle
+ */
+TrapezoidResult trapezoid_save_state(TrapezoidPsx *psx,
+                                     uint8_t *_buffer,
+                                     size_t _capacity,
+                                     size_t *_out_len);
+
+/**
+ * Load a state saved with [`trapezoid_save_state`], not supported yet, see it.
+ *
+ * # Safety
+ * `psx` must be a valid handle
+ */
+TrapezoidResult trapezoid_load_state(TrapezoidPsx *psx, const uint8_t *_data, size_t _len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* TRAPEZOID_H */
@@ -0,0 +1,485 @@
+//! C API for the trapezoid PSX emulator.
+//!
+//! The header is generated with `cbindgen` into `include/trapezoid.h`, see `cbindgen.toml`.
+//!
+//! All the functions that can fail return a [`TrapezoidResult`], and when it's not
+//! [`TrapezoidResult::Ok`], [`trapezoid_last_error`] returns a description of the error.
+//! The emulator itself is an opaque [`TrapezoidPsx`] pointer, created with
+//! [`trapezoid_create`] and freed with [`trapezoid_destroy`].
+//!
+//! A handle can be moved between threads, but must not be used from two threads
+//! at the same time.
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CStr, CString},
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::PathBuf,
+    ptr, slice,
+};
+
+use trapezoid_core::{
+    create_headless_device, DigitalControllerKey, IdleSkip, Psx, PsxConfig, PsxError,
+};
+
+/// The result of all the functions that can fail
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapezoidResult {
+    Ok = 0,
+    /// A `NULL` pointer, or an invalid value
+    InvalidArgument = 1,
+    /// The BIOS is invalid (e.g. wrong size)
+    BiosError = 2,
+    /// The disk couldn't be loaded or is not supported
+    DiskError = 3,
+    /// Couldn't create the Vulkan device used for rendering
+    VulkanError = 4,
+    /// The buffer is too small, nothing was written
+    BufferTooSmall = 5,
+    /// The emulator doesn't support this yet
+    Unsupported = 6,
+    /// The emulator panicked, the handle should not be used anymore, other
+    /// than destroying it
+    Panic = 7,
+}
+
+/// The keys of the digital controller
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapezoidKey {
+    Select = 0,
+    L3 = 1,
+    R3 = 2,
+    Start = 3,
+    Up = 4,
+    Right = 5,
+    Down = 6,
+    Left = 7,
+    L2 = 8,
+    R2 = 9,
+    L1 = 10,
+    R1 = 11,
+    Triangle = 12,
+    Circle = 13,
+    X = 14,
+    Square = 15,
+}
+
+impl From<TrapezoidKey> for DigitalControllerKey {
+    fn from(key: TrapezoidKey) -> Self {
+        match key {
+            TrapezoidKey::Select => DigitalControllerKey::Select,
+            TrapezoidKey::L3 => DigitalControllerKey::L3,
+            TrapezoidKey::R3 => DigitalControllerKey::R3,
+            TrapezoidKey::Start => DigitalControllerKey::Start,
+            TrapezoidKey::Up => DigitalControllerKey::Up,
+            TrapezoidKey::Right => DigitalControllerKey::Right,
+            TrapezoidKey::Down => DigitalControllerKey::Down,
+            TrapezoidKey::Left => DigitalControllerKey::Left,
+            TrapezoidKey::L2 => DigitalControllerKey::L2,
+            TrapezoidKey::R2 => DigitalControllerKey::R2,
+            TrapezoidKey::L1 => DigitalControllerKey::L1,
+            TrapezoidKey::R1 => DigitalControllerKey::R1,
+            TrapezoidKey::Triangle => DigitalControllerKey::Triangle,
+            TrapezoidKey::Circle => DigitalControllerKey::Circle,
+            TrapezoidKey::X => DigitalControllerKey::X,
+            TrapezoidKey::Square => DigitalControllerKey::Square,
+        }
+    }
+}
+
+/// Options for [`trapezoid_create`], get the defaults with [`trapezoid_default_config`]
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TrapezoidConfig {
+    /// Skip the BIOS shell and boot the disk directly
+    pub fast_boot: bool,
+    /// Print the BIOS/game tty output to `stdout`
+    pub stdout_debug: bool,
+    /// Don't execute loops that only wait, to use less CPU, see `PsxConfig::idle_skip`
+    pub idle_skip: bool,
+}
+
+/// The emulator, only used through pointers
+pub struct TrapezoidPsx {
+    psx: Psx,
+    /// Taken from the emulator, but didn't fit in the caller buffer yet
+    pending_audio: Vec<f32>,
+}
+
+struct FfiError {
+    result: TrapezoidResult,
+    message: String,
+}
+
+impl FfiError {
+    fn new(result: TrapezoidResult, message: impl Into<String>) -> Self {
+        Self {
+            result,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<PsxError> for FfiError {
+    fn from(e: PsxError) -> Self {
+        let result = match e {
+            PsxError::CouldNotLoadBios(_) => TrapezoidResult::BiosError,
+            PsxError::NoVulkanDevice(_) => TrapezoidResult::VulkanError,
+            _ => TrapezoidResult::DiskError,
+        };
+        Self::new(result, e.to_string())
+    }
+}
+
+type FfiResult<T = ()> = Result<T, FfiError>;
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn set_last_error(message: String) {
+    // the message is only for display, so just drop any nul bytes
+    let message = CString::new(message.replace('\0', "")).unwrap();
+    LAST_ERROR.with(|e| *e.borrow_mut() = message);
+}
+
+/// Run `f`, converting errors and panics to a result code, and saving the error message
+fn ffi_call(f: impl FnOnce() -> FfiResult) -> TrapezoidResult {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => TrapezoidResult::Ok,
+        Ok(Err(e)) => {
+            set_last_error(e.message);
+            e.result
+        }
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_owned());
+            set_last_error(format!("panic: {}", message));
+            TrapezoidResult::Panic
+        }
+    }
+}
+
+fn null_error(name: &str) -> FfiError {
+    FfiError::new(
+        TrapezoidResult::InvalidArgument,
+        format!("`{}` is NULL", name),
+    )
+}
+
+/// # Safety
+/// `psx` must be `NULL` or a valid handle from [`trapezoid_create`]
+unsafe fn psx_mut<'a>(psx: *mut TrapezoidPsx) -> FfiResult<&'a mut TrapezoidPsx> {
+    psx.as_mut().ok_or_else(|| null_error("psx"))
+}
+
+/// The description of the last error on this thread, valid until the next error
+/// on the same thread. It's an empty string if there was no error.
+#[no_mangle]
+pub extern "C" fn trapezoid_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ptr())
+}
+
+#[no_mangle]
+pub extern "C" fn trapezoid_default_config() -> TrapezoidConfig {
+    TrapezoidConfig::default()
+}
+
+/// Create an emulator, choosing the first Vulkan device for rendering.
+///
+/// `bios_data` is the BIOS image (`bios_len` must be 512KB), which is copied.
+/// `disk_path` is a `.cue` or `.exe` file (UTF-8), or `NULL` to boot to the BIOS shell.
+/// `config` can be `NULL` for the defaults.
+///
+/// On success, the handle is written to `out_psx`.
+///
+/// # Safety
+/// The pointers must be `NULL` or valid, `bios_data` for `bios_len` bytes, and
+/// `disk_path` a nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn trapezoid_create(
+    bios_data: *const u8,
+    bios_len: usize,
+    disk_path: *const c_char,
+    config: *const TrapezoidConfig,
+    out_psx: *mut *mut TrapezoidPsx,
+) -> TrapezoidResult {
+    ffi_call(|| {
+        if out_psx.is_null() {
+            return Err(null_error("out_psx"));
+        }
+        if bios_data.is_null() {
+            return Err(null_error("bios_data"));
+        }
+        let bios = slice::from_raw_parts(bios_data, bios_len).to_vec();
+
+        let disk_path = if disk_path.is_null() {
+            None
+        } else {
+            let path = CStr::from_ptr(disk_path).to_str().map_err(|_| {
+                FfiError::new(
+                    TrapezoidResult::InvalidArgument,
+                    "`disk_path` is not valid UTF-8",
+                )
+            })?;
+            Some(PathBuf::from(path))
+        };
+        let config = config.as_ref().copied().unwrap_or_default();
+
+        let (device, queue) = create_headless_device()?;
+        let psx = Psx::new_with_bios_data(
+            bios,
+            disk_path,
+            PsxConfig::builder()
+                .stdout_debug(config.stdout_debug)
+                .fast_boot(config.fast_boot)
+                .idle_skip(if config.idle_skip {
+                    IdleSkip::On
+                } else {
+                    IdleSkip::Off
+                })
+                .build(),
+            device,
+            queue,
+        )?;
+
+        *out_psx = Box::into_raw(Box::new(TrapezoidPsx {
+            psx,
+            pending_audio: Vec::new(),
+        }));
+        Ok(())
+    })
+}
+
+/// Free the emulator, `psx` can be `NULL`
+///
+/// # Safety
+/// `psx` must be `NULL` or a valid handle, which is not used after this
+#[no_mangle]
+pub unsafe extern "C" fn trapezoid_destroy(psx: *mut TrapezoidPsx) {
+    if !psx.is_null() {
+        drop(Box::from_raw(psx));
+    }
+}
+
+/// Run the emulator until the next vblank (one video frame)
+///
+/// # Safety
+/// `psx` must be a valid handle
+#[no_mangle]
+pub unsafe extern "C" fn trapezoid_run_frame(psx: *mut TrapezoidPsx) -> TrapezoidResult {
+    ffi_call(|| {
+        psx_mut(psx)?.psx.clock_full_video_frame();
+        Ok(())
+    })
+}
+
+/// Change the state of a key of the digital controller in `port` (`0` or `1`)
+///
+/// # Safety
+/// `psx` must be a valid handle
+#[no_mangle]
+pub unsafe extern "C" fn trapezoid_set_key_state(
+    psx: *mut TrapezoidPsx,
+    port: u32,
+    key: TrapezoidKey,
+    pressed: bool,
+) -> TrapezoidResult {
+    ffi_call(|| {
+        let psx = psx_mut(psx)?;
+        if port > 1 {
+            return Err(FfiError::new(
+                TrapezoidResult::InvalidArgument,
+                format!("invalid controller port {}", port),
+            ));
+        }
+        psx.psx
+            .change_port_controller_key_state(port as usize, key.into(), pressed);
+        Ok(())
+    })
+}
+
+/// Copy the audio produced since the last call into `buffer`, as interleaved stereo
+/// `f32` samples at 44100Hz. `capacity` is the number of `f32`s that fit in `buffer`,
+/// and the number written is stored in `out_len`.
+///
+/// If there are more samples than `capacity`, the rest are kept for the next call.
+///
+/// # Safety
+/// `psx` must be a valid handle, `buffer` valid for `capacity` `f32`s
+#[no_mangle]
+pub unsafe extern "C" fn trapezoid_get_audio_samples(
+    psx: *mut TrapezoidPsx,
+    buffer: *mut f32,
+    capacity: usize,
+    out_len: *mut usize,
+) -> TrapezoidResult {
+    ffi_call(|| {
+        let psx = psx_mut(psx)?;
+        if buffer.is_null() {
+            return Err(null_error("buffer"));
+        }
+        if out_len.is_null() {
+            return Err(null_error("out_len"));
+        }
+
+        psx.psx.take_audio_buffer_into(&mut psx.pending_audio);
+
+        // don't split the left and right samples
+        let len = psx.pending_audio.len().min(capacity & !1);
+        ptr::copy_nonoverlapping(psx.pending_audio.as_ptr(), buffer, len);
+        psx.pending_audio.drain(..len);
+        *out_len = len;
+        Ok(())
+    })
+}
+
+/// Copy the current display area into `buffer` as `RGBA8888` pixels, row by row.
+///
+/// The size is always written to `out_width` and `out_height`, if `capacity` (in bytes)
+/// is less than `width * height * 4`, nothing is copied and
+/// `TRAPEZOID_RESULT_BUFFER_TOO_SMALL` is returned, so this can be called with a `NULL`
+/// `buffer` to get the size. The size changes with the game video mode.
+///
+/// # Safety
+/// `psx` must be a valid handle, `buffer` `NULL` or valid for `capacity` bytes
+#[no_mangle]
+pub unsafe extern "C" fn trapezoid_get_framebuffer(
+    psx: *mut TrapezoidPsx,
+    buffer: *mut u8,
+    capacity: usize,
+    out_width: *mut u32,
+    out_height: *mut u32,
+) -> TrapezoidResult {
+    ffi_call(|| {
+        let psx = psx_mut(psx)?;
+        if out_width.is_null() || out_height.is_null() {
+            return Err(null_error("out_width/out_height"));
+        }
+
+        let (width, height, rgba) = psx.psx.read_display_rgba();
+        *out_width = width;
+        *out_height = height;
+
+        if buffer.is_null() || capacity < rgba.len() {
+            return Err(FfiError::new(
+                TrapezoidResult::BufferTooSmall,
+                format!("the frame needs {} bytes", rgba.len()),
+            ));
+        }
+        ptr::copy_nonoverlapping(rgba.as_ptr(), buffer, rgba.len());
+        Ok(())
+    })
+}
+
+/// Save the emulator state into `buffer`.
+///
+/// Save states are not supported by the emulator yet, this always returns
+/// `TRAPEZOID_RESULT_UNSUPPORTED`, it's here so that the API won't change when they are.
+///
+/// # Safety
+/// `psx` must be a valid handle
+#[no_mangle]
+pub unsafe extern "C" fn trapezoid_save_state(
+    psx: *mut TrapezoidPsx,
+    _buffer: *mut u8,
+    _capacity: usize,
+    _out_len: *mut usize,
+) -> TrapezoidResult {
+    ffi_call(|| {
+        psx_mut(psx)?;
+        Err(FfiError::new(
+            TrapezoidResult::Unsupported,
+            "save states are not supported yet",
+        ))
+    })
+}
+
+/// Load a state saved with [`trapezoid_save_state`], not supported yet, see it.
+///
+/// # Safety
+/// `psx` must be a valid handle
+#[no_mangle]
+pub unsafe extern "C" fn trapezoid_load_state(
+    psx: *mut TrapezoidPsx,
+    _data: *const u8,
+    _len: usize,
+) -> TrapezoidResult {
+    ffi_call(|| {
+        psx_mut(psx)?;
+        Err(FfiError::new(
+            TrapezoidResult::Unsupported,
+            "save states are not supported yet",
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(trapezoid_last_error()) }
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    #[test]
+    fn null_arguments() {
+        assert_eq!(last_error(), "");
+
+        let mut psx = ptr::null_mut();
+        let bios = [0u8; 16];
+        let result =
+            unsafe { trapezoid_create(ptr::null(), 0, ptr::null(), ptr::null(), &mut psx) };
+        assert_eq!(result, TrapezoidResult::InvalidArgument);
+        assert_eq!(last_error(), "`bios_data` is NULL");
+        assert!(psx.is_null());
+
+        let result = unsafe {
+            trapezoid_create(
+                bios.as_ptr(),
+                bios.len(),
+                ptr::null(),
+                ptr::null(),
+                ptr::null_mut(),
+            )
+        };
+        assert_eq!(result, TrapezoidResult::InvalidArgument);
+        assert_eq!(last_error(), "`out_psx` is NULL");
+
+        let result = unsafe { trapezoid_run_frame(ptr::null_mut()) };
+        assert_eq!(result, TrapezoidResult::InvalidArgument);
+        assert_eq!(last_error(), "`psx` is NULL");
+
+        // does nothing
+        unsafe { trapezoid_destroy(ptr::null_mut()) };
+    }
+
+    #[test]
+    fn errors_and_panics() {
+        assert_eq!(
+            ffi_call(|| Err(PsxError::CouldNotLoadBios("wrong size".to_owned()).into())),
+            TrapezoidResult::BiosError
+        );
+        assert_eq!(last_error(), "Could not load BIOS: wrong size");
+
+        assert_eq!(
+            ffi_call(|| Err(PsxError::DiskTypeNotSupported.into())),
+            TrapezoidResult::DiskError
+        );
+
+        assert_eq!(ffi_call(|| panic!("oops")), TrapezoidResult::Panic);
+        assert_eq!(last_error(), "panic: oops");
+
+        // errors are kept until the next error
+        assert_eq!(ffi_call(|| Ok(())), TrapezoidResult::Ok);
+        assert_eq!(last_error(), "panic: oops");
+    }
+}
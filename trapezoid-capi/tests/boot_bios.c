@@ -0,0 +1,101 @@
+/*
+ * Boots a BIOS through the C API, and checks that it draws something.
+ *
+ * Build and run (from `trapezoid-capi`):
+ *   cargo build -p trapezoid-capi --release
+ *   cc -I include tests/boot_bios.c -L ../target/release -ltrapezoid_capi -o boot_bios
+ *   LD_LIBRARY_PATH=../target/release ./boot_bios <bios.bin>
+ */
+
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "trapezoid.h"
+
+#define FRAMES 300
+#define AUDIO_CAPACITY 4096
+
+static unsigned char *read_file(const char *path, size_t *len) {
+  FILE *file = fopen(path, "rb");
+  if (!file) {
+    return NULL;
+  }
+  fseek(file, 0, SEEK_END);
+  long size = ftell(file);
+  fseek(file, 0, SEEK_SET);
+
+  unsigned char *data = malloc(size);
+  if (data && fread(data, 1, size, file) != (size_t)size) {
+    free(data);
+    data = NULL;
+  }
+  fclose(file);
+  *len = size;
+  return data;
+}
+
+int main(int argc, char **argv) {
+  if (argc < 2) {
+    fprintf(stderr, "usage: %s <bios.bin>\n", argv[0]);
+    return 1;
+  }
+
+  size_t bios_len;
+  unsigned char *bios = read_file(argv[1], &bios_len);
+  if (!bios) {
+    fprintf(stderr, "could not read %s\n", argv[1]);
+    return 1;
+  }
+
+  TrapezoidConfig config = trapezoid_default_config();
+  TrapezoidPsx *psx = NULL;
+  TrapezoidResult result = trapezoid_create(bios, bios_len, NULL, &config, &psx);
+  free(bios);
+  if (result != TRAPEZOID_RESULT_OK) {
+    fprintf(stderr, "trapezoid_create: %s\n", trapezoid_last_error());
+    return 1;
+  }
+
+  static float audio[AUDIO_CAPACITY];
+  size_t total_samples = 0;
+  for (int i = 0; i < FRAMES; i++) {
+    if (trapezoid_run_frame(psx) != TRAPEZOID_RESULT_OK) {
+      fprintf(stderr, "trapezoid_run_frame: %s\n", trapezoid_last_error());
+      trapezoid_destroy(psx);
+      return 1;
+    }
+
+    size_t len;
+    do {
+      trapezoid_get_audio_samples(psx, audio, AUDIO_CAPACITY, &len);
+      total_samples += len;
+    } while (len == AUDIO_CAPACITY);
+  }
+
+  uint32_t width, height;
+  /* get the size first */
+  trapezoid_get_framebuffer(psx, NULL, 0, &width, &height);
+  size_t size = (size_t)width * height * 4;
+  unsigned char *pixels = malloc(size);
+  result = trapezoid_get_framebuffer(psx, pixels, size, &width, &height);
+  if (result != TRAPEZOID_RESULT_OK) {
+    fprintf(stderr, "trapezoid_get_framebuffer: %s\n", trapezoid_last_error());
+    free(pixels);
+    trapezoid_destroy(psx);
+    return 1;
+  }
+
+  int drawn = 0;
+  for (size_t i = 0; i < size; i += 4) {
+    if (pixels[i] || pixels[i + 1] || pixels[i + 2]) {
+      drawn = 1;
+      break;
+    }
+  }
+  free(pixels);
+  trapezoid_destroy(psx);
+
+  printf("%ux%u, %zu audio samples, %s\n", width, height, total_samples,
+         drawn ? "something was drawn" : "the screen is black");
+  return drawn ? 0 : 1;
+}
@@ -0,0 +1,40 @@
+//! Run a number of frames, and write the display area to a binary PPM file.
+//!
+//! ```sh
+//! cargo run -p trapezoid-core --example frame_dump -- <BIOS> <FRAMES> <OUTPUT.ppm> [DISK]
+//! ```
+
+use std::io::Write;
+
+use trapezoid_core::{Psx, PsxConfig};
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 3 {
+        eprintln!("Usage: frame_dump <BIOS> <FRAMES> <OUTPUT.ppm> [DISK]");
+        std::process::exit(1);
+    }
+    let frames = args[1].parse::<u32>().expect("FRAMES must be a number");
+
+    let mut psx = Psx::new_headless(Some(&args[0]), args.get(3), PsxConfig::default())
+        .unwrap_or_else(|e| {
+            eprintln!("Could not create the emulator: {}", e);
+            std::process::exit(1);
+        });
+    for _ in 0..frames {
+        psx.clock_full_video_frame();
+    }
+
+    let (width, height, rgba) = psx.read_display_rgba();
+    let mut ppm = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    for pixel in rgba.chunks_exact(4) {
+        ppm.extend_from_slice(&pixel[..3]);
+    }
+    std::fs::File::create(&args[2])
+        .and_then(|mut f| f.write_all(&ppm))
+        .unwrap_or_else(|e| {
+            eprintln!("Could not write {:?}: {}", args[2], e);
+            std::process::exit(1);
+        });
+    println!("Wrote {}x{} frame to {:?}", width, height, args[2]);
+}
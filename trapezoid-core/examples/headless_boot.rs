@@ -0,0 +1,35 @@
+//! Boot a BIOS (and optionally a disk) without a window, and print the TTY output.
+//!
+//! ```sh
+//! cargo run -p trapezoid-core --example headless_boot -- <BIOS> [DISK] [FRAMES]
+//! ```
+
+use trapezoid_core::{cpu::CpuState, Psx, PsxConfig};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let Some(bios) = args.next() else {
+        eprintln!("Usage: headless_boot <BIOS> [DISK] [FRAMES]");
+        std::process::exit(1);
+    };
+    let disk = args.next();
+    let frames = args
+        .next()
+        .map(|f| f.parse::<u32>().expect("FRAMES must be a number"))
+        .unwrap_or(600);
+
+    let config = PsxConfig::builder().stdout_debug(true).build();
+    let mut psx = Psx::new_headless(Some(bios), disk, config).unwrap_or_else(|e| {
+        eprintln!("Could not create the emulator: {}", e);
+        std::process::exit(1);
+    });
+
+    for _ in 0..frames {
+        // only stops early on breakpoints, which need the `debugger` feature
+        if psx.clock_full_video_frame() != CpuState::Normal {
+            break;
+        }
+    }
+    println!();
+    println!("Ran {} frames, {} warnings", frames, psx.warning_count());
+}
@@ -0,0 +1,275 @@
+//! Writing emulator output (audio and frames) to files.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+const AUDIO_SAMPLE_RATE: u32 = 44100;
+const AUDIO_CHANNELS: u16 = 2;
+const AUDIO_BITS_PER_SAMPLE: u16 = 16;
+const WAV_HEADER_SIZE: u32 = 44;
+/// The sizes in the header are `u32`, so the data can't be larger than this,
+/// rounded down to a full stereo sample
+const WAV_MAX_DATA_SIZE: u64 = (u32::MAX - (WAV_HEADER_SIZE - 8)) as u64 & !3;
+
+/// Writes interleaved stereo `f32` samples into a 16-bit PCM WAV file.
+///
+/// The sizes in the header are only known at the end, they are written by
+/// [`WavWriter::finish`], or when the writer is dropped.
+///
+/// A WAV file can't be larger than 4GB, about 6.7 hours of audio. When
+/// the limit is reached, the samples that fit are written and
+/// [`WavWriter::write_samples`] returns an error.
+pub(crate) struct WavWriter {
+    file: BufWriter<File>,
+    data_size: u64,
+    max_data_size: u64,
+    finished: bool,
+}
+
+impl WavWriter {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        // write the header with empty sizes, will be fixed in `finish`
+        Self::write_header(&mut file, 0)?;
+
+        Ok(Self {
+            file,
+            data_size: 0,
+            max_data_size: WAV_MAX_DATA_SIZE,
+            finished: false,
+        })
+    }
+
+    fn write_header<W: Write>(w: &mut W, data_size: u32) -> io::Result<()> {
+        let block_align = AUDIO_CHANNELS * AUDIO_BITS_PER_SAMPLE / 8;
+        let byte_rate = AUDIO_SAMPLE_RATE * block_align as u32;
+
+        w.write_all(b"RIFF")?;
+        w.write_all(&(WAV_HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+        w.write_all(b"WAVE")?;
+        w.write_all(b"fmt ")?;
+        // fmt chunk size
+        w.write_all(&16u32.to_le_bytes())?;
+        // PCM format
+        w.write_all(&1u16.to_le_bytes())?;
+        w.write_all(&AUDIO_CHANNELS.to_le_bytes())?;
+        w.write_all(&AUDIO_SAMPLE_RATE.to_le_bytes())?;
+        w.write_all(&byte_rate.to_le_bytes())?;
+        w.write_all(&block_align.to_le_bytes())?;
+        w.write_all(&AUDIO_BITS_PER_SAMPLE.to_le_bytes())?;
+        w.write_all(b"data")?;
+        w.write_all(&data_size.to_le_bytes())?;
+        Ok(())
+    }
+
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        let space = (self.max_data_size - self.data_size) / 2;
+        let fitting = samples.len().min(space as usize);
+
+        for &s in &samples[..fitting] {
+            let s = (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.file.write_all(&s.to_le_bytes())?;
+        }
+        self.data_size += fitting as u64 * 2;
+
+        if fitting < samples.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "the wav file reached the 4GB size limit",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Fix the header sizes and flush the file
+    pub fn finish(mut self) -> io::Result<()> {
+        self.finish_inner()
+    }
+
+    fn finish_inner(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+
+        // always fits, `write_samples` stops at `max_data_size`
+        let data_size = u32::try_from(self.data_size).unwrap();
+        self.file.seek(SeekFrom::Start(0))?;
+        Self::write_header(&mut self.file, data_size)?;
+        self.file.flush()
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish_inner() {
+            log::error!("Failed to finish the wav file: {}", e);
+        }
+    }
+}
+
+/// A frame read from the display area in `RGB888` format.
+pub(crate) struct Frame {
+    pub width: u32,
+    pub height: u32,
+    pub rgb: Vec<u8>,
+}
+
+impl Frame {
+    /// Save the frame to `path`, the format is selected by the extension,
+    /// `png` is only available with the `image` feature, otherwise
+    /// a binary `ppm` is written.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let is_png = path
+            .extension()
+            .map(|e| e.eq_ignore_ascii_case("png"))
+            .unwrap_or(false);
+
+        if is_png {
+            self.save_png(path)
+        } else {
+            let mut file = BufWriter::new(File::create(path)?);
+            self.write_ppm(&mut file)?;
+            file.flush()
+        }
+    }
+
+    fn write_ppm<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write!(w, "P6\n{} {}\n255\n", self.width, self.height)?;
+        w.write_all(&self.rgb)
+    }
+
+    #[cfg(feature = "image")]
+    fn save_png(&self, path: &Path) -> io::Result<()> {
+        image::save_buffer_with_format(
+            path,
+            &self.rgb,
+            self.width,
+            self.height,
+            image::ColorType::Rgb8,
+            image::ImageFormat::Png,
+        )
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+
+    #[cfg(not(feature = "image"))]
+    fn save_png(&self, _path: &Path) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "png screenshots require the `image` feature, use `.ppm` instead",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wav_header_is_finalized() {
+        let path = std::env::temp_dir().join("trapezoid_wav_header_test.wav");
+
+        let mut wav = WavWriter::create(&path).unwrap();
+        wav.write_samples(&[0.0, 1.0, -1.0, 0.5]).unwrap();
+        wav.write_samples(&[0.25, -0.25]).unwrap();
+        wav.finish().unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let u32_at = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+        let u16_at = |i: usize| u16::from_le_bytes(data[i..i + 2].try_into().unwrap());
+
+        assert_eq!(data.len(), 44 + 6 * 2);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32_at(4), data.len() as u32 - 8);
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        // PCM, stereo, 44100Hz, 16 bits
+        assert_eq!(u16_at(20), 1);
+        assert_eq!(u16_at(22), 2);
+        assert_eq!(u32_at(24), 44100);
+        assert_eq!(u32_at(28), 44100 * 4);
+        assert_eq!(u16_at(32), 4);
+        assert_eq!(u16_at(34), 16);
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(u32_at(40), 12);
+        // second sample is max
+        assert_eq!(u16_at(46), i16::MAX as u16);
+    }
+
+    #[test]
+    fn wav_header_is_finalized_on_drop() {
+        let path = std::env::temp_dir().join("trapezoid_wav_drop_test.wav");
+
+        let mut wav = WavWriter::create(&path).unwrap();
+        wav.write_samples(&[0.5; 10]).unwrap();
+        drop(wav);
+
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(data.len(), 44 + 10 * 2);
+        assert_eq!(&data[4..8], &(data.len() as u32 - 8).to_le_bytes());
+        assert_eq!(&data[40..44], &20u32.to_le_bytes());
+    }
+
+    #[test]
+    fn wav_stops_at_size_limit() {
+        let path = std::env::temp_dir().join("trapezoid_wav_limit_test.wav");
+
+        let mut wav = WavWriter::create(&path).unwrap();
+        wav.max_data_size = 16;
+        wav.write_samples(&[0.5; 6]).unwrap();
+        assert!(wav.write_samples(&[0.5; 6]).is_err());
+        assert!(wav.write_samples(&[0.5; 2]).is_err());
+        wav.finish().unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(data.len(), 44 + 16);
+        assert_eq!(&data[40..44], &16u32.to_le_bytes());
+    }
+
+    #[test]
+    fn wav_max_data_size_fits_header() {
+        assert_eq!(WAV_MAX_DATA_SIZE % 4, 0);
+        assert!(WAV_MAX_DATA_SIZE + (WAV_HEADER_SIZE - 8) as u64 <= u32::MAX as u64);
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn png_output() {
+        let path = std::env::temp_dir().join("trapezoid_png_test.png");
+        let frame = Frame {
+            width: 3,
+            height: 2,
+            rgb: (0..3 * 2 * 3).collect(),
+        };
+        frame.save(&path).unwrap();
+
+        let image = image::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((image.width(), image.height()), (3, 2));
+        assert_eq!(image.into_rgb8().into_raw(), frame.rgb);
+    }
+
+    #[test]
+    fn ppm_output() {
+        let frame = Frame {
+            width: 2,
+            height: 1,
+            rgb: vec![255, 0, 0, 0, 0, 255],
+        };
+        let mut out = Vec::new();
+        frame.write_ppm(&mut out).unwrap();
+
+        assert_eq!(&out[..11], b"P6\n2 1\n255\n");
+        assert_eq!(&out[11..], &frame.rgb[..]);
+    }
+}
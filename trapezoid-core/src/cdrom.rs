@@ -1,18 +1,23 @@
+mod fifo;
+
 use crate::{
-    memory::{interrupts::InterruptRequester, BusLine, Result},
+    memory::{interrupts::InterruptRequester, BusError, BusLine, Result},
     spu::Spu,
-    PsxError,
+    trace::{TraceEvent, Tracer},
+    warnings::Warnings,
+    PsxError, Region,
 };
 use bitflags::bitflags;
+use fifo::{ParameterFifo, ResponseFifo};
 
-use std::{
-    collections::VecDeque,
-    fs,
-    io::Read,
-    path::{Path, PathBuf},
-};
+use std::{fs, path::Path};
 
 const CDROM_COMMAND_DEFAULT_DELAY: u32 = 0x1100;
+/// The delay between the first and second responses of `GetID`, measured on hardware
+const CDROM_GETID_DELAY: u32 = 0x4a00;
+/// How long `Reset` keeps the controller busy, from psx-spx, there is no
+/// response at the end
+const CDROM_RESET_DELAY: u32 = 0x400000;
 // This is to achive 75 sectors per second
 // Which is calculated as 33868800 (CPU CYCLES) / 75
 // because the default delay is always used, we subtract it from the delay needed
@@ -21,15 +26,91 @@ const CDROM_COMMAND_DEFAULT_DELAY: u32 = 0x1100;
 // Reduced a bit with 0x100, audio felt a bit jagged with the original delay
 const CDROM_READ_PLAY_DELAY: u32 = 0x6e400 - 0x100;
 
+/// The sector of the license string in the system area
+const LICENSE_SECTOR: usize = 4;
+const LICENSE_PREFIX: &[u8] = b"Sony Computer Entertainment ";
+/// The ISO9660 primary volume descriptor
+const VOLUME_DESCRIPTOR_SECTOR: usize = 16;
+
+/// The time to start any seek (spin up and settle on the track), 1/30 second
+const SEEK_BASE_CYCLES: u32 = 33868800 / 30;
+/// Makes a seek across a full disk (~74 minutes) take about a second
+const SEEK_CYCLES_PER_SECTOR: u32 = 100;
+
+/// What kind of disk is inserted, this is what `GetID` reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskType {
+    /// The first track is an audio track
+    Audio,
+    /// A data disk with a license string for the region
+    Licensed(Region),
+    /// A data disk without a license string (e.g. homebrew)
+    Unlicensed,
+}
+
+/// The volumes of the CD audio (XA-ADPCM and CD-DA) to the SPU, `0x80` is 100%
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CdAudioVolumes {
+    pub left_to_left: u8,
+    pub left_to_right: u8,
+    pub right_to_left: u8,
+    pub right_to_right: u8,
+    /// ADPMUTE, only XA-ADPCM is muted
+    pub adpcm_muted: bool,
+}
+
+/// The mode of a track in the cue file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TrackMode {
+    Mode2,
+    Audio,
+}
+
+#[derive(Debug)]
+struct CueSheet {
+    bin_file_name: String,
+    first_track: TrackMode,
+}
+
+/// How long seeking to a sector takes, see [`PsxConfig::cdrom_seek_timing`](crate::PsxConfig::cdrom_seek_timing)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CdromSeekTiming {
+    /// Seeks finish immediately
+    #[default]
+    Instant,
+    /// Like `Accurate`, but 4 times faster, for shorter loading with
+    /// a similar sequence of events
+    Fast,
+    /// A constant time, plus a time proportional to the seek distance
+    Accurate,
+}
+
+impl CdromSeekTiming {
+    fn seek_cycles(self, from_sector: usize, to_sector: usize) -> u32 {
+        let distance = from_sector.abs_diff(to_sector) as u32;
+        let accurate =
+            SEEK_BASE_CYCLES.saturating_add(distance.saturating_mul(SEEK_CYCLES_PER_SECTOR));
+
+        match self {
+            CdromSeekTiming::Instant => 0,
+            CdromSeekTiming::Fast => accurate / 4,
+            CdromSeekTiming::Accurate => accurate,
+        }
+    }
+}
+
 bitflags! {
     #[derive(Default)]
     struct FifosStatus: u8 {
         const ADPBUSY                 = 0b00000100;
-        /// 1 when empty (triggered before writing 1st byte)
+        /// 1 when empty (triggered before writing 1st byte),
+        /// not stored, computed from the parameter FIFO when read
         const PARAMETER_FIFO_EMPTY    = 0b00001000;
-        /// 0 when full (triggered after writing 16 bytes)
+        /// 0 when full (triggered after writing 16 bytes),
+        /// not stored, computed from the parameter FIFO when read
         const PARAMETER_FIFO_NOT_FULL = 0b00010000;
-        /// 0 when empty (triggered after reading LAST byte)
+        /// 0 when empty (triggered after reading LAST byte),
+        /// not stored, computed from the response FIFO when read
         const RESPONSE_FIFO_NOT_EMPTY = 0b00100000;
         /// 0 when empty (triggered after reading LAST byte)
         const DATA_FIFO_NOT_EMPTY     = 0b01000000;
@@ -61,7 +142,6 @@ enum ActionStatus {
         second_delivery_attempt: bool,
     },
     Seek,
-    #[allow(dead_code)]
     Play,
 }
 
@@ -138,6 +218,7 @@ bitflags! {
         const DOUBLE_SPEED            = 0b10000000;
         const XA_ADPCM                = 0b01000000;
         const USE_WHOLE_SECTOR        = 0b00100000;
+        /// Without `USE_WHOLE_SECTOR`, the sector size is `0x918`
         const IGNORE_BIT              = 0b00010000;
         const XA_FILTER               = 0b00001000;
         const REPORT_INTERRUPT_ENABLE = 0b00000100;
@@ -278,6 +359,11 @@ const ZIGZAG_TABLE: [[i32; 29]; 7] = [
     ],
 ];
 
+/// The most samples a single channel can produce from one sector, which happens
+/// for mono 4bit 18900Hz, 0x12 portions * 8 blocks * 28 samples,
+/// doubled to 37800Hz and then converted to 44100Hz.
+const MAX_ADPCM_SECTOR_SAMPLES: usize = 0x12 * 8 * 28 * 2 * 7 / 6;
+
 /// Performs interpolation and converts all audio
 /// sample rates (18900Hz or 37800Hz) to 44100Hz
 struct AdpcmInterpolator {
@@ -297,8 +383,16 @@ impl Default for AdpcmInterpolator {
 }
 
 impl AdpcmInterpolator {
-    // TODO: optimize
-    pub fn output_samples(&mut self, samples: &[i16], sample_rate_18900: bool, out: &mut Vec<i16>) {
+    /// Interpolate `samples` into `out`, returns the number of samples written.
+    ///
+    /// `out` must have space for `samples.len() * 7 / 3` samples.
+    pub fn output_samples(
+        &mut self,
+        samples: &[i16],
+        sample_rate_18900: bool,
+        out: &mut [i16],
+    ) -> usize {
+        let mut written = 0;
         for &s in samples {
             // double the samples
             if sample_rate_18900 {
@@ -316,10 +410,12 @@ impl AdpcmInterpolator {
             if self.sixstep_counter == 0 {
                 self.sixstep_counter = 6;
                 for i in 0..7 {
-                    out.push(self.zigzag_interpolate(i))
+                    out[written] = self.zigzag_interpolate(i);
+                    written += 1;
                 }
             }
         }
+        written
     }
 
     fn zigzag_interpolate(&mut self, table_i: usize) -> i16 {
@@ -336,11 +432,30 @@ impl AdpcmInterpolator {
     }
 }
 
+/// The 2 seconds before the first track, the cursor position starts
+/// after them (at the first track)
+const PREGAP_SECTORS: usize = 150;
+
+/// Convert a sector position to (minutes, seconds, sectors), not in bcd
+fn sector_to_msf(sector: usize) -> (u8, u8, u8) {
+    let total_seconds = sector / 75;
+    (
+        (total_seconds / 60) as u8,
+        (total_seconds % 60) as u8,
+        (sector % 75) as u8,
+    )
+}
+
 /// Utility function to convert value from bcd format to normal
 fn from_bcd(arg: u8) -> u8 {
     ((arg & 0xF0) >> 4) * 10 + (arg & 0x0F)
 }
 
+/// Like [`from_bcd`], but `None` if any of the two digits is not `0-9`
+fn from_bcd_checked(arg: u8) -> Option<u8> {
+    (arg >> 4 <= 9 && arg & 0x0F <= 9).then(|| from_bcd(arg))
+}
+
 /// Utility function to convert value from from normal format to bcd
 fn to_bcd(arg: u8) -> u8 {
     ((arg / 10) << 4) | (arg % 10)
@@ -352,8 +467,11 @@ pub struct Cdrom {
     status: CdromStatus,
     interrupt_enable: u8,
     interrupt_flag: u8,
-    parameter_fifo: VecDeque<u8>,
-    response_fifo: VecDeque<u8>,
+    /// SMEN in the request register, `INT10` is requested when the next command
+    /// is written
+    command_start_interrupt_requested: bool,
+    parameter_fifo: ParameterFifo,
+    response_fifo: ResponseFifo,
     command: Option<u8>,
     /// A timer to delay execution of cdrom commands, in clock unit.
     /// This is needed because the bios is not designed to receive interrupt
@@ -365,20 +483,37 @@ pub struct Cdrom {
     /// The type and design might change later
     command_state: Option<u8>,
 
-    cue_file: Option<PathBuf>,
     cue_file_content: String,
     disk_data: Vec<u8>,
+    /// Detected from the cue and the license string, `None` if there is no disk
+    disk_type: Option<DiskType>,
+    /// From the `BOOT` line of `SYSTEM.CNF`, `None` if not found
+    disk_id: Option<String>,
 
     // commands save buffer
     // params: minutes, seconds, sector (on entire disk)
     set_loc_params: Option<[u8; 3]>,
     // the current position on the disk
     cursor_sector_position: usize,
+    seek_timing: CdromSeekTiming,
+    /// Cycles until the current seek finishes
+    seek_timer: u32,
+    /// The action to start when the seek finishes
+    action_after_seek: ActionStatus,
+    /// The header and subheader of the last sector read, for `GetlocL`
+    last_sector_header: Option<[u8; 8]>,
 
     mode: CdromMode,
+    /// The mode of the sector being read, latched from `mode` when it starts,
+    /// so a `Setmode` during a read applies from the next sector
+    sector_mode: CdromMode,
 
     data_fifo_buffer: Vec<u8>,
+    /// The sector buffer, the last sector delivered with `INT1`, it's kept
+    /// after loading it to the data fifo
     read_data_buffer: Vec<u8>,
+    /// The sector in `read_data_buffer` is not loaded to the data fifo yet
+    sector_pending: bool,
     data_fifo_buffer_index: usize,
 
     filter_file: u8,
@@ -388,6 +523,9 @@ pub struct Cdrom {
     adpcm_decoder_right: AdpcmDecoder,
     adpcm_interpolator_left_mono: AdpcmInterpolator,
     adpcm_interpolator_right: AdpcmInterpolator,
+    // reused for every sector, so that we don't allocate 75 times a second
+    adpcm_samples_left_mono: Box<[i16]>,
+    adpcm_samples_right: Box<[i16]>,
 
     // the values cached by the input until applied
     input_cd_left_to_spu_left: u8,
@@ -403,34 +541,45 @@ pub struct Cdrom {
 
     adpcm_mute: bool,
     cd_mute: bool,
+
+    tracer: Tracer,
+    warnings: Warnings,
 }
 
 impl Default for Cdrom {
     fn default() -> Self {
         Self {
             index: 0,
-            fifo_status: FifosStatus::PARAMETER_FIFO_EMPTY | FifosStatus::PARAMETER_FIFO_NOT_FULL,
+            fifo_status: FifosStatus::empty(),
             status: CdromStatus::default(),
             interrupt_enable: 0,
             interrupt_flag: 0,
-            parameter_fifo: VecDeque::new(),
-            response_fifo: VecDeque::new(),
+            command_start_interrupt_requested: false,
+            parameter_fifo: ParameterFifo::default(),
+            response_fifo: ResponseFifo::default(),
             command: None,
             command_delay_timer: 0,
             read_play_delay_timer: 0,
             command_state: None,
-            cue_file: None,
             // empty vectors are not allocated
             cue_file_content: String::new(),
             disk_data: Vec::new(),
+            disk_type: None,
+            disk_id: None,
 
             set_loc_params: None,
             cursor_sector_position: 0,
+            seek_timing: CdromSeekTiming::default(),
+            seek_timer: 0,
+            action_after_seek: ActionStatus::None,
+            last_sector_header: None,
 
             mode: CdromMode::empty(),
+            sector_mode: CdromMode::empty(),
 
             data_fifo_buffer: Vec::new(),
             read_data_buffer: Vec::new(),
+            sector_pending: false,
             data_fifo_buffer_index: 0,
 
             filter_file: 0,
@@ -440,6 +589,8 @@ impl Default for Cdrom {
             adpcm_decoder_right: AdpcmDecoder::default(),
             adpcm_interpolator_left_mono: AdpcmInterpolator::default(),
             adpcm_interpolator_right: AdpcmInterpolator::default(),
+            adpcm_samples_left_mono: vec![0; MAX_ADPCM_SECTOR_SAMPLES].into_boxed_slice(),
+            adpcm_samples_right: vec![0; MAX_ADPCM_SECTOR_SAMPLES].into_boxed_slice(),
 
             input_cd_left_to_spu_left: 0,
             input_cd_left_to_spu_right: 0,
@@ -453,90 +604,330 @@ impl Default for Cdrom {
 
             adpcm_mute: false,
             cd_mute: true,
+
+            tracer: Tracer::default(),
+            warnings: Warnings::default(),
         }
     }
 }
 
 // file reading and handling
 impl Cdrom {
-    pub fn reset(&mut self) {
-        let cue_file = self.cue_file.take();
-        let _ = std::mem::take(self);
-        if let Some(cue_file) = cue_file {
-            let _ = self.set_cue_file(cue_file);
+    pub fn new(seek_timing: CdromSeekTiming) -> Self {
+        Self {
+            seek_timing,
+            ..Self::default()
+        }
+    }
+
+    /// Go back to the power-on state, everything is recreated from [`Default`]
+    /// except the host side: the inserted disk (loaded again), the shell state,
+    /// the seek timing, the tracer and the warnings.
+    pub fn reset(&mut self) -> Result<(), PsxError> {
+        let old = std::mem::replace(self, Self::new(self.seek_timing));
+        self.tracer = old.tracer;
+        self.warnings = old.warnings;
+        if !old.disk_data.is_empty() {
+            self.set_disk_bytes(old.cue_file_content, old.disk_data)?;
         }
+        // the shell is opened and closed by the user, not the console
+        if old.status.shell_open {
+            self.status.set_shell_open_state(true);
+        }
+        Ok(())
+    }
+
+    pub fn set_seek_timing(&mut self, seek_timing: CdromSeekTiming) {
+        self.seek_timing = seek_timing;
     }
 
     pub fn set_cue_file<P: AsRef<Path>>(&mut self, cue_file: P) -> Result<(), PsxError> {
-        let a = cue_file.as_ref().to_path_buf();
-        self.load_cue_file(&a)?;
-        self.cue_file = Some(a);
+        let (cue_content, bin_file_content) = read_cue_file(cue_file.as_ref())?;
+        self.insert_disk(cue_content, bin_file_content);
+
         Ok(())
     }
 
-    fn load_cue_file(&mut self, cue_file: &Path) -> Result<(), PsxError> {
-        macro_rules! parse_expect {
-            ($var:ident, $expected:expr) => {
-                if $var.next().unwrap_or("") != $expected {
-                    return Err(PsxError::CouldNotLoadDisk(
-                        concat!("Invalid cue file: at ", $expected).to_string(),
-                    ));
-                }
-            };
+    /// Replace the disk with another one while running, the shell must be open,
+    /// the new disk is used after it's closed.
+    pub fn swap_disk<P: AsRef<Path>>(&mut self, cue_file: P) -> Result<(), PsxError> {
+        self.check_shell_open()?;
+        let (cue_content, bin_file_content) = read_cue_file(cue_file.as_ref())?;
+        self.swap_disk_bytes(cue_content, bin_file_content)
+    }
+
+    /// Same as [`Cdrom::swap_disk`], but with the files already in memory
+    pub fn swap_disk_bytes(
+        &mut self,
+        cue_content: String,
+        bin_data: Vec<u8>,
+    ) -> Result<(), PsxError> {
+        self.check_shell_open()?;
+        parse_cue(&cue_content)?;
+
+        log::info!("CDROM swapping disk");
+        // the old position and the read sectors are from the old disk
+        self.set_loc_params = None;
+        self.cursor_sector_position = 0;
+        self.seek_timer = 0;
+        self.action_after_seek = ActionStatus::None;
+        self.last_sector_header = None;
+        self.data_fifo_buffer.clear();
+        self.read_data_buffer.clear();
+        self.sector_pending = false;
+        self.data_fifo_buffer_index = 0;
+        self.fifo_status.remove(FifosStatus::DATA_FIFO_NOT_EMPTY);
+
+        self.cue_file_content = cue_content;
+        self.set_disk_data(bin_data);
+        Ok(())
+    }
+
+    fn check_shell_open(&self) -> Result<(), PsxError> {
+        if self.status.shell_open {
+            Ok(())
+        } else {
+            Err(PsxError::CouldNotLoadDisk(
+                "the shell must be open to swap the disk".to_string(),
+            ))
         }
+    }
+
+    /// Same as [`Cdrom::set_cue_file`], but with the cue file and the bin file it
+    /// references already in memory
+    pub fn set_disk_bytes(
+        &mut self,
+        cue_content: String,
+        bin_data: Vec<u8>,
+    ) -> Result<(), PsxError> {
+        parse_cue(&cue_content)?;
+        self.insert_disk(cue_content, bin_data);
+        Ok(())
+    }
 
+    fn insert_disk(&mut self, cue_content: String, bin_data: Vec<u8>) {
         // TODO: support parsing and loading the data based on the cue file
         // TODO: since some Cds can be large, try to do mmap
         self.status.start_motor();
-
-        // read cue file
-        let mut file =
-            fs::File::open(cue_file).map_err(|e| PsxError::CouldNotLoadDisk(e.to_string()))?;
-        let mut cue_content = String::new();
-        file.read_to_string(&mut cue_content)
-            .map_err(|e| PsxError::CouldNotLoadDisk(e.to_string()))?;
-        // parse cue format
-        let mut parts = cue_content.split_whitespace();
-        parse_expect!(parts, "FILE");
-        let mut bin_file_name = parts
-            .next()
-            .ok_or_else(|| PsxError::CouldNotLoadDisk("Doesn't have bin filename".to_string()))?
-            .to_string();
-        // must be in quotes
-        assert!(bin_file_name.starts_with('"'));
-        while !bin_file_name.ends_with('"') {
-            bin_file_name.push(' ');
-            bin_file_name.push_str(parts.next().unwrap());
-        }
-        // remove quotes
-        bin_file_name = bin_file_name.trim_matches('"').to_string();
-        parse_expect!(parts, "BINARY");
-        parse_expect!(parts, "TRACK");
-        parse_expect!(parts, "01");
-        parse_expect!(parts, "MODE2/2352");
-        parse_expect!(parts, "INDEX");
-        parse_expect!(parts, "01");
-        parse_expect!(parts, "00:00:00");
-
-        // load bin file
-        let bin_file_path = cue_file.parent().unwrap().join(bin_file_name);
-        log::info!("Loading bin file: {:?}", bin_file_path);
-        let mut file =
-            fs::File::open(bin_file_path).map_err(|e| PsxError::CouldNotLoadDisk(e.to_string()))?;
-        // read to new vector
-        let mut bin_file_content = Vec::new();
-        file.read_to_end(&mut bin_file_content)
-            .map_err(|e| PsxError::CouldNotLoadDisk(e.to_string()))?;
         self.cue_file_content = cue_content;
-        self.disk_data = bin_file_content;
+        self.set_disk_data(bin_data);
+    }
 
-        Ok(())
+    fn set_disk_data(&mut self, disk_data: Vec<u8>) {
+        self.disk_data = disk_data;
+        self.disk_type = self.detect_disk_type();
+        self.disk_id = self.detect_disk_id();
+        log::info!("Disk type: {:?}, ID: {:?}", self.disk_type, self.disk_id);
+    }
+
+    /// The user data of a Mode 2 Form 1 sector
+    fn sector_data(&self, sector: usize) -> Option<&[u8]> {
+        let start = sector * 2352 + 24;
+        self.disk_data.get(start..start + 0x800)
+    }
+
+    /// Find `SYSTEM.CNF` in the root directory of the ISO9660 filesystem,
+    /// and take the name of the executable from its `BOOT` line,
+    /// e.g. `BOOT = cdrom:\SLUS_007.71;1` is `SLUS_007.71`
+    fn detect_disk_id(&self) -> Option<String> {
+        let le_u32 = |data: &[u8]| u32::from_le_bytes(data[..4].try_into().unwrap()) as usize;
+
+        let volume_descriptor = self.sector_data(VOLUME_DESCRIPTOR_SECTOR)?;
+        if &volume_descriptor[1..6] != b"CD001" {
+            return None;
+        }
+        // the record of the root directory is in the descriptor
+        let root_record = &volume_descriptor[156..156 + 34];
+        let root_sector = le_u32(&root_record[2..]);
+        let root_size = le_u32(&root_record[10..]);
+
+        let mut system_cnf = None;
+        'sectors: for sector in root_sector..root_sector + root_size.div_ceil(0x800) {
+            let directory = self.sector_data(sector)?;
+            let mut offset = 0;
+            // the records don't cross sectors, the rest of the sector is zeros
+            while let Some(&len) = directory.get(offset).filter(|&&len| len >= 34) {
+                let record = directory.get(offset..offset + len as usize)?;
+                let name = record.get(33..33 + record[32] as usize)?;
+                if name.eq_ignore_ascii_case(b"SYSTEM.CNF;1") {
+                    system_cnf = Some((le_u32(&record[2..]), le_u32(&record[10..])));
+                    break 'sectors;
+                }
+                offset += len as usize;
+            }
+        }
+
+        // it's small, only the first sector is needed
+        let (sector, size) = system_cnf?;
+        let data = self.sector_data(sector)?;
+        let content = String::from_utf8_lossy(&data[..size.min(data.len())]);
+        let boot = content.lines().find_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            (key.trim() == "BOOT").then(|| value.trim())
+        })?;
+        let file = boot.rsplit(['\\', ':']).next()?;
+        let id = file.split(';').next()?.trim();
+        (!id.is_empty()).then(|| id.to_string())
+    }
+
+    /// Must be called after setting the cue and the data
+    fn detect_disk_type(&self) -> Option<DiskType> {
+        if self.disk_data.is_empty() {
+            return None;
+        }
+        if let Ok(CueSheet {
+            first_track: TrackMode::Audio,
+            ..
+        }) = parse_cue(&self.cue_file_content)
+        {
+            return Some(DiskType::Audio);
+        }
+        Some(match self.detect_disk_region() {
+            Some(region) => DiskType::Licensed(region),
+            None => DiskType::Unlicensed,
+        })
+    }
+
+    /// The license string in the system area is `Licensed by Sony Computer
+    /// Entertainment Amer(ica)/Euro(pe)/Inc.`, the BIOS checks it for the region
+    fn detect_disk_region(&self) -> Option<Region> {
+        let start = LICENSE_SECTOR * 2352 + 24;
+        let data = self.disk_data.get(start..start + 0x800)?;
+        let position = data
+            .windows(LICENSE_PREFIX.len())
+            .position(|w| w == LICENSE_PREFIX)?;
+        let rest = &data[position + LICENSE_PREFIX.len()..];
+
+        if rest.starts_with(b"Amer") {
+            Some(Region::NorthAmerica)
+        } else if rest.starts_with(b"Euro") {
+            Some(Region::Europe)
+        } else if rest.starts_with(b"Inc") {
+            Some(Region::Japan)
+        } else {
+            None
+        }
+    }
+
+    pub fn disk_region(&self) -> Option<Region> {
+        match self.disk_type {
+            Some(DiskType::Licensed(region)) => Some(region),
+            _ => None,
+        }
+    }
+
+    pub fn disk_type(&self) -> Option<DiskType> {
+        self.disk_type
+    }
+
+    pub fn disk_id(&self) -> Option<&str> {
+        self.disk_id.as_deref()
+    }
+
+    /// The volumes applied with the "Audio Volume Apply Changes" register. The
+    /// volume registers are write only, reading them from the bus gives the
+    /// response fifo, so this is the only way to see them
+    pub fn audio_volumes(&self) -> CdAudioVolumes {
+        CdAudioVolumes {
+            left_to_left: self.vol_cd_left_to_spu_left,
+            left_to_right: self.vol_cd_left_to_spu_right,
+            right_to_left: self.vol_cd_right_to_spu_left,
+            right_to_right: self.vol_cd_right_to_spu_right,
+            adpcm_muted: self.adpcm_mute,
+        }
+    }
+
+    pub fn has_disk(&self) -> bool {
+        !self.disk_data.is_empty()
+    }
+
+    pub(crate) fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = tracer;
+    }
+
+    pub(crate) fn set_warnings(&mut self, warnings: Warnings) {
+        self.warnings = warnings;
     }
 
     pub fn change_cdrom_shell_open_state(&mut self, open: bool) {
         log::info!("CDROM shell open state: {}", open);
         self.status.set_shell_open_state(open);
+        if open {
+            // opening the shell stops the disk
+            self.status.stop_motor();
+            self.status.reset_action_status();
+        } else if self.has_disk() {
+            self.status.start_motor();
+        }
+    }
+}
+
+/// Read a cue file and the bin file it references
+fn read_cue_file(cue_file: &Path) -> Result<(String, Vec<u8>), PsxError> {
+    let cue_content =
+        fs::read_to_string(cue_file).map_err(|e| PsxError::CouldNotLoadDisk(e.to_string()))?;
+    let cue = parse_cue(&cue_content)?;
+
+    let bin_file_path = cue_file.parent().unwrap().join(cue.bin_file_name);
+    log::info!("Loading bin file: {:?}", bin_file_path);
+    let bin_file_content =
+        fs::read(bin_file_path).map_err(|e| PsxError::CouldNotLoadDisk(e.to_string()))?;
+    Ok((cue_content, bin_file_content))
+}
+
+/// Parse a single track cue file
+fn parse_cue(cue_content: &str) -> Result<CueSheet, PsxError> {
+    macro_rules! parse_expect {
+        ($var:ident, $expected:expr) => {
+            if $var.next().unwrap_or("") != $expected {
+                return Err(PsxError::CouldNotLoadDisk(
+                    concat!("Invalid cue file: at ", $expected).to_string(),
+                ));
+            }
+        };
+    }
+
+    let mut parts = cue_content.split_whitespace();
+    parse_expect!(parts, "FILE");
+    let mut bin_file_name = parts
+        .next()
+        .ok_or_else(|| PsxError::CouldNotLoadDisk("Doesn't have bin filename".to_string()))?
+        .to_string();
+    // must be in quotes
+    if !bin_file_name.starts_with('"') {
+        return Err(PsxError::CouldNotLoadDisk(
+            "Invalid cue file: the bin filename is not quoted".to_string(),
+        ));
     }
+    while bin_file_name.len() < 2 || !bin_file_name.ends_with('"') {
+        let part = parts.next().ok_or_else(|| {
+            PsxError::CouldNotLoadDisk("Invalid cue file: unterminated bin filename".to_string())
+        })?;
+        bin_file_name.push(' ');
+        bin_file_name.push_str(part);
+    }
+    // remove quotes
+    bin_file_name = bin_file_name.trim_matches('"').to_string();
+    parse_expect!(parts, "BINARY");
+    parse_expect!(parts, "TRACK");
+    parse_expect!(parts, "01");
+    let first_track = match parts.next() {
+        Some("MODE2/2352") => TrackMode::Mode2,
+        Some("AUDIO") => TrackMode::Audio,
+        _ => {
+            return Err(PsxError::CouldNotLoadDisk(
+                "Invalid cue file: the track must be MODE2/2352 or AUDIO".to_string(),
+            ))
+        }
+    };
+    parse_expect!(parts, "INDEX");
+    parse_expect!(parts, "01");
+    parse_expect!(parts, "00:00:00");
+
+    Ok(CueSheet {
+        bin_file_name,
+        first_track,
+    })
 }
 
 // clocking and commands
@@ -555,6 +946,8 @@ impl Cdrom {
             return;
         }
 
+        self.handle_seek(cycles);
+
         if self.handle_command_delay(cycles) {
             if let Some(cmd) = self.command {
                 self.handle_command(cmd);
@@ -562,7 +955,11 @@ impl Cdrom {
         }
 
         if self.handle_reading_delay(cycles) {
-            self.handle_reading_data(spu);
+            if self.status.action_status == ActionStatus::Play {
+                self.handle_playing();
+            } else {
+                self.handle_reading_data(spu);
+            }
         }
 
         // fire irq only if the interrupt is enabled
@@ -598,6 +995,14 @@ impl Cdrom {
 
         // every command starts the motor (if its not already on)
         self.status.start_motor();
+
+        // only trace the first stage of the command
+        if self.command_state.is_none() {
+            self.tracer.trace(|| TraceEvent::CdromCommand {
+                cmd,
+                params: self.parameter_fifo.iter().collect(),
+            });
+        }
         match cmd {
             0x01 => {
                 // GetStat
@@ -613,45 +1018,76 @@ impl Cdrom {
             }
             0x02 => {
                 // SetLoc
+                let Some(bcd_params) = self.read_parameters::<3>() else {
+                    self.reset_command();
+                    return;
+                };
+
+                // (minutes, seconds, sector), on error, the last `SetLoc` is kept
+                match bcd_params.map(from_bcd_checked) {
+                    [Some(minutes), Some(seconds), Some(sector)] if seconds < 60 && sector < 75 => {
+                        let params = [minutes, seconds, sector];
+                        self.set_loc_params = Some(params);
+
+                        log::info!("cdrom cmd: SetLoc({:?})", params);
+                        self.set_response(self.status.bits());
+                        self.request_interrupt_0_7(3);
+                    }
+                    _ => {
+                        log::warn!("cdrom cmd: SetLoc({:02X?}) invalid position", bcd_params);
+                        // invalid parameter
+                        self.error_response(0x10);
+                    }
+                }
+
+                self.reset_command();
+            }
+            0x03 => {
+                // Play
 
-                let mut params = [0; 3];
-                // minutes
-                params[0] = from_bcd(self.read_next_parameter().unwrap());
-                // seconds
-                params[1] = from_bcd(self.read_next_parameter().unwrap());
-                // sector
-                params[2] = from_bcd(self.read_next_parameter().unwrap());
+                // the track parameter is optional, without it (or with 0), play
+                // from the SetLoc position, or the current position
+                let track = self.read_next_parameter().map(from_bcd).unwrap_or(0);
+                log::info!("cdrom cmd: Play({})", track);
 
-                self.set_loc_params = Some(params);
+                // TODO: fix when supporting multiple tracks
+                if track != 0 {
+                    self.set_loc_params = Some([0, 2, 0]);
+                }
+                self.status.reset_action_status();
+                self.do_seek(ActionStatus::Play);
 
-                log::info!("cdrom cmd: SetLoc({:?})", params);
                 self.set_response(self.status.bits());
                 self.request_interrupt_0_7(3);
 
+                self.read_play_delay_timer = self.sector_delay();
+
                 self.reset_command();
             }
             0x06 | 0x1B => {
                 // ReadN/ReadS
 
                 log::info!("cdrom cmd: ReadN");
-                self.do_seek();
-                self.status.reset_action_status();
 
-                self.status.action_status = ActionStatus::Read {
-                    second_delivery_attempt: false,
-                };
+                let reading = matches!(self.status.action_status, ActionStatus::Read { .. });
+                // without a new `SetLoc`, reading again continues from the current
+                // position, the delivered sector (if not taken yet) is kept
+                if !reading || self.set_loc_params.is_some() {
+                    self.status.reset_action_status();
+                    self.do_seek(ActionStatus::Read {
+                        second_delivery_attempt: false,
+                    });
 
-                self.set_response(self.status.bits());
-                self.request_interrupt_0_7(3);
+                    self.read_play_delay_timer = self.sector_delay();
+                    self.sector_mode = self.mode;
 
-                self.read_play_delay_timer = if self.mode.intersects(CdromMode::DOUBLE_SPEED) {
-                    CDROM_READ_PLAY_DELAY / 2
-                } else {
-                    CDROM_READ_PLAY_DELAY
-                };
+                    // reset data buffer
+                    self.read_data_buffer.clear();
+                    self.sector_pending = false;
+                }
 
-                // reset data buffer
-                self.read_data_buffer.clear();
+                self.set_response(self.status.bits());
+                self.request_interrupt_0_7(3);
 
                 self.reset_command();
             }
@@ -698,32 +1134,24 @@ impl Cdrom {
             }
             0x0A => {
                 // Init
+                //
+                // From psx-spx (CDROM Controller Command Summary): sets the mode
+                // to `0x20`, activates the motor, stops reading (Standby), and
+                // aborts all commands, the first response has the status after that.
+                //
+                // The SetLoc target and the head position are not reset, neither
+                // psx-spx nor DuckStation (which follows hardware tests) touch them,
+                // so a `SetLoc` before `Init` is still used by the next read.
 
                 if self.command_state.is_none() {
                     // FIRST
                     log::info!("cdrom cmd: Init");
 
-                    // TODO: check what exactly needs to be reset
-                    //       do we reset all fifos?
-                    //       do we reset setloc params and cursor position?
-
-                    self.mode = CdromMode::empty();
-                    // reset the status and run the motor
-                    self.status = CdromStatus::default();
+                    self.mode = CdromMode::USE_WHOLE_SECTOR;
                     self.status.start_motor();
-                    // reset fifos
-                    self.data_fifo_buffer.clear();
-                    self.data_fifo_buffer_index = 0;
-                    self.read_data_buffer.clear();
-                    self.fifo_status.remove(FifosStatus::DATA_FIFO_NOT_EMPTY);
-                    self.reset_parameter_fifo();
+                    self.abort_reading();
+                    self.parameter_fifo.clear();
                     self.response_fifo.clear();
-                    self.fifo_status
-                        .remove(FifosStatus::RESPONSE_FIFO_NOT_EMPTY);
-
-                    // reset cursor and set_loc positions
-                    self.set_loc_params = None;
-                    self.cursor_sector_position = 0;
 
                     self.set_response(self.status.bits());
                     self.request_interrupt_0_7(3);
@@ -763,8 +1191,12 @@ impl Cdrom {
             }
             0x0D => {
                 // Setfilter
-                self.filter_file = self.read_next_parameter().unwrap();
-                self.filter_channel = self.read_next_parameter().unwrap();
+                let Some([file, channel]) = self.read_parameters() else {
+                    self.reset_command();
+                    return;
+                };
+                self.filter_file = file;
+                self.filter_channel = channel;
 
                 log::info!(
                     "cdrom cmd: Setfilter: file: {}, channel: {}",
@@ -780,14 +1212,66 @@ impl Cdrom {
             0x0E => {
                 // Setmode
 
-                self.mode = CdromMode::from_bits_retain(self.read_next_parameter().unwrap());
+                let Some([mode]) = self.read_parameters() else {
+                    self.reset_command();
+                    return;
+                };
+                let old_delay = self.sector_delay();
+                self.mode = CdromMode::from_bits_retain(mode);
                 log::info!("cdrom cmd: Setmode({:?})", self.mode);
 
+                // the sector size only changes from the next sector (see
+                // `sector_mode`), but the speed changes the time left for the
+                // current sector immediately
+                let new_delay = self.sector_delay();
+                if new_delay != old_delay
+                    && matches!(
+                        self.status.action_status,
+                        ActionStatus::Read { .. } | ActionStatus::Play
+                    )
+                {
+                    self.read_play_delay_timer = (self.read_play_delay_timer as u64
+                        * new_delay as u64
+                        / old_delay as u64) as u32;
+                }
+
                 self.set_response(self.status.bits());
                 self.request_interrupt_0_7(3);
 
                 self.reset_command();
             }
+            0x0F => {
+                // Getparam
+
+                log::info!("cdrom cmd: Getparam");
+
+                self.set_response_slice(&[
+                    self.status.bits(),
+                    self.mode.bits(),
+                    0,
+                    self.filter_file,
+                    self.filter_channel,
+                ]);
+                self.request_interrupt_0_7(3);
+
+                self.reset_command();
+            }
+            0x10 => {
+                // GetlocL
+
+                log::info!("cdrom cmd: GetlocL");
+
+                match self.last_sector_header {
+                    Some(header) if self.status.action_status != ActionStatus::Seek => {
+                        self.set_response_slice(&header);
+                        self.request_interrupt_0_7(3);
+                    }
+                    // no sector was read since the last seek
+                    _ => self.error_response(0x80),
+                }
+
+                self.reset_command();
+            }
             0x11 => {
                 // GetLocP
 
@@ -795,26 +1279,8 @@ impl Cdrom {
                 //       also, min, second, sectors below as well
 
                 log::info!("cdrom cmd: GetLocP");
-                let track = 1;
-                let index = 1;
 
-                let sector = self.cursor_sector_position % 75;
-                let total_seconds = (self.cursor_sector_position / 75) + 2;
-                let minutes = total_seconds / 60;
-                let seconds = total_seconds % 60;
-
-                self.set_response_slice(&[
-                    to_bcd(track),
-                    to_bcd(index),
-                    // track
-                    to_bcd(minutes as u8),
-                    to_bcd(seconds as u8),
-                    to_bcd(sector as u8),
-                    // whole disk
-                    to_bcd(minutes as u8),
-                    to_bcd(seconds as u8),
-                    to_bcd(sector as u8),
-                ]);
+                self.set_response_slice(&self.position_subchannel_q());
 
                 self.request_interrupt_0_7(3);
 
@@ -842,7 +1308,11 @@ impl Cdrom {
                 // GetTD
                 // TODO: fix when supporting multiple tracks
 
-                let track = from_bcd(self.read_next_parameter().unwrap());
+                let Some([track]) = self.read_parameters() else {
+                    self.reset_command();
+                    return;
+                };
+                let track = from_bcd(track);
 
                 log::info!("cdrom cmd: GetTD: track = {}", track);
 
@@ -859,7 +1329,17 @@ impl Cdrom {
                     res_minutes = 0;
                     res_seconds = 2;
                 } else {
-                    todo!("Doesn't support more than 1 track");
+                    self.warnings.warn(
+                        "CDROM",
+                        format!(
+                            "GetTD: track {} (only single track disks are supported)",
+                            track
+                        ),
+                    );
+                    // the same as a track that doesn't exist
+                    self.error_response(0x10);
+                    self.reset_command();
+                    return;
                 }
 
                 self.set_response_slice(&[
@@ -883,14 +1363,19 @@ impl Cdrom {
                     // FIRST
                     log::info!("cdrom cmd: SeekL");
 
-                    self.do_seek();
+                    // stays in `Seek` until the second response
+                    self.do_seek(ActionStatus::Seek);
 
                     self.set_response(self.status.bits());
                     self.request_interrupt_0_7(3);
                     // any data for now, just to proceed to SECOND
                     self.command_state = Some(0);
+                } else if self.seek_timer > 0 {
+                    // wait for the seek to finish
+                    self.command_delay_timer = self.seek_timer;
                 } else {
                     // SECOND
+                    self.status.reset_action_status();
                     self.set_response(self.status.bits());
                     self.request_interrupt_0_7(2);
                     self.reset_command();
@@ -903,22 +1388,58 @@ impl Cdrom {
                     // FIRST
                     log::info!("cdrom cmd: SeekP");
 
-                    self.do_seek();
+                    // stays in `Seek` until the second response
+                    self.do_seek(ActionStatus::Seek);
 
                     self.set_response(self.status.bits());
                     self.request_interrupt_0_7(3);
                     // any data for now, just to proceed to SECOND
                     self.command_state = Some(0);
+                } else if self.seek_timer > 0 {
+                    // wait for the seek to finish
+                    self.command_delay_timer = self.seek_timer;
                 } else {
                     // SECOND
+                    self.status.reset_action_status();
                     self.set_response(self.status.bits());
                     self.request_interrupt_0_7(2);
                     self.reset_command();
                 }
             }
+            0x1D => {
+                // GetQ
+
+                if self.command_state.is_none() {
+                    // FIRST
+                    let Some([adr, point]) = self.read_parameters() else {
+                        self.reset_command();
+                        return;
+                    };
+                    log::info!("cdrom cmd: GetQ(adr={:02X}, point={:02X})", adr, point);
+
+                    self.set_response(self.status.bits());
+                    self.request_interrupt_0_7(3);
+                    self.command_state = Some(point);
+                } else {
+                    // SECOND
+                    let point = self.command_state.unwrap();
+                    match self.lead_in_subchannel_q(point) {
+                        Some(q) => {
+                            self.set_response_slice(&q);
+                            self.request_interrupt_0_7(2);
+                        }
+                        // invalid parameter
+                        None => self.error_response(0x10),
+                    }
+                    self.reset_command();
+                }
+            }
             0x19 => {
                 // Test
-                let test_code = self.read_next_parameter().unwrap();
+                let Some([test_code]) = self.read_parameters() else {
+                    self.reset_command();
+                    return;
+                };
                 log::info!("cdrom cmd: Test({:02x})", test_code);
                 self.execute_test(test_code);
 
@@ -934,26 +1455,66 @@ impl Cdrom {
                     self.request_interrupt_0_7(3);
                     // any data for now, just to proceed to SECOND
                     self.command_state = Some(0);
+                    self.command_delay_timer = CDROM_GETID_DELAY;
                 } else {
                     // SECOND
-                    // TODO: rewrite GetID implementation to fill
-                    //       all the details correctly from the state of the cdrom
-                    let (response, interrupt) = if self.cue_file.is_some() {
-                        // last byte is the region code identifier
-                        // A(0x41): NTSC
-                        // E(0x45): PAL
-                        // I(0x49): JP
-                        (&[0x02, 0x00, 0x20, 0x00, 0x53, 0x43, 0x45, 0x41], 2)
-                    } else {
-                        //  5 interrupt means error
-                        (&[0x08, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], 5)
+                    // the region code is the last byte, `A`: NTSC, `E`: PAL, `I`: Japan
+                    let licensed = |region| [0x02, 0x00, 0x20, 0x00, b'S', b'C', b'E', region];
+                    //  5 interrupt means error
+                    let (response, interrupt) = match self.disk_type {
+                        None => ([0x08, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], 5),
+                        Some(DiskType::Audio) => {
+                            ([0x0A, 0x90, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], 5)
+                        }
+                        Some(DiskType::Licensed(Region::Japan)) => (licensed(b'I'), 2),
+                        Some(DiskType::Licensed(Region::Europe)) => (licensed(b'E'), 2),
+                        // unlicensed discs respond with `0A 80 ..` and an error, but that
+                        // would stop homebrew from booting, so act like a modchip
+                        Some(DiskType::Licensed(Region::NorthAmerica) | DiskType::Unlicensed) => {
+                            (licensed(b'A'), 2)
+                        }
                     };
 
-                    self.set_response_slice(response);
+                    self.set_response_slice(&response);
                     self.request_interrupt_0_7(interrupt);
                     self.reset_command();
                 }
             }
+            0x1C => {
+                // Reset
+                //
+                // From psx-spx (CDROM Controller Command Summary): resets the
+                // drive controller, the same as opening and closing the shell.
+                // Only `INT3` is sent when it starts, there is no interrupt when it
+                // finishes, the software must wait `0x400000` cycles before the
+                // next command. Parameters are ignored.
+                //
+                // Unlike `Init`, the mode is cleared to `0x00`.
+
+                if self.command_state.is_none() {
+                    // FIRST
+                    log::info!("cdrom cmd: Reset");
+                    self.set_response(self.status.bits());
+                    self.request_interrupt_0_7(3);
+
+                    // the motor is stopped and spins up again after the delay
+                    self.status.stop_motor();
+                    self.abort_reading();
+                    self.mode = CdromMode::empty();
+                    self.parameter_fifo.clear();
+
+                    // any data for now, just to proceed to SECOND
+                    self.command_state = Some(0);
+                    self.command_delay_timer = CDROM_RESET_DELAY;
+                } else {
+                    // SECOND
+                    // done silently, the motor was started when this stage began
+                    if !self.has_disk() {
+                        self.status.stop_motor();
+                    }
+                    self.reset_command();
+                }
+            }
             0x1E => {
                 // GetToc
 
@@ -971,12 +1532,48 @@ impl Cdrom {
                     self.reset_command();
                 }
             }
-            _ => todo!("cmd={:02X},state={:?}", cmd, self.command_state),
+            _ => {
+                self.warnings
+                    .warn("CDROM", format!("unknown command {:02X}", cmd));
+                // invalid command
+                self.error_response(0x40);
+                self.reset_command();
+            }
+        }
+    }
+
+    /// Respond with `INT5`, and `code` as the reason
+    fn error_response(&mut self, code: u8) {
+        self.set_response_slice(&[self.status.bits() | BitCdromStatus::ERROR.bits(), code]);
+        self.request_interrupt_0_7(5);
+    }
+
+    /// Pop the first `N` parameters, the rest are ignored. If there are less,
+    /// respond with the wrong number of parameters error, and return `None`
+    fn read_parameters<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let mut params = [0; N];
+        for param in &mut params {
+            let Some(value) = self.read_next_parameter() else {
+                log::warn!("cdrom: expected {} parameters", N);
+                self.error_response(0x20);
+                return None;
+            };
+            *param = value;
+        }
+        Some(params)
+    }
+
+    /// The cycles to read/play one sector at the current speed
+    fn sector_delay(&self) -> u32 {
+        if self.mode.intersects(CdromMode::DOUBLE_SPEED) {
+            CDROM_READ_PLAY_DELAY / 2
+        } else {
+            CDROM_READ_PLAY_DELAY
         }
     }
 
     fn handle_reading_delay(&mut self, cycles: u32) -> bool {
-        let ActionStatus::Read { .. } = &mut self.status.action_status else {
+        let (ActionStatus::Read { .. } | ActionStatus::Play) = self.status.action_status else {
             return false;
         };
 
@@ -987,11 +1584,7 @@ impl Cdrom {
         }
 
         // refresh the delay timer
-        self.read_play_delay_timer += if self.mode.intersects(CdromMode::DOUBLE_SPEED) {
-            CDROM_READ_PLAY_DELAY / 2
-        } else {
-            CDROM_READ_PLAY_DELAY
-        };
+        self.read_play_delay_timer += self.sector_delay();
 
         // if we can't execute yet, return false
         if self.interrupt_flag & 7 != 0 {
@@ -1015,6 +1608,8 @@ impl Cdrom {
         // skip the sync bytes
         let whole_sector = &self.disk_data[sector_start + 12..sector_start + 0x930];
 
+        self.last_sector_header = Some(whole_sector[..8].try_into().unwrap());
+
         // TODO: add filtering and coding info handling
         let mode = whole_sector[3];
         let file = whole_sector[4];
@@ -1093,7 +1688,7 @@ impl Cdrom {
             //
             // if we're on the second attempt, and the buffer is still not empty
             // perform buffer overrun, i.e. replace the data of the current buffer
-            if self.read_data_buffer.is_empty() || *second_delivery_attempt {
+            if !self.sector_pending || *second_delivery_attempt {
                 // wait until the data fifo buffer is empty
                 log::info!(
                     "cdrom cmd: ReadN: pushing sector {} [{:02}:{:02}:{:02}] to data fifo buffer",
@@ -1103,8 +1698,14 @@ impl Cdrom {
                     sector
                 );
 
-                let data = if self.mode.intersects(CdromMode::USE_WHOLE_SECTOR) {
+                // From psx-spx: the ignore bit ignores the sector size and
+                // the SetLoc position, the size is then `0x918`, the data after
+                // the sub header to the end of the sector (Form 2 data with EDC).
+                // The effect on the position is not documented, so it's not emulated.
+                let data = if self.sector_mode.intersects(CdromMode::USE_WHOLE_SECTOR) {
                     whole_sector
+                } else if self.sector_mode.intersects(CdromMode::IGNORE_BIT) {
+                    &whole_sector[12..]
                 } else {
                     // skip the sub header
                     &whole_sector[12..12 + 0x800]
@@ -1113,6 +1714,7 @@ impl Cdrom {
                 // if there is something, override it
                 self.read_data_buffer.clear();
                 self.read_data_buffer.extend_from_slice(data);
+                self.sector_pending = true;
 
                 *second_delivery_attempt = false;
                 sector_read = true;
@@ -1148,7 +1750,119 @@ impl Cdrom {
         // if we haven't read, just wait the default delay and re-interrupt.
         if sector_read {
             self.cursor_sector_position += 1;
+            // the next sector starts with the latest mode
+            self.sector_mode = self.mode;
+        }
+    }
+
+    fn handle_playing(&mut self) {
+        // TODO: fix when supporting multiple tracks
+        if self.cursor_sector_position >= self.disk_data.len() / 2352 {
+            // reached the end of the only track
+            log::info!("cdrom: Play: end of track");
+            self.status.reset_action_status();
+            self.set_response(self.status.bits());
+            self.request_interrupt_0_7(4);
+            return;
+        }
+
+        // only data tracks are supported, which are not played as audio, so
+        // there is no CD-DA to output, and the peak is always 0
+        let peak = 0u16;
+
+        // reports are sent every 10 sectors, alternating between the absolute
+        // time (sectors 00, 20, 40, 60) and the track relative time
+        // (sectors 10, 30, 50, 70), which is marked with bit 7 of the seconds
+        let sector = self.cursor_sector_position % 75;
+        if self.mode.intersects(CdromMode::REPORT_INTERRUPT_ENABLE) && sector % 10 == 0 {
+            let q = self.position_subchannel_q();
+            let (minutes, seconds, sector) = if sector % 20 == 0 {
+                (q[5], q[6], q[7])
+            } else {
+                (q[2], q[3] | 0x80, q[4])
+            };
+
+            self.set_response_slice(&[
+                self.status.bits(),
+                q[0],
+                q[1],
+                minutes,
+                seconds,
+                sector,
+                peak as u8,
+                (peak >> 8) as u8,
+            ]);
+            self.request_interrupt_0_7(1);
         }
+
+        self.cursor_sector_position += 1;
+    }
+
+    /// The position part of subchannel Q of the current sector, this is the
+    /// response of `GetLocP`: track, index, track relative msf, then absolute msf,
+    /// all in bcd
+    fn position_subchannel_q(&self) -> [u8; 8] {
+        // TODO: fix when supporting multiple tracks
+        let track = 1;
+        let index = 1;
+
+        let (minutes, seconds, sector) = sector_to_msf(self.cursor_sector_position);
+        let (abs_minutes, abs_seconds, abs_sector) =
+            sector_to_msf(self.cursor_sector_position + PREGAP_SECTORS);
+
+        [
+            to_bcd(track),
+            to_bcd(index),
+            to_bcd(minutes),
+            to_bcd(seconds),
+            to_bcd(sector),
+            to_bcd(abs_minutes),
+            to_bcd(abs_seconds),
+            to_bcd(abs_sector),
+        ]
+    }
+
+    /// The subchannel Q of the lead-in area (the TOC) for `point` (bcd), this
+    /// is the response of `GetQ`, without the CRC
+    fn lead_in_subchannel_q(&self, point: u8) -> Option<[u8; 10]> {
+        // TODO: fix when supporting multiple tracks
+        let first_track = 1;
+        let last_track = 1;
+        // data track, Q mode 1
+        let control_adr = 0x41;
+
+        let (p_minutes, p_seconds, p_sector) = match point {
+            0xA0 => {
+                // first track, and disk type (CD-ROM XA)
+                (to_bcd(first_track), 0x20, 0)
+            }
+            0xA1 => (to_bcd(last_track), 0, 0),
+            0xA2 => {
+                // lead-out
+                let (m, s, f) = sector_to_msf(self.disk_data.len() / 2352 + PREGAP_SECTORS);
+                (to_bcd(m), to_bcd(s), to_bcd(f))
+            }
+            0x01 => {
+                let (m, s, f) = sector_to_msf(PREGAP_SECTORS);
+                (to_bcd(m), to_bcd(s), to_bcd(f))
+            }
+            _ => return None,
+        };
+
+        Some([
+            control_adr,
+            // track 0 is the lead-in
+            0,
+            point,
+            // the running time inside the lead-in, not emulated
+            0,
+            0,
+            0,
+            0,
+            p_minutes,
+            p_seconds,
+            p_sector,
+        ])
     }
 
     // because of `&self` and `&mut self` conflict, we can't pass the
@@ -1164,11 +1878,10 @@ impl Cdrom {
         let data = &self.disk_data[sector_start + 24..sector_start + 24 + 0x900];
 
         let sample_8bit = coding_info.intersects(CodingInfo::BITS_PER_SAMPLE);
+        let stereo = coding_info.intersects(CodingInfo::STEREO);
+        let sample_rate_18900 = coding_info.intersects(CodingInfo::SAMPLE_RATE);
 
-        // TODO: try to use static allocation/slab or anything that is not heap intensive
-        let mut cd_audio_left: Vec<i16> = Vec::new();
-        let mut cd_audio_right: Vec<i16> = Vec::new();
-
+        let mut samples_len = 0;
         // contain 0x12 portions of size 128 bytes.
         for i in 0..0x12 {
             let offset = i * 128;
@@ -1183,13 +1896,13 @@ impl Cdrom {
                     sample_8bit,
                     &mut temp_block,
                 );
-                self.adpcm_interpolator_left_mono.output_samples(
+                let written = self.adpcm_interpolator_left_mono.output_samples(
                     &temp_block,
-                    coding_info.intersects(CodingInfo::SAMPLE_RATE),
-                    &mut cd_audio_left,
+                    sample_rate_18900,
+                    &mut self.adpcm_samples_left_mono[samples_len..],
                 );
 
-                if coding_info.intersects(CodingInfo::STEREO) {
+                if stereo {
                     block += 1;
                     self.adpcm_decoder_right.decode_block(
                         portion,
@@ -1198,12 +1911,15 @@ impl Cdrom {
                         &mut temp_block,
                     );
 
+                    // both channels have the same rate, so they produce the same
+                    // number of samples
                     self.adpcm_interpolator_right.output_samples(
                         &temp_block,
-                        coding_info.intersects(CodingInfo::SAMPLE_RATE),
-                        &mut cd_audio_right,
+                        sample_rate_18900,
+                        &mut self.adpcm_samples_right[samples_len..],
                     );
                 }
+                samples_len += written;
                 block += 1;
 
                 if (block == 4 && sample_8bit) || block == 8 {
@@ -1211,28 +1927,34 @@ impl Cdrom {
                 }
             }
         }
-        let mut spu_audio_left = vec![0; cd_audio_left.len()];
-        let mut spu_audio_right = vec![0; cd_audio_left.len()];
 
-        let (audio_left, audio_right) = if coding_info.intersects(CodingInfo::STEREO) {
-            (&cd_audio_left, &cd_audio_right)
+        let audio_left = &self.adpcm_samples_left_mono[..samples_len];
+        let audio_right = if stereo {
+            &self.adpcm_samples_right[..samples_len]
         } else {
-            (&cd_audio_left, &cd_audio_left)
+            audio_left
         };
 
-        if !self.cd_mute && !self.adpcm_mute {
-            for (i, (&left, &right)) in audio_left.iter().zip(audio_right.iter()).enumerate() {
-                let l = (left as i32 * self.vol_cd_left_to_spu_left as i32 / 0x80)
-                    + (right as i32 * self.vol_cd_right_to_spu_left as i32 / 0x80);
-                let r = (left as i32 * self.vol_cd_left_to_spu_right as i32 / 0x80)
-                    + (right as i32 * self.vol_cd_right_to_spu_right as i32 / 0x80);
+        let muted = self.cd_mute || self.adpcm_mute;
+        let vol_left_to_left = self.vol_cd_left_to_spu_left as i32;
+        let vol_left_to_right = self.vol_cd_left_to_spu_right as i32;
+        let vol_right_to_left = self.vol_cd_right_to_spu_left as i32;
+        let vol_right_to_right = self.vol_cd_right_to_spu_right as i32;
 
-                spu_audio_left[i] = l.clamp(-0x8000, 0x7FFF) as i16;
-                spu_audio_right[i] = r.clamp(-0x8000, 0x7FFF) as i16;
+        spu.add_cdrom_audio(audio_left.iter().zip(audio_right).map(|(&left, &right)| {
+            if muted {
+                return (0, 0);
             }
-        }
-
-        spu.add_cdrom_audio(&spu_audio_left, &spu_audio_right);
+            let l =
+                (left as i32 * vol_left_to_left / 0x80) + (right as i32 * vol_right_to_left / 0x80);
+            let r = (left as i32 * vol_left_to_right / 0x80)
+                + (right as i32 * vol_right_to_right / 0x80);
+
+            (
+                l.clamp(-0x8000, 0x7FFF) as i16,
+                r.clamp(-0x8000, 0x7FFF) as i16,
+            )
+        }));
     }
 
     fn execute_test(&mut self, test_code: u8) {
@@ -1253,24 +1975,35 @@ impl Cdrom {
                 self.set_response(self.status.bits());
                 self.request_interrupt_0_7(3);
             }
-            _ => todo!("Test code {:02X}", test_code),
+            _ => {
+                self.warnings
+                    .warn("CDROM", format!("unknown test code {:02X}", test_code));
+                // invalid sub-function
+                self.error_response(0x10);
+            }
         }
     }
 
-    #[inline]
-    fn do_seek(&mut self) {
+    /// Seek to the `SetLoc` position if there is any, then start `action`.
+    ///
+    /// Until the seek finishes, the status will be `Seek`, see [`CdromSeekTiming`]
+    fn do_seek(&mut self, action: ActionStatus) {
         if let Some(params) = self.set_loc_params {
-            self.status.action_status = ActionStatus::Seek;
-
             // setting the position from the setLoc data
             let minutes = params[0] as usize;
             let seconds = params[1] as usize;
             let sector = params[2] as usize;
 
             let total_seconds = minutes * 60 + seconds;
-            // there is an missing 2 seconds offset (for some reason)
-            assert!(total_seconds >= 2);
-            self.cursor_sector_position = (total_seconds - 2) * 75 + sector;
+            // the first 2 seconds are the pregap, which is not in the image,
+            // seeking into it stays at the start of the first track
+            let target = (total_seconds * 75 + sector).saturating_sub(PREGAP_SECTORS);
+            self.seek_timer = self
+                .seek_timing
+                .seek_cycles(self.cursor_sector_position, target);
+            self.cursor_sector_position = target;
+            // the head moved away from it
+            self.last_sector_header = None;
 
             log::info!(
                 "cdrom seek: ({:02}:{:02}:{:02}) => {:08X}",
@@ -1282,9 +2015,48 @@ impl Cdrom {
 
             self.set_loc_params = None;
         }
+
+        if self.seek_timer > 0 {
+            self.status.action_status = ActionStatus::Seek;
+            self.action_after_seek = action;
+        } else {
+            self.status.action_status = action;
+        }
+    }
+
+    fn handle_seek(&mut self, cycles: u32) {
+        if self.seek_timer == 0 {
+            return;
+        }
+
+        self.seek_timer = self.seek_timer.saturating_sub(cycles);
+        // the seek could be interrupted by another command (e.g. `Pause`)
+        if self.seek_timer == 0 && self.status.action_status == ActionStatus::Seek {
+            log::info!("cdrom seek finished");
+            self.status.action_status = self.action_after_seek;
+            // the first sector is read from here
+            self.sector_mode = self.mode;
+        }
+    }
+
+    /// Stop reading, playing, and seeking, and drop the read sectors
+    fn abort_reading(&mut self) {
+        self.status.reset_action_status();
+        self.seek_timer = 0;
+        self.action_after_seek = ActionStatus::None;
+        self.data_fifo_buffer.clear();
+        self.data_fifo_buffer_index = 0;
+        self.read_data_buffer.clear();
+        self.sector_pending = false;
+        self.fifo_status.remove(FifosStatus::DATA_FIFO_NOT_EMPTY);
     }
 
     fn put_command(&mut self, cmd: u8) {
+        if self.command_start_interrupt_requested {
+            self.command_start_interrupt_requested = false;
+            // `INT10`, not a response, so it doesn't replace `INT1-7`
+            self.interrupt_flag |= 0x10;
+        }
         self.command = Some(cmd);
         self.command_delay_timer = CDROM_COMMAND_DEFAULT_DELAY;
         self.command_state = None;
@@ -1302,7 +2074,20 @@ impl Cdrom {
 
 impl Cdrom {
     fn read_index_status(&self) -> u8 {
-        self.index | self.fifo_status.bits()
+        let mut fifo_status = self.fifo_status;
+        fifo_status.set(
+            FifosStatus::PARAMETER_FIFO_EMPTY,
+            self.parameter_fifo.is_empty(),
+        );
+        fifo_status.set(
+            FifosStatus::PARAMETER_FIFO_NOT_FULL,
+            !self.parameter_fifo.is_full(),
+        );
+        fifo_status.set(
+            FifosStatus::RESPONSE_FIFO_NOT_EMPTY,
+            !self.response_fifo.is_empty(),
+        );
+        self.index | fifo_status.bits()
     }
 
     fn write_interrupt_enable_register(&mut self, data: u8) {
@@ -1323,7 +2108,7 @@ impl Cdrom {
         self.interrupt_flag &= !interrupts_flag_to_ack;
 
         if data & 0x40 != 0 {
-            self.reset_parameter_fifo();
+            self.parameter_fifo.clear();
         }
     }
 
@@ -1336,98 +2121,64 @@ impl Cdrom {
         self.put_command(data)
     }
 
-    fn reset_parameter_fifo(&mut self) {
-        self.fifo_status.insert(FifosStatus::PARAMETER_FIFO_EMPTY);
-        self.fifo_status
-            .insert(FifosStatus::PARAMETER_FIFO_NOT_FULL);
-        self.parameter_fifo.clear();
-    }
-
     fn write_to_parameter_fifo(&mut self, data: u8) {
-        if self.parameter_fifo.is_empty() {
-            self.fifo_status.remove(FifosStatus::PARAMETER_FIFO_EMPTY);
-        } else if self.parameter_fifo.len() == 15 {
-            self.fifo_status
-                .remove(FifosStatus::PARAMETER_FIFO_NOT_FULL);
-        }
         log::info!("2.0 writing to parameter fifo={:02X}", data);
 
-        self.parameter_fifo.push_back(data);
+        // like hardware, the 17th parameter onwards are dropped
+        if !self.parameter_fifo.push(data) {
+            log::warn!("parameter fifo is full, ignoring {:02X}", data);
+        }
     }
 
     fn read_next_parameter(&mut self) -> Option<u8> {
-        let out = self.parameter_fifo.pop_front();
-        if self.parameter_fifo.is_empty() {
-            self.fifo_status.insert(FifosStatus::PARAMETER_FIFO_EMPTY);
-        } else if self.parameter_fifo.len() == 15 {
-            self.fifo_status
-                .insert(FifosStatus::PARAMETER_FIFO_NOT_FULL);
-        }
-
-        out
+        self.parameter_fifo.pop()
     }
 
     fn set_response(&mut self, data: u8) {
-        log::info!("writing to response fifo={:02X}", data);
-        // override the current response if any
-        self.response_fifo.clear();
-        self.response_fifo.push_back(data);
-        self.fifo_status
-            .insert(FifosStatus::RESPONSE_FIFO_NOT_EMPTY);
+        self.set_response_slice(&[data]);
     }
 
     fn set_response_slice(&mut self, data: &[u8]) {
         log::info!("writing to response fifo={:02X?}", data);
         // override the current response if any
-        self.response_fifo.clear();
-        self.response_fifo.extend(data);
-        self.fifo_status
-            .insert(FifosStatus::RESPONSE_FIFO_NOT_EMPTY);
+        if !self.response_fifo.set(data) {
+            log::warn!(
+                "response of {} bytes is truncated to the fifo size",
+                data.len()
+            );
+        }
     }
 
     fn read_next_response(&mut self) -> u8 {
-        let out = self.response_fifo.pop_front();
+        // after the end, this continues with the `0` padding, then
+        // repeats the response from the start
+        let out = self.response_fifo.read();
 
-        log::info!("reading from response fifo={:02X?}", out);
+        log::info!("reading from response fifo={:02X}", out);
 
-        if self.response_fifo.is_empty() {
-            self.fifo_status
-                .remove(FifosStatus::RESPONSE_FIFO_NOT_EMPTY);
-        }
-
-        // Pad with 0x00, until the size of 16 bytes
-        // TODO: currently, the fifo buffer is kinda infinite, so need to be limited to 16 bytes
-        out.unwrap_or(0)
+        out
     }
 
     fn request_interrupt_0_7(&mut self, int_value: u8) {
         self.interrupt_flag &= !0x7;
         self.interrupt_flag |= int_value & 0x7;
+
+        self.tracer.trace(|| TraceEvent::CdromResponse {
+            interrupt: int_value,
+            response: self.response_fifo.response().to_vec(),
+        });
     }
 
     fn write_request_register(&mut self, data: u8) {
         log::info!("3.0 writing to request register value={:02X}", data);
-        // TODO: implement command start interrupt on next command
-        assert!(data & 0x20 == 0);
+        // SMEN
+        if data & 0x20 != 0 {
+            self.command_start_interrupt_requested = true;
+        }
+        // BFRD
         if data & 0x80 != 0 {
-            // want data
-            // this buffer should be set by Read commands
-            if !self.read_data_buffer.is_empty() {
-                log::info!(
-                    "setting data fifo buffer, read buffer len={}",
-                    self.read_data_buffer.len()
-                );
-                self.data_fifo_buffer
-                    .extend_from_slice(&self.read_data_buffer);
-                self.read_data_buffer.clear();
-                self.fifo_status.insert(FifosStatus::DATA_FIFO_NOT_EMPTY);
-            }
-            // This is a hack, as some games would clear and request the buffer 2 times for some reason,
-            // this would result in the buffer being empty, as it was cleared after being filled
-            // so, in that case, we will only clear the buffer if its going to be filled soon.
-            //
-            // FIXME: find a better solution, or find out why the game was doing that
-        } else if !self.read_data_buffer.is_empty() {
+            self.load_data_fifo();
+        } else {
             log::info!(
                 "clearing data fifo buffer, current data fifo len={}",
                 self.data_fifo_buffer.len()
@@ -1435,12 +2186,34 @@ impl Cdrom {
             self.data_fifo_buffer_index = 0;
             self.data_fifo_buffer.clear();
             self.fifo_status.remove(FifosStatus::DATA_FIFO_NOT_EMPTY);
-        } else {
+        }
+    }
+
+    /// Load the data fifo from the sector buffer, if the fifo is not empty, it's
+    /// kept as is. The sector buffer is not changed, so resetting and loading
+    /// again starts the same sector from the beginning
+    fn load_data_fifo(&mut self) {
+        if !self.data_fifo_buffer.is_empty() {
             log::info!(
-                "data fifo buffer was not cleared, current data fifo len={}",
-                self.data_fifo_buffer.len()
+                "data fifo buffer is not empty, len={}",
+                self.data_fifo_buffer.len() - self.data_fifo_buffer_index
             );
+            return;
         }
+        if self.read_data_buffer.is_empty() {
+            log::warn!("data fifo requested, but there is no sector");
+            return;
+        }
+
+        log::info!(
+            "setting data fifo buffer, read buffer len={}",
+            self.read_data_buffer.len()
+        );
+        self.data_fifo_buffer
+            .extend_from_slice(&self.read_data_buffer);
+        self.data_fifo_buffer_index = 0;
+        self.sector_pending = false;
+        self.fifo_status.insert(FifosStatus::DATA_FIFO_NOT_EMPTY);
     }
 
     // TODO: dma should read a buffer directly from here
@@ -1483,10 +2256,16 @@ impl BusLine for Cdrom {
             1 => match self.index {
                 0 => self.write_command_register(data),
                 1 => {
-                    todo!("write 1.1 Sound Map Data Out");
+                    return Err(BusError::DeviceError {
+                        device: "CDROM",
+                        detail: "write 1.1 Sound Map Data Out".into(),
+                    })
                 }
                 2 => {
-                    todo!("write 1.2 Sound Map Coding Info");
+                    return Err(BusError::DeviceError {
+                        device: "CDROM",
+                        detail: "write 1.2 Sound Map Coding Info".into(),
+                    })
                 }
                 3 => {
                     self.input_cd_right_to_spu_right = data;
@@ -1536,3 +2315,1327 @@ impl BusLine for Cdrom {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::interrupts::Interrupts;
+    use crate::trace::{tests::CollectSink, IrqSource};
+
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    /// Counts the allocations of the current thread, only while enabled with
+    /// [`count_allocations`], so that other tests are not affected.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<Option<usize>> = const { Cell::new(None) };
+    }
+
+    fn record_allocation() {
+        let _ = ALLOCATIONS.try_with(|a| a.set(a.get().map(|n| n + 1)));
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            record_allocation();
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            record_allocation();
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn count_allocations(f: impl FnOnce()) -> usize {
+        ALLOCATIONS.with(|a| a.set(Some(0)));
+        f();
+        ALLOCATIONS.with(|a| a.take()).unwrap()
+    }
+
+    /// A cdrom with 3 sectors of pseudo random XA-ADPCM data
+    fn xa_cdrom() -> Cdrom {
+        let mut cdrom = Cdrom::default();
+        let mut seed: u32 = 0x1234_5678;
+        cdrom.disk_data = (0..2352 * 3)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                (seed >> 16) as u8
+            })
+            .collect();
+        cdrom.cd_mute = false;
+        cdrom.vol_cd_left_to_spu_left = 0x80;
+        cdrom.vol_cd_left_to_spu_right = 0x20;
+        cdrom.vol_cd_right_to_spu_left = 0x10;
+        cdrom.vol_cd_right_to_spu_right = 0x80;
+        cdrom
+    }
+
+    const XA_SECTORS: [(usize, u8); 3] = [
+        // 37800Hz, 4bit, stereo
+        (0, 0b0000_0001),
+        // 18900Hz, 4bit, mono
+        (1, 0b0000_0100),
+        // 37800Hz, 8bit, stereo
+        (2, 0b0001_0001),
+    ];
+
+    #[test]
+    fn xa_adpcm_output() {
+        let mut cdrom = xa_cdrom();
+        let mut spu = Spu::default();
+
+        let mut sectors = Vec::new();
+        for (sector, coding_info) in XA_SECTORS {
+            cdrom.deliver_adpcm_to_spu(sector, CodingInfo::from_bits_retain(coding_info), &mut spu);
+            sectors.push(spu.take_cdrom_audio());
+        }
+        let samples = sectors.concat();
+        assert_eq!(
+            sectors.iter().map(|s| s.len()).collect::<Vec<_>>(),
+            [2352, 9408, 1176]
+        );
+
+        let checksum = samples.iter().fold(0u32, |hash, &(l, r)| {
+            let hash = hash.wrapping_mul(31).wrapping_add(l as u16 as u32);
+            hash.wrapping_mul(31).wrapping_add(r as u16 as u32)
+        });
+        // computed from the output before the buffers were reused
+        assert_eq!(checksum, 0xDA16F604);
+    }
+
+    #[test]
+    fn xa_adpcm_no_allocations() {
+        let mut cdrom = xa_cdrom();
+        let mut spu = Spu::default();
+
+        // the first sectors grow the SPU queue, which keeps its capacity after
+        for (sector, coding_info) in XA_SECTORS {
+            cdrom.deliver_adpcm_to_spu(sector, CodingInfo::from_bits_retain(coding_info), &mut spu);
+        }
+        spu.take_cdrom_audio();
+
+        for (sector, coding_info) in XA_SECTORS {
+            let allocations = count_allocations(|| {
+                cdrom.deliver_adpcm_to_spu(
+                    sector,
+                    CodingInfo::from_bits_retain(coding_info),
+                    &mut spu,
+                )
+            });
+            assert_eq!(allocations, 0, "sector {}", sector);
+            spu.take_cdrom_audio();
+        }
+    }
+
+    /// Write the command with its parameters, the responses are read with
+    /// [`next_response`]
+    fn send_command(cdrom: &mut Cdrom, cmd: u8, params: &[u8]) {
+        cdrom.write_u8(0, 0).unwrap();
+        for &param in params {
+            cdrom.write_u8(2, param).unwrap();
+        }
+        cdrom.write_u8(1, cmd).unwrap();
+    }
+
+    /// Clock until the next interrupt (with a limit of CPU cycles), and returns
+    /// the interrupt and its response after acknowledging it
+    fn next_response(
+        cdrom: &mut Cdrom,
+        interrupts: &mut Interrupts,
+        spu: &mut Spu,
+        max_cycles: u32,
+    ) -> Option<(u8, Vec<u8>)> {
+        let mut cycles = 0;
+        while cdrom.interrupt_flag & 7 == 0 {
+            if cycles >= max_cycles {
+                return None;
+            }
+            cdrom.clock(interrupts, spu, 0x100);
+            cycles += 0x100;
+        }
+
+        let mut response = Vec::new();
+        while cdrom.read_u8(0).unwrap() & FifosStatus::RESPONSE_FIFO_NOT_EMPTY.bits() != 0 {
+            response.push(cdrom.read_u8(1).unwrap());
+        }
+        let interrupt = cdrom.interrupt_flag & 7;
+        cdrom.write_u8(0, 1).unwrap();
+        cdrom.write_u8(3, 0x07).unwrap();
+        interrupts.write_u32(0, 0).unwrap();
+        cdrom.write_u8(0, 0).unwrap();
+
+        Some((interrupt, response))
+    }
+
+    /// A disk with `sectors` empty sectors
+    fn empty_disk_cdrom(sectors: usize) -> (Cdrom, Interrupts, Spu) {
+        let mut cdrom = Cdrom::default();
+        cdrom.disk_data = vec![0; 2352 * sectors];
+        cdrom.status.start_motor();
+        // enable all interrupts
+        cdrom.write_u8(0, 1).unwrap();
+        cdrom.write_u8(2, 0x1F).unwrap();
+        (cdrom, Interrupts::default(), Spu::default())
+    }
+
+    #[test]
+    fn play_reports() {
+        let (mut cdrom, mut interrupts, mut spu) = empty_disk_cdrom(300);
+        let mut command = |cdrom: &mut Cdrom, cmd: u8, params: &[u8]| {
+            send_command(cdrom, cmd, params);
+            next_response(cdrom, &mut interrupts, &mut spu, 0x10000).unwrap()
+        };
+
+        // Setmode(report)
+        assert_eq!(command(&mut cdrom, 0x0E, &[0x04]), (3, vec![0x02]));
+        // SetLoc(00:03:70)
+        assert_eq!(
+            command(&mut cdrom, 0x02, &[0x00, 0x03, 0x70]),
+            (3, vec![0x02])
+        );
+        // Play
+        assert_eq!(command(&mut cdrom, 0x03, &[]), (3, vec![0x82]));
+
+        let mut reports = Vec::new();
+        // reports are 10 sectors apart, at single speed
+        while let Some(response) = next_response(
+            &mut cdrom,
+            &mut interrupts,
+            &mut spu,
+            CDROM_READ_PLAY_DELAY * 11,
+        ) {
+            reports.push(response);
+            if reports.len() == 8 {
+                break;
+            }
+        }
+
+        // rel 00:01:70 abs 00:03:70
+        #[rustfmt::skip]
+        let expected = [
+            (1, vec![0x82, 0x01, 0x01, 0x00, 0x81, 0x70, 0, 0]),
+            (1, vec![0x82, 0x01, 0x01, 0x00, 0x04, 0x00, 0, 0]),
+            (1, vec![0x82, 0x01, 0x01, 0x00, 0x82, 0x10, 0, 0]),
+            (1, vec![0x82, 0x01, 0x01, 0x00, 0x04, 0x20, 0, 0]),
+            (1, vec![0x82, 0x01, 0x01, 0x00, 0x82, 0x30, 0, 0]),
+            (1, vec![0x82, 0x01, 0x01, 0x00, 0x04, 0x40, 0, 0]),
+            (1, vec![0x82, 0x01, 0x01, 0x00, 0x82, 0x50, 0, 0]),
+            (1, vec![0x82, 0x01, 0x01, 0x00, 0x04, 0x60, 0, 0]),
+        ];
+        assert_eq!(reports, expected);
+
+        // GetLocP, the last played sector is 00:04:60, so the cursor is after it
+        send_command(&mut cdrom, 0x11, &[]);
+        assert_eq!(
+            next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap(),
+            (3, vec![0x01, 0x01, 0x00, 0x02, 0x61, 0x00, 0x04, 0x61])
+        );
+    }
+
+    #[test]
+    fn play_end_of_track() {
+        let (mut cdrom, mut interrupts, mut spu) = empty_disk_cdrom(20);
+
+        // Play(track 1), without reports
+        send_command(&mut cdrom, 0x03, &[0x01]);
+        assert_eq!(
+            next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap(),
+            (3, vec![0x82])
+        );
+        // DataEnd after all the sectors
+        assert_eq!(
+            next_response(
+                &mut cdrom,
+                &mut interrupts,
+                &mut spu,
+                CDROM_READ_PLAY_DELAY * 22
+            ),
+            Some((4, vec![0x02]))
+        );
+    }
+
+    #[test]
+    fn get_q_toc() {
+        // 2 minutes
+        let (mut cdrom, mut interrupts, mut spu) = empty_disk_cdrom(75 * 120);
+        let mut get_q = |point: u8| {
+            send_command(&mut cdrom, 0x1D, &[0x01, point]);
+            assert_eq!(
+                next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap(),
+                (3, vec![0x02])
+            );
+            next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap()
+        };
+
+        assert_eq!(
+            get_q(0x01),
+            (2, vec![0x41, 0x00, 0x01, 0, 0, 0, 0, 0x00, 0x02, 0x00])
+        );
+        assert_eq!(
+            get_q(0xA0),
+            (2, vec![0x41, 0x00, 0xA0, 0, 0, 0, 0, 0x01, 0x20, 0x00])
+        );
+        assert_eq!(
+            get_q(0xA1),
+            (2, vec![0x41, 0x00, 0xA1, 0, 0, 0, 0, 0x01, 0x00, 0x00])
+        );
+        assert_eq!(
+            get_q(0xA2),
+            (2, vec![0x41, 0x00, 0xA2, 0, 0, 0, 0, 0x02, 0x02, 0x00])
+        );
+        assert_eq!(get_q(0x05), (5, vec![0x03, 0x10]));
+    }
+
+    #[test]
+    fn unknown_command_error() {
+        let (mut cdrom, mut interrupts, mut spu) = empty_disk_cdrom(20);
+        let warnings = Warnings::default();
+        warnings.set_strict(false);
+        cdrom.set_warnings(warnings.clone());
+
+        // VideoCD, only on some drives
+        send_command(&mut cdrom, 0x1F, &[]);
+        assert_eq!(
+            next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap(),
+            (5, vec![0x03, 0x40])
+        );
+        // the drive still works
+        send_command(&mut cdrom, 0x01, &[]);
+        assert_eq!(
+            next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap(),
+            (3, vec![0x02])
+        );
+
+        assert_eq!(warnings.count(), 1);
+        assert_eq!(warnings.list()[0].message, "unknown command 1F");
+        // not supported, but not a crash
+        assert!(cdrom.write_u8(0, 1).is_ok());
+        assert!(cdrom.write_u8(1, 0).is_err());
+    }
+
+    #[test]
+    fn setloc_invalid_parameters() {
+        let (mut cdrom, mut interrupts, mut spu) = empty_disk_cdrom(20);
+
+        send_command(&mut cdrom, 0x02, &[0x00, 0x02, 0x10]);
+        assert_eq!(
+            next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap(),
+            (3, vec![0x02])
+        );
+
+        // not BCD, 60 seconds and 75 sectors
+        for params in [[0x0A, 0x02, 0x00], [0x00, 0x60, 0x00], [0x00, 0x02, 0x75]] {
+            send_command(&mut cdrom, 0x02, &params);
+            assert_eq!(
+                next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap(),
+                (5, vec![0x03, 0x10]),
+                "{:02X?}",
+                params
+            );
+        }
+        // wrong number of parameters
+        send_command(&mut cdrom, 0x02, &[0x00, 0x02]);
+        assert_eq!(
+            next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap(),
+            (5, vec![0x03, 0x20])
+        );
+        assert!(cdrom.parameter_fifo.is_empty());
+
+        // the first one is kept
+        assert_eq!(cdrom.set_loc_params, Some([0, 2, 10]));
+    }
+
+    #[test]
+    fn setloc_random_parameters() {
+        let (mut cdrom, mut interrupts, mut spu) = empty_disk_cdrom(20);
+
+        // xorshift, so that the failures can be reproduced
+        let mut state = 0x2545F491u32;
+        let mut random = || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+
+        for _ in 0..500 {
+            let count = random() % 5;
+            let params = (0..count).map(|_| random() as u8).collect::<Vec<_>>();
+            // SetLoc, then SeekL to use it
+            send_command(&mut cdrom, 0x02, &params);
+            let (interrupt, _) =
+                next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap();
+            assert!(interrupt == 3 || interrupt == 5);
+            send_command(&mut cdrom, 0x15, &[]);
+            next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap();
+            next_response(&mut cdrom, &mut interrupts, &mut spu, 0x100000).unwrap();
+        }
+    }
+
+    fn index_status(cdrom: &mut Cdrom) -> FifosStatus {
+        FifosStatus::from_bits_truncate(cdrom.read_u8(0).unwrap())
+    }
+
+    #[test]
+    fn parameter_fifo_overflow() {
+        let (mut cdrom, mut interrupts, mut spu) = empty_disk_cdrom(20);
+        cdrom.write_u8(0, 0).unwrap();
+        let status = index_status(&mut cdrom);
+        assert!(status.contains(FifosStatus::PARAMETER_FIFO_EMPTY));
+        assert!(status.contains(FifosStatus::PARAMETER_FIFO_NOT_FULL));
+
+        for i in 0..15 {
+            cdrom.write_u8(2, i).unwrap();
+            let status = index_status(&mut cdrom);
+            assert!(!status.contains(FifosStatus::PARAMETER_FIFO_EMPTY));
+            assert!(status.contains(FifosStatus::PARAMETER_FIFO_NOT_FULL));
+        }
+        // the 16th write fills it
+        cdrom.write_u8(2, 15).unwrap();
+        assert!(!index_status(&mut cdrom).contains(FifosStatus::PARAMETER_FIFO_NOT_FULL));
+        // and the rest are dropped
+        for i in 16..20 {
+            cdrom.write_u8(2, i).unwrap();
+        }
+        assert!(!index_status(&mut cdrom).contains(FifosStatus::PARAMETER_FIFO_NOT_FULL));
+        assert_eq!(
+            cdrom.parameter_fifo.iter().collect::<Vec<_>>(),
+            (0..16).collect::<Vec<_>>()
+        );
+
+        // SetLoc(00:01:02) takes the first 3, the rest are cleared after the command
+        cdrom.parameter_fifo.clear();
+        send_command(&mut cdrom, 0x02, &(0..20).collect::<Vec<_>>());
+        assert_eq!(
+            next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap(),
+            (3, vec![0x02])
+        );
+        let status = index_status(&mut cdrom);
+        assert!(status.contains(FifosStatus::PARAMETER_FIFO_EMPTY));
+        assert!(status.contains(FifosStatus::PARAMETER_FIFO_NOT_FULL));
+
+        // the interrupt flag register can clear it too
+        for i in 0..16 {
+            cdrom.write_u8(2, i).unwrap();
+        }
+        cdrom.write_u8(0, 1).unwrap();
+        cdrom.write_u8(3, 0x40).unwrap();
+        let status = index_status(&mut cdrom);
+        assert!(status.contains(FifosStatus::PARAMETER_FIFO_EMPTY));
+        assert!(status.contains(FifosStatus::PARAMETER_FIFO_NOT_FULL));
+    }
+
+    #[test]
+    fn response_fifo_underflow() {
+        let (mut cdrom, mut interrupts, mut spu) = empty_disk_cdrom(20);
+        assert!(!index_status(&mut cdrom).contains(FifosStatus::RESPONSE_FIFO_NOT_EMPTY));
+
+        // Test(20h), the BIOS date
+        send_command(&mut cdrom, 0x19, &[0x20]);
+        cycles_until_interrupt(&mut cdrom, &mut interrupts, &mut spu);
+        let response = [0x99, 0x02, 0x01, 0xC3];
+        for (i, &value) in response.iter().enumerate() {
+            assert!(index_status(&mut cdrom).contains(FifosStatus::RESPONSE_FIFO_NOT_EMPTY));
+            assert_eq!(cdrom.read_u8(1).unwrap(), value, "byte {}", i);
+        }
+        assert!(!index_status(&mut cdrom).contains(FifosStatus::RESPONSE_FIFO_NOT_EMPTY));
+
+        // padded with zeros to 16 bytes, then the same response again
+        let mut padded = response.to_vec();
+        padded.resize(16, 0);
+        for _ in 0..2 {
+            for &value in &padded {
+                assert_eq!(cdrom.read_u8(1).unwrap(), value);
+            }
+        }
+        assert!(!index_status(&mut cdrom).contains(FifosStatus::RESPONSE_FIFO_NOT_EMPTY));
+    }
+
+    #[test]
+    fn response_fifo_truncates() {
+        let mut cdrom = Cdrom::default();
+        let long = (1..=20).collect::<Vec<u8>>();
+        cdrom.set_response_slice(&long);
+
+        for &value in &long[..16] {
+            assert!(index_status(&mut cdrom).contains(FifosStatus::RESPONSE_FIFO_NOT_EMPTY));
+            assert_eq!(cdrom.read_u8(1).unwrap(), value);
+        }
+        assert!(!index_status(&mut cdrom).contains(FifosStatus::RESPONSE_FIFO_NOT_EMPTY));
+        // the 17th byte was dropped, this is the first one again
+        assert_eq!(cdrom.read_u8(1).unwrap(), 1);
+    }
+
+    /// Clock until the next interrupt, returns the number of cycles it took
+    fn cycles_until_interrupt(
+        cdrom: &mut Cdrom,
+        interrupts: &mut Interrupts,
+        spu: &mut Spu,
+    ) -> u32 {
+        let mut cycles = 0;
+        while cdrom.interrupt_flag & 7 == 0 {
+            cdrom.clock(interrupts, spu, 0x100);
+            cycles += 0x100;
+            assert!(cycles < 33868800 * 2, "no interrupt after 2 seconds");
+        }
+        cycles
+    }
+
+    /// `SeekL` from `from` to `to` (msf in bcd), returns the cycles between
+    /// the first and second responses
+    fn seek_duration(timing: CdromSeekTiming, from: [u8; 3], to: [u8; 3]) -> u32 {
+        let (mut cdrom, mut interrupts, mut spu) = empty_disk_cdrom(0);
+        cdrom.seek_timing = timing;
+
+        let mut duration = 0;
+        for msf in [from, to] {
+            send_command(&mut cdrom, 0x02, &msf);
+            next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap();
+            send_command(&mut cdrom, 0x15, &[]);
+            // seeking
+            assert_eq!(
+                next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap(),
+                (3, vec![0x42])
+            );
+
+            duration = cycles_until_interrupt(&mut cdrom, &mut interrupts, &mut spu);
+            assert_eq!(
+                next_response(&mut cdrom, &mut interrupts, &mut spu, 0).unwrap(),
+                (2, vec![0x02])
+            );
+        }
+        duration
+    }
+
+    #[test]
+    fn seek_timing_distance() {
+        let start = [0x00, 0x02, 0x00];
+        let accurate_short = seek_duration(CdromSeekTiming::Accurate, start, [0x00, 0x02, 0x01]);
+        let accurate_long = seek_duration(CdromSeekTiming::Accurate, start, [0x70, 0x00, 0x00]);
+        let fast_long = seek_duration(CdromSeekTiming::Fast, start, [0x70, 0x00, 0x00]);
+        let instant_long = seek_duration(CdromSeekTiming::Instant, start, [0x70, 0x00, 0x00]);
+
+        assert!(accurate_short >= SEEK_BASE_CYCLES);
+        assert!(accurate_long > accurate_short + 33868800 / 2);
+        assert!(fast_long < accurate_long && fast_long > accurate_long / 8);
+        // only the command delay
+        assert!(instant_long <= CDROM_COMMAND_DEFAULT_DELAY + 0x100);
+    }
+
+    #[test]
+    fn read_after_seek() {
+        let (mut cdrom, mut interrupts, mut spu) = empty_disk_cdrom(2000);
+        cdrom.seek_timing = CdromSeekTiming::Accurate;
+        let seek_cycles = CdromSeekTiming::Accurate.seek_cycles(0, 1900);
+
+        // SetLoc(00:27:25), sector 1900
+        send_command(&mut cdrom, 0x02, &[0x00, 0x27, 0x25]);
+        next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap();
+        // ReadN
+        send_command(&mut cdrom, 0x06, &[]);
+        assert_eq!(
+            next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap(),
+            (3, vec![0x42])
+        );
+
+        // GetStat while seeking
+        send_command(&mut cdrom, 0x01, &[]);
+        assert_eq!(
+            next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap(),
+            (3, vec![0x42])
+        );
+
+        // no interrupts until the seek finishes and the first sector is read
+        let cycles = cycles_until_interrupt(&mut cdrom, &mut interrupts, &mut spu);
+        assert!(cycles + 2 * CDROM_COMMAND_DEFAULT_DELAY >= seek_cycles + CDROM_READ_PLAY_DELAY);
+        let (interrupt, response) =
+            next_response(&mut cdrom, &mut interrupts, &mut spu, 0).unwrap();
+        assert_eq!((interrupt, response[0]), (1, 0x22));
+        assert_eq!(cdrom.cursor_sector_position, 1901);
+    }
+
+    /// A disk where the first data byte of each sector is its number
+    fn numbered_disk_cdrom(sectors: usize) -> (Cdrom, Interrupts, Spu) {
+        let (mut cdrom, interrupts, spu) = empty_disk_cdrom(sectors);
+        for i in 0..sectors {
+            cdrom.disk_data[i * 2352 + 24] = i as u8;
+        }
+        (cdrom, interrupts, spu)
+    }
+
+    /// Request the data of the delivered sector, returns its number
+    fn read_sector_number(cdrom: &mut Cdrom) -> u8 {
+        cdrom.write_u8(0, 0).unwrap();
+        cdrom.write_u8(3, 0x80).unwrap();
+        let data = (0..0x800)
+            .map(|_| cdrom.read_u8(2).unwrap())
+            .collect::<Vec<_>>();
+        data[0]
+    }
+
+    #[test]
+    fn setloc_during_read_is_latched() {
+        let (mut cdrom, mut interrupts, mut spu) = numbered_disk_cdrom(100);
+        let command = |cdrom: &mut Cdrom, interrupts: &mut Interrupts, cmd, params: &[u8]| {
+            send_command(cdrom, cmd, params);
+            next_response(cdrom, interrupts, &mut Spu::default(), 0x10000).unwrap()
+        };
+        let mut next_sector = |cdrom: &mut Cdrom, interrupts: &mut Interrupts| {
+            let (interrupt, _) =
+                next_response(cdrom, interrupts, &mut spu, CDROM_READ_PLAY_DELAY * 2).unwrap();
+            assert_eq!(interrupt, 1);
+            read_sector_number(cdrom)
+        };
+
+        // SetLoc(00:02:10), ReadN
+        command(&mut cdrom, &mut interrupts, 0x02, &[0x00, 0x02, 0x10]);
+        command(&mut cdrom, &mut interrupts, 0x06, &[]);
+        assert_eq!(next_sector(&mut cdrom, &mut interrupts), 10);
+        assert_eq!(next_sector(&mut cdrom, &mut interrupts), 11);
+
+        // SetLoc(00:02:50), only latched, the reading continues
+        command(&mut cdrom, &mut interrupts, 0x02, &[0x00, 0x02, 0x50]);
+        assert_eq!(next_sector(&mut cdrom, &mut interrupts), 12);
+        assert_eq!(next_sector(&mut cdrom, &mut interrupts), 13);
+
+        // the next ReadN seeks
+        command(&mut cdrom, &mut interrupts, 0x06, &[]);
+        assert_eq!(next_sector(&mut cdrom, &mut interrupts), 50);
+        assert_eq!(next_sector(&mut cdrom, &mut interrupts), 51);
+
+        // Pause, then ReadN without SetLoc continues from the position
+        command(&mut cdrom, &mut interrupts, 0x09, &[]);
+        next_response(&mut cdrom, &mut interrupts, &mut Spu::default(), 0x10000).unwrap();
+        command(&mut cdrom, &mut interrupts, 0x06, &[]);
+        assert_eq!(next_sector(&mut cdrom, &mut interrupts), 52);
+    }
+
+    /// Request the data of the delivered sector, returns all of it
+    fn read_sector_data(cdrom: &mut Cdrom) -> Vec<u8> {
+        cdrom.write_u8(0, 0).unwrap();
+        cdrom.write_u8(3, 0x80).unwrap();
+        let mut data = Vec::new();
+        while cdrom.read_u8(0).unwrap() & FifosStatus::DATA_FIFO_NOT_EMPTY.bits() != 0 {
+            data.push(cdrom.read_u8(2).unwrap());
+        }
+        data
+    }
+
+    #[test]
+    fn setmode_during_read() {
+        let (mut cdrom, mut interrupts, mut spu) = numbered_disk_cdrom(100);
+        // returns the cycles until the next sector, its number and size
+        let mut next_sector = |cdrom: &mut Cdrom, interrupts: &mut Interrupts| {
+            let cycles = cycles_until_interrupt(cdrom, interrupts, &mut spu);
+            let (interrupt, _) = next_response(cdrom, interrupts, &mut spu, 0).unwrap();
+            assert_eq!(interrupt, 1);
+            let data = read_sector_data(cdrom);
+            // the number is the first byte after the sub header
+            let number = if data.len() == 0x924 {
+                data[12]
+            } else {
+                data[0]
+            };
+            (cycles, number, data.len())
+        };
+        let setmode = |cdrom: &mut Cdrom, interrupts: &mut Interrupts, mode: u8| {
+            send_command(cdrom, 0x0E, &[mode]);
+            let (interrupt, _) =
+                next_response(cdrom, interrupts, &mut Spu::default(), 0x10000).unwrap();
+            assert_eq!(interrupt, 3);
+        };
+
+        // SetLoc(00:02:10), ReadN
+        send_command(&mut cdrom, 0x02, &[0x00, 0x02, 0x10]);
+        next_response(&mut cdrom, &mut interrupts, &mut Spu::default(), 0x10000).unwrap();
+        send_command(&mut cdrom, 0x06, &[]);
+        next_response(&mut cdrom, &mut interrupts, &mut Spu::default(), 0x10000).unwrap();
+        let (_, number, size) = next_sector(&mut cdrom, &mut interrupts);
+        assert_eq!((number, size), (10, 0x800));
+
+        // the sector being read keeps the old size
+        setmode(&mut cdrom, &mut interrupts, 0x20);
+        let (_, number, size) = next_sector(&mut cdrom, &mut interrupts);
+        assert_eq!((number, size), (11, 0x800));
+        let (cycles, number, size) = next_sector(&mut cdrom, &mut interrupts);
+        assert_eq!((number, size), (12, 0x924));
+        assert!(cycles.abs_diff(CDROM_READ_PLAY_DELAY) <= 0x200);
+
+        // the ignore bit without whole sector
+        setmode(&mut cdrom, &mut interrupts, 0x10);
+        let (_, number, size) = next_sector(&mut cdrom, &mut interrupts);
+        assert_eq!((number, size), (13, 0x924));
+        let (_, number, size) = next_sector(&mut cdrom, &mut interrupts);
+        assert_eq!((number, size), (14, 0x918));
+
+        // the speed changes the time left for the current sector immediately
+        setmode(&mut cdrom, &mut interrupts, 0x80);
+        let (cycles, number, size) = next_sector(&mut cdrom, &mut interrupts);
+        assert_eq!((number, size), (15, 0x918));
+        assert!(cycles < CDROM_READ_PLAY_DELAY * 3 / 4);
+        let (cycles, number, size) = next_sector(&mut cdrom, &mut interrupts);
+        assert_eq!((number, size), (16, 0x800));
+        assert!(cycles.abs_diff(CDROM_READ_PLAY_DELAY / 2) <= 0x200);
+
+        // and back
+        setmode(&mut cdrom, &mut interrupts, 0x00);
+        let (cycles, number, _) = next_sector(&mut cdrom, &mut interrupts);
+        assert_eq!(number, 17);
+        assert!(cycles > CDROM_READ_PLAY_DELAY * 3 / 4);
+        let (cycles, number, _) = next_sector(&mut cdrom, &mut interrupts);
+        assert_eq!(number, 18);
+        assert!(cycles.abs_diff(CDROM_READ_PLAY_DELAY) <= 0x200);
+    }
+
+    #[test]
+    fn getparam_and_getlocl() {
+        let (mut cdrom, mut interrupts, mut spu) = numbered_disk_cdrom(100);
+        // data sectors of file 1, channel 3
+        for i in 0..100 {
+            let (minutes, seconds, sector) = sector_to_msf(i + PREGAP_SECTORS);
+            let header = [
+                to_bcd(minutes),
+                to_bcd(seconds),
+                to_bcd(sector),
+                2,
+                1,
+                3,
+                0x08,
+                0,
+            ];
+            cdrom.disk_data[i * 2352 + 12..i * 2352 + 20].copy_from_slice(&header);
+        }
+        let command =
+            |cdrom: &mut Cdrom, interrupts: &mut Interrupts, spu: &mut Spu, cmd, params: &[u8]| {
+                send_command(cdrom, cmd, params);
+                next_response(cdrom, interrupts, spu, 0x10000).unwrap()
+            };
+
+        // Setmode(double speed, XA filter), Setfilter(1, 3)
+        command(&mut cdrom, &mut interrupts, &mut spu, 0x0E, &[0x88]);
+        command(&mut cdrom, &mut interrupts, &mut spu, 0x0D, &[1, 3]);
+        assert_eq!(
+            command(&mut cdrom, &mut interrupts, &mut spu, 0x0F, &[]),
+            (3, vec![0x02, 0x88, 0x00, 1, 3])
+        );
+
+        // nothing was read yet
+        assert_eq!(
+            command(&mut cdrom, &mut interrupts, &mut spu, 0x10, &[]),
+            (5, vec![0x03, 0x80])
+        );
+
+        // SetLoc(00:02:10), ReadN
+        command(
+            &mut cdrom,
+            &mut interrupts,
+            &mut spu,
+            0x02,
+            &[0x00, 0x02, 0x10],
+        );
+        assert_eq!(
+            command(&mut cdrom, &mut interrupts, &mut spu, 0x06, &[]),
+            (3, vec![0x22])
+        );
+        let (interrupt, _) = next_response(
+            &mut cdrom,
+            &mut interrupts,
+            &mut spu,
+            CDROM_READ_PLAY_DELAY * 2,
+        )
+        .unwrap();
+        assert_eq!(interrupt, 1);
+        assert_eq!(read_sector_number(&mut cdrom), 10);
+        assert_eq!(
+            command(&mut cdrom, &mut interrupts, &mut spu, 0x10, &[]),
+            (3, vec![0x00, 0x02, 0x10, 2, 1, 3, 0x08, 0])
+        );
+
+        // no header while seeking to another sector
+        cdrom.seek_timing = CdromSeekTiming::Accurate;
+        command(
+            &mut cdrom,
+            &mut interrupts,
+            &mut spu,
+            0x02,
+            &[0x00, 0x03, 0x00],
+        );
+        assert_eq!(
+            command(&mut cdrom, &mut interrupts, &mut spu, 0x06, &[]),
+            (3, vec![0x42])
+        );
+        assert_eq!(
+            command(&mut cdrom, &mut interrupts, &mut spu, 0x10, &[]),
+            (5, vec![0x43, 0x80])
+        );
+    }
+
+    #[test]
+    fn readn_while_reading() {
+        let (mut cdrom, mut interrupts, mut spu) = numbered_disk_cdrom(100);
+
+        send_command(&mut cdrom, 0x06, &[]);
+        next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap();
+        let (interrupt, _) = next_response(
+            &mut cdrom,
+            &mut interrupts,
+            &mut spu,
+            CDROM_READ_PLAY_DELAY * 2,
+        )
+        .unwrap();
+        assert_eq!(interrupt, 1);
+
+        // ReadN again, before taking the sector
+        send_command(&mut cdrom, 0x06, &[]);
+        assert_eq!(
+            next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap(),
+            (3, vec![0x22])
+        );
+        // not lost
+        assert_eq!(read_sector_number(&mut cdrom), 0);
+
+        // and not delivered again
+        for expected in 1..4 {
+            let (interrupt, _) = next_response(
+                &mut cdrom,
+                &mut interrupts,
+                &mut spu,
+                CDROM_READ_PLAY_DELAY * 2,
+            )
+            .unwrap();
+            assert_eq!(interrupt, 1);
+            assert_eq!(read_sector_number(&mut cdrom), expected);
+        }
+    }
+
+    /// `BFRD` reset and load twice, like some games do
+    #[test]
+    fn request_data_twice() {
+        let (mut cdrom, mut interrupts, mut spu) = numbered_disk_cdrom(100);
+
+        send_command(&mut cdrom, 0x06, &[]);
+        next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap();
+        for expected in 0..3 {
+            let (interrupt, _) = next_response(
+                &mut cdrom,
+                &mut interrupts,
+                &mut spu,
+                CDROM_READ_PLAY_DELAY * 2,
+            )
+            .unwrap();
+            assert_eq!(interrupt, 1);
+
+            cdrom.write_u8(0, 0).unwrap();
+            cdrom.write_u8(3, 0x00).unwrap();
+            cdrom.write_u8(3, 0x80).unwrap();
+            cdrom.write_u8(3, 0x00).unwrap();
+            cdrom.write_u8(3, 0x80).unwrap();
+            // the fifo is not empty, so this does nothing
+            cdrom.write_u8(3, 0x80).unwrap();
+
+            // the sector exactly once
+            let data = read_sector_data(&mut cdrom);
+            assert_eq!(data.len(), 0x800);
+            assert_eq!(data[0], expected);
+        }
+    }
+
+    #[test]
+    fn command_start_interrupt() {
+        let (mut cdrom, mut interrupts, mut spu) = empty_disk_cdrom(10);
+        let cdrom_irq = |interrupts: &mut Interrupts| interrupts.read_u32(0).unwrap() & 4 != 0;
+
+        // SMEN
+        cdrom.write_u8(0, 0).unwrap();
+        cdrom.write_u8(3, 0x20).unwrap();
+        cdrom.write_u8(0, 1).unwrap();
+        assert_eq!(cdrom.read_u8(3).unwrap(), 0xE0);
+
+        // GetStat
+        send_command(&mut cdrom, 0x01, &[]);
+        cdrom.write_u8(0, 1).unwrap();
+        assert_eq!(cdrom.read_u8(3).unwrap(), 0xF0);
+        cdrom.clock(&mut interrupts, &mut spu, 0x10);
+        assert!(cdrom_irq(&mut interrupts));
+
+        // acknowledge `INT10`, the response is not affected
+        cdrom.write_u8(3, 0x10).unwrap();
+        interrupts.write_u32(0, 0).unwrap();
+        cdrom.write_u8(0, 0).unwrap();
+        assert_eq!(
+            next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap(),
+            (3, vec![0x02])
+        );
+
+        // only for the next command
+        interrupts.write_u32(0, 0).unwrap();
+        send_command(&mut cdrom, 0x01, &[]);
+        cdrom.clock(&mut interrupts, &mut spu, 0x10);
+        assert!(!cdrom_irq(&mut interrupts));
+        assert_eq!(cdrom.interrupt_flag, 0);
+    }
+
+    #[test]
+    fn audio_volumes() {
+        let mut cdrom = Cdrom::default();
+        cdrom.write_u8(0, 2).unwrap();
+        cdrom.write_u8(2, 0x80).unwrap();
+        cdrom.write_u8(3, 0x20).unwrap();
+        cdrom.write_u8(0, 3).unwrap();
+        cdrom.write_u8(1, 0x40).unwrap();
+        cdrom.write_u8(2, 0x10).unwrap();
+
+        // only applied with the apply register
+        assert_eq!(cdrom.audio_volumes(), CdAudioVolumes::default());
+        cdrom.write_u8(3, 0x21).unwrap();
+        assert_eq!(
+            cdrom.audio_volumes(),
+            CdAudioVolumes {
+                left_to_left: 0x80,
+                left_to_right: 0x20,
+                right_to_left: 0x10,
+                right_to_right: 0x40,
+                adpcm_muted: true,
+            }
+        );
+
+        // write only, these are the response fifo and the interrupt flag
+        assert_ne!(cdrom.read_u8(1).unwrap(), 0x40);
+        assert_eq!(cdrom.read_u8(3).unwrap(), 0xE0);
+    }
+
+    #[test]
+    fn init_stops_reading_and_keeps_setloc() {
+        let (mut cdrom, mut interrupts, mut spu) = numbered_disk_cdrom(100);
+        let command = |cdrom: &mut Cdrom, interrupts: &mut Interrupts, cmd, params: &[u8]| {
+            send_command(cdrom, cmd, params);
+            next_response(cdrom, interrupts, &mut Spu::default(), 0x10000).unwrap()
+        };
+
+        // Setmode(double speed), SetLoc(00:02:10), ReadN
+        command(&mut cdrom, &mut interrupts, 0x0E, &[0x80]);
+        command(&mut cdrom, &mut interrupts, 0x02, &[0x00, 0x02, 0x10]);
+        command(&mut cdrom, &mut interrupts, 0x06, &[]);
+        let (interrupt, _) =
+            next_response(&mut cdrom, &mut interrupts, &mut spu, CDROM_READ_PLAY_DELAY).unwrap();
+        assert_eq!(interrupt, 1);
+        assert_eq!(read_sector_number(&mut cdrom), 10);
+
+        // SetLoc(00:02:50), latched while reading, then Init
+        command(&mut cdrom, &mut interrupts, 0x02, &[0x00, 0x02, 0x50]);
+        // the status after stopping, then the second response
+        assert_eq!(
+            command(&mut cdrom, &mut interrupts, 0x0A, &[]),
+            (3, vec![0x02])
+        );
+        assert_eq!(
+            next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap(),
+            (2, vec![0x02])
+        );
+        // no more sectors
+        assert_eq!(
+            next_response(
+                &mut cdrom,
+                &mut interrupts,
+                &mut spu,
+                CDROM_READ_PLAY_DELAY * 2
+            ),
+            None
+        );
+        assert_eq!(
+            command(&mut cdrom, &mut interrupts, 0x0F, &[]),
+            (3, vec![0x02, 0x20, 0x00, 0, 0])
+        );
+
+        // Setmode(0), the next ReadN seeks to the SetLoc before Init
+        command(&mut cdrom, &mut interrupts, 0x0E, &[0x00]);
+        command(&mut cdrom, &mut interrupts, 0x06, &[]);
+        let (interrupt, _) = next_response(
+            &mut cdrom,
+            &mut interrupts,
+            &mut spu,
+            CDROM_READ_PLAY_DELAY * 2,
+        )
+        .unwrap();
+        assert_eq!(interrupt, 1);
+        assert_eq!(read_sector_number(&mut cdrom), 50);
+    }
+
+    #[test]
+    fn reset_is_silent_and_clears_mode() {
+        let (mut cdrom, mut interrupts, mut spu) = numbered_disk_cdrom(100);
+        let command = |cdrom: &mut Cdrom, interrupts: &mut Interrupts, cmd, params: &[u8]| {
+            send_command(cdrom, cmd, params);
+            next_response(cdrom, interrupts, &mut Spu::default(), 0x10000).unwrap()
+        };
+
+        // Setmode(double speed), ReadN
+        command(&mut cdrom, &mut interrupts, 0x0E, &[0x80]);
+        command(&mut cdrom, &mut interrupts, 0x06, &[]);
+        let (interrupt, _) =
+            next_response(&mut cdrom, &mut interrupts, &mut spu, CDROM_READ_PLAY_DELAY).unwrap();
+        assert_eq!(interrupt, 1);
+
+        // Reset, the parameter is ignored, the status is from before the reset
+        assert_eq!(
+            command(&mut cdrom, &mut interrupts, 0x1C, &[0x12]),
+            (3, vec![0x22])
+        );
+        assert!(index_status(&mut cdrom).contains(FifosStatus::BUSY));
+        assert_eq!(cdrom.status.bits(), 0);
+        assert!(cdrom.read_data_buffer.is_empty());
+
+        // no interrupt during or after the reset
+        assert_eq!(
+            next_response(
+                &mut cdrom,
+                &mut interrupts,
+                &mut spu,
+                CDROM_RESET_DELAY - 0x4000
+            ),
+            None
+        );
+        assert!(index_status(&mut cdrom).contains(FifosStatus::BUSY));
+        assert_eq!(
+            next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000),
+            None
+        );
+        assert!(!index_status(&mut cdrom).contains(FifosStatus::BUSY));
+
+        // the motor is on again, not reading, and the mode is cleared
+        assert_eq!(
+            command(&mut cdrom, &mut interrupts, 0x01, &[]),
+            (3, vec![0x02])
+        );
+        assert_eq!(
+            command(&mut cdrom, &mut interrupts, 0x0F, &[]),
+            (3, vec![0x02, 0x00, 0x00, 0, 0])
+        );
+    }
+
+    #[test]
+    fn trace_command_sequence() {
+        let sink = CollectSink::default();
+        let tracer = Tracer::new(Box::new(sink.clone()));
+
+        let mut cdrom = Cdrom::default();
+        let mut interrupts = Interrupts::default();
+        let mut spu = Spu::default();
+        cdrom.set_tracer(tracer.clone());
+        interrupts.set_tracer(tracer);
+
+        // enable all interrupts
+        cdrom.write_u8(0, 1).unwrap();
+        cdrom.write_u8(2, 0x1F).unwrap();
+
+        // GetStat
+        cdrom.write_u8(0, 0).unwrap();
+        cdrom.write_u8(1, 0x01).unwrap();
+        cdrom.clock(&mut interrupts, &mut spu, CDROM_COMMAND_DEFAULT_DELAY);
+
+        // acknowledge
+        cdrom.write_u8(0, 1).unwrap();
+        cdrom.write_u8(3, 0x07).unwrap();
+        interrupts.write_u32(0, 0).unwrap();
+
+        // Test(20h), get version
+        cdrom.write_u8(0, 0).unwrap();
+        cdrom.write_u8(2, 0x20).unwrap();
+        cdrom.write_u8(1, 0x19).unwrap();
+        cdrom.clock(&mut interrupts, &mut spu, CDROM_COMMAND_DEFAULT_DELAY);
+
+        let events = sink.0.lock().unwrap();
+        assert_eq!(
+            *events,
+            [
+                TraceEvent::CdromCommand {
+                    cmd: 0x01,
+                    params: vec![]
+                },
+                TraceEvent::CdromResponse {
+                    interrupt: 3,
+                    // motor on
+                    response: vec![0x02]
+                },
+                TraceEvent::IrqRaised {
+                    source: IrqSource::Cdrom
+                },
+                TraceEvent::CdromCommand {
+                    cmd: 0x19,
+                    params: vec![0x20]
+                },
+                TraceEvent::CdromResponse {
+                    interrupt: 3,
+                    response: vec![0x99, 0x02, 0x01, 0xC3]
+                },
+                TraceEvent::IrqRaised {
+                    source: IrqSource::Cdrom
+                },
+            ]
+        );
+    }
+
+    fn licensed_disk_cdrom(license: &[u8]) -> (Cdrom, Interrupts, Spu) {
+        let (mut cdrom, interrupts, spu) = empty_disk_cdrom(16);
+        let mut data = std::mem::take(&mut cdrom.disk_data);
+        let start = LICENSE_SECTOR * 2352 + 24 + 0x20;
+        data[start..start + license.len()].copy_from_slice(license);
+        cdrom.set_disk_data(data);
+        (cdrom, interrupts, spu)
+    }
+
+    #[test]
+    fn cue_parsing() {
+        let cue =
+            "FILE \"My Game (Track 1).bin\" BINARY\n  TRACK 01 MODE2/2352\n    INDEX 01 00:00:00\n";
+        let sheet = parse_cue(cue).unwrap();
+        assert_eq!(sheet.bin_file_name, "My Game (Track 1).bin");
+        assert_eq!(sheet.first_track, TrackMode::Mode2);
+
+        for invalid in [
+            "",
+            "FILE game.bin BINARY",
+            "FILE \"game.bin BINARY",
+            "FILE \"game.bin\" BINARY TRACK 01 AUDIO",
+        ] {
+            assert!(parse_cue(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn disk_bytes_survive_reset() {
+        let cue = "FILE \"game.bin\" BINARY TRACK 01 MODE2/2352 INDEX 01 00:00:00";
+        let mut data = vec![0; 2352 * 16];
+        let start = LICENSE_SECTOR * 2352 + 24 + 0x20;
+        let license = b"Licensed  by          Sony Computer Entertainment Euro pe   ";
+        data[start..start + license.len()].copy_from_slice(license);
+
+        let mut cdrom = Cdrom::default();
+        assert!(cdrom
+            .set_disk_bytes("FILE game.bin".to_string(), data.clone())
+            .is_err());
+        assert!(cdrom.disk_data.is_empty());
+
+        cdrom.set_disk_bytes(cue.to_string(), data.clone()).unwrap();
+        cdrom.reset().unwrap();
+        assert_eq!(cdrom.disk_data, data);
+        assert_eq!(cdrom.disk_region(), Some(Region::Europe));
+        assert!(cdrom.status.bit_status.contains(BitCdromStatus::MOTOR_ON));
+    }
+
+    #[test]
+    fn reset_while_reading() {
+        let (mut cdrom, mut interrupts, mut spu) = numbered_disk_cdrom(100);
+        cdrom.cue_file_content =
+            "FILE \"game.bin\" BINARY TRACK 01 MODE2/2352 INDEX 01 00:00:00".to_string();
+        let disk_data = cdrom.disk_data.clone();
+
+        // SetLoc(00:02:10), ReadN, then reset with a sector in the data FIFO
+        send_command(&mut cdrom, 0x02, &[0x00, 0x02, 0x10]);
+        next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap();
+        send_command(&mut cdrom, 0x06, &[]);
+        next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap();
+        next_response(
+            &mut cdrom,
+            &mut interrupts,
+            &mut spu,
+            CDROM_READ_PLAY_DELAY * 2,
+        )
+        .unwrap();
+        cdrom.write_u8(0, 0).unwrap();
+        cdrom.write_u8(3, 0x80).unwrap();
+        cdrom.change_cdrom_shell_open_state(true);
+
+        cdrom.reset().unwrap();
+        assert_eq!(cdrom.disk_data, disk_data);
+        assert_eq!(cdrom.cursor_sector_position, 0);
+        assert_eq!(cdrom.status.action_status, ActionStatus::None);
+        assert!(cdrom.data_fifo_buffer.is_empty());
+        assert_eq!(cdrom.interrupt_flag, 0);
+        assert_eq!(cdrom.interrupt_enable, 0);
+        // the shell is still open
+        assert!(cdrom.status.shell_open);
+        assert!(cdrom.status.bit_status.contains(BitCdromStatus::SHELL_OPEN));
+
+        // a broken cue can't be loaded again
+        cdrom.cue_file_content.clear();
+        assert!(cdrom.reset().is_err());
+    }
+
+    #[test]
+    fn get_id_region() {
+        let licenses: [(&[u8], _, _); 4] = [
+            (
+                b"Licensed  by          Sony Computer Entertainment Inc.",
+                Some(Region::Japan),
+                b'I',
+            ),
+            (
+                b"Licensed  by          Sony Computer Entertainment Amer  ica ",
+                Some(Region::NorthAmerica),
+                b'A',
+            ),
+            (
+                b"Licensed  by          Sony Computer Entertainment Euro pe   ",
+                Some(Region::Europe),
+                b'E',
+            ),
+            (b"Homebrew", None, b'A'),
+        ];
+
+        for (license, region, region_byte) in licenses {
+            let (mut cdrom, mut interrupts, mut spu) = licensed_disk_cdrom(license);
+            assert_eq!(cdrom.disk_region(), region);
+
+            send_command(&mut cdrom, 0x1A, &[]);
+            let first = next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap();
+            assert_eq!(first, (3, vec![0x02]));
+            let (interrupt, response) =
+                next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap();
+            assert_eq!(interrupt, 2);
+            assert_eq!(
+                response,
+                [0x02, 0x00, 0x20, 0x00, b'S', b'C', b'E', region_byte]
+            );
+        }
+    }
+
+    #[test]
+    fn swap_disk() {
+        let cue = "FILE \"game.bin\" BINARY TRACK 01 MODE2/2352 INDEX 01 00:00:00";
+        let (mut cdrom, mut interrupts, mut spu) =
+            licensed_disk_cdrom(b"Licensed  by          Sony Computer Entertainment Inc.");
+        let (mut europe, _, _) =
+            licensed_disk_cdrom(b"Licensed  by          Sony Computer Entertainment Euro pe   ");
+        let europe_data = std::mem::take(&mut europe.disk_data);
+
+        // can't swap with the shell closed
+        assert!(cdrom
+            .swap_disk_bytes(cue.to_string(), europe_data.clone())
+            .is_err());
+        assert_eq!(cdrom.disk_region(), Some(Region::Japan));
+
+        cdrom.change_cdrom_shell_open_state(true);
+        cdrom.swap_disk_bytes(cue.to_string(), europe_data).unwrap();
+        cdrom.change_cdrom_shell_open_state(false);
+
+        // the first GetStat reports the shell was opened, then it's cleared
+        send_command(&mut cdrom, 0x01, &[]);
+        let (interrupt, response) =
+            next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap();
+        assert_eq!(interrupt, 5);
+        assert_ne!(response[0] & BitCdromStatus::SHELL_OPEN.bits(), 0);
+        send_command(&mut cdrom, 0x01, &[]);
+        let second = next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap();
+        assert_eq!(second, (3, vec![0x02]));
+
+        send_command(&mut cdrom, 0x1A, &[]);
+        let first = next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap();
+        assert_eq!(first, (3, vec![0x02]));
+        let (interrupt, response) =
+            next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap();
+        assert_eq!(interrupt, 2);
+        assert_eq!(response, [0x02, 0x00, 0x20, 0x00, b'S', b'C', b'E', b'E']);
+    }
+
+    /// A disk with a minimal ISO9660 filesystem, with `SYSTEM.CNF` in the root
+    /// directory if `system_cnf` is given
+    fn iso_disk_cdrom(system_cnf: Option<&[u8]>) -> Cdrom {
+        let (mut cdrom, _, _) = empty_disk_cdrom(24);
+        let mut data = std::mem::take(&mut cdrom.disk_data);
+        fn sector(data: &mut [u8], n: usize) -> &mut [u8] {
+            &mut data[n * 2352 + 24..n * 2352 + 24 + 0x800]
+        }
+
+        let directory_record = |sector: u32, size: u32, name: &[u8]| {
+            let mut record = vec![0; 33 + name.len() + (name.len() + 1) % 2];
+            record[0] = record.len() as u8;
+            record[2..6].copy_from_slice(&sector.to_le_bytes());
+            record[10..14].copy_from_slice(&size.to_le_bytes());
+            record[32] = name.len() as u8;
+            record[33..33 + name.len()].copy_from_slice(name);
+            record
+        };
+
+        let volume_descriptor = sector(&mut data, VOLUME_DESCRIPTOR_SECTOR);
+        volume_descriptor[0] = 1;
+        volume_descriptor[1..6].copy_from_slice(b"CD001");
+        volume_descriptor[156..156 + 34].copy_from_slice(&directory_record(18, 0x800, &[0]));
+
+        let mut directory = Vec::new();
+        directory.extend(directory_record(18, 0x800, &[0]));
+        directory.extend(directory_record(18, 0x800, &[1]));
+        directory.extend(directory_record(21, 0x800, b"DATA.BIN;1"));
+        if let Some(system_cnf) = system_cnf {
+            let size = system_cnf.len() as u32;
+            directory.extend(directory_record(20, size, b"SYSTEM.CNF;1"));
+            sector(&mut data, 20)[..system_cnf.len()].copy_from_slice(system_cnf);
+        }
+        sector(&mut data, 18)[..directory.len()].copy_from_slice(&directory);
+
+        cdrom.set_disk_data(data);
+        cdrom
+    }
+
+    #[test]
+    fn disk_id_from_system_cnf() {
+        let cdrom = iso_disk_cdrom(Some(b"BOOT = cdrom:\\SLUS_007.71;1\r\nTCB = 4\r\n"));
+        assert_eq!(cdrom.disk_id(), Some("SLUS_007.71"));
+
+        let cdrom = iso_disk_cdrom(Some(b"BOOT=cdrom:SCES_012.34;1"));
+        assert_eq!(cdrom.disk_id(), Some("SCES_012.34"));
+
+        let cdrom = iso_disk_cdrom(Some(b"TCB = 4\r\n"));
+        assert_eq!(cdrom.disk_id(), None);
+
+        let cdrom = iso_disk_cdrom(None);
+        assert_eq!(cdrom.disk_id(), None);
+        assert_eq!(empty_disk_cdrom(24).0.disk_id(), None);
+    }
+
+    #[test]
+    fn get_id_disk_types() {
+        let data_cue = "FILE \"game.bin\" BINARY TRACK 01 MODE2/2352 INDEX 01 00:00:00";
+        let audio_cue = "FILE \"music.bin\" BINARY TRACK 01 AUDIO INDEX 01 00:00:00";
+        let no_disk = [0x08, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let audio = [0x0A, 0x90, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let modchip = [0x02, 0x00, 0x20, 0x00, b'S', b'C', b'E', b'A'];
+
+        let disks = [
+            (None, None, (5, no_disk)),
+            (Some(audio_cue), Some(DiskType::Audio), (5, audio)),
+            (Some(data_cue), Some(DiskType::Unlicensed), (2, modchip)),
+        ];
+        for (cue, disk_type, (interrupt, response)) in disks {
+            let (mut cdrom, mut interrupts, mut spu) = empty_disk_cdrom(16);
+            let data = std::mem::take(&mut cdrom.disk_data);
+            cdrom.status.stop_motor();
+            if let Some(cue) = cue {
+                cdrom.set_disk_bytes(cue.to_string(), data).unwrap();
+            }
+            assert_eq!(cdrom.disk_type(), disk_type);
+            assert_eq!(cdrom.disk_region(), None);
+
+            send_command(&mut cdrom, 0x1A, &[]);
+            let (first, _) = next_response(&mut cdrom, &mut interrupts, &mut spu, 0x10000).unwrap();
+            assert_eq!(first, 3);
+
+            let delay = cycles_until_interrupt(&mut cdrom, &mut interrupts, &mut spu);
+            assert!((CDROM_GETID_DELAY..CDROM_GETID_DELAY + 0x200).contains(&delay));
+            assert_eq!(
+                next_response(&mut cdrom, &mut interrupts, &mut spu, 0).unwrap(),
+                (interrupt, response.to_vec())
+            );
+        }
+    }
+}
@@ -0,0 +1,142 @@
+/// The size of both the parameter and the response FIFOs
+pub(super) const FIFO_SIZE: usize = 16;
+
+/// The parameters of the next command, written through `1F801802h` (index 0).
+///
+/// Writes to a full FIFO are ignored.
+#[derive(Default)]
+pub(super) struct ParameterFifo {
+    data: [u8; FIFO_SIZE],
+    start: usize,
+    len: usize,
+}
+
+impl ParameterFifo {
+    pub(super) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub(super) fn is_full(&self) -> bool {
+        self.len == FIFO_SIZE
+    }
+
+    /// Returns `false` if the FIFO is full and `value` was dropped
+    pub(super) fn push(&mut self, value: u8) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.data[(self.start + self.len) % FIFO_SIZE] = value;
+        self.len += 1;
+        true
+    }
+
+    pub(super) fn pop(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        let value = self.data[self.start];
+        self.start = (self.start + 1) % FIFO_SIZE;
+        self.len -= 1;
+        Some(value)
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.start = 0;
+        self.len = 0;
+    }
+
+    pub(super) fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..self.len).map(|i| self.data[(self.start + i) % FIFO_SIZE])
+    }
+}
+
+/// The response of the last command, read through `1F801801h`.
+///
+/// On hardware, the response is padded with `0` to 16 bytes, reading past
+/// the end continues from the first byte, so the same 16 bytes repeat until
+/// the next response. Responses longer than 16 bytes are truncated.
+#[derive(Default)]
+pub(super) struct ResponseFifo {
+    data: [u8; FIFO_SIZE],
+    len: usize,
+    position: usize,
+    /// The response bytes not read yet, `RESPONSE_FIFO_NOT_EMPTY` is cleared
+    /// after reading the last one, and doesn't come back when wrapping around.
+    remaining: usize,
+}
+
+impl ResponseFifo {
+    pub(super) fn is_empty(&self) -> bool {
+        self.remaining == 0
+    }
+
+    /// Replace the current response (if any), returns `false` if `response`
+    /// was truncated.
+    pub(super) fn set(&mut self, response: &[u8]) -> bool {
+        let len = response.len().min(FIFO_SIZE);
+        self.data = [0; FIFO_SIZE];
+        self.data[..len].copy_from_slice(&response[..len]);
+        self.len = len;
+        self.position = 0;
+        self.remaining = len;
+        len == response.len()
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.set(&[]);
+    }
+
+    pub(super) fn read(&mut self) -> u8 {
+        let value = self.data[self.position];
+        self.position = (self.position + 1) % FIFO_SIZE;
+        self.remaining = self.remaining.saturating_sub(1);
+        value
+    }
+
+    /// The whole response, including the bytes already read
+    pub(super) fn response(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parameter_ring_buffer() {
+        let mut fifo = ParameterFifo::default();
+        for i in 0..10 {
+            assert!(fifo.push(i));
+        }
+        for i in 0..8 {
+            assert_eq!(fifo.pop(), Some(i));
+        }
+        // wraps around the end of the buffer
+        for i in 10..24 {
+            assert!(fifo.push(i));
+        }
+        assert!(fifo.is_full());
+        assert!(!fifo.push(24));
+        assert_eq!(fifo.iter().collect::<Vec<_>>(), (8..24).collect::<Vec<_>>());
+        for i in 8..24 {
+            assert_eq!(fifo.pop(), Some(i));
+        }
+        assert!(fifo.is_empty());
+        assert_eq!(fifo.pop(), None);
+    }
+
+    #[test]
+    fn response_truncated() {
+        let mut fifo = ResponseFifo::default();
+        let long = (1..=20).collect::<Vec<u8>>();
+        assert!(!fifo.set(&long));
+        assert_eq!(fifo.response(), &long[..FIFO_SIZE]);
+        for &value in &long[..FIFO_SIZE] {
+            assert!(!fifo.is_empty());
+            assert_eq!(fifo.read(), value);
+        }
+        assert!(fifo.is_empty());
+        assert_eq!(fifo.read(), 1);
+    }
+}
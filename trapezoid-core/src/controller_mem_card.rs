@@ -1,9 +1,12 @@
-use crate::memory::{interrupts::InterruptRequester, BusLine, Result};
+use crate::memory::{interrupts::InterruptRequester, BusError, BusLine, Result};
+use crate::state_hash::StateHasher;
+use crate::warnings::Warnings;
 use bitflags::bitflags;
 
 use std::collections::VecDeque;
 
-#[derive(Clone, Copy)]
+/// A button of the digital controller
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DigitalControllerKey {
     Select,
     L3,
@@ -24,11 +27,180 @@ pub enum DigitalControllerKey {
 }
 
 impl DigitalControllerKey {
-    fn mask(&self) -> u16 {
+    /// All the keys, in the order of their bits in [`DigitalControllerKey::mask`]
+    pub const ALL: [Self; 16] = [
+        Self::Select,
+        Self::L3,
+        Self::R3,
+        Self::Start,
+        Self::Up,
+        Self::Right,
+        Self::Down,
+        Self::Left,
+        Self::L2,
+        Self::R2,
+        Self::L1,
+        Self::R1,
+        Self::Triangle,
+        Self::Circle,
+        Self::X,
+        Self::Square,
+    ];
+
+    /// The bit of the key in the buttons the controller sends (where `0` is pressed)
+    pub fn mask(&self) -> u16 {
         1 << *self as u16
     }
 }
 
+/// A stick of the analog controller
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnalogStick {
+    Left,
+    Right,
+}
+
+impl AnalogStick {
+    /// The index of the X axis in the 4 axes bytes the controller sends,
+    /// which are `RightX, RightY, LeftX, LeftY`
+    fn axes_index(self) -> usize {
+        match self {
+            AnalogStick::Right => 0,
+            AnalogStick::Left => 2,
+        }
+    }
+}
+
+/// How the stick position after the deadzone maps to the position the game sees
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AnalogCurve {
+    /// The same as the input
+    #[default]
+    Linear,
+    /// `x^2`, finer control near the center
+    Quadratic,
+    /// `x^3`, even finer control near the center
+    Cubic,
+}
+
+impl AnalogCurve {
+    /// `x` is in `0.0..=1.0`
+    fn apply(self, x: f32) -> f32 {
+        match self {
+            AnalogCurve::Linear => x,
+            AnalogCurve::Quadratic => x * x,
+            AnalogCurve::Cubic => x * x * x,
+        }
+    }
+}
+
+/// The transform from the host axis value of a stick to the byte the game reads,
+/// see [`Psx::set_analog_config`](crate::Psx::set_analog_config).
+///
+/// Each axis is transformed on its own, the distance from the center is
+/// rescaled so that `deadzone` is `0.0` and `saturation` is `1.0`, then it goes
+/// through the `response_curve`.
+///
+/// The default is the identity, `-1.0`, `0.0` and `1.0` are `00h`, `80h` and `FFh`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnalogConfig {
+    /// The distance from the center (`0.0..1.0`) read as the center
+    pub deadzone: f32,
+    /// The distance from the center (up to `1.0`, more than `deadzone`) read as
+    /// the edge
+    pub saturation: f32,
+    pub response_curve: AnalogCurve,
+}
+
+impl Default for AnalogConfig {
+    fn default() -> Self {
+        Self {
+            deadzone: 0.0,
+            saturation: 1.0,
+            response_curve: AnalogCurve::Linear,
+        }
+    }
+}
+
+impl AnalogConfig {
+    pub(crate) fn is_valid(&self) -> bool {
+        (0.0..1.0).contains(&self.deadzone)
+            && self.saturation > self.deadzone
+            && self.saturation <= 1.0
+    }
+
+    /// The byte the game reads for the host axis value `value`, from `-1.0`
+    /// (left or up) to `1.0` (right or down), values outside are clamped and
+    /// `NaN` is the center.
+    ///
+    /// ```
+    /// # use trapezoid_core::{AnalogConfig, AnalogCurve};
+    /// let config = AnalogConfig {
+    ///     deadzone: 0.25,
+    ///     saturation: 0.75,
+    ///     response_curve: AnalogCurve::Linear,
+    /// };
+    /// assert_eq!(config.axis_byte(0.2), 0x80);
+    /// assert_eq!(config.axis_byte(0.5), 0xC0);
+    /// assert_eq!(config.axis_byte(-0.95), 0x00);
+    /// ```
+    pub fn axis_byte(&self, value: f32) -> u8 {
+        let distance = value.abs();
+        if value.is_nan() || distance <= self.deadzone {
+            return 0x80;
+        }
+        let scaled = ((distance - self.deadzone) / (self.saturation - self.deadzone)).min(1.0);
+        let position = self.response_curve.apply(scaled);
+
+        // `80h` is the center, there are 127 steps to the right and 128 to the left
+        if value > 0.0 {
+            0x80 + (position * 127.0).round() as u8
+        } else {
+            0x80 - (position * 128.0).round() as u8
+        }
+    }
+}
+
+/// When key changes from the host are made visible to the emulated controller.
+///
+/// Changes are kept pending until the latch point, so a poll sequence always
+/// reads the buttons from the same state.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum InputLatchMode {
+    /// Latch at the start of every vblank
+    VBlank,
+    /// Latch when the game starts a new poll sequence with the controller
+    #[default]
+    PollStart,
+}
+
+/// The CPU cycles from the end of a byte transfer to the `/ACK` of a controller
+/// (or the multitap), around `10us`
+const CONTROLLER_ACK_DELAY: u32 = 338;
+/// The CPU cycles from the end of a byte transfer to the `/ACK` of a memory card,
+/// they answer faster than the controllers
+const MEMORY_CARD_ACK_DELAY: u32 = 170;
+
+/// When the controllers and memory cards acknowledge a byte, see
+/// [`PsxConfig::controller_ack_timing`](crate::PsxConfig::controller_ack_timing)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AckTiming {
+    /// Right at the end of the byte transfer
+    Fast,
+    /// After the delay of the device, like on hardware
+    #[default]
+    Accurate,
+}
+
+impl AckTiming {
+    fn ack_delay(self, device_delay: u32) -> u32 {
+        match self {
+            AckTiming::Fast => 0,
+            AckTiming::Accurate => device_delay,
+        }
+    }
+}
+
 const JOY_CTRL_ACKKNOWLEDGE: u16 = 0b0000000000010000;
 const JOY_CTRL_RESET: u16 = 0b0000000001000000;
 bitflags! {
@@ -123,7 +295,18 @@ bitflags! {
     }
 }
 
+/// The devices ignore the bytes they don't use, the games send `0` or a flag
+/// in them, anything else is only logged
+fn check_input(inp: u8, valid: bool) {
+    if !valid {
+        log::warn!("Unexpected byte {:02X} from the game, ignoring it", inp);
+    }
+}
+
 mod controller {
+    use super::{check_input, AnalogConfig, AnalogStick, InputLatchMode};
+    use crate::warnings::Warnings;
+
     #[derive(Debug, Clone, Copy)]
     pub enum ControllerMode {
         ReadButtons,
@@ -141,11 +324,26 @@ mod controller {
         Unknown4010,
     }
 
-    /// Emulate Digital pad controller communication
+    /// The ID of the digital pad
+    const DIGITAL_DEVICE_ID: u16 = 0x5A41;
+    /// The ID of the analog pad in analog (red LED) mode
+    const ANALOG_DEVICE_ID: u16 = 0x5A73;
+
+    /// Emulate Digital pad controller communication, or the analog pad
+    /// in analog mode, which also sends the 4 axes of the sticks
     pub struct Controller {
         state: u8,
         device_id: u16,
         digital_switches: u16,
+        /// Key changes from the host, not yet visible to the game
+        pending_digital_switches: u16,
+        /// `RightX, RightY, LeftX, LeftY`, `80h` is the center
+        analog_axes: [u8; 4],
+        /// Stick changes from the host, after the [`AnalogConfig`] transform
+        pending_analog_axes: [u8; 4],
+        /// The config of the left and right sticks, indexed by [`AnalogStick::axes_index`] / 2
+        analog_configs: [AnalogConfig; 2],
+        input_latch_mode: InputLatchMode,
         connected: bool,
         current_mode: ControllerMode,
         in_config: bool,
@@ -156,6 +354,8 @@ mod controller {
         /// Internal value with many purposes in the input state flow
         /// Used to store a value that may be used later in the flow
         cache_value: u8,
+
+        warnings: Warnings,
     }
 
     impl Controller {
@@ -164,27 +364,105 @@ mod controller {
                 state: 0,
                 in_config: false,
                 current_mode: ControllerMode::ReadButtons,
-                device_id: 0x5A41,        // digital controller
+                device_id: DIGITAL_DEVICE_ID,
                 digital_switches: 0xFFFF, // all released
+                pending_digital_switches: 0xFFFF,
+                analog_axes: [0x80; 4], // centered
+                pending_analog_axes: [0x80; 4],
+                analog_configs: [AnalogConfig::default(); 2],
+                input_latch_mode: InputLatchMode::default(),
                 connected,
 
                 led: false,
                 rumble_config: [0xFF; 6],
                 cache_value: 0,
+
+                warnings: Warnings::default(),
             }
         }
 
+        pub fn set_warnings(&mut self, warnings: Warnings) {
+            self.warnings = warnings;
+        }
+
+        /// No response ends the communication, like a command the pad doesn't know
+        fn unsupported_command(&mut self, inp: u8) -> (u8, bool) {
+            self.warnings
+                .warn("Controller", format!("unsupported command {:02X}", inp));
+            self.state = 0;
+            (0xFF, true)
+        }
+
+        pub fn set_connected(&mut self, connected: bool) {
+            self.connected = connected;
+            if !connected {
+                // release all keys, so they don't get stuck when connected again
+                self.digital_switches = 0xFFFF;
+                self.pending_digital_switches = 0xFFFF;
+                self.analog_axes = [0x80; 4];
+                self.pending_analog_axes = [0x80; 4];
+            }
+        }
+
+        pub fn set_analog(&mut self, analog: bool) {
+            self.device_id = if analog {
+                ANALOG_DEVICE_ID
+            } else {
+                DIGITAL_DEVICE_ID
+            };
+        }
+
+        fn is_analog(&self) -> bool {
+            self.device_id == ANALOG_DEVICE_ID
+        }
+
+        pub fn set_analog_config(&mut self, stick: AnalogStick, config: AnalogConfig) {
+            self.analog_configs[stick.axes_index() / 2] = config;
+        }
+
+        /// Returns the bytes the game will read
+        pub fn change_analog_state(&mut self, stick: AnalogStick, x: f32, y: f32) -> [u8; 2] {
+            let config = &self.analog_configs[stick.axes_index() / 2];
+            let bytes = [config.axis_byte(x), config.axis_byte(y)];
+            self.change_analog_bytes(stick, bytes);
+            bytes
+        }
+
+        pub fn change_analog_bytes(&mut self, stick: AnalogStick, bytes: [u8; 2]) {
+            let index = stick.axes_index();
+            self.pending_analog_axes[index..index + 2].copy_from_slice(&bytes);
+        }
+
+        fn latch(&mut self) {
+            self.digital_switches = self.pending_digital_switches;
+            self.analog_axes = self.pending_analog_axes;
+        }
+
+        pub fn set_input_latch_mode(&mut self, mode: InputLatchMode) {
+            self.input_latch_mode = mode;
+        }
+
         pub fn change_key_state(&mut self, key: super::DigitalControllerKey, pressed: bool) {
             let mask = key.mask();
 
             if pressed {
-                self.digital_switches &= !mask;
+                self.pending_digital_switches &= !mask;
             } else {
-                self.digital_switches |= mask;
+                self.pending_digital_switches |= mask;
+            }
+        }
+
+        pub fn vblank(&mut self) {
+            if self.input_latch_mode == InputLatchMode::VBlank {
+                self.latch();
             }
         }
 
         pub fn start_access(&mut self) -> u8 {
+            if self.input_latch_mode == InputLatchMode::PollStart {
+                self.latch();
+            }
+
             if self.connected {
                 self.state = 1;
                 0
@@ -199,7 +477,7 @@ mod controller {
                     self.current_mode = match inp {
                         0x42 => ControllerMode::ReadButtons,
                         0x43 => ControllerMode::Config,
-                        _ => todo!("Controller first input {:02X} is not supported", inp),
+                        _ => return self.unsupported_command(inp),
                     };
 
                     self.state = 2;
@@ -209,7 +487,7 @@ mod controller {
                     // if `inp == 1`, then `multitap` is enabled
                     // but this is not a multitap controller, so will return
                     // the normal `device id`
-                    assert!(inp == 0 || inp == 1);
+                    check_input(inp, inp <= 1);
                     self.state = 3;
                     (((self.device_id >> 8) & 0xFF) as u8, false)
                 }
@@ -219,7 +497,7 @@ mod controller {
                             // TODO: handle rumble
                         }
                         ControllerMode::Config => {
-                            assert!(inp == 1 || inp == 0);
+                            check_input(inp, inp <= 1);
                             self.cache_value = inp;
                         }
                         _ => unreachable!(),
@@ -233,19 +511,40 @@ mod controller {
                             // TODO: handle rumble
                         }
                         ControllerMode::Config => {
-                            assert_eq!(inp, 0);
-                            self.in_config = self.cache_value == 1;
+                            check_input(inp, inp == 0);
                         }
                         _ => unreachable!(),
                     }
-                    self.state = 0;
-                    (((self.digital_switches >> 8) & 0xFF) as u8, true)
+                    // the analog pad sends the sticks after the buttons
+                    let done = !self.is_analog();
+                    self.state = if done { 0 } else { 5 };
+                    if done {
+                        self.end_normal_transfer();
+                    }
+                    (((self.digital_switches >> 8) & 0xFF) as u8, done)
+                }
+                5..=8 => {
+                    check_input(inp, inp == 0);
+                    let axis = self.analog_axes[self.state as usize - 5];
+                    let done = self.state == 8;
+                    self.state = if done { 0 } else { self.state + 1 };
+                    if done {
+                        self.end_normal_transfer();
+                    }
+                    (axis, done)
                 }
-                // TODO: handle for analog input
                 _ => unreachable!(),
             }
         }
 
+        /// Enter the config mode only after the last byte, the whole transfer
+        /// is answered in the normal mode
+        fn end_normal_transfer(&mut self) {
+            if let ControllerMode::Config = self.current_mode {
+                self.in_config = self.cache_value == 1;
+            }
+        }
+
         fn exchange_bytes_config(&mut self, inp: u8) -> (u8, bool) {
             match self.state {
                 1 => {
@@ -260,7 +559,7 @@ mod controller {
                         0x48 => ControllerMode::Unknown4010,
                         0x4C => ControllerMode::GetVariableResponseB,
                         0x4D => ControllerMode::SetRumble,
-                        _ => todo!("unknown controller mode: {:02X}", inp),
+                        _ => return self.unsupported_command(inp),
                     };
 
                     self.state = 2;
@@ -270,7 +569,7 @@ mod controller {
                     // if `inp == 1`, then `multitap` is enabled
                     // but this is not a multitap controller, so will return
                     // the normal `device id`
-                    assert!(inp == 0 || inp == 1);
+                    check_input(inp, inp <= 1);
                     self.state = 3;
                     (0x5A, false)
                 }
@@ -281,17 +580,17 @@ mod controller {
                             (self.digital_switches & 0xFF) as u8
                         }
                         ControllerMode::Config => {
-                            assert!(inp == 1 || inp == 0);
+                            check_input(inp, inp <= 1);
                             self.cache_value = inp;
                             0
                         }
                         ControllerMode::SetLed => {
-                            assert!(inp == 1 || inp == 0);
+                            check_input(inp, inp <= 1);
                             self.cache_value = inp;
                             0
                         }
                         ControllerMode::GetLed => {
-                            assert!(inp == 0);
+                            check_input(inp, inp == 0);
                             1
                         }
                         ControllerMode::GetVariableResponseA => {
@@ -310,7 +609,7 @@ mod controller {
                         ControllerMode::GetWhateverValues
                         | ControllerMode::Unknown60
                         | ControllerMode::Unknown4010 => {
-                            assert!(inp == 0);
+                            check_input(inp, inp == 0);
                             0
                         }
                         ControllerMode::SetRumble => {
@@ -329,7 +628,7 @@ mod controller {
                             ((self.digital_switches >> 8) & 0xFF) as u8
                         }
                         ControllerMode::Config => {
-                            assert_eq!(inp, 0);
+                            check_input(inp, inp == 0);
                             0
                         }
                         ControllerMode::SetLed => {
@@ -342,7 +641,7 @@ mod controller {
                             0
                         }
                         ControllerMode::GetLed => {
-                            assert!(inp == 0);
+                            check_input(inp, inp == 0);
                             2
                         }
                         ControllerMode::GetVariableResponseA
@@ -350,7 +649,7 @@ mod controller {
                         | ControllerMode::GetWhateverValues
                         | ControllerMode::Unknown60
                         | ControllerMode::Unknown4010 => {
-                            assert!(inp == 0);
+                            check_input(inp, inp == 0);
                             0
                         }
                         ControllerMode::SetRumble => {
@@ -471,6 +770,9 @@ mod controller {
 mod memcard {
     use std::{fmt::Write, fs};
 
+    use super::check_input;
+    use crate::state_hash::StateHasher;
+
     #[derive(Debug, Clone, Copy, PartialEq)]
     pub enum CardReadStage {
         Command,
@@ -546,6 +848,12 @@ mod memcard {
             }
         }
 
+        /// The data is loaded from the `memcard<id>.mcd` file, so it's an input
+        /// of the emulation like the BIOS and the disk
+        pub fn hash_state(&self, hasher: &mut StateHasher) {
+            hasher.write(&self.data[..]);
+        }
+
         pub fn start_access(&mut self) -> u8 {
             log::trace!("Memory card {} started access", self.id);
             self.stage = CardReadStage::Command;
@@ -570,7 +878,7 @@ mod memcard {
                     (self.flag, false)
                 }
                 CardReadStage::MemoryCardId1 => {
-                    assert_eq!(inp, 0);
+                    check_input(inp, inp == 0);
                     self.stage = CardReadStage::MemoryCardId2;
                     // In case of invalid command, the communication is aborted
                     // with 0xFF
@@ -583,7 +891,7 @@ mod memcard {
                     }
                 }
                 CardReadStage::MemoryCardId2 => {
-                    assert_eq!(inp, 0);
+                    check_input(inp, inp == 0);
                     match self.cmd {
                         CardCmd::Read | CardCmd::Write => {
                             self.stage = CardReadStage::SendAddressMsb;
@@ -625,7 +933,7 @@ mod memcard {
                     (self.previous, false)
                 }
                 CardReadStage::ConfirmAddressMsb => {
-                    assert_eq!(inp, 0);
+                    check_input(inp, inp == 0);
 
                     self.stage = CardReadStage::ConfirmAddressLsb;
                     // invalid address
@@ -636,7 +944,7 @@ mod memcard {
                     }
                 }
                 CardReadStage::ConfirmAddressLsb => {
-                    assert_eq!(inp, 0);
+                    check_input(inp, inp == 0);
                     // invalid address
                     if self.status == 0xFF {
                         self.stage = CardReadStage::Command;
@@ -650,7 +958,7 @@ mod memcard {
                 CardReadStage::Data => {
                     let r = match self.cmd {
                         CardCmd::Read => {
-                            assert_eq!(inp, 0);
+                            check_input(inp, inp == 0);
                             let addr = self.address as usize * 128 + self.read_pointer as usize;
                             let data = self.data[addr];
                             self.checksum ^= data;
@@ -700,7 +1008,7 @@ mod memcard {
                 }
                 CardReadStage::Checksum => match self.cmd {
                     CardCmd::Read => {
-                        assert_eq!(inp, 0);
+                        check_input(inp, inp == 0);
                         self.stage = CardReadStage::End;
                         self.status = 0x47; // Good
                         (self.checksum, false)
@@ -721,13 +1029,13 @@ mod memcard {
                     _ => unreachable!("Id command cannot send/recv Checksum"),
                 },
                 CardReadStage::CommandAck1 => {
-                    assert_eq!(inp, 0);
+                    check_input(inp, inp == 0);
                     // late /ACK after this byte-pair on Read command
                     self.stage = CardReadStage::CommandAck2;
                     (0x5C, false)
                 }
                 CardReadStage::CommandAck2 => {
-                    assert_eq!(inp, 0);
+                    check_input(inp, inp == 0);
                     match self.cmd {
                         CardCmd::Read => {
                             self.stage = CardReadStage::ConfirmAddressMsb;
@@ -744,7 +1052,7 @@ mod memcard {
                     (0x5D, false)
                 }
                 CardReadStage::End => {
-                    assert_eq!(inp, 0);
+                    check_input(inp, inp == 0);
 
                     // if we finished a write command successfully, flush it to disk.
                     if let CardCmd::Write = self.cmd {
@@ -755,22 +1063,22 @@ mod memcard {
                     (0x4 | self.status, true)
                 }
                 CardReadStage::CmdIdEnd1 => {
-                    assert_eq!(inp, 0);
+                    check_input(inp, inp == 0);
                     self.stage = CardReadStage::CmdIdEnd2;
                     (0x04, false)
                 }
                 CardReadStage::CmdIdEnd2 => {
-                    assert_eq!(inp, 0);
+                    check_input(inp, inp == 0);
                     self.stage = CardReadStage::CmdIdEnd3;
                     (0x00, false)
                 }
                 CardReadStage::CmdIdEnd3 => {
-                    assert_eq!(inp, 0);
+                    check_input(inp, inp == 0);
                     self.stage = CardReadStage::CmdIdEnd4;
                     (0x00, false)
                 }
                 CardReadStage::CmdIdEnd4 => {
-                    assert_eq!(inp, 0);
+                    check_input(inp, inp == 0);
                     self.stage = CardReadStage::Command;
                     (0x80, true)
                 }
@@ -787,9 +1095,98 @@ mod memcard {
             r
         }
 
-        /// Saves the data to disk
+        /// Saves the data to disk, not fatal, the game can still keep using the card
+        /// (e.g. on targets without a filesystem)
         fn flush(&mut self) {
-            fs::write(format!("memcard{}.mcd", self.id), &self.data[..]).unwrap();
+            if let Err(e) = fs::write(format!("memcard{}.mcd", self.id), &self.data[..]) {
+                log::warn!("Could not save memory card {}: {}", self.id, e);
+            }
+        }
+    }
+}
+
+mod multitap {
+    use super::controller::Controller;
+
+    /// `5A80h` ID, and 8 bytes for each of the pads A-D
+    const ALL_PADS_RESPONSE_LEN: usize = 2 + 8 * 4;
+
+    /// Emulate the multitap adapter.
+    ///
+    /// Transfers are forwarded to pad A, but when a poll sends `01h` as the 3rd byte,
+    /// the next poll is answered by the multitap itself with the 4 pads in one transfer.
+    pub struct Multitap {
+        /// Selected by the 3rd byte of the last poll
+        next_all_pads: bool,
+        /// This transfer is answered with the 4 pads
+        all_pads: bool,
+        /// The number of bytes received after the address byte
+        position: usize,
+        response: [u8; ALL_PADS_RESPONSE_LEN],
+    }
+
+    impl Multitap {
+        pub fn new() -> Self {
+            Self {
+                next_all_pads: false,
+                all_pads: false,
+                position: 0,
+                response: [0xFF; ALL_PADS_RESPONSE_LEN],
+            }
+        }
+
+        pub fn start_access(&mut self, pads: &mut [Controller; 4]) -> u8 {
+            self.position = 0;
+            self.all_pads = self.next_all_pads;
+
+            if self.all_pads {
+                self.response[..2].copy_from_slice(&[0x80, 0x5A]);
+                for (pad, slot) in pads.iter_mut().zip(self.response[2..].chunks_mut(8)) {
+                    read_slot(pad, slot);
+                }
+                0
+            } else {
+                pads[0].start_access()
+            }
+        }
+
+        pub fn exchange_bytes(&mut self, inp: u8, pads: &mut [Controller; 4]) -> (u8, bool) {
+            let position = self.position;
+            self.position += 1;
+            if position == 1 {
+                self.next_all_pads = inp == 0x01;
+            }
+
+            if self.all_pads {
+                if position == 0 && inp != 0x42 {
+                    log::warn!(
+                        "Multitap: only reading the pads is supported, got {:02X}",
+                        inp
+                    );
+                }
+                (
+                    self.response[position],
+                    position == ALL_PADS_RESPONSE_LEN - 1,
+                )
+            } else {
+                pads[0].exchange_bytes(inp)
+            }
+        }
+    }
+
+    /// Read the buttons of `pad` into its 8 bytes of the 4 pads transfer,
+    /// a missing pad, and the unused bytes are `FFh`
+    fn read_slot(pad: &mut Controller, slot: &mut [u8]) {
+        slot.fill(0xFF);
+        if pad.start_access() == 0xFF {
+            return;
+        }
+        for (i, out) in slot.iter_mut().enumerate() {
+            let (data, done) = pad.exchange_bytes(if i == 0 { 0x42 } else { 0x00 });
+            *out = data;
+            if done {
+                break;
+            }
         }
     }
 }
@@ -798,8 +1195,10 @@ mod memcard {
 struct CommunicationHandler {
     /// which component we are communicating with now
     state: u8,
-    controller: controller::Controller,
+    /// The pads A-D, only pad A is used without a multitap
+    controllers: [controller::Controller; 4],
     memory_card: memcard::MemoryCard,
+    multitap: Option<multitap::Multitap>,
 }
 
 impl CommunicationHandler {
@@ -807,8 +1206,14 @@ impl CommunicationHandler {
     fn new(id: u8, controller_connected: bool) -> Self {
         Self {
             state: 0,
-            controller: controller::Controller::new(controller_connected),
+            controllers: [
+                controller::Controller::new(controller_connected),
+                controller::Controller::new(false),
+                controller::Controller::new(false),
+                controller::Controller::new(false),
+            ],
             memory_card: memcard::MemoryCard::new(id),
+            multitap: None,
         }
     }
 }
@@ -818,11 +1223,19 @@ impl CommunicationHandler {
         match self.state {
             0 => match inp {
                 0x01 => {
-                    let out = self.controller.start_access();
-                    if out != 0xFF {
-                        self.state = 1;
+                    if let Some(multitap) = &mut self.multitap {
+                        let out = multitap.start_access(&mut self.controllers);
+                        if out != 0xFF {
+                            self.state = 3;
+                        }
+                        out
+                    } else {
+                        let out = self.controllers[0].start_access();
+                        if out != 0xFF {
+                            self.state = 1;
+                        }
+                        out
                     }
-                    out
                 }
                 0x81 => {
                     let out = self.memory_card.start_access();
@@ -838,7 +1251,7 @@ impl CommunicationHandler {
                 }
             },
             1 => {
-                let (result, done) = self.controller.exchange_bytes(inp);
+                let (result, done) = self.controllers[0].exchange_bytes(inp);
                 if done {
                     self.state = 0;
                 }
@@ -851,17 +1264,84 @@ impl CommunicationHandler {
                 }
                 result
             }
+            3 => {
+                let (result, done) = self
+                    .multitap
+                    .as_mut()
+                    .unwrap()
+                    .exchange_bytes(inp, &mut self.controllers);
+                if done {
+                    self.state = 0;
+                }
+                result
+            }
             _ => unreachable!(),
         }
     }
 
-    fn change_controller_key_state(&mut self, key: DigitalControllerKey, pressed: bool) {
-        self.controller.change_key_state(key, pressed);
+    fn change_controller_key_state(
+        &mut self,
+        pad: usize,
+        key: DigitalControllerKey,
+        pressed: bool,
+    ) {
+        self.controllers[pad].change_key_state(key, pressed);
+    }
+
+    fn set_controller_connected(&mut self, pad: usize, connected: bool) {
+        self.controllers[pad].set_connected(connected);
+    }
+
+    fn controller_mut(&mut self, pad: usize) -> &mut controller::Controller {
+        &mut self.controllers[pad]
+    }
+
+    fn set_multitap(&mut self, enabled: bool) {
+        if enabled == self.multitap.is_some() {
+            return;
+        }
+        self.multitap = enabled.then(multitap::Multitap::new);
+        // abort a transfer with the old setup
+        if matches!(self.state, 1 | 3) {
+            self.state = 0;
+        }
+    }
+
+    fn set_input_latch_mode(&mut self, mode: InputLatchMode) {
+        for controller in &mut self.controllers {
+            controller.set_input_latch_mode(mode);
+        }
+    }
+
+    fn set_warnings(&mut self, warnings: &Warnings) {
+        for controller in &mut self.controllers {
+            controller.set_warnings(warnings.clone());
+        }
+    }
+
+    fn vblank(&mut self) {
+        for controller in &mut self.controllers {
+            controller.vblank();
+        }
     }
 
     fn has_more(&self) -> bool {
         self.state != 0
     }
+
+    /// The `/ACK` delay of the device in the current transfer
+    fn ack_delay(&self) -> u32 {
+        if self.state == 2 {
+            MEMORY_CARD_ACK_DELAY
+        } else {
+            CONTROLLER_ACK_DELAY
+        }
+    }
+
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_u8(self.state);
+        self.memory_card.hash_state(hasher);
+    }
 }
 
 pub struct ControllerAndMemoryCard {
@@ -874,8 +1354,14 @@ pub struct ControllerAndMemoryCard {
     transfered_bits: u8,
     tx_fifo: VecDeque<u8>,
     rx_fifo: VecDeque<u8>,
+    /// The cycles left until the `/ACK` of the last byte, the last byte
+    /// of a transfer is not acknowledged
+    ack_timer: Option<u32>,
 
     communication_handlers: [CommunicationHandler; 2],
+    input_latch_mode: InputLatchMode,
+    ack_timing: AckTiming,
+    warnings: Warnings,
 }
 
 impl Default for ControllerAndMemoryCard {
@@ -892,11 +1378,15 @@ impl Default for ControllerAndMemoryCard {
             clk_position_high: false,
             tx_fifo: VecDeque::new(),
             rx_fifo: VecDeque::new(),
+            ack_timer: None,
 
             communication_handlers: [
                 CommunicationHandler::new(0, true),
                 CommunicationHandler::new(1, false),
             ],
+            input_latch_mode: InputLatchMode::default(),
+            ack_timing: AckTiming::default(),
+            warnings: Warnings::default(),
         }
     }
 }
@@ -904,18 +1394,23 @@ impl Default for ControllerAndMemoryCard {
 impl ControllerAndMemoryCard {
     pub fn clock(&mut self, interrupt_requester: &mut impl InterruptRequester, mut cycles: u32) {
         while cycles > 0 {
-            let (r, overflow) = cycles.overflowing_sub(self.baudrate_timer);
-
-            if overflow {
-                // this cannot overflow, because the `self.baudrate_timer` is
-                // larger than `cycles`, so no need to check
-                self.baudrate_timer -= cycles;
-                return;
+            // advance until the next clock edge or `/ACK`, whichever comes first
+            let step = cycles
+                .min(self.baudrate_timer)
+                .min(self.ack_timer.unwrap_or(u32::MAX));
+            cycles -= step;
+            self.baudrate_timer -= step;
+
+            if let Some(ack_timer) = &mut self.ack_timer {
+                *ack_timer -= step;
+                if *ack_timer == 0 {
+                    self.ack_timer = None;
+                    self.send_ack_interrupt(interrupt_requester);
+                }
+            }
+            if self.baudrate_timer != 0 {
+                continue;
             }
-
-            cycles = r;
-            // reset to zero (as if we subtracted from `cycles`)
-            self.baudrate_timer = 0;
 
             // reload timer
             self.trigger_baudrate_reload();
@@ -949,17 +1444,121 @@ impl ControllerAndMemoryCard {
                         self.push_to_rx_fifo(received_byte);
                     }
 
-                    if self.communication_handlers[slot].has_more() {
-                        self.send_ack_interrupt();
-                        interrupt_requester.request_controller_mem_card();
+                    let handler = &self.communication_handlers[slot];
+                    if handler.has_more() {
+                        match self.ack_timing.ack_delay(handler.ack_delay()) {
+                            0 => self.send_ack_interrupt(interrupt_requester),
+                            delay => self.ack_timer = Some(delay),
+                        }
                     }
                 }
             }
         }
     }
 
-    pub fn change_controller_key_state(&mut self, key: DigitalControllerKey, pressed: bool) {
-        self.communication_handlers[0].change_controller_key_state(key, pressed);
+    /// `pad` is `0` for the controller in the port, or `0..4` for the pads A-D
+    /// in a multitap
+    pub fn change_controller_key_state(
+        &mut self,
+        port: usize,
+        pad: usize,
+        key: DigitalControllerKey,
+        pressed: bool,
+    ) {
+        self.communication_handlers[port].change_controller_key_state(pad, key, pressed);
+    }
+
+    pub fn set_controller_connected(&mut self, port: usize, pad: usize, connected: bool) {
+        self.communication_handlers[port].set_controller_connected(pad, connected);
+    }
+
+    pub fn set_multitap(&mut self, port: usize, enabled: bool) {
+        self.communication_handlers[port].set_multitap(enabled);
+    }
+
+    pub fn set_controller_analog(&mut self, port: usize, pad: usize, analog: bool) {
+        self.communication_handlers[port]
+            .controller_mut(pad)
+            .set_analog(analog);
+    }
+
+    pub fn set_analog_config(
+        &mut self,
+        port: usize,
+        pad: usize,
+        stick: AnalogStick,
+        config: AnalogConfig,
+    ) {
+        self.communication_handlers[port]
+            .controller_mut(pad)
+            .set_analog_config(stick, config);
+    }
+
+    /// Returns the bytes the game will read, after the [`AnalogConfig`] of the stick
+    pub fn change_controller_analog_state(
+        &mut self,
+        port: usize,
+        pad: usize,
+        stick: AnalogStick,
+        x: f32,
+        y: f32,
+    ) -> [u8; 2] {
+        self.communication_handlers[port]
+            .controller_mut(pad)
+            .change_analog_state(stick, x, y)
+    }
+
+    pub fn change_controller_analog_bytes(
+        &mut self,
+        port: usize,
+        pad: usize,
+        stick: AnalogStick,
+        bytes: [u8; 2],
+    ) {
+        self.communication_handlers[port]
+            .controller_mut(pad)
+            .change_analog_bytes(stick, bytes);
+    }
+
+    pub fn input_latch_mode(&self) -> InputLatchMode {
+        self.input_latch_mode
+    }
+
+    pub fn set_input_latch_mode(&mut self, mode: InputLatchMode) {
+        self.input_latch_mode = mode;
+        for handler in &mut self.communication_handlers {
+            handler.set_input_latch_mode(mode);
+        }
+    }
+
+    /// Takes effect from the next byte, a pending `/ACK` is kept
+    pub fn set_ack_timing(&mut self, ack_timing: AckTiming) {
+        self.ack_timing = ack_timing;
+    }
+
+    pub(crate) fn set_warnings(&mut self, warnings: Warnings) {
+        for handler in &mut self.communication_handlers {
+            handler.set_warnings(&warnings);
+        }
+        self.warnings = warnings;
+    }
+
+    /// Called by the bus at the start of vblank
+    pub fn vblank(&mut self) {
+        for handler in &mut self.communication_handlers {
+            handler.vblank();
+        }
+    }
+
+    pub(crate) fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_u16(self.ctrl.bits());
+        hasher.write_u16(self.mode.bits());
+        hasher.write_u32(self.get_stat());
+        hasher.write_u8(self.transfered_bits);
+        hasher.write_u32(self.ack_timer.unwrap_or(0));
+        for handler in &self.communication_handlers {
+            handler.hash_state(hasher);
+        }
     }
 }
 
@@ -969,15 +1568,24 @@ impl ControllerAndMemoryCard {
         self.stat.bits() | (timer << 11)
     }
 
+    /// The CPU cycles to transfer one bit, the clock toggles every half of it
+    fn bit_cycles(&self) -> u32 {
+        self.baudrate_timer_reload << self.mode.baudrate_reload_factor_shift()
+    }
+
     fn trigger_baudrate_reload(&mut self) {
-        let factored_reload =
-            self.baudrate_timer_reload << self.mode.baudrate_reload_factor_shift();
-        self.baudrate_timer = factored_reload / 2;
+        self.baudrate_timer = self.bit_cycles() / 2;
     }
 
     fn push_to_tx_fifo(&mut self, data: u8) {
         // max size is 2
-        assert!(self.tx_fifo.len() < 2);
+        if self.tx_fifo.len() >= 2 {
+            self.warnings.warn(
+                "Controller",
+                format!("TX FIFO is full, dropping {:02X}", data),
+            );
+            return;
+        }
         self.tx_fifo.push_back(data);
     }
 
@@ -996,10 +1604,11 @@ impl ControllerAndMemoryCard {
         out
     }
 
-    fn send_ack_interrupt(&mut self) {
+    fn send_ack_interrupt(&mut self, interrupt_requester: &mut impl InterruptRequester) {
         // self.stat.insert(JoyStat::ACK_INPUT_LEVEL_LOW);
         if self.ctrl.ack_interrupt_enable() {
             self.stat.insert(JoyStat::INTERRUPT_REQUEST);
+            interrupt_requester.request_controller_mem_card();
         }
     }
 
@@ -1009,11 +1618,19 @@ impl ControllerAndMemoryCard {
     }
 }
 
+/// The other halves of the registers in the bus map windows
+fn joy_error(detail: String) -> BusError {
+    BusError::DeviceError {
+        device: "Controller",
+        detail: detail.into(),
+    }
+}
+
 impl BusLine for ControllerAndMemoryCard {
     fn read_u32(&mut self, addr: u32) -> Result<u32> {
         let r = match addr {
             0x4 => self.get_stat(),
-            _ => unreachable!(),
+            _ => return Err(joy_error(format!("u32 read {:X}", addr))),
         };
         Ok(r)
     }
@@ -1024,7 +1641,7 @@ impl BusLine for ControllerAndMemoryCard {
             0x8 => self.mode.bits(),
             0xA => self.ctrl.bits(),
             0xE => self.baudrate_timer_reload as u16,
-            _ => unreachable!(),
+            _ => return Err(joy_error(format!("u16 read {:X}", addr))),
         };
         Ok(r)
     }
@@ -1050,6 +1667,7 @@ impl BusLine for ControllerAndMemoryCard {
                     self.transfered_bits = 0;
                     self.tx_fifo.clear();
                     self.rx_fifo.clear();
+                    self.ack_timer = None;
                     self.clk_position_high = false;
 
                     // reset the communication handlers
@@ -1063,6 +1681,7 @@ impl BusLine for ControllerAndMemoryCard {
                     self.transfered_bits = 0;
                     self.tx_fifo.clear();
                     self.rx_fifo.clear();
+                    self.ack_timer = None;
                     self.clk_position_high = false;
 
                     self.communication_handlers[0].state = 0;
@@ -1074,7 +1693,7 @@ impl BusLine for ControllerAndMemoryCard {
                 self.baudrate_timer_reload = data as u32;
                 self.trigger_baudrate_reload();
             }
-            _ => unreachable!(),
+            _ => return Err(joy_error(format!("u16 write {:X}", addr))),
         }
         Ok(())
     }
@@ -1093,3 +1712,358 @@ impl BusLine for ControllerAndMemoryCard {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Starts a `ReadButtons` poll, returns the first 3 bytes
+    fn start_poll(handler: &mut CommunicationHandler) -> [u8; 3] {
+        [
+            handler.exchange_bytes(0x01),
+            handler.exchange_bytes(0x42),
+            handler.exchange_bytes(0x00),
+        ]
+    }
+
+    fn read_buttons(handler: &mut CommunicationHandler) -> u16 {
+        start_poll(handler);
+        let low = handler.exchange_bytes(0x00);
+        let high = handler.exchange_bytes(0x00);
+        assert!(!handler.has_more());
+        u16::from_le_bytes([low, high])
+    }
+
+    #[test]
+    fn key_change_during_poll() {
+        let mut handler = CommunicationHandler::new(0, true);
+        assert_eq!(start_poll(&mut handler), [0x00, 0x41, 0x5A]);
+
+        let low = handler.exchange_bytes(0x00);
+        // `Select` is in the low byte, `Square` is in the high byte
+        handler.change_controller_key_state(0, DigitalControllerKey::Select, true);
+        handler.change_controller_key_state(0, DigitalControllerKey::Square, true);
+        let high = handler.exchange_bytes(0x00);
+        assert_eq!(u16::from_le_bytes([low, high]), 0xFFFF);
+
+        assert_eq!(read_buttons(&mut handler), 0x7FFE);
+    }
+
+    #[test]
+    fn unsupported_command() {
+        let mut handler = CommunicationHandler::new(0, true);
+        let warnings = Warnings::default();
+        warnings.set_strict(false);
+        handler.set_warnings(&warnings);
+
+        // `0x44` (set led state) is only for the analog pads
+        handler.exchange_bytes(0x01);
+        assert_eq!(handler.exchange_bytes(0x44), 0xFF);
+        assert!(!handler.has_more());
+        assert_eq!(warnings.count(), 1);
+
+        assert_eq!(read_buttons(&mut handler), 0xFFFF);
+    }
+
+    #[test]
+    fn vblank_latch() {
+        let mut handler = CommunicationHandler::new(0, true);
+        handler.set_input_latch_mode(InputLatchMode::VBlank);
+
+        handler.change_controller_key_state(0, DigitalControllerKey::X, true);
+        assert_eq!(read_buttons(&mut handler), 0xFFFF);
+
+        handler.vblank();
+        handler.change_controller_key_state(0, DigitalControllerKey::X, false);
+        assert_eq!(read_buttons(&mut handler), !DigitalControllerKey::X.mask());
+        handler.vblank();
+        assert_eq!(read_buttons(&mut handler), 0xFFFF);
+    }
+
+    /// Exchange `sent` after the address byte, the transfer must end
+    /// exactly after the last one
+    fn transfer(handler: &mut CommunicationHandler, sent: &[u8]) -> Vec<u8> {
+        let mut received = vec![handler.exchange_bytes(0x01)];
+        for (i, &byte) in sent.iter().enumerate() {
+            assert!(handler.has_more(), "ended after {} bytes", i + 1);
+            received.push(handler.exchange_bytes(byte));
+        }
+        assert!(!handler.has_more());
+        received
+    }
+
+    #[test]
+    fn multitap_poll() {
+        let mut handler = CommunicationHandler::new(0, true);
+        handler.set_controller_connected(2, true);
+        handler.change_controller_key_state(0, DigitalControllerKey::Start, true);
+        handler.change_controller_key_state(2, DigitalControllerKey::X, true);
+        let pad_a = [0x00, 0x41, 0x5A, 0xF7, 0xFF];
+
+        // without a multitap, selecting it does nothing
+        for _ in 0..2 {
+            assert_eq!(transfer(&mut handler, &[0x42, 0x01, 0x00, 0x00]), pad_a);
+        }
+
+        handler.set_multitap(true);
+        // the first poll selects the 4 pads, and is still answered by pad A
+        assert_eq!(transfer(&mut handler, &[0x42, 0x01, 0x00, 0x00]), pad_a);
+
+        let mut sent = vec![0x42, 0x01];
+        sent.resize(2 + 8 * 4, 0x00);
+        let mut expected = vec![0x00, 0x80, 0x5A];
+        // pad A, `Start` pressed
+        expected.extend([0x41, 0x5A, 0xF7, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        // pad B, not connected
+        expected.extend([0xFF; 8]);
+        // pad C, `X` pressed
+        expected.extend([0x41, 0x5A, 0xFF, 0xBF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        // pad D, not connected
+        expected.extend([0xFF; 8]);
+        assert_eq!(transfer(&mut handler, &sent), expected);
+
+        // not selecting it anymore, this one is still the 4 pads
+        sent[1] = 0x00;
+        assert_eq!(transfer(&mut handler, &sent), expected);
+        assert_eq!(transfer(&mut handler, &[0x42, 0x00, 0x00, 0x00]), pad_a);
+    }
+
+    /// `-1.0..=1.0` in steps of `0.05`
+    fn axis_grid() -> impl Iterator<Item = f32> {
+        (-20..=20).map(|i| i as f32 / 20.0)
+    }
+
+    #[test]
+    fn analog_config_grid() {
+        let curves = [
+            AnalogCurve::Linear,
+            AnalogCurve::Quadratic,
+            AnalogCurve::Cubic,
+        ];
+        for response_curve in curves {
+            for (deadzone, saturation) in [(0.0, 1.0), (0.2, 0.8), (0.5, 0.55)] {
+                let config = AnalogConfig {
+                    deadzone,
+                    saturation,
+                    response_curve,
+                };
+                assert!(config.is_valid());
+
+                let mut previous = 0x00;
+                for value in axis_grid() {
+                    let byte = config.axis_byte(value);
+                    assert!(byte >= previous, "{:?} not monotonic at {}", config, value);
+                    previous = byte;
+
+                    if value.abs() <= deadzone {
+                        assert_eq!(byte, 0x80, "{:?} at {}", config, value);
+                    } else if value >= saturation {
+                        assert_eq!(byte, 0xFF, "{:?} at {}", config, value);
+                    } else if value <= -saturation {
+                        assert_eq!(byte, 0x00, "{:?} at {}", config, value);
+                    } else {
+                        // the curves can round small positions to the center
+                        let side_ok = if value > 0.0 {
+                            byte >= 0x80
+                        } else {
+                            byte <= 0x80
+                        };
+                        assert!(side_ok, "{:?} at {}", config, value);
+                    }
+                }
+
+                // out of range and `NaN`
+                assert_eq!(config.axis_byte(3.0), 0xFF);
+                assert_eq!(config.axis_byte(-3.0), 0x00);
+                assert_eq!(config.axis_byte(f32::NAN), 0x80);
+            }
+        }
+
+        // the middle of the range, rounded
+        let identity = AnalogConfig::default();
+        let byte = |response_curve, value| {
+            AnalogConfig {
+                response_curve,
+                ..identity
+            }
+            .axis_byte(value)
+        };
+        assert_eq!(byte(AnalogCurve::Linear, 0.5), 0xC0);
+        assert_eq!(byte(AnalogCurve::Linear, -0.5), 0x40);
+        assert_eq!(byte(AnalogCurve::Quadratic, 0.5), 0xA0);
+        assert_eq!(byte(AnalogCurve::Quadratic, -0.5), 0x60);
+        assert_eq!(byte(AnalogCurve::Cubic, 0.5), 0x90);
+        assert_eq!(byte(AnalogCurve::Cubic, -0.5), 0x70);
+
+        for (deadzone, saturation) in [(-0.1, 1.0), (0.5, 0.5), (0.0, 1.5), (f32::NAN, 1.0)] {
+            let config = AnalogConfig {
+                deadzone,
+                saturation,
+                ..identity
+            };
+            assert!(!config.is_valid(), "{:?}", config);
+        }
+    }
+
+    #[test]
+    fn analog_poll() {
+        let mut handler = CommunicationHandler::new(0, true);
+        handler.controller_mut(0).set_analog(true);
+        handler.controller_mut(0).set_analog_config(
+            AnalogStick::Left,
+            AnalogConfig {
+                deadzone: 0.25,
+                ..AnalogConfig::default()
+            },
+        );
+
+        let poll = [0x42, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let centered = [0x00, 0x73, 0x5A, 0xFF, 0xFF, 0x80, 0x80, 0x80, 0x80];
+        assert_eq!(transfer(&mut handler, &poll), centered);
+
+        assert_eq!(
+            handler
+                .controller_mut(0)
+                .change_analog_state(AnalogStick::Right, 1.0, -1.0),
+            [0xFF, 0x00]
+        );
+        // in the deadzone of the left stick
+        assert_eq!(
+            handler
+                .controller_mut(0)
+                .change_analog_state(AnalogStick::Left, 0.2, -0.25),
+            [0x80, 0x80]
+        );
+        assert_eq!(
+            transfer(&mut handler, &poll),
+            [0x00, 0x73, 0x5A, 0xFF, 0xFF, 0xFF, 0x00, 0x80, 0x80]
+        );
+
+        handler
+            .controller_mut(0)
+            .change_analog_bytes(AnalogStick::Left, [0x12, 0x34]);
+        assert_eq!(
+            transfer(&mut handler, &poll),
+            [0x00, 0x73, 0x5A, 0xFF, 0xFF, 0xFF, 0x00, 0x12, 0x34]
+        );
+
+        // entering the config mode, after the sticks
+        assert_eq!(
+            transfer(
+                &mut handler,
+                &[0x43, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00]
+            ),
+            [0x00, 0x73, 0x5A, 0xFF, 0xFF, 0xFF, 0x00, 0x12, 0x34]
+        );
+        assert_eq!(start_poll(&mut handler), [0x00, 0xF3, 0x5A]);
+    }
+
+    #[derive(Default)]
+    struct AckCounter {
+        requests: u32,
+    }
+
+    impl InterruptRequester for AckCounter {
+        fn request_vblank(&mut self) {}
+        fn request_gpu(&mut self) {}
+        fn request_cdrom(&mut self) {}
+        fn request_dma(&mut self) {}
+        fn request_timer0(&mut self) {}
+        fn request_timer1(&mut self) {}
+        fn request_timer2(&mut self) {}
+        fn request_controller_mem_card(&mut self) {
+            self.requests += 1;
+        }
+        fn request_spu(&mut self) {}
+    }
+
+    /// Send `sent` to the first port one cycle at a time, each byte is written on
+    /// the `/ACK` of the previous one, like the interrupt handler of a game.
+    /// Returns the cycles at which each `/ACK` interrupt was requested, and the
+    /// received bytes
+    fn run_transfer(ack_timing: AckTiming, sent: &[u8]) -> (Vec<u32>, Vec<u8>) {
+        let mut sio = ControllerAndMemoryCard::default();
+        sio.set_ack_timing(ack_timing);
+        let mut irqs = AckCounter::default();
+        // restart the baudrate timer, so the clock starts at a known phase
+        sio.write_u16(0xE, 0x0088).unwrap();
+        // TX enable, select, and ACK interrupt enable
+        sio.write_u16(0xA, 0x1003).unwrap();
+        assert_eq!(sio.bit_cycles(), 0x88);
+
+        let mut acks = Vec::new();
+        let mut received = Vec::new();
+        let mut sent = sent.iter();
+        sio.write_u8(0, *sent.next().unwrap()).unwrap();
+        for cycle in 1..=sio.bit_cycles() * 8 * 10 {
+            sio.clock(&mut irqs, 1);
+            if irqs.requests as usize > acks.len() {
+                acks.push(cycle);
+                assert!(sio.get_stat() & JoyStat::INTERRUPT_REQUEST.bits() != 0);
+                // acknowledge
+                sio.write_u16(0xA, 0x1013).unwrap();
+                assert!(sio.get_stat() & JoyStat::INTERRUPT_REQUEST.bits() == 0);
+
+                received.push(sio.read_u8(0).unwrap());
+                if let Some(&byte) = sent.next() {
+                    sio.write_u8(0, byte).unwrap();
+                }
+            }
+        }
+        // the last byte is not acknowledged, but still received
+        if !sio.rx_fifo.is_empty() {
+            received.push(sio.read_u8(0).unwrap());
+        }
+        (acks, received)
+    }
+
+    #[test]
+    fn ack_timing() {
+        let poll = [0x01, 0x42, 0x00, 0x00, 0x00];
+        let pad_response = vec![0x00, 0x41, 0x5A, 0xFF, 0xFF];
+        // a byte takes 8 bits of `0x88` cycles, the bits are sent on the falling
+        // edges of the clock, which are at multiples of `0x88` from the restart
+        let (acks, received) = run_transfer(AckTiming::Fast, &poll);
+        assert_eq!(acks, [1088, 2176, 3264, 4352]);
+        assert_eq!(received, pad_response);
+
+        // the next byte starts on the first falling edge after the `/ACK`
+        let (acks, received) = run_transfer(AckTiming::Accurate, &poll);
+        assert_eq!(
+            acks,
+            [
+                1088 + CONTROLLER_ACK_DELAY,
+                2448 + CONTROLLER_ACK_DELAY,
+                3808 + CONTROLLER_ACK_DELAY,
+                5168 + CONTROLLER_ACK_DELAY
+            ]
+        );
+        assert_eq!(received, pad_response);
+
+        // `Id` command to the memory card, the transfer goes on after it
+        let (acks, _) = run_transfer(AckTiming::Accurate, &[0x81, b'S']);
+        assert_eq!(
+            acks,
+            [1088 + MEMORY_CARD_ACK_DELAY, 2312 + MEMORY_CARD_ACK_DELAY]
+        );
+    }
+    #[test]
+    fn tx_fifo_full() {
+        let mut sio = ControllerAndMemoryCard::default();
+        let warnings = Warnings::default();
+        warnings.set_strict(false);
+        sio.set_warnings(warnings.clone());
+
+        // nothing is sent without TX enable, so the FIFO fills up
+        for byte in [0x01, 0x42, 0x00] {
+            sio.write_u8(0, byte).unwrap();
+        }
+        assert_eq!(sio.tx_fifo, [0x01, 0x42]);
+        assert_eq!(warnings.count(), 1);
+        assert_eq!(warnings.list()[0].message, "TX FIFO is full, dropping 00");
+
+        // the other half of `JOY_STAT` and the gap after `JOY_CTRL`
+        assert!(sio.read_u16(0x6).is_err());
+        assert!(sio.write_u16(0xC, 0).is_err());
+    }
+}
@@ -1,6 +1,22 @@
 /// CXD8606CQ CPU ID
 const PRID: u32 = 0x2;
 
+/// `DCIC` bits that can be written, the rest read as zero
+const DCIC_WRITE_MASK: u32 = 0xFF80_F03F;
+
+// `DCIC` status bits, set by the hardware when a breakpoint hits
+const DCIC_ANY_BREAK: u32 = 1 << 0;
+const DCIC_CODE_BREAK: u32 = 1 << 1;
+const DCIC_DATA_BREAK: u32 = 1 << 2;
+const DCIC_DATA_READ_BREAK: u32 = 1 << 3;
+const DCIC_DATA_WRITE_BREAK: u32 = 1 << 4;
+
+// `DCIC` enable bits, each breakpoint needs the two super-master enables
+// (bits 23 and 31) and the master enable (bit 30) as well
+const DCIC_CODE_ENABLE: u32 = 0xC180_0000;
+const DCIC_DATA_READ_ENABLE: u32 = 0xC680_0000;
+const DCIC_DATA_WRITE_ENABLE: u32 = 0xCA80_0000;
+
 #[derive(Default)]
 pub struct SystemControlCoprocessor {
     bpc: u32,
@@ -13,6 +29,10 @@ pub struct SystemControlCoprocessor {
     sr: u32,
     cause: u32,
     epc: u32,
+
+    /// Any of the hardware breakpoints is enabled in `DCIC`, cached so that
+    /// the CPU only needs one check per instruction when none are used
+    hw_breakpoints_active: bool,
 }
 
 impl SystemControlCoprocessor {
@@ -36,6 +56,10 @@ impl SystemControlCoprocessor {
         self.sr = data;
     }
 
+    pub fn read_epc(&self) -> u32 {
+        self.epc
+    }
+
     pub fn write_epc(&mut self, data: u32) {
         self.epc = data;
     }
@@ -43,34 +67,66 @@ impl SystemControlCoprocessor {
     pub fn write_bad_vaddr(&mut self, addr: u32) {
         self.bad_vaddr = addr;
     }
-}
 
-impl SystemControlCoprocessor {
-    pub fn read_ctrl(&self, num: u8) -> u32 {
-        assert!(num <= 0x1F);
-        // no control registers
-        todo!("cop0 ctrl read {}", num)
+    #[inline]
+    pub fn hw_breakpoints_active(&self) -> bool {
+        self.hw_breakpoints_active
     }
 
-    pub fn write_ctrl(&mut self, num: u8, data: u32) {
-        assert!(num <= 0x1F);
-        // no control registers
-        todo!("cop0 ctrl write {}, data={:08X}", num, data)
+    /// Check the execution breakpoint (`BPC`/`BPCM`) against the address of
+    /// the instruction about to be fetched, and update the `DCIC` status bits
+    /// when it hits
+    pub fn check_code_breakpoint(&mut self, pc: u32) -> bool {
+        let hit =
+            self.dcic & DCIC_CODE_ENABLE == DCIC_CODE_ENABLE && (pc ^ self.bpc) & self.bpcm == 0;
+        if hit {
+            self.dcic |= DCIC_ANY_BREAK | DCIC_CODE_BREAK;
+        }
+        hit
+    }
+
+    /// Check the data breakpoint (`BDA`/`BDAM`) against the address of a load
+    /// or store, and update the `DCIC` status bits when it hits
+    pub fn check_data_breakpoint(&mut self, addr: u32, write: bool) -> bool {
+        let (enable, status) = if write {
+            (DCIC_DATA_WRITE_ENABLE, DCIC_DATA_WRITE_BREAK)
+        } else {
+            (DCIC_DATA_READ_ENABLE, DCIC_DATA_READ_BREAK)
+        };
+        let hit = self.dcic & enable == enable && (addr ^ self.bda) & self.bdam == 0;
+        if hit {
+            self.dcic |= DCIC_ANY_BREAK | DCIC_DATA_BREAK | status;
+        }
+        hit
     }
 
-    pub fn read_data(&self, num: u8) -> u32 {
+    fn write_dcic(&mut self, data: u32) {
+        self.dcic = data & DCIC_WRITE_MASK;
+        self.hw_breakpoints_active = [
+            DCIC_CODE_ENABLE,
+            DCIC_DATA_READ_ENABLE,
+            DCIC_DATA_WRITE_ENABLE,
+        ]
+        .iter()
+        .any(|&enable| self.dcic & enable == enable);
+    }
+}
+
+impl SystemControlCoprocessor {
+    /// `None` for the registers that don't exist or can't be read
+    pub fn read_data(&self, num: u8) -> Option<u32> {
         assert!(num <= 0x1F);
 
         let out = match num {
             // FIXME: reading any of these causes reserved instruction exception
             //0..=2 | 4 | 10 => 0, // N/A
-            //3 => self.bpc,
-            //5 => self.bda,
+            3 => self.bpc,
+            5 => self.bda,
             6 => self.jmp_dest,
             7 => self.dcic,
             8 => self.bad_vaddr,
-            //9 => self.bdam,
-            //11 => self.bpcm,
+            9 => self.bdam,
+            11 => self.bpcm,
             12 => self.sr,
             13 => self.cause,
             14 => self.epc,
@@ -81,14 +137,15 @@ impl SystemControlCoprocessor {
             // the return value is usually 00000020h, or when reading much
             // later it returns 00000040h, or even 00000100h.
             16..=31 => 0xFF,
-            0..=15 => todo!("cop0 data read {}", num),
+            0..=15 => return None,
             _ => unreachable!(),
         };
         log::info!("cop0 data read {}, data={:08X}", num, out);
-        out
+        Some(out)
     }
 
-    pub fn write_data(&mut self, num: u8, data: u32) {
+    /// `false` for the registers that don't exist or can't be written
+    pub fn write_data(&mut self, num: u8, data: u32) -> bool {
         assert!(num <= 0x1F);
 
         log::info!("cop0 data write {}, data={:08X}", num, data);
@@ -98,7 +155,7 @@ impl SystemControlCoprocessor {
             3 => self.bpc = data,
             5 => self.bda = data,
             6 => {}
-            7 => self.dcic = data,
+            7 => self.write_dcic(data),
             // 8 => {}
             9 => self.bdam = data,
             11 => self.bpcm = data,
@@ -110,8 +167,9 @@ impl SystemControlCoprocessor {
             //14 => {}
             //15 -> {}
             16..=31 => {} // garbage
-            0..=15 => todo!("cop0 data write {}, vaule {:08X}", num, data),
+            0..=15 => return false,
             _ => unreachable!(),
         }
+        true
     }
 }
@@ -152,10 +152,7 @@ pub struct Gte {
     sxy: [(i16, i16); 3],
     sz: [u16; 4],
     rgb: [u32; 3],
-    irgb: u16,
-    orgb: u16,
     lzcs: i32,
-    lzcr: u32,
 
     rotation_matrix: [[i16; 3]; 3],
     translation_vector: [i32; 3],
@@ -174,34 +171,32 @@ pub struct Gte {
 }
 
 impl Gte {
-    /// updates the ir 1, 2, 3 registers on any write/change to irgb
-    fn update_ir123(&mut self) {
-        let r = (self.irgb) & 0x1F;
-        let g = (self.irgb >> 5) & 0x1F;
-        let b = (self.irgb >> 10) & 0x1F;
+    /// irgb write, expands the 5:5:5 color to ir 1, 2, 3
+    fn write_irgb(&mut self, irgb: u32) {
+        let r = irgb & 0x1F;
+        let g = (irgb >> 5) & 0x1F;
+        let b = (irgb >> 10) & 0x1F;
 
         self.ir[1] = (r * 0x80) as i16;
         self.ir[2] = (g * 0x80) as i16;
         self.ir[3] = (b * 0x80) as i16;
     }
 
-    /// updates orgb register on any write/change to ir 1,2,3
-    /// orgb also acts as irgb mirror
-    fn update_orgb_irgb(&mut self) {
-        let r = (self.ir[1] >> 7).clamp(0, 0x1F) as u16;
-        let g = (self.ir[2] >> 7).clamp(0, 0x1F) as u16;
-        let b = (self.ir[3] >> 7).clamp(0, 0x1F) as u16;
+    /// orgb, the 5:5:5 color of ir 1, 2, 3 saturated, irgb reads the same
+    fn orgb(&self) -> u32 {
+        let r = (self.ir[1] >> 7).clamp(0, 0x1F) as u32;
+        let g = (self.ir[2] >> 7).clamp(0, 0x1F) as u32;
+        let b = (self.ir[3] >> 7).clamp(0, 0x1F) as u32;
 
-        self.orgb = b << 10 | g << 5 | r;
-        self.irgb = self.orgb;
+        b << 10 | g << 5 | r
     }
 
-    /// count the number of leading ones or zeros from lzcs
-    fn update_lzcr(&mut self) {
+    /// lzcr, the number of leading ones or zeros of lzcs
+    fn lzcr(&self) -> u32 {
         if self.lzcs.is_negative() {
-            self.lzcr = self.lzcs.leading_ones()
+            self.lzcs.leading_ones()
         } else {
-            self.lzcr = self.lzcs.leading_zeros()
+            self.lzcs.leading_zeros()
         }
     }
 
@@ -269,9 +264,6 @@ impl Gte {
         for i in 1..=3 {
             self.ir[i] = self.saturate_put_flag(self.mac[i], min, 0x7FFF, flags[i - 1]) as i16;
         }
-
-        // because of a change in ir1,2,3 vector
-        self.update_orgb_irgb();
     }
 
     fn update_mac123_overflow_flags(&mut self, mac1: i64, mac2: i64, mac3: i64) {
@@ -675,10 +667,9 @@ impl Gte {
             20..=22 => self.rgb[num as usize - 20],
             23 => self.res1,
             24..=27 => self.mac[num as usize - 24] as u32,
-            28 => self.irgb as u32,
-            29 => self.orgb as u32,
+            28 | 29 => self.orgb(),
             30 => self.lzcs as u32,
-            31 => self.lzcr,
+            31 => self.lzcr(),
             _ => unreachable!(),
         };
 
@@ -701,10 +692,7 @@ impl Gte {
             1 | 3 | 5 => self.vectors[num as usize / 2][2] = (data & 0xFFFF) as i16,
             6 => self.rgbc = data,
             7 => self.otz = data as u16,
-            8..=11 => {
-                self.ir[num as usize - 8] = (data & 0xFFFF) as i16;
-                self.update_orgb_irgb();
-            }
+            8..=11 => self.ir[num as usize - 8] = (data & 0xFFFF) as i16,
             12..=14 => {
                 // (x, y)
                 self.sxy[num as usize - 12] = (lsb, msb);
@@ -717,16 +705,9 @@ impl Gte {
             20..=22 => self.rgb[num as usize - 20] = data,
             23 => self.res1 = data,
             24..=27 => self.mac[num as usize - 24] = data as i32,
-            28 => {
-                self.irgb = (data as u16) & 0x7FFF;
-                self.orgb = self.irgb;
-                self.update_ir123();
-            }
+            28 => self.write_irgb(data),
             29 => {} // orgb is read only
-            30 => {
-                self.lzcs = data as i32;
-                self.update_lzcr();
-            }
+            30 => self.lzcs = data as i32,
             31 => {} // lzcr is read only
             _ => unreachable!(),
         }
@@ -874,12 +855,14 @@ impl Gte {
             28 => self.dqb = data as i32,
             29 => self.zsf3 = data as i16,
             30 => self.zsf4 = data as i16,
-            31 => self.flag = Flag::from_bits_retain(data),
+            // bits 0-11 are always 0, and the error bit is computed
+            31 => self.flag = Flag::from_bits_truncate(data),
             _ => unreachable!(),
         }
     }
 
-    pub fn execute_command(&mut self, cmd_word: u32) {
+    /// Returns `false` for unknown commands, which do nothing
+    pub fn execute_command(&mut self, cmd_word: u32) -> bool {
         // clear before start of command
         self.flag = Flag::empty();
 
@@ -888,9 +871,7 @@ impl Gte {
         log::info!("cop2 executing command {:?}", cmd);
 
         match cmd.opcode {
-            GteCommandOpcode::Na => {
-                println!("WARN: GTE: unknown command command, cmd: {:08X}", cmd_word);
-            }
+            GteCommandOpcode::Na => return false,
             GteCommandOpcode::Rtps => {
                 self.rtps(0, cmd.sf, cmd.lm, false, true);
             }
@@ -1150,5 +1131,177 @@ impl Gte {
                 self.gpf(mac1, mac2, mac3, cmd.sf, cmd.lm);
             }
         }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `(register, written, read back)`, each on a new GTE
+    const DATA_REGISTERS: [(u8, u32, u32); 32] = [
+        (0, 0x8001_7FFF, 0x8001_7FFF),  // VXY0
+        (1, 0x1234_8001, 0xFFFF_8001),  // VZ0, sign extended
+        (2, 0x0102_F304, 0x0102_F304),  // VXY1
+        (3, 0xFFFF_7001, 0x0000_7001),  // VZ1
+        (4, 0x5555_AAAA, 0x5555_AAAA),  // VXY2
+        (5, 0x0000_ABCD, 0xFFFF_ABCD),  // VZ2
+        (6, 0x1234_5678, 0x1234_5678),  // RGBC
+        (7, 0xFFFF_8765, 0x0000_8765),  // OTZ, zero extended
+        (8, 0x1234_8765, 0xFFFF_8765),  // IR0
+        (9, 0xABCD_0F80, 0x0000_0F80),  // IR1
+        (10, 0x0000_F000, 0xFFFF_F000), // IR2
+        (11, 0x8000_7FFF, 0x0000_7FFF), // IR3
+        (12, 0x8001_7FFF, 0x8001_7FFF), // SXY0
+        (13, 0x0102_F304, 0x0102_F304), // SXY1
+        (14, 0x5555_AAAA, 0x5555_AAAA), // SXY2
+        (15, 0x1234_5678, 0x1234_5678), // SXYP, reads SXY2
+        (16, 0xFFFF_8765, 0x0000_8765), // SZ0
+        (17, 0x1234_0001, 0x0000_0001), // SZ1
+        (18, 0x0000_FFFF, 0x0000_FFFF), // SZ2
+        (19, 0x8000_7FFF, 0x0000_7FFF), // SZ3
+        (20, 0x1234_5678, 0x1234_5678), // RGB0
+        (21, 0x8765_4321, 0x8765_4321), // RGB1
+        (22, 0xFFFF_0000, 0xFFFF_0000), // RGB2
+        (23, 0xDEAD_BEEF, 0xDEAD_BEEF), // RES1
+        (24, 0x8000_0001, 0x8000_0001), // MAC0
+        (25, 0x7FFF_FFFF, 0x7FFF_FFFF), // MAC1
+        (26, 0xFFFF_FFFF, 0xFFFF_FFFF), // MAC2
+        (27, 0x1234_5678, 0x1234_5678), // MAC3
+        (28, 0xFFFF_FFFF, 0x0000_7FFF), // IRGB
+        (29, 0x1234_5678, 0x0000_0000), // ORGB, read only
+        (30, 0x0000_1234, 0x0000_1234), // LZCS
+        (31, 0x1234_5678, 0x0000_0020), // LZCR, read only, from LZCS = 0
+    ];
+
+    const CTRL_REGISTERS: [(u8, u32, u32); 32] = [
+        (0, 0x8001_7FFF, 0x8001_7FFF),  // RT11RT12
+        (1, 0x0102_F304, 0x0102_F304),  // RT13RT21
+        (2, 0x5555_AAAA, 0x5555_AAAA),  // RT22RT23
+        (3, 0x1234_5678, 0x1234_5678),  // RT31RT32
+        (4, 0x1234_8001, 0xFFFF_8001),  // RT33, sign extended
+        (5, 0x8000_0001, 0x8000_0001),  // TRX
+        (6, 0x7FFF_FFFF, 0x7FFF_FFFF),  // TRY
+        (7, 0xFFFF_FFFF, 0xFFFF_FFFF),  // TRZ
+        (8, 0x8001_7FFF, 0x8001_7FFF),  // L11L12
+        (9, 0x0102_F304, 0x0102_F304),  // L13L21
+        (10, 0x5555_AAAA, 0x5555_AAAA), // L22L23
+        (11, 0x1234_5678, 0x1234_5678), // L31L32
+        (12, 0xFFFF_7001, 0x0000_7001), // L33
+        (13, 0x8000_0001, 0x8000_0001), // RBK
+        (14, 0x1234_5678, 0x1234_5678), // GBK
+        (15, 0xFFFF_0000, 0xFFFF_0000), // BBK
+        (16, 0x8001_7FFF, 0x8001_7FFF), // LR1LR2
+        (17, 0x0102_F304, 0x0102_F304), // LR3LG1
+        (18, 0x5555_AAAA, 0x5555_AAAA), // LG2LG3
+        (19, 0x1234_5678, 0x1234_5678), // LB1LB2
+        (20, 0x0000_ABCD, 0xFFFF_ABCD), // LB3
+        (21, 0x8000_0001, 0x8000_0001), // RFC
+        (22, 0x1234_5678, 0x1234_5678), // GFC
+        (23, 0xFFFF_0000, 0xFFFF_0000), // BFC
+        (24, 0x8000_0001, 0x8000_0001), // OFX
+        (25, 0x7FFF_FFFF, 0x7FFF_FFFF), // OFY
+        (26, 0x1234_8000, 0xFFFF_8000), // H, unsigned, but sign extended on read
+        (27, 0xFFFF_7001, 0x0000_7001), // DQA
+        (28, 0x8765_4321, 0x8765_4321), // DQB
+        (29, 0x0000_8001, 0xFFFF_8001), // ZSF3
+        (30, 0xFFFF_7FFF, 0x0000_7FFF), // ZSF4
+        (31, 0xFFFF_FFFF, 0xFFFF_F000), // FLAG, bits 0-11 are 0
+    ];
+
+    #[test]
+    fn data_register_masking() {
+        for (num, written, expected) in DATA_REGISTERS {
+            let mut gte = Gte::default();
+            gte.write_data(num, written);
+            assert_eq!(gte.read_data(num), expected, "data register {}", num);
+        }
+    }
+
+    #[test]
+    fn ctrl_register_masking() {
+        for (num, written, expected) in CTRL_REGISTERS {
+            let mut gte = Gte::default();
+            gte.write_ctrl(num, written);
+            assert_eq!(gte.read_ctrl(num), expected, "ctrl register {}", num);
+        }
+    }
+
+    #[test]
+    fn flag_error_bit() {
+        let mut gte = Gte::default();
+        // IR0 saturated is not an error
+        gte.write_ctrl(31, 0x0000_1000);
+        assert_eq!(gte.read_ctrl(31), 0x0000_1000);
+        // the error bit can't be written
+        gte.write_ctrl(31, 0x8000_0000);
+        assert_eq!(gte.read_ctrl(31), 0);
+        // SX2 saturated
+        gte.write_ctrl(31, 0x0000_4000);
+        assert_eq!(gte.read_ctrl(31), 0x8000_4000);
+    }
+
+    #[test]
+    fn lzcs_lzcr() {
+        let mut gte = Gte::default();
+        for (lzcs, lzcr) in [
+            (0x0000_0000, 32),
+            (0xFFFF_FFFF, 32),
+            (0x0000_0001, 31),
+            (0x7FFF_FFFF, 1),
+            (0x8000_0000, 1),
+            (0x0012_3456, 11),
+            (0xFFF0_0000, 12),
+        ] {
+            gte.write_data(30, lzcs);
+            assert_eq!(gte.read_data(30), lzcs);
+            assert_eq!(gte.read_data(31), lzcr, "LZCS {:08X}", lzcs);
+            // read only
+            gte.write_data(31, 0);
+            assert_eq!(gte.read_data(31), lzcr);
+        }
+    }
+
+    #[test]
+    fn sxyp_pushes_the_fifo() {
+        let mut gte = Gte::default();
+        gte.write_data(12, 0x0001_0002);
+        gte.write_data(13, 0x0003_0004);
+        gte.write_data(14, 0x0005_0006);
+
+        gte.write_data(15, 0xFFFF_8000);
+        assert_eq!(gte.read_data(12), 0x0003_0004);
+        assert_eq!(gte.read_data(13), 0x0005_0006);
+        assert_eq!(gte.read_data(14), 0xFFFF_8000);
+        // reading doesn't push
+        assert_eq!(gte.read_data(15), 0xFFFF_8000);
+        assert_eq!(gte.read_data(13), 0x0005_0006);
+    }
+
+    #[test]
+    fn irgb_orgb_conversion() {
+        let mut gte = Gte::default();
+        // r=0x1F, g=0x01, b=0x10
+        gte.write_data(28, 0x0000_403F);
+        assert_eq!(gte.read_data(9), 0x0F80);
+        assert_eq!(gte.read_data(10), 0x0080);
+        assert_eq!(gte.read_data(11), 0x0800);
+        assert_eq!(gte.read_data(28), 0x403F);
+        assert_eq!(gte.read_data(29), 0x403F);
+
+        // saturated to 0-0x1F
+        gte.write_data(9, 0xFFFF_8000);
+        gte.write_data(10, 0x0000_7FFF);
+        gte.write_data(11, 0x0000_017F);
+        assert_eq!(gte.read_data(29), 0x0BE0);
+        assert_eq!(gte.read_data(28), 0x0BE0);
+
+        // from the commands as well, SQR sf=1: IR = IR * IR >> 12
+        gte.write_data(9, 0x0000_1000);
+        gte.write_data(10, 0x0000_0800);
+        gte.write_data(11, 0x0000_0000);
+        gte.execute_command(0x0008_0028);
+        assert_eq!(gte.read_data(29), 0x0000_011F);
     }
 }
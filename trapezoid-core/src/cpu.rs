@@ -1,17 +1,30 @@
+mod bios_functions;
 #[cfg(feature = "debugger")]
 mod debugger;
+#[cfg(feature = "debugger")]
+mod execution_trace;
+mod hle_bios;
+mod idle_loop;
 mod instruction;
 mod instructions_table;
 mod register;
+mod unwind;
 
 use crate::coprocessor::{Gte, SystemControlCoprocessor};
-use crate::memory::BusLine;
+use crate::memory::{self, BusLine};
+use crate::perf::CpuCounters;
+use crate::warnings::Warnings;
 
+pub use bios_functions::bios_function_name;
+pub use idle_loop::IdleSkip;
 pub use instruction::{Instruction, Opcode};
 pub use register::{RegisterType, Registers, CPU_REGISTERS};
+pub use unwind::{unwind_stack, StackFrame};
 
 #[cfg(feature = "debugger")]
 pub use self::debugger::Debugger;
+#[cfg(feature = "debugger")]
+pub use self::execution_trace::TraceConfig;
 
 #[cfg(not(feature = "debugger"))]
 struct Debugger;
@@ -52,7 +65,7 @@ impl Debugger {
     pub fn trace_instruction(
         &mut self,
         _regs: &Registers,
-        _jumping: bool,
+        _jump_dest: Option<u32>,
         _instruction: &Instruction,
     ) -> bool {
         false
@@ -69,6 +82,32 @@ impl Debugger {
 
 const SHELL_LOCATION: u32 = 0x80030000;
 
+/// Disassemble a single instruction `instr` located at `addr`.
+///
+/// `addr` is used to resolve the targets of branches and jumps.
+pub fn disasm(addr: u32, instr: u32) -> String {
+    Instruction::from_u32(instr, addr).to_string()
+}
+
+/// Disassemble `count` instructions starting from `start`, reading them
+/// through the bus of `psx`.
+///
+/// Addresses that can't be read produce `"<bus error>"`.
+pub fn disasm_range(
+    psx: &mut crate::Psx,
+    start: u32,
+    count: u32,
+) -> impl Iterator<Item = (u32, String)> + '_ {
+    (0..count).map(move |i| {
+        let addr = start.wrapping_add(i * 4);
+        let text = match psx.bus_read_u32(addr) {
+            Ok(instr) => disasm(addr, instr),
+            Err(_) => "<bus error>".to_string(),
+        };
+        (addr, text)
+    })
+}
+
 pub(crate) trait CpuBusProvider: BusLine {
     fn pending_interrupts(&self) -> bool;
     fn should_run_dma(&self) -> bool;
@@ -79,7 +118,7 @@ enum Exception {
     Interrupt = 0x00,
     AddressErrorLoad = 0x04,
     AddressErrorStore = 0x05,
-    _BusErrorInstructionFetch = 0x06,
+    BusErrorInstructionFetch = 0x06,
     _BusErrorDataLoadStore = 0x07,
     Syscall = 0x08,
     Breakpoint = 0x09,
@@ -88,40 +127,56 @@ enum Exception {
     ArithmeticOverflow = 0x0C,
 }
 
+/// Why the CPU stopped running, returned from [`Psx::clock_full_video_frame`](crate::Psx::clock_full_video_frame)
+/// and the other clock functions
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum CpuState {
     /// Normal execution, no breakpoints
     Normal,
 
     #[cfg(feature = "debugger")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "debugger")))]
     /// Paused on an execution breakpoint, the pause happen BEFORE execution
     InstructionBreakpoint(u32),
 
     #[cfg(feature = "debugger")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "debugger")))]
     /// Paused on a write breakpoint, together with the value that was written
     /// the pause happen AFTER the operation
     WriteBreakpoint { addr: u32, bits: u8 },
 
     #[cfg(feature = "debugger")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "debugger")))]
     /// Paused on a read breakpoint
     /// the pause happen AFTER the operation
     ReadBreakpoint { addr: u32, bits: u8 },
 
     #[cfg(feature = "debugger")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "debugger")))]
     /// Paused after a single instruction was executed
     Step,
 
     #[cfg(feature = "debugger")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "debugger")))]
     /// Paused after a single instruction was executed, if the instruction is `Jal` or `Jalr`
     /// which is used for function calls, the pause will happen after the function returns,
     /// i.e. step over the function
     StepOver,
 
     #[cfg(feature = "debugger")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "debugger")))]
     /// Continue execution until the CPU exit the current function
     StepOut,
+
+    /// The host GPU backend failed, if it `recovered`, the emulation can continue
+    /// (with the VRAM content lost), otherwise, nothing is rendered anymore
+    GpuError {
+        error: crate::GpuError,
+        recovered: bool,
+    },
 }
 
+/// The MIPS R3000A CPU, with its coprocessors (`COP0` and the `GTE`)
 pub struct Cpu {
     regs: Registers,
     cop0: SystemControlCoprocessor,
@@ -133,8 +188,19 @@ pub struct Cpu {
 
     shell_reached: bool,
     current_instr_pc: u32,
+    /// The current instruction is in the delay slot of a jump/branch,
+    /// used to set `BD` and `EPC` correctly on exceptions
+    current_instr_in_delay_slot: bool,
+
+    /// When set, the kernel calls are handled here instead of the BIOS
+    hle_bios: Option<hle_bios::HleBios>,
+    idle_loop: idle_loop::IdleLoopDetector,
+    warnings: Warnings,
+    perf: CpuCounters,
 
     debugger: Debugger,
+    #[cfg(feature = "debugger")]
+    execution_tracer: Option<execution_trace::ExecutionTracer>,
 }
 
 impl Cpu {
@@ -149,11 +215,21 @@ impl Cpu {
             elapsed_cycles: 0,
             shell_reached: false,
             current_instr_pc: 0,
+            current_instr_in_delay_slot: false,
+
+            hle_bios: None,
+            idle_loop: idle_loop::IdleLoopDetector::default(),
+            warnings: Warnings::default(),
+            perf: CpuCounters::default(),
 
             debugger: Debugger::new(),
+            #[cfg(feature = "debugger")]
+            execution_tracer: None,
         }
     }
 
+    /// Reset the registers and the coprocessors to the power-on state, the PC
+    /// starts from the BIOS entry
     pub fn reset(&mut self) {
         self.regs = Registers::new();
         self.cop0 = SystemControlCoprocessor::default();
@@ -162,12 +238,38 @@ impl Cpu {
         self.elapsed_cycles = 0;
         self.shell_reached = false;
         self.current_instr_pc = 0;
+        self.current_instr_in_delay_slot = false;
+        self.idle_loop.reset();
+    }
+
+    pub(crate) fn enable_hle_bios(&mut self, print_tty: bool) {
+        self.hle_bios = Some(hle_bios::HleBios::new(print_tty));
     }
 
+    pub(crate) fn set_hle_bios_print_tty(&mut self, print_tty: bool) {
+        if let Some(hle_bios) = &mut self.hle_bios {
+            hle_bios.set_print_tty(print_tty);
+        }
+    }
+
+    pub(crate) fn set_idle_skip(&mut self, mode: IdleSkip) {
+        self.idle_loop.set_mode(mode);
+    }
+
+    pub(crate) fn set_warnings(&mut self, warnings: Warnings) {
+        self.warnings = warnings;
+    }
+
+    pub(crate) fn perf_counters_mut(&mut self) -> &mut CpuCounters {
+        &mut self.perf
+    }
+
+    /// The registers, as of the last executed instruction
     pub fn registers(&self) -> &Registers {
         &self.regs
     }
 
+    /// Change the registers, the change takes effect from the next instruction
     pub fn registers_mut(&mut self) -> &mut Registers {
         &mut self.regs
     }
@@ -177,6 +279,23 @@ impl Cpu {
         &mut self.debugger
     }
 
+    /// Start writing every executed instruction to a file, or stop if `None`.
+    ///
+    /// The trace stops by itself when `end_pc` or `max_instructions` is reached.
+    #[cfg(feature = "debugger")]
+    pub fn set_trace(&mut self, config: Option<TraceConfig>) -> std::io::Result<()> {
+        self.execution_tracer = config
+            .map(execution_trace::ExecutionTracer::new)
+            .transpose()?;
+        Ok(())
+    }
+
+    /// Returns `true` if the execution trace is running
+    #[cfg(feature = "debugger")]
+    pub fn tracing(&self) -> bool {
+        self.execution_tracer.is_some()
+    }
+
     pub(crate) fn clock<P: CpuBusProvider>(
         &mut self,
         bus: &mut P,
@@ -190,10 +309,23 @@ impl Cpu {
         self.debugger
             .handle_pending_processing(bus, &self.regs, self.jump_dest_next.is_some());
 
-        let pending_interrupts = bus.pending_interrupts();
-        self.check_and_execute_interrupt(pending_interrupts);
+        // nothing to execute until something changes, only let the time pass
+        if self.idle_loop.is_idle() {
+            debug_assert_eq!(self.idle_loop.idle_loop_head(), Some(self.regs.pc));
+            debug_assert!(self.jump_dest_next.is_none());
+            debug_assert_eq!(self.elapsed_cycles, 0);
+
+            if !self.idle_loop.try_wake(bus) {
+                return (false, idle_loop::IDLE_SKIP_CYCLES, state);
+            }
+        }
 
         for _ in 0..clocks {
+            // checked before every instruction, since the CPU itself can change
+            // `I_STAT`/`I_MASK` or `SR`, and `cause.10` must follow them immediately
+            let pending_interrupts = bus.pending_interrupts();
+            self.check_and_execute_interrupt(pending_interrupts);
+
             // notify the UI when the shell location is reached
             if !self.shell_reached && self.regs.pc == SHELL_LOCATION {
                 self.shell_reached = true;
@@ -202,10 +334,25 @@ impl Cpu {
                 break;
             }
 
-            if let Some(instruction) = self.bus_read_u32(bus, self.regs.pc) {
-                let instruction = Instruction::from_u32(instruction, self.regs.pc);
+            if let Some(hle_bios) = &mut self.hle_bios {
+                if hle_bios.try_handle(&mut self.regs, &mut self.cop0, bus) {
+                    self.elapsed_cycles += hle_bios::HLE_CALL_CYCLES;
+                    continue;
+                }
+            }
+
+            // set before fetching, since the fetch itself can fail
+            self.current_instr_pc = self.regs.pc;
+            // the previous instruction is a jump/branch that has been taken
+            self.current_instr_in_delay_slot = self.jump_dest_next.is_some();
 
-                self.current_instr_pc = self.regs.pc;
+            if self.cop0.hw_breakpoints_active() && self.cop0.check_code_breakpoint(self.regs.pc) {
+                self.execute_debug_exception();
+                continue;
+            }
+
+            if let Some(instruction) = self.fetch_instruction(bus) {
+                let instruction = Instruction::from_u32(instruction, self.regs.pc);
 
                 log::trace!(
                     "{:08X}: {}{}",
@@ -219,14 +366,28 @@ impl Cpu {
                 );
 
                 // breakpoint hit
-                if self.debugger.trace_instruction(
+                if self
+                    .debugger
+                    .trace_instruction(&self.regs, self.jump_dest_next, &instruction)
+                {
+                    break;
+                }
+
+                if self.idle_loop.before_instruction(
                     &self.regs,
-                    self.jump_dest_next.is_some(),
+                    self.current_instr_in_delay_slot,
                     &instruction,
+                    bus,
                 ) {
                     break;
                 }
 
+                #[cfg(feature = "debugger")]
+                let tracing = self
+                    .execution_tracer
+                    .as_mut()
+                    .is_some_and(|t| t.begin(&self.regs));
+
                 self.regs.pc += 4;
                 if let Some(jump_dest) = self.jump_dest_next.take() {
                     log::trace!("pc jump {:08X}", jump_dest);
@@ -234,7 +395,13 @@ impl Cpu {
                 }
 
                 self.execute_instruction(&instruction, bus);
+                self.perf.instruction_retired();
                 self.regs.handle_delayed_load();
+                self.idle_loop
+                    .after_instruction(self.current_instr_pc, self.jump_dest_next);
+
+                #[cfg(feature = "debugger")]
+                self.trace_execution(tracing, &instruction);
 
                 if self.debugger.paused() {
                     break;
@@ -275,6 +442,16 @@ impl Cpu {
 
 impl Cpu {
     fn execute_exception(&mut self, cause: Exception) {
+        self.enter_exception(cause, false);
+    }
+
+    /// The `COP0` hardware breakpoints use `Breakpoint` as the cause, but jump
+    /// to the debug vector instead of the general one
+    fn execute_debug_exception(&mut self) {
+        self.enter_exception(Exception::Breakpoint, true);
+    }
+
+    fn enter_exception(&mut self, cause: Exception, debug: bool) {
         log::info!(
             "executing exception: {:?}, cause code: {:02X}",
             cause,
@@ -282,10 +459,29 @@ impl Cpu {
         );
 
         let cause_code = cause as u8;
+        self.perf.exception(cause_code);
+
+        // `EPC` should point to the instruction that caused the exception,
+        // or the jump/branch before it if it was in the delay slot (and `BD` is set).
+        //
+        // Interrupts are handled between instructions, so `pc` is the next
+        // instruction to execute, and if there is a pending jump, then the
+        // next instruction is in the delay slot.
+        let (bd, target_pc) = match cause {
+            Exception::Interrupt => {
+                let bd = self.jump_dest_next.is_some();
+                // execute the branch again if in the delay slot
+                (bd, self.regs.pc.wrapping_sub((bd as u32) * 4))
+            }
+            _ => {
+                let bd = self.current_instr_in_delay_slot;
+                (bd, self.current_instr_pc.wrapping_sub((bd as u32) * 4))
+            }
+        };
+        // remove the next jump
+        self.jump_dest_next = None;
 
         let old_cause = self.cop0.read_cause();
-        // remove the next jump
-        let bd = self.jump_dest_next.take().is_some();
         let new_cause =
             (old_cause & 0x7FFFFF00) | ((bd as u32) << 31) | ((cause_code & 0x1F) as u32) << 2;
         self.cop0.write_cause(new_cause);
@@ -301,19 +497,11 @@ impl Cpu {
 
         let bev = (sr >> 22) & 1 == 1;
 
-        let jmp_vector = if bev { 0xBFC00180 } else { 0x80000080 };
-
-        // TODO: check the written value to EPC
-        let target_pc = match cause {
-            Exception::Interrupt => {
-                if bd {
-                    // execute branch again
-                    self.regs.pc - 4
-                } else {
-                    self.regs.pc
-                }
-            }
-            _ => self.regs.pc - 4,
+        let jmp_vector = match (bev, debug) {
+            (false, false) => 0x80000080,
+            (true, false) => 0xBFC00180,
+            (false, true) => 0x80000040,
+            (true, true) => 0xBFC00140,
         };
 
         self.cop0.write_epc(target_pc);
@@ -394,6 +582,9 @@ impl Cpu {
     {
         let rs = self.regs.read_general(instruction.rs_raw);
         let computed_addr = rs.wrapping_add(Self::sign_extend_16(instruction.imm16()));
+        if self.data_breakpoint_hit(computed_addr, false) {
+            return;
+        }
 
         if let Some(data) = handler(self, computed_addr) {
             self.regs.write_delayed(instruction.rt_raw, data);
@@ -408,10 +599,24 @@ impl Cpu {
         let rs = self.regs.read_general(instruction.rs_raw);
         let rt = self.regs.read_general(instruction.rt_raw);
         let computed_addr = rs.wrapping_add(Self::sign_extend_16(instruction.imm16()));
+        if self.data_breakpoint_hit(computed_addr, true) {
+            return;
+        }
 
         handler(self, computed_addr, rt);
     }
 
+    /// Check the `COP0` data breakpoint before a load/store, if it hits, the
+    /// debug exception is executed and the access must not happen
+    #[inline]
+    fn data_breakpoint_hit(&mut self, addr: u32, write: bool) -> bool {
+        if self.cop0.hw_breakpoints_active() && self.cop0.check_data_breakpoint(addr, write) {
+            self.execute_debug_exception();
+            return true;
+        }
+        false
+    }
+
     fn execute_instruction<P: CpuBusProvider>(&mut self, instruction: &Instruction, bus: &mut P) {
         match instruction.opcode {
             Opcode::Nop => {
@@ -443,9 +648,14 @@ impl Cpu {
                 });
             }
             Opcode::Lwl => {
-                // TODO: test these unaligned addressing instructions
+                // `Lwl` and `Lwr` merge with the pending load of the same
+                // register (if any), so that a pair of them in sequence works
+                // without a delay between them, see `read_general_latest`
                 let rs = self.regs.read_general(instruction.rs_raw);
                 let computed_addr = rs.wrapping_add(Self::sign_extend_16(instruction.imm16()));
+                if self.data_breakpoint_hit(computed_addr, false) {
+                    return;
+                }
 
                 // round to the nearest floor of four
                 let start = computed_addr & !3;
@@ -471,6 +681,9 @@ impl Cpu {
             Opcode::Lwr => {
                 let rs = self.regs.read_general(instruction.rs_raw);
                 let computed_addr = rs.wrapping_add(Self::sign_extend_16(instruction.imm16()));
+                if self.data_breakpoint_hit(computed_addr, false) {
+                    return;
+                }
 
                 let start = computed_addr;
                 let end = computed_addr | 3;
@@ -493,7 +706,7 @@ impl Cpu {
             }
             Opcode::Sb => {
                 self.execute_store(instruction, |s, computed_addr, data| {
-                    s.bus_write_u8(bus, computed_addr, data as u8)
+                    s.bus_write_u8(bus, computed_addr, data)
                 });
             }
             Opcode::Sh => {
@@ -510,6 +723,9 @@ impl Cpu {
                 let rs = self.regs.read_general(instruction.rs_raw);
                 let mut rt = self.regs.read_general(instruction.rt_raw);
                 let computed_addr = rs.wrapping_add(Self::sign_extend_16(instruction.imm16()));
+                if self.data_breakpoint_hit(computed_addr, true) {
+                    return;
+                }
 
                 // round to the nearest floor of four
                 let start = computed_addr & !3;
@@ -522,7 +738,7 @@ impl Cpu {
 
                 // write the data in little endian
                 for part_addr in start..=end {
-                    self.bus_write_u8(bus, part_addr, rt as u8);
+                    self.bus_write_u8(bus, part_addr, rt);
                     rt >>= 8;
                 }
             }
@@ -530,13 +746,16 @@ impl Cpu {
                 let rs = self.regs.read_general(instruction.rs_raw);
                 let mut rt = self.regs.read_general(instruction.rt_raw);
                 let computed_addr = rs.wrapping_add(Self::sign_extend_16(instruction.imm16()));
+                if self.data_breakpoint_hit(computed_addr, true) {
+                    return;
+                }
 
                 let start = computed_addr;
                 let end = computed_addr | 3;
 
                 // write the data in little endian
                 for part_addr in start..=end {
-                    self.bus_write_u8(bus, part_addr, rt as u8);
+                    self.bus_write_u8(bus, part_addr, rt);
                     rt >>= 8;
                 }
             }
@@ -781,44 +1000,58 @@ impl Cpu {
             Opcode::Cop(n) => {
                 // the only cop0 command RFE is handled as its own opcode
                 // so we only handle cop2 commands
-                assert!(n == 2);
-
-                self.cop2.execute_command(instruction.imm25());
+                if n != 2 {
+                    self.missing_coprocessor(n);
+                } else if !self.cop2.execute_command(instruction.imm25()) {
+                    self.warnings.warn(
+                        "GTE",
+                        format!(
+                            "unknown command {:08X} at {:08X}",
+                            instruction.imm25(),
+                            self.current_instr_pc
+                        ),
+                    );
+                }
             }
             Opcode::Mfc(n) => {
                 let result = match n {
-                    0 => self.cop0.read_data(instruction.rd_raw),
+                    0 => self.read_cop0(instruction.rd_raw),
                     2 => self.cop2.read_data(instruction.rd_raw),
-                    _ => unreachable!(),
+                    _ => {
+                        self.missing_coprocessor(n);
+                        0
+                    }
                 };
 
-                self.regs.write_general(instruction.rt_raw, result);
+                // coprocessor reads have a delay slot similar to loads
+                self.regs.write_delayed(instruction.rt_raw, result);
             }
             Opcode::Cfc(n) => {
                 let result = match n {
-                    0 => self.cop0.read_ctrl(instruction.rd_raw),
                     2 => self.cop2.read_ctrl(instruction.rd_raw),
-                    _ => unreachable!(),
+                    _ => {
+                        self.missing_coprocessor_ctrl(n);
+                        0
+                    }
                 };
 
-                self.regs.write_general(instruction.rt_raw, result);
+                self.regs.write_delayed(instruction.rt_raw, result);
             }
             Opcode::Mtc(n) => {
                 let rt = self.regs.read_general(instruction.rt_raw);
 
                 match n {
-                    0 => self.cop0.write_data(instruction.rd_raw, rt),
+                    0 => self.write_cop0(instruction.rd_raw, rt),
                     2 => self.cop2.write_data(instruction.rd_raw, rt),
-                    _ => unreachable!(),
+                    _ => self.missing_coprocessor(n),
                 }
             }
             Opcode::Ctc(n) => {
                 let rt = self.regs.read_general(instruction.rt_raw);
 
                 match n {
-                    0 => self.cop0.write_ctrl(instruction.rd_raw, rt),
                     2 => self.cop2.write_ctrl(instruction.rd_raw, rt),
-                    _ => unreachable!(),
+                    _ => self.missing_coprocessor_ctrl(n),
                 }
             }
             //Opcode::Bcf(_) => {}
@@ -837,20 +1070,26 @@ impl Cpu {
             Opcode::Lwc(n) => {
                 let rs = self.regs.read_general(instruction.rs_raw);
                 let computed_addr = rs.wrapping_add(Self::sign_extend_16(instruction.imm16()));
+                if self.data_breakpoint_hit(computed_addr, false) {
+                    return;
+                }
 
                 if let Some(data) = self.bus_read_u32(bus, computed_addr) {
                     match n {
-                        0 => self.cop0.write_data(instruction.rt_raw, data),
+                        0 => self.write_cop0(instruction.rt_raw, data),
                         2 => self.cop2.write_data(instruction.rt_raw, data),
-                        _ => unreachable!(),
+                        _ => self.missing_coprocessor(n),
                     }
                 }
             }
             Opcode::Swc(n) => {
                 let result = match n {
-                    0 => self.cop0.read_data(instruction.rt_raw),
+                    0 => self.read_cop0(instruction.rt_raw),
                     2 => self.cop2.read_data(instruction.rt_raw),
-                    _ => unreachable!(),
+                    _ => {
+                        self.missing_coprocessor(n);
+                        0
+                    }
                 };
 
                 self.execute_store(instruction, |s, computed_addr, _| {
@@ -861,12 +1100,79 @@ impl Cpu {
                 self.execute_exception(Exception::ReservedInstruction);
             }
             Opcode::SecondaryOpcode => unreachable!(),
-            _ => todo!("unimplemented_instruction {:?}", instruction.opcode),
+            // there is no coprocessor condition line, so they act as `nop`
+            _ => self.warnings.warn(
+                "CPU",
+                format!(
+                    "unimplemented instruction {:?} at {:08X}, ignoring it",
+                    instruction.opcode, self.current_instr_pc
+                ),
+            ),
+        }
+    }
+
+    /// The unused registers read as `0`
+    fn read_cop0(&mut self, num: u8) -> u32 {
+        self.cop0.read_data(num).unwrap_or_else(|| {
+            self.warnings.warn(
+                "COP0",
+                format!(
+                    "read from unsupported register {} at {:08X}",
+                    num, self.current_instr_pc
+                ),
+            );
+            0
+        })
+    }
+
+    fn write_cop0(&mut self, num: u8, data: u32) {
+        if !self.cop0.write_data(num, data) {
+            self.warnings.warn(
+                "COP0",
+                format!(
+                    "write {:08X} to unsupported register {} at {:08X}",
+                    data, num, self.current_instr_pc
+                ),
+            );
         }
     }
+
+    /// Only cop0 and cop2 exist, the others are ignored
+    fn missing_coprocessor(&mut self, n: u8) {
+        self.warnings.warn(
+            "CPU",
+            format!(
+                "cop{} instruction at {:08X}, the coprocessor doesn't exist",
+                n, self.current_instr_pc
+            ),
+        );
+    }
+
+    /// cop0 doesn't have control registers
+    fn missing_coprocessor_ctrl(&mut self, n: u8) {
+        self.warnings.warn(
+            "CPU",
+            format!(
+                "cop{} control register access at {:08X}, it has none",
+                n, self.current_instr_pc
+            ),
+        );
+    }
 }
 
 impl Cpu {
+    #[cfg(feature = "debugger")]
+    fn trace_execution(&mut self, tracing: bool, instruction: &Instruction) {
+        if let Some(tracer) = &mut self.execution_tracer {
+            if tracing {
+                tracer.record(instruction, &self.regs, self.current_instr_in_delay_slot);
+            }
+            if tracer.done() {
+                self.execution_tracer = None;
+            }
+        }
+    }
+
     fn print_call_stack(&self) {
         let call_stack = self.debugger.call_stack();
 
@@ -880,6 +1186,21 @@ impl Cpu {
         }
     }
 
+    /// Read the instruction at `pc`.
+    ///
+    /// The scratchpad is the D-cache, the fetches go to the bus where nothing
+    /// is mapped at its address, so they are bus errors
+    fn fetch_instruction<P: BusLine>(&mut self, bus: &mut P) -> Option<u32> {
+        let pc = self.regs.pc;
+        if memory::is_scratchpad_address(pc) {
+            log::error!("BusErrorInstructionFetch: scratchpad at {:08X}", pc);
+            self.elapsed_cycles += 2;
+            self.execute_exception(Exception::BusErrorInstructionFetch);
+            return None;
+        }
+        self.bus_read_u32(bus, pc)
+    }
+
     fn bus_read_u32<P: BusLine>(&mut self, bus: &mut P, addr: u32) -> Option<u32> {
         self.elapsed_cycles += 2;
 
@@ -910,6 +1231,7 @@ impl Cpu {
                             err
                         );
                         self.print_call_stack();
+                        self.warnings.bus_error(&err);
                         None
                     }
                 }
@@ -942,6 +1264,7 @@ impl Cpu {
                             err
                         );
                         self.print_call_stack();
+                        self.warnings.bus_error(&err);
                     }
                 }
             }
@@ -977,6 +1300,7 @@ impl Cpu {
                             err
                         );
                         self.print_call_stack();
+                        self.warnings.bus_error(&err);
                         None
                     }
                 }
@@ -1008,6 +1332,7 @@ impl Cpu {
                             err
                         );
                         self.print_call_stack();
+                        self.warnings.bus_error(&err);
                     }
                 }
             }
@@ -1031,6 +1356,7 @@ impl Cpu {
                             err
                         );
                         self.print_call_stack();
+                        self.warnings.bus_error(&err);
                         0
                     }
                 }
@@ -1038,13 +1364,15 @@ impl Cpu {
         }
     }
 
-    fn bus_write_u8<P: BusLine>(&mut self, bus: &mut P, addr: u32, data: u8) {
+    /// `data` is the whole register, only the lowest 8 bits are written,
+    /// except for devices that use the rest, see [`BusLine::write_u8_from_word`]
+    fn bus_write_u8<P: BusLine>(&mut self, bus: &mut P, addr: u32, data: u32) {
         self.elapsed_cycles += 1;
         self.debugger.trace_write(addr, 8);
         match addr {
             0x00000000..=0x00001000 if self.cop0.is_cache_isolated() => {}
             _ => {
-                let r = bus.write_u8(addr, data);
+                let r = bus.write_u8_from_word(addr, data);
                 if let Err(err) = r {
                     log::error!(
                         "bus_write_u8: {:08X} at {:08X}: {}",
@@ -1053,6 +1381,7 @@ impl Cpu {
                         err
                     );
                     self.print_call_stack();
+                    self.warnings.bus_error(&err);
                 }
             }
         }
@@ -0,0 +1,282 @@
+//! The names of the BIOS kernel functions, called by jumping to `A0h`, `B0h` or
+//! `C0h` with the function number in `t1`.
+//!
+//! The names are the ones used in the nocash PSX specs, the entries that only
+//! return `0`, or jump to `SystemError` for being unused, have no name.
+
+/// The name of the kernel function `function` of the table at `table`
+/// (`0xA0`, `0xB0` or `0xC0`), `None` if it's unknown or unused.
+///
+/// ```
+/// # use trapezoid_core::cpu::bios_function_name;
+/// assert_eq!(bios_function_name(0xA0, 0x3F), Some("printf"));
+/// assert_eq!(bios_function_name(0xB0, 0x17), Some("ReturnFromException"));
+/// assert_eq!(bios_function_name(0xD0, 0x00), None);
+/// ```
+pub fn bios_function_name(table: u32, function: u32) -> Option<&'static str> {
+    match table {
+        0xA0 => a0_function_name(function),
+        0xB0 => b0_function_name(function),
+        0xC0 => c0_function_name(function),
+        _ => None,
+    }
+}
+
+fn a0_function_name(function: u32) -> Option<&'static str> {
+    let name = match function {
+        0x00 => "FileOpen",
+        0x01 => "FileSeek",
+        0x02 => "FileRead",
+        0x03 => "FileWrite",
+        0x04 => "FileClose",
+        0x05 => "FileIoctl",
+        0x06 => "exit",
+        0x07 => "FileGetDeviceFlag",
+        0x08 => "FileGetc",
+        0x09 => "FilePutc",
+        0x0A => "todigit",
+        0x0B => "atof",
+        0x0C => "strtoul",
+        0x0D => "strtol",
+        0x0E => "abs",
+        0x0F => "labs",
+        0x10 => "atoi",
+        0x11 => "atol",
+        0x12 => "atob",
+        0x13 => "SaveState",
+        0x14 => "RestoreState",
+        0x15 => "strcat",
+        0x16 => "strncat",
+        0x17 => "strcmp",
+        0x18 => "strncmp",
+        0x19 => "strcpy",
+        0x1A => "strncpy",
+        0x1B => "strlen",
+        0x1C => "index",
+        0x1D => "rindex",
+        0x1E => "strchr",
+        0x1F => "strrchr",
+        0x20 => "strpbrk",
+        0x21 => "strspn",
+        0x22 => "strcspn",
+        0x23 => "strtok",
+        0x24 => "strstr",
+        0x25 => "toupper",
+        0x26 => "tolower",
+        0x27 => "bcopy",
+        0x28 => "bzero",
+        0x29 => "bcmp",
+        0x2A => "memcpy",
+        0x2B => "memset",
+        0x2C => "memmove",
+        0x2D => "memcmp",
+        0x2E => "memchr",
+        0x2F => "rand",
+        0x30 => "srand",
+        0x31 => "qsort",
+        0x32 => "strtod",
+        0x33 => "malloc",
+        0x34 => "free",
+        0x35 => "lsearch",
+        0x36 => "bsearch",
+        0x37 => "calloc",
+        0x38 => "realloc",
+        0x39 => "InitHeap",
+        0x3A => "SystemErrorExit",
+        0x3B => "std_in_getchar",
+        0x3C => "std_out_putchar",
+        0x3D => "std_in_gets",
+        0x3E => "std_out_puts",
+        0x3F => "printf",
+        0x40 => "SystemErrorUnresolvedException",
+        0x41 => "LoadExeHeader",
+        0x42 => "LoadExeFile",
+        0x43 => "DoExecute",
+        0x44 => "FlushCache",
+        0x45 => "init_a0_b0_c0_vectors",
+        0x46 => "GPU_dw",
+        0x47 => "gpu_send_dma",
+        0x48 => "SendGP1Command",
+        0x49 => "GPU_cw",
+        0x4A => "GPU_cwp",
+        0x4B => "send_gpu_linked_list",
+        0x4C => "gpu_abort_dma",
+        0x4D => "GetGPUStatus",
+        0x4E => "gpu_sync",
+        0x51 => "LoadAndExecute",
+        0x52 => "GetSysSp",
+        0x54 | 0x71 => "CdInit",
+        0x55 | 0x70 => "_bu_init",
+        0x56 | 0x72 => "CdRemove",
+        0x5B => "dev_tty_init",
+        0x5C => "dev_tty_open",
+        0x5D => "dev_tty_in_out",
+        0x5E => "dev_tty_ioctl",
+        0x5F => "dev_cd_open",
+        0x60 => "dev_cd_read",
+        0x61 => "dev_cd_close",
+        0x62 => "dev_cd_firstfile",
+        0x63 => "dev_cd_nextfile",
+        0x64 => "dev_cd_chdir",
+        0x65 => "dev_card_open",
+        0x66 => "dev_card_read",
+        0x67 => "dev_card_write",
+        0x68 => "dev_card_close",
+        0x69 => "dev_card_firstfile",
+        0x6A => "dev_card_nextfile",
+        0x6B => "dev_card_erase",
+        0x6C => "dev_card_undelete",
+        0x6D => "dev_card_format",
+        0x6E => "dev_card_rename",
+        0x6F => "card_clear_error",
+        0x78 => "CdAsyncSeekL",
+        0x7C => "CdAsyncGetStatus",
+        0x7E => "CdAsyncReadSector",
+        0x81 => "CdAsyncSetMode",
+        0x90 => "CdromIoIrqFunc1",
+        0x91 => "CdromDmaIrqFunc1",
+        0x92 => "CdromIoIrqFunc2",
+        0x93 => "CdromDmaIrqFunc2",
+        0x94 => "CdromGetInt5errCode",
+        0x95 => "CdInitSubFunc",
+        0x96 => "AddCDROMDevice",
+        0x97 => "AddMemCardDevice",
+        0x98 => "AddDuartTtyDevice",
+        0x99 => "AddDummyTtyDevice",
+        0x9C => "SetConf",
+        0x9D => "GetConf",
+        0x9E => "SetCdromIrqAutoAbort",
+        0x9F => "SetMemSize",
+        0xA0 => "WarmBoot",
+        0xA1 => "SystemErrorBootOrDiskFailure",
+        0xA2 => "EnqueueCdIntr",
+        0xA3 => "DequeueCdIntr",
+        0xA4 => "CdGetLbn",
+        0xA5 => "CdReadSector",
+        0xA6 => "CdGetStatus",
+        0xA7 => "bu_callback_okay",
+        0xA8 => "bu_callback_err_write",
+        0xA9 => "bu_callback_err_busy",
+        0xAA => "bu_callback_err_eject",
+        0xAB => "_card_info",
+        0xAC => "_card_async_load_directory",
+        0xAD => "set_card_auto_format",
+        0xAE => "bu_callback_err_prev_write",
+        0xAF => "card_write_test",
+        0xB2 => "ioabort_raw",
+        0xB4 => "GetSystemInfo",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn b0_function_name(function: u32) -> Option<&'static str> {
+    let name = match function {
+        0x00 => "alloc_kernel_memory",
+        0x01 => "free_kernel_memory",
+        0x02 => "init_timer",
+        0x03 => "get_timer",
+        0x04 => "enable_timer_irq",
+        0x05 => "disable_timer_irq",
+        0x06 => "restart_timer",
+        0x07 => "DeliverEvent",
+        0x08 => "OpenEvent",
+        0x09 => "CloseEvent",
+        0x0A => "WaitEvent",
+        0x0B => "TestEvent",
+        0x0C => "EnableEvent",
+        0x0D => "DisableEvent",
+        0x0E => "OpenThread",
+        0x0F => "CloseThread",
+        0x10 => "ChangeThread",
+        0x11 => "jump_to_00000000h",
+        0x12 => "InitPad",
+        0x13 => "StartPad",
+        0x14 => "StopPad",
+        0x15 => "OutdatedPadInitAndStart",
+        0x16 => "OutdatedPadGetButtons",
+        0x17 => "ReturnFromException",
+        0x18 => "SetDefaultExitFromException",
+        0x19 => "SetCustomExitFromException",
+        0x20 => "UnDeliverEvent",
+        0x32 => "FileOpen",
+        0x33 => "FileSeek",
+        0x34 => "FileRead",
+        0x35 => "FileWrite",
+        0x36 => "FileClose",
+        0x37 => "FileIoctl",
+        0x38 => "exit",
+        0x39 => "FileGetDeviceFlag",
+        0x3A => "FileGetc",
+        0x3B => "FilePutc",
+        0x3C => "std_in_getchar",
+        0x3D => "std_out_putchar",
+        0x3E => "std_in_gets",
+        0x3F => "std_out_puts",
+        0x40 => "chdir",
+        0x41 => "FormatDevice",
+        0x42 => "firstfile",
+        0x43 => "nextfile",
+        0x44 => "FileRename",
+        0x45 => "FileDelete",
+        0x46 => "FileUndelete",
+        0x47 => "AddDevice",
+        0x48 => "RemoveDevice",
+        0x49 => "PrintInstalledDevices",
+        0x4A => "InitCard",
+        0x4B => "StartCard",
+        0x4C => "StopCard",
+        0x4D => "_card_info_subfunc",
+        0x4E => "write_card_sector",
+        0x4F => "read_card_sector",
+        0x50 => "allow_new_card",
+        0x51 => "Krom2RawAdd",
+        0x53 => "Krom2Offset",
+        0x54 => "GetLastError",
+        0x55 => "GetLastFileError",
+        0x56 => "GetC0Table",
+        0x57 => "GetB0Table",
+        0x58 => "get_bu_callback_port",
+        0x59 => "testdevice",
+        0x5B => "ChangeClearPad",
+        0x5C => "get_card_status",
+        0x5D => "wait_card_status",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn c0_function_name(function: u32) -> Option<&'static str> {
+    let name = match function {
+        0x00 => "EnqueueTimerAndVblankIrqs",
+        0x01 => "EnqueueSyscallHandler",
+        0x02 => "SysEnqIntRP",
+        0x03 => "SysDeqIntRP",
+        0x04 => "get_free_EvCB_slot",
+        0x05 => "get_free_TCB_slot",
+        0x06 => "ExceptionHandler",
+        0x07 => "InstallExceptionHandlers",
+        0x08 => "SysInitMemory",
+        0x09 => "SysInitKernelVariables",
+        0x0A => "ChangeClearRCnt",
+        0x0C => "InitDefInt",
+        0x0D => "SetIrqAutoAck",
+        0x0E => "dev_sio_init",
+        0x0F => "dev_sio_open",
+        0x10 => "dev_sio_in_out",
+        0x11 => "dev_sio_ioctl",
+        0x12 => "InstallDevices",
+        0x13 => "FlushStdInOutPut",
+        0x15 => "tty_cdevinput",
+        0x16 => "tty_cdevscan",
+        0x17 => "tty_circgetc",
+        0x18 => "tty_circputc",
+        0x19 => "ioabort",
+        0x1A => "set_card_find_mode",
+        0x1B => "KernelRedirect",
+        0x1C => "AdjustA0Table",
+        0x1D => "get_card_find_mode",
+        _ => return None,
+    };
+    Some(name)
+}
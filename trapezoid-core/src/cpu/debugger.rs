@@ -13,7 +13,8 @@ struct EnabledBreakpoints {
     normal: bool,
 }
 
-type InstructionTraceHandler = Box<dyn Fn(&Registers, &Instruction, bool)>;
+type InstructionTraceHandler = Box<dyn Fn(&Registers, &Instruction, bool) + Send>;
+type CallTraceHandler = Box<dyn Fn(u32, u32, usize) + Send>;
 
 pub struct Debugger {
     paused: bool,
@@ -32,6 +33,7 @@ pub struct Debugger {
     step_over: bool,
 
     instruction_trace_handler: Option<InstructionTraceHandler>,
+    call_trace_handler: Option<CallTraceHandler>,
 
     last_instruction: Instruction,
 }
@@ -51,6 +53,7 @@ impl Debugger {
             step: false,
             step_over: false,
             instruction_trace_handler: None,
+            call_trace_handler: None,
 
             last_instruction: Instruction::from_u32(0, 0),
         }
@@ -125,9 +128,11 @@ impl Debugger {
     pub(crate) fn trace_instruction(
         &mut self,
         regs: &Registers,
-        jumping: bool,
+        jump_dest: Option<u32>,
         instruction: &Instruction,
     ) -> bool {
+        let jumping = jump_dest.is_some();
+
         if let Some(breakpoints_data) = self.instruction_breakpoints.get_mut(&regs.pc) {
             if breakpoints_data.step_over {
                 breakpoints_data.step_over = false;
@@ -166,6 +171,10 @@ impl Debugger {
             match self.last_instruction.opcode {
                 Opcode::Jal | Opcode::Jalr => {
                     self.call_stack.push(self.last_instruction.pc + 8);
+
+                    if let (Some(handler), Some(target)) = (&self.call_trace_handler, jump_dest) {
+                        handler(self.last_instruction.pc, target, self.call_stack.len());
+                    }
                 }
                 Opcode::Jr => {
                     // Sometimes, the return address is not always the last on the stack.
@@ -253,6 +262,14 @@ impl Debugger {
         self.instruction_trace_handler = handler;
     }
 
+    /// The handler is called when a `jal` or `jalr` is executed, its arguments are:
+    /// - the address of the call instruction
+    /// - the target of the call
+    /// - the nesting depth, after the call
+    pub fn set_call_trace_handler(&mut self, handler: Option<CallTraceHandler>) {
+        self.call_trace_handler = handler;
+    }
+
     pub fn add_breakpoint(&mut self, address: u32) {
         self.instruction_breakpoints
             .entry(address)
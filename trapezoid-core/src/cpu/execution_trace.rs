@@ -0,0 +1,158 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+};
+
+use super::{instruction::Instruction, register::Registers, RegisterType};
+
+/// Configuration of the execution trace, see [`Cpu::set_trace`](super::Cpu::set_trace)
+#[derive(Debug, Clone)]
+pub struct TraceConfig {
+    /// The file to write the trace to, it will be truncated
+    pub path: PathBuf,
+    /// Start tracing when this address is executed, or immediately if `None`
+    pub start_pc: Option<u32>,
+    /// Stop tracing when this address is reached, it is not included in the trace
+    pub end_pc: Option<u32>,
+    /// Stop tracing after this many instructions
+    pub max_instructions: Option<u64>,
+    /// Append the registers modified by each instruction
+    pub include_registers: bool,
+}
+
+/// Writes one line per executed instruction, in the form:
+///
+/// ```text
+/// 80010000: 27BDFFE8 addiu sp, sp, 0xFFE8 ; sp=801FFEE8
+/// 80010004: 0C004AF1 jal 0x0004AF1 => 0x80012BC4 ; ra=8001000C
+/// 80010008: 00000000 _nop
+/// ```
+///
+/// instructions in a delay slot are prefixed with `_`. Values written by loads
+/// show on the next instruction, since that's when they become visible.
+pub(crate) struct ExecutionTracer {
+    writer: BufWriter<File>,
+    config: TraceConfig,
+    started: bool,
+    done: bool,
+    written: u64,
+
+    general_regs_before: [u32; 32],
+    hi_before: u32,
+    lo_before: u32,
+}
+
+impl ExecutionTracer {
+    pub(crate) fn new(config: TraceConfig) -> io::Result<Self> {
+        let file = File::create(&config.path)?;
+        Ok(Self {
+            // big enough to not hit the disk for every few lines
+            writer: BufWriter::with_capacity(1 << 20, file),
+            started: config.start_pc.is_none(),
+            config,
+            done: false,
+            written: 0,
+            general_regs_before: [0; 32],
+            hi_before: 0,
+            lo_before: 0,
+        })
+    }
+
+    pub(crate) fn done(&self) -> bool {
+        self.done
+    }
+
+    /// Called before executing the instruction at `regs.pc`,
+    /// returns `true` if it should be recorded with [`Self::record`]
+    pub(crate) fn begin(&mut self, regs: &Registers) -> bool {
+        if !self.started {
+            self.started = self.config.start_pc == Some(regs.pc);
+        }
+        if self.config.end_pc == Some(regs.pc) {
+            self.done = true;
+        }
+        if !self.started || self.done {
+            return false;
+        }
+
+        if self.config.include_registers {
+            self.general_regs_before = regs.general_regs;
+            self.hi_before = regs.hi;
+            self.lo_before = regs.lo;
+        }
+        true
+    }
+
+    /// Write the line of `instruction`, after it was executed
+    pub(crate) fn record(
+        &mut self,
+        instruction: &Instruction,
+        regs: &Registers,
+        in_delay_slot: bool,
+    ) {
+        if let Err(e) = self.write_line(instruction, regs, in_delay_slot) {
+            log::error!("Failed to write the execution trace: {}", e);
+            self.done = true;
+            return;
+        }
+
+        self.written += 1;
+        if Some(self.written) == self.config.max_instructions {
+            self.done = true;
+        }
+        if self.done {
+            if let Err(e) = self.writer.flush() {
+                log::error!("Failed to write the execution trace: {}", e);
+            }
+        }
+    }
+
+    fn write_line(
+        &mut self,
+        instruction: &Instruction,
+        regs: &Registers,
+        in_delay_slot: bool,
+    ) -> io::Result<()> {
+        write!(
+            self.writer,
+            "{:08X}: {:08X} {}{}",
+            instruction.pc,
+            instruction.instruction,
+            if in_delay_slot { "_" } else { "" },
+            instruction
+        )?;
+
+        if self.config.include_registers {
+            let mut separator = " ;";
+            for (i, (before, after)) in self
+                .general_regs_before
+                .iter()
+                .zip(regs.general_regs.iter())
+                .enumerate()
+            {
+                if before != after {
+                    write!(
+                        self.writer,
+                        "{} {}={:08X}",
+                        separator,
+                        RegisterType::from(i as u8),
+                        after
+                    )?;
+                    separator = "";
+                }
+            }
+            for (name, before, after) in [
+                ("hi", self.hi_before, regs.hi),
+                ("lo", self.lo_before, regs.lo),
+            ] {
+                if before != after {
+                    write!(self.writer, "{} {}={:08X}", separator, name, after)?;
+                    separator = "";
+                }
+            }
+        }
+
+        writeln!(self.writer)
+    }
+}
@@ -0,0 +1,575 @@
+//! A very small high level emulation of the BIOS kernel.
+//!
+//! This is only meant to run homebrew EXEs (and test EXEs) without a BIOS
+//! image, it does not try to emulate the BIOS in any real capacity.
+//! The kernel functions are called through the `A0`, `B0` and `C0` jump
+//! tables, and instead of executing the code there, we intercept the jump
+//! and perform the function in Rust, then return to `ra`.
+//!
+//! Exceptions are also intercepted at the default exception vector
+//! (`0x80000080`), `syscall` handles Enter/ExitCriticalSection and interrupts
+//! are acknowledged and ignored since there are no events to deliver.
+
+use crate::{coprocessor::SystemControlCoprocessor, memory::BusLine};
+
+use super::{RegisterType, Registers};
+
+/// Approximate cost of a kernel function call, the real BIOS functions would
+/// take a lot more than this, but we don't need to be accurate here.
+pub(super) const HLE_CALL_CYCLES: u32 = 20;
+
+const I_STAT: u32 = 0x1F801070;
+const I_MASK: u32 = 0x1F801074;
+
+pub(super) struct HleBios {
+    print_tty: bool,
+    line_temp_buffer: String,
+    rand_seed: u32,
+    next_event_id: u32,
+}
+
+impl HleBios {
+    pub fn new(print_tty: bool) -> Self {
+        Self {
+            print_tty,
+            line_temp_buffer: String::new(),
+            rand_seed: 0,
+            next_event_id: 0,
+        }
+    }
+
+    pub fn set_print_tty(&mut self, print_tty: bool) {
+        self.print_tty = print_tty;
+    }
+
+    /// Check if the `pc` is at one of the kernel entry points, and if so,
+    /// perform the operation and return `true`, the `pc` will be updated
+    /// to the return address.
+    pub fn try_handle<P: BusLine>(
+        &mut self,
+        regs: &mut Registers,
+        cop0: &mut SystemControlCoprocessor,
+        bus: &mut P,
+    ) -> bool {
+        // the kernel is in the first 64KB of RAM, so any of the segments can be used
+        let entry = regs.pc & 0x1FFFFFFF;
+        if !matches!(entry, 0x80 | 0xA0 | 0xB0 | 0xC0) {
+            return false;
+        }
+        // any pending load must be visible to the kernel function
+        regs.flush_delayed_load();
+
+        match entry {
+            0xA0 => self.call_a0(regs, bus),
+            0xB0 => self.call_b0(regs, bus),
+            0xC0 => self.call_c0(regs),
+            _ => {
+                self.handle_exception(regs, cop0, bus);
+                return true;
+            }
+        }
+
+        regs.write(RegisterType::Pc, regs.read(RegisterType::Ra));
+        true
+    }
+}
+
+impl HleBios {
+    /// Get the function argument `n` following the calling convention,
+    /// the first 4 are in registers, and the rest are in the stack after
+    /// the reserved space of the first 4.
+    fn arg<P: BusLine>(regs: &Registers, bus: &mut P, n: u32) -> u32 {
+        match n {
+            0 => regs.read(RegisterType::A0),
+            1 => regs.read(RegisterType::A1),
+            2 => regs.read(RegisterType::A2),
+            3 => regs.read(RegisterType::A3),
+            _ => bus
+                .read_u32(regs.read(RegisterType::Sp).wrapping_add(n * 4))
+                .unwrap_or(0),
+        }
+    }
+
+    fn ret(regs: &mut Registers, value: u32) {
+        regs.write(RegisterType::V0, value);
+    }
+
+    fn read_u8<P: BusLine>(bus: &mut P, addr: u32) -> u8 {
+        bus.read_u8(addr).unwrap_or_else(|err| {
+            log::error!("hle bios: read_u8 {:08X}: {}", addr, err);
+            0
+        })
+    }
+
+    fn write_u8<P: BusLine>(bus: &mut P, addr: u32, data: u8) {
+        if let Err(err) = bus.write_u8(addr, data) {
+            log::error!("hle bios: write_u8 {:08X}: {}", addr, err);
+        }
+    }
+
+    fn read_string<P: BusLine>(bus: &mut P, mut addr: u32) -> Vec<u8> {
+        let mut s = Vec::new();
+        // limit the length in case the pointer is garbage
+        while s.len() < 0x10000 {
+            let c = Self::read_u8(bus, addr);
+            if c == 0 {
+                break;
+            }
+            s.push(c);
+            addr = addr.wrapping_add(1);
+        }
+        s
+    }
+
+    fn putchar(&mut self, ch: u8) {
+        let ch = ch as char;
+        // printing each line on line break to not get mixed with logs
+        if ch == '\n' {
+            if self.print_tty {
+                println!("DEBUG: {}", self.line_temp_buffer);
+            }
+            self.line_temp_buffer.clear();
+        } else {
+            self.line_temp_buffer.push(ch);
+        }
+    }
+
+    fn memcpy<P: BusLine>(bus: &mut P, dst: u32, src: u32, len: u32) {
+        if dst > src && dst < src.wrapping_add(len) {
+            // overlapping, copy backward
+            for i in (0..len).rev() {
+                let b = Self::read_u8(bus, src.wrapping_add(i));
+                Self::write_u8(bus, dst.wrapping_add(i), b);
+            }
+        } else {
+            for i in 0..len {
+                let b = Self::read_u8(bus, src.wrapping_add(i));
+                Self::write_u8(bus, dst.wrapping_add(i), b);
+            }
+        }
+    }
+
+    fn memset<P: BusLine>(bus: &mut P, dst: u32, value: u8, len: u32) {
+        for i in 0..len {
+            Self::write_u8(bus, dst.wrapping_add(i), value);
+        }
+    }
+
+    /// A minimal `printf`, supports `%d %i %u %x %X %p %c %s %%` with
+    /// optional `-`, `0` flags and width.
+    ///
+    /// Returns the number of characters printed
+    fn printf<P: BusLine>(&mut self, regs: &Registers, bus: &mut P) -> u32 {
+        let fmt_addr = Self::arg(regs, bus, 0);
+        let fmt = Self::read_string(bus, fmt_addr);
+        let mut arg_n = 1;
+        let mut out = Vec::new();
+
+        let mut iter = fmt.into_iter().peekable();
+        while let Some(c) = iter.next() {
+            if c != b'%' {
+                out.push(c);
+                continue;
+            }
+
+            let mut left_align = false;
+            let mut zero_pad = false;
+            let mut width = 0;
+            while let Some(&f) = iter.peek() {
+                match f {
+                    b'-' => left_align = true,
+                    b'0' if width == 0 => zero_pad = true,
+                    b'0'..=b'9' => width = width * 10 + (f - b'0') as usize,
+                    // length modifiers are all 32bit here
+                    b'l' | b'h' => {}
+                    _ => break,
+                }
+                iter.next();
+            }
+
+            let Some(spec) = iter.next() else {
+                break;
+            };
+            let formatted = match spec {
+                b'%' => b"%".to_vec(),
+                b'c' => vec![Self::arg(regs, bus, arg_n) as u8],
+                b's' => {
+                    let addr = Self::arg(regs, bus, arg_n);
+                    Self::read_string(bus, addr)
+                }
+                b'd' | b'i' => format!("{}", Self::arg(regs, bus, arg_n) as i32).into_bytes(),
+                b'u' => format!("{}", Self::arg(regs, bus, arg_n)).into_bytes(),
+                b'x' => format!("{:x}", Self::arg(regs, bus, arg_n)).into_bytes(),
+                b'X' => format!("{:X}", Self::arg(regs, bus, arg_n)).into_bytes(),
+                b'p' => format!("{:08x}", Self::arg(regs, bus, arg_n)).into_bytes(),
+                _ => {
+                    log::warn!("hle bios: printf unsupported format '{}'", spec as char);
+                    vec![b'%', spec]
+                }
+            };
+            if spec != b'%' {
+                arg_n += 1;
+            }
+
+            let padding = width.saturating_sub(formatted.len());
+            let pad_char = if zero_pad && !left_align { b'0' } else { b' ' };
+            if !left_align {
+                out.extend(std::iter::repeat(pad_char).take(padding));
+            }
+            out.extend(formatted);
+            if left_align {
+                out.extend(std::iter::repeat(b' ').take(padding));
+            }
+        }
+
+        for &c in &out {
+            self.putchar(c);
+        }
+        out.len() as u32
+    }
+}
+
+impl HleBios {
+    fn call_a0<P: BusLine>(&mut self, regs: &mut Registers, bus: &mut P) {
+        let function = regs.read(RegisterType::T1) & 0xFF;
+        let a0 = regs.read(RegisterType::A0);
+        let a1 = regs.read(RegisterType::A1);
+        let a2 = regs.read(RegisterType::A2);
+
+        log::trace!("hle bios: A0({:02X})", function);
+        let result = match function {
+            // abs/labs
+            0x0E | 0x0F => (a0 as i32).unsigned_abs(),
+            // SaveState (setjmp)
+            0x13 => {
+                let saved = [
+                    RegisterType::Ra,
+                    RegisterType::Sp,
+                    RegisterType::Fp,
+                    RegisterType::S0,
+                    RegisterType::S1,
+                    RegisterType::S2,
+                    RegisterType::S3,
+                    RegisterType::S4,
+                    RegisterType::S5,
+                    RegisterType::S6,
+                    RegisterType::S7,
+                    RegisterType::Gp,
+                ];
+                for (i, reg) in saved.into_iter().enumerate() {
+                    let _ = bus.write_u32(a0.wrapping_add(i as u32 * 4), regs.read(reg));
+                }
+                0
+            }
+            // RestoreState (longjmp)
+            0x14 => {
+                let saved = [
+                    RegisterType::Ra,
+                    RegisterType::Sp,
+                    RegisterType::Fp,
+                    RegisterType::S0,
+                    RegisterType::S1,
+                    RegisterType::S2,
+                    RegisterType::S3,
+                    RegisterType::S4,
+                    RegisterType::S5,
+                    RegisterType::S6,
+                    RegisterType::S7,
+                    RegisterType::Gp,
+                ];
+                for (i, reg) in saved.into_iter().enumerate() {
+                    let value = bus.read_u32(a0.wrapping_add(i as u32 * 4)).unwrap_or(0);
+                    regs.write(reg, value);
+                }
+                a1
+            }
+            // strcat
+            0x15 => {
+                let dst_len = Self::read_string(bus, a0).len() as u32;
+                let src_len = Self::read_string(bus, a1).len() as u32;
+                Self::memcpy(bus, a0.wrapping_add(dst_len), a1, src_len + 1);
+                a0
+            }
+            // strcmp
+            0x17 => {
+                let s1 = Self::read_string(bus, a0);
+                let s2 = Self::read_string(bus, a1);
+                match s1.cmp(&s2) {
+                    std::cmp::Ordering::Less => -1i32 as u32,
+                    std::cmp::Ordering::Equal => 0,
+                    std::cmp::Ordering::Greater => 1,
+                }
+            }
+            // strcpy
+            0x19 => {
+                let len = Self::read_string(bus, a1).len() as u32;
+                Self::memcpy(bus, a0, a1, len + 1);
+                a0
+            }
+            // strlen
+            0x1B => Self::read_string(bus, a0).len() as u32,
+            // toupper
+            0x25 => (a0 as u8).to_ascii_uppercase() as u32,
+            // tolower
+            0x26 => (a0 as u8).to_ascii_lowercase() as u32,
+            // bzero
+            0x28 => {
+                Self::memset(bus, a0, 0, a1);
+                a0
+            }
+            // memcpy/memmove
+            0x2A | 0x2C => {
+                Self::memcpy(bus, a0, a1, a2);
+                a0
+            }
+            // memset
+            0x2B => {
+                Self::memset(bus, a0, a1 as u8, a2);
+                a0
+            }
+            // rand
+            0x2F => {
+                self.rand_seed = self.rand_seed.wrapping_mul(0x41C64E6D).wrapping_add(0x3039);
+                (self.rand_seed >> 16) & 0x7FFF
+            }
+            // srand
+            0x30 => {
+                self.rand_seed = a0;
+                0
+            }
+            // InitHeap, the heap functions are not implemented
+            0x39 => 0,
+            // std_out_putchar
+            0x3C => {
+                self.putchar(a0 as u8);
+                a0
+            }
+            // std_out_puts
+            0x3E => {
+                for c in Self::read_string(bus, a0) {
+                    self.putchar(c);
+                }
+                self.putchar(b'\n');
+                0
+            }
+            // printf
+            0x3F => self.printf(regs, bus),
+            // FlushCache
+            0x44 => 0,
+            _ => {
+                log::warn!(
+                    "hle bios: unimplemented A0({:02X}), a0={:08X}, a1={:08X}, a2={:08X}",
+                    function,
+                    a0,
+                    a1,
+                    a2
+                );
+                0
+            }
+        };
+
+        Self::ret(regs, result);
+    }
+
+    fn call_b0<P: BusLine>(&mut self, regs: &mut Registers, bus: &mut P) {
+        let function = regs.read(RegisterType::T1) & 0xFF;
+        let a0 = regs.read(RegisterType::A0);
+
+        log::trace!("hle bios: B0({:02X})", function);
+        let result = match function {
+            // OpenEvent, there are no events so just give a unique handle
+            0x08 => {
+                let id = self.next_event_id;
+                self.next_event_id = (self.next_event_id + 1) & 0xFFFF;
+                0xF1000000 | id
+            }
+            // CloseEvent, WaitEvent, EnableEvent, DisableEvent
+            0x09 | 0x0A | 0x0C | 0x0D => 1,
+            // TestEvent
+            0x0B => 0,
+            // SetDefaultExitFromException, SetCustomExitFromException
+            0x18 | 0x19 => 0,
+            // InitPad, StartPad, StopPad, ChangeClearPad
+            0x12 | 0x13 | 0x14 | 0x5B => 1,
+            // std_out_putchar
+            0x3D => {
+                self.putchar(a0 as u8);
+                a0
+            }
+            // std_out_puts
+            0x3F => {
+                for c in Self::read_string(bus, a0) {
+                    self.putchar(c);
+                }
+                0
+            }
+            _ => {
+                log::warn!(
+                    "hle bios: unimplemented B0({:02X}), a0={:08X}",
+                    function,
+                    a0
+                );
+                0
+            }
+        };
+
+        Self::ret(regs, result);
+    }
+
+    fn call_c0(&mut self, regs: &mut Registers) {
+        let function = regs.read(RegisterType::T1) & 0xFF;
+
+        log::trace!("hle bios: C0({:02X})", function);
+        match function {
+            // SysEnqIntRP, SysDeqIntRP, ChangeClearRCnt
+            0x02 | 0x03 | 0x0A => {}
+            _ => {
+                log::warn!("hle bios: unimplemented C0({:02X})", function);
+            }
+        }
+
+        Self::ret(regs, 0);
+    }
+
+    fn handle_exception<P: BusLine>(
+        &mut self,
+        regs: &mut Registers,
+        cop0: &mut SystemControlCoprocessor,
+        bus: &mut P,
+    ) {
+        let cause_code = (cop0.read_cause() >> 2) & 0x1F;
+        let epc = cop0.read_epc();
+
+        let return_pc = match cause_code {
+            // Interrupt
+            0x00 => {
+                // nothing to deliver the events to, just acknowledge them
+                // so that we don't get stuck in the interrupt forever
+                let stat = bus.read_u32(I_STAT).unwrap_or(0);
+                let mask = bus.read_u32(I_MASK).unwrap_or(0);
+                let _ = bus.write_u32(I_STAT, !(stat & mask));
+                epc
+            }
+            // Syscall
+            0x08 => {
+                let mut sr = cop0.read_sr();
+                match regs.read(RegisterType::A0) {
+                    // EnterCriticalSection
+                    1 => {
+                        let was_enabled = sr & 0x404 == 0x404;
+                        sr &= !0x404;
+                        Self::ret(regs, was_enabled as u32);
+                    }
+                    // ExitCriticalSection
+                    2 => {
+                        sr |= 0x404;
+                    }
+                    n => {
+                        log::warn!("hle bios: unimplemented syscall({:X})", n);
+                    }
+                }
+                cop0.write_sr(sr);
+                epc.wrapping_add(4)
+            }
+            _ => {
+                log::error!(
+                    "hle bios: unhandled exception cause={:02X}, epc={:08X}, skipping instruction",
+                    cause_code,
+                    epc
+                );
+                epc.wrapping_add(4)
+            }
+        };
+
+        // rfe
+        let mut sr = cop0.read_sr();
+        let second_two_bits = (sr >> 2) & 3;
+        let third_two_bits = (sr >> 4) & 3;
+        sr &= !0b1111;
+        sr |= second_two_bits;
+        sr |= third_two_bits << 2;
+        cop0.write_sr(sr);
+
+        regs.write(RegisterType::Pc, return_pc);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Result;
+
+    struct TestRam(Vec<u8>);
+
+    impl BusLine for TestRam {
+        fn read_u32(&mut self, addr: u32) -> Result<u32> {
+            let i = (addr & 0xFFFF) as usize;
+            Ok(u32::from_le_bytes(self.0[i..i + 4].try_into().unwrap()))
+        }
+
+        fn write_u32(&mut self, addr: u32, data: u32) -> Result<()> {
+            let i = (addr & 0xFFFF) as usize;
+            self.0[i..i + 4].copy_from_slice(&data.to_le_bytes());
+            Ok(())
+        }
+
+        fn read_u8(&mut self, addr: u32) -> Result<u8> {
+            Ok(self.0[(addr & 0xFFFF) as usize])
+        }
+
+        fn write_u8(&mut self, addr: u32, data: u8) -> Result<()> {
+            self.0[(addr & 0xFFFF) as usize] = data;
+            Ok(())
+        }
+    }
+
+    fn call(hle: &mut HleBios, regs: &mut Registers, bus: &mut TestRam, table: u32, f: u32) {
+        regs.write(RegisterType::T1, f);
+        regs.write(RegisterType::Ra, 0x80010000);
+        regs.write(RegisterType::Pc, table);
+        assert!(hle.try_handle(regs, &mut SystemControlCoprocessor::default(), bus));
+        assert_eq!(regs.read(RegisterType::Pc), 0x80010000);
+    }
+
+    #[test]
+    fn printf_and_string_functions() {
+        let mut hle = HleBios::new(false);
+        let mut regs = Registers::new();
+        let mut bus = TestRam(vec![0; 0x10000]);
+
+        bus.0[0x1000..0x1000 + 12].copy_from_slice(b"%s=%04x %d\0");
+        bus.0[0x2000..0x2000 + 4].copy_from_slice(b"val\0");
+        regs.write(RegisterType::A0, 0x1000);
+        regs.write(RegisterType::A1, 0x2000);
+        regs.write(RegisterType::A2, 0xAB);
+        regs.write(RegisterType::A3, -5i32 as u32);
+        call(&mut hle, &mut regs, &mut bus, 0xA0, 0x3F);
+        assert_eq!(hle.line_temp_buffer, "val=00ab -5");
+        assert_eq!(regs.read(RegisterType::V0), 11);
+
+        // strlen
+        regs.write(RegisterType::A0, 0x2000);
+        call(&mut hle, &mut regs, &mut bus, 0xA0, 0x1B);
+        assert_eq!(regs.read(RegisterType::V0), 3);
+
+        // memcpy
+        regs.write(RegisterType::A0, 0x3000);
+        regs.write(RegisterType::A1, 0x2000);
+        regs.write(RegisterType::A2, 4);
+        call(&mut hle, &mut regs, &mut bus, 0xA0, 0x2A);
+        assert_eq!(&bus.0[0x3000..0x3004], b"val\0");
+        assert_eq!(regs.read(RegisterType::V0), 0x3000);
+    }
+
+    #[test]
+    fn unimplemented_functions_return() {
+        let mut hle = HleBios::new(false);
+        let mut regs = Registers::new();
+        let mut bus = TestRam(vec![0; 0x10000]);
+
+        call(&mut hle, &mut regs, &mut bus, 0xB0, 0xFF);
+        assert_eq!(regs.read(RegisterType::V0), 0);
+        call(&mut hle, &mut regs, &mut bus, 0x800000C0, 0xFF);
+        assert_eq!(regs.read(RegisterType::V0), 0);
+    }
+}
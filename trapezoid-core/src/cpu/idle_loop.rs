@@ -0,0 +1,299 @@
+//! Detection of loops that wait for something outside the CPU, like
+//! `b .` or polling `I_STAT`/a RAM variable until vblank, so that their iterations
+//! can be skipped, see [`IdleSkip`].
+//!
+//! A loop is idle when two consecutive iterations start with the exact same
+//! registers, and they only executed instructions without side effects, and only
+//! read memory that doesn't change by reading it. Then, the next iterations are the
+//! same as long as the memory they read doesn't change and there is no interrupt.
+
+use super::{CpuBusProvider, Instruction, Opcode, Registers};
+
+/// Loops longer than this (in bytes, with the delay slot) are not considered
+const MAX_LOOP_SIZE: u32 = 16 * 4;
+
+/// The CPU cycles reported for every skip, the loop is checked for changes
+/// between them, so this is also the latency of leaving the loop
+pub(super) const IDLE_SKIP_CYCLES: u32 = 1000;
+
+/// Skipping of loops that only wait for an interrupt or some memory to change,
+/// see [`PsxConfig::idle_skip`](crate::PsxConfig::idle_skip)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum IdleSkip {
+    #[default]
+    Off,
+    /// Stop executing idle loops, the emulated time still passes
+    On,
+    /// Detect idle loops, but keep executing them, and check that every
+    /// iteration is the same as long as nothing they read has changed.
+    /// This panics if the detection is wrong, and is only useful for testing
+    Verify,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LoopRead {
+    addr: u32,
+    bits: u8,
+}
+
+/// A read done by the loop, with the value it got
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ReadValue {
+    read: LoopRead,
+    value: u32,
+}
+
+struct LoopCandidate {
+    head: u32,
+    /// The delay slot of the backward jump
+    end: u32,
+    /// The registers at the start of the current iteration
+    start_regs: Option<Registers>,
+    /// The current iteration has no side effects so far
+    pure: bool,
+    /// The reads of the current iteration
+    reads: Vec<ReadValue>,
+}
+
+#[derive(Default)]
+pub(super) struct IdleLoopDetector {
+    mode: IdleSkip,
+    candidate: Option<LoopCandidate>,
+    /// The reads of the last iteration, when the loop is detected as idle
+    idle_reads: Option<Vec<ReadValue>>,
+}
+
+impl IdleLoopDetector {
+    pub fn set_mode(&mut self, mode: IdleSkip) {
+        self.mode = mode;
+        self.reset();
+    }
+
+    pub fn reset(&mut self) {
+        self.candidate = None;
+        self.idle_reads = None;
+    }
+
+    /// The CPU should be stopped at the head of the loop
+    pub fn is_idle(&self) -> bool {
+        self.mode == IdleSkip::On && self.idle_reads.is_some()
+    }
+
+    pub fn idle_loop_head(&self) -> Option<u32> {
+        self.idle_reads
+            .as_ref()
+            .and(self.candidate.as_ref())
+            .map(|c| c.head)
+    }
+
+    /// Check if the loop can exit now, i.e. there is an interrupt or the memory
+    /// it reads has changed, and if so, stop skipping it
+    pub fn try_wake<P: CpuBusProvider>(&mut self, bus: &mut P) -> bool {
+        let Some(reads) = &self.idle_reads else {
+            return true;
+        };
+        let woken =
+            bus.pending_interrupts() || reads.iter().any(|r| read_value(bus, r.read) != r.value);
+        if woken {
+            self.reset();
+        }
+        woken
+    }
+
+    /// Called before executing `instruction`, with `regs.pc` pointing to it.
+    ///
+    /// Returns `true` if the loop is now idle, and the instruction should not be executed
+    pub fn before_instruction<P: CpuBusProvider>(
+        &mut self,
+        regs: &Registers,
+        in_delay_slot: bool,
+        instruction: &Instruction,
+        bus: &mut P,
+    ) -> bool {
+        if self.mode == IdleSkip::Off {
+            return false;
+        }
+        let Some(candidate) = &self.candidate else {
+            return false;
+        };
+
+        let pc = regs.pc;
+        if !(candidate.head..=candidate.end).contains(&pc) {
+            if let (IdleSkip::Verify, Some(idle_reads)) = (self.mode, &self.idle_reads) {
+                // an exception, or the loop exited
+                assert!(
+                    bus.pending_interrupts() || saw_different_values(&candidate.reads, idle_reads),
+                    "idle loop at {:08X} exited to {:08X} without any of its inputs changing",
+                    candidate.head,
+                    pc
+                );
+            }
+            self.reset();
+            return false;
+        }
+
+        if pc == candidate.head && !in_delay_slot && self.start_iteration(regs) {
+            return true;
+        }
+
+        let candidate = self.candidate.as_mut().unwrap();
+        match load_access(instruction, regs) {
+            Some(read) if is_side_effect_free_read(read.addr) => {
+                let read = ReadValue {
+                    read,
+                    value: read_value(bus, read),
+                };
+                if !candidate.reads.contains(&read) {
+                    candidate.reads.push(read);
+                }
+            }
+            Some(_) => candidate.pure = false,
+            None => candidate.pure &= is_side_effect_free(instruction.opcode),
+        }
+
+        false
+    }
+
+    /// Returns `true` if the loop is detected as idle and should be skipped
+    fn start_iteration(&mut self, regs: &Registers) -> bool {
+        let candidate = self.candidate.as_mut().unwrap();
+        let same_iteration = candidate.pure && candidate.start_regs.as_ref() == Some(regs);
+
+        if let (IdleSkip::Verify, Some(idle_reads)) = (self.mode, &self.idle_reads) {
+            assert!(
+                same_iteration || saw_different_values(&candidate.reads, idle_reads),
+                "idle loop at {:08X} changed the state without any of its inputs changing",
+                candidate.head
+            );
+        }
+
+        let reads = std::mem::take(&mut candidate.reads);
+        candidate.start_regs = Some(regs.clone());
+        candidate.pure = true;
+
+        if same_iteration {
+            self.idle_reads = Some(reads);
+            if self.mode == IdleSkip::On {
+                log::trace!("idle loop detected at {:08X}", candidate.head);
+                return true;
+            }
+        } else {
+            self.idle_reads = None;
+        }
+        false
+    }
+
+    /// Called after executing the instruction at `pc`, `jump_dest` is the pending jump (if any)
+    pub fn after_instruction(&mut self, pc: u32, jump_dest: Option<u32>) {
+        if self.mode == IdleSkip::Off {
+            return;
+        }
+        let Some(dest) = jump_dest else {
+            return;
+        };
+
+        let is_short_backward_jump = dest <= pc && pc - dest < MAX_LOOP_SIZE;
+        let is_current_loop = self.candidate.as_ref().is_some_and(|c| c.head == dest);
+        if is_short_backward_jump && !is_current_loop {
+            self.idle_reads = None;
+            self.candidate = Some(LoopCandidate {
+                head: dest,
+                end: pc + 4,
+                start_regs: None,
+                pure: false,
+                reads: Vec::new(),
+            });
+        }
+    }
+}
+
+/// Whether the loop got a value in `current` that it didn't get in `previous`
+fn saw_different_values(current: &[ReadValue], previous: &[ReadValue]) -> bool {
+    current.iter().any(|r| !previous.contains(r))
+}
+
+/// The address read by the instruction, if its a load
+fn load_access(instruction: &Instruction, regs: &Registers) -> Option<LoopRead> {
+    let bits = match instruction.opcode {
+        Opcode::Lb | Opcode::Lbu => 8,
+        Opcode::Lh | Opcode::Lhu => 16,
+        Opcode::Lw | Opcode::Lwl | Opcode::Lwr => 32,
+        _ => return None,
+    };
+    // the value before any pending load, same as the execution
+    let base = regs.general_regs[instruction.rs_raw as usize];
+    let addr = base.wrapping_add(instruction.imm16() as i16 as i32 as u32);
+    // `lwl`/`lwr` read the aligned word
+    let addr = if bits == 32 { addr & !3 } else { addr };
+
+    Some(LoopRead { addr, bits })
+}
+
+/// Instructions that only change the registers (no stores, coprocessors or exceptions
+/// other than overflow), the loads are checked by address
+fn is_side_effect_free(opcode: Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::Nop
+            | Opcode::Slt
+            | Opcode::Sltu
+            | Opcode::Slti
+            | Opcode::Sltiu
+            | Opcode::Addu
+            | Opcode::Add
+            | Opcode::Subu
+            | Opcode::Sub
+            | Opcode::Addiu
+            | Opcode::Addi
+            | Opcode::And
+            | Opcode::Or
+            | Opcode::Xor
+            | Opcode::Nor
+            | Opcode::Andi
+            | Opcode::Ori
+            | Opcode::Xori
+            | Opcode::Sllv
+            | Opcode::Srlv
+            | Opcode::Srav
+            | Opcode::Sll
+            | Opcode::Srl
+            | Opcode::Sra
+            | Opcode::Lui
+            | Opcode::Mfhi
+            | Opcode::Mflo
+            | Opcode::J
+            | Opcode::Jr
+            | Opcode::Beq
+            | Opcode::Bne
+            | Opcode::Bgtz
+            | Opcode::Blez
+            | Opcode::Bltz
+            | Opcode::Bgez
+    )
+}
+
+/// Memory that can only change by the CPU, DMA or interrupts, and reading it doesn't
+/// change anything: RAM, scratchpad, `I_STAT`/`I_MASK`, and the BIOS
+fn is_side_effect_free_read(addr: u32) -> bool {
+    // KUSEG, KSEG0 and KSEG1
+    if !matches!(addr >> 29, 0 | 4 | 5) {
+        return false;
+    }
+    matches!(
+        addr & 0x1FFFFFFF,
+        0x00000000..=0x007FFFFF
+            | 0x1F800000..=0x1F8003FF
+            | 0x1F801070..=0x1F801077
+            | 0x1FC00000..=0x1FC7FFFF
+    )
+}
+
+fn read_value<P: CpuBusProvider>(bus: &mut P, read: LoopRead) -> u32 {
+    let value = match read.bits {
+        8 => bus.read_u8(read.addr).map(u32::from),
+        16 => bus.read_u16(read.addr).map(u32::from),
+        _ => bus.read_u32(read.addr),
+    };
+    // errors are the same every time, and the instruction handles them
+    value.unwrap_or(0)
+}
@@ -196,6 +196,26 @@ impl Instruction {
         )
     }
 
+    /// The destination of `j`, `jal` and branches, which is known without the registers
+    pub fn jump_target(&self) -> Option<u32> {
+        match self.opcode {
+            Opcode::J | Opcode::Jal => Some((self.pc & 0xF0000000) | (self.imm26() * 4)),
+            Opcode::Beq
+            | Opcode::Bne
+            | Opcode::Bgtz
+            | Opcode::Blez
+            | Opcode::Bltz
+            | Opcode::Bgez
+            | Opcode::Bltzal
+            | Opcode::Bgezal => Some(
+                self.pc
+                    .wrapping_add((self.imm16() as i16 as i32 as u32).wrapping_mul(4))
+                    .wrapping_add(4),
+            ),
+            _ => None,
+        }
+    }
+
     #[inline]
     pub fn rd(&self) -> RegisterType {
         RegisterType::from(self.rd_raw)
@@ -362,14 +382,14 @@ const fn opcode_str(opcode: Opcode) -> &'static str {
         Opcode::Ctc(1) => "ctc1",
         Opcode::Ctc(2) => "ctc2",
         Opcode::Ctc(3) => "ctc3",
-        Opcode::Bcf(0) => "bcf0",
-        Opcode::Bcf(1) => "bcf1",
-        Opcode::Bcf(2) => "bcf2",
-        Opcode::Bcf(3) => "bcf3",
-        Opcode::Bct(0) => "bct0",
-        Opcode::Bct(1) => "bct1",
-        Opcode::Bct(2) => "bct2",
-        Opcode::Bct(3) => "bct3",
+        Opcode::Bcf(0) => "bc0f",
+        Opcode::Bcf(1) => "bc1f",
+        Opcode::Bcf(2) => "bc2f",
+        Opcode::Bcf(3) => "bc3f",
+        Opcode::Bct(0) => "bc0t",
+        Opcode::Bct(1) => "bc1t",
+        Opcode::Bct(2) => "bc2t",
+        Opcode::Bct(3) => "bc3t",
         Opcode::Rfe => "rfe",
         Opcode::Lwc(0) => "lwc0",
         Opcode::Lwc(1) => "lwc1",
@@ -383,12 +403,48 @@ const fn opcode_str(opcode: Opcode) -> &'static str {
     }
 }
 
+const COP0_REG_NAMES: [&str; 16] = [
+    "r0", "r1", "r2", "bpc", "r4", "bda", "jumpdest", "dcic", "badvaddr", "bdam", "r10", "bpcm",
+    "sr", "cause", "epc", "prid",
+];
+
+const GTE_DATA_REG_NAMES: [&str; 32] = [
+    "vxy0", "vz0", "vxy1", "vz1", "vxy2", "vz2", "rgbc", "otz", "ir0", "ir1", "ir2", "ir3", "sxy0",
+    "sxy1", "sxy2", "sxyp", "sz0", "sz1", "sz2", "sz3", "rgb0", "rgb1", "rgb2", "res1", "mac0",
+    "mac1", "mac2", "mac3", "irgb", "orgb", "lzcs", "lzcr",
+];
+
+const GTE_CTRL_REG_NAMES: [&str; 32] = [
+    "rt11rt12", "rt13rt21", "rt22rt23", "rt31rt32", "rt33", "trx", "try", "trz", "l11l12",
+    "l13l21", "l22l23", "l31l32", "l33", "rbk", "gbk", "bbk", "lr1lr2", "lr3lg1", "lg2lg3",
+    "lb1lb2", "lb3", "rfc", "gfc", "bfc", "ofx", "ofy", "h", "dqa", "dqb", "zsf3", "zsf4", "flag",
+];
+
+/// Name of the coprocessor register, `$n` is used if there is no known name
+fn cop_register_name(cop_n: u8, ctrl: bool, reg: u8) -> String {
+    let reg = reg & 0x1F;
+    let name = match (cop_n, ctrl) {
+        (0, false) => COP0_REG_NAMES.get(reg as usize).copied(),
+        (2, false) => Some(GTE_DATA_REG_NAMES[reg as usize]),
+        (2, true) => Some(GTE_CTRL_REG_NAMES[reg as usize]),
+        _ => None,
+    };
+
+    match name {
+        Some(name) => name.to_string(),
+        None => format!("${}", reg),
+    }
+}
+
 fn format_load_store(f: &mut fmt::Formatter, instr: &Instruction) -> fmt::Result {
     let opcode = instr.opcode;
 
     let src = instr.rs();
     let off = instr.imm16();
-    let dst = instr.rt();
+    let dst = match opcode {
+        Opcode::Lwc(n) | Opcode::Swc(n) => cop_register_name(n, false, instr.rt_raw),
+        _ => instr.rt().to_string(),
+    };
 
     write!(f, "{} {}, 0x{:04X}({})", opcode_str(opcode), dst, off, src)
 }
@@ -473,7 +529,71 @@ fn format_branch(f: &mut fmt::Formatter, instr: &Instruction, rt: bool) -> fmt::
 fn format_cop_ops(f: &mut fmt::Formatter, instr: &Instruction) -> fmt::Result {
     let opcode = instr.opcode;
 
-    write!(f, "{} {}, {}", opcode_str(opcode), instr.rt(), instr.rd())
+    let cop_reg = match opcode {
+        Opcode::Mfc(n) | Opcode::Mtc(n) => cop_register_name(n, false, instr.rd_raw),
+        Opcode::Cfc(n) | Opcode::Ctc(n) => cop_register_name(n, true, instr.rd_raw),
+        _ => unreachable!(),
+    };
+
+    write!(f, "{} {}, {}", opcode_str(opcode), instr.rt(), cop_reg)
+}
+
+fn format_cop_branch(f: &mut fmt::Formatter, instr: &Instruction) -> fmt::Result {
+    let dest = instr.imm16();
+
+    write!(
+        f,
+        "{} 0x{:04X} => 0x{:08X}",
+        opcode_str(instr.opcode),
+        dest,
+        instr
+            .pc
+            .wrapping_add((dest as i16 as i32 as u32).wrapping_mul(4))
+            .wrapping_add(4)
+    )
+}
+
+/// GTE commands, with the flags they use
+fn format_gte_command(f: &mut fmt::Formatter, instr: &Instruction) -> fmt::Result {
+    let cmd = instr.imm25();
+
+    let name = match cmd & 0x3F {
+        0x01 => "rtps",
+        0x06 => "nclip",
+        0x0C => "op",
+        0x10 => "dpcs",
+        0x11 => "intpl",
+        0x12 => "mvmva",
+        0x13 => "ncds",
+        0x14 => "cdp",
+        0x16 => "ncdt",
+        0x1B => "nccs",
+        0x1C => "cc",
+        0x1E => "ncs",
+        0x20 => "nct",
+        0x28 => "sqr",
+        0x29 => "dcpl",
+        0x2A => "dpct",
+        0x2D => "avsz3",
+        0x2E => "avsz4",
+        0x30 => "rtpt",
+        0x3D => "gpf",
+        0x3E => "gpl",
+        0x3F => "ncct",
+        _ => return write!(f, "{} 0x{:07X}", opcode_str(instr.opcode), cmd),
+    };
+
+    let sf = (cmd >> 19) & 1;
+    let lm = (cmd >> 10) & 1;
+
+    write!(f, "{} sf={} lm={}", name, sf, lm)?;
+    if cmd & 0x3F == 0x12 {
+        let mx = (cmd >> 17) & 3;
+        let v = (cmd >> 15) & 3;
+        let cv = (cmd >> 13) & 3;
+        write!(f, " mx={} v={} cv={}", mx, v, cv)?;
+    }
+    Ok(())
 }
 
 impl fmt::Display for Instruction {
@@ -526,13 +646,12 @@ impl fmt::Display for Instruction {
                 (self.pc & 0xF0000000) | (self.imm26() * 4)
             ),
             Opcode::Jr => write!(f, "{} {}", opcode_str(self.opcode), self.rs()),
-            // some specs says "jalr rd,rs"
             Opcode::Jalr => write!(
                 f,
                 "{} {}, {}",
                 opcode_str(self.opcode),
-                self.rs(),
-                self.rd()
+                self.rd(),
+                self.rs()
             ),
             Opcode::Beq | Opcode::Bne => format_branch(f, self, true),
             Opcode::Bgtz
@@ -544,15 +663,131 @@ impl fmt::Display for Instruction {
             Opcode::Nop | Opcode::Syscall | Opcode::Break | Opcode::Rfe => {
                 f.write_str(opcode_str(self.opcode))
             }
+            Opcode::Cop(2) => format_gte_command(f, self),
             Opcode::Cop(_) => write!(f, "{} 0x{:07X}", opcode_str(self.opcode), self.imm25()),
             Opcode::Mfc(_) | Opcode::Cfc(_) | Opcode::Mtc(_) | Opcode::Ctc(_) => {
                 format_cop_ops(f, self)
             }
-            Opcode::Bcf(_) => todo!(),
-            Opcode::Bct(_) => todo!(),
+            Opcode::Bcf(_) | Opcode::Bct(_) => format_cop_branch(f, self),
             Opcode::Lwc(_) | Opcode::Swc(_) => format_load_store(f, self),
             Opcode::Invalid => write!(f, "Invalid instruction"),
             _ => unreachable!(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Instruction;
+    use crate::cpu::disasm;
+
+    const PC: u32 = 0x80010000;
+
+    fn check(cases: &[(u32, &str)]) {
+        for &(instr, expected) in cases {
+            assert_eq!(disasm(PC, instr), expected, "instruction 0x{:08X}", instr);
+        }
+    }
+
+    #[test]
+    fn alu_and_shifts() {
+        check(&[
+            (0x00000000, "nop"),
+            (0x00851021, "addu v0, a0, a1"),
+            (0x0085102A, "slt v0, a0, a1"),
+            (0x27BDFFE8, "addiu sp, sp, 0xFFE8"),
+            (0x30820FFF, "andi v0, a0, 0x0FFF"),
+            (0x3C011F80, "lui at, 0x1F80"),
+            (0x00094100, "sll t0, t1, 0x04"),
+            (0x00A41007, "srav v0, a0, a1"),
+            (0x00850018, "mult a0, a1"),
+            (0x00001012, "mflo v0"),
+        ]);
+    }
+
+    #[test]
+    fn loads_and_stores() {
+        check(&[
+            (0x80820001, "lb v0, 0x0001(a0)"),
+            (0x88820003, "lwl v0, 0x0003(a0)"),
+            (0x8FBF0010, "lw ra, 0x0010(sp)"),
+            (0xAD040000, "sw a0, 0x0000(t0)"),
+        ]);
+    }
+
+    #[test]
+    fn jumps_and_branches() {
+        check(&[
+            (0x08004040, "j 0x0004040 => 0x80010100"),
+            (0x0C004040, "jal 0x0004040 => 0x80010100"),
+            (0x03E00008, "jr ra"),
+            (0x0100F809, "jalr ra, t0"),
+            (0x10800003, "beq a0, zero, 0x0003 => 0x80010010"),
+            (0x1480FFFF, "bne a0, zero, 0xFFFF => 0x80010000"),
+            (0x04800002, "bltz a0, 0x0002 => 0x8001000C"),
+            (0x04910002, "bgezal a0, 0x0002 => 0x8001000C"),
+            (0x0000000C, "syscall"),
+            (0x0000000D, "break"),
+        ]);
+    }
+
+    #[test]
+    fn jump_targets() {
+        let target = |instr| Instruction::from_u32(instr, PC).jump_target();
+        assert_eq!(target(0x0C004040), Some(0x80010100));
+        assert_eq!(target(0x1480FFFF), Some(0x80010000));
+        assert_eq!(target(0x04910002), Some(0x8001000C));
+        // depends on registers
+        assert_eq!(target(0x03E00008), None);
+        assert_eq!(target(0x00851021), None);
+    }
+
+    #[test]
+    fn branch_likely_is_invalid() {
+        // the R3000A doesn't have the `likely` branches of MIPS II
+        check(&[
+            (0x50800003, "Invalid instruction"),
+            (0x54800003, "Invalid instruction"),
+            (0x58800003, "Invalid instruction"),
+            (0x5C800003, "Invalid instruction"),
+        ]);
+    }
+
+    #[test]
+    fn cop0() {
+        check(&[
+            (0x401A6800, "mfc0 k0, cause"),
+            (0x40886000, "mtc0 t0, sr"),
+            (0x401B7000, "mfc0 k1, epc"),
+            (0x42000010, "rfe"),
+            // tlbr, no TLB on the PSX
+            (0x42000001, "Invalid instruction"),
+        ]);
+    }
+
+    #[test]
+    fn cop2_transfers() {
+        check(&[
+            (0x48087000, "mfc2 t0, sxy2"),
+            (0x4848D000, "cfc2 t0, h"),
+            (0x48C8F800, "ctc2 t0, flag"),
+            (0xC9000000, "lwc2 vxy0, 0x0000(t0)"),
+            (0xE88E0004, "swc2 sxy2, 0x0004(a0)"),
+            (0x49000002, "bc2f 0x0002 => 0x8001000C"),
+        ]);
+    }
+
+    #[test]
+    fn gte_commands() {
+        check(&[
+            (0x4A280030, "rtpt sf=1 lm=0"),
+            (0x4A180001, "rtps sf=1 lm=0"),
+            (0x4AE80413, "ncds sf=1 lm=1"),
+            (0x4A400012 | (3 << 13), "mvmva sf=0 lm=0 mx=0 v=0 cv=3"),
+            (0x4A486012, "mvmva sf=1 lm=0 mx=0 v=0 cv=3"),
+            (0x4B58002D, "avsz3 sf=1 lm=0"),
+            // unknown command
+            (0x4A000002, "cop2 0x0000002"),
+        ]);
+    }
+}
@@ -1,5 +1,7 @@
 use std::fmt;
 
+use crate::state_hash::StateHasher;
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 #[repr(u8)]
 pub enum RegisterType {
@@ -134,6 +136,7 @@ impl fmt::Display for RegisterType {
     }
 }
 
+#[derive(Clone, PartialEq, Eq)]
 pub struct Registers {
     pub(crate) general_regs: [u32; 32],
     pub(crate) pc: u32,
@@ -161,6 +164,24 @@ impl Registers {
         }
     }
 
+    pub(crate) fn hash_state(&self, hasher: &mut StateHasher) {
+        for &reg in &self.general_regs {
+            hasher.write_u32(reg);
+        }
+        hasher.write_u32(self.pc);
+        hasher.write_u32(self.hi);
+        hasher.write_u32(self.lo);
+        for slot in [
+            self.load_delay_slot_running,
+            self.load_delay_slot_committing,
+        ] {
+            // `0xFF` is not a register index, so it can't be mistaken for a load
+            let (idx, data) = slot.unwrap_or((0xFF, 0));
+            hasher.write_u8(idx);
+            hasher.write_u32(data);
+        }
+    }
+
     #[inline]
     pub fn read(&self, ty: RegisterType) -> u32 {
         match ty {
@@ -0,0 +1,131 @@
+//! Heuristic stack unwinder.
+//!
+//! MIPS code doesn't have to keep a frame pointer, so the call stack is recovered
+//! from the function prologues instead. Starting from `pc`, we scan backwards for
+//! `addiu sp, sp, -N`, which gives the frame size, and then look for `sw ra, off(sp)`
+//! between the prologue and `pc` to know where the return address was saved.
+//!
+//! This works for compiler generated code, but can be confused by hand written
+//! assembly, or functions that adjust `sp` more than once.
+
+/// `addiu sp, sp, imm`, the lower 16 bits are the immediate
+const ADDIU_SP_SP: u32 = 0x27BD_0000;
+/// `sw ra, off(sp)`, the lower 16 bits are the offset
+const SW_RA_SP: u32 = 0xAFBF_0000;
+/// `jr ra`, the end of the previous function
+const JR_RA: u32 = 0x03E0_0008;
+
+/// How far to scan backwards for a function prologue, in instructions
+const MAX_PROLOGUE_SCAN: u32 = 0x1000;
+
+/// A single frame recovered by [`unwind_stack`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackFrame {
+    /// The current `pc` for the top frame, and the return address for the rest
+    pub pc: u32,
+    /// The value of `sp` in this frame
+    pub sp: u32,
+    /// The start of the function, if its prologue was found
+    pub function: Option<u32>,
+}
+
+/// The prologue of the function containing `pc`
+struct Prologue {
+    start: u32,
+    frame_size: u32,
+    /// Offset of the saved `ra` from `sp`, if it was saved before `pc`
+    ra_offset: Option<u32>,
+}
+
+fn find_prologue(pc: u32, read_u32: &mut impl FnMut(u32) -> Option<u32>) -> Option<Prologue> {
+    let mut addr = pc;
+    for _ in 0..MAX_PROLOGUE_SCAN {
+        addr = addr.wrapping_sub(4);
+        let instr = read_u32(addr)?;
+
+        // reached the previous function, so this one doesn't have a frame
+        if instr == JR_RA {
+            return None;
+        }
+        if instr & 0xFFFF_0000 == ADDIU_SP_SP {
+            let imm = instr as u16 as i16;
+            // `addiu sp, sp, +N` is an epilogue, we are past it
+            if imm >= 0 {
+                return None;
+            }
+
+            let mut ra_offset = None;
+            for a in (addr.wrapping_add(4)..pc).step_by(4) {
+                match read_u32(a) {
+                    Some(instr) if instr & 0xFFFF_0000 == SW_RA_SP => {
+                        ra_offset = Some(instr as u16 as i16 as u32);
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+
+            return Some(Prologue {
+                start: addr,
+                frame_size: -(imm as i32) as u32,
+                ra_offset,
+            });
+        }
+    }
+    None
+}
+
+/// Reconstruct the call stack from the current `pc`, `sp` and `ra` registers,
+/// reading the code and the stack with `read_u32`.
+///
+/// The `ra` register is only trusted for the top frame, since a leaf function may
+/// not save it. The unwinding stops when a frame can't be recovered, or after
+/// `max_depth` frames.
+pub fn unwind_stack(
+    pc: u32,
+    sp: u32,
+    ra: u32,
+    max_depth: usize,
+    mut read_u32: impl FnMut(u32) -> Option<u32>,
+) -> Vec<StackFrame> {
+    let mut frames = Vec::new();
+    let mut pc = pc;
+    let mut sp = sp;
+
+    while frames.len() < max_depth {
+        let prologue = find_prologue(pc, &mut read_u32);
+        let is_top = frames.is_empty();
+        frames.push(StackFrame {
+            pc,
+            sp,
+            function: prologue.as_ref().map(|p| p.start),
+        });
+
+        let (return_addr, caller_sp) = match prologue {
+            Some(Prologue {
+                frame_size,
+                ra_offset: Some(offset),
+                ..
+            }) => {
+                let Some(return_addr) = read_u32(sp.wrapping_add(offset)) else {
+                    break;
+                };
+                (return_addr, sp.wrapping_add(frame_size))
+            }
+            // leaf function that allocated a frame, but `ra` is not saved
+            Some(Prologue { frame_size, .. }) if is_top => (ra, sp.wrapping_add(frame_size)),
+            // leaf function without a frame
+            None if is_top => (ra, sp),
+            _ => break,
+        };
+
+        // the stack grows down, so a bad frame would make us loop forever
+        if return_addr == 0 || return_addr % 4 != 0 || caller_sp < sp {
+            break;
+        }
+        pc = return_addr;
+        sp = caller_sp;
+    }
+
+    frames
+}
@@ -0,0 +1,324 @@
+//! Emulated time keeping, and the speed multiplier for slow-motion and fast-forward.
+
+use std::time::Duration;
+
+const CPU_CLOCK: u64 = 33_868_800;
+
+/// CPU cycles of `1/60` second, which is 735 audio samples
+const AUDIO_FRAME_CYCLES: u32 = 564480;
+
+/// Audio frames of a chunk in the time-stretching, ~23ms,
+/// the chunks are windowed and overlap by half
+const STRETCH_CHUNK: usize = 1024;
+const STRETCH_HOP: usize = STRETCH_CHUNK / 2;
+/// How far a chunk can move from its position to line up with the previous one
+const STRETCH_SEARCH: usize = 256;
+
+pub(crate) struct EmulationClock {
+    elapsed_cycles: u64,
+    elapsed_frames: u64,
+    speed_multiplier: f64,
+    /// Video frames that are not yet a full frame with the speed multiplier
+    video_frame_credit: f64,
+    /// The audio not yet time-stretched, interleaved stereo
+    stretch_input: Vec<f32>,
+    /// Position of the next chunk in `stretch_input`, in audio frames
+    stretch_position: f64,
+    /// Where the last chunk would have continued in `stretch_input`, the next
+    /// chunk is lined up with it
+    stretch_continuation: Option<usize>,
+    /// The second half of the last windowed chunk, added to the next one
+    stretch_overlap: Vec<f32>,
+}
+
+impl Default for EmulationClock {
+    fn default() -> Self {
+        Self {
+            elapsed_cycles: 0,
+            elapsed_frames: 0,
+            speed_multiplier: 1.0,
+            video_frame_credit: 0.0,
+            stretch_input: Vec::new(),
+            stretch_position: STRETCH_SEARCH as f64,
+            stretch_continuation: None,
+            stretch_overlap: vec![0.0; STRETCH_HOP * 2],
+        }
+    }
+}
+
+impl EmulationClock {
+    pub fn add_cycles(&mut self, cycles: u32) {
+        self.elapsed_cycles += cycles as u64;
+    }
+
+    pub fn add_frame(&mut self) {
+        self.elapsed_frames += 1;
+    }
+
+    pub fn elapsed_cycles(&self) -> u64 {
+        self.elapsed_cycles
+    }
+
+    pub fn elapsed_frames(&self) -> u64 {
+        self.elapsed_frames
+    }
+
+    pub fn emulated_time(&self) -> Duration {
+        let secs = self.elapsed_cycles / CPU_CLOCK;
+        let nanos = (self.elapsed_cycles % CPU_CLOCK) * 1_000_000_000 / CPU_CLOCK;
+        Duration::new(secs, nanos as u32)
+    }
+
+    pub fn set_speed_multiplier(&mut self, speed_multiplier: f64) {
+        assert!(
+            speed_multiplier.is_finite() && speed_multiplier > 0.0,
+            "invalid speed multiplier {}",
+            speed_multiplier
+        );
+        if speed_multiplier != self.speed_multiplier {
+            self.reset_stretch();
+        }
+        self.speed_multiplier = speed_multiplier;
+    }
+
+    pub fn speed_multiplier(&self) -> f64 {
+        self.speed_multiplier
+    }
+
+    /// The CPU cycles to run for one frame of audio
+    pub fn audio_frame_cycles(&self) -> u32 {
+        (AUDIO_FRAME_CYCLES as f64 * self.speed_multiplier).round() as u32
+    }
+
+    /// The number of video frames to run for one frame, the fractions are
+    /// accumulated, so in slow-motion some calls will run no frames at all
+    pub fn video_frames_to_run(&mut self) -> u32 {
+        self.video_frame_credit += self.speed_multiplier;
+        let frames = self.video_frame_credit.floor();
+        self.video_frame_credit -= frames;
+        frames as u32
+    }
+
+    /// Time-stretch the stereo `buffer` produced at the current speed, so that it
+    /// plays at the normal rate, keeping the pitch. The result is appended to `out`.
+    ///
+    /// This is overlap-add of windowed chunks (WSOLA), chunks are taken every
+    /// `speed_multiplier` hops of the input, so some of the input is dropped (fast-forward)
+    /// or repeated (slow-motion). Each chunk is moved a bit to line up
+    /// with the previous one, so the waveform continues without clicks.
+    ///
+    /// The output comes in hops of [`STRETCH_HOP`] frames, and the first one needs
+    /// ~35ms of input, so it's the same length as the input over time, but not every call.
+    pub fn stretch_audio(&mut self, buffer: &[f32], out: &mut Vec<f32>) {
+        if self.speed_multiplier == 1.0 {
+            out.extend_from_slice(buffer);
+            return;
+        }
+        self.stretch_input.extend_from_slice(buffer);
+
+        let analysis_hop = STRETCH_HOP as f64 * self.speed_multiplier;
+        let input_frames = self.stretch_input.len() / 2;
+        while self.stretch_position as usize + STRETCH_SEARCH + STRETCH_CHUNK <= input_frames {
+            let position = self.stretch_position as usize;
+            let start = match self.stretch_continuation {
+                Some(continuation) => self.best_chunk_start(position, continuation),
+                None => position,
+            };
+
+            for i in 0..STRETCH_HOP {
+                let weight = hann_window(i);
+                let overlap_weight = hann_window(i + STRETCH_HOP);
+                for channel in 0..2 {
+                    out.push(
+                        self.stretch_overlap[i * 2 + channel]
+                            + self.stretch_input[(start + i) * 2 + channel] * weight,
+                    );
+                    self.stretch_overlap[i * 2 + channel] = self.stretch_input
+                        [(start + STRETCH_HOP + i) * 2 + channel]
+                        * overlap_weight;
+                }
+            }
+
+            self.stretch_continuation = Some(start + STRETCH_HOP);
+            self.stretch_position += analysis_hop;
+        }
+
+        // remove the input that no chunk can use anymore
+        let used = (self.stretch_position as usize - STRETCH_SEARCH)
+            .min(self.stretch_continuation.unwrap_or(usize::MAX));
+        self.stretch_input.drain(..used * 2);
+        self.stretch_position -= used as f64;
+        if let Some(continuation) = &mut self.stretch_continuation {
+            *continuation -= used;
+        }
+    }
+
+    /// The start of the chunk around `position`, where the first half is the most
+    /// similar to the audio at `continuation`
+    fn best_chunk_start(&self, position: usize, continuation: usize) -> usize {
+        let mono = |frame: usize| self.stretch_input[frame * 2] + self.stretch_input[frame * 2 + 1];
+
+        let mut best_start = position;
+        let mut best_correlation = f32::NEG_INFINITY;
+        for start in position - STRETCH_SEARCH..=position + STRETCH_SEARCH {
+            let correlation = (0..STRETCH_HOP)
+                .map(|i| mono(start + i) * mono(continuation + i))
+                .sum::<f32>();
+            if correlation > best_correlation {
+                best_correlation = correlation;
+                best_start = start;
+            }
+        }
+        best_start
+    }
+
+    fn reset_stretch(&mut self) {
+        self.stretch_input.clear();
+        self.stretch_position = STRETCH_SEARCH as f64;
+        self.stretch_continuation = None;
+        self.stretch_overlap.fill(0.0);
+    }
+}
+
+/// Periodic Hann window over [`STRETCH_CHUNK`], the two halves add up to `1`
+fn hann_window(i: usize) -> f32 {
+    0.5 - 0.5 * (std::f32::consts::TAU * i as f32 / STRETCH_CHUNK as f32).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// NTSC frame
+    const VIDEO_FRAME_CYCLES: u32 = 566204;
+
+    #[test]
+    fn emulated_time() {
+        let mut clock = EmulationClock::default();
+        const FRAMES: u64 = 300;
+        for _ in 0..FRAMES {
+            clock.add_cycles(VIDEO_FRAME_CYCLES);
+            clock.add_frame();
+        }
+
+        assert_eq!(clock.elapsed_frames(), FRAMES);
+        assert_eq!(clock.elapsed_cycles(), FRAMES * VIDEO_FRAME_CYCLES as u64);
+
+        let frame_duration = Duration::from_secs_f64(1. / 59.817);
+        let expected = frame_duration * FRAMES as u32;
+        let diff = clock.emulated_time().as_secs_f64() - expected.as_secs_f64();
+        assert!(
+            diff.abs() < 0.001,
+            "{:?} != {:?}",
+            clock.emulated_time(),
+            expected
+        );
+    }
+
+    #[test]
+    fn speed_multiplier_cycles() {
+        let mut clock = EmulationClock::default();
+        assert_eq!(clock.audio_frame_cycles(), AUDIO_FRAME_CYCLES);
+        clock.set_speed_multiplier(2.0);
+        assert_eq!(clock.audio_frame_cycles(), AUDIO_FRAME_CYCLES * 2);
+        clock.set_speed_multiplier(0.5);
+        assert_eq!(clock.audio_frame_cycles(), AUDIO_FRAME_CYCLES / 2);
+    }
+
+    #[test]
+    fn speed_multiplier_video_frames() {
+        let mut clock = EmulationClock::default();
+        clock.set_speed_multiplier(2.0);
+        assert_eq!(clock.video_frames_to_run(), 2);
+
+        clock.set_speed_multiplier(0.5);
+        let frames = (0..10).map(|_| clock.video_frames_to_run()).sum::<u32>();
+        assert_eq!(frames, 5);
+    }
+
+    #[test]
+    fn stretched_audio_length() {
+        let mut clock = EmulationClock::default();
+        // 735 audio frames per frame
+        let buffer = (0..735 * 2).map(|i| i as f32).collect::<Vec<_>>();
+        let mut out = Vec::new();
+        clock.stretch_audio(&buffer, &mut out);
+        assert_eq!(out, buffer);
+
+        for speed_multiplier in [2.0, 0.8, 1.6] {
+            clock.set_speed_multiplier(speed_multiplier);
+            let input_frames = (735. * speed_multiplier) as usize;
+            let mut output_frames = 0;
+            for _ in 0..100 {
+                let buffer = vec![0.0; input_frames * 2];
+                let mut out = Vec::new();
+                clock.stretch_audio(&buffer, &mut out);
+                output_frames += out.len() / 2;
+            }
+            // the position carries between buffers, so only the delay
+            // and the last partial hop are missing
+            let missing = 73500 - output_frames as i64;
+            let delay = ((STRETCH_SEARCH * 2 + STRETCH_CHUNK) as f64 / speed_multiplier) as i64;
+            assert!(
+                (delay - STRETCH_HOP as i64 - 1..=delay).contains(&missing),
+                "{}: {}",
+                speed_multiplier,
+                output_frames
+            );
+        }
+    }
+
+    /// The frequency of the left channel, from the zero crossings
+    fn frequency(samples: &[f32]) -> f64 {
+        let crossings = samples
+            .chunks(2)
+            .map(|frame| frame[0])
+            .collect::<Vec<_>>()
+            .windows(2)
+            .enumerate()
+            .filter(|(_, w)| w[0] < 0.0 && w[1] >= 0.0)
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        let first = crossings[0];
+        let last = crossings[crossings.len() - 1];
+        (crossings.len() - 1) as f64 * 44100. / (last - first) as f64
+    }
+
+    #[test]
+    fn stretched_audio_keeps_pitch() {
+        const FREQUENCY: f64 = 441.;
+
+        let mut clock = EmulationClock::default();
+        clock.set_speed_multiplier(2.0);
+
+        let input_frames = 735 * 2;
+        let mut out = Vec::new();
+        for buffer_index in 0..30 {
+            let buffer = (0..input_frames)
+                .map(|i| {
+                    let t = (buffer_index * input_frames + i) as f64 / 44100.;
+                    (std::f64::consts::TAU * FREQUENCY * t).sin() as f32 * 0.5
+                })
+                .flat_map(|s| [s, s])
+                .collect::<Vec<_>>();
+            clock.stretch_audio(&buffer, &mut out);
+        }
+        // half the input
+        assert!(out.len() / 2 > 735 * 30 - STRETCH_CHUNK * 2);
+
+        // skip the fade in of the first chunk
+        let out = &out[STRETCH_HOP * 2..];
+        let frequency = frequency(out);
+        assert!(
+            (frequency - FREQUENCY).abs() < FREQUENCY * 0.01,
+            "{}",
+            frequency
+        );
+        // the chunks are lined up, so there is no dip where they overlap
+        let min_peak = out
+            .chunks_exact(2 * 100)
+            .map(|c| c.iter().fold(0f32, |a, s| a.max(s.abs())))
+            .fold(f32::INFINITY, f32::min);
+        assert!(min_peak > 0.45, "{}", min_peak);
+    }
+}
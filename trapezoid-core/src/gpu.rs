@@ -1,29 +1,65 @@
 mod command;
+mod command_capture;
+mod drawing;
+#[cfg(feature = "vulkan")]
 mod front_blit;
+#[cfg(feature = "vulkan")]
 mod gpu_backend;
+#[cfg(feature = "vulkan")]
 mod gpu_context;
-
+#[cfg(not(feature = "vulkan"))]
+mod memory_backend;
+mod video_timing;
+#[cfg(feature = "vulkan")]
+mod vram_rect;
+mod vram_transfer;
+
+use crate::capture::Frame;
 use crate::memory::{interrupts::InterruptRequester, BusLine, Result};
+use crate::region::RegionOverride;
+use crate::state_hash::StateHasher;
+use crate::trace::{GpuPrimitive, TraceEvent, Tracer};
+#[cfg(feature = "vulkan")]
+use crate::PsxError;
 use command::{instantiate_gp0_command, Gp0CmdType, Gp0Command};
-use gpu_backend::GpuBackend;
-
-use crossbeam::{
-    atomic::AtomicCell,
-    channel::{Receiver, Sender},
-};
+use command_capture::GpuCaptureWriter;
+#[cfg(feature = "vulkan")]
+use gpu_backend::VulkanBackend as Backend;
+#[cfg(not(feature = "vulkan"))]
+use memory_backend::MemoryBackend as Backend;
+use video_timing::{VideoClocks, VideoMode, VideoStandard, VideoTiming};
+use vram_transfer::VramReadTransfer;
+
+pub use command_capture::{GpuCaptureReader, GpuCaptureRecord};
+
+use crossbeam::{atomic::AtomicCell, channel::Sender};
+#[cfg(feature = "vulkan")]
 use vulkano::{
+    buffer::AllocateBufferError,
     command_buffer::{
-        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, BlitImageInfo,
-        CommandBufferUsage, PrimaryAutoCommandBuffer,
+        AutoCommandBufferBuilder, BlitImageInfo, CommandBufferExecError, CommandBufferUsage,
+        PrimaryAutoCommandBuffer,
     },
     device::{Device, Queue},
-    image::{sampler::Filter, Image},
-    sync::GpuFuture,
+    format::{Format, FormatFeatures, NumericFormat},
+    image::{sampler::Filter, AllocateImageError, Image},
+    sync::{GpuFuture, HostAccessError},
+    Validated, ValidationError, VulkanError,
 };
 
-use std::{ops::Range, sync::Arc, thread::JoinHandle};
+use std::{
+    io::{self, Read},
+    ops::Range,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
 
-use self::gpu_context::{DrawingTextureParams, DrawingVertex};
+use self::drawing::{DrawingTextureParams, DrawingVertex};
+
+/// The format of the front images, unless changed with [`Gpu::set_output_format`]
+#[cfg(feature = "vulkan")]
+pub(crate) const DEFAULT_OUTPUT_FORMAT: Format = Format::B8G8R8A8_UNORM;
 
 bitflags::bitflags! {
     #[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
@@ -119,7 +155,30 @@ impl GpuStat {
         !self.intersects(Self::VIDEO_MODE)
     }
 
-    fn _display_enabled(&self) -> bool {
+    /// The video mode bit as the game reads it, forced by the override
+    fn with_region_override(mut self, region_override: RegionOverride) -> Self {
+        match region_override {
+            RegionOverride::Auto => {}
+            RegionOverride::Ntsc => self.remove(Self::VIDEO_MODE),
+            RegionOverride::Pal => self.insert(Self::VIDEO_MODE),
+        }
+        self
+    }
+
+    fn video_mode(&self) -> VideoMode {
+        VideoMode {
+            standard: if self.is_ntsc_video_mode() {
+                VideoStandard::Ntsc
+            } else {
+                VideoStandard::Pal
+            },
+            dots_divider: self.horizontal_dots_divider(),
+            vertical_resolution: self.vertical_resolution(),
+            interlace: self.intersects(Self::VERTICAL_INTERLACE),
+        }
+    }
+
+    fn display_enabled(&self) -> bool {
         !self.intersects(Self::DISPLAY_DISABLED)
     }
 
@@ -144,10 +203,152 @@ impl GpuStat {
     }
 }
 
+/// How dithering is applied to gouraud-shaded and texture-blended draws
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DitherMode {
+    /// Dither when the game enables it in `GPUSTAT` bit 9
+    #[default]
+    Hardware,
+    Off,
+    /// Dither even if the game doesn't enable it
+    ForceOn,
+}
+
+/// Options that change how the GPU output looks,
+/// without changing the emulated state
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct GpuRenderOptions {
+    pub dithering: DitherMode,
+}
+
+/// Work done by the host GPU backend in one frame, counted from one front
+/// image to the next
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct GpuStats {
+    /// Batches of buffered primitives submitted as one draw
+    pub draw_calls: u32,
+    pub vertices: u32,
+    /// `CPU to VRAM` transfers, including the write half of `VRAM to VRAM` blits
+    pub vram_writes: u32,
+    pub vram_write_bytes: u64,
+    /// `VRAM to CPU` transfers, including the read half of `VRAM to VRAM` blits
+    pub vram_reads: u32,
+    pub vram_read_bytes: u64,
+    /// Copies of the render image into the back image, needed before
+    /// sampling textures or blending after a write
+    pub back_image_updates: u32,
+    /// Submits of the buffered draw commands to the queue
+    pub command_buffer_flushes: u32,
+    /// Time the backend thread was blocked waiting for the host GPU
+    pub fence_wait_time: Duration,
+    /// Front images allocated, they are reused after that, so this stays 0 after
+    /// the first frames, unless the display size or the output format changes
+    pub front_image_allocations: u32,
+}
+
+/// An error of the host GPU backend, the details are logged when it happens.
+///
+/// After an error, the backend recreates its resources on the same device, the
+/// emulation continues, but the VRAM content is lost. It's reported with
+/// [`CpuState::GpuError`](crate::cpu::CpuState::GpuError).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpuError {
+    /// The device was lost, e.g. the driver was reset or the laptop switched GPUs
+    DeviceLost,
+    /// Out of host or device memory, even after waiting for the pending draws to free theirs
+    OutOfMemory,
+    /// Any other Vulkan error
+    Other,
+}
+
+impl std::error::Error for GpuError {}
+impl std::fmt::Display for GpuError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GpuError::DeviceLost => write!(f, "The GPU device was lost"),
+            GpuError::OutOfMemory => write!(f, "Out of GPU memory"),
+            GpuError::Other => write!(f, "GPU error"),
+        }
+    }
+}
+
+#[cfg(feature = "vulkan")]
+impl From<VulkanError> for GpuError {
+    fn from(e: VulkanError) -> Self {
+        match e {
+            VulkanError::DeviceLost => GpuError::DeviceLost,
+            VulkanError::OutOfHostMemory | VulkanError::OutOfDeviceMemory => GpuError::OutOfMemory,
+            e => {
+                log::error!("Vulkan error: {}", e);
+                GpuError::Other
+            }
+        }
+    }
+}
+
+#[cfg(feature = "vulkan")]
+impl From<Box<ValidationError>> for GpuError {
+    fn from(e: Box<ValidationError>) -> Self {
+        log::error!("Vulkan validation error: {}", e);
+        GpuError::Other
+    }
+}
+
+#[cfg(feature = "vulkan")]
+impl<E: Into<GpuError>> From<Validated<E>> for GpuError {
+    fn from(e: Validated<E>) -> Self {
+        match e {
+            Validated::Error(e) => e.into(),
+            Validated::ValidationError(e) => e.into(),
+        }
+    }
+}
+
+#[cfg(feature = "vulkan")]
+impl From<AllocateBufferError> for GpuError {
+    fn from(e: AllocateBufferError) -> Self {
+        match e {
+            AllocateBufferError::CreateBuffer(e) | AllocateBufferError::BindMemory(e) => e.into(),
+            AllocateBufferError::AllocateMemory(_) => GpuError::OutOfMemory,
+        }
+    }
+}
+
+#[cfg(feature = "vulkan")]
+impl From<AllocateImageError> for GpuError {
+    fn from(e: AllocateImageError) -> Self {
+        match e {
+            AllocateImageError::CreateImage(e) | AllocateImageError::BindMemory(e) => e.into(),
+            AllocateImageError::AllocateMemory(_) => GpuError::OutOfMemory,
+        }
+    }
+}
+
+#[cfg(feature = "vulkan")]
+impl From<CommandBufferExecError> for GpuError {
+    fn from(e: CommandBufferExecError) -> Self {
+        log::error!("Could not execute the command buffer: {}", e);
+        GpuError::Other
+    }
+}
+
+#[cfg(feature = "vulkan")]
+impl From<HostAccessError> for GpuError {
+    fn from(e: HostAccessError) -> Self {
+        log::error!("Could not read the buffer: {}", e);
+        GpuError::Other
+    }
+}
+
 /// The state of the gpu at the execution of the command in the rendering thread
 /// Because the state can chanage after setting the command but before execution,
 /// we need to send the current state and keep it unmodified until the command is executed.
+///
+/// Without Vulkan, the draws are not rasterized, so the drawing state is never read.
 #[derive(Clone, Default)]
+#[cfg_attr(not(feature = "vulkan"), allow(dead_code))]
 struct GpuStateSnapshot {
     gpu_stat: GpuStat,
 
@@ -170,19 +371,83 @@ struct GpuStateSnapshot {
     cached_gp0_e3: u32,
     cached_gp0_e4: u32,
     cached_gp0_e5: u32,
+
+    render_options: GpuRenderOptions,
+}
+
+impl GpuStateSnapshot {
+    /// Only gouraud-shaded and texture-blended draws can be dithered
+    fn dither(&self, shaded_or_blended: bool) -> bool {
+        shaded_or_blended
+            && match self.render_options.dithering {
+                DitherMode::Hardware => self.gpu_stat.dither_enabled(),
+                DitherMode::Off => false,
+                DitherMode::ForceOn => true,
+            }
+    }
+
+    /// The top left position (in VRAM) and the size (in pixels) of the
+    /// area that will be displayed on the screen.
+    fn display_area(&self) -> ([u32; 2], [u32; 2]) {
+        let gpu_stat = self.gpu_stat;
+
+        // (((X2-X1)/cycles_per_pix)+2) AND NOT 3
+        let mut horizontal_size = (((self.display_horizontal_range.1
+            - self.display_horizontal_range.0)
+            / gpu_stat.horizontal_dots_divider())
+            + 2)
+            & !3;
+
+        if horizontal_size == 0 {
+            horizontal_size = gpu_stat.horizontal_resolution();
+        }
+
+        let should_double = gpu_stat.vertical_resolution() == 480;
+
+        // Y2-Y1, double if we are interlacing
+        let mut vertical_size =
+            (self.display_vertical_range.1 - self.display_vertical_range.0) << should_double as u32;
+
+        if vertical_size == 0 {
+            vertical_size = gpu_stat.vertical_resolution();
+        }
+
+        (
+            [
+                self.vram_display_area_start.0,
+                self.vram_display_area_start.1,
+            ],
+            [horizontal_size, vertical_size],
+        )
+    }
 }
 
+/// Without Vulkan, the draws are dropped, see [`GpuStateSnapshot`]
+#[cfg_attr(not(feature = "vulkan"), allow(dead_code))]
 enum BackendCommand {
+    #[cfg(feature = "vulkan")]
     BlitFront {
         full_vram: bool,
+        output_format: Format,
         state_snapshot: GpuStateSnapshot,
     },
     DrawPolyline {
         vertices: Vec<DrawingVertex>,
         semi_transparent: bool,
+        dither: bool,
         state_snapshot: GpuStateSnapshot,
     },
     DrawPolygon {
+        vertices: Vec<DrawingVertex>,
+        texture_params: DrawingTextureParams,
+        textured: bool,
+        texture_blending: bool,
+        semi_transparent: bool,
+        dither: bool,
+        state_snapshot: GpuStateSnapshot,
+    },
+    /// Rectangles are drawn as 2 triangles, but they are never dithered
+    DrawRectangle {
         vertices: Vec<DrawingVertex>,
         texture_params: DrawingTextureParams,
         textured: bool,
@@ -201,54 +466,62 @@ enum BackendCommand {
     VramReadBlock {
         block_range: (Range<u32>, Range<u32>),
     },
+    /// Read a block of VRAM and send it as is to the sender, the backend
+    /// never receives `VramReadBlock`, as it is converted to this one so that
+    /// `GPUREAD` can stream the result.
+    VramReadBlockRaw {
+        block_range: (Range<u32>, Range<u32>),
+        result_sender: Sender<Vec<u16>>,
+    },
     FillColor {
         top_left: (u32, u32),
         size: (u32, u32),
         color: (u8, u8, u8),
     },
+    /// Make the backend fail, to test the recovery, see [`Gpu::inject_error`]
+    #[cfg(feature = "vulkan")]
+    InjectError(GpuError),
 }
 
 pub struct Gpu {
-    // used for blitting to frontend
-    queue: Arc<Queue>,
-    device: Arc<Device>,
-
-    // handle the backend gpu thread
-    _gpu_backend_thread_handle: JoinHandle<()>,
+    // the VRAM and the rendering, in the host GPU or in memory
+    backend: Backend,
 
     /// holds commands that needs extra parameter and complex, like sending
     /// to/from VRAM, and rendering
     current_command: Option<Box<dyn Gp0Command>>,
-    // GPUREAD channel
-    gpu_read_sender: Sender<u32>,
-    gpu_read_receiver: Receiver<u32>,
-    // backend commands channel
-    gpu_backend_sender: Sender<BackendCommand>,
-    // channel for front image coming from backend
-    gpu_front_image_receiver: Receiver<Arc<Image>>,
-
-    first_frame: bool,
-    current_front_image: Option<Arc<Image>>,
-    command_buffer_allocator: StandardCommandBufferAllocator,
+    // the current `VRAM to CPU` transfer, served through GPUREAD
+    vram_read: Option<VramReadTransfer>,
+    // the value of GPUREAD when there is no transfer, set by GP1(10h)
+    gpu_read_latch: u32,
 
     // shared GPUSTAT
     gpu_stat: Arc<AtomicCell<GpuStat>>,
     state_snapshot: GpuStateSnapshot,
 
-    scanline: u32,
-    dot: u32,
-    drawing_odd: bool,
-    in_vblank: bool,
+    video_timing: VideoTiming,
+    region_override: RegionOverride,
+    /// `GPUSTAT.24` when IRQ1 was last requested, so it's requested only once
+    /// until acknowledged with `GP1(02h)`
+    irq_requested: bool,
 
-    cpu_cycles_counter: u32,
+    command_capture: Option<GpuCaptureWriter>,
+    tracer: Tracer,
 }
 
 impl Gpu {
+    #[cfg(feature = "vulkan")]
     pub fn new(device: Arc<Device>, queue: Arc<Queue>) -> Self {
-        let (gpu_read_sender, gpu_read_receiver) = crossbeam::channel::unbounded();
-        let (gpu_backend_sender, gpu_backend_receiver) = crossbeam::channel::unbounded();
-        let (gpu_front_image_sender, gpu_front_image_receiver) = crossbeam::channel::unbounded();
+        Self::with_backend(Backend::new(device, queue))
+    }
 
+    /// Without Vulkan, the VRAM is kept in memory, and the draws are dropped
+    #[cfg(not(feature = "vulkan"))]
+    pub fn new() -> Self {
+        Self::with_backend(Backend::default())
+    }
+
+    fn with_backend(backend: Backend) -> Self {
         let gpu_stat = Arc::new(AtomicCell::new(
             GpuStat::READY_FOR_CMD_RECV | GpuStat::READY_FOR_DMA_RECV,
         ));
@@ -272,163 +545,226 @@ impl Gpu {
             vram_display_area_start: (0, 0),
             display_horizontal_range: (0, 0),
             display_vertical_range: (0, 0),
-        };
 
-        let _gpu_backend_thread_handle = GpuBackend::start(
-            device.clone(),
-            queue.clone(),
-            gpu_stat.clone(),
-            gpu_read_sender.clone(),
-            gpu_backend_receiver,
-            gpu_front_image_sender,
-        );
+            render_options: GpuRenderOptions::default(),
+        };
 
         Self {
-            queue,
-            device: device.clone(),
-
-            _gpu_backend_thread_handle,
+            backend,
 
             current_command: None,
-            gpu_read_sender,
-            gpu_read_receiver,
-            gpu_backend_sender,
-            gpu_front_image_receiver,
-
-            first_frame: true,
-            current_front_image: None,
-            command_buffer_allocator: StandardCommandBufferAllocator::new(
-                device,
-                Default::default(),
-            ),
+            vram_read: None,
+            gpu_read_latch: 0,
 
             gpu_stat,
             state_snapshot,
 
-            scanline: 0,
-            dot: 0,
-            drawing_odd: false,
-            in_vblank: false,
-            cpu_cycles_counter: 0,
+            video_timing: VideoTiming::default(),
+            region_override: RegionOverride::Auto,
+            irq_requested: false,
+
+            command_capture: None,
+            tracer: Tracer::default(),
         }
     }
 
+    /// Recreate the GPU, the capture, the render options, the output format and
+    /// the region override continue across resets.
+    ///
+    /// The VRAM is cleared. On hardware it keeps its content through the reset
+    /// button, but the BIOS overwrites the parts it shows, and a cleared VRAM makes
+    /// a reset boot the same as a new emulator.
     pub fn reset(&mut self) {
-        let _ = std::mem::replace(self, Self::new(self.device.clone(), self.queue.clone()));
+        // the capture and the options continue across resets
+        let command_capture = self.command_capture.take();
+        let render_options = self.state_snapshot.render_options;
+        let region_override = self.region_override;
+        // the output format is kept by the new backend
+        let backend = self.backend.recreate();
+        let _ = std::mem::replace(self, Self::with_backend(backend));
+        self.command_capture = command_capture;
+        self.state_snapshot.render_options = render_options;
+        self.region_override = region_override;
     }
 
-    /// returns the number of `dot_clocks`, and if `hblank_clock` occurres
-    /// when clocking the gpu for `cycles` cycles.
-    /// These clocks are used for timers.
-    pub fn clock(
-        &mut self,
-        interrupt_requester: &mut impl InterruptRequester,
-        cpu_cycles: u32,
-    ) -> (u32, bool) {
-        // The GPU clock is CPU*11/7 == 53.222400MHz
-        // The FPS is determined by the mode, NTSC is 60~Hz, PAL is 50~Hz
-        self.cpu_cycles_counter += cpu_cycles * 11;
+    pub fn render_options(&self) -> GpuRenderOptions {
+        self.state_snapshot.render_options
+    }
 
-        let cycles = self.cpu_cycles_counter / 7;
-        self.cpu_cycles_counter %= 7;
+    /// Applies to the draws after this call
+    pub fn set_render_options(&mut self, render_options: GpuRenderOptions) {
+        self.state_snapshot.render_options = render_options;
+    }
 
-        let gpu_stat = self.gpu_stat.load();
-        let max_dots = if gpu_stat.is_ntsc_video_mode() {
-            3413
-        } else {
-            3406
-        };
-        let max_scanlines = if gpu_stat.is_ntsc_video_mode() {
-            263
-        } else {
-            314
-        };
-        let horizontal_dots_divider = gpu_stat.horizontal_dots_divider();
-        let vertical_resolution = gpu_stat.vertical_resolution();
-        let is_interlace = gpu_stat.intersects(GpuStat::VERTICAL_INTERLACE);
-
-        // we can't overflow the max_dots and clock for example more than one
-        // scanline at a time.
-        assert!(cycles < max_dots);
-        self.dot += cycles;
-
-        // If the increment is more than the divider, we will clock the timer by the number
-        // of times the divider fits in the increment.
-        let mut dot_clocks = cycles / horizontal_dots_divider;
-
-        // We may have extra cycles to clock for one more time.
-        // For example:
-        // - divider = 10
-        // - cycles = 15
-        // If we follow the cycles increment, we will skip one value:
-        // 0 -> 15 -> 30. We lose the increment, when we got to `20` we will lose the
-        // `dot_clock`, but with the following check, we can know that we missed it
-        // and handle it accordingly.
-        if (self.dot % horizontal_dots_divider) < (cycles % horizontal_dots_divider) {
-            dot_clocks += 1;
+    #[cfg(feature = "vulkan")]
+    pub fn output_format(&self) -> Format {
+        self.backend.output_format
+    }
+
+    /// The format of the next front images, the backend renders the display area
+    /// directly into it, so frontends can use the format of their swapchain and
+    /// copy the image without a conversion.
+    ///
+    /// It must be a color format with red, green and blue components, stored as
+    /// normalized integers or floats, that the device can render to, sample, and
+    /// blit from. The image requested before this call still has the old format.
+    #[cfg(feature = "vulkan")]
+    pub fn set_output_format(&mut self, format: Format) -> Result<(), PsxError> {
+        let required_features = FormatFeatures::COLOR_ATTACHMENT
+            | FormatFeatures::SAMPLED_IMAGE
+            | FormatFeatures::TRANSFER_SRC
+            | FormatFeatures::TRANSFER_DST
+            | FormatFeatures::BLIT_SRC;
+        let supported_features = self
+            .backend
+            .device
+            .physical_device()
+            .format_properties(format)
+            .map(|properties| properties.optimal_tiling_features)
+            .unwrap_or_default();
+        let rgb = format.components()[..3].iter().all(|&bits| bits > 0);
+        let numeric_format = matches!(
+            format.numeric_format_color(),
+            Some(NumericFormat::UNORM | NumericFormat::SRGB | NumericFormat::SFLOAT)
+        );
+
+        if !rgb || !numeric_format || !supported_features.contains(required_features) {
+            return Err(PsxError::UnsupportedOutputFormat(format));
         }
+        self.backend.output_format = format;
+        Ok(())
+    }
+
+    /// The stats of the frame of the last image returned by
+    /// [`sync_and_take_front_image`](Self::sync_and_take_front_image),
+    /// all zeros without Vulkan
+    pub fn stats(&self) -> GpuStats {
+        self.backend.stats()
+    }
 
-        let mut hblank_clock = false;
-        if self.dot >= max_dots {
-            hblank_clock = true;
-            self.dot -= max_dots;
-            self.scanline += 1;
+    /// The next error of the backend, and whether it recovered from it by
+    /// recreating its resources, if not, nothing is rendered anymore
+    pub fn take_error(&mut self) -> Option<(GpuError, bool)> {
+        self.backend.take_error()
+    }
 
-            if is_interlace && vertical_resolution == 240 && self.scanline < 240 {
-                self.drawing_odd = !self.drawing_odd;
-            }
+    /// Make the next draw of the backend fail with `error`
+    #[cfg(feature = "vulkan")]
+    pub(crate) fn inject_error(&mut self, error: GpuError) {
+        self.send_backend_command(BackendCommand::InjectError(error));
+    }
 
-            if self.scanline >= max_scanlines {
-                self.scanline = 0;
-                self.in_vblank = false;
+    fn send_backend_command(&mut self, command: BackendCommand) {
+        self.backend.send(command);
+    }
 
-                if is_interlace && vertical_resolution == 480 {
-                    self.drawing_odd = !self.drawing_odd;
-                }
-            }
+    /// Takes effect immediately, the current frame continues with the new timing
+    pub fn set_region_override(&mut self, region_override: RegionOverride) {
+        self.region_override = region_override;
+    }
 
-            if self.scanline == 240 {
-                interrupt_requester.request_vblank();
-                self.in_vblank = true;
-            }
+    /// Only `GPUSTAT`, the VRAM is in the host GPU, and reading it back every
+    /// time would stall the rendering
+    pub(crate) fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_u32(self.gpu_stat.load().bits());
+    }
+
+    /// The refresh rate of the current video mode, in frames per second
+    pub fn frame_rate(&self) -> f64 {
+        self.gpu_stat
+            .load()
+            .with_region_override(self.region_override)
+            .video_mode()
+            .standard
+            .frame_rate()
+    }
+
+    pub(crate) fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = tracer;
+    }
+
+    /// Clock the video timing for `cpu_cycles`, returns the dot clocks and
+    /// hblanks that happened, these are used for the timers.
+    pub fn clock(
+        &mut self,
+        interrupt_requester: &mut impl InterruptRequester,
+        cpu_cycles: u32,
+    ) -> VideoClocks {
+        let video_mode = self
+            .gpu_stat
+            .load()
+            .with_region_override(self.region_override)
+            .video_mode();
+        let clocks = self.video_timing.clock(&video_mode, cpu_cycles);
+        if clocks.vblank_started {
+            interrupt_requester.request_vblank();
+            self.capture_command(GpuCaptureRecord::FrameEnd);
+        }
+
+        // set by `GP0(1Fh)`
+        let irq = self.gpu_stat.load().intersects(GpuStat::INTERRUPT_REQUEST);
+        if irq && !self.irq_requested {
+            interrupt_requester.request_gpu();
         }
+        self.irq_requested = irq;
 
-        (dot_clocks, hblank_clock)
+        clocks
     }
 
     pub fn in_vblank(&self) -> bool {
-        self.in_vblank
+        self.video_timing.in_vblank()
     }
 
-    pub fn sync_gpu_and_blit_to_front(
-        &mut self,
-        dest_image: Arc<Image>,
-        full_vram: bool,
-        in_future: Box<dyn GpuFuture>,
-    ) -> Box<dyn GpuFuture> {
+    pub fn vblank_count(&self) -> u64 {
+        self.video_timing.vblank_count()
+    }
+
+    pub fn hblank_count(&self) -> u64 {
+        self.video_timing.hblank_count()
+    }
+
+    /// Wait for the previous front image, and request the next one from the backend.
+    ///
+    /// The returned image is fully rendered, so it can be used from any thread.
+    /// Returns `None` on the first call, as there is no previous request.
+    #[cfg(feature = "vulkan")]
+    pub fn sync_and_take_front_image(&mut self, full_vram: bool) -> Option<Arc<Image>> {
         // if we have a previous image, then we are not in the first frame,
         // so there should be an image in the channel.
-        if !self.first_frame {
+        if !self.backend.first_frame {
             // `recv` is blocking, here we will wait for the GPU to finish all drawing.
             // FIXME: Do not block. Find a way to keep the GPU synced with minimal performance loss.
-            self.current_front_image = Some(self.gpu_front_image_receiver.recv().unwrap());
+            // if the backend stopped, keep showing the last image
+            if let Ok((front_image, stats)) = self.backend.front_image_receiver.recv() {
+                self.backend.current_front_image = Some(front_image);
+                self.backend.stats = stats;
+            }
         }
-        self.first_frame = false;
+        self.backend.first_frame = false;
 
         // send command for next frame from now, so when we recv later, its mostly will be ready
         self.state_snapshot.gpu_stat = self.gpu_stat.load();
-        self.gpu_backend_sender
-            .send(BackendCommand::BlitFront {
-                full_vram,
-                state_snapshot: self.state_snapshot.clone(),
-            })
-            .unwrap();
+        self.send_backend_command(BackendCommand::BlitFront {
+            full_vram,
+            output_format: self.backend.output_format,
+            state_snapshot: self.state_snapshot.clone(),
+        });
 
-        if let Some(img) = self.current_front_image.as_ref() {
+        self.backend.current_front_image.clone()
+    }
+
+    #[cfg(feature = "vulkan")]
+    pub fn sync_gpu_and_blit_to_front(
+        &mut self,
+        dest_image: Arc<Image>,
+        full_vram: bool,
+        in_future: Box<dyn GpuFuture>,
+    ) -> Box<dyn GpuFuture> {
+        if let Some(img) = self.sync_and_take_front_image(full_vram) {
             let mut builder: AutoCommandBufferBuilder<PrimaryAutoCommandBuffer> =
                 AutoCommandBufferBuilder::primary(
-                    &self.command_buffer_allocator,
-                    self.queue.queue_family_index(),
+                    &self.backend.command_buffer_allocator,
+                    self.backend.queue.queue_family_index(),
                     CommandBufferUsage::OneTimeSubmit,
                 )
                 .unwrap();
@@ -436,14 +772,14 @@ impl Gpu {
             builder
                 .blit_image(BlitImageInfo {
                     filter: Filter::Nearest,
-                    ..BlitImageInfo::images(img.clone(), dest_image)
+                    ..BlitImageInfo::images(img, dest_image)
                 })
                 .unwrap();
             let cb = builder.build().unwrap();
 
             // TODO: remove wait
             in_future
-                .then_execute(self.queue.clone(), cb)
+                .then_execute(self.backend.queue.clone(), cb)
                 .unwrap()
                 .then_signal_fence_and_flush()
                 .unwrap()
@@ -455,11 +791,126 @@ impl Gpu {
     }
 }
 
+impl Gpu {
+    /// Start recording all `GP0` and `GP1` writes into `path`, see
+    /// [`GpuCaptureReader`] for the format. Any running capture is stopped first.
+    pub(crate) fn start_command_capture<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.stop_command_capture()?;
+        self.command_capture = Some(GpuCaptureWriter::create(path)?);
+        Ok(())
+    }
+
+    pub(crate) fn stop_command_capture(&mut self) -> io::Result<()> {
+        if let Some(command_capture) = self.command_capture.take() {
+            command_capture.finish()?;
+        }
+        Ok(())
+    }
+
+    fn capture_command(&mut self, record: GpuCaptureRecord) {
+        if let Some(command_capture) = &mut self.command_capture {
+            if let Err(e) = command_capture.write(record) {
+                log::error!("Failed to write GPU capture, stopping: {}", e);
+                self.command_capture = None;
+            }
+        }
+    }
+
+    /// Write all the commands of a capture, returns the number of frames in it.
+    pub(crate) fn replay_command_capture<R: Read>(
+        &mut self,
+        reader: GpuCaptureReader<R>,
+    ) -> io::Result<u64> {
+        let mut frames = 0;
+        for record in reader {
+            match record? {
+                GpuCaptureRecord::Gp0(word) => self.write_u32(0, word).unwrap(),
+                GpuCaptureRecord::Gp1(word) => self.write_u32(4, word).unwrap(),
+                GpuCaptureRecord::FrameEnd => frames += 1,
+            }
+        }
+        Ok(frames)
+    }
+
+    /// Read a block of VRAM, row by row.
+    ///
+    /// This will block until the GPU finishes all pending draws.
+    pub(crate) fn read_vram_block(
+        &mut self,
+        left: u32,
+        top: u32,
+        width: u32,
+        height: u32,
+    ) -> Vec<u16> {
+        let (result_sender, result_receiver) = crossbeam::channel::bounded(1);
+        self.send_backend_command(BackendCommand::VramReadBlockRaw {
+            block_range: (left..left + width, top..top + height),
+            result_sender,
+        });
+        // if the backend stopped, there is nothing to read
+        result_receiver
+            .recv()
+            .unwrap_or_else(|_| vec![0; (width * height) as usize])
+    }
+
+    /// Read the current display area from VRAM, and convert it to `RGB888`
+    ///
+    /// This will block until the GPU finishes all pending draws.
+    pub(crate) fn read_display_frame(&mut self) -> Frame {
+        self.state_snapshot.gpu_stat = self.gpu_stat.load();
+        let is_24bit = self.state_snapshot.gpu_stat.is_24bit_color_depth();
+        let ([left, top], [width, height]) = self.state_snapshot.display_area();
+
+        // disabled with `GP1(03h)`, the same as the front image
+        if !self.state_snapshot.gpu_stat.display_enabled() {
+            return Frame {
+                width,
+                height,
+                rgb: vec![0; (width * height * 3) as usize],
+            };
+        }
+
+        // in 24bit mode, every 2 pixels take 3 halfwords
+        let vram_width = if is_24bit {
+            (width * 3).div_ceil(2)
+        } else {
+            width
+        };
+
+        let block = self.read_vram_block(left, top, vram_width, height);
+
+        let mut rgb = Vec::with_capacity((width * height * 3) as usize);
+        for row in block.chunks_exact(vram_width as usize) {
+            if is_24bit {
+                let bytes = row.iter().flat_map(|p| p.to_le_bytes());
+                rgb.extend(bytes.take(width as usize * 3));
+            } else {
+                for &p in row {
+                    let r = (p & 0x1F) as u8;
+                    let g = ((p >> 5) & 0x1F) as u8;
+                    let b = ((p >> 10) & 0x1F) as u8;
+                    rgb.extend([
+                        (r << 3) | (r >> 2),
+                        (g << 3) | (g >> 2),
+                        (b << 3) | (b >> 2),
+                    ]);
+                }
+            }
+        }
+
+        Frame { width, height, rgb }
+    }
+}
+
 impl Gpu {
     fn read_gpu_stat(&self) -> u32 {
-        let interlace_bit = (self.drawing_odd && !self.in_vblank) as u32;
+        let interlace_bit = self.video_timing.drawing_odd() as u32;
         // set by GP1(0x8)
-        let interlace_field = if self.gpu_stat.load().intersects(GpuStat::INTERLACE_FIELD) {
+        let gpu_stat = self
+            .gpu_stat
+            .load()
+            .with_region_override(self.region_override);
+        let interlace_field = if gpu_stat.intersects(GpuStat::INTERLACE_FIELD) {
             1 // always on
         } else {
             interlace_bit ^ 1
@@ -467,23 +918,27 @@ impl Gpu {
 
         // Ready to receive Cmd Word
         // Ready to receive DMA Block
-        let out = self.gpu_stat.load().bits() | (interlace_bit << 31) | (interlace_field << 13);
+        let out = gpu_stat.bits() | (interlace_bit << 31) | (interlace_field << 13);
         log::trace!("GPUSTAT = {:08X}", out);
-        log::trace!("GPUSTAT = {:?}", self.gpu_stat);
+        log::trace!("GPUSTAT = {:?}", gpu_stat);
         out
     }
 
     fn gpu_read(&mut self) -> u32 {
-        let out = self.gpu_read_receiver.try_recv();
+        if let Some(transfer) = &mut self.vram_read {
+            // the last word stays in GPUREAD after the transfer
+            self.gpu_read_latch = transfer.read_word();
 
-        if self.gpu_read_receiver.is_empty() {
-            self.gpu_stat
-                .fetch_update(|s| Some(s - GpuStat::READY_FOR_TO_SEND_VRAM))
-                .unwrap();
+            if transfer.is_done() {
+                self.vram_read = None;
+                self.gpu_stat
+                    .fetch_update(|s| Some(s - GpuStat::READY_FOR_TO_SEND_VRAM))
+                    .unwrap();
+            }
         }
 
-        log::trace!("GPUREAD = {:08X?}", out);
-        out.unwrap_or(0)
+        log::trace!("GPUREAD = {:08X}", self.gpu_read_latch);
+        self.gpu_read_latch
     }
 }
 impl Gpu {
@@ -503,12 +958,7 @@ impl Gpu {
                         .fetch_update(|s| Some(s - GpuStat::READY_FOR_DMA_RECV))
                         .unwrap();
 
-                    log::info!("executing command {:?}", cmd.cmd_type());
-                    if let Some(backend_cmd) =
-                        cmd.exec_command(self.gpu_stat.clone(), &mut self.state_snapshot)
-                    {
-                        self.gpu_backend_sender.send(backend_cmd).unwrap();
-                    }
+                    self.exec_gp0_command(cmd);
 
                     // ready for next command
                     self.gpu_stat
@@ -529,16 +979,54 @@ impl Gpu {
                     .fetch_update(|s| Some(s - GpuStat::READY_FOR_CMD_RECV))
                     .unwrap();
             } else {
-                log::info!("executing command {:?}", cmd.cmd_type());
-                if let Some(backend_cmd) =
-                    cmd.exec_command(self.gpu_stat.clone(), &mut self.state_snapshot)
-                {
-                    self.gpu_backend_sender.send(backend_cmd).unwrap();
-                }
+                self.exec_gp0_command(cmd);
+            }
+        }
+    }
+
+    fn exec_gp0_command(&mut self, cmd: Box<dyn Gp0Command>) {
+        let cmd_type = cmd.cmd_type();
+        log::info!("executing command {:?}", cmd_type);
+        if let Some(backend_cmd) = cmd.exec_command(self.gpu_stat.clone(), &mut self.state_snapshot)
+        {
+            if let BackendCommand::DrawPolygon { vertices, .. }
+            | BackendCommand::DrawRectangle { vertices, .. }
+            | BackendCommand::DrawPolyline { vertices, .. } = &backend_cmd
+            {
+                self.tracer.trace(|| TraceEvent::GpuDrawCall {
+                    prim: match cmd_type {
+                        Gp0CmdType::Rectangle => GpuPrimitive::Rectangle,
+                        Gp0CmdType::Line => GpuPrimitive::Line,
+                        _ => GpuPrimitive::Polygon,
+                    },
+                    vertices: vertices.len() as u32,
+                });
+            }
+            if let BackendCommand::VramReadBlock { block_range } = backend_cmd {
+                self.start_vram_read(block_range);
+            } else {
+                self.send_backend_command(backend_cmd);
             }
         }
     }
 
+    /// Start reading the block from the backend, without waiting for it,
+    /// the data will be served by `gpu_read` word by word.
+    fn start_vram_read(&mut self, block_range: (Range<u32>, Range<u32>)) {
+        let (result_sender, result_receiver) = crossbeam::channel::bounded(1);
+        self.send_backend_command(BackendCommand::VramReadBlockRaw {
+            block_range,
+            result_sender,
+        });
+
+        let transfer = VramReadTransfer::new(result_receiver);
+        // a new transfer replaces the old one
+        self.vram_read = Some(transfer);
+        self.gpu_stat
+            .fetch_update(|s| Some(s | GpuStat::READY_FOR_TO_SEND_VRAM))
+            .unwrap();
+    }
+
     /// Execute instructions we can from frontend, or else send to backend.
     /// This allows for GPU_STAT register to be synced.
     fn handle_gp1(&mut self, data: u32) {
@@ -554,6 +1042,7 @@ impl Gpu {
                         | GpuStat::READY_FOR_DMA_RECV
                         | GpuStat::READY_FOR_CMD_RECV,
                 );
+                self.irq_requested = false;
             }
             0x01 => {
                 // Reset command fifo buffer
@@ -568,7 +1057,7 @@ impl Gpu {
                         if let Some(backend_cmd) =
                             cmd.exec_command(self.gpu_stat.clone(), &mut self.state_snapshot)
                         {
-                            self.gpu_backend_sender.send(backend_cmd).unwrap();
+                            self.send_backend_command(backend_cmd);
                         }
                     }
                 }
@@ -579,6 +1068,7 @@ impl Gpu {
                 self.gpu_stat
                     .fetch_update(|s| Some(s.difference(GpuStat::INTERRUPT_REQUEST)))
                     .unwrap();
+                self.irq_requested = false;
             }
             0x03 => {
                 // Display enable
@@ -690,8 +1180,8 @@ impl Gpu {
                         self.state_snapshot.cached_gp0_e5
                     }
                     6 => {
-                        // TODO: return old value of GPUREAD
-                        0
+                        // keep the old value of GPUREAD
+                        self.gpu_read_latch
                     }
                     7 => {
                         // GPU type
@@ -702,14 +1192,14 @@ impl Gpu {
                         0
                     }
                     _ => {
-                        // TODO: return old value of GPUREAD
-                        0
+                        // keep the old value of GPUREAD
+                        self.gpu_read_latch
                     }
                 };
 
-                self.gpu_read_sender.send(result).unwrap();
+                self.gpu_read_latch = result;
             }
-            _ => todo!("gp1 command {:02X}", cmd),
+            _ => log::warn!("gp1 command {:02X} is not supported", cmd),
         }
     }
 }
@@ -727,9 +1217,11 @@ impl BusLine for Gpu {
     fn write_u32(&mut self, addr: u32, data: u32) -> Result<()> {
         match addr {
             0 => {
+                self.capture_command(GpuCaptureRecord::Gp0(data));
                 self.handle_gp0(data);
             }
             4 => {
+                self.capture_command(GpuCaptureRecord::Gp1(data));
                 self.handle_gp1(data);
             }
             _ => unreachable!(),
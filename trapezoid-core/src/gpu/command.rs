@@ -2,7 +2,7 @@ use std::sync::Arc;
 
 use crossbeam::atomic::AtomicCell;
 
-use super::gpu_context::{vertex_position_from_u32, DrawingTextureParams, DrawingVertex};
+use super::drawing::{vertex_position_from_u32, DrawingTextureParams, DrawingVertex};
 use super::{BackendCommand, GpuStat, GpuStateSnapshot};
 
 #[derive(Debug)]
@@ -150,12 +150,15 @@ impl Gp0Command for PolygonCommand {
                 .unwrap();
         }
         state_snapshot.gpu_stat = gpu_stat.load();
+        let dither =
+            state_snapshot.dither(self.gouraud || (self.textured && self.texture_blending));
         Some(BackendCommand::DrawPolygon {
             vertices: self.vertices[..input_pointer].to_vec(),
             texture_params: self.texture_params,
             textured: self.textured,
             texture_blending: self.texture_blending,
             semi_transparent: self.semi_transparent,
+            dither,
             state_snapshot: state_snapshot.clone(),
         })
     }
@@ -257,6 +260,7 @@ impl Gp0Command for LineCommand {
         Some(BackendCommand::DrawPolyline {
             vertices: self.vertices,
             semi_transparent: self.semi_transparent,
+            dither: state_snapshot.dither(self.gouraud),
             state_snapshot: state_snapshot.clone(),
         })
     }
@@ -358,19 +362,27 @@ impl Gp0Command for RectangleCommand {
             return None; // empty rect
         }
         // The tex_coords, are large i32 numbers, they can be negative or more
-        // than 255, and the shader will handle repeating and flipping based on the values.
+        // than 255, and the shader will handle repeating based on the values.
+        //
+        // Rectangles are not interpolated on hardware, the texcoord is stepped by 1
+        // for every pixel, starting from the top left one (decremented if flipped).
+        // The shader samples at the pixel center and floors, so for `tl..tl+size` pixel `i`
+        // gets `tl+i+0.5` => `tl+i`. For flipped axes, we use `tl+1..tl+1-size`, so
+        // pixel `i` gets `tl-i+0.5` => `tl-i`.
         let size_f32 = [size[0] as f32, size[1] as f32];
-        let mut bottom_right_tex = [top_left_tex[0], top_left_tex[1]];
-        if state_snapshot.textured_rect_flip.0 {
-            bottom_right_tex[0] -= size[0] - 1;
-        } else {
-            bottom_right_tex[0] += size[0];
-        }
-        if state_snapshot.textured_rect_flip.1 {
-            bottom_right_tex[1] -= size[1] - 1;
-        } else {
-            bottom_right_tex[1] += size[1];
-        }
+        let edges_tex = |start: i32, size: i32, flip: bool| {
+            if flip {
+                (start + 1, start + 1 - size)
+            } else {
+                (start, start + size)
+            }
+        };
+        let (flip_x, flip_y) = state_snapshot.textured_rect_flip;
+        let (left_tex, right_tex) = edges_tex(top_left_tex[0], size[0], flip_x);
+        let (top_tex, bottom_tex) = edges_tex(top_left_tex[1], size[1], flip_y);
+        let top_left_tex = [left_tex, top_tex];
+        let bottom_right_tex = [right_tex, bottom_tex];
+        self.vertices[0].set_tex_coord(top_left_tex);
 
         // top right
         self.vertices[1].set_position([top_left[0] + size_f32[0], top_left[1]]);
@@ -405,7 +417,7 @@ impl Gp0Command for RectangleCommand {
         log::info!("RECTANGLE executing {:#?}", self);
 
         state_snapshot.gpu_stat = gpu_stat.load();
-        Some(BackendCommand::DrawPolygon {
+        Some(BackendCommand::DrawRectangle {
             vertices: self.vertices,
             texture_params: self.texture_params,
             textured: self.textured,
@@ -440,7 +452,7 @@ impl Gp0Command for MiscCommand {
 
     fn exec_command(
         self: Box<Self>,
-        _gpu_stat: Arc<AtomicCell<GpuStat>>,
+        gpu_stat: Arc<AtomicCell<GpuStat>>,
         _state_snapshot: &mut GpuStateSnapshot,
     ) -> Option<BackendCommand> {
         let data = self.0;
@@ -452,7 +464,14 @@ impl Gp0Command for MiscCommand {
             0x01 => {
                 // Invalidate CLUT cache
             }
-            _ => todo!("gp0 misc command {:02X}", cmd),
+            0x1F => {
+                // Interrupt Request (IRQ1), stays until `GP1(02h)`,
+                // the interrupt itself is requested in `Gpu::clock`
+                gpu_stat
+                    .fetch_update(|s| Some(s.union(GpuStat::INTERRUPT_REQUEST)))
+                    .unwrap();
+            }
+            _ => log::warn!("gp0 misc command {:02X} is not supported", cmd),
         }
         None
     }
@@ -914,7 +933,8 @@ impl Gp0Command for EnvironmentCommand {
                     })
                     .unwrap();
             }
-            _ => todo!("gp0 environment command {:02X}", cmd),
+            // `E0`, `E7..=EF` are not used
+            _ => log::warn!("gp0 environment command {:02X} is not valid", cmd),
         }
 
         None
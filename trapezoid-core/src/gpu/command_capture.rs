@@ -0,0 +1,229 @@
+//! Capture of the GPU command stream, all words written to `GP0` and `GP1`
+//! (from the CPU and DMA), with the frame boundaries.
+//!
+//! The format is (all values are little endian):
+//! - Header: the magic `TRPZGPU\0`, followed by the version as `u32` (currently `1`).
+//! - Records until the end of the file, each starts with a `u8` kind:
+//!   - `0`: GP0 words, followed by the number of words `u32`, then the words.
+//!     Consecutive GP0 writes are grouped into one record.
+//!   - `1`: GP1 word, followed by the word `u32`.
+//!   - `2`: Frame end (start of vblank), no data.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+const MAGIC: &[u8; 8] = b"TRPZGPU\0";
+const VERSION: u32 = 1;
+
+const RECORD_GP0: u8 = 0;
+const RECORD_GP1: u8 = 1;
+const RECORD_FRAME_END: u8 = 2;
+
+/// A record of the capture, the grouped GP0 words are read as one record each
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpuCaptureRecord {
+    /// A word written to `GP0`
+    Gp0(u32),
+    /// A word written to `GP1`
+    Gp1(u32),
+    /// The start of vblank
+    FrameEnd,
+}
+
+pub(crate) struct GpuCaptureWriter<W: Write = BufWriter<File>> {
+    writer: W,
+    /// Consecutive GP0 words, written as one record when another record comes
+    gp0_words: Vec<u32>,
+}
+
+impl GpuCaptureWriter {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> GpuCaptureWriter<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        Ok(Self {
+            writer,
+            gp0_words: Vec::new(),
+        })
+    }
+
+    pub fn write(&mut self, record: GpuCaptureRecord) -> io::Result<()> {
+        match record {
+            GpuCaptureRecord::Gp0(word) => {
+                self.gp0_words.push(word);
+                Ok(())
+            }
+            GpuCaptureRecord::Gp1(word) => {
+                self.flush_gp0()?;
+                self.writer.write_all(&[RECORD_GP1])?;
+                self.writer.write_all(&word.to_le_bytes())
+            }
+            GpuCaptureRecord::FrameEnd => {
+                self.flush_gp0()?;
+                self.writer.write_all(&[RECORD_FRAME_END])
+            }
+        }
+    }
+
+    fn flush_gp0(&mut self) -> io::Result<()> {
+        if self.gp0_words.is_empty() {
+            return Ok(());
+        }
+        self.writer.write_all(&[RECORD_GP0])?;
+        self.writer
+            .write_all(&(self.gp0_words.len() as u32).to_le_bytes())?;
+        for word in self.gp0_words.drain(..) {
+            self.writer.write_all(&word.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Write the remaining records and flush, returns the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush_gp0()?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Reads the records of a capture file, see the [module docs](self) for the format.
+pub struct GpuCaptureReader<R: Read = BufReader<File>> {
+    reader: R,
+    /// The remaining words of the current GP0 record
+    gp0_remaining: u32,
+}
+
+impl GpuCaptureReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> GpuCaptureReader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a GPU capture file",
+            ));
+        }
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported GPU capture version {}", version),
+            ));
+        }
+
+        Ok(Self {
+            reader,
+            gp0_remaining: 0,
+        })
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<GpuCaptureRecord>> {
+        if self.gp0_remaining > 0 {
+            self.gp0_remaining -= 1;
+            return read_u32(&mut self.reader).map(|w| Some(GpuCaptureRecord::Gp0(w)));
+        }
+
+        let mut kind = [0];
+        // the end of the file is only valid between records
+        if self.reader.read(&mut kind)? == 0 {
+            return Ok(None);
+        }
+
+        match kind[0] {
+            RECORD_GP0 => {
+                self.gp0_remaining = read_u32(&mut self.reader)?;
+                if self.gp0_remaining == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "empty GP0 record",
+                    ));
+                }
+                self.read_record()
+            }
+            RECORD_GP1 => read_u32(&mut self.reader).map(|w| Some(GpuCaptureRecord::Gp1(w))),
+            RECORD_FRAME_END => Ok(Some(GpuCaptureRecord::FrameEnd)),
+            kind => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid GPU capture record {}", kind),
+            )),
+        }
+    }
+}
+
+impl<R: Read> Iterator for GpuCaptureReader<R> {
+    type Item = io::Result<GpuCaptureRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_roundtrip() {
+        let records = [
+            GpuCaptureRecord::Gp1(0x00000000),
+            GpuCaptureRecord::Gp0(0xE3000000),
+            GpuCaptureRecord::Gp0(0xE4007C2F),
+            GpuCaptureRecord::Gp0(0x600000FF),
+            GpuCaptureRecord::FrameEnd,
+            GpuCaptureRecord::Gp1(0x03000000),
+            GpuCaptureRecord::FrameEnd,
+            GpuCaptureRecord::Gp0(0x02000000),
+        ];
+
+        let mut writer = GpuCaptureWriter::new(Vec::new()).unwrap();
+        for record in records {
+            writer.write(record).unwrap();
+        }
+        let data = writer.finish().unwrap();
+        // header + gp1 + 2 gp0 records (3+1 words) + 2 frames + gp1
+        assert_eq!(data.len(), 12 + 5 + (5 + 12) + 2 + 5 + (5 + 4));
+
+        let read = GpuCaptureReader::new(data.as_slice())
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(read, records);
+    }
+
+    #[test]
+    fn invalid_files() {
+        assert!(GpuCaptureReader::new(&b"TRPZGPU"[..]).is_err());
+        assert!(GpuCaptureReader::new(&b"NOTGPU\0\0\x01\0\0\0"[..]).is_err());
+        assert!(GpuCaptureReader::new(&b"TRPZGPU\0\x02\0\0\0"[..]).is_err());
+
+        // truncated GP0 record
+        let data = b"TRPZGPU\0\x01\0\0\0\x00\x02\0\0\0\x01\0\0\0";
+        let mut reader = GpuCaptureReader::new(&data[..]).unwrap();
+        assert_eq!(reader.next().unwrap().unwrap(), GpuCaptureRecord::Gp0(1));
+        assert!(reader.next().unwrap().is_err());
+
+        let data = b"TRPZGPU\0\x01\0\0\0\x07";
+        let mut reader = GpuCaptureReader::new(&data[..]).unwrap();
+        assert!(reader.next().unwrap().is_err());
+    }
+}
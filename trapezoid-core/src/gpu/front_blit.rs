@@ -1,9 +1,11 @@
 use std::sync::Arc;
 
+use super::GpuError;
+
 use vulkano::{
     buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer},
     command_buffer::{
-        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder,
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, ClearColorImageInfo,
         CommandBufferExecFuture, CommandBufferUsage, CopyBufferToImageInfo, CopyImageToBufferInfo,
         PrimaryAutoCommandBuffer, RenderPassBeginInfo,
     },
@@ -43,6 +45,10 @@ use vulkano::{
 const COMPUTE_24BIT_ROW_OPERATIONS: u32 = 512 / 3;
 const COMPUTE_LOCAL_SIZE_XY: u32 = 8;
 
+/// The front images kept for reuse, enough for the one being rendered, the last
+/// one kept by the `Gpu`, and the ones the frontend is still presenting
+const FRONT_IMAGE_POOL_SIZE: usize = 4;
+
 mod vs {
     vulkano_shaders::shader! {
         ty: "vertex",
@@ -144,6 +150,7 @@ struct Vertex {
 pub(super) struct FrontBlit {
     device: Arc<Device>,
     queue: Arc<Queue>,
+    memory_allocator: Arc<dyn MemoryAllocator>,
 
     command_buffer_allocator: StandardCommandBufferAllocator,
     descriptor_set_allocator: StandardDescriptorSetAllocator,
@@ -155,6 +162,10 @@ pub(super) struct FrontBlit {
     texture_24bit_out_buffer: Subbuffer<[u32]>,
     texture_24bit_desc_set: Arc<PersistentDescriptorSet>,
 
+    /// The format of the front images
+    format: Format,
+    front_images: Vec<Arc<Image>>,
+
     render_pass: Arc<RenderPass>,
     g_pipeline: Arc<GraphicsPipeline>,
     c_pipeline: Arc<ComputePipeline>,
@@ -162,88 +173,87 @@ pub(super) struct FrontBlit {
     vertex_buffer: Subbuffer<[Vertex]>,
 }
 
+/// The render pass and the pipeline that draw the display area to a front
+/// image of `format`
+fn graphics_pipeline(
+    device: &Arc<Device>,
+    format: Format,
+) -> Result<(Arc<RenderPass>, Arc<GraphicsPipeline>), GpuError> {
+    let vs = vs::load(device.clone())?.entry_point("main").unwrap();
+    let fs = fs::load(device.clone())?.entry_point("main").unwrap();
+
+    let render_pass = vulkano::single_pass_renderpass!(
+        device.clone(),
+        attachments: {
+            color: {
+                format: format,
+                samples: 1,
+                load_op: DontCare,
+                store_op: Store,
+            },
+        },
+        pass: {
+            color: [color],
+            depth_stencil: {},
+        },
+    )?;
+
+    let vertex_input_state = Vertex::per_vertex().definition(&vs.info().input_interface)?;
+    let g_stages = [
+        PipelineShaderStageCreateInfo::new(vs),
+        PipelineShaderStageCreateInfo::new(fs),
+    ];
+
+    let g_layout = PipelineLayout::new(
+        device.clone(),
+        PipelineDescriptorSetLayoutCreateInfo::from_stages(&g_stages)
+            .into_pipeline_layout_create_info(device.clone())
+            .unwrap(),
+    )?;
+
+    let subpass = Subpass::from(render_pass.clone(), 0).unwrap();
+    let g_pipeline = GraphicsPipeline::new(
+        device.clone(),
+        None,
+        GraphicsPipelineCreateInfo {
+            stages: g_stages.iter().cloned().collect(),
+            vertex_input_state: Some(vertex_input_state),
+            input_assembly_state: Some(InputAssemblyState {
+                topology: PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            }),
+            rasterization_state: Some(RasterizationState::default()),
+            multisample_state: Some(MultisampleState::default()),
+            viewport_state: Some(ViewportState::default()),
+            dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+            color_blend_state: Some(ColorBlendState::with_attachment_states(
+                1,
+                ColorBlendAttachmentState::default(),
+            )),
+            subpass: Some(subpass.into()),
+            ..GraphicsPipelineCreateInfo::layout(g_layout)
+        },
+    )?;
+
+    Ok((render_pass, g_pipeline))
+}
+
 impl FrontBlit {
     pub fn new(
         device: Arc<Device>,
         queue: Arc<Queue>,
         source_image: Arc<Image>,
         memory_allocator: Arc<dyn MemoryAllocator>,
-    ) -> Self {
-        let vs = vs::load(device.clone())
-            .unwrap()
-            .entry_point("main")
-            .unwrap();
-        let fs = fs::load(device.clone())
-            .unwrap()
-            .entry_point("main")
-            .unwrap();
-        let cs = cs::load(device.clone())
-            .unwrap()
-            .entry_point("main")
-            .unwrap();
+        format: Format,
+    ) -> Result<Self, GpuError> {
+        let cs = cs::load(device.clone())?.entry_point("main").unwrap();
 
         let descriptor_set_allocator =
             StandardDescriptorSetAllocator::new(device.clone(), Default::default());
         let command_buffer_allocator =
             StandardCommandBufferAllocator::new(device.clone(), Default::default());
 
-        let render_pass = vulkano::single_pass_renderpass!(
-            device.clone(),
-            attachments: {
-                color: {
-                    format: Format::B8G8R8A8_UNORM,
-                    samples: 1,
-                    load_op: DontCare,
-                    store_op: Store,
-                },
-            },
-            pass: {
-                color: [color],
-                depth_stencil: {},
-            },
-        )
-        .unwrap();
-
-        let vertex_input_state = Vertex::per_vertex()
-            .definition(&vs.info().input_interface)
-            .unwrap();
-        let g_stages = [
-            PipelineShaderStageCreateInfo::new(vs),
-            PipelineShaderStageCreateInfo::new(fs),
-        ];
-
-        let g_layout = PipelineLayout::new(
-            device.clone(),
-            PipelineDescriptorSetLayoutCreateInfo::from_stages(&g_stages)
-                .into_pipeline_layout_create_info(device.clone())
-                .unwrap(),
-        )
-        .unwrap();
-
-        let subpass = Subpass::from(render_pass.clone(), 0).unwrap();
-        let g_pipeline = GraphicsPipeline::new(
-            device.clone(),
-            None,
-            GraphicsPipelineCreateInfo {
-                stages: g_stages.iter().cloned().collect(),
-                vertex_input_state: Some(vertex_input_state),
-                input_assembly_state: Some(InputAssemblyState {
-                    topology: PrimitiveTopology::TriangleStrip,
-                    ..Default::default()
-                }),
-                rasterization_state: Some(RasterizationState::default()),
-                multisample_state: Some(MultisampleState::default()),
-                viewport_state: Some(ViewportState::default()),
-                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
-                color_blend_state: Some(ColorBlendState::with_attachment_states(
-                    1,
-                    ColorBlendAttachmentState::default(),
-                )),
-                subpass: Some(subpass.into()),
-                ..GraphicsPipelineCreateInfo::layout(g_layout)
-            },
-        )
-        .unwrap();
+        let (render_pass, g_pipeline) = graphics_pipeline(&device, format)?;
 
         let c_stage = PipelineShaderStageCreateInfo::new(cs);
         let c_layout = PipelineLayout::new(
@@ -251,14 +261,12 @@ impl FrontBlit {
             PipelineDescriptorSetLayoutCreateInfo::from_stages([&c_stage])
                 .into_pipeline_layout_create_info(device.clone())
                 .unwrap(),
-        )
-        .unwrap();
+        )?;
         let c_pipeline = ComputePipeline::new(
             device.clone(),
             None,
             ComputePipelineCreateInfo::stage_layout(c_stage, c_layout),
-        )
-        .unwrap();
+        )?;
 
         let texture_24bit_image = Image::new(
             memory_allocator.clone(),
@@ -270,8 +278,7 @@ impl FrontBlit {
                 ..Default::default()
             },
             AllocationCreateInfo::default(),
-        )
-        .unwrap();
+        )?;
 
         let texture_24bit_in_buffer = Buffer::new_slice::<u16>(
             memory_allocator.clone(),
@@ -284,8 +291,7 @@ impl FrontBlit {
                 ..Default::default()
             },
             1024 * 512,
-        )
-        .unwrap();
+        )?;
 
         let texture_24bit_out_buffer = Buffer::new_slice::<u32>(
             memory_allocator.clone(),
@@ -298,8 +304,7 @@ impl FrontBlit {
                 ..Default::default()
             },
             1024 * 512,
-        )
-        .unwrap();
+        )?;
 
         let texture_24bit_desc_set = PersistentDescriptorSet::new(
             &descriptor_set_allocator,
@@ -309,8 +314,7 @@ impl FrontBlit {
                 WriteDescriptorSet::buffer(1, texture_24bit_out_buffer.clone()),
             ],
             [],
-        )
-        .unwrap();
+        )?;
 
         let vertex_buffer = Buffer::from_iter(
             memory_allocator.clone(),
@@ -337,12 +341,12 @@ impl FrontBlit {
                     position: [1.0, 1.0],
                 },
             ],
-        )
-        .unwrap();
+        )?;
 
-        Self {
+        Ok(Self {
             device,
             queue,
+            memory_allocator,
             command_buffer_allocator,
             descriptor_set_allocator,
             texture_image: source_image,
@@ -350,11 +354,64 @@ impl FrontBlit {
             texture_24bit_in_buffer,
             texture_24bit_out_buffer,
             texture_24bit_desc_set,
+            format,
+            front_images: Vec::new(),
             render_pass,
             g_pipeline,
             c_pipeline,
             vertex_buffer,
+        })
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Draw the next front images in `format`, the images of the old format are dropped
+    pub fn set_format(&mut self, format: Format) -> Result<(), GpuError> {
+        (self.render_pass, self.g_pipeline) = graphics_pipeline(&self.device, format)?;
+        self.format = format;
+        self.front_images.clear();
+        Ok(())
+    }
+
+    /// A front image of `size`, reused from the ones the frontend dropped, or a
+    /// new one, returns `true` with it if it was allocated
+    pub fn front_image(&mut self, size: [u32; 2]) -> Result<(Arc<Image>, bool), GpuError> {
+        // nothing else holds a reference to it, not even a command buffer
+        let is_free = |image: &Arc<Image>| Arc::strong_count(image) == 1;
+
+        if let Some(image) = self
+            .front_images
+            .iter()
+            .find(|image| is_free(image) && image.extent()[..2] == size)
+        {
+            return Ok((image.clone(), false));
+        }
+
+        let image = Image::new(
+            self.memory_allocator.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                extent: [size[0], size[1], 1],
+                format: self.format,
+                // frontends can sample it in their output shaders
+                usage: ImageUsage::TRANSFER_DST
+                    | ImageUsage::TRANSFER_SRC
+                    | ImageUsage::SAMPLED
+                    | ImageUsage::COLOR_ATTACHMENT,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )?;
+
+        // the free ones have the wrong size, the display mode changed
+        if let Some(i) = self.front_images.iter().position(is_free) {
+            self.front_images[i] = image.clone();
+        } else if self.front_images.len() < FRONT_IMAGE_POOL_SIZE {
+            self.front_images.push(image.clone());
         }
+        Ok((image, true))
     }
 
     pub fn blit<IF>(
@@ -364,7 +421,7 @@ impl FrontBlit {
         size: [u32; 2],
         is_24bit_color_depth: bool,
         mut in_future: IF,
-    ) -> CommandBufferExecFuture<IF>
+    ) -> Result<CommandBufferExecFuture<IF>, GpuError>
     where
         IF: GpuFuture,
     {
@@ -378,36 +435,30 @@ impl FrontBlit {
                 &self.command_buffer_allocator,
                 self.queue.queue_family_index(),
                 CommandBufferUsage::OneTimeSubmit,
-            )
-            .unwrap();
+            )?;
 
         if is_24bit_color_depth {
             builder
                 .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
                     self.texture_image.clone(),
                     self.texture_24bit_in_buffer.clone(),
-                ))
-                .unwrap()
-                .bind_pipeline_compute(self.c_pipeline.clone())
-                .unwrap()
+                ))?
+                .bind_pipeline_compute(self.c_pipeline.clone())?
                 .bind_descriptor_sets(
                     PipelineBindPoint::Compute,
                     self.c_pipeline.layout().clone(),
                     0,
                     self.texture_24bit_desc_set.clone(),
-                )
-                .unwrap()
+                )?
                 .dispatch([
                     COMPUTE_24BIT_ROW_OPERATIONS / COMPUTE_LOCAL_SIZE_XY,
                     512 / COMPUTE_LOCAL_SIZE_XY,
                     1,
-                ])
-                .unwrap()
+                ])?
                 .copy_buffer_to_image(CopyBufferToImageInfo::buffer_image(
                     self.texture_24bit_out_buffer.clone(),
                     self.texture_24bit_image.clone(),
-                ))
-                .unwrap();
+                ))?;
 
             source_image = self.texture_24bit_image.clone();
         }
@@ -421,8 +472,7 @@ impl FrontBlit {
                 address_mode: [SamplerAddressMode::Repeat; 3],
                 ..Default::default()
             },
-        )
-        .unwrap();
+        )?;
 
         let layout = self.g_pipeline.layout().set_layouts().first().unwrap();
 
@@ -436,8 +486,7 @@ impl FrontBlit {
                 },
                 ..ImageViewCreateInfo::from_image(&source_image)
             },
-        )
-        .unwrap();
+        )?;
 
         let set = PersistentDescriptorSet::new(
             &self.descriptor_set_allocator,
@@ -448,16 +497,14 @@ impl FrontBlit {
                 sampler,
             )],
             [],
-        )
-        .unwrap();
+        )?;
         let framebuffer = Framebuffer::new(
             self.render_pass.clone(),
             FramebufferCreateInfo {
-                attachments: vec![ImageView::new_default(dest_image).unwrap()],
+                attachments: vec![ImageView::new_default(dest_image)?],
                 ..Default::default()
             },
-        )
-        .unwrap();
+        )?;
 
         let push_constants = vs::PushConstantData { topleft, size };
 
@@ -468,8 +515,7 @@ impl FrontBlit {
                     ..RenderPassBeginInfo::framebuffer(framebuffer)
                 },
                 Default::default(),
-            )
-            .unwrap()
+            )?
             .set_viewport(
                 0,
                 [Viewport {
@@ -479,37 +525,54 @@ impl FrontBlit {
                 }]
                 .into_iter()
                 .collect(),
-            )
-            .unwrap()
-            .bind_pipeline_graphics(self.g_pipeline.clone())
-            .unwrap()
+            )?
+            .bind_pipeline_graphics(self.g_pipeline.clone())?
             .bind_descriptor_sets(
                 PipelineBindPoint::Graphics,
                 self.g_pipeline.layout().clone(),
                 0,
                 set.clone(),
-            )
-            .unwrap()
+            )?
             .bind_descriptor_sets(
                 PipelineBindPoint::Graphics,
                 self.g_pipeline.layout().clone(),
                 0,
                 set.clone(),
-            )
-            .unwrap()
-            .push_constants(self.g_pipeline.layout().clone(), 0, push_constants)
-            .unwrap()
-            .bind_vertex_buffers(0, self.vertex_buffer.clone())
-            .unwrap()
-            .draw(4, 1, 0, 0)
-            .unwrap()
-            .end_render_pass(Default::default())
-            .unwrap();
-
-        let command_buffer = builder.build().unwrap();
-
-        in_future
-            .then_execute(self.queue.clone(), command_buffer)
-            .unwrap()
+            )?
+            .push_constants(self.g_pipeline.layout().clone(), 0, push_constants)?
+            .bind_vertex_buffers(0, self.vertex_buffer.clone())?
+            .draw(4, 1, 0, 0)?
+            .end_render_pass(Default::default())?;
+
+        let command_buffer = builder.build()?;
+
+        Ok(in_future.then_execute(self.queue.clone(), command_buffer)?)
+    }
+
+    /// Fill `dest_image` with black, used instead of [`FrontBlit::blit`] when
+    /// the display is disabled
+    pub fn clear<IF>(
+        &mut self,
+        dest_image: Arc<Image>,
+        mut in_future: IF,
+    ) -> Result<CommandBufferExecFuture<IF>, GpuError>
+    where
+        IF: GpuFuture,
+    {
+        in_future.cleanup_finished();
+
+        let mut builder: AutoCommandBufferBuilder<PrimaryAutoCommandBuffer> =
+            AutoCommandBufferBuilder::primary(
+                &self.command_buffer_allocator,
+                self.queue.queue_family_index(),
+                CommandBufferUsage::OneTimeSubmit,
+            )?;
+        builder.clear_color_image(ClearColorImageInfo {
+            clear_value: [0.0, 0.0, 0.0, 1.0].into(),
+            ..ClearColorImageInfo::image(dest_image)
+        })?;
+        let command_buffer = builder.build()?;
+
+        Ok(in_future.then_execute(self.queue.clone(), command_buffer)?)
     }
 }
@@ -1,133 +1,274 @@
-use crate::gpu::GpuStat;
-
-use super::{gpu_context::GpuContext, BackendCommand};
-use crossbeam::{
-    atomic::AtomicCell,
-    channel::{Receiver, Sender},
-};
+use super::{gpu_context::GpuContext, BackendCommand, GpuError, GpuStats, DEFAULT_OUTPUT_FORMAT};
+use crossbeam::channel::{Receiver, Sender};
 use std::{
     sync::Arc,
     thread::{self, JoinHandle},
 };
 use vulkano::{
+    command_buffer::allocator::StandardCommandBufferAllocator,
     device::{Device, Queue},
+    format::Format,
     image::Image,
 };
 
+/// The `Gpu` side of the [`GpuBackend`] thread, the commands are sent to it,
+/// and the front images and the errors come back
+pub(super) struct VulkanBackend {
+    // used for blitting to frontend
+    pub(super) queue: Arc<Queue>,
+    pub(super) device: Arc<Device>,
+
+    // handle the backend gpu thread
+    _gpu_backend_thread_handle: JoinHandle<()>,
+    // backend commands channel
+    gpu_backend_sender: Sender<BackendCommand>,
+    // channel for front image coming from backend
+    pub(super) front_image_receiver: Receiver<(Arc<Image>, GpuStats)>,
+    // the backend errors, and whether it recovered from them
+    gpu_error_receiver: Receiver<(GpuError, bool)>,
+
+    pub(super) first_frame: bool,
+    pub(super) current_front_image: Option<Arc<Image>>,
+    // the stats of the frame of `current_front_image`
+    pub(super) stats: GpuStats,
+    pub(super) output_format: Format,
+    pub(super) command_buffer_allocator: StandardCommandBufferAllocator,
+}
+
+impl VulkanBackend {
+    pub(super) fn new(device: Arc<Device>, queue: Arc<Queue>) -> Self {
+        let (gpu_backend_sender, gpu_backend_receiver) = crossbeam::channel::unbounded();
+        let (gpu_front_image_sender, front_image_receiver) = crossbeam::channel::unbounded();
+        let (gpu_error_sender, gpu_error_receiver) = crossbeam::channel::unbounded();
+
+        let _gpu_backend_thread_handle = GpuBackend::start(
+            device.clone(),
+            queue.clone(),
+            gpu_backend_receiver,
+            gpu_front_image_sender,
+            gpu_error_sender,
+        );
+
+        Self {
+            queue,
+            device: device.clone(),
+
+            _gpu_backend_thread_handle,
+            gpu_backend_sender,
+            front_image_receiver,
+            gpu_error_receiver,
+
+            first_frame: true,
+            current_front_image: None,
+            stats: GpuStats::default(),
+            output_format: DEFAULT_OUTPUT_FORMAT,
+            command_buffer_allocator: StandardCommandBufferAllocator::new(
+                device,
+                Default::default(),
+            ),
+        }
+    }
+
+    /// A new backend on the same device, with a cleared VRAM, only the output
+    /// format is kept
+    pub(super) fn recreate(&self) -> Self {
+        let mut backend = Self::new(self.device.clone(), self.queue.clone());
+        backend.output_format = self.output_format;
+        backend
+    }
+
+    /// The backend stops if it can't recover from an error, then the commands
+    /// are dropped, the error is reported from [`VulkanBackend::take_error`]
+    pub(super) fn send(&mut self, command: BackendCommand) {
+        let _ = self.gpu_backend_sender.send(command);
+    }
+
+    pub(super) fn take_error(&mut self) -> Option<(GpuError, bool)> {
+        self.gpu_error_receiver.try_recv().ok()
+    }
+
+    pub(super) fn stats(&self) -> GpuStats {
+        self.stats
+    }
+}
+
 pub struct GpuBackend {
     gpu_context: GpuContext,
-    gpu_stat: Arc<AtomicCell<GpuStat>>,
-
-    gpu_read_sender: Sender<u32>,
     gpu_backend_receiver: Receiver<BackendCommand>,
+    /// The errors, and whether the context was recreated after them
+    gpu_error_sender: Sender<(GpuError, bool)>,
 }
 
 impl GpuBackend {
     pub(super) fn start(
         device: Arc<Device>,
         queue: Arc<Queue>,
-        gpu_stat: Arc<AtomicCell<GpuStat>>,
-        gpu_read_sender: Sender<u32>,
         gpu_backend_receiver: Receiver<BackendCommand>,
-        gpu_front_image_sender: Sender<Arc<Image>>,
+        gpu_front_image_sender: Sender<(Arc<Image>, GpuStats)>,
+        gpu_error_sender: Sender<(GpuError, bool)>,
     ) -> JoinHandle<()> {
         thread::spawn(move || {
+            let gpu_context = match GpuContext::new(device, queue, gpu_front_image_sender) {
+                Ok(gpu_context) => gpu_context,
+                Err(e) => {
+                    log::error!("Could not create the GPU context: {}", e);
+                    let _ = gpu_error_sender.send((e, false));
+                    return;
+                }
+            };
             let b = GpuBackend {
-                gpu_context: GpuContext::new(device, queue, gpu_front_image_sender),
-                gpu_stat,
-                gpu_read_sender,
+                gpu_context,
                 gpu_backend_receiver,
+                gpu_error_sender,
             };
             b.run();
         })
     }
 
     fn run(mut self) {
-        loop {
-            match self.gpu_backend_receiver.recv() {
-                Ok(BackendCommand::BlitFront {
-                    full_vram,
-                    state_snapshot,
-                }) => {
-                    self.gpu_context.blit_to_front(full_vram, state_snapshot);
-                }
-                Ok(BackendCommand::DrawPolyline {
-                    vertices,
-                    semi_transparent,
-                    state_snapshot,
-                }) => {
-                    self.gpu_context
-                        .draw_polyline(&vertices, semi_transparent, state_snapshot);
+        // stops when the `Gpu` is dropped (reset, or the emulator was replaced)
+        while let Ok(command) = self.gpu_backend_receiver.recv() {
+            if self.execute(command).is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Report `error`, and replace the context with a new one on the same device.
+    ///
+    /// Fails if the context can't be created, then the backend stops, and the
+    /// emulation continues without rendering.
+    fn recover(&mut self, error: GpuError) -> Result<(), GpuError> {
+        log::error!("GPU backend error: {}, recreating the GPU context", error);
+        let result = self.gpu_context.recreate();
+        if let Err(e) = &result {
+            log::error!("Could not recreate the GPU context: {}", e);
+        }
+        // the `Gpu` could have been dropped
+        let _ = self.gpu_error_sender.send((error, result.is_ok()));
+        self.gpu_context = result?;
+        Ok(())
+    }
+
+    /// Run `f` on the context, and recover from its error, returns `None` if
+    /// there was an error
+    fn with_recovery<T>(
+        &mut self,
+        f: impl FnOnce(&mut GpuContext) -> Result<T, GpuError>,
+    ) -> Result<Option<T>, GpuError> {
+        match f(&mut self.gpu_context) {
+            Ok(t) => Ok(Some(t)),
+            Err(error) => {
+                self.recover(error)?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Returns an error if the backend can't continue
+    fn execute(&mut self, command: BackendCommand) -> Result<(), GpuError> {
+        match command {
+            BackendCommand::BlitFront {
+                full_vram,
+                output_format,
+                state_snapshot,
+            } => {
+                let blit = self.with_recovery(|c| {
+                    c.blit_to_front(full_vram, output_format, state_snapshot.clone())
+                })?;
+                if blit.is_none() {
+                    // the `Gpu` is waiting for the image, send it from the new context,
+                    // or stop, so that it doesn't wait forever
+                    if let Err(error) =
+                        self.gpu_context
+                            .blit_to_front(full_vram, output_format, state_snapshot)
+                    {
+                        log::error!("GPU backend error: {}, stopping", error);
+                        let _ = self.gpu_error_sender.send((error, false));
+                        return Err(error);
+                    }
                 }
-                Ok(BackendCommand::DrawPolygon {
-                    vertices,
-                    texture_params,
-                    textured,
-                    texture_blending,
-                    semi_transparent,
-                    state_snapshot,
-                }) => {
-                    self.gpu_context.draw_polygon(
+            }
+            BackendCommand::DrawPolyline {
+                vertices,
+                semi_transparent,
+                dither,
+                state_snapshot,
+            } => {
+                self.with_recovery(|c| {
+                    c.draw_polyline(&vertices, semi_transparent, dither, state_snapshot)
+                })?;
+            }
+            BackendCommand::DrawPolygon {
+                vertices,
+                texture_params,
+                textured,
+                texture_blending,
+                semi_transparent,
+                dither,
+                state_snapshot,
+            } => {
+                self.with_recovery(|c| {
+                    c.draw_polygon(
                         &vertices,
                         texture_params,
                         textured,
                         texture_blending,
                         semi_transparent,
+                        dither,
                         state_snapshot,
-                    );
-                }
-                Ok(BackendCommand::WriteVramBlock { block_range, block }) => {
-                    self.gpu_context.write_vram_block(block_range, &block);
-                }
-                Ok(BackendCommand::VramVramBlit { src, dst }) => {
-                    self.gpu_context.vram_vram_blit(src, dst);
-                }
-                Ok(BackendCommand::VramReadBlock { block_range }) => {
-                    let src = (block_range.0.start, block_range.1.start);
-                    let size = (
-                        block_range.0.end - block_range.0.start,
-                        block_range.1.end - block_range.1.start,
-                    );
-
-                    let block = self.gpu_context.read_vram_block(block_range);
-
-                    let mut block_counter = 0;
-                    while block_counter < block.len() {
-                        // used for debugging only
-                        let vram_pos = (
-                            (block_counter as u32 % size.0) + src.0,
-                            (block_counter as u32 / size.0) + src.1,
-                        );
-                        let d1 = block[block_counter];
-                        let d2 = if block_counter + 1 < block.len() {
-                            block[block_counter + 1]
-                        } else {
-                            0
-                        };
-                        block_counter += 2;
-
-                        let data = ((d2 as u32) << 16) | d1 as u32;
-                        log::info!("IN TRANSFERE, src={:?}, data={:08X}", vram_pos, data);
-
-                        // TODO: send full block
-                        self.gpu_read_sender.send(data).unwrap();
-                    }
-                    // after sending all the data, we set the gpu_stat bit to indicate that
-                    // the data can be read now
-                    self.gpu_stat
-                        .fetch_update(|s| Some(s | GpuStat::READY_FOR_TO_SEND_VRAM))
-                        .unwrap();
-                    log::info!("DONE TRANSFERE");
-                }
-                Ok(BackendCommand::FillColor {
-                    top_left,
-                    size,
-                    color,
-                }) => {
-                    self.gpu_context.fill_color(top_left, size, color);
-                }
-                Err(_) => {}
+                    )
+                })?;
+            }
+            BackendCommand::DrawRectangle {
+                vertices,
+                texture_params,
+                textured,
+                texture_blending,
+                semi_transparent,
+                state_snapshot,
+            } => {
+                self.with_recovery(|c| {
+                    c.draw_rectangle(
+                        &vertices,
+                        texture_params,
+                        textured,
+                        texture_blending,
+                        semi_transparent,
+                        state_snapshot,
+                    )
+                })?;
+            }
+            BackendCommand::WriteVramBlock { block_range, block } => {
+                self.with_recovery(|c| c.write_vram_block(block_range, &block))?;
+            }
+            BackendCommand::VramVramBlit { src, dst } => {
+                self.with_recovery(|c| c.vram_vram_blit(src, dst))?;
+            }
+            BackendCommand::VramReadBlock { .. } => {
+                unreachable!("VRAM reads are streamed by the frontend")
+            }
+            BackendCommand::VramReadBlockRaw {
+                block_range,
+                result_sender,
+            } => {
+                let len = block_range.0.len() * block_range.1.len();
+                // the new context has a cleared VRAM
+                let block = self
+                    .with_recovery(|c| c.read_vram_block(block_range))?
+                    .unwrap_or_else(|| vec![0; len]);
+                // the receiver could have been dropped, nothing to do
+                let _ = result_sender.send(block);
+            }
+            BackendCommand::FillColor {
+                top_left,
+                size,
+                color,
+            } => {
+                self.with_recovery(|c| c.fill_color(top_left, size, color))?;
             }
+            BackendCommand::InjectError(error) => self.gpu_context.inject_error(error),
         }
+        Ok(())
     }
 }
@@ -1,6 +1,6 @@
 use crossbeam::channel::Sender;
 use vulkano::{
-    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage},
+    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer},
     command_buffer::{
         allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, BufferImageCopy,
         ClearAttachment, ClearColorImageInfo, ClearRect, CommandBufferUsage, CopyBufferToImageInfo,
@@ -42,11 +42,14 @@ use vulkano::{
     sync::{self, GpuFuture},
 };
 
+use super::drawing::{DrawingTextureParams, DrawingVertex};
 use super::front_blit::FrontBlit;
-use super::GpuStateSnapshot;
+use super::vram_rect::{DirtyRects, VramRect};
+use super::{GpuError, GpuStateSnapshot, GpuStats, DEFAULT_OUTPUT_FORMAT};
 
 use std::ops::Range;
 use std::sync::Arc;
+use std::time::Instant;
 
 mod vs {
     vulkano_shaders::shader! {
@@ -62,74 +65,9 @@ mod fs {
     }
 }
 
-#[inline]
-pub fn vertex_position_from_u32(position: u32) -> [f32; 2] {
-    let x = position & 0x7ff;
-    let sign_extend = 0xfffff800 * ((x >> 10) & 1);
-    let x = (x | sign_extend) as i32;
-    let y = (position >> 16) & 0x7ff;
-    let sign_extend = 0xfffff800 * ((y >> 10) & 1);
-    let y = (y | sign_extend) as i32;
-    [x as f32, y as f32]
-}
-
-#[derive(Copy, Clone, Debug, Default)]
-#[repr(C)]
-pub struct DrawingVertex {
-    position: [f32; 2],
-    color: [f32; 3],
-    tex_coord: [i32; 2],
-}
-
-impl DrawingVertex {
-    #[inline]
-    pub fn position(&self) -> [f32; 2] {
-        self.position
-    }
-
-    #[inline]
-    pub fn set_position(&mut self, position: [f32; 2]) {
-        self.position = position;
-    }
-
-    #[inline]
-    pub fn tex_coord(&mut self) -> [i32; 2] {
-        self.tex_coord
-    }
-
-    #[inline]
-    pub fn set_tex_coord(&mut self, tex_coord: [i32; 2]) {
-        self.tex_coord = tex_coord;
-    }
-
-    #[inline]
-    pub fn new_with_color(color: u32) -> Self {
-        let mut s = Self::default();
-        s.color_from_u32(color);
-        s
-    }
-
-    #[inline]
-    pub fn position_from_u32(&mut self, position: u32) {
-        self.position = vertex_position_from_u32(position);
-    }
-
-    #[inline]
-    pub fn color_from_u32(&mut self, color: u32) {
-        let r = (color & 0xFF) as u8;
-        let g = ((color >> 8) & 0xFF) as u8;
-        let b = ((color >> 16) & 0xFF) as u8;
-
-        self.color = [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0];
-    }
-
-    #[inline]
-    pub fn tex_coord_from_u32(&mut self, tex_coord: u32) {
-        self.tex_coord = [(tex_coord & 0xFF) as i32, ((tex_coord >> 8) & 0xFF) as i32];
-    }
-}
-
-/// Contains the vertex data `position, color, tex_coord`, as well as
+/// Contains the vertex data `position, color, tex_coord`, the `position` has the
+/// drawing offset already added, so draws with different offsets can be batched
+/// together. As well as
 /// data that is global to the whole polygon/polyline, and were normally sent through
 /// `push_constants`, but after using polygon/polyline draw buffering, it would be better
 /// to group them into the vertex data.
@@ -175,6 +113,7 @@ impl DrawingVertexFull {
     #[allow(clippy::too_many_arguments)]
     fn new(
         v: &DrawingVertex,
+        drawing_offset: (i32, i32),
         texture_params: &DrawingTextureParams,
         texture_window_mask: (u32, u32),
         texture_window_offset: (u32, u32),
@@ -189,7 +128,10 @@ impl DrawingVertexFull {
             | (textured as u32) << 2
             | (texture_blending as u32) << 3;
         Self {
-            position: v.position,
+            position: [
+                v.position[0] + drawing_offset.0 as f32,
+                v.position[1] + drawing_offset.1 as f32,
+            ],
             color: v.color,
             tex_coord: v.tex_coord,
             tex_info: [
@@ -213,47 +155,6 @@ impl DrawingVertexFull {
     }
 }
 
-#[derive(Copy, Clone, Debug, Default)]
-pub struct DrawingTextureParams {
-    pub clut_base: [u32; 2],
-    pub tex_page_base: [u32; 2],
-    pub semi_transparency_mode: u8,
-    pub tex_page_color_mode: u8,
-    pub texture_disable: bool,
-}
-
-impl DrawingTextureParams {
-    /// Process tex page params, from the lower 16 bits, this is only used
-    /// for when drawing rectangle, as the tex_page is take fron the gpu_stat
-    /// and not from a parameter
-    #[inline]
-    pub fn tex_page_from_gpustat(&mut self, param: u32) {
-        let x = param & 0xF;
-        let y = (param >> 4) & 1;
-
-        self.tex_page_base = [x * 64, y * 256];
-        self.semi_transparency_mode = ((param >> 5) & 3) as u8;
-        self.tex_page_color_mode = ((param >> 7) & 3) as u8;
-        self.texture_disable = (param >> 11) & 1 == 1;
-    }
-
-    /// Process tex page params, from the higher 16 bits, which is found
-    /// in tex page parameter in drawing stuff
-    #[inline]
-    pub fn tex_page_from_u32(&mut self, param: u32) {
-        let param = param >> 16;
-        self.tex_page_from_gpustat(param);
-    }
-
-    #[inline]
-    pub fn clut_from_u32(&mut self, param: u32) {
-        let param = param >> 16;
-        let x = param & 0x3F;
-        let y = (param >> 6) & 0x1FF;
-        self.clut_base = [x * 16, y];
-    }
-}
-
 /// The type of the draw command, informs how the drawing vertices should be handled
 #[derive(PartialEq, Debug)]
 pub enum DrawType {
@@ -270,20 +171,18 @@ struct BufferedDrawsState {
     semi_transparency_mode: u8,
     /// The type of the vertices buffered
     draw_type: DrawType,
-    /// It is used for push constants, and will rarely change
+    /// The drawing area is the viewport (the clip rectangle), and it is used
+    /// for push constants, it will rarely change.
+    ///
+    /// The drawing offset is not here, since it is added to the vertices.
     left: u32,
-    /// It is used for push constants, and will rarely change
     top: u32,
-    /// It is used for push constants, and will rarely change
     width: u32,
-    /// It is used for push constants, and will rarely change
     height: u32,
-    /// It is used for push constants, and will rarely change
-    drawing_offset: (i32, i32),
 }
 
 pub struct GpuContext {
-    pub(super) gpu_front_image_sender: Sender<Arc<Image>>,
+    pub(super) gpu_front_image_sender: Sender<(Arc<Image>, GpuStats)>,
 
     pub(super) device: Arc<Device>,
     queue: Arc<Queue>,
@@ -293,7 +192,9 @@ pub struct GpuContext {
 
     render_image: Arc<Image>,
     render_image_back_image: Arc<Image>,
-    should_update_back_image: bool,
+    /// The areas of `render_image` that changed since it was copied to
+    /// `render_image_back_image`
+    back_image_dirty: DirtyRects,
 
     render_image_framebuffer: Arc<Framebuffer>,
     polygon_pipelines: Vec<Arc<GraphicsPipeline>>,
@@ -309,14 +210,20 @@ pub struct GpuContext {
 
     command_builder: AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
     buffered_commands: u32,
+
+    /// Counters of the current frame, sent and reset with the front image
+    stats: GpuStats,
+
+    /// See [`GpuContext::inject_error`]
+    injected_error: Option<GpuError>,
 }
 
 impl GpuContext {
     pub(super) fn new(
         device: Arc<Device>,
         queue: Arc<Queue>,
-        gpu_front_image_sender: Sender<Arc<Image>>,
-    ) -> Self {
+        gpu_front_image_sender: Sender<(Arc<Image>, GpuStats)>,
+    ) -> Result<Self, GpuError> {
         let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
         let descriptor_set_allocator =
             StandardDescriptorSetAllocator::new(device.clone(), Default::default());
@@ -336,8 +243,7 @@ impl GpuContext {
                 ..Default::default()
             },
             Default::default(),
-        )
-        .unwrap();
+        )?;
 
         let render_image_back_image = Image::new(
             memory_allocator.clone(),
@@ -349,33 +255,26 @@ impl GpuContext {
                 ..Default::default()
             },
             Default::default(),
-        )
-        .unwrap();
+        )?;
 
         let mut builder: AutoCommandBufferBuilder<PrimaryAutoCommandBuffer> =
             AutoCommandBufferBuilder::primary(
                 &command_buffer_allocator,
                 queue.queue_family_index(),
                 CommandBufferUsage::OneTimeSubmit,
-            )
-            .unwrap();
+            )?;
 
-        builder
-            .clear_color_image(ClearColorImageInfo::image(render_image.clone()))
-            .unwrap();
+        builder.clear_color_image(ClearColorImageInfo::image(render_image.clone()))?;
+        // the back image is sampled by textured draws before the first update,
+        // if it's not cleared, they would read whatever was in the memory before
+        builder.clear_color_image(ClearColorImageInfo::image(render_image_back_image.clone()))?;
         // add command to clear the render image, and keep the future
         // for stacking later
-        let command_buffer = builder.build().unwrap();
-        let image_clear_future = command_buffer.execute(queue.clone()).unwrap();
+        let command_buffer = builder.build()?;
+        let image_clear_future = command_buffer.execute(queue.clone())?;
 
-        let vs = vs::load(device.clone())
-            .unwrap()
-            .entry_point("main")
-            .unwrap();
-        let fs = fs::load(device.clone())
-            .unwrap()
-            .entry_point("main")
-            .unwrap();
+        let vs = vs::load(device.clone())?.entry_point("main").unwrap();
+        let fs = fs::load(device.clone())?.entry_point("main").unwrap();
 
         let render_pass = vulkano::single_pass_renderpass!(
             device.clone(),
@@ -391,12 +290,10 @@ impl GpuContext {
                 color: [color],
                 depth_stencil: {},
             }
-        )
-        .unwrap();
+        )?;
 
-        let vertex_input_state = DrawingVertexFull::per_vertex()
-            .definition(&vs.info().input_interface)
-            .unwrap();
+        let vertex_input_state =
+            DrawingVertexFull::per_vertex().definition(&vs.info().input_interface)?;
         let stages = [
             PipelineShaderStageCreateInfo::new(vs),
             PipelineShaderStageCreateInfo::new(fs),
@@ -407,8 +304,7 @@ impl GpuContext {
             PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
                 .into_pipeline_layout_create_info(device.clone())
                 .unwrap(),
-        )
-        .unwrap();
+        )?;
         let subpass = Subpass::from(render_pass.clone(), 0).unwrap();
         // create multiple pipelines, one for each semi_transparency_mode
         // TODO: is there a better way to do this?
@@ -433,9 +329,8 @@ impl GpuContext {
                         ..GraphicsPipelineCreateInfo::layout(layout.clone())
                     },
                 )
-                .unwrap()
             })
-            .collect::<Vec<_>>();
+            .collect::<Result<Vec<_>, _>>()?;
 
         // multiple pipelines
         let polyline_pipelines = (0..5)
@@ -459,9 +354,8 @@ impl GpuContext {
                         ..GraphicsPipelineCreateInfo::layout(layout.clone())
                     },
                 )
-                .unwrap()
             })
-            .collect::<Vec<_>>();
+            .collect::<Result<Vec<_>, _>>()?;
 
         let sampler = Sampler::new(
             device.clone(),
@@ -472,8 +366,7 @@ impl GpuContext {
                 address_mode: [SamplerAddressMode::Repeat; 3],
                 ..Default::default()
             },
-        )
-        .unwrap();
+        )?;
 
         // even though, we are using the layout from the `polygon_pipeline`
         // it still works without issues with `line_pipeline` since its the
@@ -490,8 +383,7 @@ impl GpuContext {
                 },
                 ..ImageViewCreateInfo::from_image(&render_image_back_image)
             },
-        )
-        .unwrap();
+        )?;
 
         let descriptor_set = PersistentDescriptorSet::new(
             &descriptor_set_allocator,
@@ -502,23 +394,22 @@ impl GpuContext {
                 sampler,
             )],
             [],
-        )
-        .unwrap();
+        )?;
         let render_image_framebuffer = Framebuffer::new(
             render_pass,
             FramebufferCreateInfo {
-                attachments: vec![ImageView::new_default(render_image.clone()).unwrap()],
+                attachments: vec![ImageView::new_default(render_image.clone())?],
                 ..Default::default()
             },
-        )
-        .unwrap();
+        )?;
 
         let front_blit = FrontBlit::new(
             device.clone(),
             queue.clone(),
             render_image.clone(),
             memory_allocator.clone(),
-        );
+            DEFAULT_OUTPUT_FORMAT,
+        )?;
 
         let gpu_future = Some(image_clear_future.boxed());
 
@@ -526,10 +417,9 @@ impl GpuContext {
             &command_buffer_allocator,
             queue.queue_family_index(),
             CommandBufferUsage::OneTimeSubmit,
-        )
-        .unwrap();
+        )?;
 
-        Self {
+        Ok(Self {
             gpu_front_image_sender,
 
             device,
@@ -542,7 +432,7 @@ impl GpuContext {
             render_image_framebuffer,
 
             render_image_back_image,
-            should_update_back_image: false,
+            back_image_dirty: DirtyRects::default(),
 
             polygon_pipelines,
             polyline_pipelines,
@@ -557,19 +447,46 @@ impl GpuContext {
 
             command_builder,
             buffered_commands: 0,
-        }
+
+            stats: GpuStats::default(),
+
+            injected_error: None,
+        })
+    }
+
+    /// A new context on the same device, for recovering after an error, the
+    /// VRAM content is lost
+    pub(super) fn recreate(&self) -> Result<Self, GpuError> {
+        Self::new(
+            self.device.clone(),
+            self.queue.clone(),
+            self.gpu_front_image_sender.clone(),
+        )
+    }
+
+    /// Make the next vertex buffer allocation fail with `error`, to test the
+    /// recovery without a real device loss
+    pub(super) fn inject_error(&mut self, error: GpuError) {
+        self.injected_error = Some(error);
     }
 }
 
 impl GpuContext {
-    pub fn write_vram_block(&mut self, block_range: (Range<u32>, Range<u32>), block: &[u16]) {
-        self.check_and_flush_buffered_draws(None);
+    pub fn write_vram_block(
+        &mut self,
+        block_range: (Range<u32>, Range<u32>),
+        block: &[u16],
+    ) -> Result<(), GpuError> {
+        self.check_and_flush_buffered_draws(None)?;
 
         let left = block_range.0.start;
         let top = block_range.1.start;
         let width = block_range.0.len() as u32;
         let height = block_range.1.len() as u32;
 
+        self.stats.vram_writes += 1;
+        self.stats.vram_write_bytes += block.len() as u64 * 2;
+
         let buffer = Buffer::from_iter(
             self.memory_allocator.clone(),
             BufferCreateInfo {
@@ -582,8 +499,7 @@ impl GpuContext {
                 ..Default::default()
             },
             block.iter().cloned(),
-        )
-        .unwrap();
+        )?;
 
         let overflow_x = left + width > 1024;
         let overflow_y = top + height > 512;
@@ -598,15 +514,13 @@ impl GpuContext {
                     ..Default::default()
                 },
                 Default::default(),
-            )
-            .unwrap();
+            )?;
 
             self.command_builder
                 .copy_buffer_to_image(CopyBufferToImageInfo::buffer_image(
                     buffer,
                     stage_image.clone(),
-                ))
-                .unwrap();
+                ))?;
 
             // if we are not overflowing in a direction, just keep the old value
             let not_overflowing_width = (1024 - left).min(width);
@@ -615,68 +529,60 @@ impl GpuContext {
             let remaining_height = height - not_overflowing_height;
 
             // copy the not overflowing content
-            self.command_builder
-                .copy_image(CopyImageInfo {
+            self.command_builder.copy_image(CopyImageInfo {
+                regions: [ImageCopy {
+                    src_subresource: stage_image.subresource_layers(),
+                    src_offset: [0, 0, 0],
+                    dst_subresource: self.render_image.subresource_layers(),
+                    dst_offset: [left, top, 0],
+                    extent: [not_overflowing_width, not_overflowing_height, 1],
+                    ..Default::default()
+                }]
+                .into(),
+                ..CopyImageInfo::images(stage_image.clone(), self.render_image.clone())
+            })?;
+
+            if overflow_x {
+                self.command_builder.copy_image(CopyImageInfo {
                     regions: [ImageCopy {
                         src_subresource: stage_image.subresource_layers(),
-                        src_offset: [0, 0, 0],
+                        src_offset: [not_overflowing_width, 0, 0],
                         dst_subresource: self.render_image.subresource_layers(),
-                        dst_offset: [left, top, 0],
-                        extent: [not_overflowing_width, not_overflowing_height, 1],
+                        dst_offset: [0, top, 0],
+                        extent: [remaining_width, not_overflowing_height, 1],
                         ..Default::default()
                     }]
                     .into(),
                     ..CopyImageInfo::images(stage_image.clone(), self.render_image.clone())
-                })
-                .unwrap();
-
-            if overflow_x {
-                self.command_builder
-                    .copy_image(CopyImageInfo {
-                        regions: [ImageCopy {
-                            src_subresource: stage_image.subresource_layers(),
-                            src_offset: [not_overflowing_width, 0, 0],
-                            dst_subresource: self.render_image.subresource_layers(),
-                            dst_offset: [0, top, 0],
-                            extent: [remaining_width, not_overflowing_height, 1],
-                            ..Default::default()
-                        }]
-                        .into(),
-                        ..CopyImageInfo::images(stage_image.clone(), self.render_image.clone())
-                    })
-                    .unwrap();
+                })?;
             }
             if overflow_y {
-                self.command_builder
-                    .copy_image(CopyImageInfo {
-                        regions: [ImageCopy {
-                            src_subresource: stage_image.subresource_layers(),
-                            src_offset: [0, not_overflowing_height, 0],
-                            dst_subresource: self.render_image.subresource_layers(),
-                            dst_offset: [left, 0, 0],
-                            extent: [not_overflowing_width, remaining_height, 1],
-                            ..Default::default()
-                        }]
-                        .into(),
-                        ..CopyImageInfo::images(stage_image.clone(), self.render_image.clone())
-                    })
-                    .unwrap();
+                self.command_builder.copy_image(CopyImageInfo {
+                    regions: [ImageCopy {
+                        src_subresource: stage_image.subresource_layers(),
+                        src_offset: [0, not_overflowing_height, 0],
+                        dst_subresource: self.render_image.subresource_layers(),
+                        dst_offset: [left, 0, 0],
+                        extent: [not_overflowing_width, remaining_height, 1],
+                        ..Default::default()
+                    }]
+                    .into(),
+                    ..CopyImageInfo::images(stage_image.clone(), self.render_image.clone())
+                })?;
             }
             if overflow_x && overflow_y {
-                self.command_builder
-                    .copy_image(CopyImageInfo {
-                        regions: [ImageCopy {
-                            src_subresource: stage_image.subresource_layers(),
-                            src_offset: [not_overflowing_width, not_overflowing_height, 0],
-                            dst_subresource: self.render_image.subresource_layers(),
-                            dst_offset: [0, 0, 0],
-                            extent: [remaining_width, remaining_height, 1],
-                            ..Default::default()
-                        }]
-                        .into(),
-                        ..CopyImageInfo::images(stage_image, self.render_image.clone())
-                    })
-                    .unwrap();
+                self.command_builder.copy_image(CopyImageInfo {
+                    regions: [ImageCopy {
+                        src_subresource: stage_image.subresource_layers(),
+                        src_offset: [not_overflowing_width, not_overflowing_height, 0],
+                        dst_subresource: self.render_image.subresource_layers(),
+                        dst_offset: [0, 0, 0],
+                        extent: [remaining_width, remaining_height, 1],
+                        ..Default::default()
+                    }]
+                    .into(),
+                    ..CopyImageInfo::images(stage_image, self.render_image.clone())
+                })?;
             }
         } else {
             self.command_builder
@@ -689,32 +595,37 @@ impl GpuContext {
                     }]
                     .into(),
                     ..CopyBufferToImageInfo::buffer_image(buffer, self.render_image.clone())
-                })
-                .unwrap();
+                })?;
         }
 
-        self.increment_command_builder_commands_and_flush();
+        self.increment_command_builder_commands_and_flush()?;
 
-        // update back image when loading textures
-        self.schedule_back_image_update();
+        self.back_image_dirty
+            .add(VramRect::new(left, top, width, height));
+        Ok(())
     }
 
-    pub fn read_vram_block(&mut self, block_range: (Range<u32>, Range<u32>)) -> Vec<u16> {
-        self.check_and_flush_buffered_draws(None);
-        self.flush_command_builder();
+    pub fn read_vram_block(
+        &mut self,
+        block_range: (Range<u32>, Range<u32>),
+    ) -> Result<Vec<u16>, GpuError> {
+        self.check_and_flush_buffered_draws(None)?;
+        self.flush_command_builder()?;
 
         let left = block_range.0.start;
         let top = block_range.1.start;
         let width = block_range.0.len() as u32;
         let height = block_range.1.len() as u32;
 
+        self.stats.vram_reads += 1;
+        self.stats.vram_read_bytes += width as u64 * height as u64 * 2;
+
         let mut builder: AutoCommandBufferBuilder<PrimaryAutoCommandBuffer> =
             AutoCommandBufferBuilder::primary(
                 &self.command_buffer_allocator,
                 self.queue.queue_family_index(),
                 CommandBufferUsage::OneTimeSubmit,
-            )
-            .unwrap();
+            )?;
 
         let buffer = Buffer::new_slice::<u16>(
             self.memory_allocator.clone(),
@@ -727,8 +638,7 @@ impl GpuContext {
                 ..Default::default()
             },
             (width * height) as u64,
-        )
-        .unwrap();
+        )?;
 
         let overflow_x = left + width > 1024;
         let overflow_y = top + height > 512;
@@ -743,8 +653,7 @@ impl GpuContext {
                     ..Default::default()
                 },
                 Default::default(),
-            )
-            .unwrap();
+            )?;
 
             // if we are not overflowing in a direction, just keep the old value
             let not_overflowing_width = (1024 - left).min(width);
@@ -753,130 +662,123 @@ impl GpuContext {
             let remaining_height = height - not_overflowing_height;
 
             // copy the not overflowing content
-            builder
-                .copy_image(CopyImageInfo {
+            builder.copy_image(CopyImageInfo {
+                regions: [ImageCopy {
+                    src_subresource: self.render_image.subresource_layers(),
+                    src_offset: [left, top, 0],
+                    dst_subresource: stage_image.subresource_layers(),
+                    dst_offset: [0, 0, 0],
+                    extent: [not_overflowing_width, not_overflowing_height, 1],
+                    ..Default::default()
+                }]
+                .into(),
+                ..CopyImageInfo::images(self.render_image.clone(), stage_image.clone())
+            })?;
+
+            if overflow_x {
+                builder.copy_image(CopyImageInfo {
                     regions: [ImageCopy {
                         src_subresource: self.render_image.subresource_layers(),
-                        src_offset: [left, top, 0],
+                        src_offset: [0, top, 0],
                         dst_subresource: stage_image.subresource_layers(),
-                        dst_offset: [0, 0, 0],
-                        extent: [not_overflowing_width, not_overflowing_height, 1],
+                        dst_offset: [not_overflowing_width, 0, 0],
+                        extent: [remaining_width, not_overflowing_height, 1],
                         ..Default::default()
                     }]
                     .into(),
                     ..CopyImageInfo::images(self.render_image.clone(), stage_image.clone())
-                })
-                .unwrap();
-
-            if overflow_x {
-                builder
-                    .copy_image(CopyImageInfo {
-                        regions: [ImageCopy {
-                            src_subresource: self.render_image.subresource_layers(),
-                            src_offset: [0, top, 0],
-                            dst_subresource: stage_image.subresource_layers(),
-                            dst_offset: [not_overflowing_width, 0, 0],
-                            extent: [remaining_width, not_overflowing_height, 1],
-                            ..Default::default()
-                        }]
-                        .into(),
-                        ..CopyImageInfo::images(self.render_image.clone(), stage_image.clone())
-                    })
-                    .unwrap();
+                })?;
             }
             if overflow_y {
-                builder
-                    .copy_image(CopyImageInfo {
-                        regions: [ImageCopy {
-                            src_subresource: self.render_image.subresource_layers(),
-                            src_offset: [left, 0, 0],
-                            dst_subresource: stage_image.subresource_layers(),
-                            dst_offset: [0, not_overflowing_height, 0],
-                            extent: [not_overflowing_width, remaining_height, 1],
-                            ..Default::default()
-                        }]
-                        .into(),
-                        ..CopyImageInfo::images(self.render_image.clone(), stage_image.clone())
-                    })
-                    .unwrap();
+                builder.copy_image(CopyImageInfo {
+                    regions: [ImageCopy {
+                        src_subresource: self.render_image.subresource_layers(),
+                        src_offset: [left, 0, 0],
+                        dst_subresource: stage_image.subresource_layers(),
+                        dst_offset: [0, not_overflowing_height, 0],
+                        extent: [not_overflowing_width, remaining_height, 1],
+                        ..Default::default()
+                    }]
+                    .into(),
+                    ..CopyImageInfo::images(self.render_image.clone(), stage_image.clone())
+                })?;
             }
             if overflow_x && overflow_y {
-                builder
-                    .copy_image(CopyImageInfo {
-                        regions: [ImageCopy {
-                            src_subresource: self.render_image.subresource_layers(),
-                            src_offset: [0, 0, 0],
-                            dst_subresource: stage_image.subresource_layers(),
-                            dst_offset: [not_overflowing_width, not_overflowing_height, 0],
-                            extent: [remaining_width, remaining_height, 1],
-                            ..Default::default()
-                        }]
-                        .into(),
-                        ..CopyImageInfo::images(self.render_image.clone(), stage_image.clone())
-                    })
-                    .unwrap();
-            }
-
-            builder
-                .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
-                    stage_image,
-                    buffer.clone(),
-                ))
-                .unwrap();
-        } else {
-            builder
-                .copy_image_to_buffer(CopyImageToBufferInfo {
-                    regions: [BufferImageCopy {
-                        image_subresource: self.render_image.subresource_layers(),
-                        image_offset: [left, top, 0],
-                        image_extent: [width, height, 1],
+                builder.copy_image(CopyImageInfo {
+                    regions: [ImageCopy {
+                        src_subresource: self.render_image.subresource_layers(),
+                        src_offset: [0, 0, 0],
+                        dst_subresource: stage_image.subresource_layers(),
+                        dst_offset: [not_overflowing_width, not_overflowing_height, 0],
+                        extent: [remaining_width, remaining_height, 1],
                         ..Default::default()
                     }]
                     .into(),
-                    ..CopyImageToBufferInfo::image_buffer(self.render_image.clone(), buffer.clone())
-                })
-                .unwrap();
+                    ..CopyImageInfo::images(self.render_image.clone(), stage_image.clone())
+                })?;
+            }
+
+            builder.copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+                stage_image,
+                buffer.clone(),
+            ))?;
+        } else {
+            builder.copy_image_to_buffer(CopyImageToBufferInfo {
+                regions: [BufferImageCopy {
+                    image_subresource: self.render_image.subresource_layers(),
+                    image_offset: [left, top, 0],
+                    image_extent: [width, height, 1],
+                    ..Default::default()
+                }]
+                .into(),
+                ..CopyImageToBufferInfo::image_buffer(self.render_image.clone(), buffer.clone())
+            })?;
         }
 
-        let command_buffer = builder.build().unwrap();
+        let command_buffer = builder.build()?;
 
-        self.gpu_future
+        let fence = self
+            .gpu_future
             .take()
             .unwrap()
-            .then_execute(self.queue.clone(), command_buffer)
-            .unwrap()
-            .then_signal_fence_and_flush()
-            .unwrap()
-            .wait(None)
-            .unwrap();
+            .then_execute(self.queue.clone(), command_buffer)?
+            .then_signal_fence_and_flush()?;
+        let wait_start = Instant::now();
+        fence.wait(None)?;
+        self.stats.fence_wait_time += wait_start.elapsed();
         self.gpu_future = Some(sync::now(self.device.clone()).boxed());
 
-        let buffer_read = buffer.read().unwrap();
+        let buffer_read = buffer.read()?;
 
-        buffer_read.to_vec()
+        Ok(buffer_read.to_vec())
     }
 
     pub fn vram_vram_blit(
         &mut self,
         src_range: (Range<u32>, Range<u32>),
         dst_range: (Range<u32>, Range<u32>),
-    ) {
+    ) -> Result<(), GpuError> {
         if src_range == dst_range {
-            return;
+            return Ok(());
         }
         // TODO: use vulkan image copy itself
-        let block = self.read_vram_block(src_range);
-        self.write_vram_block(dst_range, &block);
+        let block = self.read_vram_block(src_range)?;
+        self.write_vram_block(dst_range, &block)
     }
 
-    pub fn fill_color(&mut self, top_left: (u32, u32), size: (u32, u32), color: (u8, u8, u8)) {
+    pub fn fill_color(
+        &mut self,
+        top_left: (u32, u32),
+        size: (u32, u32),
+        color: (u8, u8, u8),
+    ) -> Result<(), GpuError> {
         let mut width = size.0;
         let mut height = size.1;
 
         if width * height == 0 {
-            return;
+            return Ok(());
         }
-        self.check_and_flush_buffered_draws(None);
+        self.check_and_flush_buffered_draws(None)?;
 
         // TODO: I'm not sure if we should support wrapping, but for now
         //       we do not, since we would need to do extra clean draws
@@ -894,8 +796,7 @@ impl GpuContext {
                     ..RenderPassBeginInfo::framebuffer(self.render_image_framebuffer.clone())
                 },
                 Default::default(),
-            )
-            .unwrap()
+            )?
             .clear_attachments(
                 [ClearAttachment::Color {
                     color_attachment: 0,
@@ -917,11 +818,13 @@ impl GpuContext {
                 }]
                 .into_iter()
                 .collect(),
-            )
-            .unwrap()
-            .end_render_pass(Default::default())
-            .unwrap();
-        self.increment_command_builder_commands_and_flush();
+            )?
+            .end_render_pass(Default::default())?;
+        self.increment_command_builder_commands_and_flush()?;
+
+        self.back_image_dirty
+            .add(VramRect::new(top_left.0, top_left.1, width, height));
+        Ok(())
     }
 
     /// Create ColorBlendState for a specific semi_transparency_mode, to be
@@ -989,90 +892,93 @@ impl GpuContext {
         }
     }
 
-    fn new_command_buffer_builder(&mut self) -> AutoCommandBufferBuilder<PrimaryAutoCommandBuffer> {
-        AutoCommandBufferBuilder::primary(
+    fn new_command_buffer_builder(
+        &mut self,
+    ) -> Result<AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>, GpuError> {
+        Ok(AutoCommandBufferBuilder::primary(
             &self.command_buffer_allocator,
             self.queue.queue_family_index(),
             CommandBufferUsage::OneTimeSubmit,
-        )
-        .unwrap()
-    }
-
-    fn schedule_back_image_update(&mut self) {
-        self.should_update_back_image = true;
+        )?)
     }
 
-    fn update_back_image_if_needed(&mut self) {
-        // copy to the back buffer
-        if self.should_update_back_image {
-            self.should_update_back_image = false;
-            self.command_builder
-                .copy_image(CopyImageInfo::images(
-                    self.render_image.clone(),
-                    self.render_image_back_image.clone(),
-                ))
-                .unwrap();
+    /// Copy to the back image, if any of `rects` changed since the last copy.
+    ///
+    /// The buffered draws are flushed first, as they might be the ones that changed it.
+    fn update_back_image_if_needed(&mut self, rects: &[VramRect]) -> Result<(), GpuError> {
+        if rects.iter().any(|r| self.back_image_dirty.intersects(r)) {
+            self.check_and_flush_buffered_draws(None)?;
+            self.back_image_dirty.clear();
+            self.stats.back_image_updates += 1;
+            self.command_builder.copy_image(CopyImageInfo::images(
+                self.render_image.clone(),
+                self.render_image_back_image.clone(),
+            ))?;
         }
+        Ok(())
     }
 
-    fn flush_command_builder(&mut self) {
+    fn flush_command_builder(&mut self) -> Result<(), GpuError> {
         // No need to flush if there no draw commands
         if self.buffered_commands == 0 {
-            return;
+            return Ok(());
         }
-        let new_builder = self.new_command_buffer_builder();
+        let new_builder = self.new_command_buffer_builder()?;
         let command_buffer_builder = std::mem::replace(&mut self.command_builder, new_builder);
         self.buffered_commands = 0;
+        self.stats.command_buffer_flushes += 1;
 
-        let command_buffer = command_buffer_builder.build().unwrap();
+        let command_buffer = command_buffer_builder.build()?;
 
         let mut future = self.gpu_future.take().unwrap();
         future.cleanup_finished();
         self.gpu_future = Some(
             future
-                .then_execute(self.queue.clone(), command_buffer)
-                .unwrap()
-                .then_signal_fence_and_flush()
-                .unwrap()
+                .then_execute(self.queue.clone(), command_buffer)?
+                .then_signal_fence_and_flush()?
                 .boxed(),
         );
+        Ok(())
     }
 
     // Checks the `new_state` with the `current_state`, if they are different,
     // it will flush the buffered vertices, and set the `current_state` to `new_state`.
     //
     // Using `None` as `new_state` will always flush the buffered vertices (if any).
-    fn check_and_flush_buffered_draws(&mut self, new_state: Option<BufferedDrawsState>) {
+    fn check_and_flush_buffered_draws(
+        &mut self,
+        new_state: Option<BufferedDrawsState>,
+    ) -> Result<(), GpuError> {
         if new_state == self.current_buffered_draws_state {
-            return;
+            return Ok(());
         }
         let current_state = std::mem::replace(&mut self.current_buffered_draws_state, new_state);
 
         let current_state = if let Some(state) = current_state {
             state
         } else {
-            return;
+            return Ok(());
         };
 
         let vertices_len = self.buffered_draw_vertices.len();
         // if we have a valid instance, then there must be some vertices
         assert!(vertices_len > 0);
 
-        // we create a "cloned iter" here so that we don't clone the vector
-        let vertex_buffer = Buffer::from_iter(
-            self.memory_allocator.clone(),
-            BufferCreateInfo {
-                usage: BufferUsage::VERTEX_BUFFER,
-                ..Default::default()
-            },
-            AllocationCreateInfo {
-                memory_type_filter: MemoryTypeFilter::PREFER_HOST
-                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-                ..Default::default()
-            },
-            self.buffered_draw_vertices.iter().cloned(),
-        )
-        .unwrap();
+        let vertex_buffer = match self.allocate_vertex_buffer() {
+            Ok(vertex_buffer) => vertex_buffer,
+            // the memory of the submitted draws is only freed when they finish,
+            // big batches can run out of it, so submit and wait for them, then try again
+            Err(GpuError::OutOfMemory) => {
+                log::warn!(
+                    "Out of memory for {} vertices, flushing and retrying",
+                    vertices_len
+                );
+                self.flush_command_builder()?;
+                self.wait_for_gpu()?;
+                self.allocate_vertex_buffer()?
+            }
+            Err(e) => return Err(e),
+        };
 
         let pipelines_set = match current_state.draw_type {
             DrawType::Polygon => &self.polygon_pipelines,
@@ -1081,10 +987,6 @@ impl GpuContext {
         let pipeline = &pipelines_set[current_state.semi_transparency_mode as usize];
 
         let push_constants = vs::PushConstantData {
-            offset: [
-                current_state.drawing_offset.0,
-                current_state.drawing_offset.1,
-            ],
             drawing_top_left: [current_state.left, current_state.top],
             drawing_size: [current_state.width, current_state.height],
         };
@@ -1096,8 +998,7 @@ impl GpuContext {
                     ..RenderPassBeginInfo::framebuffer(self.render_image_framebuffer.clone())
                 },
                 Default::default(),
-            )
-            .unwrap()
+            )?
             .set_viewport(
                 0,
                 [Viewport {
@@ -1107,43 +1008,76 @@ impl GpuContext {
                 }]
                 .into_iter()
                 .collect(),
-            )
-            .unwrap()
+            )?
             .bind_descriptor_sets(
                 PipelineBindPoint::Graphics,
                 pipeline.layout().clone(),
                 0,
                 self.descriptor_set.clone(),
-            )
-            .unwrap()
-            .bind_pipeline_graphics(pipeline.clone())
-            .unwrap()
-            .push_constants(pipeline.layout().clone(), 0, push_constants)
-            .unwrap()
-            .bind_vertex_buffers(0, vertex_buffer)
-            .unwrap()
-            .draw(vertices_len as u32, 1, 0, 0)
-            .unwrap()
-            .end_render_pass(Default::default())
-            .unwrap();
+            )?
+            .bind_pipeline_graphics(pipeline.clone())?
+            .push_constants(pipeline.layout().clone(), 0, push_constants)?
+            .bind_vertex_buffers(0, vertex_buffer)?
+            .draw(vertices_len as u32, 1, 0, 0)?
+            .end_render_pass(Default::default())?;
 
-        self.increment_command_builder_commands_and_flush();
+        self.stats.draw_calls += 1;
+        self.stats.vertices += vertices_len as u32;
+
+        self.increment_command_builder_commands_and_flush()?;
 
         // prepare for next batch
         self.buffered_draw_vertices.clear();
+        Ok(())
+    }
+
+    fn allocate_vertex_buffer(&mut self) -> Result<Subbuffer<[DrawingVertexFull]>, GpuError> {
+        if let Some(error) = self.injected_error.take() {
+            return Err(error);
+        }
+
+        // we create a "cloned iter" here so that we don't clone the vector
+        Ok(Buffer::from_iter(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::VERTEX_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            self.buffered_draw_vertices.iter().cloned(),
+        )?)
+    }
+
+    /// Block until all the submitted commands are finished
+    fn wait_for_gpu(&mut self) -> Result<(), GpuError> {
+        let fence = self
+            .gpu_future
+            .take()
+            .unwrap()
+            .then_signal_fence_and_flush()?;
+        let wait_start = Instant::now();
+        fence.wait(None)?;
+        self.stats.fence_wait_time += wait_start.elapsed();
+        self.gpu_future = Some(sync::now(self.device.clone()).boxed());
+        Ok(())
     }
 
     /// Adds to the buffered commands counter and flushes the command builder if needed exceeded a
     /// specific threshold.
-    fn increment_command_builder_commands_and_flush(&mut self) {
+    fn increment_command_builder_commands_and_flush(&mut self) -> Result<(), GpuError> {
         // NOTE: this number is arbitrary, it should be tested later or maybe
         //       make it dynamic
         const MAX_BUFFERED_COMMANDS: u32 = 20;
 
         self.buffered_commands += 1;
         if self.buffered_commands > MAX_BUFFERED_COMMANDS {
-            self.flush_command_builder();
+            self.flush_command_builder()?;
         }
+        Ok(())
     }
 
     /// common function to draw polygons and polylines
@@ -1156,8 +1090,9 @@ impl GpuContext {
         textured: bool,
         texture_blending: bool,
         semi_transparent: bool,
+        dither: bool,
         state_snapshot: GpuStateSnapshot,
-    ) {
+    ) -> Result<(), GpuError> {
         let gpu_stat = state_snapshot.gpu_stat;
 
         let (drawing_left, drawing_top) = state_snapshot.drawing_area_top_left;
@@ -1170,7 +1105,7 @@ impl GpuContext {
         let width = (drawing_right + 1).saturating_sub(drawing_left);
 
         if height == 0 || width == 0 {
-            return;
+            return Ok(());
         }
 
         let texture_window_mask = state_snapshot.texture_window_mask;
@@ -1187,17 +1122,19 @@ impl GpuContext {
             }
         };
 
+        let draw_rect = Self::draw_bounding_rect(
+            vertices,
+            &draw_type,
+            drawing_offset,
+            (left, top, width, height),
+        );
+
         let mut semi_transparent_mode_3 = false;
-        // we might need to update back image if we are drawing `textured`
-        // But, updating textures isn't done a lot, so most of the updates
-        // will be not needed. Thus, we don't update if its `textured`
-        // TODO: fix texture updates and back image updates
         if semi_transparent {
             if semi_transparency_mode == 3 {
                 // flush previous batch because semi_transparent mode 3 cannot be grouped
                 // with other draws, since it relies on updated back image
-                self.check_and_flush_buffered_draws(None);
-                self.schedule_back_image_update();
+                self.check_and_flush_buffered_draws(None)?;
                 semi_transparent_mode_3 = true;
             }
         } else {
@@ -1207,44 +1144,97 @@ impl GpuContext {
             semi_transparency_mode = 3;
         }
 
-        // update back image only if we are going to use it
-        if textured || semi_transparent_mode_3 {
-            self.update_back_image_if_needed();
+        // update back image only if we are going to use it, and only if the
+        // parts we are going to read from it changed
+        let mut read_rects = Vec::with_capacity(3);
+        if semi_transparent_mode_3 {
+            read_rects.extend(draw_rect);
+        }
+        if textured {
+            read_rects.extend(Self::texture_rects(&texture_params));
         }
+        self.update_back_image_if_needed(&read_rects)?;
 
         // flush previous draws if this is a different state
         self.check_and_flush_buffered_draws(Some(BufferedDrawsState {
             semi_transparency_mode,
             draw_type,
-            drawing_offset,
             left,
             top,
             width,
             height,
-        }));
+        }))?;
 
         let converted_vertices_iter = vertices.iter().map(|v| {
             DrawingVertexFull::new(
                 v,
+                drawing_offset,
                 &texture_params,
                 texture_window_mask,
                 texture_window_offset,
                 semi_transparency_mode,
                 semi_transparent,
-                gpu_stat.dither_enabled(),
+                dither,
                 textured,
                 texture_blending,
             )
         });
 
         self.buffered_draw_vertices.extend(converted_vertices_iter);
+        if let Some(rect) = draw_rect {
+            self.back_image_dirty.add(rect);
+        }
 
         if semi_transparent_mode_3 {
             // flush the draw immediately
-            self.check_and_flush_buffered_draws(None);
+            self.check_and_flush_buffered_draws(None)?;
+        }
+        Ok(())
+    }
+
+    /// The area a draw can write to, clipped to the drawing area
+    fn draw_bounding_rect(
+        vertices: &[DrawingVertex],
+        draw_type: &DrawType,
+        drawing_offset: (i32, i32),
+        (left, top, width, height): (u32, u32, u32, u32),
+    ) -> Option<VramRect> {
+        let (mut min_x, mut min_y) = (f32::MAX, f32::MAX);
+        let (mut max_x, mut max_y) = (f32::MIN, f32::MIN);
+        for v in vertices {
+            min_x = min_x.min(v.position[0]);
+            min_y = min_y.min(v.position[1]);
+            max_x = max_x.max(v.position[0]);
+            max_y = max_y.max(v.position[1]);
         }
+        // lines include their end pixel
+        let extra = (*draw_type == DrawType::Polyline) as i64;
+
+        let clip = |min: f32, max: f32, offset: i32, start: u32, len: u32| {
+            let min = (min.floor() as i64 + offset as i64).max(start as i64);
+            let max = (max.ceil() as i64 + offset as i64 + extra).min((start + len) as i64);
+            (min < max).then_some((min as u32, (max - min) as u32))
+        };
+        let (x, w) = clip(min_x, max_x, drawing_offset.0, left, width)?;
+        let (y, h) = clip(min_y, max_y, drawing_offset.1, top, height)?;
+        Some(VramRect::new(x, y, w, h))
     }
 
+    /// The areas a textured draw reads from, the texture page and the CLUT
+    fn texture_rects(texture_params: &DrawingTextureParams) -> impl Iterator<Item = VramRect> {
+        let [page_x, page_y] = texture_params.tex_page_base;
+        let [clut_x, clut_y] = texture_params.clut_base;
+        // 4 and 8 bit pages are 64 and 128 halfwords wide
+        let (page_width, clut_width) = match texture_params.tex_page_color_mode {
+            0 => (64, Some(16)),
+            1 => (128, Some(256)),
+            _ => (256, None),
+        };
+        std::iter::once(VramRect::new(page_x, page_y, page_width, 256))
+            .chain(clut_width.map(|w| VramRect::new(clut_x, clut_y, w, 1)))
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub(super) fn draw_polygon(
         &mut self,
         vertices: &[DrawingVertex],
@@ -1252,8 +1242,9 @@ impl GpuContext {
         textured: bool,
         texture_blending: bool,
         semi_transparent: bool,
+        dither: bool,
         state_snapshot: GpuStateSnapshot,
-    ) {
+    ) -> Result<(), GpuError> {
         self.draw(
             vertices,
             DrawType::Polygon,
@@ -1261,16 +1252,40 @@ impl GpuContext {
             textured,
             texture_blending,
             semi_transparent,
+            dither,
             state_snapshot,
-        );
+        )
+    }
+
+    pub(super) fn draw_rectangle(
+        &mut self,
+        vertices: &[DrawingVertex],
+        texture_params: DrawingTextureParams,
+        textured: bool,
+        texture_blending: bool,
+        semi_transparent: bool,
+        state_snapshot: GpuStateSnapshot,
+    ) -> Result<(), GpuError> {
+        // the hardware never dithers rectangles, even if dithering is enabled
+        self.draw(
+            vertices,
+            DrawType::Polygon,
+            texture_params,
+            textured,
+            texture_blending,
+            semi_transparent,
+            false,
+            state_snapshot,
+        )
     }
 
     pub(super) fn draw_polyline(
         &mut self,
         vertices: &[DrawingVertex],
         semi_transparent: bool,
+        dither: bool,
         state_snapshot: GpuStateSnapshot,
-    ) {
+    ) -> Result<(), GpuError> {
         // Textures are not supported for polylines
         self.draw(
             vertices,
@@ -1279,46 +1294,26 @@ impl GpuContext {
             false,
             false,
             semi_transparent,
+            dither,
             state_snapshot,
-        );
+        )
     }
 
-    pub(super) fn blit_to_front(&mut self, full_vram: bool, state_snapshot: GpuStateSnapshot) {
+    pub(super) fn blit_to_front(
+        &mut self,
+        full_vram: bool,
+        output_format: Format,
+        state_snapshot: GpuStateSnapshot,
+    ) -> Result<(), GpuError> {
         let gpu_stat = state_snapshot.gpu_stat;
-        let vram_display_area_start = state_snapshot.vram_display_area_start;
 
-        self.check_and_flush_buffered_draws(None);
-        self.flush_command_builder();
+        self.check_and_flush_buffered_draws(None)?;
+        self.flush_command_builder()?;
 
         let (mut topleft, size) = if full_vram {
             ([0; 2], [1024, 512])
         } else {
-            // (((X2-X1)/cycles_per_pix)+2) AND NOT 3
-            let mut horizontal_size = (((state_snapshot.display_horizontal_range.1
-                - state_snapshot.display_horizontal_range.0)
-                / gpu_stat.horizontal_dots_divider())
-                + 2)
-                & !3;
-
-            if horizontal_size == 0 {
-                horizontal_size = gpu_stat.horizontal_resolution();
-            }
-
-            let should_double = gpu_stat.vertical_resolution() == 480;
-
-            // Y2-Y1, double if we are interlacing
-            let mut vertical_size = (state_snapshot.display_vertical_range.1
-                - state_snapshot.display_vertical_range.0)
-                << should_double as u32;
-
-            if vertical_size == 0 {
-                vertical_size = gpu_stat.vertical_resolution();
-            }
-
-            (
-                [vram_display_area_start.0, vram_display_area_start.1],
-                [horizontal_size, vertical_size],
-            )
+            state_snapshot.display_area()
         };
 
         // the rendering offset is more of a byte offset than pixel offset
@@ -1327,39 +1322,40 @@ impl GpuContext {
             topleft[0] = (topleft[0] * 2) / 3;
         }
 
-        let front_image = Image::new(
-            self.memory_allocator.clone(),
-            ImageCreateInfo {
-                image_type: ImageType::Dim2d,
-                extent: [size[0], size[1], 1],
-                format: Format::B8G8R8A8_UNORM,
-                usage: ImageUsage::TRANSFER_DST
-                    | ImageUsage::TRANSFER_SRC
-                    | ImageUsage::COLOR_ATTACHMENT,
-                ..Default::default()
-            },
-            Default::default(),
-        )
-        .unwrap();
+        if self.front_blit.format() != output_format {
+            self.front_blit.set_format(output_format)?;
+        }
+        let (front_image, allocated) = self.front_blit.front_image(size)?;
+        if allocated {
+            self.stats.front_image_allocations += 1;
+        }
 
-        // TODO: try to remove the `wait` from here
-        self.front_blit
-            .blit(
+        let in_future = self.gpu_future.take().unwrap();
+        // disabled with `GP1(03h)`, the VRAM is still there, but not shown
+        let blit_future = if !full_vram && !gpu_stat.display_enabled() {
+            self.front_blit.clear(front_image.clone(), in_future)?
+        } else {
+            self.front_blit.blit(
                 front_image.clone(),
                 topleft,
                 size,
                 !full_vram && gpu_stat.is_24bit_color_depth(),
-                self.gpu_future.take().unwrap(),
-            )
-            .then_signal_fence_and_flush()
-            .unwrap()
-            .wait(None)
-            .unwrap();
+                in_future,
+            )?
+        };
+        // TODO: try to remove the `wait` from here
+        let fence = blit_future.then_signal_fence_and_flush()?;
+        let wait_start = Instant::now();
+        fence.wait(None)?;
+        self.stats.fence_wait_time += wait_start.elapsed();
 
-        // send the front buffer
-        self.gpu_front_image_sender.send(front_image).unwrap();
+        // send the front buffer, with the stats of the frame that made it,
+        // the `Gpu` could have been dropped while this frame was in flight
+        let stats = std::mem::take(&mut self.stats);
+        let _ = self.gpu_front_image_sender.send((front_image, stats));
 
         // reset future since we are waiting
         self.gpu_future = Some(sync::now(self.device.clone()).boxed());
+        Ok(())
     }
 }
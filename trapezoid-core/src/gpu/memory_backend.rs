@@ -0,0 +1,264 @@
+//! The GPU backend without Vulkan, the VRAM is kept in memory, and the transfers,
+//! fills and copies are done the same way as the Vulkan backend does them, but
+//! nothing is rasterized, so the draws are dropped.
+//!
+//! It runs in the emulation thread, so it works where threads can't be spawned.
+
+use super::{BackendCommand, GpuError, GpuStats};
+
+use std::ops::Range;
+
+const VRAM_WIDTH: u32 = 1024;
+const VRAM_HEIGHT: u32 = 512;
+
+pub(super) struct MemoryBackend {
+    /// `RGB555` pixels with the mask bit, row by row
+    vram: Box<[u16]>,
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        Self {
+            vram: vec![0; (VRAM_WIDTH * VRAM_HEIGHT) as usize].into_boxed_slice(),
+        }
+    }
+}
+
+impl MemoryBackend {
+    /// A new backend with a cleared VRAM
+    pub(super) fn recreate(&self) -> Self {
+        Self::default()
+    }
+
+    /// The commands are done before this returns, the result of
+    /// `VramReadBlockRaw` is already in the channel
+    pub(super) fn send(&mut self, command: BackendCommand) {
+        match command {
+            // not rasterized
+            BackendCommand::DrawPolyline { .. }
+            | BackendCommand::DrawPolygon { .. }
+            | BackendCommand::DrawRectangle { .. } => {}
+            BackendCommand::WriteVramBlock { block_range, block } => {
+                self.write_vram_block(block_range, &block)
+            }
+            BackendCommand::VramVramBlit { src, dst } => self.vram_vram_blit(src, dst),
+            BackendCommand::VramReadBlock { .. } => {
+                unreachable!("VRAM reads are streamed by the frontend")
+            }
+            BackendCommand::VramReadBlockRaw {
+                block_range,
+                result_sender,
+            } => {
+                // the receiver could have been dropped, nothing to do
+                let _ = result_sender.send(self.read_vram_block(block_range));
+            }
+            BackendCommand::FillColor {
+                top_left,
+                size,
+                color,
+            } => self.fill_color(top_left, size, color),
+        }
+    }
+
+    /// Nothing can fail here
+    pub(super) fn take_error(&mut self) -> Option<(GpuError, bool)> {
+        None
+    }
+
+    /// There is no host GPU work
+    pub(super) fn stats(&self) -> GpuStats {
+        GpuStats::default()
+    }
+
+    /// The index of the pixel at `(x, y)`, wrapping around the edges of the VRAM
+    fn index(x: u32, y: u32) -> usize {
+        ((y % VRAM_HEIGHT) * VRAM_WIDTH + (x % VRAM_WIDTH)) as usize
+    }
+
+    /// Wraps around the right and bottom edges
+    fn write_vram_block(&mut self, block_range: (Range<u32>, Range<u32>), block: &[u16]) {
+        let width = block_range.0.len();
+        for (row, y) in block.chunks_exact(width).zip(block_range.1) {
+            for (&pixel, x) in row.iter().zip(block_range.0.clone()) {
+                self.vram[Self::index(x, y)] = pixel;
+            }
+        }
+    }
+
+    /// Wraps around the right and bottom edges
+    fn read_vram_block(&self, block_range: (Range<u32>, Range<u32>)) -> Vec<u16> {
+        let mut block = Vec::with_capacity(block_range.0.len() * block_range.1.len());
+        for y in block_range.1 {
+            block.extend(block_range.0.clone().map(|x| self.vram[Self::index(x, y)]));
+        }
+        block
+    }
+
+    fn vram_vram_blit(
+        &mut self,
+        src_range: (Range<u32>, Range<u32>),
+        dst_range: (Range<u32>, Range<u32>),
+    ) {
+        if src_range == dst_range {
+            return;
+        }
+        let block = self.read_vram_block(src_range);
+        self.write_vram_block(dst_range, &block);
+    }
+
+    /// Clipped to the edges of the VRAM, not wrapped, the same as the Vulkan backend
+    fn fill_color(&mut self, top_left: (u32, u32), size: (u32, u32), color: (u8, u8, u8)) {
+        let right = (top_left.0 + size.0).min(VRAM_WIDTH);
+        let bottom = (top_left.1 + size.1).min(VRAM_HEIGHT);
+
+        // rounded to the nearest 5-bit value, like the clear of the Vulkan backend,
+        // the mask bit is cleared
+        let component = |c: u8| (c as u16 * 31 + 127) / 255;
+        let pixel = component(color.0) | (component(color.1) << 5) | (component(color.2) << 10);
+
+        for y in top_left.1..bottom {
+            let row = Self::index(0, y);
+            self.vram[row + top_left.0 as usize..row + right as usize].fill(pixel);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu::Gpu;
+    use crate::memory::BusLine;
+
+    fn pattern(len: usize) -> Vec<u16> {
+        (0..len as u16)
+            .map(|i| 0x8000 | i.wrapping_mul(0x3A7))
+            .collect()
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        let mut backend = MemoryBackend::default();
+        let block = pattern(5 * 3);
+        backend.write_vram_block((100..105, 200..203), &block);
+        assert_eq!(backend.read_vram_block((100..105, 200..203)), block);
+        // the rest is untouched
+        assert_eq!(backend.read_vram_block((99..100, 200..203)), [0; 3]);
+        assert_eq!(backend.read_vram_block((100..105, 203..204)), [0; 5]);
+    }
+
+    #[test]
+    fn transfers_wrap_around() {
+        let mut backend = MemoryBackend::default();
+        let block = pattern(4 * 4);
+        backend.write_vram_block((1022..1026, 510..514), &block);
+        assert_eq!(backend.read_vram_block((1022..1026, 510..514)), block);
+
+        // the corners of the VRAM
+        assert_eq!(backend.vram[MemoryBackend::index(1023, 511)], block[5]);
+        assert_eq!(backend.vram[MemoryBackend::index(0, 511)], block[6]);
+        assert_eq!(backend.vram[MemoryBackend::index(1023, 0)], block[9]);
+        assert_eq!(backend.vram[MemoryBackend::index(0, 0)], block[10]);
+    }
+
+    #[test]
+    fn vram_copy() {
+        let mut backend = MemoryBackend::default();
+        let block = pattern(8 * 2);
+        backend.write_vram_block((0..8, 0..2), &block);
+
+        // overlapping, the source is read before writing
+        backend.vram_vram_blit((0..8, 0..2), (4..12, 1..3));
+        assert_eq!(backend.read_vram_block((4..12, 1..3)), block);
+        assert_eq!(backend.read_vram_block((0..4, 0..1)), block[..4]);
+    }
+
+    #[test]
+    fn fill_is_clipped() {
+        let mut backend = MemoryBackend::default();
+        backend.fill_color((1008, 508), (32, 8), (0xFF, 0x80, 0x00));
+
+        // red in the low bits, and the mask bit is cleared
+        let pixel = 0x1F | (0x10 << 5);
+        assert_eq!(
+            backend.read_vram_block((1008..1024, 508..512)),
+            [pixel; 16 * 4]
+        );
+        assert_eq!(backend.read_vram_block((0..16, 0..4)), [0; 16 * 4]);
+        assert_eq!(backend.read_vram_block((1008..1024, 507..508)), [0; 16]);
+    }
+
+    /// Pixels packed 2 per word, the last word is padded if the count is odd
+    fn pack(pixels: &[u16]) -> Vec<u32> {
+        pixels
+            .chunks(2)
+            .map(|p| p[0] as u32 | (p.get(1).copied().unwrap_or(0) as u32) << 16)
+            .collect()
+    }
+
+    fn gp0(gpu: &mut Gpu, words: &[u32]) {
+        for &word in words {
+            gpu.write_u32(0, word).unwrap();
+        }
+    }
+
+    /// `VRAM to CPU` through `GPUREAD`
+    fn read_through_gpuread(gpu: &mut Gpu, x: u32, y: u32, width: u32, height: u32) -> Vec<u32> {
+        gp0(gpu, &[0xC0000000, (y << 16) | x, (height << 16) | width]);
+        (0..(width * height).div_ceil(2))
+            .map(|_| gpu.read_u32(0).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn gp0_transfers_round_trip() {
+        let mut gpu = Gpu::new();
+        let (width, height) = (5, 3);
+        let pixels = pattern((width * height) as usize);
+
+        // `CPU to VRAM`
+        gp0(
+            &mut gpu,
+            &[0xA0000000, (40 << 16) | 30, (height << 16) | width],
+        );
+        gp0(&mut gpu, &pack(&pixels));
+        assert_eq!(gpu.read_vram_block(30, 40, width, height), pixels);
+
+        assert_eq!(
+            read_through_gpuread(&mut gpu, 30, 40, width, height),
+            pack(&pixels)
+        );
+        // the transfer is done
+        assert_eq!(gpu.read_u32(4).unwrap() & (1 << 27), 0);
+
+        // `VRAM to VRAM`
+        gp0(
+            &mut gpu,
+            &[
+                0x80000000,
+                (40 << 16) | 30,
+                (300 << 16) | 600,
+                (height << 16) | width,
+            ],
+        );
+        assert_eq!(gpu.read_vram_block(600, 300, width, height), pixels);
+
+        // fill, the position and width are in steps of 16
+        gp0(&mut gpu, &[0x02FFFFFF, (40 << 16) | 31, (1 << 16) | 1]);
+        assert_eq!(gpu.read_vram_block(16, 40, 16, 1), [0x7FFF; 16]);
+        // the rest of the block is kept
+        assert_eq!(gpu.read_vram_block(32, 40, 3, 1), pixels[2..5]);
+        assert_eq!(
+            gpu.read_vram_block(30, 41, width, 2),
+            pixels[width as usize..]
+        );
+    }
+
+    #[test]
+    fn reset_clears_vram() {
+        let mut gpu = Gpu::new();
+        gp0(&mut gpu, &[0x02FFFFFF, 0, (1 << 16) | 16]);
+        assert_eq!(gpu.read_vram_block(0, 0, 1, 1), [0x7FFF]);
+        gpu.reset();
+        assert_eq!(gpu.read_vram_block(0, 0, 1, 1), [0]);
+    }
+}
@@ -14,13 +14,26 @@ layout(set = 0, binding = 0) uniform sampler2D back_tex;
 
 const vec2 SCREEN_DIM = vec2(1024, 512);
 
+// the 4x4 dither matrix, the offsets are added to the 8-bit color
 const float dither_table[16] = {
-    -4.0/255.0,  +0.0/255.0,  -3.0/255.0,  +1.0/255.0,   //\dither offsets for first two scanlines
-    +2.0/255.0,  -2.0/255.0,  +3.0/255.0,  -1.0/255.0,   ///
-    -3.0/255.0,  +1.0/255.0,  -4.0/255.0,  +0.0/255.0,   //\dither offsets for next two scanlines
-    +3.0/255.0,  -1.0/255.0,  +2.0/255.0,  -2.0/255.0    ///(same as above, but shifted two pixels horizontally)
+    -4.0, +0.0, -3.0, +1.0,
+    +2.0, -2.0, +3.0, -1.0,
+    -3.0, +1.0, -4.0, +0.0,
+    +3.0, -1.0, +2.0, -2.0
 };
 
+// the hardware adds the offset to the 8-bit color, clamps it to 0-255, and then
+// drops the lower 3 bits. We do the truncation here, so that the conversion to
+// the 5-bit target (which rounds) gives the same value
+vec3 dither(vec3 color) {
+    uint x = uint(gl_FragCoord.x) % 4;
+    uint y = uint(gl_FragCoord.y) % 4;
+
+    vec3 color_8bit = round(color * 255.0) + dither_table[y * 4 + x];
+    color_8bit = clamp(color_8bit, 0.0, 255.0);
+    return floor(color_8bit / 8.0) / 31.0;
+}
+
 // this gets the back value from the texture and does manual blending
 // since we can't acheive this blending using Vulkan's alphaBlending ops
 vec3 get_color_with_semi_transparency_for_mode_3(vec3 color, bool semi_transparency_param) {
@@ -79,7 +92,7 @@ uint u16_from_color_with_alpha(vec4 raw_color_value) {
 }
 
 void main() {
-    vec3 t_color;
+    vec3 t_color = v_color;
     vec4 out_color;
 
     uint bool_flags = v_extra_draw_state.z;
@@ -88,16 +101,6 @@ void main() {
     bool is_textured = (bool_flags & 0x4u) != 0;
     bool is_texture_blended = (bool_flags & 0x8u) != 0;
 
-    if (dither_enabled) {
-        uint x = uint(gl_FragCoord.x) % 4;
-        uint y = uint(gl_FragCoord.y) % 4;
-
-        float change = dither_table[y * 4 + x];
-        t_color = v_color + change;
-    } else {
-        t_color = v_color;
-    }
-
     if (is_textured) {
         uvec2 clut_base = v_tex_info.xy;
         uvec2 tex_page_base = v_tex_info.zw;
@@ -152,8 +155,15 @@ void main() {
         if (is_texture_blended) {
             color *= t_color * 2;
         }
+        // dithering is done after blending with the vertex color
+        if (dither_enabled) {
+            color = dither(color);
+        }
         out_color = get_color_with_semi_transparency(color, semi_transparent && color_value.a == 1.0);
     } else {
+        if (dither_enabled) {
+            t_color = dither(t_color);
+        }
         out_color = get_color_with_semi_transparency(t_color, semi_transparent);
     }
     // swizzle the colors
@@ -16,14 +16,14 @@ layout(location = 2)  flat out uvec4 v_tex_info;
 layout(location = 3)  flat out uvec4 v_tex_window;
 layout(location = 4)  flat out uvec3 v_extra_draw_state;
 
+// the drawing offset is already added to `position`
 layout(push_constant) uniform PushConstantData {
-    ivec2 offset;
     uvec2 drawing_top_left;
     uvec2 drawing_size;
 } pc;
 
 void main() {
-    vec2 pos = ((position + pc.offset - pc.drawing_top_left) / pc.drawing_size) * 2 - 1;
+    vec2 pos = ((position - pc.drawing_top_left) / pc.drawing_size) * 2 - 1;
 
     gl_Position = vec4(pos, 0.0, 1.0);
     v_color = color;
@@ -0,0 +1,265 @@
+//! Video timing, the GPU runs on its own clock, which is converted from the
+//! CPU clock (33.8688MHz) here, and from it the dot clock, hblank and vblank
+//! are derived. These are used for the GPU itself and the timers.
+
+/// The GPU clock ratios are `clock / 33.8688MHz`, both have the same
+/// denominator, so switching the video mode keeps the remainder valid.
+const CLOCK_RATIO_DENOMINATOR: u64 = 451584;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum VideoStandard {
+    Ntsc,
+    Pal,
+}
+
+impl VideoStandard {
+    /// NTSC GPU runs at 53.693175MHz, and PAL at 53.203425MHz
+    fn clock_ratio_numerator(self) -> u64 {
+        match self {
+            VideoStandard::Ntsc => 715909,
+            VideoStandard::Pal => 709379,
+        }
+    }
+
+    /// The frames per second, for the frontends to pace the emulation
+    pub fn frame_rate(self) -> f64 {
+        // the GPU clock is `33.8688MHz * numerator / denominator`
+        let gpu_clock =
+            33_868_800. * self.clock_ratio_numerator() as f64 / CLOCK_RATIO_DENOMINATOR as f64;
+        gpu_clock / (self.dots_per_scanline() * self.scanlines_per_frame()) as f64
+    }
+
+    fn dots_per_scanline(self) -> u32 {
+        match self {
+            VideoStandard::Ntsc => 3413,
+            VideoStandard::Pal => 3406,
+        }
+    }
+
+    fn scanlines_per_frame(self) -> u32 {
+        match self {
+            VideoStandard::Ntsc => 263,
+            VideoStandard::Pal => 314,
+        }
+    }
+
+    /// The first scanline of vblank, the ones before are visible
+    fn vblank_start(self) -> u32 {
+        match self {
+            VideoStandard::Ntsc => 240,
+            VideoStandard::Pal => 288,
+        }
+    }
+}
+
+/// The parts of `GPUSTAT` that affect the timing
+#[derive(Debug, Clone, Copy)]
+pub(super) struct VideoMode {
+    pub standard: VideoStandard,
+    /// GPU cycles per dot, depends on the horizontal resolution
+    pub dots_divider: u32,
+    pub vertical_resolution: u32,
+    pub interlace: bool,
+}
+
+/// The clocks that happened during [`VideoTiming::clock`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct VideoClocks {
+    pub dot_clocks: u32,
+    pub hblanks: u32,
+    pub vblank_started: bool,
+}
+
+#[derive(Default)]
+pub(super) struct VideoTiming {
+    /// CPU cycles multiplied by the clock ratio numerator, that are not yet
+    /// a full GPU cycle
+    cycles_remainder: u64,
+    /// GPU cycles that are not yet a full dot
+    dot_remainder: u32,
+    /// GPU cycles in the current scanline
+    dot: u32,
+    scanline: u32,
+    drawing_odd: bool,
+    in_vblank: bool,
+    vblank_count: u64,
+    hblank_count: u64,
+}
+
+impl VideoTiming {
+    pub fn clock(&mut self, mode: &VideoMode, cpu_cycles: u32) -> VideoClocks {
+        self.cycles_remainder += cpu_cycles as u64 * mode.standard.clock_ratio_numerator();
+        let gpu_cycles = (self.cycles_remainder / CLOCK_RATIO_DENOMINATOR) as u32;
+        self.cycles_remainder %= CLOCK_RATIO_DENOMINATOR;
+
+        let mut clocks = VideoClocks::default();
+
+        self.dot_remainder += gpu_cycles;
+        clocks.dot_clocks = self.dot_remainder / mode.dots_divider;
+        self.dot_remainder %= mode.dots_divider;
+
+        self.dot += gpu_cycles;
+        let dots_per_scanline = mode.standard.dots_per_scanline();
+        while self.dot >= dots_per_scanline {
+            self.dot -= dots_per_scanline;
+            clocks.hblanks += 1;
+            self.hblank_count += 1;
+            if self.next_scanline(mode) {
+                clocks.vblank_started = true;
+                self.vblank_count += 1;
+            }
+        }
+
+        clocks
+    }
+
+    /// Returns `true` if vblank started
+    fn next_scanline(&mut self, mode: &VideoMode) -> bool {
+        let vblank_start = mode.standard.vblank_start();
+        self.scanline += 1;
+
+        if mode.interlace && mode.vertical_resolution == 240 && self.scanline < vblank_start {
+            self.drawing_odd = !self.drawing_odd;
+        }
+
+        if self.scanline >= mode.standard.scanlines_per_frame() {
+            self.scanline = 0;
+            self.in_vblank = false;
+
+            if mode.interlace && mode.vertical_resolution == 480 {
+                self.drawing_odd = !self.drawing_odd;
+            }
+        }
+
+        if self.scanline == vblank_start {
+            self.in_vblank = true;
+            return true;
+        }
+        false
+    }
+
+    pub fn in_vblank(&self) -> bool {
+        self.in_vblank
+    }
+
+    /// The vblanks since the timing started
+    pub fn vblank_count(&self) -> u64 {
+        self.vblank_count
+    }
+
+    /// The hblanks (scanlines) since the timing started
+    pub fn hblank_count(&self) -> u64 {
+        self.hblank_count
+    }
+
+    /// `GPUSTAT.31`, the line being displayed is odd, always `0` in vblank
+    pub fn drawing_odd(&self) -> bool {
+        self.drawing_odd && !self.in_vblank
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CPU_CLOCK: u32 = 33_868_800;
+
+    fn mode(standard: VideoStandard, dots_divider: u32) -> VideoMode {
+        VideoMode {
+            standard,
+            dots_divider,
+            vertical_resolution: 240,
+            interlace: false,
+        }
+    }
+
+    /// Run a full frame (from vblank to vblank), returns the total clocks and
+    /// the number of CPU cycles it took
+    fn run_frame(timing: &mut VideoTiming, mode: &VideoMode) -> (VideoClocks, u32) {
+        // the first vblank
+        while !timing.clock(mode, 100).vblank_started {}
+
+        let mut total = VideoClocks::default();
+        let mut cpu_cycles = 0;
+        loop {
+            let clocks = timing.clock(mode, 100);
+            cpu_cycles += 100;
+            total.dot_clocks += clocks.dot_clocks;
+            total.hblanks += clocks.hblanks;
+            if clocks.vblank_started {
+                return (total, cpu_cycles);
+            }
+        }
+    }
+
+    #[test]
+    fn scanlines_per_frame() {
+        for (standard, scanlines, fps) in [
+            (VideoStandard::Ntsc, 263, 59.826),
+            (VideoStandard::Pal, 314, 49.748),
+        ] {
+            // all horizontal resolutions
+            for dots_divider in [10, 8, 5, 4, 7] {
+                let mode = mode(standard, dots_divider);
+                let mut timing = VideoTiming::default();
+                let (clocks, cpu_cycles) = run_frame(&mut timing, &mode);
+
+                assert_eq!(clocks.hblanks, scanlines, "{:?}/{}", standard, dots_divider);
+                let dots = clocks.dot_clocks as f64 / scanlines as f64;
+                let expected_dots = standard.dots_per_scanline() as f64 / dots_divider as f64;
+                assert!(
+                    (dots - expected_dots).abs() < 0.1,
+                    "{} != {}",
+                    dots,
+                    expected_dots
+                );
+
+                let frame_fps = CPU_CLOCK as f64 / cpu_cycles as f64;
+                assert!((frame_fps - fps).abs() < 0.01, "{} != {}", frame_fps, fps);
+                assert!((standard.frame_rate() - fps).abs() < 0.01);
+            }
+        }
+    }
+
+    #[test]
+    fn vblank_range() {
+        let mode = mode(VideoStandard::Ntsc, 10);
+        let mut timing = VideoTiming::default();
+
+        let mut vblank_scanlines = 0;
+        for _ in 0..263 {
+            let scanline = timing.scanline;
+            while timing.scanline == scanline {
+                timing.clock(&mode, 100);
+            }
+            if timing.in_vblank {
+                assert!(timing.scanline >= 240);
+                vblank_scanlines += 1;
+            }
+        }
+        assert_eq!(timing.scanline, 0);
+        assert_eq!(vblank_scanlines, 263 - 240);
+    }
+
+    #[test]
+    fn large_clock() {
+        let mode = mode(VideoStandard::Ntsc, 10);
+        let mut timing = VideoTiming::default();
+        // more than a few scanlines at once
+        let clocks = timing.clock(&mode, 10000);
+        assert_eq!(clocks.hblanks as u64, 10000 * 715909 / 451584 / 3413);
+        assert_eq!(timing.scanline, clocks.hblanks);
+        assert_eq!(timing.hblank_count(), clocks.hblanks as u64);
+    }
+
+    #[test]
+    fn blank_counts() {
+        let mode = mode(VideoStandard::Pal, 8);
+        let mut timing = VideoTiming::default();
+        while timing.vblank_count() < 6 {
+            timing.clock(&mode, 100);
+        }
+        // the first vblank is after the visible scanlines, then a full frame each
+        assert_eq!(timing.hblank_count(), 288 + 5 * 314);
+    }
+}
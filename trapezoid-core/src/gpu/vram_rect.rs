@@ -0,0 +1,127 @@
+const VRAM_WIDTH: u32 = 1024;
+const VRAM_HEIGHT: u32 = 512;
+/// After this many, [`DirtyRects`] gives up and marks the whole VRAM
+const MAX_DIRTY_RECTS: usize = 64;
+
+/// A rectangle in VRAM, wrapping around the right and bottom edges like the
+/// hardware addressing does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct VramRect {
+    left: u32,
+    top: u32,
+    width: u32,
+    height: u32,
+}
+
+impl VramRect {
+    pub(super) const FULL: Self = Self {
+        left: 0,
+        top: 0,
+        width: VRAM_WIDTH,
+        height: VRAM_HEIGHT,
+    };
+
+    pub(super) fn new(left: u32, top: u32, width: u32, height: u32) -> Self {
+        Self {
+            left: left % VRAM_WIDTH,
+            top: top % VRAM_HEIGHT,
+            width: width.min(VRAM_WIDTH),
+            height: height.min(VRAM_HEIGHT),
+        }
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    pub(super) fn intersects(&self, other: &Self) -> bool {
+        /// One of the ranges starts inside the other, on a circle of `size`
+        fn overlap(a: u32, a_len: u32, b: u32, b_len: u32, size: u32) -> bool {
+            (b + size - a) % size < a_len || (a + size - b) % size < b_len
+        }
+
+        !self.is_empty()
+            && !other.is_empty()
+            && overlap(self.left, self.width, other.left, other.width, VRAM_WIDTH)
+            && overlap(self.top, self.height, other.top, other.height, VRAM_HEIGHT)
+    }
+}
+
+/// The areas of the render image that were written (by draws, fills and
+/// `CPU to VRAM` transfers) since the last copy to the back image, which is what
+/// the textured draws sample from.
+#[derive(Default)]
+pub(super) struct DirtyRects {
+    rects: Vec<VramRect>,
+}
+
+impl DirtyRects {
+    pub(super) fn add(&mut self, rect: VramRect) {
+        if rect.is_empty() {
+            return;
+        }
+        if self.rects.len() >= MAX_DIRTY_RECTS {
+            self.rects.clear();
+            self.rects.push(VramRect::FULL);
+        } else {
+            self.rects.push(rect);
+        }
+    }
+
+    pub(super) fn intersects(&self, rect: &VramRect) -> bool {
+        self.rects.iter().any(|r| r.intersects(rect))
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.rects.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intersection_with_wrapping() {
+        let rect = VramRect::new(100, 100, 50, 20);
+        assert!(rect.intersects(&VramRect::new(149, 119, 10, 10)));
+        assert!(rect.intersects(&VramRect::new(0, 0, 512, 512)));
+        // touching edges
+        assert!(!rect.intersects(&VramRect::new(150, 100, 10, 10)));
+        assert!(!rect.intersects(&VramRect::new(100, 120, 10, 10)));
+        assert!(!rect.intersects(&VramRect::new(100, 100, 0, 10)));
+
+        // a 256 wide CLUT at x=1008 continues from x=0
+        let clut = VramRect::new(1008, 480, 256, 1);
+        assert!(clut.intersects(&VramRect::new(0, 480, 16, 16)));
+        assert!(clut.intersects(&VramRect::new(1020, 470, 2, 11)));
+        assert!(!clut.intersects(&VramRect::new(240, 480, 16, 16)));
+        // a page at the bottom of the VRAM doesn't wrap to a rectangle above it
+        let page = VramRect::new(960, 256, 256, 256);
+        assert!(page.intersects(&VramRect::new(100, 300, 1, 1)));
+        assert!(!page.intersects(&VramRect::new(100, 0, 64, 256)));
+    }
+
+    #[test]
+    fn dirty_rects_limit() {
+        let mut dirty = DirtyRects::default();
+        assert!(dirty.is_empty());
+        dirty.add(VramRect::new(0, 0, 0, 0));
+        assert!(dirty.is_empty());
+
+        for i in 0..MAX_DIRTY_RECTS as u32 {
+            dirty.add(VramRect::new(i * 2, 0, 1, 1));
+        }
+        assert!(!dirty.intersects(&VramRect::new(1, 0, 1, 1)));
+        // merged into the whole VRAM
+        dirty.add(VramRect::new(500, 500, 1, 1));
+        assert!(dirty.intersects(&VramRect::new(1, 0, 1, 1)));
+
+        dirty.clear();
+        assert!(!dirty.intersects(&VramRect::FULL));
+    }
+}
@@ -0,0 +1,88 @@
+use crossbeam::channel::Receiver;
+
+/// A `VRAM to CPU` transfer (GP0 C0h) being read word by word through `GPUREAD`,
+/// either by the CPU or by DMA channel 2.
+///
+/// The block is read from the backend asynchronously, and we only wait for it
+/// on the first read. Pixels are packed 2 per word, continuing across rows, and
+/// the last word is padded with `0` if the number of pixels is odd.
+pub(super) struct VramReadTransfer {
+    pending: Option<Receiver<Vec<u16>>>,
+    block: Vec<u16>,
+    position: usize,
+}
+
+impl VramReadTransfer {
+    pub(super) fn new(block_receiver: Receiver<Vec<u16>>) -> Self {
+        Self {
+            pending: Some(block_receiver),
+            block: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// Read the next word, must not be called after [`Self::is_done`]
+    pub(super) fn read_word(&mut self) -> u32 {
+        if let Some(receiver) = self.pending.take() {
+            // if the backend is gone, there is nothing to read
+            self.block = receiver.recv().unwrap_or_default();
+        }
+
+        let low = self.block.get(self.position).copied().unwrap_or(0);
+        let high = self.block.get(self.position + 1).copied().unwrap_or(0);
+        self.position += 2;
+
+        ((high as u32) << 16) | low as u32
+    }
+
+    pub(super) fn is_done(&self) -> bool {
+        self.pending.is_none() && self.position >= self.block.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(block: Vec<u16>) -> Vec<u32> {
+        let (sender, receiver) = crossbeam::channel::bounded(1);
+        let mut transfer = VramReadTransfer::new(receiver);
+        assert!(!transfer.is_done());
+        sender.send(block).unwrap();
+
+        let mut words = Vec::new();
+        while !transfer.is_done() {
+            words.push(transfer.read_word());
+        }
+        words
+    }
+
+    #[test]
+    fn words_packing() {
+        // 4x2 block
+        let block = (0..8).map(|i| 0x1000 + i).collect::<Vec<u16>>();
+        assert_eq!(
+            read_all(block),
+            [0x10011000, 0x10031002, 0x10051004, 0x10071006]
+        );
+    }
+
+    #[test]
+    fn odd_pixel_count() {
+        // 3x3 block, rows are not padded, only the last word is
+        let block = (0..9).map(|i| 0x1000 + i).collect::<Vec<u16>>();
+        assert_eq!(
+            read_all(block),
+            [0x10011000, 0x10031002, 0x10051004, 0x10071006, 0x00001008]
+        );
+    }
+
+    #[test]
+    fn backend_gone() {
+        let (sender, receiver) = crossbeam::channel::bounded::<Vec<u16>>(1);
+        let mut transfer = VramReadTransfer::new(receiver);
+        drop(sender);
+        assert_eq!(transfer.read_word(), 0);
+        assert!(transfer.is_done());
+    }
+}
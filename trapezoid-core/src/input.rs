@@ -0,0 +1,178 @@
+//! Input that can be sent to the emulator from other threads.
+
+use crossbeam::channel::{Receiver, Sender};
+
+use crate::DigitalControllerKey;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum InputEvent {
+    ControllerKey {
+        port: usize,
+        pad: usize,
+        key: DigitalControllerKey,
+        pressed: bool,
+    },
+    CdromShellOpen(bool),
+}
+
+/// A handle to send input to a [`Psx`](crate::Psx) from any thread, created
+/// with [`Psx::input_handle`](crate::Psx::input_handle).
+///
+/// The input is queued and applied when the emulator clocks next (at the start
+/// of any of the `clock_*` functions), then the controller itself decides when
+/// the game sees it, see [`InputLatchMode`](crate::InputLatchMode).
+///
+/// The handle is cheap to clone, and if the emulator is dropped, the input is
+/// discarded.
+///
+/// ```
+/// use trapezoid_core::{DigitalControllerKey, Psx};
+///
+/// fn run(mut psx: Psx) {
+///     let input = psx.input_handle();
+///
+///     let input_thread = std::thread::spawn(move || {
+///         input.change_controller_key_state(DigitalControllerKey::Start, true);
+///         input.change_cdrom_shell_open_state(false);
+///     });
+///
+///     // the emulation thread keeps running, and picks the input up
+///     psx.clock_full_video_frame();
+///     input_thread.join().unwrap();
+/// }
+/// ```
+#[derive(Clone)]
+pub struct PsxInputHandle {
+    sender: Sender<InputEvent>,
+}
+
+impl PsxInputHandle {
+    /// Change the key state of the controller in the first port
+    pub fn change_controller_key_state(&self, key: DigitalControllerKey, pressed: bool) {
+        self.change_port_controller_key_state(0, key, pressed);
+    }
+
+    /// Change the key state of the controller in `port` (`0` or `1`)
+    pub fn change_port_controller_key_state(
+        &self,
+        port: usize,
+        key: DigitalControllerKey,
+        pressed: bool,
+    ) {
+        self.change_multitap_controller_key_state(port, 0, key, pressed);
+    }
+
+    /// Change the key state of the pad `pad` (`0..4` for A-D) in the multitap
+    /// in `port`, see [`Psx::set_multitap`](crate::Psx::set_multitap). An invalid
+    /// `port` or `pad` is ignored with a warning.
+    pub fn change_multitap_controller_key_state(
+        &self,
+        port: usize,
+        pad: usize,
+        key: DigitalControllerKey,
+        pressed: bool,
+    ) {
+        if !crate::is_valid_controller(port, pad) {
+            return;
+        }
+        self.send(InputEvent::ControllerKey {
+            port,
+            pad,
+            key,
+            pressed,
+        });
+    }
+
+    /// See [`Psx::change_cdrom_shell_open_state`](crate::Psx::change_cdrom_shell_open_state)
+    pub fn change_cdrom_shell_open_state(&self, open: bool) {
+        self.send(InputEvent::CdromShellOpen(open));
+    }
+
+    fn send(&self, event: InputEvent) {
+        // only fails if the emulator is dropped, nothing to do then
+        let _ = self.sender.send(event);
+    }
+}
+
+/// The receiving side of [`PsxInputHandle`]s, owned by the emulator
+pub(crate) struct InputQueue {
+    sender: Sender<InputEvent>,
+    receiver: Receiver<InputEvent>,
+}
+
+impl Default for InputQueue {
+    fn default() -> Self {
+        let (sender, receiver) = crossbeam::channel::unbounded();
+        Self { sender, receiver }
+    }
+}
+
+impl InputQueue {
+    pub fn handle(&self) -> PsxInputHandle {
+        PsxInputHandle {
+            sender: self.sender.clone(),
+        }
+    }
+
+    /// The events sent since the last call, in order
+    pub fn pending(&self) -> impl Iterator<Item = InputEvent> + '_ {
+        self.receiver.try_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_from_other_thread() {
+        let queue = InputQueue::default();
+        let handle = queue.handle();
+
+        std::thread::spawn(move || {
+            handle.change_controller_key_state(DigitalControllerKey::X, true);
+            handle.change_port_controller_key_state(1, DigitalControllerKey::X, false);
+            handle.change_multitap_controller_key_state(0, 3, DigitalControllerKey::L1, true);
+            handle.change_cdrom_shell_open_state(true);
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(
+            queue.pending().collect::<Vec<_>>(),
+            [
+                InputEvent::ControllerKey {
+                    port: 0,
+                    pad: 0,
+                    key: DigitalControllerKey::X,
+                    pressed: true
+                },
+                InputEvent::ControllerKey {
+                    port: 1,
+                    pad: 0,
+                    key: DigitalControllerKey::X,
+                    pressed: false
+                },
+                InputEvent::ControllerKey {
+                    port: 0,
+                    pad: 3,
+                    key: DigitalControllerKey::L1,
+                    pressed: true
+                },
+                InputEvent::CdromShellOpen(true),
+            ]
+        );
+        assert_eq!(queue.pending().count(), 0);
+    }
+
+    #[test]
+    fn invalid_controller_is_ignored() {
+        let queue = InputQueue::default();
+        let handle = queue.handle();
+
+        handle.change_port_controller_key_state(2, DigitalControllerKey::X, true);
+        handle.change_multitap_controller_key_state(0, 4, DigitalControllerKey::X, true);
+
+        assert_eq!(queue.pending().count(), 0);
+    }
+}
@@ -1,61 +1,487 @@
+//! A PSX emulator, rendering with Vulkan through [vulkano](https://docs.rs/vulkano).
+//!
+//! Without the default `vulkan` feature, the VRAM is kept in memory and nothing is
+//! drawn, for builds that only need the CPU, SPU and CDROM, see [`Psx::new_headless`].
+//!
+//! [`Psx`] is the emulator, it needs a BIOS image (unless [`PsxConfig::hle_bios`]
+//! is set), and optionally a disk or an EXE to run:
+//!
+//! ```no_run
+//! use trapezoid_core::{Psx, PsxConfig};
+//!
+//! # fn main() -> Result<(), trapezoid_core::PsxError> {
+//! let mut psx = Psx::new_headless(Some("SCPH1001.BIN"), Some("game.cue"), PsxConfig::default())?;
+//! loop {
+//!     psx.clock_full_video_frame();
+//!     let (width, height, rgba) = psx.read_display_rgba();
+//!     // present `rgba`...
+//! #   break;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! See the `examples` directory for complete programs.
+#![cfg_attr(docsrs, feature(doc_cfg))]
+
+mod capture;
 mod cdrom;
 mod controller_mem_card;
 mod coprocessor;
 pub mod cpu;
+mod emulation_clock;
 mod gpu;
+mod input;
+pub mod machine;
 mod mdec;
 mod memory;
+mod memory_card;
+pub mod netplay;
+mod perf;
+mod region;
+#[cfg(feature = "scripting")]
+#[cfg_attr(docsrs, doc(cfg(feature = "scripting")))]
+pub mod scripting;
 mod spu;
+mod state_hash;
+mod step;
+#[doc(hidden)]
+pub mod testing;
 mod timers;
+pub mod trace;
+mod warnings;
 
 #[cfg(test)]
 mod tests;
 
+#[cfg(feature = "scripting")]
+use std::collections::HashSet;
+#[cfg(feature = "vulkan")]
+use std::sync::Arc;
 use std::{
+    ops::RangeInclusive,
     path::{Path, PathBuf},
-    sync::Arc,
+    time::Duration,
 };
 
 use cpu::RegisterType;
+use emulation_clock::EmulationClock;
+use gpu::Gpu;
+use input::{InputEvent, InputQueue};
 pub use memory::hw_registers::HW_REGISTERS;
 use memory::{Bios, BusLine, CpuBus, Result};
+pub use memory::{BiosInfo, BusError, OpenBus, PsxExe, RamSize, BIOS_SIZE};
 
-pub use controller_mem_card::DigitalControllerKey;
+pub use cdrom::{CdAudioVolumes, CdromSeekTiming, DiskType};
+pub use controller_mem_card::{
+    AckTiming, AnalogConfig, AnalogCurve, AnalogStick, DigitalControllerKey, InputLatchMode,
+};
+pub use cpu::IdleSkip;
+pub use gpu::{
+    DitherMode, GpuCaptureReader, GpuCaptureRecord, GpuError, GpuRenderOptions, GpuStats,
+};
+pub use input::PsxInputHandle;
+pub use machine::describe;
+pub use mdec::MdecTiming;
+pub use memory_card::{MemoryCard, MemoryCardError, SaveInfo, MEMORY_CARD_SIZE};
+pub use perf::{PerfFrameReport, EXCEPTION_CAUSES};
+pub use region::{Region, RegionOverride};
+pub use spu::{ADSRState, SpuInterpolation, SpuState, VoiceState};
+pub use step::{InstructionStep, StepResult};
+#[cfg(feature = "vulkan")]
 use vulkano::{
-    device::{Device, Queue},
+    device::{Device, DeviceCreateInfo, Queue, QueueCreateInfo, QueueFlags},
+    format::Format,
     image::Image,
+    instance::{Instance, InstanceCreateInfo},
     sync::GpuFuture,
+    VulkanLibrary,
 };
+pub use warnings::EmulationWarning;
 
 const MAX_CPU_CYCLES_TO_CLOCK: u32 = 2000;
+// this number doesn't mean anything
+// TODO: research on when to stop the CPU (maybe fixed number? block of code? other?)
+const CPU_INSTRUCTIONS_PER_CLOCK: u32 = 56;
+
+/// The allowed values of [`PsxConfig::cpu_overclock`], others are clamped to it
+pub const CPU_OVERCLOCK_RANGE: RangeInclusive<f32> = 1.0..=4.0;
 
+// frontends rely on moving `Psx` to an emulation thread
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<Psx>();
+};
+
+/// The errors of creating the emulator, of loading disks into it, and of
+/// [`Psx::step_instruction`]
 #[derive(Debug)]
 pub enum PsxError {
-    CouldNotLoadBios,
+    /// With the reason, e.g. the file is missing or has the wrong size
+    CouldNotLoadBios(String),
+    /// With the reason, e.g. the `.cue` file or the `.bin` file it references is missing
     CouldNotLoadDisk(String),
+    /// Only `.cue` and `.exe` files are supported
     DiskTypeNotSupported,
+    /// [`PsxConfig::hle_bios`] is set, but there is no EXE to run
+    HleBiosRequiresExe,
+    /// The `PS-X EXE` header is malformed, with the reason
+    InvalidExe(String),
+    /// No Vulkan device could be created by [`create_headless_device`], with the reason
+    NoVulkanDevice(String),
+    /// [`Psx::step_instruction`] needs [`PsxConfig::idle_skip`] to be `Off`
+    IdleSkipEnabled,
+    /// The device can't render the front images in this format, see [`Psx::set_output_format`]
+    #[cfg(feature = "vulkan")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "vulkan")))]
+    UnsupportedOutputFormat(Format),
 }
 
 impl std::error::Error for PsxError {}
 impl std::fmt::Display for PsxError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            PsxError::CouldNotLoadBios => write!(f, "Could not load BIOS"),
+            PsxError::CouldNotLoadBios(s) => write!(f, "Could not load BIOS: {}", s),
             PsxError::CouldNotLoadDisk(s) => write!(f, "Could not load disk: {}", s),
             PsxError::DiskTypeNotSupported => write!(f, "Disk type not supported"),
+            PsxError::HleBiosRequiresExe => {
+                write!(f, "HLE BIOS mode can only run EXE files")
+            }
+            PsxError::InvalidExe(s) => write!(f, "Invalid EXE: {}", s),
+            PsxError::NoVulkanDevice(s) => write!(f, "No Vulkan device: {}", s),
+            PsxError::IdleSkipEnabled => {
+                write!(f, "Can't step single instructions with idle skip enabled")
+            }
+            #[cfg(feature = "vulkan")]
+            PsxError::UnsupportedOutputFormat(format) => {
+                write!(f, "Unsupported output format: {:?}", format)
+            }
         }
     }
 }
 
+/// A disk or an executable already in memory, see [`Psx::new_headless_from_bytes`]
+#[derive(Debug, Clone)]
+pub enum DiskImage {
+    /// The content of a single track `.cue` file, and of the `.bin` file it references
+    Cue { cue: String, bin: Vec<u8> },
+    /// The content of a `PS-X EXE` file
+    Exe(Vec<u8>),
+}
+
+enum DiskSource {
+    CueFile(PathBuf),
+    Image(DiskImage),
+}
+
+impl DiskSource {
+    /// The type is chosen from the extension, `.cue` or `.exe`
+    fn from_path(path: &Path) -> Result<Self, PsxError> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("cue") => Ok(Self::CueFile(path.to_owned())),
+            Some("exe") => {
+                let data = std::fs::read(path).map_err(|e| {
+                    PsxError::CouldNotLoadDisk(format!("could not read {}: {}", path.display(), e))
+                })?;
+                Ok(Self::Image(DiskImage::Exe(data)))
+            }
+            _ => Err(PsxError::DiskTypeNotSupported),
+        }
+    }
+}
+
+/// The emulator options, built with [`PsxConfig::builder`].
+///
+/// The config can be changed while running with [`Psx::set_config`], each field
+/// says when the change takes effect.
 #[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
 pub struct PsxConfig {
+    /// Print the TTY output of the BIOS and the game to `stdout`.
+    ///
+    /// Takes effect immediately.
     pub stdout_debug: bool,
+    /// Skip the BIOS shell and boot the disk directly.
+    ///
+    /// Takes effect the next time the BIOS reaches the shell (i.e. after a reset).
     pub fast_boot: bool,
+    /// Run without a BIOS, the kernel functions are emulated (only the common ones)
+    /// and the EXE is loaded and started immediately.
+    ///
+    /// This is mostly for homebrew and test EXEs, games will not work.
+    ///
+    /// Can't be changed after creating the emulator.
+    pub hle_bios: bool,
+    /// How long the CD-ROM seeks take, `Instant` is the fastest for loading,
+    /// but some games depend on the seek latency.
+    ///
+    /// Takes effect from the next seek.
+    pub cdrom_seek_timing: CdromSeekTiming,
+    /// Skip the execution of loops that only wait for an interrupt or a change in
+    /// memory (e.g. waiting for vblank), to use less host CPU.
+    ///
+    /// Leaving the loop is delayed a bit (up to 1000 CPU cycles).
+    ///
+    /// Takes effect immediately.
+    pub idle_skip: IdleSkip,
+    /// The installed main RAM, `Dev8MB` is for homebrew made for development consoles,
+    /// games expect `Std2MB` and its mirrors.
+    ///
+    /// Takes effect at the next reset, since the RAM is cleared.
+    pub ram_size: RamSize,
+    /// Force NTSC or PAL video timing, for BIOS and disc combinations that pick the
+    /// "wrong" mode, or to run PAL games at `60Hz`. The vblank timing, the video mode
+    /// bit in `GPUSTAT` and [`Psx::frame_rate`] all follow the override.
+    ///
+    /// Some PAL games expect the `50Hz` timing and run too fast, or not at all, with `Ntsc`.
+    ///
+    /// Takes effect immediately.
+    pub region_override: RegionOverride,
+    /// Keep the SPU RAM through [`Psx::reset`], like the reset button on hardware.
+    /// Otherwise it's cleared, so a reset starts the same as a new emulator.
+    ///
+    /// Takes effect at the next reset.
+    pub keep_spu_ram_on_reset: bool,
+    /// When the controllers and memory cards acknowledge each byte. Some games are
+    /// sensitive to it, and decide that no controller is connected if it doesn't
+    /// come when they expect. `Fast` acknowledges right after the byte, for titles
+    /// that don't work with `Accurate`.
+    ///
+    /// Takes effect from the next byte sent to them.
+    pub controller_ack_timing: AckTiming,
+    /// How fast the MDEC decodes the FMV frames. `Accurate` spreads the decoding
+    /// over the frame like on hardware, with `Instant` the decoding of a whole
+    /// frame can happen at once and the frame takes longer to emulate.
+    ///
+    /// Takes effect immediately.
+    pub mdec_timing: MdecTiming,
+    /// Run the CPU faster than the rest of the console, to reduce the slowdown of
+    /// games that drop frames. `2.0` executes twice the instructions in the same
+    /// emulated time, the video, audio and timers keep the stock timing, and so
+    /// do the cycle counts ([`Psx::elapsed_cycles`] and the DMA). Clamped to
+    /// [`CPU_OVERCLOCK_RANGE`].
+    ///
+    /// Games that time themselves by counting instructions (busy loops for short
+    /// delays, some CD-ROM and controller code) can run too fast or fail with it,
+    /// and games that run at a fixed rate don't gain anything.
+    ///
+    /// Takes effect immediately.
+    pub cpu_overclock: f32,
+    /// What the reads from unmapped addresses return. `Lenient` returns what the
+    /// hardware does where it doesn't fault (the address in the expansion regions,
+    /// and the last value on the bus in the rest of the I/O area), `Strict` fails
+    /// all of them, to catch emulation bugs in tests. The other regions always fail.
+    ///
+    /// Takes effect immediately.
+    pub open_bus: OpenBus,
+    /// How the SPU voices interpolate their samples when played at another rate.
+    /// `Gaussian` is the 4-tap filter of the hardware, `Nearest` repeats the samples,
+    /// which sounds harsher (aliased), mostly to compare the two.
+    ///
+    /// Takes effect immediately.
+    pub spu_interpolation: SpuInterpolation,
+}
+
+impl Default for PsxConfig {
+    fn default() -> Self {
+        Self {
+            stdout_debug: false,
+            fast_boot: false,
+            hle_bios: false,
+            cdrom_seek_timing: CdromSeekTiming::default(),
+            idle_skip: IdleSkip::default(),
+            ram_size: RamSize::default(),
+            region_override: RegionOverride::default(),
+            keep_spu_ram_on_reset: false,
+            controller_ack_timing: AckTiming::default(),
+            mdec_timing: MdecTiming::default(),
+            cpu_overclock: 1.0,
+            open_bus: OpenBus::default(),
+            spu_interpolation: SpuInterpolation::default(),
+        }
+    }
+}
+
+impl PsxConfig {
+    /// Start from the defaults, new options added later will keep their default
+    pub fn builder() -> PsxConfigBuilder {
+        PsxConfigBuilder::default()
+    }
+}
+
+/// Builds a [`PsxConfig`], see the fields there for their documentation
+#[derive(Debug, Default, Clone)]
+pub struct PsxConfigBuilder {
+    config: PsxConfig,
+}
+
+impl PsxConfigBuilder {
+    pub fn stdout_debug(mut self, stdout_debug: bool) -> Self {
+        self.config.stdout_debug = stdout_debug;
+        self
+    }
+
+    pub fn fast_boot(mut self, fast_boot: bool) -> Self {
+        self.config.fast_boot = fast_boot;
+        self
+    }
+
+    pub fn hle_bios(mut self, hle_bios: bool) -> Self {
+        self.config.hle_bios = hle_bios;
+        self
+    }
+
+    pub fn cdrom_seek_timing(mut self, cdrom_seek_timing: CdromSeekTiming) -> Self {
+        self.config.cdrom_seek_timing = cdrom_seek_timing;
+        self
+    }
+
+    pub fn idle_skip(mut self, idle_skip: IdleSkip) -> Self {
+        self.config.idle_skip = idle_skip;
+        self
+    }
+
+    pub fn ram_size(mut self, ram_size: RamSize) -> Self {
+        self.config.ram_size = ram_size;
+        self
+    }
+
+    pub fn region_override(mut self, region_override: RegionOverride) -> Self {
+        self.config.region_override = region_override;
+        self
+    }
+
+    pub fn keep_spu_ram_on_reset(mut self, keep_spu_ram_on_reset: bool) -> Self {
+        self.config.keep_spu_ram_on_reset = keep_spu_ram_on_reset;
+        self
+    }
+
+    pub fn controller_ack_timing(mut self, controller_ack_timing: AckTiming) -> Self {
+        self.config.controller_ack_timing = controller_ack_timing;
+        self
+    }
+
+    pub fn mdec_timing(mut self, mdec_timing: MdecTiming) -> Self {
+        self.config.mdec_timing = mdec_timing;
+        self
+    }
+
+    pub fn cpu_overclock(mut self, cpu_overclock: f32) -> Self {
+        self.config.cpu_overclock = clamp_cpu_overclock(cpu_overclock);
+        self
+    }
+
+    pub fn open_bus(mut self, open_bus: OpenBus) -> Self {
+        self.config.open_bus = open_bus;
+        self
+    }
+
+    pub fn spu_interpolation(mut self, spu_interpolation: SpuInterpolation) -> Self {
+        self.config.spu_interpolation = spu_interpolation;
+        self
+    }
+
+    /// The config, with the defaults for the options that were not set
+    pub fn build(self) -> PsxConfig {
+        self.config
+    }
 }
 
+/// The controller `port` (`0` or `1`) and multitap `pad` (`0..4`) given to the
+/// input functions, invalid ones are ignored with a warning
+pub(crate) fn is_valid_controller(port: usize, pad: usize) -> bool {
+    let valid = port < 2 && pad < 4;
+    if !valid {
+        log::warn!("Invalid controller port {} pad {}, ignoring it", port, pad);
+    }
+    valid
+}
+
+fn clamp_cpu_overclock(overclock: f32) -> f32 {
+    if CPU_OVERCLOCK_RANGE.contains(&overclock) {
+        return overclock;
+    }
+    log::warn!(
+        "CPU overclock {} is out of the range {:?}, clamping it",
+        overclock,
+        CPU_OVERCLOCK_RANGE
+    );
+    if overclock.is_nan() {
+        1.0
+    } else {
+        overclock.clamp(*CPU_OVERCLOCK_RANGE.start(), *CPU_OVERCLOCK_RANGE.end())
+    }
+}
+
+/// The stock cycles per CPU cycle, in `16.16` fixed point, so that the result
+/// doesn't depend on the host floating point
+fn cpu_cycle_scale(overclock: f32) -> u32 {
+    (65536.0 / clamp_cpu_overclock(overclock)).round() as u32
+}
+
+/// Create a Vulkan device and queue for an emulator without a window, the first
+/// device with graphics and compute support is used.
+///
+/// [`Psx::new_headless`] uses this, it's useful to share the device with other
+/// rendering when creating the emulator with [`Psx::new`].
+#[cfg(feature = "vulkan")]
+#[cfg_attr(docsrs, doc(cfg(feature = "vulkan")))]
+pub fn create_headless_device() -> Result<(Arc<Device>, Arc<Queue>), PsxError> {
+    let library =
+        VulkanLibrary::new().map_err(|e| PsxError::NoVulkanDevice(format!("no library: {}", e)))?;
+    let instance = Instance::new(library, InstanceCreateInfo::default())
+        .map_err(|e| PsxError::NoVulkanDevice(format!("could not create an instance: {}", e)))?;
+
+    let (physical_device, queue_family_index) = instance
+        .enumerate_physical_devices()
+        .map_err(|e| PsxError::NoVulkanDevice(e.to_string()))?
+        .find_map(|p| {
+            p.queue_family_properties()
+                .iter()
+                .position(|q| {
+                    q.queue_flags
+                        .contains(QueueFlags::GRAPHICS | QueueFlags::COMPUTE)
+                })
+                .map(|i| (p, i as u32))
+        })
+        .ok_or_else(|| PsxError::NoVulkanDevice("no device with graphics support".to_owned()))?;
+
+    let (device, mut queues) = Device::new(
+        physical_device,
+        DeviceCreateInfo {
+            queue_create_infos: vec![QueueCreateInfo {
+                queue_family_index,
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+    )
+    .map_err(|e| PsxError::NoVulkanDevice(format!("could not create the device: {}", e)))?;
+
+    Ok((device, queues.next().unwrap()))
+}
+
+/// The emulator.
+///
+/// `Psx` is `Send` (but not `Sync`), so it can be created on one thread and moved
+/// to a dedicated emulation thread. All the vulkano resources it owns are created from
+/// the `Device` and `Queue` given to [`Psx::new`], the frontend can keep using them
+/// from its own thread to present the images returned by [`Psx::take_front_image`].
+///
+/// Queries that only observe the state (elapsed counters, [`Psx::in_vblank`],
+/// [`Psx::cpu_registers`], [`Psx::pending_interrupts`]) take `&self`. Input can be
+/// sent from other threads with a [`PsxInputHandle`].
+///
+/// The rest is exclusive (`&mut self`) and must be called from the thread that owns
+/// the emulator: clocking, reset, the GPU and audio outputs, and the bus reads,
+/// since reading hardware registers can change their state (e.g. popping a FIFO).
 pub struct Psx {
     bus: CpuBus,
-    exe_file: Option<PathBuf>,
+    exe: Option<PsxExe>,
     // used to control when to execute fastboot
     disk_available: bool,
     config: PsxConfig,
@@ -66,96 +492,394 @@ pub struct Psx {
     /// a lot of CPU cycles, clocking the components with this many CPU cycles
     /// will crash the emulator, so we split clocking across multiple `clock` calls.
     excess_cpu_cycles: u32,
+    /// From [`PsxConfig::cpu_overclock`], see [`cpu_cycle_scale`]
+    cpu_cycle_scale: u32,
+    /// The part of a stock cycle left from the last scaling of the CPU cycles
+    cpu_cycle_fraction: u32,
     cpu_frame_cycles: u32,
+    clock: EmulationClock,
+    input: InputQueue,
+    audio_capture: Option<capture::WavWriter>,
+    /// The SPU output before time-stretching, when the speed is changed
+    stretch_buffer: Vec<f32>,
+    /// The counters of the last frame, until [`Psx::perf_frame_report`] takes it
+    perf_report: PerfFrameReport,
+    #[cfg(feature = "scripting")]
+    script: Option<Box<dyn scripting::ScriptEngine>>,
+    /// The breakpoints that call the script instead of pausing
+    #[cfg(feature = "scripting")]
+    script_breakpoints: HashSet<u32>,
 }
 
 impl Psx {
-    // TODO: produce a valid `Error` struct
+    /// `bios_file_path` can only be `None` when `config.hle_bios` is set.
+    ///
+    /// The disk is a `.cue` file, or a `.exe` file that is loaded when the BIOS
+    /// reaches the shell. `device` and `queue` are used for the GPU rendering, see
+    /// [`Psx::new_headless`] to create the emulator without them.
+    ///
+    /// ```no_run
+    /// use trapezoid_core::{create_headless_device, Psx, PsxConfig};
+    ///
+    /// # fn main() -> Result<(), trapezoid_core::PsxError> {
+    /// let (device, queue) = create_headless_device()?;
+    /// let config = PsxConfig::builder().fast_boot(true).build();
+    /// let psx = Psx::new(Some("SCPH1001.BIN"), Some("game.cue"), config, device, queue)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "vulkan")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "vulkan")))]
     pub fn new<BiosPath: AsRef<Path>, DiskPath: AsRef<Path>>(
-        bios_file_path: BiosPath,
+        bios_file_path: Option<BiosPath>,
         disk_file: Option<DiskPath>,
         config: PsxConfig,
         device: Arc<Device>,
         queue: Arc<Queue>,
     ) -> Result<Self, PsxError> {
-        let bios = Bios::from_file(bios_file_path)?;
-
-        // save the exe file if there is any
-        // The PSX itself is only responsible for loading normal cue files
-        let (exe_file, disk_file) = if let Some(disk_file) = disk_file {
-            let path = disk_file.as_ref().to_owned();
-            // if this is an exe file
-            match path
-                .extension()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .to_ascii_lowercase()
-                .as_str()
-            {
-                "exe" => (Some(path), None),
-                "cue" => (None, Some(path)),
-                _ => {
-                    return Err(PsxError::DiskTypeNotSupported);
-                }
+        Self::from_files(bios_file_path, disk_file, config, Gpu::new(device, queue))
+    }
+
+    /// Same as [`Psx::new`], but creates its own Vulkan device with
+    /// [`create_headless_device`], for tools and tests that don't present the
+    /// frames, or only read them back (see [`Psx::read_display_rgba`]).
+    ///
+    /// Without the `vulkan` feature, this is how the emulator is created, the VRAM
+    /// is kept in memory, the VRAM transfers, fills and copies work, and the draws
+    /// are dropped.
+    pub fn new_headless<BiosPath: AsRef<Path>, DiskPath: AsRef<Path>>(
+        bios_file_path: Option<BiosPath>,
+        disk_file: Option<DiskPath>,
+        config: PsxConfig,
+    ) -> Result<Self, PsxError> {
+        Self::from_files(bios_file_path, disk_file, config, Self::headless_gpu()?)
+    }
+
+    /// Same as [`Psx::new_headless`], but with the BIOS and the disk in memory,
+    /// for platforms without a filesystem, `bios_data` is ignored when
+    /// `config.hle_bios` is set
+    pub fn new_headless_from_bytes(
+        bios_data: Vec<u8>,
+        disk: Option<DiskImage>,
+        config: PsxConfig,
+    ) -> Result<Self, PsxError> {
+        Self::from_bytes(bios_data, disk, config, Self::headless_gpu()?)
+    }
+
+    fn headless_gpu() -> Result<Gpu, PsxError> {
+        #[cfg(feature = "vulkan")]
+        let gpu = {
+            let (device, queue) = create_headless_device()?;
+            Gpu::new(device, queue)
+        };
+        #[cfg(not(feature = "vulkan"))]
+        let gpu = Gpu::new();
+        Ok(gpu)
+    }
+
+    fn from_files<BiosPath: AsRef<Path>, DiskPath: AsRef<Path>>(
+        bios_file_path: Option<BiosPath>,
+        disk_file: Option<DiskPath>,
+        config: PsxConfig,
+        gpu: Gpu,
+    ) -> Result<Self, PsxError> {
+        let bios = match bios_file_path {
+            // the BIOS is not used in HLE mode
+            _ if config.hle_bios => Bios::empty(),
+            Some(bios_file_path) => Bios::from_file(bios_file_path)?,
+            None => {
+                return Err(PsxError::CouldNotLoadBios(
+                    "no BIOS file was provided".to_owned(),
+                ))
             }
+        };
+
+        let disk = disk_file
+            .map(|path| DiskSource::from_path(path.as_ref()))
+            .transpose()?;
+        Self::with_bios(bios, disk, config, gpu)
+    }
+
+    /// Same as [`Psx::new`], but with the BIOS image already in memory, `bios_data`
+    /// is ignored when `config.hle_bios` is set
+    #[cfg(feature = "vulkan")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "vulkan")))]
+    pub fn new_with_bios_data<DiskPath: AsRef<Path>>(
+        bios_data: Vec<u8>,
+        disk_file: Option<DiskPath>,
+        config: PsxConfig,
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+    ) -> Result<Self, PsxError> {
+        let bios = if config.hle_bios {
+            Bios::empty()
         } else {
-            // only fast_boot if there is anything to run
-            (None, None)
+            Bios::from_data(bios_data)?
         };
 
-        Ok(Self {
+        let disk = disk_file
+            .map(|path| DiskSource::from_path(path.as_ref()))
+            .transpose()?;
+        Self::with_bios(bios, disk, config, Gpu::new(device, queue))
+    }
+
+    /// Same as [`Psx::new_with_bios_data`], but with the disk in memory as well,
+    /// for platforms without a filesystem
+    #[cfg(feature = "vulkan")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "vulkan")))]
+    pub fn new_from_bytes(
+        bios_data: Vec<u8>,
+        disk: Option<DiskImage>,
+        config: PsxConfig,
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+    ) -> Result<Self, PsxError> {
+        Self::from_bytes(bios_data, disk, config, Gpu::new(device, queue))
+    }
+
+    fn from_bytes(
+        bios_data: Vec<u8>,
+        disk: Option<DiskImage>,
+        config: PsxConfig,
+        gpu: Gpu,
+    ) -> Result<Self, PsxError> {
+        let bios = if config.hle_bios {
+            Bios::empty()
+        } else {
+            Bios::from_data(bios_data)?
+        };
+
+        Self::with_bios(bios, disk.map(DiskSource::Image), config, gpu)
+    }
+
+    fn with_bios(
+        bios: Bios,
+        disk: Option<DiskSource>,
+        config: PsxConfig,
+        gpu: Gpu,
+    ) -> Result<Self, PsxError> {
+        let has_exe = matches!(disk, Some(DiskSource::Image(DiskImage::Exe(_))));
+        if config.hle_bios && !has_exe {
+            return Err(PsxError::HleBiosRequiresExe);
+        }
+
+        let mut bus = CpuBus::new(bios, config, gpu);
+        // The PSX itself is only responsible for loading normal cue files,
+        // the exe is loaded by us
+        let mut exe = None;
+        match disk {
+            Some(DiskSource::CueFile(path)) => bus.cdrom_mut().set_cue_file(path)?,
+            Some(DiskSource::Image(DiskImage::Cue { cue, bin })) => {
+                bus.cdrom_mut().set_disk_bytes(cue, bin)?
+            }
+            Some(DiskSource::Image(DiskImage::Exe(data))) => {
+                exe = Some(PsxExe::parse(&data, config.ram_size)?)
+            }
+            // only fast_boot if there is anything to run
+            None => {}
+        }
+
+        let mut s = Self {
             cpu: cpu::Cpu::new(),
-            disk_available: disk_file.is_some(),
-            bus: CpuBus::new(bios, disk_file, config, device, queue)?,
-            exe_file,
+            disk_available: bus.cdrom().has_disk(),
+            bus,
+            exe,
             config,
             excess_cpu_cycles: 0,
+            cpu_cycle_scale: cpu_cycle_scale(config.cpu_overclock),
+            cpu_cycle_fraction: 0,
             cpu_frame_cycles: 0,
-        })
+            clock: EmulationClock::default(),
+            input: InputQueue::default(),
+            audio_capture: None,
+            stretch_buffer: Vec::new(),
+            perf_report: PerfFrameReport::default(),
+            #[cfg(feature = "scripting")]
+            script: None,
+            #[cfg(feature = "scripting")]
+            script_breakpoints: HashSet::new(),
+        };
+
+        // a single log shared by the CPU and the devices
+        let warnings = warnings::Warnings::default();
+        s.cpu.set_warnings(warnings.clone());
+        s.bus.set_warnings(warnings);
+
+        s.cpu.set_idle_skip(config.idle_skip);
+        if config.hle_bios {
+            s.cpu.enable_hle_bios(config.stdout_debug);
+            s.load_exe();
+        }
+
+        s.check_region_mismatch();
+
+        Ok(s)
     }
 
-    pub fn reset(&mut self) {
+    /// The current config, see [`Psx::set_config`] to change it
+    pub fn config(&self) -> &PsxConfig {
+        &self.config
+    }
+
+    /// Change the config while running, each field of [`PsxConfig`] says when
+    /// the change takes effect.
+    pub fn set_config(&mut self, config: PsxConfig) {
+        if config.hle_bios != self.config.hle_bios {
+            log::warn!("`hle_bios` can't be changed after creating the emulator, ignoring it");
+        }
+        let config = PsxConfig {
+            hle_bios: self.config.hle_bios,
+            ..config
+        };
+
+        self.cpu.set_idle_skip(config.idle_skip);
+        self.cpu.set_hle_bios_print_tty(config.stdout_debug);
+        self.cpu_cycle_scale = cpu_cycle_scale(config.cpu_overclock);
+        self.bus.set_config(config);
+        self.config = config;
+    }
+
+    /// Go back to the power-on state, with the same BIOS, disk and config, the
+    /// emulation continues the same as a new emulator would (see
+    /// [`PsxConfig::keep_spu_ram_on_reset`] for the exception).
+    ///
+    /// The host side is kept: the shell open state, the GPU render options and
+    /// capture, the audio mix settings and the elapsed counters. If the disk can't be
+    /// loaded again, the error is returned and the emulator is reset without it.
+    pub fn reset(&mut self) -> Result<(), PsxError> {
         self.cpu.reset();
-        self.bus.reset();
+        self.excess_cpu_cycles = 0;
+        self.cpu_cycle_fraction = 0;
+        self.cpu_frame_cycles = 0;
+        self.bus.reset()?;
+
+        // there is no BIOS to load the EXE for us
+        if self.config.hle_bios {
+            self.load_exe();
+        }
+        Ok(())
+    }
+
+    /// Reset the emulator, and run the EXE at `path` instead of the current disk or EXE,
+    /// e.g. after rebuilding it.
+    ///
+    /// The file is read and checked first, if it fails the emulator keeps running
+    /// unchanged. Without [`PsxConfig::hle_bios`], the BIOS kernel must be initialized
+    /// before, so the EXE is started when the BIOS reaches the shell like at boot,
+    /// otherwise it's started immediately.
+    pub fn reload_exe(&mut self, path: &Path) -> Result<(), PsxError> {
+        let data = std::fs::read(path).map_err(|e| {
+            PsxError::CouldNotLoadDisk(format!("could not read {}: {}", path.display(), e))
+        })?;
+        self.exe = Some(PsxExe::parse(&data, self.config.ram_size)?);
+        self.reset()
+    }
+
+    /// Games usually refuse to boot on a BIOS of another region, which is
+    /// hard to tell from a black screen
+    fn check_region_mismatch(&self) {
+        if let (Some(bios_region), Some(disk_region)) =
+            (self.bios_info().region, self.disk_region())
+        {
+            if bios_region != disk_region {
+                log::warn!(
+                    "REGION MISMATCH: the BIOS is {:?} but the disk is {:?}, \
+                     the game will most likely not boot, use a BIOS of the disk region",
+                    bios_region,
+                    disk_region
+                );
+            }
+        }
+    }
+
+    /// Load the EXE file into memory and jump to it
+    fn load_exe(&mut self) {
+        let Some(exe) = &self.exe else {
+            return;
+        };
+        self.bus.load_exe(exe);
+        exe.apply_registers(self.cpu.registers_mut());
+        println!(
+            "Loaded EXE into pc: {:08x}. gp: {:08x}, sp_fp: {:08x}",
+            exe.pc,
+            exe.gp,
+            exe.stack_pointer().unwrap_or(0)
+        );
+    }
+
+    /// Apply the input sent from [`PsxInputHandle`]s
+    fn apply_pending_input(&mut self) {
+        for event in self.input.pending() {
+            match event {
+                InputEvent::ControllerKey {
+                    port,
+                    pad,
+                    key,
+                    pressed,
+                } => self
+                    .bus
+                    .controller_mem_card_mut()
+                    .change_controller_key_state(port, pad, key, pressed),
+                InputEvent::CdromShellOpen(open) => {
+                    self.bus.cdrom_mut().change_cdrom_shell_open_state(open)
+                }
+            }
+        }
     }
 
     #[inline(always)]
     fn common_clock(&mut self) -> (u32, cpu::CpuState) {
+        let (added_clock, _, _, cpu_state) =
+            self.clock_limited(CPU_INSTRUCTIONS_PER_CLOCK, MAX_CPU_CYCLES_TO_CLOCK);
+        (added_clock.unwrap_or(0), cpu_state)
+    }
+
+    /// Convert the cycles of the (overclocked) CPU to stock cycles, which the
+    /// components are clocked by
+    #[inline(always)]
+    fn stock_cycles(&mut self, cpu_cycles: u32) -> u32 {
+        let scaled =
+            cpu_cycles as u64 * self.cpu_cycle_scale as u64 + self.cpu_cycle_fraction as u64;
+        self.cpu_cycle_fraction = (scaled & 0xFFFF) as u32;
+        (scaled >> 16) as u32
+    }
+
+    /// Run up to `instructions` if the components caught up with the CPU, then clock
+    /// the components by up to `max_cycles` of the cycles they are behind.
+    ///
+    /// Returns `(the cycles added by the CPU and DMA, the cycles clocked, vblank started, state)`,
+    /// the added cycles are `None` if the CPU didn't run
+    #[inline(always)]
+    fn clock_limited(
+        &mut self,
+        instructions: u32,
+        max_cycles: u32,
+    ) -> (Option<u32>, u32, bool, cpu::CpuState) {
         let mut cpu_state = cpu::CpuState::Normal;
-        let mut added_clock = 0;
+        let mut added_clock = None;
         if self.excess_cpu_cycles == 0 {
-            // this number doesn't mean anything
-            // TODO: research on when to stop the CPU (maybe fixed number? block of code? other?)
             let cpu_cycles;
             let shell_reached;
 
-            (shell_reached, cpu_cycles, cpu_state) = self.cpu.clock(&mut self.bus, 56);
+            (shell_reached, cpu_cycles, cpu_state) = self.cpu.clock(&mut self.bus, instructions);
 
-            // handle fast booting and hijacking the bios to load exe
-            if shell_reached && (self.config.fast_boot || self.exe_file.is_some()) {
-                if let Some(exe_file) = &self.exe_file {
-                    let (pc, gp, sp_fp) = self.bus.load_exe_in_memory(exe_file);
+            #[cfg(feature = "scripting")]
+            if let cpu::CpuState::InstructionBreakpoint(addr) = cpu_state {
+                if self.script_breakpoints.contains(&addr) {
+                    self.run_script(|script, psx| script.on_breakpoint(psx, addr));
+                    cpu_state = cpu::CpuState::Normal;
+                }
+            }
 
-                    let regs = self.cpu.registers_mut();
-                    println!(
-                        "Loaded EXE {} into pc: {:08x}. gp: {:08x}, sp_fp: {:08x}",
-                        exe_file.display(),
-                        pc,
-                        gp,
-                        sp_fp
-                    );
-
-                    assert_ne!(pc, 0, "PC value cannot be zero");
-                    regs.write(RegisterType::Pc, pc);
-
-                    if gp != 0 {
-                        regs.write(RegisterType::Gp, gp);
-                    }
-                    if sp_fp != 0 {
-                        regs.write(RegisterType::Sp, sp_fp);
-                        regs.write(RegisterType::Fp, sp_fp);
-                    }
+            // handle fast booting and hijacking the bios to load exe
+            // in HLE mode, the EXE is already loaded, and the shell location
+            // could be part of the EXE itself
+            if shell_reached
+                && !self.config.hle_bios
+                && (self.config.fast_boot || self.exe.is_some())
+            {
+                if self.exe.is_some() {
+                    self.load_exe();
                 } else if self.disk_available {
                     // we are either in a cd game or not, either way, skip the shell
                     let regs = self.cpu.registers_mut();
@@ -165,29 +889,49 @@ impl Psx {
             }
 
             if cpu_cycles == 0 {
-                return (0, cpu_state);
+                return (None, 0, false, cpu_state);
             }
-            // the DMA is running of the CPU
-            self.excess_cpu_cycles = cpu_cycles + self.bus.clock_dma();
-            added_clock = self.excess_cpu_cycles;
+            // the DMA is running of the CPU, at the stock speed
+            self.excess_cpu_cycles = self.stock_cycles(cpu_cycles) + self.bus.clock_dma();
+            added_clock = Some(self.excess_cpu_cycles);
         }
 
-        let cpu_cycles_to_run = self.excess_cpu_cycles.min(MAX_CPU_CYCLES_TO_CLOCK);
+        let cpu_cycles_to_run = self.excess_cpu_cycles.min(max_cycles);
         self.excess_cpu_cycles -= cpu_cycles_to_run;
+        let was_in_vblank = self.bus.gpu().in_vblank();
         self.bus.clock_components(cpu_cycles_to_run);
 
-        (added_clock, cpu_state)
+        self.clock.add_cycles(cpu_cycles_to_run);
+        let vblank_started = !was_in_vblank && self.bus.gpu().in_vblank();
+        if vblank_started {
+            self.clock.add_frame();
+            self.perf_report = PerfFrameReport::take(
+                self.cpu.perf_counters_mut(),
+                self.bus.dma_perf_counters_mut(),
+            );
+            #[cfg(feature = "scripting")]
+            self.run_script(|script, psx| script.on_frame(psx));
+
+            if let Some((error, recovered)) = self.bus.gpu_mut().take_error() {
+                if cpu_state == cpu::CpuState::Normal {
+                    cpu_state = cpu::CpuState::GpuError { error, recovered };
+                }
+            }
+        }
+
+        (added_clock, cpu_cycles_to_run, vblank_started, cpu_state)
     }
 
     /// Return `true` if the frame is finished, `false` otherwise.
     /// Return the CPU state.
     pub fn clock_based_on_audio(&mut self, max_clocks: u32) -> (bool, cpu::CpuState) {
+        self.apply_pending_input();
         // sync the CPU clocks to the SPU so that the audio would be clearer.
-        const CYCLES_PER_FRAME: u32 = 564480;
+        let cycles_per_frame = self.clock.audio_frame_cycles();
 
         let mut clocks = 0;
 
-        while self.cpu_frame_cycles < CYCLES_PER_FRAME {
+        while self.cpu_frame_cycles < cycles_per_frame {
             let (added_clock, cpu_state) = self.common_clock();
             clocks += added_clock;
             self.cpu_frame_cycles += added_clock;
@@ -196,7 +940,7 @@ impl Psx {
                 return (false, cpu_state);
             }
         }
-        self.cpu_frame_cycles -= CYCLES_PER_FRAME;
+        self.cpu_frame_cycles -= cycles_per_frame;
 
         (true, cpu::CpuState::Normal)
     }
@@ -204,6 +948,7 @@ impl Psx {
     /// Return `true` if the frame is finished, `false` otherwise.
     /// Return the CPU state.
     pub fn clock_based_on_video(&mut self, max_clocks: u32) -> (bool, cpu::CpuState) {
+        self.apply_pending_input();
         let mut prev_vblank = self.bus.gpu().in_vblank();
         let mut current_vblank = prev_vblank;
 
@@ -227,12 +972,15 @@ impl Psx {
         (true, cpu::CpuState::Normal)
     }
 
+    /// Run one audio frame (the SPU samples of one video frame), and return the CPU state,
+    /// this keeps the audio smooth, but the video frames are not aligned to the calls
     pub fn clock_full_audio_frame(&mut self) -> cpu::CpuState {
+        self.apply_pending_input();
         // sync the CPU clocks to the SPU so that the audio would be clearer.
-        const CYCLES_PER_FRAME: u32 = 564480;
+        let cycles_per_frame = self.clock.audio_frame_cycles();
 
         let mut clocks = 0;
-        while clocks < CYCLES_PER_FRAME {
+        while clocks < cycles_per_frame {
             let (added_clock, cpu_state) = self.common_clock();
             clocks += added_clock;
             if cpu_state != cpu::CpuState::Normal {
@@ -243,33 +991,434 @@ impl Psx {
         cpu::CpuState::Normal
     }
 
+    /// Run until the next vblank, with a speed multiplier other than `1.0`,
+    /// this can run multiple frames or none at all, see [`Psx::set_speed_multiplier`].
+    ///
+    /// Returns early if the CPU stops on a breakpoint (with the `debugger` feature),
+    /// otherwise the state is always [`CpuState::Normal`](cpu::CpuState::Normal).
+    ///
+    /// ```no_run
+    /// # use trapezoid_core::{cpu::CpuState, Psx, PsxConfig};
+    /// # fn main() -> Result<(), trapezoid_core::PsxError> {
+    /// # let mut psx = Psx::new_headless(Some("SCPH1001.BIN"), None::<&str>, PsxConfig::default())?;
+    /// // one second of NTSC video
+    /// for _ in 0..60 {
+    ///     if psx.clock_full_video_frame() != CpuState::Normal {
+    ///         break;
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
     pub fn clock_full_video_frame(&mut self) -> cpu::CpuState {
-        let mut prev_vblank = self.bus.gpu().in_vblank();
-        let mut current_vblank = prev_vblank;
+        self.apply_pending_input();
+        for _ in 0..self.clock.video_frames_to_run() {
+            let mut prev_vblank = self.bus.gpu().in_vblank();
+            let mut current_vblank = prev_vblank;
 
-        while !current_vblank || prev_vblank {
-            let cpu_state = self.common_clock().1;
+            while !current_vblank || prev_vblank {
+                let cpu_state = self.common_clock().1;
+                if cpu_state != cpu::CpuState::Normal {
+                    return cpu_state;
+                }
+
+                prev_vblank = current_vblank;
+                current_vblank = self.bus.gpu().in_vblank();
+            }
+        }
+
+        cpu::CpuState::Normal
+    }
+
+    /// Run at most `max_cycles` CPU cycles, for embeddings that do their own frame
+    /// and audio pacing. Nothing is rendered to the front image, use
+    /// [`Psx::take_front_image`] or [`Psx::read_display_rgba`] when needed.
+    ///
+    /// Returns early when a vblank starts, or when the CPU stops (e.g. on a
+    /// breakpoint). The CPU runs ahead of the other components by a few
+    /// instructions, the cycles they are behind are clocked by the next calls, so
+    /// the cycles add up to [`Psx::elapsed_cycles`] over any number of calls.
+    ///
+    /// The speed multiplier doesn't apply, the caller decides how much to run.
+    ///
+    /// ```no_run
+    /// # use trapezoid_core::{cpu::CpuState, Psx, PsxConfig};
+    /// # fn main() -> Result<(), trapezoid_core::PsxError> {
+    /// # let mut psx = Psx::new_headless(Some("SCPH1001.BIN"), None::<&str>, PsxConfig::default())?;
+    /// // run one scanline at a time
+    /// loop {
+    ///     let result = psx.step(2152);
+    ///     if result.vblank_crossed || result.cpu_state != CpuState::Normal {
+    ///         break;
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn step(&mut self, max_cycles: u32) -> StepResult {
+        self.apply_pending_input();
+
+        let mut cycles_executed = 0;
+        let mut vblank_crossed = false;
+        let mut cpu_state = cpu::CpuState::Normal;
+        while cycles_executed < max_cycles && !vblank_crossed {
+            let max_cycles = (max_cycles - cycles_executed).min(MAX_CPU_CYCLES_TO_CLOCK);
+            let (_, clocked, vblank_started, state) =
+                self.clock_limited(CPU_INSTRUCTIONS_PER_CLOCK, max_cycles);
+            cycles_executed += clocked;
+            vblank_crossed = vblank_started;
+            cpu_state = state;
             if cpu_state != cpu::CpuState::Normal {
-                return cpu_state;
+                break;
             }
+        }
 
-            prev_vblank = current_vblank;
-            current_vblank = self.bus.gpu().in_vblank();
+        StepResult {
+            cycles_executed,
+            cpu_state,
+            vblank_crossed,
+            audio_samples_ready: self.bus.spu().audio_buffer_len() / 2,
         }
+    }
 
-        cpu::CpuState::Normal
+    /// Execute exactly one CPU instruction, and clock the other components by its
+    /// cycles, for lockstep comparison against other emulators.
+    ///
+    /// The calls handled by the HLE BIOS count as one instruction. Fails with
+    /// [`PsxError::IdleSkipEnabled`] unless [`PsxConfig::idle_skip`] is `Off`, since
+    /// the idle loops are skipped as a whole.
+    pub fn step_instruction(&mut self) -> Result<InstructionStep, PsxError> {
+        if self.config.idle_skip != IdleSkip::Off {
+            return Err(PsxError::IdleSkipEnabled);
+        }
+        self.apply_pending_input();
+
+        // the components have to catch up with the previous instructions first
+        let mut vblank_crossed = self.clock_excess_cycles();
+
+        let mut cycles = None;
+        let mut cpu_state = cpu::CpuState::Normal;
+        // the CPU stops without executing anything when reaching the shell
+        while cycles.is_none() && cpu_state == cpu::CpuState::Normal {
+            let (added_clock, _, vblank_started, state) = self.clock_limited(1, 0);
+            cycles = added_clock;
+            vblank_crossed |= vblank_started;
+            cpu_state = state;
+        }
+        vblank_crossed |= self.clock_excess_cycles();
+
+        Ok(InstructionStep {
+            pc: self.cpu.registers().read(RegisterType::Pc),
+            cycles: cycles.unwrap_or(0),
+            cpu_state,
+            vblank_crossed,
+        })
+    }
+
+    /// Clock the components by the cycles they are behind the CPU, returns `true`
+    /// if a vblank started
+    fn clock_excess_cycles(&mut self) -> bool {
+        let mut vblank_crossed = false;
+        while self.excess_cpu_cycles > 0 {
+            let (_, _, vblank_started, _) = self.clock_limited(0, MAX_CPU_CYCLES_TO_CLOCK);
+            vblank_crossed |= vblank_started;
+        }
+        vblank_crossed
+    }
+
+    /// The CPU cycles emulated since the emulator was created
+    pub fn elapsed_cycles(&self) -> u64 {
+        self.clock.elapsed_cycles()
+    }
+
+    /// The video frames (vblanks) emulated since the emulator was created
+    pub fn elapsed_frames(&self) -> u64 {
+        self.clock.elapsed_frames()
+    }
+
+    /// The time passed inside the emulation, based on [`Psx::elapsed_cycles`]
+    pub fn emulated_time(&self) -> Duration {
+        self.clock.emulated_time()
     }
 
+    /// A checksum of the emulated state: the CPU registers, main RAM, scratchpad,
+    /// SPU RAM, the memory cards and the registers of the interrupts, timers, SPU,
+    /// controller port and `GPUSTAT`.
+    ///
+    /// The emulation is deterministic, so two emulators started from the same BIOS,
+    /// disk, memory cards and config, and given the same input at the same frames,
+    /// have the same hash after the same number of frames. Input sent from a
+    /// [`PsxInputHandle`] is applied when the next clock call starts, so it's only
+    /// deterministic if it's sent between frames. The hash is the same on all hosts and
+    /// builds, so it can be compared between netplay peers to detect desyncs.
+    ///
+    /// The VRAM is not included, since it's in the host GPU. This is not cheap
+    /// (it goes over all the RAM), calling it every few frames is fine.
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = state_hash::StateHasher::default();
+        self.cpu.registers().hash_state(&mut hasher);
+        self.bus.hash_state(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Scale how much is emulated in a "frame" by `clock_based_on_audio`,
+    /// `clock_full_audio_frame` and `clock_full_video_frame`, for
+    /// fast-forward (`> 1.0`) and slow-motion (`< 1.0`).
+    ///
+    /// The audio returned from [`Psx::take_audio_buffer_into`] is time-stretched, so
+    /// it keeps the pitch and the same amount of samples over time. It's ~35ms
+    /// behind, and comes in chunks of 512 samples, so some frames get none.
+    pub fn set_speed_multiplier(&mut self, speed_multiplier: f64) {
+        self.clock.set_speed_multiplier(speed_multiplier);
+    }
+
+    /// See [`Psx::set_speed_multiplier`]
+    pub fn speed_multiplier(&self) -> f64 {
+        self.clock.speed_multiplier()
+    }
+
+    /// Whether the GPU is currently in vblank
+    pub fn in_vblank(&self) -> bool {
+        self.bus.gpu().in_vblank()
+    }
+
+    /// The vblanks since power on or the last [`Psx::reset`], counted by the GPU
+    /// video timing, unlike [`Psx::elapsed_frames`] which is kept across resets.
+    pub fn vblank_count(&self) -> u64 {
+        self.bus.gpu().vblank_count()
+    }
+
+    /// The hblanks (scanlines) since power on or the last [`Psx::reset`],
+    /// these are the clock of timer 1 when it is in hblank mode.
+    pub fn hblank_count(&self) -> u64 {
+        self.bus.gpu().hblank_count()
+    }
+
+    /// The refresh rate of the current video mode (including [`PsxConfig::region_override`]),
+    /// about `59.8` for NTSC and `49.7` for PAL.
+    ///
+    /// Frontends running one [`Psx::clock_based_on_video`] frame at a time should run at this
+    /// rate, as it can change when the game switches the video mode.
+    pub fn frame_rate(&self) -> f64 {
+        self.bus.gpu().frame_rate()
+    }
+
+    /// Create a handle to send input from another thread, see [`PsxInputHandle`]
+    pub fn input_handle(&self) -> PsxInputHandle {
+        self.input.handle()
+    }
+
+    /// Change the key state of the controller in the first port, the game sees
+    /// it at the latch point (see [`Psx::set_input_latch_mode`]).
+    ///
+    /// ```no_run
+    /// # use trapezoid_core::{DigitalControllerKey, Psx, PsxConfig};
+    /// # fn main() -> Result<(), trapezoid_core::PsxError> {
+    /// # let mut psx = Psx::new_headless(Some("SCPH1001.BIN"), Some("game.cue"), PsxConfig::default())?;
+    /// // press `Start` for a few frames
+    /// psx.change_controller_key_state(DigitalControllerKey::Start, true);
+    /// for _ in 0..5 {
+    ///     psx.clock_full_video_frame();
+    /// }
+    /// psx.change_controller_key_state(DigitalControllerKey::Start, false);
+    /// # Ok(())
+    /// # }
+    /// ```
     pub fn change_controller_key_state(&mut self, key: DigitalControllerKey, pressed: bool) {
+        self.change_port_controller_key_state(0, key, pressed);
+    }
+
+    /// Change the key state of the controller in `port` (`0` or `1`), an invalid
+    /// `port` is ignored with a warning
+    pub fn change_port_controller_key_state(
+        &mut self,
+        port: usize,
+        key: DigitalControllerKey,
+        pressed: bool,
+    ) {
+        self.change_multitap_controller_key_state(port, 0, key, pressed);
+    }
+
+    /// Change the key state of the pad `pad` (`0..4` for A-D) in the multitap
+    /// in `port`, pad A is the same as the controller in the port. An invalid
+    /// `port` or `pad` is ignored with a warning.
+    pub fn change_multitap_controller_key_state(
+        &mut self,
+        port: usize,
+        pad: usize,
+        key: DigitalControllerKey,
+        pressed: bool,
+    ) {
+        if !is_valid_controller(port, pad) {
+            return;
+        }
+        self.bus
+            .controller_mem_card_mut()
+            .change_controller_key_state(port, pad, key, pressed);
+    }
+
+    /// Plug or unplug the controller in `port` (`0` or `1`), only the first port
+    /// is connected by default. An invalid `port` is ignored with a warning.
+    pub fn set_controller_connected(&mut self, port: usize, connected: bool) {
+        self.set_multitap_controller_connected(port, 0, connected);
+    }
+
+    /// Plug or unplug the pad `pad` (`0..4` for A-D) in the multitap in `port`,
+    /// the pads B-D are not connected by default. An invalid `port` or `pad` is
+    /// ignored with a warning.
+    pub fn set_multitap_controller_connected(&mut self, port: usize, pad: usize, connected: bool) {
+        if !is_valid_controller(port, pad) {
+            return;
+        }
+        self.bus
+            .controller_mem_card_mut()
+            .set_controller_connected(port, pad, connected);
+    }
+
+    /// Plug a multitap into `port` (`0` or `1`) for up to 4 pads in the port.
+    ///
+    /// Games see the pads B-D only after they select the multitap in a poll, before
+    /// that, and without a multitap, the port works as a single pad (pad A).
+    /// An invalid `port` is ignored with a warning.
+    pub fn set_multitap(&mut self, port: usize, enabled: bool) {
+        if !is_valid_controller(port, 0) {
+            return;
+        }
+        self.bus
+            .controller_mem_card_mut()
+            .set_multitap(port, enabled);
+    }
+
+    /// Plug an analog pad in analog mode into `port` (`0` or `1`) instead of the
+    /// digital pad, the game reads the sticks after the buttons. An invalid `port`
+    /// is ignored with a warning.
+    pub fn set_controller_analog(&mut self, port: usize, analog: bool) {
+        if !is_valid_controller(port, 0) {
+            return;
+        }
+        self.bus
+            .controller_mem_card_mut()
+            .set_controller_analog(port, 0, analog);
+    }
+
+    /// Set how the host axis values of `stick` of the controller in `port` are
+    /// converted to the bytes the game reads, the identity by default.
+    ///
+    /// The config is applied in [`Psx::change_controller_analog_state`], so it
+    /// doesn't change the stick position already sent.
+    ///
+    /// An invalid `port` or `config` (see [`AnalogConfig::is_valid`]) is ignored
+    /// with a warning.
+    pub fn set_analog_config(&mut self, port: usize, stick: AnalogStick, config: AnalogConfig) {
+        if !is_valid_controller(port, 0) {
+            return;
+        }
+        if !config.is_valid() {
+            log::warn!("Invalid analog config {:?}, ignoring it", config);
+            return;
+        }
+        self.bus
+            .controller_mem_card_mut()
+            .set_analog_config(port, 0, stick, config);
+    }
+
+    /// Move `stick` of the analog controller in `port` to `x` (`-1.0` left to
+    /// `1.0` right) and `y` (`-1.0` up to `1.0` down), the game sees it at the
+    /// latch point like the keys (see [`Psx::set_input_latch_mode`]).
+    ///
+    /// Returns the `[x, y]` bytes the game will read, after the [`AnalogConfig`]
+    /// of the stick. Input recordings should keep these and replay them with
+    /// [`Psx::change_controller_analog_bytes`], so the playback doesn't depend
+    /// on the config. With an invalid `port`, nothing changes and the center
+    /// `[0x80, 0x80]` is returned, with a warning.
+    ///
+    /// ```no_run
+    /// # use trapezoid_core::{AnalogConfig, AnalogCurve, AnalogStick, Psx, PsxConfig};
+    /// # fn main() -> Result<(), trapezoid_core::PsxError> {
+    /// # let mut psx = Psx::new_headless(Some("SCPH1001.BIN"), Some("game.cue"), PsxConfig::default())?;
+    /// psx.set_controller_analog(0, true);
+    /// psx.set_analog_config(
+    ///     0,
+    ///     AnalogStick::Left,
+    ///     AnalogConfig {
+    ///         deadzone: 0.15,
+    ///         saturation: 0.95,
+    ///         response_curve: AnalogCurve::Quadratic,
+    ///     },
+    /// );
+    /// // a worn stick resting off the center is still centered
+    /// assert_eq!(
+    ///     psx.change_controller_analog_state(0, AnalogStick::Left, 0.1, -0.05),
+    ///     [0x80, 0x80]
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn change_controller_analog_state(
+        &mut self,
+        port: usize,
+        stick: AnalogStick,
+        x: f32,
+        y: f32,
+    ) -> [u8; 2] {
+        if !is_valid_controller(port, 0) {
+            return [0x80; 2];
+        }
+        self.bus
+            .controller_mem_card_mut()
+            .change_controller_analog_state(port, 0, stick, x, y)
+    }
+
+    /// Move `stick` of the analog controller in `port` to the `[x, y]` bytes
+    /// the game reads (`80h` is the center), without the [`AnalogConfig`].
+    /// An invalid `port` is ignored with a warning.
+    pub fn change_controller_analog_bytes(
+        &mut self,
+        port: usize,
+        stick: AnalogStick,
+        bytes: [u8; 2],
+    ) {
+        if !is_valid_controller(port, 0) {
+            return;
+        }
+        self.bus
+            .controller_mem_card_mut()
+            .change_controller_analog_bytes(port, 0, stick, bytes);
+    }
+
+    /// Choose when key changes are made visible to the game, see [`InputLatchMode`]
+    pub fn set_input_latch_mode(&mut self, mode: InputLatchMode) {
         self.bus
             .controller_mem_card_mut()
-            .change_controller_key_state(key, pressed);
+            .set_input_latch_mode(mode);
     }
 
+    /// Open or close the CD-ROM shell (lid), the BIOS goes to its shell menu
+    /// when it's open
     pub fn change_cdrom_shell_open_state(&mut self, open: bool) {
         self.bus.cdrom_mut().change_cdrom_shell_open_state(open);
     }
 
+    /// Replace the disk in the CD-ROM with another `.cue` file, for multi-disc games.
+    ///
+    /// The shell must be open (see [`Psx::change_cdrom_shell_open_state`]), the game
+    /// sees the new disk after it's closed.
+    pub fn swap_disk<P: AsRef<Path>>(&mut self, path: P) -> Result<(), PsxError> {
+        // the shell could have been opened from a `PsxInputHandle`
+        self.apply_pending_input();
+        match DiskSource::from_path(path.as_ref())? {
+            DiskSource::CueFile(path) => self.bus.cdrom_mut().swap_disk(path)?,
+            DiskSource::Image(_) => return Err(PsxError::DiskTypeNotSupported),
+        }
+        self.disk_available = true;
+        Ok(())
+    }
+
+    /// Sync the GPU, and record the copy of the display area (or the whole VRAM if
+    /// `full_vram`) into `dest_image` after `in_future`, returns the future of the
+    /// copy. See [`Psx::take_front_image`] to present from another thread.
+    #[cfg(feature = "vulkan")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "vulkan")))]
     pub fn blit_to_front(
         &mut self,
         dest_image: Arc<Image>,
@@ -281,35 +1430,389 @@ impl Psx {
             .sync_gpu_and_blit_to_front(dest_image, full_vram, in_future)
     }
 
+    /// Get the last rendered front image, and request the next one.
+    ///
+    /// This is an alternative to [`Psx::blit_to_front`] for frontends that present
+    /// from another thread. The image is fully rendered when returned, it is `None`
+    /// for the first call only. It is in the format of [`Psx::set_output_format`],
+    /// and can be blitted or sampled.
+    ///
+    /// The images are reused once they are dropped, so don't keep more than the
+    /// ones being presented.
+    #[cfg(feature = "vulkan")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "vulkan")))]
+    pub fn take_front_image(&mut self, full_vram: bool) -> Option<Arc<Image>> {
+        self.bus.gpu_mut().sync_and_take_front_image(full_vram)
+    }
+
+    /// The format of the front images, `B8G8R8A8_UNORM` by default
+    #[cfg(feature = "vulkan")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "vulkan")))]
+    pub fn output_format(&self) -> Format {
+        self.bus.gpu().output_format()
+    }
+
+    /// Render the next front images in `format`, usually the format of the
+    /// frontend's swapchain, so that presenting them doesn't need a conversion.
+    ///
+    /// It must be a color format with red, green and blue components, stored as
+    /// normalized integers or floats, that the device can render to, sample and
+    /// blit from, otherwise [`PsxError::UnsupportedOutputFormat`] is returned and
+    /// the format is not changed. It continues across resets.
+    #[cfg(feature = "vulkan")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "vulkan")))]
+    pub fn set_output_format(&mut self, format: Format) -> Result<(), PsxError> {
+        self.bus.gpu_mut().set_output_format(format)
+    }
+
+    /// The host GPU work done for the last frame returned by [`Psx::take_front_image`]
+    /// or [`Psx::blit_to_front`], all zeros before the first frame.
+    ///
+    /// The stats travel with the front image, so they always match that frame,
+    /// even though the rendering happens in another thread. Without the `vulkan`
+    /// feature, there is no host GPU work, so they stay all zeros.
+    pub fn gpu_stats(&self) -> GpuStats {
+        self.bus.gpu().stats()
+    }
+
+    /// The CPU and DMA work of the last complete video frame, reset on read, so
+    /// calling it again before the next vblank returns all zeros.
+    ///
+    /// With the `no-perf-counters` feature, nothing is counted.
+    pub fn perf_frame_report(&mut self) -> PerfFrameReport {
+        std::mem::take(&mut self.perf_report)
+    }
+
+    /// Append the audio produced since the last call to `out`, as interleaved
+    /// stereo samples at 44100Hz.
+    ///
+    /// `out` is not cleared, so the same buffer can be reused every frame without
+    /// allocating.
+    pub fn take_audio_buffer_into(&mut self, out: &mut Vec<f32>) {
+        let start = out.len();
+        if self.clock.speed_multiplier() == 1.0 {
+            self.bus.spu_mut().take_audio_buffer(out);
+        } else {
+            self.bus
+                .spu_mut()
+                .take_audio_buffer(&mut self.stretch_buffer);
+            self.clock.stretch_audio(&self.stretch_buffer, out);
+            self.stretch_buffer.clear();
+        }
+
+        if let Some(audio_capture) = &mut self.audio_capture {
+            if let Err(e) = audio_capture.write_samples(&out[start..]) {
+                log::error!("Failed to write audio capture, stopping: {}", e);
+                self.audio_capture = None;
+            }
+        }
+    }
+
+    /// Same as [`Psx::take_audio_buffer_into`], but returns a new buffer.
+    ///
+    /// ```no_run
+    /// # use trapezoid_core::{Psx, PsxConfig};
+    /// # fn main() -> Result<(), trapezoid_core::PsxError> {
+    /// # let mut psx = Psx::new_headless(Some("SCPH1001.BIN"), None::<&str>, PsxConfig::default())?;
+    /// // instead of `psx.take_audio_buffer()`, reuse the same buffer every frame
+    /// let mut samples = Vec::new();
+    /// for _ in 0..60 {
+    ///     psx.clock_full_audio_frame();
+    ///     psx.take_audio_buffer_into(&mut samples);
+    ///     // play `samples`, 44100Hz interleaved stereo...
+    ///     samples.clear();
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[deprecated(note = "allocates a new buffer every call, use `take_audio_buffer_into`")]
     pub fn take_audio_buffer(&mut self) -> Vec<f32> {
-        self.bus.spu_mut().take_audio_buffer()
+        let mut out = Vec::new();
+        self.take_audio_buffer_into(&mut out);
+        out
+    }
+
+    /// Start writing the audio output into a `wav` file (16bit, 44100Hz, stereo).
+    ///
+    /// The captured audio is the same as the one returned from [`Psx::take_audio_buffer_into`],
+    /// so the audio is only captured when it is taken by the frontend.
+    /// If there is a capture already running, it will be stopped first.
+    /// The capture stops by itself when the file reaches the 4GB limit of `wav`,
+    /// about 6.7 hours.
+    pub fn start_audio_capture<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<()> {
+        self.stop_audio_capture()?;
+        self.audio_capture = Some(capture::WavWriter::create(path)?);
+        Ok(())
     }
 
+    /// Stop the audio capture and finalize the `wav` file
+    pub fn stop_audio_capture(&mut self) -> std::io::Result<()> {
+        if let Some(audio_capture) = self.audio_capture.take() {
+            audio_capture.finish()?;
+        }
+        Ok(())
+    }
+
+    /// See [`Psx::set_gpu_render_options`]
+    pub fn gpu_render_options(&self) -> GpuRenderOptions {
+        self.bus.gpu().render_options()
+    }
+
+    /// Change how the GPU renders, this doesn't change the emulation state,
+    /// and it is kept across resets. It applies to the draws after this call.
+    pub fn set_gpu_render_options(&mut self, render_options: GpuRenderOptions) {
+        self.bus.gpu_mut().set_render_options(render_options);
+    }
+
+    /// Write `word` to `GP0`, the same as a CPU or DMA write.
+    ///
+    /// This can be used outside the emulation loop, for example to replay
+    /// GPU commands or test the renderer, unsupported commands are logged.
+    pub fn gpu_gp0_write(&mut self, word: u32) {
+        self.bus.gpu_mut().write_u32(0, word).unwrap();
+    }
+
+    /// Write `word` to `GP1`, the same as a CPU write.
+    ///
+    /// See [`Psx::gpu_gp0_write`].
+    pub fn gpu_gp1_write(&mut self, word: u32) {
+        self.bus.gpu_mut().write_u32(4, word).unwrap();
+    }
+
+    /// Start recording every word written to `GP0` and `GP1` (from the CPU and DMA),
+    /// with the frame boundaries, into `path`.
+    ///
+    /// The format is documented in [`GpuCaptureReader`], and it can be replayed
+    /// with [`Psx::replay_gpu_capture`]. If there is a capture already running,
+    /// it will be stopped first.
+    pub fn start_gpu_capture<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<()> {
+        self.bus.gpu_mut().start_command_capture(path)
+    }
+
+    /// Stop the GPU capture and flush the file
+    pub fn stop_gpu_capture(&mut self) -> std::io::Result<()> {
+        self.bus.gpu_mut().stop_command_capture()
+    }
+
+    /// Write all the commands of a GPU capture file into the GPU, without running
+    /// the CPU, returns the number of frames replayed.
+    ///
+    /// The result can be read with [`Psx::read_vram_block`] or [`Psx::take_front_image`].
+    pub fn replay_gpu_capture<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<u64> {
+        let reader = GpuCaptureReader::open(path)?;
+        self.bus.gpu_mut().replay_command_capture(reader)
+    }
+
+    /// Read a block of VRAM (`RGB555` pixels, row by row).
+    ///
+    /// This will block until the GPU finishes all pending draws.
+    pub fn read_vram_block(&mut self, left: u32, top: u32, width: u32, height: u32) -> Vec<u16> {
+        self.bus.gpu_mut().read_vram_block(left, top, width, height)
+    }
+
+    /// Save the current display area into an image file.
+    ///
+    /// If the extension is `png`, the image is saved as PNG, which requires
+    /// the `image` feature, otherwise, it is saved as binary `ppm`.
+    pub fn capture_screenshot<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<()> {
+        self.bus.gpu_mut().read_display_frame().save(path)
+    }
+
+    /// Read the current display area as `RGBA8888` pixels, row by row, returns
+    /// `(width, height, pixels)`, the alpha is always `255`.
+    ///
+    /// This will block until the GPU finishes all pending draws.
+    pub fn read_display_rgba(&mut self) -> (u32, u32, Vec<u8>) {
+        let frame = self.bus.gpu_mut().read_display_frame();
+        let rgba = frame
+            .rgb
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect();
+        (frame.width, frame.height, rgba)
+    }
+
+    /// Install a sink to receive [`trace::TraceEvent`]s from the hardware components,
+    /// replacing the previous one if any.
+    pub fn set_trace_sink(&mut self, sink: Box<dyn trace::TraceSink>) {
+        self.bus.set_tracer(trace::Tracer::new(sink));
+    }
+
+    /// Remove the installed trace sink, events will not be generated anymore
+    pub fn clear_trace_sink(&mut self) {
+        self.bus.set_tracer(trace::Tracer::default());
+    }
+
+    /// Everything the BIOS and the game printed to the TTY since the last reset,
+    /// whether or not [`PsxConfig::stdout_debug`] is set. The HLE BIOS doesn't
+    /// print here.
+    pub fn tty_output(&self) -> &str {
+        self.bus.tty_output()
+    }
+
+    /// How many times the game did something that is not emulated, like an
+    /// unknown CD-ROM command, the emulator continues with a default behavior
+    /// instead, which may be why the game misbehaves
+    pub fn warning_count(&self) -> u64 {
+        self.bus.warnings().count()
+    }
+
+    /// The distinct warnings seen so far, see [`Psx::warning_count`]
+    pub fn emulation_warnings(&self) -> Vec<EmulationWarning> {
+        self.bus.warnings().list()
+    }
+
+    /// Forget the warnings seen so far, the count starts from `0` again
+    pub fn clear_warnings(&self) {
+        self.bus.warnings().clear();
+    }
+
+    /// Panic on unsupported behavior instead of continuing, so tests don't
+    /// silently pass over it. Bus errors are only recorded, as before.
+    pub fn set_strict_warnings(&self, strict: bool) {
+        self.bus.warnings().set_strict(strict);
+    }
+
+    /// Run `engine` on every frame and on its breakpoints, replacing the previous
+    /// one if any, see [`scripting::ScriptEngine`]
+    #[cfg(feature = "scripting")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "scripting")))]
+    pub fn attach_script_engine(&mut self, engine: Box<dyn scripting::ScriptEngine>) {
+        self.detach_script_engine();
+        self.script = Some(engine);
+        self.run_script(|script, psx| script.on_attach(psx));
+    }
+
+    /// Remove the script and its breakpoints, and return it
+    #[cfg(feature = "scripting")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "scripting")))]
+    pub fn detach_script_engine(&mut self) -> Option<Box<dyn scripting::ScriptEngine>> {
+        for addr in self.script_breakpoints.drain() {
+            self.cpu.debugger().remove_breakpoint(addr);
+        }
+        self.script.take()
+    }
+
+    #[cfg(feature = "scripting")]
+    fn run_script(
+        &mut self,
+        f: impl FnOnce(&mut dyn scripting::ScriptEngine, &mut scripting::ScriptHandle),
+    ) {
+        // taken out while running, so that the handle can borrow the emulator,
+        // the handle can't attach another script, so this is put back as is
+        if let Some(mut script) = self.script.take() {
+            f(script.as_mut(), &mut scripting::ScriptHandle::new(self));
+            self.script = Some(script);
+        }
+    }
+
+    /// The interrupts that are requested and not masked (`I_STAT & I_MASK`),
+    /// bit 0 is VBLANK, bit 1 is GPU, bit 2 is CD-ROM, and so on
+    pub fn pending_interrupts(&self) -> u16 {
+        self.bus.pending_interrupts_flags()
+    }
+
+    /// The CPU, for the debugger and the execution trace
     pub fn cpu(&mut self) -> &mut cpu::Cpu {
         &mut self.cpu
     }
 
+    /// The CPU registers, as of the last executed instruction
+    pub fn cpu_registers(&self) -> &cpu::Registers {
+        self.cpu.registers()
+    }
+
+    /// Read from the CPU address space, like a CPU load (without the cache).
+    ///
+    /// Reading some hardware registers changes their state, e.g. popping a FIFO.
     pub fn bus_read_u32(&mut self, addr: u32) -> Result<u32> {
         // make sure its aligned
         if addr % 4 != 0 {
-            return Err("Unaligned memory access".to_string());
+            return Err(BusError::UnalignedAccess { addr, size: 32 });
         }
         self.bus.read_u32(addr)
     }
 
+    /// See [`Psx::bus_read_u32`]
     pub fn bus_read_u16(&mut self, addr: u32) -> Result<u16> {
         // make sure its aligned
         if addr % 2 != 0 {
-            return Err("Unaligned memory access".to_string());
+            return Err(BusError::UnalignedAccess { addr, size: 16 });
         }
 
         self.bus.read_u16(addr)
     }
 
+    /// See [`Psx::bus_read_u32`]
     pub fn bus_read_u8(&mut self, addr: u32) -> Result<u8> {
         self.bus.read_u8(addr)
     }
 
+    /// The region and version of the loaded BIOS, detected when loading it
+    pub fn bios_info(&self) -> &BiosInfo {
+        self.bus.bios().info()
+    }
+
+    /// The region of the inserted disk, detected from its license string,
+    /// `None` if there is no disk or it's unlicensed (e.g. homebrew)
+    pub fn disk_region(&self) -> Option<Region> {
+        self.bus.cdrom().disk_region()
+    }
+
+    /// What kind of disk is in the CD-ROM, `None` if there is no disk
+    pub fn disk_type(&self) -> Option<DiskType> {
+        self.bus.cdrom().disk_type()
+    }
+
+    /// The name of the boot executable in `SYSTEM.CNF` of the disk, e.g.
+    /// `SLUS_007.71`, which is usually the serial of the game.
+    /// `None` if there is no disk or it's not a PlayStation data disk
+    pub fn disk_id(&self) -> Option<&str> {
+        self.bus.cdrom().disk_id()
+    }
+
+    /// The volumes of the CD audio to the SPU, set by the game
+    pub fn cdrom_audio_volumes(&self) -> CdAudioVolumes {
+        self.bus.cdrom().audio_volumes()
+    }
+
+    /// The state of the SPU registers and voices, cheap enough to call every frame
+    pub fn spu_state(&self) -> SpuState {
+        self.bus.spu().state()
+    }
+
+    /// Mute `voice` (`0..24`) in the audio output, this doesn't affect the emulation,
+    /// the game still sees it playing
+    pub fn set_voice_muted(&mut self, voice: usize, muted: bool) {
+        self.bus.spu_mut().set_voice_muted(voice, muted);
+    }
+
+    /// Only play `voice` (`0..24`) and the CD audio, or all the voices with `None`.
+    ///
+    /// Like [`Psx::set_voice_muted`], this only affects the audio output
+    pub fn solo_voice(&mut self, voice: Option<usize>) {
+        self.bus.spu_mut().solo_voice(voice);
+    }
+
+    /// Mute the CD audio (CD-DA and XA-ADPCM) in the audio output, see [`Psx::set_voice_muted`]
+    pub fn set_cd_audio_muted(&mut self, muted: bool) {
+        self.bus.spu_mut().set_cd_audio_muted(muted);
+    }
+
+    /// Feed the external audio input of the SPU (e.g. the audio of an expansion
+    /// port device), as `(left, right)` samples at 44100Hz, the SPU rate.
+    ///
+    /// The SPU plays one sample per cycle, and mixes it into the output with the
+    /// external volume when `SPUCNT` enables the external audio. Up to half a second
+    /// is buffered, older samples are dropped, so push about as many samples as
+    /// the emulated frame plays. The reverb is not emulated, so the external audio
+    /// reverb bit has no effect.
+    pub fn push_external_audio(&mut self, samples: &[(i16, i16)]) {
+        self.bus.spu_mut().add_external_audio(samples);
+    }
+
+    /// Print the state of the SPU and its voices to `stdout`, see [`Psx::spu_state`]
+    /// to read it instead
     pub fn print_spu_state(&self) {
         self.bus.spu().print_state();
     }
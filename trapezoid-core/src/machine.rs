@@ -0,0 +1,224 @@
+//! A description of the emulated machine, for tools that wrap the emulator
+//! and want to find the memory map and the devices without hardcoding them.
+//!
+//! With the `serde` feature, all the types are `Serialize`.
+
+use crate::memory::bus_map::{Access, BusDevice, BUS_MAP};
+
+/// What is behind a [`MemoryRegion`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum RegionKind {
+    Ram,
+    Rom,
+    /// The registers of a device, see [`MachineDescription::devices`]
+    Io,
+    /// The parallel port and the debug registers
+    Expansion,
+}
+
+/// A range of physical addresses, the same memory is also mirrored in
+/// `KSEG0` (`0x80000000`) and `KSEG1` (`0xA0000000`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MemoryRegion {
+    pub name: &'static str,
+    pub base: u32,
+    pub size: u32,
+    pub kind: RegionKind,
+}
+
+/// Registers of a device that support the same access widths
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct RegisterWindow {
+    pub base: u32,
+    pub size: u32,
+    /// In bits, `8`, `16` or `32`
+    pub read_widths: Vec<u8>,
+    /// In bits, `8`, `16` or `32`
+    pub write_widths: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DeviceDescription {
+    pub name: &'static str,
+    pub registers: Vec<RegisterWindow>,
+}
+
+/// The GPU backends compiled in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum GpuBackendKind {
+    Vulkan,
+}
+
+/// The cargo features of `trapezoid-core` that were enabled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Features {
+    pub debugger: bool,
+    pub scripting: bool,
+    pub image: bool,
+}
+
+/// Returned from [`describe`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MachineDescription {
+    /// Sorted by the base address
+    pub memory_map: Vec<MemoryRegion>,
+    /// The devices with registers, in the order of their addresses
+    pub devices: Vec<DeviceDescription>,
+    pub gpu_backends: Vec<GpuBackendKind>,
+    pub features: Features,
+}
+
+fn device_info(device: BusDevice) -> (&'static str, RegionKind) {
+    match device {
+        BusDevice::MainRam => ("Main RAM", RegionKind::Ram),
+        BusDevice::ExpansionRegion1 => ("Expansion Region 1", RegionKind::Expansion),
+        BusDevice::Scratchpad => ("Scratchpad", RegionKind::Ram),
+        BusDevice::MemoryControl1 => ("Memory Control 1", RegionKind::Io),
+        BusDevice::ControllerMemCard => ("Controller and Memory Card", RegionKind::Io),
+        BusDevice::MemoryControl2 => ("Memory Control 2", RegionKind::Io),
+        BusDevice::Interrupts => ("Interrupts", RegionKind::Io),
+        BusDevice::Dma => ("DMA", RegionKind::Io),
+        BusDevice::Timers => ("Timers", RegionKind::Io),
+        BusDevice::Cdrom => ("CDROM", RegionKind::Io),
+        BusDevice::Gpu => ("GPU", RegionKind::Io),
+        BusDevice::Mdec => ("MDEC", RegionKind::Io),
+        BusDevice::Spu => ("SPU", RegionKind::Io),
+        BusDevice::ExpansionRegion2 => ("Expansion Region 2", RegionKind::Expansion),
+        BusDevice::Bios => ("BIOS", RegionKind::Rom),
+        BusDevice::CacheControl => ("Cache Control", RegionKind::Io),
+    }
+}
+
+fn widths(access: Access, widths: [Access; 3]) -> Vec<u8> {
+    [8, 16, 32]
+        .into_iter()
+        .zip(widths)
+        .filter(|(_, width)| access.contains(*width))
+        .map(|(bits, _)| bits)
+        .collect()
+}
+
+/// Describe the emulated machine, this is the same memory map the CPU bus
+/// uses, so it doesn't depend on a running emulator.
+///
+/// ```
+/// let machine = trapezoid_core::describe();
+/// let gpu = machine.devices.iter().find(|d| d.name == "GPU").unwrap();
+/// assert_eq!(gpu.registers[0].base, 0x1F801810);
+/// ```
+pub fn describe() -> MachineDescription {
+    let mut memory_map: Vec<MemoryRegion> = Vec::new();
+    let mut devices: Vec<DeviceDescription> = Vec::new();
+
+    for window in BUS_MAP {
+        let (name, kind) = device_info(window.device);
+
+        // the windows of a device are next to each other
+        match memory_map.last_mut() {
+            Some(region) if region.name == name => {
+                region.size = window.base + window.size - region.base;
+            }
+            _ => memory_map.push(MemoryRegion {
+                name,
+                base: window.base,
+                size: window.size,
+                kind,
+            }),
+        }
+
+        if kind == RegionKind::Io {
+            let registers = RegisterWindow {
+                base: window.base,
+                size: window.size,
+                read_widths: widths(
+                    window.access,
+                    [Access::READ_8, Access::READ_16, Access::READ_32],
+                ),
+                write_widths: widths(
+                    window.access,
+                    [Access::WRITE_8, Access::WRITE_16, Access::WRITE_32],
+                ),
+            };
+            match devices.last_mut() {
+                Some(device) if device.name == name => device.registers.push(registers),
+                _ => devices.push(DeviceDescription {
+                    name,
+                    registers: vec![registers],
+                }),
+            }
+        }
+    }
+
+    MachineDescription {
+        memory_map,
+        devices,
+        gpu_backends: vec![GpuBackendKind::Vulkan],
+        features: Features {
+            debugger: cfg!(feature = "debugger"),
+            scripting: cfg!(feature = "scripting"),
+            image: cfg!(feature = "image"),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_map() {
+        let machine = describe();
+        let region = |name| {
+            machine
+                .memory_map
+                .iter()
+                .find(|r| r.name == name)
+                .unwrap()
+                .clone()
+        };
+
+        assert_eq!(
+            region("Main RAM"),
+            MemoryRegion {
+                name: "Main RAM",
+                base: 0,
+                size: 0x800000,
+                kind: RegionKind::Ram
+            }
+        );
+        assert_eq!(region("BIOS").base, 0x1FC00000);
+        assert_eq!(region("BIOS").kind, RegionKind::Rom);
+        // the 3 windows of the controller are merged
+        assert_eq!(region("Controller and Memory Card").base, 0x1F801040);
+        assert_eq!(region("Controller and Memory Card").size, 0x10);
+        assert!(machine
+            .memory_map
+            .windows(2)
+            .all(|w| w[0].base + w[0].size <= w[1].base));
+
+        assert!(machine
+            .devices
+            .iter()
+            .all(|d| region(d.name).kind == RegionKind::Io));
+        let controller = machine
+            .devices
+            .iter()
+            .find(|d| d.name == "Controller and Memory Card")
+            .unwrap();
+        assert_eq!(controller.registers.len(), 3);
+        assert_eq!(controller.registers[0].read_widths, [8]);
+        assert_eq!(controller.registers[1].read_widths, [16, 32]);
+        assert_eq!(controller.registers[2].read_widths, [16]);
+        assert_eq!(controller.registers[2].write_widths, [16]);
+
+        assert_eq!(machine.gpu_backends, [GpuBackendKind::Vulkan]);
+        assert_eq!(machine.features.debugger, cfg!(feature = "debugger"));
+    }
+}
@@ -24,6 +24,26 @@ const DEFAULT_SCALETABLE: [u16; 64] = [
     52996, 30273, 35262, 12539, 6392, 47331, 27245, 33397, 32138, 38290, 18204, 59143,
 ];
 
+// The MDEC decodes about 9000 macroblocks (of 6 blocks in 24bit/15bit mode)
+// per second, this is the time of one block of them
+const DECODE_CYCLES_PER_BLOCK: i32 = 33868800 / 9000 / 6;
+
+/// The number of words the data-in queue holds before the DMA has to wait for
+/// the decoder
+const IN_FIFO_WORDS: usize = 0x100;
+
+/// How fast the MDEC decodes, see [`PsxConfig::mdec_timing`](crate::PsxConfig::mdec_timing)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MdecTiming {
+    /// A block is decoded as soon as its data is written, so a whole frame
+    /// can be decoded in one DMA burst
+    Instant,
+    /// The data is queued and decoded over time at the hardware rate, the DMA
+    /// waits for room in the queue and for the decoded data
+    #[default]
+    Accurate,
+}
+
 const fn extend_sign<const N: usize>(x: u16) -> i32 {
     let mask: u32 = (1 << N) - 1;
     let x = x as u32 & mask;
@@ -132,6 +152,14 @@ pub struct Mdec {
     remaining_params: u16,
     current_cmd: Option<MdecCommand>,
     params_ptr: usize,
+    data_in_enabled: bool,
+    data_out_enabled: bool,
+
+    timing: MdecTiming,
+    /// The parameters of `DecodeMacroBlock` that were not decoded yet
+    in_fifo: VecDeque<u32>,
+    /// Can go negative, the time of the last block is paid after decoding it
+    decode_cycles: i32,
 
     out_fifo: VecDeque<FifoBlock>,
 
@@ -147,6 +175,12 @@ impl Default for Mdec {
             remaining_params: 0,
             current_cmd: None,
             params_ptr: 0,
+            data_in_enabled: false,
+            data_out_enabled: false,
+
+            timing: MdecTiming::default(),
+            in_fifo: VecDeque::new(),
+            decode_cycles: 0,
 
             out_fifo: VecDeque::new(),
 
@@ -184,10 +218,14 @@ impl Mdec {
             for x in 0..8 {
                 let r = cr_blk[((x + xx) / 2) + (((y + yy) / 2) * 8)];
                 let b = cb_blk[((x + xx) / 2) + (((y + yy) / 2) * 8)];
-                let g = ((r as f32 * -0.3437) + (b as f32 * -0.3437)) as i16;
+                // integer math instead of `f32`, so the output doesn't depend on
+                // the host float rounding, the division truncates toward zero
+                // like the `as i16` cast of the float did
+                let (r, b) = (r as i32, b as i32);
+                let g = ((r * -3437 + b * -3437) / 10000) as i16;
 
-                let r = (r as f32 * 1.402) as i16;
-                let b = (b as f32 * 1.772) as i16;
+                let r = ((r * 1402) / 1000) as i16;
+                let b = ((b * 1772) / 1000) as i16;
 
                 let y_data = y_blk[x + y * 8];
 
@@ -292,7 +330,9 @@ impl Mdec {
         }
     }
 
-    fn handle_current_cmd(&mut self, input: u32) {
+    /// Returns `true` if a block was decoded
+    fn handle_current_cmd(&mut self, input: u32) -> bool {
+        let mut block_decoded = false;
         if let Some(current_cmd) = &mut self.current_cmd {
             // the purpose of these variables is to own the data,
             // since we need `&mut self` to call `push_to_out_fifo`, and we
@@ -312,6 +352,8 @@ impl Mdec {
                         }
                     }
 
+                    // the Cr and Cb blocks take time too, even if there is no output yet
+                    block_decoded = idct_out.is_some();
                     if let Some(idct_out) = idct_out {
                         match self.status.output_depth() {
                             0 | 1 => {
@@ -415,6 +457,38 @@ impl Mdec {
                 self.current_cmd = None;
             }
         }
+        block_decoded
+    }
+
+    fn decoding(&self) -> bool {
+        matches!(self.current_cmd, Some(MdecCommand::DecodeMacroBlock(_)))
+    }
+
+    /// Decode the queued data, until the time runs out, if `cpu_cycles` is
+    /// `None`, decode all of it
+    fn decode_queued(&mut self, cpu_cycles: Option<u32>) {
+        match cpu_cycles {
+            Some(cpu_cycles) => {
+                self.decode_cycles = self.decode_cycles.saturating_add(cpu_cycles as i32);
+                while self.decode_cycles > 0 {
+                    let Some(input) = self.in_fifo.pop_front() else {
+                        break;
+                    };
+                    if self.handle_current_cmd(input) {
+                        self.decode_cycles -= DECODE_CYCLES_PER_BLOCK;
+                    }
+                }
+                // the time waiting for data can't be used later
+                if self.in_fifo.is_empty() {
+                    self.decode_cycles = self.decode_cycles.min(0);
+                }
+            }
+            None => {
+                while let Some(input) = self.in_fifo.pop_front() {
+                    self.handle_current_cmd(input);
+                }
+            }
+        }
     }
 }
 
@@ -504,6 +578,16 @@ impl Mdec {
     }
 
     fn read_status(&mut self) -> u32 {
+        let in_fifo_full = self.in_fifo.len() >= IN_FIFO_WORDS;
+        self.status.set(MdecStatus::DATA_IN_FIFO_FULL, in_fifo_full);
+        self.status.set(
+            MdecStatus::DATA_IN_REQUEST,
+            self.data_in_enabled && !in_fifo_full,
+        );
+        self.status.set(
+            MdecStatus::DATA_OUT_REQUEST,
+            self.data_out_enabled && !self.out_fifo.is_empty(),
+        );
         log::trace!(
             "mdec read status {:?}, remaining_params: {}",
             self.status,
@@ -514,6 +598,16 @@ impl Mdec {
 
     // handles commands params and execution
     fn write_command_params(&mut self, input: u32) {
+        if self.timing == MdecTiming::Accurate && self.decoding() {
+            // the queued words are still counted in `remaining_params`
+            if self.in_fifo.len() < self.remaining_params as usize {
+                self.in_fifo.push_back(input);
+                return;
+            }
+            // a new command, it has to wait for the current one
+            self.decode_queued(None);
+        }
+
         // receiveing params
         if self.current_cmd.is_some() {
             self.handle_current_cmd(input);
@@ -574,23 +668,59 @@ impl Mdec {
 
         // reset MDEC
         if (data >> 31) & 1 != 0 {
-            // clear everything and set `current_block` to 4
+            // abort the command, clear everything and set `current_block` to 4
             self.status = MdecStatus::from_bits_retain(0x80040000);
+            self.current_cmd = None;
+            self.remaining_params = 0;
+            self.in_fifo.clear();
+            self.out_fifo.clear();
+            self.decode_cycles = 0;
         }
 
         // enable data in request
-        if (data >> 30) & 1 != 0 {
-            self.status.insert(MdecStatus::DATA_IN_REQUEST);
-        }
-
+        self.data_in_enabled = (data >> 30) & 1 != 0;
         // enable data out request
-        if (data >> 29) & 1 != 0 {
-            self.status.insert(MdecStatus::DATA_OUT_REQUEST);
-        }
+        self.data_out_enabled = (data >> 29) & 1 != 0;
     }
 }
 
 impl Mdec {
+    pub fn set_timing(&mut self, timing: MdecTiming) {
+        self.timing = timing;
+        if timing == MdecTiming::Instant {
+            self.decode_queued(None);
+        }
+    }
+
+    /// Decode the blocks that can be done in `cpu_cycles`
+    pub fn clock(&mut self, cpu_cycles: u32) {
+        if !self.in_fifo.is_empty() {
+            self.decode_queued(Some(cpu_cycles));
+        }
+    }
+
+    /// Can the data-in DMA send `words` now, or does it have to wait for the
+    /// decoder
+    pub fn can_receive(&self, words: usize) -> bool {
+        self.timing == MdecTiming::Instant
+            || self.in_fifo.is_empty()
+            || self.in_fifo.len() + words <= IN_FIFO_WORDS
+    }
+
+    /// Can the data-out DMA read `words` now, or does it have to wait for the
+    /// decoder. When nothing is being decoded, the missing words are garbage.
+    pub fn can_send(&self, words: usize) -> bool {
+        if self.timing == MdecTiming::Instant || !self.decoding() {
+            return true;
+        }
+        let available: usize = self
+            .out_fifo
+            .iter()
+            .map(|block| block.size - block.state.index)
+            .sum();
+        available >= words
+    }
+
     pub fn read_fifo(&mut self) -> u32 {
         if let Some(block) = self.out_fifo.front_mut() {
             let out = block.data[block.state.index];
@@ -641,3 +771,207 @@ impl BusLine for Mdec {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A DMA block, the usual size games use
+    const DMA_BLOCK_WORDS: usize = 0x20;
+
+    /// `DecodeMacroBlock` commands of random macroblocks, for the output depth `depth`
+    fn fmv_stream(depth: u32, macroblocks: usize, seed: &mut u32) -> Vec<u32> {
+        let mut random = || {
+            *seed ^= *seed << 13;
+            *seed ^= *seed >> 17;
+            *seed ^= *seed << 5;
+            *seed
+        };
+
+        let mut halfwords = Vec::new();
+        for _ in 0..macroblocks * 6 {
+            // q_scale is never 0, so the first halfword is never 0 either
+            let q_scale = (random() % 63 + 1) as u16;
+            halfwords.push((q_scale << 10) | (random() & 0x3FF) as u16);
+            for _ in 0..random() % 8 {
+                let run = (random() % 6) as u16;
+                halfwords.push((run << 10) | (random() & 0x3FF) as u16);
+            }
+            halfwords.push(0xFE00);
+        }
+        if halfwords.len() % 2 != 0 {
+            halfwords.push(0xFE00);
+        }
+
+        let mut stream = vec![0x20000000 | (depth << 27) | (halfwords.len() / 2) as u32];
+        stream.extend(
+            halfwords
+                .chunks(2)
+                .map(|h| h[0] as u32 | ((h[1] as u32) << 16)),
+        );
+        stream
+    }
+
+    /// Feed `stream` like the data-in DMA and drain the output like the data-out DMA,
+    /// returns the `(block_type, index, data)` of every output word
+    fn decode(timing: MdecTiming, stream: &[u32], cycles_per_step: u32) -> Vec<(u8, usize, u32)> {
+        let mut mdec = Mdec::default();
+        mdec.set_timing(timing);
+        mdec.write_control(0xE0000000);
+
+        let mut input = stream.iter().copied().peekable();
+        let mut out = Vec::new();
+        loop {
+            if input.peek().is_some() && mdec.can_receive(DMA_BLOCK_WORDS) {
+                for word in input.by_ref().take(DMA_BLOCK_WORDS) {
+                    mdec.write_command_params(word);
+                }
+            }
+            mdec.clock(cycles_per_step);
+            while !mdec.out_fifo.is_empty() {
+                let state = mdec.fifo_current_state();
+                out.push((state.block_type as u8, state.index, mdec.read_fifo()));
+            }
+            if input.peek().is_none() && !mdec.decoding() {
+                break;
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn accurate_output_same_as_instant() {
+        let mut seed = 0x12345678;
+        for depth in 0..4 {
+            let mut stream = fmv_stream(depth, 40, &mut seed);
+            // two commands back to back
+            stream.extend(fmv_stream(depth, 3, &mut seed));
+
+            let instant = decode(MdecTiming::Instant, &stream, 100);
+            let accurate = decode(MdecTiming::Accurate, &stream, 100);
+            assert!(!instant.is_empty());
+            assert_eq!(instant, accurate, "depth {}", depth);
+        }
+    }
+
+    #[test]
+    fn decode_rate() {
+        let mut seed = 1;
+        let stream = fmv_stream(2, 2, &mut seed);
+        assert!(stream.len() < IN_FIFO_WORDS);
+
+        let mut mdec = Mdec::default();
+        mdec.write_control(0x60000000);
+        for &word in &stream {
+            mdec.write_command_params(word);
+        }
+        // nothing is decoded yet
+        assert!(mdec.out_fifo.is_empty());
+        assert!(!mdec.can_send(1));
+        assert_eq!(mdec.read_status() & MdecStatus::DATA_OUT_REQUEST.bits(), 0);
+
+        // Cr, Cb and Y1
+        mdec.clock(DECODE_CYCLES_PER_BLOCK as u32 * 3);
+        assert_eq!(mdec.out_fifo.len(), 1);
+        assert!(mdec.can_send(48));
+        assert!(!mdec.can_send(49));
+        assert_ne!(mdec.read_status() & MdecStatus::DATA_OUT_REQUEST.bits(), 0);
+
+        mdec.clock(DECODE_CYCLES_PER_BLOCK as u32);
+        assert_eq!(mdec.out_fifo.len(), 2);
+
+        // waiting for data doesn't make the decoding faster later
+        mdec.clock(DECODE_CYCLES_PER_BLOCK as u32 * 100);
+        assert_eq!(mdec.out_fifo.len(), 8);
+        assert!(!mdec.decoding());
+        assert_eq!(mdec.decode_cycles, 0);
+    }
+
+    #[test]
+    fn data_in_waits_for_decoder() {
+        let mut seed = 2;
+        let stream = fmv_stream(3, 60, &mut seed);
+        assert!(stream.len() > IN_FIFO_WORDS);
+
+        let mut mdec = Mdec::default();
+        mdec.write_control(0x60000000);
+        let mut sent = 0;
+        while mdec.can_receive(DMA_BLOCK_WORDS) {
+            for &word in &stream[sent..sent + DMA_BLOCK_WORDS] {
+                mdec.write_command_params(word);
+            }
+            sent += DMA_BLOCK_WORDS;
+        }
+        // the command word isn't queued
+        assert_eq!(sent, IN_FIFO_WORDS);
+        assert_eq!(mdec.in_fifo.len(), IN_FIFO_WORDS - 1);
+        assert_eq!(mdec.read_status() & MdecStatus::DATA_IN_FIFO_FULL.bits(), 0);
+
+        mdec.write_command_params(stream[sent]);
+        assert_ne!(mdec.read_status() & MdecStatus::DATA_IN_FIFO_FULL.bits(), 0);
+        assert_eq!(mdec.read_status() & MdecStatus::DATA_IN_REQUEST.bits(), 0);
+
+        mdec.clock(DECODE_CYCLES_PER_BLOCK as u32 * 24);
+        assert!(mdec.can_receive(DMA_BLOCK_WORDS));
+    }
+
+    /// Compares the longest time of a step of the emulation while decoding FMV frames,
+    /// run with `cargo test --release mdec_frame_time -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn mdec_frame_time() {
+        // 320x240 in 15bit mode
+        const MACROBLOCKS: usize = 20 * 15;
+        const FRAMES: usize = 30;
+        // one scanline
+        const CYCLES_PER_STEP: u32 = 2150;
+
+        let mut seed = 3;
+        let frames: Vec<Vec<u32>> = (0..FRAMES)
+            .map(|_| fmv_stream(3, MACROBLOCKS, &mut seed))
+            .collect();
+
+        for timing in [MdecTiming::Instant, MdecTiming::Accurate] {
+            let mut mdec = Mdec::default();
+            mdec.set_timing(timing);
+            mdec.write_control(0x60000000);
+
+            let mut worst = std::time::Duration::ZERO;
+            let mut steps = 0;
+            let start = std::time::Instant::now();
+            for frame in &frames {
+                let mut input = frame.chunks(DMA_BLOCK_WORDS).peekable();
+                while input.peek().is_some() || mdec.decoding() {
+                    let step_start = std::time::Instant::now();
+                    // the DMA runs until the step is over or it has to wait
+                    let mut dma_cycles = 0;
+                    while dma_cycles < CYCLES_PER_STEP {
+                        let Some(block) = input.peek() else {
+                            break;
+                        };
+                        if !mdec.can_receive(block.len()) {
+                            break;
+                        }
+                        for &word in *block {
+                            mdec.write_command_params(word);
+                        }
+                        dma_cycles += block.len() as u32;
+                        input.next();
+                    }
+                    mdec.clock(CYCLES_PER_STEP);
+                    while !mdec.out_fifo.is_empty() {
+                        std::hint::black_box(mdec.read_fifo());
+                    }
+                    worst = worst.max(step_start.elapsed());
+                    steps += 1;
+                }
+            }
+            println!(
+                "{:?}: worst step {:?}, average step {:?}",
+                timing,
+                worst,
+                start.elapsed() / steps
+            );
+        }
+    }
+}
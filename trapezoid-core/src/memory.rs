@@ -1,17 +1,20 @@
+mod bios_info;
+pub(crate) mod bus_map;
 mod dma;
+mod exe;
 mod expansion_regions;
 pub(crate) mod hw_registers;
 pub(crate) mod interrupts;
 mod memory_control;
+mod open_bus;
 mod ram;
 
+use std::borrow::Cow;
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::Read;
 use std::path::Path;
-use std::sync::Arc;
 
-use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
-use vulkano::device::{Device, Queue};
+use byteorder::{ByteOrder, LittleEndian};
 
 use crate::cdrom::Cdrom;
 use crate::controller_mem_card::ControllerAndMemoryCard;
@@ -19,67 +22,141 @@ use crate::cpu::CpuBusProvider;
 use crate::gpu::Gpu;
 use crate::mdec::Mdec;
 use crate::spu::Spu;
+use crate::state_hash::StateHasher;
 use crate::timers::Timers;
+use crate::trace::Tracer;
+use crate::warnings::Warnings;
 use crate::{PsxConfig, PsxError};
 
+pub use bios_info::BiosInfo;
+pub use bios_info::BIOS_SIZE;
+use bus_map::{Access, BusDevice};
+#[cfg(test)]
+pub(crate) use exe::tests::build_exe;
+pub use exe::PsxExe;
+
 use dma::Dma;
 use expansion_regions::{ExpansionRegion1, ExpansionRegion2};
 use interrupts::Interrupts;
-use memory_control::{CacheControl, MemoryControl1, MemoryControl2};
+use memory_control::{CacheControl, MemoryControl1, MemoryControl2, RamWindow};
+use open_bus::BusWord;
+pub use open_bus::OpenBus;
+pub use ram::RamSize;
 use ram::{MainRam, Scratchpad};
 
-pub type Result<T, E = String> = std::result::Result<T, E>;
+pub type Result<T, E = BusError> = std::result::Result<T, E>;
+
+/// An error from accessing the bus, `size` is the width of the access in bits
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BusError {
+    UnalignedAccess {
+        addr: u32,
+        size: u8,
+    },
+    /// Nothing is mapped at this address
+    UnmappedRead {
+        addr: u32,
+        size: u8,
+    },
+    /// Nothing is mapped at this address
+    UnmappedWrite {
+        addr: u32,
+        size: u8,
+        value: u32,
+    },
+    /// The device doesn't support this access
+    DeviceError {
+        device: &'static str,
+        detail: Cow<'static, str>,
+    },
+}
+
+impl std::error::Error for BusError {}
+impl std::fmt::Display for BusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BusError::UnalignedAccess { addr, size } => {
+                write!(f, "Unaligned memory access: u{} at {:08X}", size, addr)
+            }
+            // the `u32` accesses have always been prefixed, keep the same messages
+            BusError::UnmappedRead { addr, size: 32 } => {
+                write!(f, "MainBus: u32 read from {:08X}", addr)
+            }
+            BusError::UnmappedWrite { addr, size: 32, .. } => {
+                write!(f, "MainBus: u32 write to {:08X}", addr)
+            }
+            BusError::UnmappedRead { addr, size } => write!(f, "u{} read from {:08X}", size, addr),
+            BusError::UnmappedWrite { addr, size, .. } => {
+                write!(f, "u{} write to {:08X}", size, addr)
+            }
+            BusError::DeviceError { device, detail } => write!(f, "{}: {}", device, detail),
+        }
+    }
+}
+
+impl BusError {
+    fn unsupported<T: ?Sized>(detail: String) -> Self {
+        BusError::DeviceError {
+            device: std::any::type_name::<T>(),
+            detail: detail.into(),
+        }
+    }
+}
 
 pub trait BusLine {
     fn read_u32(&mut self, addr: u32) -> Result<u32> {
-        Err(format!(
-            "{}: u32 read from {:08X}",
-            std::any::type_name::<Self>(),
+        Err(BusError::unsupported::<Self>(format!(
+            "u32 read from {:08X}",
             addr
-        ))
+        )))
     }
 
     fn write_u32(&mut self, addr: u32, _data: u32) -> Result<()> {
-        Err(format!(
-            "{}: u32 write to {:08X}",
-            std::any::type_name::<Self>(),
+        Err(BusError::unsupported::<Self>(format!(
+            "u32 write to {:08X}",
             addr
-        ))
+        )))
     }
 
     fn read_u16(&mut self, addr: u32) -> Result<u16> {
-        Err(format!(
-            "{}: u16 read from {:08X}",
-            std::any::type_name::<Self>(),
+        Err(BusError::unsupported::<Self>(format!(
+            "u16 read from {:08X}",
             addr
-        ))
+        )))
     }
     fn write_u16(&mut self, addr: u32, _data: u16) -> Result<()> {
-        Err(format!(
-            "{}: u16 write to {:08X}",
-            std::any::type_name::<Self>(),
+        Err(BusError::unsupported::<Self>(format!(
+            "u16 write to {:08X}",
             addr
-        ))
+        )))
     }
 
     fn read_u8(&mut self, addr: u32) -> Result<u8> {
-        Err(format!(
-            "{}: u8 read from {:08X}",
-            std::any::type_name::<Self>(),
+        Err(BusError::unsupported::<Self>(format!(
+            "u8 read from {:08X}",
             addr
-        ))
+        )))
     }
     fn write_u8(&mut self, addr: u32, _data: u8) -> Result<()> {
-        Err(format!(
-            "{}: u8 write to {:08X}",
-            std::any::type_name::<Self>(),
+        Err(BusError::unsupported::<Self>(format!(
+            "u8 write to {:08X}",
             addr
-        ))
+        )))
+    }
+
+    /// 8bit write from the CPU, `word` is the whole register being stored,
+    /// and the byte to write is the lowest 8 bits.
+    ///
+    /// Devices on a 16bit databus (the SPU) use the rest of the word,
+    /// others just use [`BusLine::write_u8`].
+    fn write_u8_from_word(&mut self, addr: u32, word: u32) -> Result<()> {
+        self.write_u8(addr, word as u8)
     }
 }
 
 pub struct Bios {
     data: Vec<u8>,
+    info: BiosInfo,
 }
 
 impl Bios {
@@ -135,22 +212,68 @@ impl Bios {
 }
 
 impl Bios {
-    // TODO: produce a valid `Error` struct
     pub fn from_file<P: AsRef<Path>>(bios_file_path: P) -> Result<Self, PsxError> {
+        let path = bios_file_path.as_ref();
         let mut data = Vec::new();
 
-        let mut file = File::open(bios_file_path).map_err(|_| PsxError::CouldNotLoadBios)?;
+        let mut file = File::open(path).map_err(|e| {
+            PsxError::CouldNotLoadBios(format!("could not open {}: {}", path.display(), e))
+        })?;
+
+        file.read_to_end(&mut data).map_err(|e| {
+            PsxError::CouldNotLoadBios(format!("could not read {}: {}", path.display(), e))
+        })?;
+
+        Self::from_data(data)
+    }
+
+    /// Validate and identify the BIOS image, only the size is required to be
+    /// correct, unknown dumps are loaded with a warning
+    pub fn from_data(data: Vec<u8>) -> Result<Self, PsxError> {
+        if data.len() != BIOS_SIZE {
+            return Err(PsxError::CouldNotLoadBios(format!(
+                "wrong size {} bytes, expected {} bytes (512KB)",
+                data.len(),
+                BIOS_SIZE
+            )));
+        }
 
-        file.read_to_end(&mut data)
-            .map_err(|_| PsxError::CouldNotLoadBios)?;
+        let info = BiosInfo::from_data(&data);
+        if info.known {
+            log::info!(
+                "BIOS: version {}, region {:?}",
+                info.version.as_deref().unwrap_or("?"),
+                info.region.unwrap()
+            );
+        } else {
+            log::warn!(
+                "BIOS: unknown dump (crc32 {:08x}), detected version {:?}, region {:?}",
+                info.crc32,
+                info.version,
+                info.region
+            );
+        }
 
-        let mut s = Self { data };
+        let mut s = Self { data, info };
 
         s.apply_patches();
 
         Ok(s)
     }
 
+    /// An empty BIOS image, used when the kernel is emulated with `hle_bios`,
+    /// nothing should run from here.
+    pub fn empty() -> Self {
+        Self {
+            data: vec![0; BIOS_SIZE],
+            info: BiosInfo::hle(),
+        }
+    }
+
+    pub fn info(&self) -> &BiosInfo {
+        &self.info
+    }
+
     pub fn read_u32(&self, addr: u32) -> Result<u32> {
         let index = (addr & 0xFFFFF) as usize;
 
@@ -170,6 +293,19 @@ impl Bios {
     }
 }
 
+/// `KSEG1` is the uncached mirror of the physical memory
+#[inline(always)]
+fn is_kseg1(addr: u32) -> bool {
+    addr >> 29 == 5
+}
+
+/// `addr` is in the scratchpad, through `KUSEG` or `KSEG0`, the only segments
+/// that reach it
+#[inline(always)]
+pub(crate) fn is_scratchpad_address(addr: u32) -> bool {
+    matches!(addr >> 29, 0 | 4) && (addr & 0x1FFFFFFF).wrapping_sub(0x1F800000) < 0x400
+}
+
 /// A structure that holds the elements of the emulator that the DMA can access
 ///
 /// These are the elements access by the channels:
@@ -180,7 +316,9 @@ impl Bios {
 /// 5- PIO
 /// 6- OTC (GPU)
 ///
-/// And also the main ram to write/read to/from.
+/// And also the main ram to write/read to/from. The scratchpad is not here,
+/// it's inside the CPU, and the DMA addresses are 24 bits, so they only reach
+/// the RAM and its mirrors.
 ///
 /// The reason for this design, is to be able to pass this structure `&mut`
 /// to `Dma` without problems of double mut.
@@ -209,85 +347,140 @@ pub struct CpuBus {
     dma_bus: DmaBus,
 
     scratchpad: Scratchpad,
+    /// The last value driven on the data bus, see [`OpenBus`]
+    last_bus_word: u32,
     config: PsxConfig,
+
+    tracer: Tracer,
+    warnings: Warnings,
 }
 
 impl CpuBus {
-    pub fn new<DiskPath: AsRef<Path>>(
-        bios: Bios,
-        disk_file: Option<DiskPath>,
-        config: PsxConfig,
-        device: Arc<Device>,
-        queue: Arc<Queue>,
-    ) -> Result<Self, PsxError> {
-        let mut s = Self {
+    pub fn new(bios: Bios, config: PsxConfig, mut gpu: Gpu) -> Self {
+        gpu.set_region_override(config.region_override);
+        let mut controller_mem_card = ControllerAndMemoryCard::default();
+        controller_mem_card.set_ack_timing(config.controller_ack_timing);
+        let mut mdec = Mdec::default();
+        mdec.set_timing(config.mdec_timing);
+        let mut spu = Spu::default();
+        spu.set_interpolation(config.spu_interpolation);
+
+        Self {
             bios,
             mem_ctrl_1: MemoryControl1::default(),
             mem_ctrl_2: MemoryControl2::default(),
             cache_control: CacheControl::default(),
             interrupts: Interrupts::default(),
-            controller_mem_card: ControllerAndMemoryCard::default(),
+            controller_mem_card,
 
             expansion_region_1: ExpansionRegion1::default(),
-            expansion_region_2: ExpansionRegion2::new(config),
+            expansion_region_2: ExpansionRegion2::new(config.stdout_debug),
             dma: Dma::default(),
 
             timers: Timers::default(),
 
             dma_bus: DmaBus {
-                cdrom: Cdrom::default(),
-                gpu: Gpu::new(device, queue),
-                main_ram: MainRam::default(),
-                mdec: Mdec::default(),
-                spu: Spu::default(),
+                cdrom: Cdrom::new(config.cdrom_seek_timing),
+                gpu,
+                main_ram: MainRam::new(config.ram_size),
+                mdec,
+                spu,
             },
 
             scratchpad: Scratchpad::default(),
+            last_bus_word: 0,
             config,
-        };
 
-        // TODO: handle errors in loading
-        if let Some(disk_file) = disk_file {
-            let path = disk_file.as_ref().to_owned();
-            // if this is an exe file
-            match path
-                .extension()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .to_ascii_lowercase()
-                .as_str()
-            {
-                "cue" => s.dma_bus.cdrom.set_cue_file(disk_file)?,
-                _ => {
-                    return Err(PsxError::DiskTypeNotSupported);
-                }
-            }
+            tracer: Tracer::default(),
+            warnings: Warnings::default(),
         }
-
-        Ok(s)
     }
 
-    pub fn reset(&mut self) {
+    /// Fails if the disk can't be loaded again, the rest is still reset
+    pub fn reset(&mut self) -> Result<(), PsxError> {
         self.mem_ctrl_1 = MemoryControl1::default();
         self.mem_ctrl_2 = MemoryControl2::default();
         self.cache_control = CacheControl::default();
         self.interrupts = Interrupts::default();
+        // the latch mode is a host setting, keep it
+        let input_latch_mode = self.controller_mem_card.input_latch_mode();
         self.controller_mem_card = ControllerAndMemoryCard::default();
+        self.controller_mem_card
+            .set_input_latch_mode(input_latch_mode);
+        self.controller_mem_card
+            .set_ack_timing(self.config.controller_ack_timing);
 
         self.expansion_region_1 = ExpansionRegion1::default();
-        self.expansion_region_2 = ExpansionRegion2::new(self.config);
+        self.expansion_region_2 = ExpansionRegion2::new(self.config.stdout_debug);
         self.dma = Dma::default();
 
         self.timers = Timers::default();
 
-        self.dma_bus.cdrom.reset();
+        let cdrom_result = self.dma_bus.cdrom.reset();
         self.dma_bus.gpu.reset();
-        self.dma_bus.main_ram = MainRam::default();
+        self.dma_bus.main_ram = MainRam::new(self.config.ram_size);
         self.dma_bus.mdec = Mdec::default();
-        self.dma_bus.spu = Spu::default();
+        self.dma_bus.mdec.set_timing(self.config.mdec_timing);
+        self.dma_bus.spu.reset(self.config.keep_spu_ram_on_reset);
 
         self.scratchpad = Scratchpad::default();
+        self.last_bus_word = 0;
+
+        // the components were recreated, so give them the tracer and warnings again
+        self.set_tracer(self.tracer.clone());
+        self.set_warnings(self.warnings.clone());
+        cdrom_result
+    }
+
+    /// Apply the parts of the config that can change while running, the rest
+    /// is used at the next reset
+    pub(crate) fn set_config(&mut self, config: PsxConfig) {
+        self.expansion_region_2
+            .set_stdout_debug(config.stdout_debug);
+        self.dma_bus.cdrom.set_seek_timing(config.cdrom_seek_timing);
+        self.dma_bus.gpu.set_region_override(config.region_override);
+        self.controller_mem_card
+            .set_ack_timing(config.controller_ack_timing);
+        self.dma_bus.mdec.set_timing(config.mdec_timing);
+        self.dma_bus.spu.set_interpolation(config.spu_interpolation);
+        self.config = config;
+    }
+
+    pub(crate) fn set_tracer(&mut self, tracer: Tracer) {
+        self.interrupts.set_tracer(tracer.clone());
+        self.dma.set_tracer(tracer.clone());
+        self.dma_bus.cdrom.set_tracer(tracer.clone());
+        self.dma_bus.gpu.set_tracer(tracer.clone());
+        self.dma_bus.spu.set_tracer(tracer.clone());
+        self.tracer = tracer;
+    }
+
+    pub(crate) fn set_warnings(&mut self, warnings: Warnings) {
+        self.controller_mem_card.set_warnings(warnings.clone());
+        self.dma.set_warnings(warnings.clone());
+        self.dma_bus.cdrom.set_warnings(warnings.clone());
+        self.dma_bus.spu.set_warnings(warnings.clone());
+        self.warnings = warnings;
+    }
+
+    /// The memories and the registers of the devices that affect the emulation,
+    /// see [`Psx::state_hash`](crate::Psx::state_hash)
+    pub(crate) fn hash_state(&self, hasher: &mut StateHasher) {
+        self.dma_bus.main_ram.hash_state(hasher);
+        self.scratchpad.hash_state(hasher);
+        self.dma_bus.spu.hash_state(hasher);
+        self.interrupts.hash_state(hasher);
+        self.timers.hash_state(hasher);
+        self.controller_mem_card.hash_state(hasher);
+        self.dma_bus.gpu.hash_state(hasher);
+    }
+
+    pub(crate) fn warnings(&self) -> &Warnings {
+        &self.warnings
+    }
+
+    pub fn pending_interrupts_flags(&self) -> u16 {
+        self.interrupts.pending_interrupts_flags()
     }
 
     pub fn gpu(&self) -> &Gpu {
@@ -306,52 +499,89 @@ impl CpuBus {
         &self.dma_bus.spu
     }
 
+    pub fn bios(&self) -> &Bios {
+        &self.bios
+    }
+
     pub fn spu_mut(&mut self) -> &mut Spu {
         &mut self.dma_bus.spu
     }
 
+    pub fn cdrom(&self) -> &Cdrom {
+        &self.dma_bus.cdrom
+    }
+
     pub fn cdrom_mut(&mut self) -> &mut Cdrom {
         &mut self.dma_bus.cdrom
     }
+
+    pub fn tty_output(&self) -> &str {
+        self.expansion_region_2.tty_output()
+    }
 }
 
 impl CpuBus {
-    // TODO: handle errors
-    //
-    /// Returns the metadata of the loaded exe
-    pub fn load_exe_in_memory<P: AsRef<Path>>(&mut self, exe_file_path: P) -> (u32, u32, u32) {
-        let mut file = File::open(exe_file_path).unwrap();
-        let mut magic = [0; 8];
-        let mut data = Vec::new();
+    pub fn load_exe(&mut self, exe: &PsxExe) {
+        exe.load(&mut self.dma_bus.main_ram);
+    }
 
-        file.read_exact(&mut magic).unwrap();
-        assert!(&magic == b"PS-X EXE");
-        // bytes from 0x8 to 0xF
-        assert!(file.read_u64::<LittleEndian>().unwrap() == 0);
-
-        let initial_pc = file.read_u32::<LittleEndian>().unwrap();
-        let initial_gp = file.read_u32::<LittleEndian>().unwrap();
-        let destination = file.read_u32::<LittleEndian>().unwrap();
-        let file_size = file.read_u32::<LittleEndian>().unwrap();
-        let _data_section_start = file.read_u32::<LittleEndian>().unwrap();
-        let _data_section_size = file.read_u32::<LittleEndian>().unwrap();
-        let _bss_section_start = file.read_u32::<LittleEndian>().unwrap();
-        let _bss_section_size = file.read_u32::<LittleEndian>().unwrap();
-        let mut initial_sp_fp = file.read_u32::<LittleEndian>().unwrap();
-        initial_sp_fp += file.read_u32::<LittleEndian>().unwrap();
-        // ascii marker and zero filled data
-        file.seek(SeekFrom::Start(0x800)).unwrap();
-
-        file.read_to_end(&mut data).unwrap();
-
-        assert!(data.len() == file_size as usize);
-
-        // put the data at the correct location in ram
-        self.dma_bus
-            .main_ram
-            .put_at_address(&data, destination & 0x1FFFFF);
+    /// Read from main RAM through the `RAM_SIZE` window, `size` is in bits
+    fn read_ram<T: BusWord>(
+        &mut self,
+        addr: u32,
+        size: u8,
+        read: impl FnOnce(&mut MainRam, u32) -> Result<T>,
+    ) -> Result<T> {
+        match self.mem_ctrl_2.ram_window(addr) {
+            RamWindow::Mapped(offset) => read(&mut self.dma_bus.main_ram, offset),
+            // nothing drives the data lines
+            RamWindow::HighZ => Ok(T::from_bus(open_bus::floating_value(
+                addr,
+                self.last_bus_word,
+            ))),
+            RamWindow::Locked => Err(BusError::UnmappedRead { addr, size }),
+        }
+    }
+
+    /// Write to main RAM through the `RAM_SIZE` window, `size` is in bits
+    fn write_ram(
+        &mut self,
+        addr: u32,
+        size: u8,
+        value: u32,
+        write: impl FnOnce(&mut MainRam, u32) -> Result<()>,
+    ) -> Result<()> {
+        match self.mem_ctrl_2.ram_window(addr) {
+            RamWindow::Mapped(offset) => write(&mut self.dma_bus.main_ram, offset),
+            RamWindow::HighZ => Ok(()),
+            RamWindow::Locked => Err(BusError::UnmappedWrite { addr, size, value }),
+        }
+    }
+
+    /// No device answers the read at the physical `addr`, see [`OpenBus`]
+    fn unmapped_read<T: BusWord>(&self, addr: u32) -> Result<T> {
+        let value = match self.config.open_bus {
+            OpenBus::Lenient => open_bus::open_bus_value(addr, self.last_bus_word),
+            OpenBus::Strict => None,
+        };
+        match value {
+            Some(value) => {
+                log::info!("open bus u{} read from {:08X}", T::BITS, addr);
+                Ok(T::from_bus(value))
+            }
+            None => Err(BusError::UnmappedRead {
+                addr,
+                size: T::BITS,
+            }),
+        }
+    }
 
-        (initial_pc, initial_gp, initial_sp_fp)
+    /// Keep `value` on the byte lanes of the access at `addr`, for [`OpenBus`] reads
+    #[inline(always)]
+    fn drive_bus<T: BusWord>(&mut self, addr: u32, value: T) -> T {
+        self.last_bus_word =
+            open_bus::drive_lanes(self.last_bus_word, addr, T::BITS, value.to_bus());
+        value
     }
 
     /// Since DMA is running using the CPU resources, we should run it and
@@ -360,11 +590,20 @@ impl CpuBus {
         self.dma.clock_dma(&mut self.dma_bus, &mut self.interrupts)
     }
 
+    pub(crate) fn dma_perf_counters_mut(&mut self) -> &mut crate::perf::DmaCounters {
+        self.dma.perf_counters_mut()
+    }
+
     pub fn clock_components(&mut self, cpu_cycles: u32) {
-        let (dot_clocks, hblank_clock) = self.dma_bus.gpu.clock(&mut self.interrupts, cpu_cycles);
+        let video_clocks = self.dma_bus.gpu.clock(&mut self.interrupts, cpu_cycles);
+        if video_clocks.vblank_started {
+            self.controller_mem_card.vblank();
+        }
 
         self.dma_bus.spu.clock(&mut self.interrupts, cpu_cycles);
 
+        self.dma_bus.mdec.clock(cpu_cycles);
+
         // controller and mem card
         self.controller_mem_card
             .clock(&mut self.interrupts, cpu_cycles);
@@ -375,15 +614,32 @@ impl CpuBus {
             .clock(&mut self.interrupts, &mut self.dma_bus.spu, cpu_cycles);
 
         // timers
+        self.timers.set_in_vblank(self.dma_bus.gpu.in_vblank());
         self.timers.clock_from_system(cpu_cycles);
-        if hblank_clock {
-            self.timers.clock_from_hblank();
-        }
-        self.timers.clock_from_gpu_dot(dot_clocks);
+        self.timers.clock_from_hblank(video_clocks.hblanks);
+        self.timers.clock_from_gpu_dot(video_clocks.dot_clocks);
         // interrupts for the timers
         self.timers.handle_interrupts(&mut self.interrupts);
     }
 
+    fn map_error(detail: &'static str) -> BusError {
+        BusError::DeviceError {
+            device: "MainBus",
+            detail: Cow::Borrowed(detail),
+        }
+    }
+
+    /// The device for the physical `addr`.
+    ///
+    /// The scratchpad is the D-cache inside the CPU, so only the cached segments
+    /// can reach it. Through `KSEG1` the access goes out to the bus, where
+    /// nothing is mapped at its address.
+    #[inline(always)]
+    fn lookup(addr: u32, uncached: bool, access: Access) -> Option<(BusDevice, u32)> {
+        bus_map::lookup(addr, access)
+            .filter(|(device, _)| !(uncached && *device == BusDevice::Scratchpad))
+    }
+
     // implement the PSX memory map
     // Note that `addr >= 0xFFFE0000` point to the cache control registers and isn't changed
     fn map_address(&self, addr: u32) -> Result<u32> {
@@ -393,20 +649,14 @@ impl CpuBus {
         match region {
             // KUSEG mirror of KSEG0/KSEG1
             0 => Ok(addr & MASK_512M),
-            1..=3 => Err(String::from("Accessing bottom 1.5G of KUSEG")),
+            1..=3 => Err(Self::map_error("Accessing bottom 1.5G of KUSEG")),
             // KSEG0
             4 => Ok(addr & MASK_512M),
-            // KSEG1
-            5 => {
-                if (0xBF800000..0xBF801000).contains(&addr) {
-                    Err(String::from("Cannot access scratchpad from KSEG1"))
-                } else {
-                    Ok(addr & MASK_512M)
-                }
-            }
+            // KSEG1, the scratchpad is handled in `lookup`
+            5 => Ok(addr & MASK_512M),
             // KSEG2
             7 if addr >= 0xFFFE0000 => Ok(addr), // no change
-            6 | 7 => Err(String::from(
+            6 | 7 => Err(Self::map_error(
                 "KSEG2 has only the cache control registers at 0xFFFE0000",
             )),
             _ => unreachable!(),
@@ -417,109 +667,198 @@ impl CpuBus {
 impl BusLine for CpuBus {
     fn read_u32(&mut self, addr: u32) -> Result<u32> {
         assert!(addr % 4 == 0, "unalligned u32 read");
+        let uncached = is_kseg1(addr);
         let addr = self.map_address(addr)?;
 
-        match addr {
+        let Some((device, offset)) = Self::lookup(addr, uncached, Access::READ_32) else {
+            return self.unmapped_read(addr);
+        };
+        let value = match device {
             // TODO: implement I-cache isolation properly
-            0x00000000..=0x007FFFFF => self.dma_bus.main_ram.read_u32(addr & 0x1FFFFF),
-            0x1FC00000..=0x1FC80000 => self.bios.read_u32(addr),
-            0x1F800000..=0x1F8003FF => self.scratchpad.read_u32(addr & 0x3FF),
-            0x1F801000..=0x1F801020 => self.mem_ctrl_1.read_u32(addr),
-            0x1F801044..=0x1F80104F => self.controller_mem_card.read_u32(addr & 0xF),
-            0x1F801060 => self.mem_ctrl_2.read_u32(addr),
-            0x1F801070..=0x1F801077 => self.interrupts.read_u32(addr & 0xF),
-            0x1F801080..=0x1F8010FC => self.dma.read_u32(addr & 0xFF),
-            0x1F801100..=0x1F80112F => self.timers.read_u32(addr & 0xFF),
-            0x1F801810..=0x1F801814 => self.dma_bus.gpu.read_u32(addr & 0xF),
-            0x1F801820..=0x1F801824 => self.dma_bus.mdec.read_u32(addr & 0xF),
-            0x1F801C00..=0x1F801FFC => self.dma_bus.spu.read_u32(addr & 0x3FF),
-            0x1F802000..=0x1F80208F => self.expansion_region_2.read_u32(addr & 0xFF),
-            0xFFFE0130 => self.cache_control.read_u32(addr),
-            _ => Err(format!("MainBus: u32 read from {:08X}", addr)),
-        }
+            BusDevice::MainRam => self.read_ram(addr, 32, |ram, addr| ram.read_u32(addr)),
+            BusDevice::Bios => self.bios.read_u32(offset),
+            BusDevice::Scratchpad => self.scratchpad.read_u32(offset),
+            BusDevice::MemoryControl1 => self.mem_ctrl_1.read_u32(offset),
+            BusDevice::ControllerMemCard => self.controller_mem_card.read_u32(offset),
+            BusDevice::MemoryControl2 => self.mem_ctrl_2.read_u32(offset),
+            BusDevice::Interrupts => self.interrupts.read_u32(offset),
+            BusDevice::Dma => self.dma.read_u32(offset),
+            BusDevice::Timers => self.timers.read_u32(offset),
+            BusDevice::Gpu => self.dma_bus.gpu.read_u32(offset),
+            BusDevice::Mdec => self.dma_bus.mdec.read_u32(offset),
+            BusDevice::Spu => self.dma_bus.spu.read_u32(offset),
+            BusDevice::ExpansionRegion2 => self.expansion_region_2.read_u32(offset),
+            BusDevice::CacheControl => self.cache_control.read_u32(offset),
+            BusDevice::ExpansionRegion1 | BusDevice::Cdrom => {
+                unreachable!("u32 reads are not in the bus map")
+            }
+        }?;
+        Ok(self.drive_bus(addr, value))
     }
 
     fn write_u32(&mut self, addr: u32, data: u32) -> Result<()> {
         assert!(addr % 4 == 0, "unalligned u32 write");
+        let uncached = is_kseg1(addr);
         let addr = self.map_address(addr)?;
-
-        match addr {
-            0x00000000..=0x007FFFFF => self.dma_bus.main_ram.write_u32(addr & 0x1FFFFF, data),
-            0x1F800000..=0x1F8003FF => self.scratchpad.write_u32(addr & 0x3FF, data),
-            0x1F801000..=0x1F801020 => self.mem_ctrl_1.write_u32(addr, data),
-            0x1F801060 => self.mem_ctrl_2.write_u32(addr, data),
-            0x1F801070..=0x1F801077 => self.interrupts.write_u32(addr & 0xF, data),
-            0x1F801080..=0x1F8010FC => self.dma.write_u32(addr & 0xFF, data),
-            0x1F801100..=0x1F80112F => self.timers.write_u32(addr & 0xFF, data),
-            0x1F801810..=0x1F801814 => self.dma_bus.gpu.write_u32(addr & 0xF, data),
-            0x1F801820..=0x1F801824 => self.dma_bus.mdec.write_u32(addr & 0xF, data),
-            0x1F801C00..=0x1F801FFC => self.dma_bus.spu.write_u32(addr & 0x3FF, data),
-            0x1F802000..=0x1F80208F => self.expansion_region_2.write_u32(addr & 0xFF, data),
-            0xFFFE0130 => self.cache_control.write_u32(addr, data),
-            _ => Err(format!("MainBus: u32 write to {:08X}", addr)),
+        self.drive_bus(addr, data);
+
+        let Some((device, offset)) = Self::lookup(addr, uncached, Access::WRITE_32) else {
+            return Err(BusError::UnmappedWrite {
+                addr,
+                size: 32,
+                value: data,
+            });
+        };
+        match device {
+            BusDevice::MainRam => {
+                self.write_ram(addr, 32, data, |ram, addr| ram.write_u32(addr, data))
+            }
+            BusDevice::Scratchpad => self.scratchpad.write_u32(offset, data),
+            BusDevice::MemoryControl1 => self.mem_ctrl_1.write_u32(offset, data),
+            BusDevice::MemoryControl2 => self.mem_ctrl_2.write_u32(offset, data),
+            BusDevice::Interrupts => self.interrupts.write_u32(offset, data),
+            BusDevice::Dma => self.dma.write_u32(offset, data),
+            BusDevice::Timers => self.timers.write_u32(offset, data),
+            BusDevice::Gpu => self.dma_bus.gpu.write_u32(offset, data),
+            BusDevice::Mdec => self.dma_bus.mdec.write_u32(offset, data),
+            BusDevice::Spu => self.dma_bus.spu.write_u32(offset, data),
+            BusDevice::ExpansionRegion2 => self.expansion_region_2.write_u32(offset, data),
+            BusDevice::CacheControl => self.cache_control.write_u32(offset, data),
+            BusDevice::ExpansionRegion1
+            | BusDevice::ControllerMemCard
+            | BusDevice::Cdrom
+            | BusDevice::Bios => unreachable!("u32 writes are not in the bus map"),
         }
     }
 
     fn read_u16(&mut self, addr: u32) -> Result<u16> {
         assert!(addr % 2 == 0, "unalligned u16 read");
+        let uncached = is_kseg1(addr);
         let addr = self.map_address(addr)?;
 
-        match addr {
-            0x00000000..=0x007FFFFF => self.dma_bus.main_ram.read_u16(addr & 0x1FFFFF),
-            0x1F800000..=0x1F8003FF => self.scratchpad.read_u16(addr & 0x3FF),
-            0x1F801044..=0x1F80104F => self.controller_mem_card.read_u16(addr & 0xF),
-            0x1F801070..=0x1F801077 => self.interrupts.read_u16(addr & 0xF),
-            0x1F801100..=0x1F80112F => self.timers.read_u16(addr & 0xFF),
-            0x1F801C00..=0x1F801FFC => self.dma_bus.spu.read_u16(addr & 0x3FF),
-            0x1FC00000..=0x1FC80000 => self.bios.read_u16(addr),
-            0x1F802000..=0x1F80208F => self.expansion_region_2.read_u16(addr & 0xFF),
-            _ => Err(format!("u16 read from {:08X}", addr)),
-        }
+        let Some((device, offset)) = Self::lookup(addr, uncached, Access::READ_16) else {
+            return self.unmapped_read(addr);
+        };
+        let value = match device {
+            BusDevice::MainRam => self.read_ram(addr, 16, |ram, addr| ram.read_u16(addr)),
+            BusDevice::Scratchpad => self.scratchpad.read_u16(offset),
+            BusDevice::ControllerMemCard => self.controller_mem_card.read_u16(offset),
+            BusDevice::Interrupts => self.interrupts.read_u16(offset),
+            BusDevice::Timers => self.timers.read_u16(offset),
+            BusDevice::Spu => self.dma_bus.spu.read_u16(offset),
+            BusDevice::Bios => self.bios.read_u16(offset),
+            BusDevice::ExpansionRegion2 => self.expansion_region_2.read_u16(offset),
+            BusDevice::ExpansionRegion1
+            | BusDevice::MemoryControl1
+            | BusDevice::MemoryControl2
+            | BusDevice::Dma
+            | BusDevice::Cdrom
+            | BusDevice::Gpu
+            | BusDevice::Mdec
+            | BusDevice::CacheControl => unreachable!("u16 reads are not in the bus map"),
+        }?;
+        Ok(self.drive_bus(addr, value))
     }
 
     fn write_u16(&mut self, addr: u32, data: u16) -> Result<()> {
         assert!(addr % 2 == 0, "unalligned u16 write");
+        let uncached = is_kseg1(addr);
         let addr = self.map_address(addr)?;
-
-        match addr {
-            0x00000000..=0x007FFFFF => self.dma_bus.main_ram.write_u16(addr & 0x1FFFFF, data),
-            0x1F800000..=0x1F8003FF => self.scratchpad.write_u16(addr & 0x3FF, data),
-            0x1F801048..=0x1F80104F => self.controller_mem_card.write_u16(addr & 0xF, data),
-            0x1F801070..=0x1F801077 => self.interrupts.write_u16(addr & 0xF, data),
-            0x1F801100..=0x1F80112F => self.timers.write_u16(addr & 0xFF, data),
-            0x1F801C00..=0x1F801FFC => self.dma_bus.spu.write_u16(addr & 0x3FF, data),
-            0x1F802000..=0x1F80208F => self.expansion_region_2.write_u16(addr & 0xFF, data),
-            _ => Err(format!("u16 write to {:08X}", addr)),
+        self.drive_bus(addr, data);
+
+        let Some((device, offset)) = Self::lookup(addr, uncached, Access::WRITE_16) else {
+            return Err(BusError::UnmappedWrite {
+                addr,
+                size: 16,
+                value: data as u32,
+            });
+        };
+        match device {
+            BusDevice::MainRam => {
+                self.write_ram(addr, 16, data as u32, |ram, addr| ram.write_u16(addr, data))
+            }
+            BusDevice::Scratchpad => self.scratchpad.write_u16(offset, data),
+            BusDevice::ControllerMemCard => self.controller_mem_card.write_u16(offset, data),
+            BusDevice::Interrupts => self.interrupts.write_u16(offset, data),
+            BusDevice::Timers => self.timers.write_u16(offset, data),
+            BusDevice::Spu => self.dma_bus.spu.write_u16(offset, data),
+            BusDevice::ExpansionRegion2 => self.expansion_region_2.write_u16(offset, data),
+            BusDevice::ExpansionRegion1
+            | BusDevice::MemoryControl1
+            | BusDevice::MemoryControl2
+            | BusDevice::Dma
+            | BusDevice::Cdrom
+            | BusDevice::Gpu
+            | BusDevice::Mdec
+            | BusDevice::Bios
+            | BusDevice::CacheControl => unreachable!("u16 writes are not in the bus map"),
         }
     }
+
     fn read_u8(&mut self, addr: u32) -> Result<u8> {
+        let uncached = is_kseg1(addr);
         let addr = self.map_address(addr)?;
 
-        match addr {
-            0x00000000..=0x007FFFFF => self.dma_bus.main_ram.read_u8(addr & 0x1FFFFF),
-            0x1F800000..=0x1F8003FF => self.scratchpad.read_u8(addr & 0x3FF),
-            0x1F801040 => self.controller_mem_card.read_u8(addr & 0xF),
-            0x1F000000..=0x1F080000 => self.expansion_region_1.read_u8(addr & 0xFFFFF),
-            0x1F801080..=0x1F8010FF => self.dma.read_u8(addr & 0xFF),
-            0x1F801800..=0x1F801803 => self.dma_bus.cdrom.read_u8(addr & 3),
-            0x1F802000..=0x1F80208F => self.expansion_region_2.read_u8(addr & 0xFF),
-            0x1FC00000..=0x1FC80000 => self.bios.read_u8(addr),
-            _ => Err(format!("u8 read from {:08X}", addr)),
-        }
+        let Some((device, offset)) = Self::lookup(addr, uncached, Access::READ_8) else {
+            return self.unmapped_read(addr);
+        };
+        let value = match device {
+            BusDevice::MainRam => self.read_ram(addr, 8, |ram, addr| ram.read_u8(addr)),
+            BusDevice::Scratchpad => self.scratchpad.read_u8(offset),
+            BusDevice::ControllerMemCard => self.controller_mem_card.read_u8(offset),
+            BusDevice::ExpansionRegion1 => self.expansion_region_1.read_u8(offset),
+            BusDevice::Dma => self.dma.read_u8(offset),
+            BusDevice::Cdrom => self.dma_bus.cdrom.read_u8(offset),
+            BusDevice::ExpansionRegion2 => self.expansion_region_2.read_u8(offset),
+            BusDevice::Bios => self.bios.read_u8(offset),
+            BusDevice::MemoryControl1
+            | BusDevice::MemoryControl2
+            | BusDevice::Interrupts
+            | BusDevice::Timers
+            | BusDevice::Gpu
+            | BusDevice::Mdec
+            | BusDevice::Spu
+            | BusDevice::CacheControl => unreachable!("u8 reads are not in the bus map"),
+        }?;
+        Ok(self.drive_bus(addr, value))
     }
 
     fn write_u8(&mut self, addr: u32, data: u8) -> Result<()> {
-        let addr = self.map_address(addr)?;
+        self.write_u8_from_word(addr, data as u32)
+    }
 
-        match addr {
-            0x00000000..=0x007FFFFF => self.dma_bus.main_ram.write_u8(addr & 0x1FFFFF, data),
-            0x1F800000..=0x1F8003FF => self.scratchpad.write_u8(addr & 0x3FF, data),
-            0x1F801040 => self.controller_mem_card.write_u8(addr & 0xF, data),
-            0x1F000000..=0x1F080000 => self.expansion_region_1.write_u8(addr & 0xFFFFF, data),
-            0x1F801080..=0x1F8010FF => self.dma.write_u8(addr & 0xFF, data),
-            0x1F801800..=0x1F801803 => self.dma_bus.cdrom.write_u8(addr & 3, data),
-            0x1F802000..=0x1F80208F => self.expansion_region_2.write_u8(addr & 0xFF, data),
-            _ => Err(format!("u8 write to {:08X}", addr)),
+    fn write_u8_from_word(&mut self, addr: u32, word: u32) -> Result<()> {
+        let uncached = is_kseg1(addr);
+        let addr = self.map_address(addr)?;
+        let data = word as u8;
+        self.drive_bus(addr, data);
+
+        let Some((device, offset)) = Self::lookup(addr, uncached, Access::WRITE_8) else {
+            return Err(BusError::UnmappedWrite {
+                addr,
+                size: 8,
+                value: data as u32,
+            });
+        };
+        match device {
+            BusDevice::MainRam => {
+                self.write_ram(addr, 8, data as u32, |ram, addr| ram.write_u8(addr, data))
+            }
+            BusDevice::Scratchpad => self.scratchpad.write_u8(offset, data),
+            BusDevice::ControllerMemCard => self.controller_mem_card.write_u8(offset, data),
+            BusDevice::ExpansionRegion1 => self.expansion_region_1.write_u8(offset, data),
+            BusDevice::Dma => self.dma.write_u8(offset, data),
+            BusDevice::Cdrom => self.dma_bus.cdrom.write_u8(offset, data),
+            BusDevice::Spu => self.dma_bus.spu.write_u8_from_word(offset, word),
+            BusDevice::ExpansionRegion2 => self.expansion_region_2.write_u8(offset, data),
+            BusDevice::MemoryControl1
+            | BusDevice::MemoryControl2
+            | BusDevice::Interrupts
+            | BusDevice::Timers
+            | BusDevice::Gpu
+            | BusDevice::Mdec
+            | BusDevice::Bios
+            | BusDevice::CacheControl => unreachable!("u8 writes are not in the bus map"),
         }
     }
 }
@@ -533,3 +872,485 @@ impl CpuBusProvider for CpuBus {
         self.dma.needs_to_run()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NoAccess;
+    impl BusLine for NoAccess {}
+
+    #[test]
+    fn unsupported_access() {
+        let err = NoAccess.write_u16(0x10, 0).unwrap_err();
+        assert!(matches!(
+            err,
+            BusError::DeviceError { device, .. } if device.ends_with("NoAccess")
+        ));
+        assert!(err.to_string().ends_with("NoAccess: u16 write to 00000010"));
+    }
+
+    #[test]
+    fn bios_size_validation() {
+        for size in [0, 0x40000, BIOS_SIZE + 1] {
+            let err = Bios::from_data(vec![0; size]).err().unwrap();
+            assert!(
+                matches!(err, PsxError::CouldNotLoadBios(ref reason) if reason.contains("wrong size"))
+            );
+        }
+
+        let bios = Bios::from_data(vec![0; BIOS_SIZE]).unwrap();
+        assert!(!bios.info().known);
+        assert_eq!(bios.read_u32(0x1FC00000).unwrap(), 0);
+
+        let err = Bios::from_file("/nonexistent/bios.bin").err().unwrap();
+        assert!(
+            matches!(err, PsxError::CouldNotLoadBios(ref reason) if reason.contains("could not open"))
+        );
+    }
+
+    /// Strict, so that the unmapped reads are errors
+    #[cfg(feature = "gpu-tests")]
+    fn new_bus() -> CpuBus {
+        new_bus_with(OpenBus::Strict)
+    }
+
+    #[cfg(feature = "gpu-tests")]
+    fn new_bus_with(open_bus: OpenBus) -> CpuBus {
+        let (device, queue) = crate::create_headless_device().unwrap();
+        let config = PsxConfig::builder().open_bus(open_bus).build();
+        CpuBus::new(Bios::empty(), config, Gpu::new(device, queue))
+    }
+
+    /// Every access the bus map has for a window is routed to a device, and the
+    /// others are unmapped
+    #[test]
+    #[cfg(feature = "gpu-tests")]
+    fn bus_map_routing() {
+        let mut bus = new_bus();
+
+        // the mirrors reach the same RAM
+        bus.write_u32(0x00001000, 0x12345678).unwrap();
+        assert_eq!(bus.read_u32(0x80001000).unwrap(), 0x12345678);
+        assert_eq!(bus.read_u16(0xA0001002).unwrap(), 0x1234);
+        // between the windows
+        assert!(matches!(
+            bus.read_u32(0x1F801030),
+            Err(BusError::UnmappedRead { .. })
+        ));
+
+        let widths = [
+            (8, Access::READ_8, Access::WRITE_8),
+            (16, Access::READ_16, Access::WRITE_16),
+            (32, Access::READ_32, Access::WRITE_32),
+        ];
+        for window in bus_map::BUS_MAP {
+            // through `KSEG0`, if it's mirrored
+            let addr = if window.base < 0x20000000 {
+                window.base | 0x80000000
+            } else {
+                window.base
+            };
+
+            for (bits, read, write) in widths {
+                let result = match bits {
+                    8 => bus.read_u8(addr).map(|_| ()),
+                    16 => bus.read_u16(addr).map(|_| ()),
+                    _ => bus.read_u32(addr).map(|_| ()),
+                };
+                assert_eq!(
+                    matches!(result, Err(BusError::UnmappedRead { .. })),
+                    !window.access.contains(read),
+                    "u{} read from {:08X}: {:?}",
+                    bits,
+                    addr,
+                    result
+                );
+
+                let result = match bits {
+                    8 => bus.write_u8(addr, 0),
+                    16 => bus.write_u16(addr, 0),
+                    _ => bus.write_u32(addr, 0),
+                };
+                assert_eq!(
+                    matches!(result, Err(BusError::UnmappedWrite { .. })),
+                    !window.access.contains(write),
+                    "u{} write to {:08X}: {:?}",
+                    bits,
+                    addr,
+                    result
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn scratchpad_segments() {
+        assert!(is_scratchpad_address(0x1F800000));
+        assert!(is_scratchpad_address(0x9F8003FF));
+        assert!(!is_scratchpad_address(0xBF800000));
+        assert!(!is_scratchpad_address(0x1F800400));
+        assert!(!is_scratchpad_address(0x1F7FFFFC));
+        assert!(!is_scratchpad_address(0x3F800000));
+        assert!(!is_scratchpad_address(0xFF800000));
+    }
+
+    #[test]
+    #[cfg(feature = "gpu-tests")]
+    fn scratchpad_access() {
+        let mut bus = new_bus();
+
+        // all the widths, through `KUSEG` and `KSEG0`
+        bus.write_u32(0x1F800000, 0x12345678).unwrap();
+        assert_eq!(bus.read_u32(0x9F800000).unwrap(), 0x12345678);
+        assert_eq!(bus.read_u16(0x9F800002).unwrap(), 0x1234);
+        assert_eq!(bus.read_u8(0x1F800001).unwrap(), 0x56);
+        bus.write_u16(0x9F800002, 0xABCD).unwrap();
+        bus.write_u8(0x1F800000, 0xEF).unwrap();
+        assert_eq!(bus.read_u32(0x1F800000).unwrap(), 0xABCD56EF);
+
+        // the last word, and nothing after it
+        bus.write_u32(0x9F8003FC, 0xAABBCCDD).unwrap();
+        assert_eq!(bus.read_u8(0x9F8003FF).unwrap(), 0xAA);
+        assert_eq!(bus.read_u16(0x1F8003FE).unwrap(), 0xAABB);
+        assert!(matches!(
+            bus.read_u32(0x9F800400),
+            Err(BusError::UnmappedRead { .. })
+        ));
+        assert_eq!(bus.read_u32(0x9F800000).unwrap(), 0xABCD56EF);
+
+        // `KSEG1` goes out to the bus
+        assert!(matches!(
+            bus.read_u32(0xBF800000),
+            Err(BusError::UnmappedRead { .. })
+        ));
+        assert!(matches!(
+            bus.write_u8(0xBF8003FF, 0),
+            Err(BusError::UnmappedWrite { .. })
+        ));
+        assert_eq!(bus.read_u8(0x9F8003FF).unwrap(), 0xAA);
+    }
+
+    #[test]
+    #[cfg(feature = "gpu-tests")]
+    fn open_bus_reads() {
+        let mut bus = new_bus_with(OpenBus::Lenient);
+
+        // the expansion regions echo the address
+        assert_eq!(bus.read_u32(0x9F080000).unwrap(), 0x1F080000);
+        assert_eq!(bus.read_u16(0xBFA01232).unwrap(), 0x1232);
+        assert_eq!(bus.read_u8(0x1F802101).unwrap(), 0x01);
+
+        // the rest of the I/O area reads the last value on the bus
+        bus.write_u32(0x80001000, 0x12345678).unwrap();
+        assert_eq!(bus.read_u32(0x1F801030).unwrap(), 0x12345678);
+        assert_eq!(bus.read_u16(0x1F801032).unwrap(), 0x1234);
+        assert_eq!(bus.read_u32(0xBF800000).unwrap(), 0x12345678);
+        // reads drive the bus too, in their byte lanes
+        bus.write_u32(0x80001000, 0xAABBCCDD).unwrap();
+        bus.write_u32(0x80001004, 0).unwrap();
+        assert_eq!(bus.read_u8(0x80001001).unwrap(), 0xCC);
+        assert_eq!(bus.read_u32(0x1F801030).unwrap(), 0x0000CC00);
+
+        // the high-Z part of the RAM window doesn't drive the bus either
+        bus.write_u32(0x1F801060, 0xC88).unwrap(); // `RAM_SIZE`, 2MB + 2MB high-Z
+        bus.write_u32(0x80001000, 0x55667788).unwrap();
+        assert_eq!(bus.read_u32(0x80200000).unwrap(), 0x55667788);
+
+        // the rest still faults
+        assert!(matches!(
+            bus.read_u32(0x80800000),
+            Err(BusError::UnmappedRead { .. })
+        ));
+        assert!(matches!(
+            bus.read_u32(0xFFFE0000),
+            Err(BusError::UnmappedRead { .. })
+        ));
+        assert!(bus.read_u32(0x20000000).is_err());
+
+        // strict fails in all of them
+        bus.set_config(PsxConfig::builder().open_bus(OpenBus::Strict).build());
+        for addr in [0x9F080000, 0x1F801030, 0xBF800000] {
+            assert!(
+                matches!(bus.read_u32(addr), Err(BusError::UnmappedRead { .. })),
+                "{:08X}",
+                addr
+            );
+        }
+    }
+
+    /// The DMA addresses are 24 bits, so a scratchpad address reaches a RAM mirror
+    #[test]
+    #[cfg(feature = "gpu-tests")]
+    fn dma_cannot_reach_scratchpad() {
+        let mut bus = new_bus();
+        bus.write_u32(0x1F80000C, 0x11111111).unwrap();
+        bus.write_u32(0x1F800010, 0x22222222).unwrap();
+
+        // OTC (channel 6), 4 entries ending at the scratchpad address
+        bus.write_u32(0x1F8010F0, 0x0F654321).unwrap();
+        bus.write_u32(0x1F8010E0, 0x1F800010).unwrap();
+        bus.write_u32(0x1F8010E4, 4).unwrap();
+        bus.write_u32(0x1F8010E8, 0x11000002).unwrap();
+        assert!(bus.should_run_dma());
+        bus.clock_dma();
+
+        assert_eq!(bus.read_u32(0x1F80000C).unwrap(), 0x11111111);
+        assert_eq!(bus.read_u32(0x1F800010).unwrap(), 0x22222222);
+        assert_eq!(bus.read_u32(0x80000010).unwrap(), 0x0080000C);
+        assert_eq!(bus.read_u32(0x80000004).unwrap(), 0x00FFFFFF);
+    }
+
+    /// `SPUCNT` transfer modes
+    #[cfg(feature = "gpu-tests")]
+    const SPU_DMA_WRITE: u16 = 2;
+    #[cfg(feature = "gpu-tests")]
+    const SPU_DMA_READ: u16 = 3;
+
+    /// Set the SPU transfer `mode`, and the transfer address to `spu_addr` (in bytes)
+    #[cfg(feature = "gpu-tests")]
+    fn spu_transfer(bus: &mut CpuBus, mode: u16, spu_addr: u32) {
+        bus.write_u16(0x1F801DAA, 0x8000 | (mode << 4)).unwrap();
+        bus.write_u16(0x1F801DAC, 2 << 1).unwrap();
+        bus.write_u16(0x1F801DA6, (spu_addr / 8) as u16).unwrap();
+    }
+
+    /// Start the SPU channel (4) and run it with the components until it finishes,
+    /// returns the words of each step, and fails if it's still running after `max_steps`
+    #[cfg(feature = "gpu-tests")]
+    fn run_spu_dma(bus: &mut CpuBus, madr: u32, bcr: u32, chcr: u32, max_steps: usize) -> Vec<u32> {
+        bus.write_u32(0x1F8010F0, 0x000B0000).unwrap();
+        bus.write_u32(0x1F8010C0, madr).unwrap();
+        bus.write_u32(0x1F8010C4, bcr).unwrap();
+        bus.write_u32(0x1F8010C8, chcr).unwrap();
+
+        let mut steps = Vec::new();
+        for _ in 0..max_steps {
+            if bus.read_u32(0x1F8010C8).unwrap() & 0x01000000 == 0 {
+                return steps;
+            }
+            let words = bus.clock_dma();
+            if words != 0 {
+                steps.push(words);
+            }
+            // one SPU clock, to raise the request again
+            bus.clock_components(0x300);
+        }
+        panic!("the transfer is still running, steps so far: {:?}", steps);
+    }
+
+    /// The words of the SPU RAM at `spu_addr`, read through the data port
+    #[cfg(feature = "gpu-tests")]
+    fn read_spu_ram(bus: &mut CpuBus, spu_addr: u32, words: usize) -> Vec<u32> {
+        spu_transfer(bus, SPU_DMA_READ, spu_addr);
+        (0..words)
+            .map(|_| {
+                let low = bus.read_u16(0x1F801DA8).unwrap() as u32;
+                let high = bus.read_u16(0x1F801DA8).unwrap() as u32;
+                high << 16 | low
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "gpu-tests")]
+    fn fill_ram(bus: &mut CpuBus, addr: u32, words: u32, seed: u32) -> Vec<u32> {
+        (0..words)
+            .map(|i| {
+                let word = (i + seed).wrapping_mul(0x9E3779B9);
+                bus.write_u32(addr + i * 4, word).unwrap();
+                word
+            })
+            .collect()
+    }
+
+    /// Upload and read back with the block sizes games use, including odd ones
+    #[test]
+    #[cfg(feature = "gpu-tests")]
+    fn spu_dma_blocks() {
+        for (block_size, blocks) in [(1, 1), (16, 4), (3, 5), (7, 3), (0x10, 0x10)] {
+            let mut bus = new_bus();
+            let words = block_size * blocks;
+            let data = fill_ram(&mut bus, 0x1000, words, block_size);
+
+            spu_transfer(&mut bus, SPU_DMA_WRITE, 0x2000);
+            let steps = run_spu_dma(&mut bus, 0x1000, blocks << 16 | block_size, 0x01000201, 100);
+            assert_eq!(steps, vec![block_size; blocks as usize]);
+            // the address is after the last block, and no blocks are left
+            assert_eq!(bus.read_u32(0x1F8010C0).unwrap(), 0x1000 + words * 4);
+            assert_eq!(bus.read_u32(0x1F8010C4).unwrap(), block_size);
+            let spu_ram = read_spu_ram(&mut bus, 0x2000, words as usize + 1);
+            assert_eq!(spu_ram[..words as usize], data);
+            assert_eq!(spu_ram[words as usize], 0);
+
+            // and back to RAM, to another address
+            spu_transfer(&mut bus, SPU_DMA_READ, 0x2000);
+            let steps = run_spu_dma(&mut bus, 0x8000, blocks << 16 | block_size, 0x01000200, 100);
+            assert_eq!(steps, vec![block_size; blocks as usize]);
+            for (i, &word) in data.iter().enumerate() {
+                assert_eq!(
+                    bus.read_u32(0x8000 + i as u32 * 4).unwrap(),
+                    word,
+                    "{}x{} [{}]",
+                    block_size,
+                    blocks,
+                    i
+                );
+            }
+            assert_eq!(bus.read_u32(0x8000 + words * 4).unwrap(), 0);
+        }
+    }
+
+    /// Sync mode 0 transfers everything at once, and keeps the registers
+    #[test]
+    #[cfg(feature = "gpu-tests")]
+    fn spu_dma_sync_mode_0() {
+        let mut bus = new_bus();
+        let data = fill_ram(&mut bus, 0x1000, 9, 0);
+
+        spu_transfer(&mut bus, SPU_DMA_WRITE, 0x400);
+        assert_eq!(run_spu_dma(&mut bus, 0x1000, 9, 0x11000001, 10), [9]);
+        assert_eq!(bus.read_u32(0x1F8010C0).unwrap(), 0x1000);
+        assert_eq!(bus.read_u32(0x1F8010C4).unwrap(), 9);
+        let spu_ram = read_spu_ram(&mut bus, 0x400, 10);
+        assert_eq!(spu_ram[..9], data);
+        assert_eq!(spu_ram[9], 0);
+
+        // 0 is the maximum, half of the SPU RAM
+        spu_transfer(&mut bus, SPU_DMA_WRITE, 0);
+        assert_eq!(run_spu_dma(&mut bus, 0x1000, 0, 0x11000001, 10), [0x10000]);
+        assert_eq!(read_spu_ram(&mut bus, 0, 9), data);
+    }
+
+    /// The SPU transfer address continues from the end of the last transfer
+    #[test]
+    #[cfg(feature = "gpu-tests")]
+    fn spu_dma_back_to_back() {
+        let mut bus = new_bus();
+        let first = fill_ram(&mut bus, 0x1000, 6, 0);
+        let second = fill_ram(&mut bus, 0x3000, 5, 100);
+
+        spu_transfer(&mut bus, SPU_DMA_WRITE, 0x1000);
+        run_spu_dma(&mut bus, 0x1000, 0x0002_0003, 0x01000201, 10);
+        // without setting the transfer address again
+        run_spu_dma(&mut bus, 0x3000, 0x0001_0005, 0x01000201, 10);
+        assert_eq!(read_spu_ram(&mut bus, 0x1000, 11), [first, second].concat());
+
+        // the same for reads, backwards in RAM
+        spu_transfer(&mut bus, SPU_DMA_READ, 0x1000);
+        run_spu_dma(&mut bus, 0x601C, 0x0002_0002, 0x01000202, 10);
+        run_spu_dma(&mut bus, 0x600C, 0x0001_0004, 0x01000202, 10);
+        let read_back = (0..8)
+            .map(|i| bus.read_u32(0x601C - i * 4).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(read_back, read_spu_ram(&mut bus, 0x1000, 8));
+        assert_eq!(bus.read_u32(0x5FFC).unwrap(), 0);
+    }
+
+    /// The DMA waits while the SPU requests the other direction, without
+    /// changing the SPU state
+    #[test]
+    #[cfg(feature = "gpu-tests")]
+    fn spu_dma_direction_mismatch() {
+        let mut bus = new_bus();
+        let data = fill_ram(&mut bus, 0x1000, 4, 0);
+
+        spu_transfer(&mut bus, SPU_DMA_READ, 0x1000);
+        bus.write_u32(0x1F8010F0, 0x000B0000).unwrap();
+        bus.write_u32(0x1F8010C0, 0x1000).unwrap();
+        bus.write_u32(0x1F8010C4, 0x0001_0004).unwrap();
+        bus.write_u32(0x1F8010C8, 0x01000201).unwrap();
+        for _ in 0..4 {
+            bus.clock_components(0x300);
+            assert_eq!(bus.clock_dma(), 0);
+        }
+        assert_ne!(bus.read_u32(0x1F8010C8).unwrap() & 0x01000000, 0);
+        // not busy, nothing was transferred
+        assert_eq!(bus.read_u16(0x1F801DAE).unwrap() & 0x400, 0);
+
+        // the request comes on the next SPU clock
+        bus.write_u16(0x1F801DAA, 0x8000 | (SPU_DMA_WRITE << 4))
+            .unwrap();
+        assert_eq!(bus.clock_dma(), 0);
+        bus.clock_components(0x300);
+        assert_eq!(bus.clock_dma(), 4);
+        assert_eq!(bus.read_u32(0x1F8010C8).unwrap() & 0x01000000, 0);
+        assert_eq!(read_spu_ram(&mut bus, 0x1000, 4), data);
+    }
+
+    /// Measures the time of the bus accesses, run with
+    /// `cargo test --release --features gpu-tests bus_access_speed -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    #[cfg(feature = "gpu-tests")]
+    fn bus_access_speed() {
+        const ACCESSES: u32 = 10_000_000;
+        let mut bus = new_bus();
+
+        let cases: [(&str, u32); 4] = [
+            ("RAM", 0x80010000),
+            ("scratchpad", 0x1F800100),
+            ("I_STAT", 0x1F801070),
+            ("BIOS", 0xBFC00100),
+        ];
+        for (name, base) in cases {
+            let start = std::time::Instant::now();
+            let mut sum = 0u32;
+            for i in 0..ACCESSES {
+                sum = sum.wrapping_add(bus.read_u32(base + (i & 1) * 4).unwrap());
+            }
+            let elapsed = start.elapsed();
+            std::hint::black_box(sum);
+            println!(
+                "{:>10}: {:.2} ns/read",
+                name,
+                elapsed.as_nanos() as f64 / ACCESSES as f64
+            );
+        }
+    }
+
+    #[test]
+    fn error_messages() {
+        assert_eq!(
+            BusError::UnmappedRead {
+                addr: 0x1F802100,
+                size: 8
+            }
+            .to_string(),
+            "u8 read from 1F802100"
+        );
+        assert_eq!(
+            BusError::UnmappedWrite {
+                addr: 0x1F801200,
+                size: 32,
+                value: 0x1234
+            }
+            .to_string(),
+            "MainBus: u32 write to 1F801200"
+        );
+        assert_eq!(
+            BusError::UnmappedRead {
+                addr: 0x1F801200,
+                size: 32
+            }
+            .to_string(),
+            "MainBus: u32 read from 1F801200"
+        );
+        assert_eq!(
+            BusError::UnmappedWrite {
+                addr: 0x1F801200,
+                size: 16,
+                value: 0x1234
+            }
+            .to_string(),
+            "u16 write to 1F801200"
+        );
+        assert_eq!(
+            BusError::UnalignedAccess {
+                addr: 0x80010002,
+                size: 32
+            }
+            .to_string(),
+            "Unaligned memory access: u32 at 80010002"
+        );
+    }
+}
@@ -0,0 +1,206 @@
+//! Identification of BIOS dumps, by checksum, or by the version string
+//! embedded in the ROM.
+
+use crate::Region;
+
+/// The size of all PSX BIOS ROMs
+pub const BIOS_SIZE: usize = 0x80000;
+
+/// Information about the loaded BIOS, see [`Psx::bios_info`](crate::Psx::bios_info)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BiosInfo {
+    /// `None` if the region couldn't be detected, or when running with `hle_bios`
+    pub region: Option<Region>,
+    /// The version, e.g. `"4.1"`
+    pub version: Option<String>,
+    /// The CRC32 of the ROM, before any patches are applied
+    pub crc32: u32,
+    /// Whether the dump matches a known good dump of a BIOS revision
+    pub known: bool,
+}
+
+struct KnownBios {
+    crc32: u32,
+    version: &'static str,
+    region: Region,
+}
+
+/// Common dumps of retail BIOS revisions
+const KNOWN_BIOSES: &[KnownBios] = &[
+    // SCPH-1000
+    KnownBios {
+        crc32: 0x3b601fc8,
+        version: "1.0",
+        region: Region::Japan,
+    },
+    // SCPH-1001
+    KnownBios {
+        crc32: 0x37157331,
+        version: "2.2",
+        region: Region::NorthAmerica,
+    },
+    // SCPH-5500
+    KnownBios {
+        crc32: 0xff3eeb8c,
+        version: "3.0",
+        region: Region::Japan,
+    },
+    // SCPH-5501
+    KnownBios {
+        crc32: 0x8d8cb7e4,
+        version: "3.0",
+        region: Region::NorthAmerica,
+    },
+    // SCPH-5502
+    KnownBios {
+        crc32: 0xd786f0b9,
+        version: "3.0",
+        region: Region::Europe,
+    },
+    // SCPH-7001
+    KnownBios {
+        crc32: 0x502224b6,
+        version: "4.1",
+        region: Region::NorthAmerica,
+    },
+    // SCPH-7502
+    KnownBios {
+        crc32: 0x318178bf,
+        version: "4.1",
+        region: Region::Europe,
+    },
+    // SCPH-101
+    KnownBios {
+        crc32: 0x171bdcec,
+        version: "4.5",
+        region: Region::NorthAmerica,
+    },
+];
+
+/// All retail BIOSes contain `System ROM Version <version> <date> <region letter>`
+const VERSION_STRING_PREFIX: &[u8] = b"System ROM Version ";
+
+impl BiosInfo {
+    /// Identify the BIOS image `data`, without loading it
+    pub fn from_data(data: &[u8]) -> Self {
+        Self::identify(data, crc32(data))
+    }
+
+    fn identify(data: &[u8], crc32: u32) -> Self {
+        if let Some(known) = KNOWN_BIOSES.iter().find(|b| b.crc32 == crc32) {
+            return Self {
+                region: Some(known.region),
+                version: Some(known.version.to_owned()),
+                crc32,
+                known: true,
+            };
+        }
+
+        let (version, region) = parse_version_string(data).unwrap_or((None, None));
+        Self {
+            region,
+            version,
+            crc32,
+            known: false,
+        }
+    }
+
+    /// The info of the empty BIOS used with `hle_bios`
+    pub(super) fn hle() -> Self {
+        Self {
+            region: None,
+            version: None,
+            crc32: 0,
+            known: false,
+        }
+    }
+}
+
+/// Parse the version and region from the first `System ROM Version` string
+fn parse_version_string(data: &[u8]) -> Option<(Option<String>, Option<Region>)> {
+    let start = data
+        .windows(VERSION_STRING_PREFIX.len())
+        .position(|w| w == VERSION_STRING_PREFIX)?
+        + VERSION_STRING_PREFIX.len();
+    let end = data[start..]
+        .iter()
+        .position(|&b| !(b.is_ascii_graphic() || b == b' '))
+        .map_or(data.len(), |len| start + len);
+    let string = std::str::from_utf8(&data[start..end]).ok()?;
+
+    let mut parts = string.split_whitespace();
+    let version = parts.next().map(str::to_owned);
+    let region = match parts.nth(1) {
+        Some("A") => Some(Region::NorthAmerica),
+        Some("E") => Some(Region::Europe),
+        Some("J") => Some(Region::Japan),
+        _ => None,
+    };
+
+    Some((version, region))
+}
+
+/// The standard CRC32 (as used by zip and most dump databases)
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB88320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bios_with_version_string(string: &[u8]) -> Vec<u8> {
+        let mut data = vec![0; BIOS_SIZE];
+        // where the string is in most revisions
+        data[0x7FF32..0x7FF32 + string.len()].copy_from_slice(string);
+        data
+    }
+
+    #[test]
+    fn crc32_values() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32(&[0; BIOS_SIZE]), 0x75660AAC);
+    }
+
+    #[test]
+    fn version_string_detection() {
+        let data = bios_with_version_string(b"System ROM Version 4.1 12/16/97 E");
+        let info = BiosInfo::from_data(&data);
+        assert_eq!(info.region, Some(Region::Europe));
+        assert_eq!(info.version.as_deref(), Some("4.1"));
+        assert!(!info.known);
+
+        let data = bios_with_version_string(b"System ROM Version 2.2 12/04/95 A");
+        let info = BiosInfo::from_data(&data);
+        assert_eq!(info.region, Some(Region::NorthAmerica));
+        assert_eq!(info.version.as_deref(), Some("2.2"));
+
+        let info = BiosInfo::from_data(&[0; BIOS_SIZE]);
+        assert_eq!(info.region, None);
+        assert_eq!(info.version, None);
+        assert!(!info.known);
+    }
+
+    #[test]
+    fn known_dumps_take_priority() {
+        let data = bios_with_version_string(b"System ROM Version 3.0 09/09/96 J");
+        let info = BiosInfo::from_data(&data);
+        assert!(!info.known);
+        assert_eq!(info.region, Some(Region::Japan));
+
+        // SCPH-7502, the checksum is trusted over the string
+        let info = BiosInfo::identify(&data, 0x318178bf);
+        assert!(info.known);
+        assert_eq!(info.region, Some(Region::Europe));
+        assert_eq!(info.version.as_deref(), Some("4.1"));
+        assert_eq!(info.crc32, 0x318178bf);
+    }
+}
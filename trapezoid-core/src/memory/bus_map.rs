@@ -0,0 +1,205 @@
+//! The physical memory map, the [`CpuBus`](super::CpuBus) dispatches with it,
+//! and [`describe`](crate::describe) reports it.
+
+use bitflags::bitflags;
+
+bitflags! {
+    /// The accesses a window supports, the others are unmapped
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub(crate) struct Access: u8 {
+        const READ_8   = 0b000001;
+        const READ_16  = 0b000010;
+        const READ_32  = 0b000100;
+        const WRITE_8  = 0b001000;
+        const WRITE_16 = 0b010000;
+        const WRITE_32 = 0b100000;
+
+        const READ_WRITE_8 = Self::READ_8.bits() | Self::WRITE_8.bits();
+        const READ_WRITE_32 = Self::READ_32.bits() | Self::WRITE_32.bits();
+        const READ_WRITE_16_32 = Self::READ_WRITE_32.bits()
+            | Self::READ_16.bits()
+            | Self::WRITE_16.bits();
+        const READ_ONLY = Self::READ_8.bits() | Self::READ_16.bits() | Self::READ_32.bits();
+        const ALL = Self::READ_ONLY.bits()
+            | Self::WRITE_8.bits()
+            | Self::WRITE_16.bits()
+            | Self::WRITE_32.bits();
+    }
+}
+
+/// The targets of the bus, a device can have more than one window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BusDevice {
+    MainRam,
+    ExpansionRegion1,
+    Scratchpad,
+    MemoryControl1,
+    ControllerMemCard,
+    MemoryControl2,
+    Interrupts,
+    Dma,
+    Timers,
+    Cdrom,
+    Gpu,
+    Mdec,
+    Spu,
+    ExpansionRegion2,
+    Bios,
+    CacheControl,
+}
+
+/// A range of physical addresses routed to a device, the device gets
+/// `addr & offset_mask`
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BusWindow {
+    pub base: u32,
+    pub size: u32,
+    pub device: BusDevice,
+    pub offset_mask: u32,
+    pub access: Access,
+}
+
+const fn window(
+    base: u32,
+    size: u32,
+    device: BusDevice,
+    offset_mask: u32,
+    access: Access,
+) -> BusWindow {
+    BusWindow {
+        base,
+        size,
+        device,
+        offset_mask,
+        access,
+    }
+}
+
+/// The whole address is passed to the device
+const FULL_ADDRESS: u32 = 0xFFFFFFFF;
+
+/// Sorted by `base`, and not overlapping.
+///
+/// The addresses are physical, after removing the `KUSEG`/`KSEG0`/`KSEG1`
+/// mirroring, see [`CpuBus::map_address`](super::CpuBus).
+#[rustfmt::skip]
+pub(crate) const BUS_MAP: &[BusWindow] = &[
+    // goes through the `RAM_SIZE` window, so it gets the whole address
+    window(0x00000000, 0x800000, BusDevice::MainRam,           FULL_ADDRESS, Access::ALL),
+    window(0x1F000000, 0x80000,  BusDevice::ExpansionRegion1,  0xFFFFF,      Access::READ_WRITE_8),
+    window(0x1F800000, 0x400,    BusDevice::Scratchpad,        0x3FF,        Access::ALL),
+    window(0x1F801000, 0x24,     BusDevice::MemoryControl1,    FULL_ADDRESS, Access::READ_WRITE_32),
+    // `JOY_DATA`
+    window(0x1F801040, 1,        BusDevice::ControllerMemCard, 0xF,          Access::READ_WRITE_8),
+    // `JOY_STAT`
+    window(0x1F801044, 4,        BusDevice::ControllerMemCard, 0xF,          Access::READ_16.union(Access::READ_32)),
+    // `JOY_MODE`, `JOY_CTRL` and `JOY_BAUD`
+    window(0x1F801048, 8,        BusDevice::ControllerMemCard, 0xF,          Access::READ_16.union(Access::WRITE_16)),
+    window(0x1F801060, 4,        BusDevice::MemoryControl2,    FULL_ADDRESS, Access::READ_WRITE_32),
+    window(0x1F801070, 8,        BusDevice::Interrupts,        0xF,          Access::READ_WRITE_16_32),
+    window(0x1F801080, 0x80,     BusDevice::Dma,               0xFF,         Access::READ_WRITE_32.union(Access::READ_WRITE_8)),
+    window(0x1F801100, 0x30,     BusDevice::Timers,            0xFF,         Access::READ_WRITE_16_32),
+    window(0x1F801800, 4,        BusDevice::Cdrom,             3,            Access::READ_WRITE_8),
+    window(0x1F801810, 8,        BusDevice::Gpu,               0xF,          Access::READ_WRITE_32),
+    window(0x1F801820, 8,        BusDevice::Mdec,              0xF,          Access::READ_WRITE_32),
+    // the 8bit writes use the whole register, see `BusLine::write_u8_from_word`
+    window(0x1F801C00, 0x400,    BusDevice::Spu,               0x3FF,        Access::READ_WRITE_16_32.union(Access::WRITE_8)),
+    window(0x1F802000, 0x90,     BusDevice::ExpansionRegion2,  0xFF,         Access::ALL),
+    window(0x1FC00000, 0x80000,  BusDevice::Bios,              FULL_ADDRESS, Access::READ_ONLY),
+    window(0xFFFE0130, 4,        BusDevice::CacheControl,      FULL_ADDRESS, Access::READ_WRITE_32),
+];
+
+/// The device and its offset for `addr` if it supports `access`
+#[inline(always)]
+pub(crate) fn lookup(addr: u32, access: Access) -> Option<(BusDevice, u32)> {
+    // most accesses are to the main RAM, the first window
+    let window = if addr < BUS_MAP[0].size {
+        &BUS_MAP[0]
+    } else {
+        let index = BUS_MAP.partition_point(|w| w.base <= addr).checked_sub(1)?;
+        let window = &BUS_MAP[index];
+        if addr - window.base >= window.size {
+            return None;
+        }
+        window
+    };
+
+    window
+        .access
+        .contains(access)
+        .then_some((window.device, addr & window.offset_mask))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorted_and_not_overlapping() {
+        assert_eq!(BUS_MAP[0].base, 0);
+        for w in BUS_MAP.windows(2) {
+            assert!(
+                w[0].base.checked_add(w[0].size).unwrap() <= w[1].base,
+                "{:?} overlaps {:?}",
+                w[0],
+                w[1]
+            );
+        }
+    }
+
+    #[test]
+    fn lookup_windows() {
+        let cases = [
+            (
+                0x00000000,
+                Access::WRITE_8,
+                Some((BusDevice::MainRam, 0x00000000)),
+            ),
+            (
+                0x007FFFFC,
+                Access::READ_32,
+                Some((BusDevice::MainRam, 0x007FFFFC)),
+            ),
+            (0x00800000, Access::READ_32, None),
+            (
+                0x1F8003FE,
+                Access::WRITE_16,
+                Some((BusDevice::Scratchpad, 0x3FE)),
+            ),
+            (
+                0x1F801040,
+                Access::READ_8,
+                Some((BusDevice::ControllerMemCard, 0)),
+            ),
+            (0x1F801040, Access::READ_32, None),
+            (
+                0x1F80104A,
+                Access::WRITE_16,
+                Some((BusDevice::ControllerMemCard, 0xA)),
+            ),
+            (0x1F801044, Access::WRITE_16, None),
+            (0x1F8010F4, Access::READ_32, Some((BusDevice::Dma, 0xF4))),
+            (0x1F801803, Access::WRITE_8, Some((BusDevice::Cdrom, 3))),
+            (0x1F801814, Access::READ_32, Some((BusDevice::Gpu, 4))),
+            (0x1F801814, Access::READ_16, None),
+            (0x1F801DAA, Access::WRITE_8, Some((BusDevice::Spu, 0x1AA))),
+            (0x1F801DAA, Access::READ_8, None),
+            (
+                0x1FC7FFFF,
+                Access::READ_8,
+                Some((BusDevice::Bios, 0x1FC7FFFF)),
+            ),
+            (0x1FC80000, Access::READ_8, None),
+            (0x1FC00000, Access::WRITE_32, None),
+            (
+                0xFFFE0130,
+                Access::WRITE_32,
+                Some((BusDevice::CacheControl, 0xFFFE0130)),
+            ),
+            (0xFFFFFFFC, Access::READ_32, None),
+        ];
+        for (addr, access, expected) in cases {
+            assert_eq!(lookup(addr, access), expected, "{:08X} {:?}", addr, access);
+        }
+    }
+}
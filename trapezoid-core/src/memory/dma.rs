@@ -1,9 +1,22 @@
 use crate::mdec;
 use crate::memory::Result;
+use crate::perf::DmaCounters;
+use crate::spu::Spu;
+use crate::trace::{TraceEvent, Tracer};
+use crate::warnings::Warnings;
 
 use super::interrupts::InterruptRequester;
+use super::ram::MainRam;
 use super::BusLine;
 
+// chrom transfer rate:
+// BIOS: 24 clk/word
+// GAMES: 40 clk/word
+//
+// Not sure exactly what is BIOS and what is GAMES, so for now, lets make
+// it 30 clk/word (around the middle).
+const CDROM_DMA_CYCLES_PER_WORD: u32 = 30;
+
 bitflags::bitflags! {
     #[derive(Default, Debug)]
     struct ChannelControl: u32 {
@@ -66,6 +79,9 @@ bitflags::bitflags! {
     }
 }
 
+/// Bits of `DICR` that are always zero
+const DMA_INTERRUPT_NOT_USED: u32 = 0b00000000000000000111111111000000;
+
 impl DmaInterruptRegister {
     #[inline]
     fn master_flag(&self) -> bool {
@@ -157,6 +173,10 @@ pub struct Dma {
     interrupt: DmaInterruptRegister,
 
     channels: [DmaChannel; 7],
+
+    tracer: Tracer,
+    warnings: Warnings,
+    perf: DmaCounters,
 }
 
 impl Default for Dma {
@@ -165,34 +185,58 @@ impl Default for Dma {
             control: 0x07654321,
             interrupt: Default::default(),
             channels: Default::default(),
+            tracer: Tracer::default(),
+            warnings: Warnings::default(),
+            perf: DmaCounters::default(),
         }
     }
 }
 
-/// All Dma handles are of type `fn(&mut DmaChannel, &mut super::DmaBus) -> (u32, bool)`
-/// The return values are `(The number of cpu cycles spent, Is dma finished)`
+/// All Dma handles take the channel and the parts of `super::DmaBus` they transfer with.
+/// The return values are
+/// `(The number of cpu cycles spent, The number of words transferred, Is dma finished)`
+///
+/// The channel control is checked with [`Dma::unsupported_control`] before.
 impl Dma {
+    /// Why the channel control written by the game can't be used for the channel,
+    /// the transfer is then skipped, as if it finished right away
+    fn unsupported_control(channel: usize, control: &ChannelControl) -> Option<&'static str> {
+        let from_ram = control.intersects(ChannelControl::DIRECTION_FROM_RAM);
+        let forward = control.address_step() == 4;
+        let chopping = control.intersects(ChannelControl::CHOPPING_ENABLED);
+        let sync_mode = control.sync_mode();
+
+        match channel {
+            _ if sync_mode == 3 => Some("reserved sync mode 3"),
+            0 if !from_ram => Some("MDEC in to RAM"),
+            1 if from_ram => Some("MDEC out from RAM"),
+            0 | 1 if !forward => Some("MDEC backward step"),
+            0 | 1 if sync_mode != 1 => Some("MDEC sync mode other than 1"),
+            0 | 1 if chopping => Some("MDEC chopping"),
+            2 if sync_mode == 0 => Some("GPU sync mode 0"),
+            2 if sync_mode == 2 && !forward => Some("GPU linked list backward step"),
+            3 if from_ram => Some("CDROM from RAM"),
+            3 if !forward => Some("CDROM backward step"),
+            3 if sync_mode != 0 => Some("CDROM sync mode other than 0"),
+            4 if sync_mode == 2 => Some("SPU linked list"),
+            4 if chopping => Some("SPU chopping"),
+            _ => None,
+        }
+    }
+
     fn perform_mdec_in_channel0_dma(
         channel: &mut DmaChannel,
         dma_bus: &mut super::DmaBus,
-    ) -> (u32, bool) {
-        // must be from main ram
-        assert!(channel
-            .channel_control
-            .intersects(ChannelControl::DIRECTION_FROM_RAM));
-        // must be forward
-        assert!(channel.channel_control.address_step() == 4);
-        // must be sync mode 1
-        assert!(channel.channel_control.sync_mode() == 1);
-        // chopping is only for sync mode 0
-        assert!(!channel
-            .channel_control
-            .intersects(ChannelControl::CHOPPING_ENABLED));
-
+    ) -> (u32, u32, bool) {
         // TODO: check if the max is 32 or not
         let block_size = channel.block_control & 0xFFFF;
         let blocks = channel.block_control >> 16;
 
+        // wait for the decoder to make room
+        if !dma_bus.mdec.can_receive(block_size as usize) {
+            return (0, 0, false);
+        }
+
         // word align
         let mut address = channel.base_address & 0xFFFFFC;
 
@@ -212,30 +256,22 @@ impl Dma {
         channel.block_control |= blocks << 16;
         channel.base_address = address;
 
-        (block_size, blocks == 0)
+        (block_size, block_size, blocks == 0)
     }
 
     fn perform_mdec_out_channel1_dma(
         channel: &mut DmaChannel,
         dma_bus: &mut super::DmaBus,
-    ) -> (u32, bool) {
-        // must be to main ram
-        assert!(!channel
-            .channel_control
-            .intersects(ChannelControl::DIRECTION_FROM_RAM));
-        // must be forward
-        assert!(channel.channel_control.address_step() == 4);
-        // must be sync mode 1
-        assert!(channel.channel_control.sync_mode() == 1);
-        // chopping is only for sync mode 0
-        assert!(!channel
-            .channel_control
-            .intersects(ChannelControl::CHOPPING_ENABLED));
-
+    ) -> (u32, u32, bool) {
         // TODO: check if the max is 32 or not
         let block_size = channel.block_control & 0xFFFF;
         let blocks = channel.block_control >> 16;
 
+        // wait for the decoder to have the whole block
+        if !dma_bus.mdec.can_send(block_size as usize) {
+            return (0, 0, false);
+        }
+
         // word align
         let mut address = channel.base_address & 0xFFFFFC;
 
@@ -302,13 +338,13 @@ impl Dma {
         channel.block_control |= blocks << 16;
         channel.base_address = address;
 
-        (block_size, blocks == 0)
+        (block_size, block_size, blocks == 0)
     }
 
     fn perform_gpu_channel2_dma(
         channel: &mut DmaChannel,
         dma_bus: &mut super::DmaBus,
-    ) -> (u32, bool) {
+    ) -> (u32, u32, bool) {
         // GPU channel
         match channel.channel_control.sync_mode() {
             1 => {
@@ -346,84 +382,86 @@ impl Dma {
                 channel.block_control |= blocks << 16;
                 channel.base_address = address;
 
-                (block_size, blocks == 0)
+                (block_size, block_size, blocks == 0)
             }
-            2 => {
-                // Linked list mode, to sending GP0 commands
-                assert!(channel.channel_control.address_step() == 4);
-                let mut linked_entry_addr = channel.base_address & 0xFFFFFC;
-
-                let mut linked_list_data = dma_bus.main_ram.read_u32(linked_entry_addr).unwrap();
-                let mut n_entries = linked_list_data >> 24;
-                // make sure the GPU can handle this entry
-                log::info!(
-                    "got {} entries, from data {:08X} located at address {:08X}",
-                    n_entries,
-                    linked_list_data,
-                    linked_entry_addr
-                );
-                // The GPU only support 16 enteries, but noticed some games
-                // use value higher than that. for us it doesn't really matter
-                // as we can manage any number
-                // assert!(n_entries < 16);
-
-                while n_entries == 0 && linked_list_data & 0xFFFFFF != 0xFFFFFF {
-                    linked_entry_addr = linked_list_data & 0xFFFFFC;
-                    linked_list_data = dma_bus.main_ram.read_u32(linked_entry_addr).unwrap();
-                    n_entries = linked_list_data >> 24;
-
-                    if n_entries != 0 {
-                        channel.base_address = linked_entry_addr & 0xFFFFFC;
-                        // return, so we can start again from the beginning
-                        // TODO: should we just continue?
-                        return (0, false);
-                    }
-
-                    log::trace!(
-                        "skipping: got {} entries, from data {:08X} located at address {:08X}",
-                        n_entries,
-                        linked_list_data,
-                        linked_entry_addr
-                    );
-                }
+            2 => Self::perform_gpu_linked_list(channel, &mut dma_bus.main_ram, |cmd| {
+                // gp0 command
+                // TODO: make sure that `gp1(04h)` is set to 2
+                dma_bus.gpu.write_u32(0, cmd).unwrap();
+            }),
+            // checked by `unsupported_control`
+            _ => unreachable!("{}", channel.channel_control.sync_mode()),
+        }
+    }
 
-                for i in 1..(n_entries + 1) {
-                    let cmd = dma_bus
-                        .main_ram
-                        .read_u32(linked_entry_addr + i * 4)
-                        .unwrap();
-                    // gp0 command
-                    // TODO: make sure that `gp1(04h)` is set to 2
-                    dma_bus.gpu.write_u32(0, cmd).unwrap();
-                }
+    /// Linked list mode, sends the GP0 commands of one entry to `gp0`,
+    /// the list ends with an entry pointing to `0xFFFFFF`
+    fn perform_gpu_linked_list(
+        channel: &mut DmaChannel,
+        main_ram: &mut MainRam,
+        mut gp0: impl FnMut(u32),
+    ) -> (u32, u32, bool) {
+        let mut linked_entry_addr = channel.base_address & 0xFFFFFC;
+
+        let mut linked_list_data = main_ram.read_u32(linked_entry_addr).unwrap();
+        let mut n_entries = linked_list_data >> 24;
+        // make sure the GPU can handle this entry
+        log::info!(
+            "got {} entries, from data {:08X} located at address {:08X}",
+            n_entries,
+            linked_list_data,
+            linked_entry_addr
+        );
+        // The GPU only support 16 enteries, but noticed some games
+        // use value higher than that. for us it doesn't really matter
+        // as we can manage any number
+        // assert!(n_entries < 16);
+
+        while n_entries == 0 && linked_list_data & 0xFFFFFF != 0xFFFFFF {
+            linked_entry_addr = linked_list_data & 0xFFFFFC;
+            linked_list_data = main_ram.read_u32(linked_entry_addr).unwrap();
+            n_entries = linked_list_data >> 24;
+
+            if n_entries != 0 {
+                channel.base_address = linked_entry_addr & 0xFFFFFC;
+                // return, so we can start again from the beginning
+                // TODO: should we just continue?
+                return (0, 0, false);
+            }
 
-                channel.base_address = linked_list_data & 0xFFFFFF;
+            log::trace!(
+                "skipping: got {} entries, from data {:08X} located at address {:08X}",
+                n_entries,
+                linked_list_data,
+                linked_entry_addr
+            );
+        }
 
-                (n_entries + 1, channel.base_address == 0xFFFFFF)
-            }
-            _ => unreachable!("{}", channel.channel_control.sync_mode()),
+        for i in 1..(n_entries + 1) {
+            let cmd = main_ram.read_u32(linked_entry_addr + i * 4).unwrap();
+            gp0(cmd);
         }
+
+        channel.base_address = linked_list_data & 0xFFFFFF;
+
+        // the header is read as well
+        (
+            n_entries + 1,
+            n_entries + 1,
+            channel.base_address == 0xFFFFFF,
+        )
     }
 
     fn perform_cdrom_channel3_dma(
         channel: &mut DmaChannel,
         dma_bus: &mut super::DmaBus,
-    ) -> (u32, bool) {
-        // must be to main ram
-        assert!(!channel
-            .channel_control
-            .intersects(ChannelControl::DIRECTION_FROM_RAM));
-        // must be forward
-        assert!(channel.channel_control.address_step() == 4);
-        // must be sync mode 0
-        assert!(channel.channel_control.sync_mode() == 0);
-
+    ) -> (u32, u32, bool) {
         // must be triggered manually
         if !channel
             .channel_control
             .intersects(ChannelControl::START_TRIGGER)
         {
-            return (0, true);
+            return (0, 0, true);
         }
 
         let chopping = channel
@@ -469,81 +507,72 @@ impl Dma {
             channel.base_address = address;
         }
 
-        // chrom transfer rate:
-        // BIOS: 24 clk/word
-        // GAMES: 40 clk/word
-        //
-        // Not sure exactly what is BIOS and what is GAMES, so for now, lets make
-        // it 30 clk/word (around the middle).
-        (block_size * 30, true)
+        (block_size * CDROM_DMA_CYCLES_PER_WORD, block_size, true)
     }
 
     fn perform_spu_channel4_dma(
         channel: &mut DmaChannel,
-        dma_bus: &mut super::DmaBus,
-    ) -> (u32, bool) {
-        // must be sync mode 0 or 1
-        assert!(channel.channel_control.sync_mode() != 2);
-        // chopping is only for sync mode 0
-        assert!(!channel
-            .channel_control
-            .intersects(ChannelControl::CHOPPING_ENABLED));
-
+        main_ram: &mut MainRam,
+        spu: &mut Spu,
+    ) -> (u32, u32, bool) {
         let direction_from_main_ram = channel
             .channel_control
             .intersects(ChannelControl::DIRECTION_FROM_RAM);
 
         // check first that the SPU is ready for DMA transfer
-        if !dma_bus.spu.is_ready_for_dma(direction_from_main_ram) {
-            return (0, false);
+        if !spu.is_ready_for_dma(direction_from_main_ram) {
+            return (0, 0, false);
         }
 
         let address_step = channel.channel_control.address_step();
+        let sync_mode = channel.channel_control.sync_mode();
 
-        // TODO: check if the max is 16 or not
-        let block_size = channel.block_control & 0xFFFF;
-        let mut blocks = channel.block_control >> 16;
+        // in sync mode 0, this is the size of the whole transfer
+        let block_size = match channel.block_control & 0xFFFF {
+            0 => 0x10000,
+            size => size,
+        };
 
         let mut address = channel.base_address & 0xFFFFFC;
 
         if direction_from_main_ram {
             let mut block = Vec::with_capacity(block_size as usize);
             for _ in 0..block_size {
-                let data = dma_bus.main_ram.read_u32(address).unwrap();
+                let data = main_ram.read_u32(address).unwrap();
                 block.push(data);
-                // step
-                address = (address as i32 + address_step) as u32;
+                // step, the address wraps around inside the 24bit range
+                address = (address as i32).wrapping_add(address_step) as u32 & 0xFFFFFC;
             }
 
-            dma_bus.spu.dma_write_buf(&block);
+            spu.dma_write_buf(&block);
         } else {
-            let block = dma_bus.spu.dma_read_buf(block_size as usize);
+            let block = spu.dma_read_buf(block_size as usize);
 
             for data in block {
-                dma_bus.main_ram.write_u32(address, data).unwrap();
-                // step
-                address = (address as i32 + address_step) as u32;
+                main_ram.write_u32(address, data).unwrap();
+                // step, the address wraps around inside the 24bit range
+                address = (address as i32).wrapping_add(address_step) as u32 & 0xFFFFFC;
             }
         }
 
-        // sync mode 0, does everything in one go, and doesn't update the register
-        // TODO: fix if we are in chopping
-        if channel.channel_control.sync_mode() == 1 {
-            blocks -= 1;
+        // sync mode 0 does everything in one go, and doesn't update the registers
+        let finished = if sync_mode == 1 {
+            // 0 is 0x10000 blocks, it reaches 0 again after all of them
+            let blocks = (channel.block_control >> 16).wrapping_sub(1) & 0xFFFF;
 
             channel.block_control &= 0xFFFF;
             channel.block_control |= blocks << 16;
             channel.base_address = address;
-        }
-
-        // we don't care about the block value in sync mode 0
-        let finished = blocks == 0 || channel.channel_control.sync_mode() == 0;
+            blocks == 0
+        } else {
+            true
+        };
 
         if finished {
-            dma_bus.spu.finish_dma();
+            spu.finish_dma();
         }
 
-        (block_size, finished)
+        (block_size, block_size, finished)
     }
 
     // Some control flags are ignored here like:
@@ -552,10 +581,15 @@ impl Dma {
     // - Chopping
     // - sync mode
     // Becuase they are hardwired
+    //
+    // The table is filled backwards starting from the base address, each
+    // entry points to the one before it, and the last one (lowest address)
+    // is the end marker `0xFFFFFF`. The base address and block control
+    // registers are not updated.
     fn perform_otc_channel6_dma(
         channel: &mut DmaChannel,
-        dma_bus: &mut super::DmaBus,
-    ) -> (u32, bool) {
+        main_ram: &mut MainRam,
+    ) -> (u32, u32, bool) {
         // must be to main ram
         assert!(!channel
             .channel_control
@@ -570,13 +604,9 @@ impl Dma {
             .channel_control
             .intersects(ChannelControl::START_TRIGGER)
         {
-            return (0, true);
+            return (0, 0, true);
         }
 
-        let chopping = channel
-            .channel_control
-            .intersects(ChannelControl::CHOPPING_ENABLED);
-
         // word align
         let mut current = channel.base_address & 0xFFFFFC;
         let mut n_entries = channel.block_control & 0xFFFF;
@@ -584,25 +614,32 @@ impl Dma {
             n_entries = 0x10000;
         }
 
-        // TODO: check if we should add one more linked list entry
         for _ in 0..(n_entries - 1) {
-            let next = current - 4;
+            // the address wraps around inside the 24bit range
+            let next = current.wrapping_sub(4) & 0xFFFFFC;
             // write a pointer to the next address
-            dma_bus.main_ram.write_u32(current, next).unwrap();
+            main_ram.write_u32(current, next).unwrap();
             current = next;
         }
-        dma_bus.main_ram.write_u32(current, 0xFFFFFF).unwrap();
-
-        if chopping {
-            channel.block_control = 0;
-            channel.base_address = current;
-        }
+        main_ram.write_u32(current, 0xFFFFFF).unwrap();
 
-        (n_entries, true)
+        (n_entries, n_entries, true)
     }
 }
 
 impl Dma {
+    pub(super) fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = tracer;
+    }
+
+    pub(super) fn set_warnings(&mut self, warnings: Warnings) {
+        self.warnings = warnings;
+    }
+
+    pub(super) fn perf_counters_mut(&mut self) -> &mut DmaCounters {
+        &mut self.perf
+    }
+
     pub(super) fn needs_to_run(&self) -> bool {
         self.channels.iter().enumerate().any(|(i, channel)| {
             let channel_enabled = (self.control >> (i * 4)) & 0b1000 != 0;
@@ -670,15 +707,38 @@ impl Dma {
             let channel = &mut self.channels[i];
             log::trace!("channel {} doing DMA", i);
 
-            let (cycles_to_delay, finished) = match i {
-                0 => Self::perform_mdec_in_channel0_dma(channel, dma_bus),
-                1 => Self::perform_mdec_out_channel1_dma(channel, dma_bus),
-                2 => Self::perform_gpu_channel2_dma(channel, dma_bus),
-                3 => Self::perform_cdrom_channel3_dma(channel, dma_bus),
-                4 => Self::perform_spu_channel4_dma(channel, dma_bus),
-                5 => todo!("DMA channel PIO 5"),
-                6 => Self::perform_otc_channel6_dma(channel, dma_bus),
-                _ => unreachable!(),
+            let unsupported = Self::unsupported_control(i, &channel.channel_control);
+            let (cycles_to_delay, words, finished) = if let Some(reason) = unsupported {
+                self.warnings.warn(
+                    "DMA",
+                    format!(
+                        "channel {}: {} is not supported (CHCR {:08X})",
+                        i,
+                        reason,
+                        channel.channel_control.bits()
+                    ),
+                );
+                (1, 0, true)
+            } else {
+                match i {
+                    0 => Self::perform_mdec_in_channel0_dma(channel, dma_bus),
+                    1 => Self::perform_mdec_out_channel1_dma(channel, dma_bus),
+                    2 => Self::perform_gpu_channel2_dma(channel, dma_bus),
+                    3 => Self::perform_cdrom_channel3_dma(channel, dma_bus),
+                    4 => Self::perform_spu_channel4_dma(
+                        channel,
+                        &mut dma_bus.main_ram,
+                        &mut dma_bus.spu,
+                    ),
+                    5 => {
+                        // nothing is connected to the expansion port, finish right away
+                        self.warnings
+                            .warn("DMA", "channel 5 (PIO) is not supported".to_string());
+                        (1, 0, true)
+                    }
+                    6 => Self::perform_otc_channel6_dma(channel, &mut dma_bus.main_ram),
+                    _ => unreachable!(),
+                }
             };
 
             if cycles_to_delay == 0 {
@@ -686,6 +746,11 @@ impl Dma {
             }
 
             cpu_cycles = cycles_to_delay;
+            self.perf.transfer(i, cycles_to_delay);
+            self.tracer.trace(|| TraceEvent::DmaTransfer {
+                channel: i as u8,
+                words,
+            });
 
             // remove trigger afterwards, since some handlers might check
             //  for manual trigger
@@ -700,6 +765,14 @@ impl Dma {
             break;
         }
 
+        self.update_irq_master_flag(interrupt_requester);
+
+        cpu_cycles
+    }
+
+    /// Recompute `DICR.31`, the interrupt is only requested on the transition
+    /// from `0` to `1`, so it must be acknowledged in `DICR` before another one.
+    fn update_irq_master_flag(&mut self, interrupt_requester: &mut impl InterruptRequester) {
         let new_master_flag = self.interrupt.compute_irq_master_flag();
         // only in transition from false to true, so it should be false now
         if new_master_flag && !self.interrupt.master_flag() {
@@ -708,8 +781,6 @@ impl Dma {
 
         self.interrupt
             .set(DmaInterruptRegister::IRQ_MASTER_FLAG, new_master_flag);
-
-        cpu_cycles
     }
 }
 
@@ -754,7 +825,7 @@ impl BusLine for Dma {
             0xF4 => {
                 // we will keep the upper-most bit
                 let old_interrupt = self.interrupt.bits();
-                let new_data = data & 0xFFFFFF;
+                let new_data = data & 0xFFFFFF & !DMA_INTERRUPT_NOT_USED;
                 // and the flags will be reset on write
                 let irq_flags_reset = data & 0x7F000000;
 
@@ -818,6 +889,308 @@ impl BusLine for Dma {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::memory::interrupts::Interrupts;
+
+    const DMA_IRQ: u16 = 1 << 3;
+
+    fn dma_irq_requested(interrupts: &mut Interrupts) -> bool {
+        let requested = interrupts.read_u16(0).unwrap() & DMA_IRQ != 0;
+        interrupts.write_u32(0, 0).unwrap();
+        requested
+    }
+
+    /// Setup channel 6 through the registers, like the BIOS does
+    fn otc_dma(base_address: u32, n_entries: u32) -> Dma {
+        let mut dma = Dma::default();
+        dma.write_u32(0xF0, 0x08000000).unwrap();
+        dma.write_u32(0xE0, base_address).unwrap();
+        dma.write_u32(0xE4, n_entries).unwrap();
+        dma.write_u32(0xE8, 0x11000002).unwrap();
+        dma
+    }
+
+    #[test]
+    fn otc_fill() {
+        let mut main_ram = MainRam::default();
+        let mut dma = otc_dma(0x100C, 4);
+        assert!(dma.needs_to_run());
+
+        let channel = &mut dma.channels[6];
+        assert_eq!(channel.channel_control.address_step(), -4);
+        assert_eq!(
+            Dma::perform_otc_channel6_dma(channel, &mut main_ram),
+            (4, 4, true)
+        );
+
+        for (addr, data) in [
+            (0x100C, 0x1008),
+            (0x1008, 0x1004),
+            (0x1004, 0x1000),
+            (0x1000, 0xFFFFFF),
+        ] {
+            assert_eq!(main_ram.read_u32(addr).unwrap(), data, "{:08X}", addr);
+        }
+        // nothing outside the table
+        assert_eq!(main_ram.read_u32(0x1010).unwrap(), 0);
+        assert_eq!(main_ram.read_u32(0xFFC).unwrap(), 0);
+        // registers are not updated
+        assert_eq!(channel.base_address, 0x100C);
+        assert_eq!(channel.block_control, 4);
+    }
+
+    #[test]
+    fn otc_fill_max_entries() {
+        let mut main_ram = MainRam::default();
+        // `0` means 0x10000 entries
+        let mut dma = otc_dma(0x1FFFFC, 0);
+        assert_eq!(
+            Dma::perform_otc_channel6_dma(&mut dma.channels[6], &mut main_ram),
+            (0x10000, 0x10000, true)
+        );
+        assert_eq!(main_ram.read_u32(0x1FFFFC).unwrap(), 0x1FFFF8);
+        assert_eq!(main_ram.read_u32(0x1C0004).unwrap(), 0x1C0000);
+        assert_eq!(main_ram.read_u32(0x1C0000).unwrap(), 0xFFFFFF);
+        assert_eq!(main_ram.read_u32(0x1BFFFC).unwrap(), 0);
+    }
+
+    #[test]
+    fn otc_control_hardwired() {
+        let mut dma = Dma::default();
+        // try to set direction, forward step, chopping and sync mode
+        dma.write_u32(0xE8, 0x11770701).unwrap();
+        assert_eq!(dma.read_u32(0xE8).unwrap(), 0x11000002);
+    }
+
+    #[test]
+    fn unsupported_channel_control() {
+        let unsupported = |channel, chcr| {
+            Dma::unsupported_control(channel, &ChannelControl::from_bits_retain(chcr))
+        };
+
+        // what the BIOS and games use
+        for (channel, chcr) in [
+            (0, 0x01000201),
+            (1, 0x01000200),
+            (2, 0x01000201),
+            (2, 0x01000401),
+            (3, 0x11000000),
+            (4, 0x01000201),
+            (6, 0x11000002),
+        ] {
+            assert_eq!(unsupported(channel, chcr), None, "{} {:08X}", channel, chcr);
+        }
+
+        assert_eq!(unsupported(3, 0x11000001), Some("CDROM from RAM"));
+        assert_eq!(
+            unsupported(0, 0x01000001),
+            Some("MDEC sync mode other than 1")
+        );
+        assert_eq!(unsupported(2, 0x01000601), Some("reserved sync mode 3"));
+        assert_eq!(unsupported(4, 0x01000401), Some("SPU linked list"));
+    }
+
+    fn gpu_linked_list_channel(base_address: u32) -> DmaChannel {
+        DmaChannel {
+            base_address,
+            block_control: 0,
+            channel_control: ChannelControl::from_bits_retain(0x01000401),
+        }
+    }
+
+    /// Run the linked list DMA to the end, returns the GP0 commands sent
+    fn run_gpu_linked_list(channel: &mut DmaChannel, main_ram: &mut MainRam) -> Vec<u32> {
+        let mut commands = Vec::new();
+        for _ in 0..100 {
+            let (_, _, finished) =
+                Dma::perform_gpu_linked_list(channel, main_ram, |cmd| commands.push(cmd));
+            if finished {
+                return commands;
+            }
+        }
+        panic!("linked list did not terminate");
+    }
+
+    #[test]
+    fn gpu_linked_list() {
+        let mut main_ram = MainRam::default();
+        // 2 commands
+        main_ram.write_u32(0x100, 0x02000200).unwrap();
+        main_ram.write_u32(0x104, 0xE1000001).unwrap();
+        main_ram.write_u32(0x108, 0xE1000002).unwrap();
+        // empty entries are skipped
+        main_ram.write_u32(0x200, 0x00000300).unwrap();
+        main_ram.write_u32(0x300, 0x00000400).unwrap();
+        // last entry, only the lower 24 bits are the end marker
+        main_ram.write_u32(0x400, 0x01FFFFFF).unwrap();
+        main_ram.write_u32(0x404, 0xE1000003).unwrap();
+
+        let mut channel = gpu_linked_list_channel(0x100);
+        assert_eq!(
+            run_gpu_linked_list(&mut channel, &mut main_ram),
+            [0xE1000001, 0xE1000002, 0xE1000003]
+        );
+        assert_eq!(channel.base_address, 0xFFFFFF);
+    }
+
+    #[test]
+    fn gpu_linked_list_empty() {
+        let mut main_ram = MainRam::default();
+        main_ram.write_u32(0x100, 0x00000200).unwrap();
+        main_ram.write_u32(0x200, 0x00FFFFFF).unwrap();
+
+        let mut channel = gpu_linked_list_channel(0x100);
+        assert_eq!(
+            run_gpu_linked_list(&mut channel, &mut main_ram),
+            Vec::<u32>::new()
+        );
+
+        let mut channel = gpu_linked_list_channel(0x200);
+        assert_eq!(
+            Dma::perform_gpu_linked_list(&mut channel, &mut main_ram, |_| unreachable!()),
+            (1, 1, true)
+        );
+    }
+
+    #[test]
+    fn spu_block_mode() {
+        let mut main_ram = MainRam::default();
+        let mut interrupts = Interrupts::default();
+        let mut spu = Spu::default();
+        // SPU enable, DMA write
+        spu.write_u16(0x1AA, 0x8020).unwrap();
+        spu.write_u16(0x1AC, 2 << 1).unwrap();
+        spu.write_u16(0x1A6, 0x1000 / 8).unwrap();
+
+        // 3 blocks of 4 words, from RAM, sync mode 1
+        let mut channel = DmaChannel {
+            base_address: 0x1000,
+            block_control: 0x0003_0004,
+            channel_control: ChannelControl::from_bits_retain(0x01000201),
+        };
+
+        let mut blocks = Vec::new();
+        loop {
+            // the SPU raises the DMA request on its clock
+            spu.clock(&mut interrupts, 0x300);
+            let (_, words, finished) =
+                Dma::perform_spu_channel4_dma(&mut channel, &mut main_ram, &mut spu);
+            if words != 0 {
+                blocks.push(words);
+            }
+            if finished {
+                break;
+            }
+        }
+
+        assert_eq!(blocks, [4, 4, 4]);
+        assert_eq!(channel.base_address, 0x1000 + 3 * 4 * 4);
+        assert_eq!(channel.block_control, 4);
+    }
+
+    #[test]
+    fn spu_block_count_zero() {
+        let mut main_ram = MainRam::default();
+        let mut interrupts = Interrupts::default();
+        let mut spu = Spu::default();
+        // SPU enable, DMA read
+        spu.write_u16(0x1AA, 0x8030).unwrap();
+        spu.clock(&mut interrupts, 0x300);
+
+        // 0 blocks is the maximum, backwards from the start of the address space
+        let mut channel = DmaChannel {
+            base_address: 0,
+            block_control: 0x0000_0002,
+            channel_control: ChannelControl::from_bits_retain(0x01000202),
+        };
+        assert_eq!(
+            Dma::perform_spu_channel4_dma(&mut channel, &mut main_ram, &mut spu),
+            (2, 2, false)
+        );
+        assert_eq!(channel.block_control, 0xFFFF_0002);
+        assert_eq!(channel.base_address, 0xFFFFF8);
+    }
+
+    #[test]
+    fn irq_flags_channel_enabled() {
+        let mut interrupts = Interrupts::default();
+        let mut dma = Dma::default();
+        // master enable, channel 6 enable
+        dma.write_u32(0xF4, 0x00C00000).unwrap();
+
+        dma.update_irq_master_flag(&mut interrupts);
+        assert!(!dma_irq_requested(&mut interrupts));
+
+        dma.interrupt.request_interrupt(6);
+        dma.update_irq_master_flag(&mut interrupts);
+        assert_eq!(dma.read_u32(0xF4).unwrap(), 0xC0C00000);
+        assert!(dma_irq_requested(&mut interrupts));
+
+        // only requested on the edge
+        dma.interrupt.request_interrupt(6);
+        dma.update_irq_master_flag(&mut interrupts);
+        assert!(!dma_irq_requested(&mut interrupts));
+
+        // acknowledge with `1`
+        dma.write_u32(0xF4, 0x40C00000).unwrap();
+        dma.update_irq_master_flag(&mut interrupts);
+        assert_eq!(dma.read_u32(0xF4).unwrap(), 0x00C00000);
+
+        dma.interrupt.request_interrupt(6);
+        dma.update_irq_master_flag(&mut interrupts);
+        assert!(dma_irq_requested(&mut interrupts));
+    }
+
+    #[test]
+    fn irq_flags_channel_disabled() {
+        let mut interrupts = Interrupts::default();
+        let mut dma = Dma::default();
+        // master enable, channel 2 enable only
+        dma.write_u32(0xF4, 0x00840000).unwrap();
+
+        dma.interrupt.request_interrupt(6);
+        dma.update_irq_master_flag(&mut interrupts);
+        assert_eq!(dma.read_u32(0xF4).unwrap(), 0x00840000);
+        assert!(!dma_irq_requested(&mut interrupts));
+
+        // channel enabled, but master disabled, the flag is set
+        // but no interrupt
+        dma.write_u32(0xF4, 0x00400000).unwrap();
+        dma.interrupt.request_interrupt(6);
+        dma.update_irq_master_flag(&mut interrupts);
+        assert_eq!(dma.read_u32(0xF4).unwrap(), 0x40400000);
+        assert!(!dma_irq_requested(&mut interrupts));
+
+        // enabling master later raises it
+        dma.write_u32(0xF4, 0x00C00000).unwrap();
+        dma.update_irq_master_flag(&mut interrupts);
+        assert!(dma_irq_requested(&mut interrupts));
+    }
+
+    #[test]
+    fn irq_force() {
+        let mut interrupts = Interrupts::default();
+        let mut dma = Dma::default();
+
+        // even without master enable
+        dma.write_u32(0xF4, 0x00008000).unwrap();
+        dma.update_irq_master_flag(&mut interrupts);
+        assert_eq!(dma.read_u32(0xF4).unwrap(), 0x80008000);
+        assert!(dma_irq_requested(&mut interrupts));
+
+        dma.update_irq_master_flag(&mut interrupts);
+        assert!(!dma_irq_requested(&mut interrupts));
+
+        dma.write_u32(0xF4, 0).unwrap();
+        dma.update_irq_master_flag(&mut interrupts);
+        assert_eq!(dma.read_u32(0xF4).unwrap(), 0);
+    }
+
+    #[test]
+    fn irq_unused_bits() {
+        let mut dma = Dma::default();
+        dma.write_u32(0xF4, 0x00FFFFFF).unwrap();
+        assert_eq!(dma.read_u32(0xF4).unwrap(), 0x00FF803F);
+    }
 
     #[test]
     fn get_channels_order_no_channels_enabled() {
@@ -0,0 +1,220 @@
+//! Parsing and loading of `PS-X EXE` files, the same way the BIOS `Exec` does it
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::cpu::{RegisterType, Registers};
+use crate::PsxError;
+
+use super::{BusLine, RamSize};
+
+const HEADER_SIZE: usize = 0x800;
+const MAGIC: &[u8; 8] = b"PS-X EXE";
+
+/// An executable, checked to fit in RAM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PsxExe {
+    pub pc: u32,
+    pub gp: u32,
+    /// Where `text` is loaded
+    pub text_addr: u32,
+    /// The code and data, padded to the size in the header
+    pub text: Vec<u8>,
+    /// The range filled with zeros before starting, `bss_size` can be `0`
+    pub bss_addr: u32,
+    pub bss_size: u32,
+    /// `sp` and `fp` are set to `stack_base + stack_offset`, only if `stack_base` is not `0`
+    pub stack_base: u32,
+    pub stack_offset: u32,
+}
+
+fn invalid<S: Into<String>>(reason: S) -> PsxError {
+    PsxError::InvalidExe(reason.into())
+}
+
+/// The section must be in RAM, in KUSEG, KSEG0 or KSEG1
+fn check_in_ram(name: &str, addr: u32, size: usize, ram_size: RamSize) -> Result<(), PsxError> {
+    let start = (addr & 0x1FFFFFFF) as usize;
+    if !matches!(addr >> 29, 0 | 4 | 5) || start + size > ram_size.bytes() {
+        return Err(invalid(format!(
+            "the {} section ({:08X}, size {:X}) is outside of RAM",
+            name, addr, size
+        )));
+    }
+    Ok(())
+}
+
+impl PsxExe {
+    pub fn parse(data: &[u8], ram_size: RamSize) -> Result<Self, PsxError> {
+        if data.len() < HEADER_SIZE {
+            return Err(invalid(format!(
+                "the file is too small ({} bytes) for the header",
+                data.len()
+            )));
+        }
+        if &data[..MAGIC.len()] != MAGIC {
+            return Err(invalid("the `PS-X EXE` magic is missing"));
+        }
+
+        let word = |offset: usize| LittleEndian::read_u32(&data[offset..offset + 4]);
+        // 0x08..0x10 are the file offsets of the sections, which are not used by the BIOS
+        let pc = word(0x10);
+        let gp = word(0x14);
+        let text_addr = word(0x18);
+        let text_size = word(0x1C) as usize;
+        // 0x20..0x28 is the data section, which is part of `text`
+        let bss_addr = word(0x28);
+        let bss_size = word(0x2C);
+        let stack_base = word(0x30);
+        let stack_offset = word(0x34);
+
+        if pc == 0 {
+            return Err(invalid("the initial pc is 0"));
+        }
+        check_in_ram("text", text_addr, text_size, ram_size)?;
+        if bss_size != 0 {
+            check_in_ram("bss", bss_addr, bss_size as usize, ram_size)?;
+        }
+
+        let mut text = data[HEADER_SIZE..].to_vec();
+        if text.len() != text_size {
+            log::warn!(
+                "EXE: the header size is {:X}, but the file has {:X} bytes after the header, \
+                 {} to the header size",
+                text_size,
+                text.len(),
+                if text.len() < text_size {
+                    "padding"
+                } else {
+                    "truncating"
+                }
+            );
+            text.resize(text_size, 0);
+        }
+
+        Ok(Self {
+            pc,
+            gp,
+            text_addr,
+            text,
+            bss_addr,
+            bss_size,
+            stack_base,
+            stack_offset,
+        })
+    }
+
+    /// The value of `sp` and `fp` when starting, if they are set
+    pub fn stack_pointer(&self) -> Option<u32> {
+        if self.stack_base == 0 {
+            None
+        } else {
+            Some(self.stack_base.wrapping_add(self.stack_offset))
+        }
+    }
+
+    /// Copy the executable into `ram` and clear the bss
+    pub(crate) fn load<B: BusLine>(&self, ram: &mut B) {
+        let write = |ram: &mut B, addr: u32, value: u8| {
+            ram.write_u8(addr, value)
+                .expect("the EXE was checked to be in RAM")
+        };
+        for (i, &byte) in self.text.iter().enumerate() {
+            write(ram, self.text_addr + i as u32, byte);
+        }
+        for i in 0..self.bss_size {
+            write(ram, self.bss_addr + i, 0);
+        }
+    }
+
+    /// Setup the registers to start executing
+    pub fn apply_registers(&self, regs: &mut Registers) {
+        regs.write(RegisterType::Pc, self.pc);
+        regs.write(RegisterType::Gp, self.gp);
+        if let Some(sp) = self.stack_pointer() {
+            regs.write(RegisterType::Sp, sp);
+            regs.write(RegisterType::Fp, sp);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Build an EXE with the header fields from `0x10` to `0x38` (pc, gp, t_addr,
+    /// t_size, d_addr, d_size, b_addr, b_size, s_addr, s_size), followed by `text`
+    pub(crate) fn build_exe(fields: [u32; 10], text: &[u8]) -> Vec<u8> {
+        let mut exe = vec![0; HEADER_SIZE];
+        exe[..8].copy_from_slice(MAGIC);
+        for (i, field) in fields.iter().enumerate() {
+            LittleEndian::write_u32(&mut exe[0x10 + i * 4..], *field);
+        }
+        exe.extend_from_slice(text);
+        exe
+    }
+
+    fn reason(result: Result<PsxExe, PsxError>) -> String {
+        match result {
+            Err(PsxError::InvalidExe(reason)) => reason,
+            other => panic!("expected InvalidExe, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn invalid_headers() {
+        let text = [0; 0x800];
+        let exe = |fields| build_exe(fields, &text);
+        let ram = RamSize::Std2MB;
+
+        assert!(reason(PsxExe::parse(&exe(Default::default())[..0x100], ram)).contains("small"));
+
+        let mut bad_magic = exe([0x80010000, 0, 0x80010000, 0x800, 0, 0, 0, 0, 0, 0]);
+        bad_magic[3] = b'Y';
+        assert!(reason(PsxExe::parse(&bad_magic, ram)).contains("magic"));
+
+        let no_pc = exe([0, 0, 0x80010000, 0x800, 0, 0, 0, 0, 0, 0]);
+        assert!(reason(PsxExe::parse(&no_pc, ram)).contains("pc"));
+
+        for (text_addr, text_size) in [
+            // past 2MB
+            (0x801FF800, 0x1000),
+            // KSEG2
+            (0xC0000000, 0x800),
+            // not RAM
+            (0x1F000000, 0x800),
+        ] {
+            let outside = exe([0x80010000, 0, text_addr, text_size, 0, 0, 0, 0, 0, 0]);
+            assert!(reason(PsxExe::parse(&outside, ram)).contains("text"));
+        }
+
+        let bss_outside = exe([
+            0x80010000, 0, 0x80010000, 0x800, 0, 0, 0x80100000, 0x200000, 0, 0,
+        ]);
+        assert!(reason(PsxExe::parse(&bss_outside, ram)).contains("bss"));
+        // fits in 8MB
+        assert!(PsxExe::parse(&bss_outside, RamSize::Dev8MB).is_ok());
+    }
+
+    #[test]
+    fn text_padding() {
+        let fields = [0x80010000, 0, 0x80010000, 0x800, 0, 0, 0, 0, 0, 0];
+
+        let short = PsxExe::parse(&build_exe(fields, &[1, 2, 3]), RamSize::Std2MB).unwrap();
+        assert_eq!(short.text.len(), 0x800);
+        assert_eq!(&short.text[..4], &[1, 2, 3, 0]);
+
+        let long = PsxExe::parse(&build_exe(fields, &[1; 0x900]), RamSize::Std2MB).unwrap();
+        assert_eq!(long.text, vec![1; 0x800]);
+    }
+
+    #[test]
+    fn stack_pointer() {
+        let mut fields = [0x80010000, 0, 0x80010000, 0, 0, 0, 0, 0, 0, 0x100];
+        let exe = PsxExe::parse(&build_exe(fields, &[]), RamSize::Std2MB).unwrap();
+        assert_eq!(exe.stack_pointer(), None);
+
+        fields[8] = 0x801FFF00;
+        let exe = PsxExe::parse(&build_exe(fields, &[]), RamSize::Std2MB).unwrap();
+        assert_eq!(exe.stack_pointer(), Some(0x80200000));
+    }
+}
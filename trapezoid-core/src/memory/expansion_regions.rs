@@ -1,4 +1,4 @@
-use crate::{memory::Result, PsxConfig};
+use crate::memory::{BusError, Result};
 
 use super::BusLine;
 
@@ -39,48 +39,44 @@ impl BusLine for ExpansionRegion1 {
 }
 
 struct DuartTTY {
-    // TODO: add a way to display this buffer, maybe using another window?
+    /// Everything written, see `Psx::tty_output`
     tty_buffer: String,
     line_temp_buffer: String,
-    config: PsxConfig,
+    stdout_debug: bool,
+    /// The lines printed to `stdout`
+    #[cfg(test)]
+    printed_lines: Vec<String>,
 }
 
 // This is just the minimum for the TTY to work, as the duart is not used
 // for anything else
 impl DuartTTY {
-    fn new(config: PsxConfig) -> Self {
+    fn new(stdout_debug: bool) -> Self {
         Self {
             tty_buffer: String::new(),
             line_temp_buffer: String::new(),
-            config,
+            stdout_debug,
+            #[cfg(test)]
+            printed_lines: Vec::new(),
         }
     }
 
-    fn read(&self, addr: u32) -> u8 {
-        match addr & 0xF {
-            0x0 => todo!(),
+    fn read(&self, addr: u32) -> Result<u8> {
+        let out = match addr & 0xF {
             // DUART Status Register A
             // bit.2: Tx Empty (ready to send)
             0x1 => 0b100,
-            0x2 => todo!(),
-            0x3 => todo!(),
-            0x4 => todo!(),
-            0x5 => todo!(),
-            0x6 => todo!(),
-            0x7 => todo!(),
-            0x8 => todo!(),
-            0x9 => todo!(),
-            0xA => todo!(),
-            0xB => todo!(),
-            0xC => todo!(),
-            0xD => todo!(),
-            0xE => todo!(),
-            0xF => todo!(),
-            _ => unreachable!(),
-        }
+            _ => {
+                return Err(BusError::unsupported::<Self>(format!(
+                    "read from {:X}",
+                    addr
+                )))
+            }
+        };
+        Ok(out)
     }
 
-    fn write(&mut self, addr: u32, data: u8) {
+    fn write(&mut self, addr: u32, data: u8) -> Result<()> {
         match addr & 0xF {
             // DUART Mode Register A
             0x0 => {}
@@ -96,8 +92,10 @@ impl DuartTTY {
 
                 // printing each line on line break to not get mixed with logs
                 if ch == '\n' {
-                    if self.config.stdout_debug {
+                    if self.stdout_debug {
                         println!("DEBUG: {}", self.line_temp_buffer);
+                        #[cfg(test)]
+                        self.printed_lines.push(self.line_temp_buffer.clone());
                     }
                     self.line_temp_buffer.clear();
                 } else {
@@ -109,21 +107,20 @@ impl DuartTTY {
             // DUART Interrupt Mask Register
             // 0 is written here, so no need to handle any interrupts
             0x5 => {}
-            0x6 => todo!(),
-            0x7 => todo!(),
-            0x8 => todo!(),
-            0x9 => todo!(),
             // DUART Command Register B
             0xA => {}
-            0xB => todo!(),
-            0xC => todo!(),
             // DUART Output Port Configuration Register
             0xD => {}
             // DUART Set Output Port Bits Command
             0xE => {}
-            0xF => todo!(),
-            _ => unreachable!(),
+            _ => {
+                return Err(BusError::unsupported::<Self>(format!(
+                    "write {:02X} to {:X}",
+                    data, addr
+                )))
+            }
         }
+        Ok(())
     }
 }
 
@@ -135,18 +132,26 @@ pub struct ExpansionRegion2 {
 }
 
 impl ExpansionRegion2 {
-    pub fn new(config: PsxConfig) -> Self {
+    pub fn new(stdout_debug: bool) -> Self {
         Self {
             data: [0; 0x90],
-            tty_duart: DuartTTY::new(config),
+            tty_duart: DuartTTY::new(stdout_debug),
         }
     }
+
+    pub fn set_stdout_debug(&mut self, stdout_debug: bool) {
+        self.tty_duart.stdout_debug = stdout_debug;
+    }
+
+    pub fn tty_output(&self) -> &str {
+        &self.tty_duart.tty_buffer
+    }
 }
 
 impl BusLine for ExpansionRegion2 {
     fn read_u8(&mut self, addr: u32) -> Result<u8> {
         let out = match addr {
-            0x20..=0x2F => self.tty_duart.read(addr & 0xF),
+            0x20..=0x2F => self.tty_duart.read(addr & 0xF)?,
             _ => self.data[addr as usize],
         };
 
@@ -162,7 +167,7 @@ impl BusLine for ExpansionRegion2 {
         );
 
         match addr {
-            0x20..=0x2F => self.tty_duart.write(addr & 0xF, data),
+            0x20..=0x2F => self.tty_duart.write(addr & 0xF, data)?,
             // POST register used for debugging the BIOS and kernel init
             0x41 => println!("TraceStep {:02X}", data),
             _ => self.data[addr as usize] = data,
@@ -196,3 +201,41 @@ impl BusLine for ExpansionRegion2 {
         self.write_u16(addr + 2, (data >> 16) as u16)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn print(region: &mut ExpansionRegion2, text: &str) {
+        for byte in text.bytes() {
+            region.write_u8(0x23, byte).unwrap();
+        }
+    }
+
+    #[test]
+    fn toggle_stdout_debug() {
+        let mut region = ExpansionRegion2::new(false);
+        print(&mut region, "hidden\n");
+        assert!(region.tty_duart.printed_lines.is_empty());
+
+        region.set_stdout_debug(true);
+        print(&mut region, "shown\n");
+        region.set_stdout_debug(false);
+        print(&mut region, "hidden again\n");
+
+        assert_eq!(region.tty_duart.printed_lines, ["shown"]);
+        // everything is kept in the buffer
+        assert_eq!(region.tty_output(), "hidden\nshown\nhidden again\n");
+    }
+    #[test]
+    fn unsupported_duart_registers() {
+        let mut region = ExpansionRegion2::new(false);
+        // the Rx holding register, reading it used to abort
+        assert!(matches!(
+            region.read_u8(0x23),
+            Err(BusError::DeviceError { .. })
+        ));
+        assert!(region.write_u8(0x26, 0x10).is_err());
+        assert_eq!(region.read_u8(0x21).unwrap(), 0b100);
+    }
+}
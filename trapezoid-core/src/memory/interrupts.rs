@@ -1,4 +1,6 @@
 use crate::memory::Result;
+use crate::state_hash::StateHasher;
+use crate::trace::{IrqSource, TraceEvent, Tracer};
 
 use super::BusLine;
 
@@ -21,6 +23,7 @@ bitflags::bitflags! {
 
 pub trait InterruptRequester {
     fn request_vblank(&mut self);
+    fn request_gpu(&mut self);
     fn request_cdrom(&mut self);
     fn request_dma(&mut self);
     fn request_timer0(&mut self);
@@ -30,16 +33,54 @@ pub trait InterruptRequester {
     fn request_spu(&mut self);
 }
 
+/// The interrupt controller, `I_STAT` and `I_MASK`.
+///
+/// The `I_STAT` bits are latched when a device requests an interrupt, and
+/// stay set until acknowledged by writing `0` to them (writing `1` keeps them).
+///
+/// Most devices request once per event (edge), but the CD-ROM keeps its line
+/// asserted as long as `interrupt_flag & interrupt_enable` is non-zero, and requests
+/// on every clock (level), so acknowledging `I_STAT` before the CD-ROM flags
+/// will set the bit again on the next clock.
 #[derive(Default)]
 pub struct Interrupts {
     stat: InterruptFlags,
     mask: InterruptFlags,
+
+    tracer: Tracer,
 }
 
 impl Interrupts {
     pub fn pending_interrupts(&self) -> bool {
         !(self.stat & self.mask).is_empty()
     }
+
+    /// The bits of `I_STAT & I_MASK`
+    pub fn pending_interrupts_flags(&self) -> u16 {
+        (self.stat & self.mask).bits()
+    }
+
+    /// Writing `0` acknowledges the interrupt, and `1` keeps it as is
+    fn acknowledge(&mut self, data: u16) {
+        self.stat &= InterruptFlags::from_bits_retain(data);
+        log::info!("write interrupts stat {:?}", self.stat);
+    }
+
+    pub(crate) fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = tracer;
+    }
+
+    pub(crate) fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_u16(self.stat.bits());
+        hasher.write_u16(self.mask.bits());
+    }
+
+    fn request(&mut self, flag: InterruptFlags, source: IrqSource) {
+        if !self.stat.contains(flag) {
+            self.tracer.trace(|| TraceEvent::IrqRaised { source });
+        }
+        self.stat.insert(flag);
+    }
 }
 
 impl BusLine for Interrupts {
@@ -55,11 +96,7 @@ impl BusLine for Interrupts {
     fn write_u32(&mut self, addr: u32, data: u32) -> Result<()> {
         log::info!("write interrupts 32, regs {:X} = {:08X}", addr, data);
         match addr {
-            0 => {
-                let bits_to_keep = InterruptFlags::from_bits_retain(data as u16);
-                self.stat &= bits_to_keep;
-                log::info!("write interrupts stat {:?}", self.stat);
-            }
+            0 => self.acknowledge(data as u16),
             4 => {
                 self.mask = InterruptFlags::from_bits_retain(data as u16);
                 log::info!("write interrupts mask {:?}", self.mask);
@@ -83,11 +120,7 @@ impl BusLine for Interrupts {
     fn write_u16(&mut self, addr: u32, data: u16) -> Result<()> {
         log::info!("write interrupts 16, regs {:X} = {:08X}", addr, data);
         match addr {
-            0 => {
-                let bits_to_keep = InterruptFlags::from_bits_retain(data);
-                self.stat &= bits_to_keep;
-                log::info!("write interrupts stat {:?}", self.stat);
-            }
+            0 => self.acknowledge(data),
             2 => {}
             4 => {
                 self.mask = InterruptFlags::from_bits_retain(data);
@@ -103,40 +136,160 @@ impl BusLine for Interrupts {
 impl InterruptRequester for Interrupts {
     fn request_vblank(&mut self) {
         log::info!("requesting VBLANK interrupt");
-        self.stat.insert(InterruptFlags::VBLANK);
+        self.request(InterruptFlags::VBLANK, IrqSource::Vblank);
+    }
+
+    fn request_gpu(&mut self) {
+        log::info!("requesting GPU interrupt");
+        self.request(InterruptFlags::GPU, IrqSource::Gpu);
     }
 
     fn request_cdrom(&mut self) {
         log::info!("requesting CDROM interrupt");
-        self.stat.insert(InterruptFlags::CDROM);
+        self.request(InterruptFlags::CDROM, IrqSource::Cdrom);
     }
 
     fn request_dma(&mut self) {
         log::info!("requesting DMA interrupt");
-        self.stat.insert(InterruptFlags::DMA);
+        self.request(InterruptFlags::DMA, IrqSource::Dma);
     }
 
     fn request_timer0(&mut self) {
         log::info!("requesting TIMER0 interrupt");
-        self.stat.insert(InterruptFlags::TIMER0)
+        self.request(InterruptFlags::TIMER0, IrqSource::Timer0);
     }
     fn request_timer1(&mut self) {
         log::info!("requesting TIMER1 interrupt");
-        self.stat.insert(InterruptFlags::TIMER1)
+        self.request(InterruptFlags::TIMER1, IrqSource::Timer1);
     }
 
     fn request_timer2(&mut self) {
         log::info!("requesting TIMER2 interrupt");
-        self.stat.insert(InterruptFlags::TIMER2)
+        self.request(InterruptFlags::TIMER2, IrqSource::Timer2);
     }
 
     fn request_controller_mem_card(&mut self) {
         log::info!("requesting CONTROLLER_AND_MEMCARD interrupt");
-        self.stat.insert(InterruptFlags::CONTROLLER_AND_MEMCARD);
+        self.request(
+            InterruptFlags::CONTROLLER_AND_MEMCARD,
+            IrqSource::ControllerAndMemCard,
+        );
     }
 
     fn request_spu(&mut self) {
         log::info!("requesting SPU interrupt");
-        self.stat.insert(InterruptFlags::SPU);
+        self.request(InterruptFlags::SPU, IrqSource::Spu);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A device that keeps its line asserted, like the CD-ROM
+    struct LevelDevice {
+        asserted: bool,
+    }
+
+    impl LevelDevice {
+        fn clock(&mut self, interrupt_requester: &mut impl InterruptRequester) {
+            if self.asserted {
+                interrupt_requester.request_cdrom();
+            }
+        }
+    }
+
+    /// A device that requests once per event, like the timers
+    struct EdgeDevice {
+        event: bool,
+    }
+
+    impl EdgeDevice {
+        fn clock(&mut self, interrupt_requester: &mut impl InterruptRequester) {
+            if std::mem::take(&mut self.event) {
+                interrupt_requester.request_timer0();
+            }
+        }
+    }
+
+    const CDROM: u16 = InterruptFlags::CDROM.bits();
+    const TIMER0: u16 = InterruptFlags::TIMER0.bits();
+    const VBLANK: u16 = InterruptFlags::VBLANK.bits();
+
+    fn stat(interrupts: &mut Interrupts) -> u16 {
+        interrupts.read_u16(0).unwrap()
+    }
+
+    #[test]
+    fn acknowledge_zero_bits() {
+        let mut interrupts = Interrupts::default();
+        interrupts.write_u32(4, 0x7FF).unwrap();
+        interrupts.request_vblank();
+        interrupts.request_timer0();
+        interrupts.request_cdrom();
+
+        // only the zero bits are acknowledged
+        interrupts.write_u32(0, !(VBLANK as u32)).unwrap();
+        assert_eq!(stat(&mut interrupts), TIMER0 | CDROM);
+        interrupts.write_u16(0, !CDROM).unwrap();
+        assert_eq!(stat(&mut interrupts), TIMER0);
+        // writing ones doesn't request anything
+        interrupts.write_u16(0, 0xFFFF).unwrap();
+        assert_eq!(stat(&mut interrupts), TIMER0);
+        interrupts.write_u16(0, 0).unwrap();
+        assert_eq!(stat(&mut interrupts), 0);
+        assert!(!interrupts.pending_interrupts());
+    }
+
+    #[test]
+    fn acknowledge_while_asserted() {
+        let mut interrupts = Interrupts::default();
+        interrupts
+            .write_u32(4, CDROM as u32 | TIMER0 as u32)
+            .unwrap();
+        let mut cdrom = LevelDevice { asserted: true };
+        let mut timer = EdgeDevice { event: true };
+
+        cdrom.clock(&mut interrupts);
+        timer.clock(&mut interrupts);
+        assert_eq!(interrupts.pending_interrupts_flags(), CDROM | TIMER0);
+
+        interrupts.write_u32(0, 0).unwrap();
+        assert!(!interrupts.pending_interrupts());
+
+        // the CD-ROM is still asserted, the timer is not
+        cdrom.clock(&mut interrupts);
+        timer.clock(&mut interrupts);
+        assert_eq!(interrupts.pending_interrupts_flags(), CDROM);
+
+        // acknowledged in the device first, then in `I_STAT`
+        cdrom.asserted = false;
+        interrupts.write_u32(0, 0).unwrap();
+        cdrom.clock(&mut interrupts);
+        assert!(!interrupts.pending_interrupts());
+    }
+
+    #[test]
+    fn mask_toggling() {
+        let mut interrupts = Interrupts::default();
+        let mut timer = EdgeDevice { event: true };
+
+        // masked interrupts are still latched in `I_STAT`
+        timer.clock(&mut interrupts);
+        assert_eq!(stat(&mut interrupts), TIMER0);
+        assert!(!interrupts.pending_interrupts());
+
+        interrupts.write_u16(4, TIMER0).unwrap();
+        assert!(interrupts.pending_interrupts());
+        assert_eq!(interrupts.pending_interrupts_flags(), TIMER0);
+
+        interrupts.write_u16(4, 0).unwrap();
+        assert!(!interrupts.pending_interrupts());
+        assert_eq!(stat(&mut interrupts), TIMER0);
+
+        // acknowledged while masked, unmasking doesn't bring it back
+        interrupts.write_u16(0, 0).unwrap();
+        interrupts.write_u16(4, TIMER0).unwrap();
+        assert!(!interrupts.pending_interrupts());
     }
 }
@@ -37,22 +37,63 @@ impl BusLine for MemoryControl1 {
     }
 }
 
+/// How an address in the first 8MB is mapped by `RAM_SIZE`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RamWindow {
+    /// Mapped to RAM at this offset, mirrored by the RAM itself
+    Mapped(u32),
+    /// Nothing responds, reads return garbage and writes are ignored
+    HighZ,
+    /// Accessing it triggers a bus error
+    Locked,
+}
+
 // RAM_SIZE
-#[derive(Default)]
 pub struct MemoryControl2(u32);
 
+impl Default for MemoryControl2 {
+    fn default() -> Self {
+        // the value set by the BIOS, needed when booting without it
+        Self(0xB88)
+    }
+}
+
+impl MemoryControl2 {
+    /// Map an address to the first 8MB of the address space, using the window
+    /// size in bits 9-11
+    pub fn ram_window(&self, addr: u32) -> RamWindow {
+        const MB: u32 = 0x100000;
+        let (mapped, high_z) = match (self.0 >> 9) & 7 {
+            0 => (MB, 0),
+            1 => (4 * MB, 0),
+            2 => (MB, MB),
+            3 => (4 * MB, 4 * MB),
+            4 => (2 * MB, 0),
+            5 | 7 => (8 * MB, 0),
+            6 => (2 * MB, 2 * MB),
+            _ => unreachable!(),
+        };
+
+        let offset = addr & 0x7FFFFF;
+        if offset < mapped {
+            RamWindow::Mapped(offset)
+        } else if offset < mapped + high_z {
+            RamWindow::HighZ
+        } else {
+            RamWindow::Locked
+        }
+    }
+}
+
 impl BusLine for MemoryControl2 {
     fn read_u32(&mut self, _addr: u32) -> Result<u32> {
         Ok(self.0)
     }
 
     fn write_u32(&mut self, _addr: u32, data: u32) -> Result<()> {
-        // TODO: implement different ram modes
-        assert!(
-            data == 0xB88 || data == 0x888,
-            "mem_ctrl2 value is wrong, should be 0xB88, got {:08X}",
-            data
-        );
+        if data != 0xB88 {
+            log::info!("RAM_SIZE written with {:08X}", data);
+        }
         self.0 = data;
         Ok(())
     }
@@ -73,3 +114,27 @@ impl BusLine for CacheControl {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ram_windows() {
+        let mut ram_size = MemoryControl2::default();
+        // 8MB
+        assert_eq!(ram_size.ram_window(0x7FFFFC), RamWindow::Mapped(0x7FFFFC));
+        assert_eq!(ram_size.ram_window(0x200000), RamWindow::Mapped(0x200000));
+
+        // 2MB and 6MB locked
+        ram_size.write_u32(0, 0x888).unwrap();
+        assert_eq!(ram_size.ram_window(0x1FFFFC), RamWindow::Mapped(0x1FFFFC));
+        assert_eq!(ram_size.ram_window(0x200000), RamWindow::Locked);
+
+        // 2MB, 2MB HighZ and 4MB locked
+        ram_size.write_u32(0, 0xC88).unwrap();
+        assert_eq!(ram_size.ram_window(0x100), RamWindow::Mapped(0x100));
+        assert_eq!(ram_size.ram_window(0x3FFFFC), RamWindow::HighZ);
+        assert_eq!(ram_size.ram_window(0x400000), RamWindow::Locked);
+    }
+}
@@ -0,0 +1,141 @@
+//! What the CPU reads where no device answers.
+//!
+//! Only the upper 16MB of the physical space (the expansion regions, the I/O ports
+//! and the BIOS) is decoded so that every read completes, the rest of the space
+//! (after the RAM window) fails with a bus error on hardware.
+
+use std::ops::Range;
+
+/// What reads from unmapped addresses return, see
+/// [`PsxConfig::open_bus`](crate::PsxConfig::open_bus)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OpenBus {
+    /// Return what the hardware does where it doesn't fault, some games read
+    /// stray pointers and expect not to crash
+    #[default]
+    Lenient,
+    /// Fail with [`BusError::UnmappedRead`](super::BusError::UnmappedRead) for all
+    /// of them, to catch emulation bugs in tests
+    Strict,
+}
+
+/// The expansion bus multiplexes the address and the data, so with nothing
+/// connected, the address is still on the lines when the data is read
+const EXPANSION_REGIONS: [Range<u32>; 3] = [
+    0x1F000000..0x1F800000,
+    0x1F802000..0x1F804000,
+    0x1FA00000..0x1FC00000,
+];
+
+/// Reads here don't fault, the data lines keep the last value driven on them
+const NON_FAULTING: Range<u32> = 0x1F000000..0x20000000;
+
+/// The widths of the bus accesses
+pub(super) trait BusWord: Copy {
+    const BITS: u8;
+
+    /// Keep only the low bits of the width
+    fn from_bus(word: u32) -> Self;
+    fn to_bus(self) -> u32;
+}
+
+macro_rules! impl_bus_word {
+    ($($ty:ty),*) => {
+        $(
+            impl BusWord for $ty {
+                const BITS: u8 = <$ty>::BITS as u8;
+
+                fn from_bus(word: u32) -> Self {
+                    word as $ty
+                }
+
+                fn to_bus(self) -> u32 {
+                    self as u32
+                }
+            }
+        )*
+    };
+}
+
+impl_bus_word!(u8, u16, u32);
+
+/// The value read from the unmapped physical `addr`, in the low bits, `last_word`
+/// is the last value on the bus. `None` if the hardware faults there.
+pub(super) fn open_bus_value(addr: u32, last_word: u32) -> Option<u32> {
+    if EXPANSION_REGIONS.iter().any(|r| r.contains(&addr)) {
+        Some(addr)
+    } else if NON_FAULTING.contains(&addr) {
+        Some(floating_value(addr, last_word))
+    } else {
+        None
+    }
+}
+
+/// The value of a read at `addr` where nothing drives the data lines, in the low bits
+pub(super) fn floating_value(addr: u32, last_word: u32) -> u32 {
+    // the narrow accesses read their own byte lanes
+    last_word >> ((addr & 3) * 8)
+}
+
+/// `last_word` after an access of `bits` at `addr` drove `value` on its byte lanes
+pub(super) fn drive_lanes(last_word: u32, addr: u32, bits: u8, value: u32) -> u32 {
+    if bits == 32 {
+        return value;
+    }
+    let shift = (addr & 3) * 8;
+    let mask = ((1 << bits) - 1) << shift;
+    (last_word & !mask) | ((value << shift) & mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expansion_address_echo() {
+        for addr in [0x1F080000, 0x1F7FFFFC, 0x1F802100, 0x1F803FFE, 0x1FA00000] {
+            assert_eq!(open_bus_value(addr, 0x12345678), Some(addr), "{:08X}", addr);
+        }
+        assert_eq!(
+            u16::from_bus(open_bus_value(0x1FA01232, 0).unwrap()),
+            0x1232
+        );
+        assert_eq!(u8::from_bus(open_bus_value(0x1F080041, 0).unwrap()), 0x41);
+    }
+
+    #[test]
+    fn floating_io_reads() {
+        // the holes between the I/O ports, after the scratchpad, and after the BIOS
+        for addr in [0x1F801030, 0x1F800400, 0x1F804000, 0x1FC80000] {
+            assert_eq!(
+                open_bus_value(addr, 0x12345678),
+                Some(0x12345678),
+                "{:08X}",
+                addr
+            );
+        }
+        assert_eq!(
+            u16::from_bus(open_bus_value(0x1F801032, 0x12345678).unwrap()),
+            0x1234
+        );
+        assert_eq!(
+            u8::from_bus(open_bus_value(0x1F801031, 0x12345678).unwrap()),
+            0x56
+        );
+    }
+
+    #[test]
+    fn faulting_regions() {
+        for addr in [0x00800000, 0x0F000000, 0x1EFFFFFC, 0x20000000, 0xFFFE0000] {
+            assert_eq!(open_bus_value(addr, 0x12345678), None, "{:08X}", addr);
+        }
+    }
+
+    #[test]
+    fn byte_lanes() {
+        assert_eq!(drive_lanes(0x12345678, 0, 32, 0xAABBCCDD), 0xAABBCCDD);
+        assert_eq!(drive_lanes(0x12345678, 2, 16, 0xAABB), 0xAABB5678);
+        assert_eq!(drive_lanes(0x12345678, 1, 8, 0xFFCC), 0x1234CC78);
+        assert_eq!(drive_lanes(0x12345678, 0, 16, 0xAABB), 0x1234AABB);
+    }
+}
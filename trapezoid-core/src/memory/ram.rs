@@ -1,63 +1,87 @@
 use byteorder::{ByteOrder, LittleEndian};
 
 use crate::memory::Result;
+use crate::state_hash::StateHasher;
 
 use super::BusLine;
 
+/// The installed main RAM, see [`PsxConfig::ram_size`](crate::PsxConfig::ram_size)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RamSize {
+    /// Retail consoles
+    #[default]
+    Std2MB,
+    /// Development consoles (DTL-H2000 and similar)
+    Dev8MB,
+}
+
+impl RamSize {
+    pub fn bytes(self) -> usize {
+        match self {
+            RamSize::Std2MB => 0x200000,
+            RamSize::Dev8MB => 0x800000,
+        }
+    }
+}
+
+/// The RAM chips, addresses are mirrored every `size`, the mapping of the CPU
+/// address space is done with the `RAM_SIZE` register
 pub struct MainRam {
     data: Vec<u8>,
+    mask: usize,
 }
 
 impl Default for MainRam {
     fn default() -> Self {
-        Self {
-            data: vec![0; 0x200000],
-        }
+        Self::new(RamSize::default())
     }
 }
 
 impl MainRam {
-    pub fn put_at_address(&mut self, block_data: &[u8], addr: u32) {
-        let addr = (addr as usize) & 0x1FFFFF;
-        let block_len = block_data.len();
-        assert!((block_len + addr) < self.data.len());
+    pub fn new(size: RamSize) -> Self {
+        Self {
+            data: vec![0; size.bytes()],
+            mask: size.bytes() - 1,
+        }
+    }
 
-        self.data[addr..(addr + block_len)].copy_from_slice(block_data);
+    pub(crate) fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write(&self.data);
     }
 }
 
 impl BusLine for MainRam {
     fn read_u32(&mut self, addr: u32) -> Result<u32> {
-        let index = (addr as usize) & 0x1FFFFF;
+        let index = (addr as usize) & self.mask;
 
         Ok(LittleEndian::read_u32(&self.data[index..index + 4]))
     }
 
     fn write_u32(&mut self, addr: u32, data: u32) -> Result<()> {
-        let index = (addr as usize) & 0x1FFFFF;
+        let index = (addr as usize) & self.mask;
 
         LittleEndian::write_u32(&mut self.data[index..index + 4], data);
         Ok(())
     }
 
     fn read_u16(&mut self, addr: u32) -> Result<u16> {
-        let index = (addr as usize) & 0x1FFFFF;
+        let index = (addr as usize) & self.mask;
         Ok(LittleEndian::read_u16(&self.data[index..index + 2]))
     }
 
     fn write_u16(&mut self, addr: u32, data: u16) -> Result<()> {
-        let index = (addr as usize) & 0x1FFFFF;
+        let index = (addr as usize) & self.mask;
 
         LittleEndian::write_u16(&mut self.data[index..index + 2], data);
         Ok(())
     }
 
     fn read_u8(&mut self, addr: u32) -> Result<u8> {
-        Ok(self.data[(addr as usize) & 0x1FFFFF])
+        Ok(self.data[(addr as usize) & self.mask])
     }
 
     fn write_u8(&mut self, addr: u32, data: u8) -> Result<()> {
-        self.data[(addr as usize) & 0x1FFFFF] = data;
+        self.data[(addr as usize) & self.mask] = data;
         Ok(())
     }
 }
@@ -74,6 +98,12 @@ impl Default for Scratchpad {
     }
 }
 
+impl Scratchpad {
+    pub(crate) fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write(&self.data);
+    }
+}
+
 impl BusLine for Scratchpad {
     fn read_u32(&mut self, addr: u32) -> Result<u32> {
         let index = addr as usize;
@@ -110,3 +140,28 @@ impl BusLine for Scratchpad {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mirrors() {
+        let mut ram = MainRam::new(RamSize::Std2MB);
+        ram.write_u32(0x1000, 0x12345678).unwrap();
+        for mirror in [0x201000, 0x401000, 0x601000] {
+            assert_eq!(ram.read_u32(mirror).unwrap(), 0x12345678);
+        }
+        ram.write_u16(0x7FFFFE, 0xABCD).unwrap();
+        assert_eq!(ram.read_u16(0x1FFFFE).unwrap(), 0xABCD);
+
+        let mut ram = MainRam::new(RamSize::Dev8MB);
+        ram.write_u32(0x1000, 0x12345678).unwrap();
+        ram.write_u8(0x601000, 0xAA).unwrap();
+        for other in [0x201000, 0x401000] {
+            assert_eq!(ram.read_u32(other).unwrap(), 0);
+        }
+        assert_eq!(ram.read_u32(0x1000).unwrap(), 0x12345678);
+        assert_eq!(ram.read_u8(0x601000).unwrap(), 0xAA);
+    }
+}
@@ -0,0 +1,651 @@
+//! Reading and editing memory card images (`.mcd`, the raw 128KB format), without
+//! running the BIOS, for frontends that want to show and manage the saves.
+//!
+//! The card has 16 blocks of 8KB, block 0 holds the header and the directory, with
+//! one frame (128 bytes) for each of the blocks 1-15. A save is a chain of blocks,
+//! and its first block starts with the title and the icon.
+
+use std::{fmt, fs, io, path::Path};
+
+use byteorder::{ByteOrder, LittleEndian};
+
+const FRAME_SIZE: usize = 0x80;
+const BLOCK_SIZE: usize = 0x2000;
+const BLOCKS: usize = 16;
+/// The size of a `.mcd` image
+pub const MEMORY_CARD_SIZE: usize = BLOCK_SIZE * BLOCKS;
+
+/// The directory entry states
+const STATE_FIRST: u32 = 0x51;
+const STATE_MIDDLE: u32 = 0x52;
+const STATE_LAST: u32 = 0x53;
+const STATE_FREE: u32 = 0xA0;
+/// `A1h-A3h` are deleted saves, which can be overwritten
+const STATE_DELETED_LAST: u32 = 0xA3;
+const NO_NEXT_BLOCK: u16 = 0xFFFF;
+
+/// `0Ah-1Eh` in the directory entry, terminated with `00h`
+const FILENAME_OFFSET: usize = 0x0A;
+const FILENAME_MAX_LEN: usize = 20;
+
+const ICON_SIZE: usize = 16;
+
+#[derive(Debug)]
+pub enum MemoryCardError {
+    Io(io::Error),
+    /// The data is not a memory card image, with the reason
+    InvalidImage(String),
+    /// A directory entry or a save that can't be read, the save is skipped
+    CorruptedEntry {
+        block: usize,
+        reason: String,
+    },
+    SaveNotFound(String),
+    /// The `.mcs` data is malformed, with the reason
+    InvalidSave(String),
+    SaveExists(String),
+    NotEnoughSpace {
+        needed: usize,
+        free: usize,
+    },
+}
+
+impl std::error::Error for MemoryCardError {}
+impl fmt::Display for MemoryCardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemoryCardError::Io(e) => write!(f, "{}", e),
+            MemoryCardError::InvalidImage(s) => write!(f, "Invalid memory card image: {}", s),
+            MemoryCardError::CorruptedEntry { block, reason } => {
+                write!(f, "Corrupted block {}: {}", block, reason)
+            }
+            MemoryCardError::SaveNotFound(name) => write!(f, "No save named {:?}", name),
+            MemoryCardError::InvalidSave(s) => write!(f, "Invalid save file: {}", s),
+            MemoryCardError::SaveExists(name) => {
+                write!(f, "A save named {:?} already exists", name)
+            }
+            MemoryCardError::NotEnoughSpace { needed, free } => write!(
+                f,
+                "Not enough space, the save needs {} blocks, {} are free",
+                needed, free
+            ),
+        }
+    }
+}
+
+impl From<io::Error> for MemoryCardError {
+    fn from(e: io::Error) -> Self {
+        MemoryCardError::Io(e)
+    }
+}
+
+/// A save in a [`MemoryCard`], read from its directory entries and its first block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveInfo {
+    /// The filename in the directory, e.g. `BASLUS-00594GT`, this is what
+    /// identifies the save
+    pub name: String,
+    /// The game, from the filename, e.g. `SLUS-00594`
+    pub product_code: String,
+    /// The first block of the save (1-15)
+    pub first_block: usize,
+    /// The number of blocks used
+    pub blocks: usize,
+    /// Decoded from Shift-JIS, the characters that can't be decoded are `U+FFFD`
+    pub title: String,
+    /// 1 to 3 frames of the 16x16 icon in `RGB555`, row by row
+    pub icon_frames: Vec<[u16; ICON_SIZE * ICON_SIZE]>,
+}
+
+fn checksum(frame: &[u8]) -> u8 {
+    frame[..FRAME_SIZE - 1].iter().fold(0, |a, b| a ^ b)
+}
+
+fn corrupted<S: Into<String>>(block: usize, reason: S) -> MemoryCardError {
+    MemoryCardError::CorruptedEntry {
+        block,
+        reason: reason.into(),
+    }
+}
+
+fn filename(frame: &[u8]) -> String {
+    let name = &frame[FILENAME_OFFSET..FILENAME_OFFSET + FILENAME_MAX_LEN];
+    let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    String::from_utf8_lossy(&name[..len]).into_owned()
+}
+
+/// A memory card image
+pub struct MemoryCard {
+    data: Vec<u8>,
+}
+
+impl MemoryCard {
+    /// An empty card, formatted the same way as the BIOS does
+    pub fn formatted() -> Self {
+        fn set_frame(data: &mut [u8], index: usize, fill: impl Fn(&mut [u8])) {
+            let frame = &mut data[index * FRAME_SIZE..(index + 1) * FRAME_SIZE];
+            fill(frame);
+            frame[FRAME_SIZE - 1] = checksum(frame);
+        }
+        let header = |frame: &mut [u8]| frame[..2].copy_from_slice(b"MC");
+
+        let mut data = vec![0; MEMORY_CARD_SIZE];
+        set_frame(&mut data, 0, header);
+        for block in 1..BLOCKS {
+            set_frame(&mut data, block, |frame| {
+                LittleEndian::write_u32(&mut frame[0..], STATE_FREE);
+                LittleEndian::write_u16(&mut frame[8..], NO_NEXT_BLOCK);
+            });
+        }
+        // the broken sectors list, none
+        for index in 16..36 {
+            set_frame(&mut data, index, |frame| {
+                LittleEndian::write_u32(&mut frame[0..], 0xFFFFFFFF);
+                LittleEndian::write_u16(&mut frame[8..], NO_NEXT_BLOCK);
+            });
+        }
+        // the write test frame
+        set_frame(&mut data, 63, header);
+
+        Self { data }
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, MemoryCardError> {
+        if data.len() != MEMORY_CARD_SIZE {
+            return Err(MemoryCardError::InvalidImage(format!(
+                "the size is {} bytes, instead of {}",
+                data.len(),
+                MEMORY_CARD_SIZE
+            )));
+        }
+        if &data[..2] != b"MC" {
+            return Err(MemoryCardError::InvalidImage(
+                "the `MC` magic is missing".to_string(),
+            ));
+        }
+        Ok(Self { data })
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, MemoryCardError> {
+        Self::from_bytes(fs::read(path)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), MemoryCardError> {
+        fs::write(path, &self.data)?;
+        Ok(())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The saves in the card, the ones that can't be read are skipped,
+    /// see [`MemoryCard::directory_errors`]
+    pub fn list_saves(&self) -> Vec<SaveInfo> {
+        self.scan().0
+    }
+
+    /// The problems found in the directory, each one is a save (or an entry)
+    /// that is missing from [`MemoryCard::list_saves`]
+    pub fn directory_errors(&self) -> Vec<MemoryCardError> {
+        self.scan().1
+    }
+
+    /// The save `name` in the raw single save format (`.mcs`), the directory
+    /// entry followed by the blocks of the save
+    pub fn export_save_bytes(&self, name: &str) -> Result<Vec<u8>, MemoryCardError> {
+        let save = self
+            .list_saves()
+            .into_iter()
+            .find(|s| s.name == name)
+            .ok_or_else(|| MemoryCardError::SaveNotFound(name.to_string()))?;
+        let chain = self.chain(save.first_block)?;
+
+        let mut out = self.dir_frame(save.first_block).to_vec();
+        LittleEndian::write_u32(&mut out[4..], (chain.len() * BLOCK_SIZE) as u32);
+        LittleEndian::write_u16(&mut out[8..], NO_NEXT_BLOCK);
+        out[FRAME_SIZE - 1] = checksum(&out);
+        for block in chain {
+            out.extend_from_slice(self.block(block));
+        }
+        Ok(out)
+    }
+
+    pub fn export_save<P: AsRef<Path>>(&self, name: &str, path: P) -> Result<(), MemoryCardError> {
+        fs::write(path, self.export_save_bytes(name)?)?;
+        Ok(())
+    }
+
+    /// Add a save in the raw single save format (`.mcs`) into the free blocks
+    pub fn import_save_bytes(&mut self, mcs: &[u8]) -> Result<SaveInfo, MemoryCardError> {
+        let invalid = MemoryCardError::InvalidSave;
+        if mcs.len() < FRAME_SIZE {
+            return Err(invalid(format!(
+                "the file is too small ({} bytes) for the header",
+                mcs.len()
+            )));
+        }
+        let header = &mcs[..FRAME_SIZE];
+        if LittleEndian::read_u32(header) != STATE_FIRST {
+            return Err(invalid("the header is not a first block entry".to_string()));
+        }
+        let name = filename(header);
+        if name.is_empty() {
+            return Err(invalid("the filename is empty".to_string()));
+        }
+        let data = &mcs[FRAME_SIZE..];
+        if data.is_empty() || data.len() % BLOCK_SIZE != 0 || data.len() > BLOCK_SIZE * 15 {
+            return Err(invalid(format!(
+                "the save data is {:X} bytes, which is not 1-15 blocks",
+                data.len()
+            )));
+        }
+        if header[FRAME_SIZE - 1] != checksum(header) {
+            log::warn!("Memory card: the header checksum of {:?} is wrong", name);
+        }
+
+        if self.list_saves().iter().any(|s| s.name == name) {
+            return Err(MemoryCardError::SaveExists(name));
+        }
+        let free = (1..BLOCKS)
+            .filter(|&block| {
+                let state = LittleEndian::read_u32(self.dir_frame(block));
+                (STATE_FREE..=STATE_DELETED_LAST).contains(&state)
+            })
+            .collect::<Vec<_>>();
+        let needed = data.len() / BLOCK_SIZE;
+        if free.len() < needed {
+            return Err(MemoryCardError::NotEnoughSpace {
+                needed,
+                free: free.len(),
+            });
+        }
+
+        let blocks = &free[..needed];
+        for (i, (&block, block_data)) in blocks.iter().zip(data.chunks(BLOCK_SIZE)).enumerate() {
+            let state = if i == 0 {
+                STATE_FIRST
+            } else if i == needed - 1 {
+                STATE_LAST
+            } else {
+                STATE_MIDDLE
+            };
+            let next = blocks.get(i + 1).map_or(NO_NEXT_BLOCK, |&b| b as u16 - 1);
+
+            let frame = self.dir_frame_mut(block);
+            // the filename (and the rest) is only in the first entry
+            if i == 0 {
+                frame.copy_from_slice(header);
+                LittleEndian::write_u32(&mut frame[4..], data.len() as u32);
+            } else {
+                frame.fill(0);
+            }
+            LittleEndian::write_u32(&mut frame[0..], state);
+            LittleEndian::write_u16(&mut frame[8..], next);
+            frame[FRAME_SIZE - 1] = checksum(frame);
+
+            self.block_mut(block).copy_from_slice(block_data);
+        }
+
+        self.read_save(blocks[0])
+    }
+
+    pub fn import_save<P: AsRef<Path>>(&mut self, path: P) -> Result<SaveInfo, MemoryCardError> {
+        self.import_save_bytes(&fs::read(path)?)
+    }
+}
+
+impl MemoryCard {
+    fn dir_frame(&self, block: usize) -> &[u8] {
+        &self.data[block * FRAME_SIZE..(block + 1) * FRAME_SIZE]
+    }
+
+    fn dir_frame_mut(&mut self, block: usize) -> &mut [u8] {
+        &mut self.data[block * FRAME_SIZE..(block + 1) * FRAME_SIZE]
+    }
+
+    fn block(&self, block: usize) -> &[u8] {
+        &self.data[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE]
+    }
+
+    fn block_mut(&mut self, block: usize) -> &mut [u8] {
+        &mut self.data[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE]
+    }
+
+    fn scan(&self) -> (Vec<SaveInfo>, Vec<MemoryCardError>) {
+        let mut saves = Vec::new();
+        let mut errors = Vec::new();
+        for block in 1..BLOCKS {
+            let frame = self.dir_frame(block);
+            if frame[FRAME_SIZE - 1] != checksum(frame) {
+                errors.push(corrupted(block, "the directory entry checksum is wrong"));
+                continue;
+            }
+            if LittleEndian::read_u32(frame) != STATE_FIRST {
+                continue;
+            }
+            match self.read_save(block) {
+                Ok(save) => saves.push(save),
+                Err(e) => errors.push(e),
+            }
+        }
+        (saves, errors)
+    }
+
+    /// The blocks of the save starting at `first`, in order
+    fn chain(&self, first: usize) -> Result<Vec<usize>, MemoryCardError> {
+        let mut blocks = vec![first];
+        loop {
+            let current = *blocks.last().unwrap();
+            let next = LittleEndian::read_u16(&self.dir_frame(current)[8..]);
+            if next == NO_NEXT_BLOCK {
+                break;
+            }
+            let next = next as usize + 1;
+            if !(1..BLOCKS).contains(&next) || blocks.contains(&next) {
+                return Err(corrupted(
+                    first,
+                    format!("the block {} points to an invalid block {}", current, next),
+                ));
+            }
+            let state = LittleEndian::read_u32(self.dir_frame(next));
+            if state != STATE_MIDDLE && state != STATE_LAST {
+                return Err(corrupted(
+                    first,
+                    format!("the block {} points to an unused block {}", current, next),
+                ));
+            }
+            blocks.push(next);
+        }
+        Ok(blocks)
+    }
+
+    fn read_save(&self, first: usize) -> Result<SaveInfo, MemoryCardError> {
+        let frame = self.dir_frame(first);
+        let name = filename(frame);
+        let blocks = self.chain(first)?.len();
+        let size = LittleEndian::read_u32(&frame[4..]) as usize;
+        if size != blocks * BLOCK_SIZE {
+            return Err(corrupted(
+                first,
+                format!(
+                    "the size {:X} of {:?} doesn't match its {} blocks",
+                    size, name, blocks
+                ),
+            ));
+        }
+
+        let data = self.block(first);
+        if &data[..2] != b"SC" {
+            return Err(corrupted(
+                first,
+                format!("the title header of {:?} is missing", name),
+            ));
+        }
+        let icon_frames_count = match data[2] {
+            0x11 => 1,
+            0x12 => 2,
+            0x13 => 3,
+            flag => {
+                return Err(corrupted(
+                    first,
+                    format!("invalid icon flag {:02X} in {:?}", flag, name),
+                ))
+            }
+        };
+        let title = decode_shift_jis(&data[0x04..0x44]);
+
+        let mut palette = [0; 16];
+        LittleEndian::read_u16_into(&data[0x60..0x80], &mut palette);
+        let icon_frames = (1..=icon_frames_count)
+            .map(|index| {
+                let pixels = &data[index * FRAME_SIZE..(index + 1) * FRAME_SIZE];
+                let mut frame = [0; ICON_SIZE * ICON_SIZE];
+                // 4bit, the left pixel is in the low nibble
+                for (i, byte) in pixels.iter().enumerate() {
+                    frame[i * 2] = palette[(byte & 0xF) as usize];
+                    frame[i * 2 + 1] = palette[(byte >> 4) as usize];
+                }
+                frame
+            })
+            .collect();
+
+        Ok(SaveInfo {
+            product_code: name.get(2..12).unwrap_or_default().to_string(),
+            name,
+            first_block: first,
+            blocks,
+            title,
+            icon_frames,
+        })
+    }
+}
+
+/// Decode the save title, the titles mostly use the full width characters, these
+/// are converted to ASCII. Kanji are not supported.
+fn decode_shift_jis(data: &[u8]) -> String {
+    const UNKNOWN: char = char::REPLACEMENT_CHARACTER;
+    // the full width punctuation from `8140h`
+    const PUNCTUATION: &[(u8, char)] = &[
+        (0x40, ' '),
+        (0x43, ','),
+        (0x44, '.'),
+        (0x46, ':'),
+        (0x47, ';'),
+        (0x48, '?'),
+        (0x49, '!'),
+        (0x51, '_'),
+        (0x5B, '-'),
+        (0x5E, '/'),
+        (0x66, '\''),
+        (0x68, '"'),
+        (0x69, '('),
+        (0x6A, ')'),
+        (0x6D, '['),
+        (0x6E, ']'),
+        (0x7B, '+'),
+        (0x7C, '-'),
+        (0x81, '='),
+        (0x83, '<'),
+        (0x84, '>'),
+        (0x90, '$'),
+        (0x93, '%'),
+        (0x94, '#'),
+        (0x95, '&'),
+        (0x96, '*'),
+        (0x97, '@'),
+    ];
+    let offset_char = |base: u32, offset: u8| char::from_u32(base + offset as u32).unwrap();
+
+    let mut out = String::new();
+    let mut bytes = data.iter().copied();
+    while let Some(lead) = bytes.next() {
+        let c = match lead {
+            0 => break,
+            0x20..=0x7E => lead as char,
+            // half width katakana
+            0xA1..=0xDF => offset_char(0xFF61, lead - 0xA1),
+            0x81..=0x9F | 0xE0..=0xEF => {
+                let Some(trail) = bytes.next() else {
+                    out.push(UNKNOWN);
+                    break;
+                };
+                match (lead, trail) {
+                    (0x81, _) => PUNCTUATION
+                        .iter()
+                        .find(|(t, _)| *t == trail)
+                        .map_or(UNKNOWN, |(_, c)| *c),
+                    (0x82, 0x4F..=0x58) => (b'0' + trail - 0x4F) as char,
+                    (0x82, 0x60..=0x79) => (b'A' + trail - 0x60) as char,
+                    (0x82, 0x81..=0x9A) => (b'a' + trail - 0x81) as char,
+                    (0x82, 0x9F..=0xF1) => offset_char(0x3041, trail - 0x9F),
+                    // `7Fh` is not used as a trail byte
+                    (0x83, 0x40..=0x7E) => offset_char(0x30A1, trail - 0x40),
+                    (0x83, 0x80..=0x96) => offset_char(0x30E0, trail - 0x80),
+                    _ => UNKNOWN,
+                }
+            }
+            _ => UNKNOWN,
+        };
+        out.push(c);
+    }
+    // the titles are padded with spaces
+    out.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An `.mcs` save of `blocks` blocks, with a 2 frames icon
+    fn build_mcs(name: &str, blocks: usize) -> Vec<u8> {
+        let mut mcs = vec![0; FRAME_SIZE + blocks * BLOCK_SIZE];
+        LittleEndian::write_u32(&mut mcs[0..], STATE_FIRST);
+        LittleEndian::write_u32(&mut mcs[4..], (blocks * BLOCK_SIZE) as u32);
+        LittleEndian::write_u16(&mut mcs[8..], NO_NEXT_BLOCK);
+        mcs[FILENAME_OFFSET..FILENAME_OFFSET + name.len()].copy_from_slice(name.as_bytes());
+        mcs[FRAME_SIZE - 1] = checksum(&mcs);
+
+        let data = &mut mcs[FRAME_SIZE..];
+        data[..4].copy_from_slice(b"SC\x12\x01");
+        // "ＧＴ ｾｰﾌﾞ" with full width letters, full width space and half width katakana
+        let title = b"\x82\x66\x82\x73\x81\x40\xBE\xB0\xCC\xDE";
+        data[4..4 + title.len()].copy_from_slice(title);
+        // the palette, index 1 is red
+        LittleEndian::write_u16(&mut data[0x62..], 0x001F);
+        // first frame: the first pixel is red, second frame: the second pixel is red
+        data[FRAME_SIZE] = 0x01;
+        data[FRAME_SIZE * 2] = 0x10;
+        // something to check the order of the blocks
+        for (i, block) in data.chunks_mut(BLOCK_SIZE).enumerate() {
+            block[BLOCK_SIZE - 1] = i as u8 + 1;
+        }
+        mcs
+    }
+
+    #[test]
+    fn formatted_card() {
+        let card = MemoryCard::formatted();
+        assert!(card.list_saves().is_empty());
+        assert!(card.directory_errors().is_empty());
+        // the same data as the BIOS writes
+        assert_eq!(&card.as_bytes()[..2], b"MC");
+        assert_eq!(card.as_bytes()[0x7F], 0x0E);
+        assert_eq!(card.as_bytes()[0x80], 0xA0);
+        assert_eq!(card.as_bytes()[0xFF], 0xA0 ^ 0xFF ^ 0xFF);
+
+        let reopened = MemoryCard::from_bytes(card.as_bytes().to_vec()).unwrap();
+        assert_eq!(reopened.as_bytes(), card.as_bytes());
+    }
+
+    #[test]
+    fn invalid_images() {
+        assert!(matches!(
+            MemoryCard::from_bytes(vec![0; 0x1000]),
+            Err(MemoryCardError::InvalidImage(_))
+        ));
+        assert!(matches!(
+            MemoryCard::from_bytes(vec![0; MEMORY_CARD_SIZE]),
+            Err(MemoryCardError::InvalidImage(_))
+        ));
+    }
+
+    #[test]
+    fn import_list_export() {
+        let mut card = MemoryCard::formatted();
+        let single = build_mcs("BISLPS-00001SAVE", 1);
+        let multi = build_mcs("BASLUS-00594GT", 3);
+        card.import_save_bytes(&single).unwrap();
+        let imported = card.import_save_bytes(&multi).unwrap();
+
+        let mut red_first = [0; 256];
+        red_first[0] = 0x001F;
+        let mut red_second = [0; 256];
+        red_second[1] = 0x001F;
+        assert_eq!(
+            imported,
+            SaveInfo {
+                name: "BASLUS-00594GT".to_string(),
+                product_code: "SLUS-00594".to_string(),
+                first_block: 2,
+                blocks: 3,
+                title: "GT ｾｰﾌﾞ".to_string(),
+                icon_frames: vec![red_first, red_second],
+            }
+        );
+        let saves = card.list_saves();
+        assert_eq!(saves.len(), 2);
+        assert_eq!(saves[0].product_code, "SLPS-00001");
+        assert_eq!(saves[1], imported);
+        assert!(card.directory_errors().is_empty());
+
+        // the directory chain
+        let states = (1..5)
+            .map(|block| LittleEndian::read_u32(card.dir_frame(block)))
+            .collect::<Vec<_>>();
+        assert_eq!(states, [STATE_FIRST, STATE_FIRST, STATE_MIDDLE, STATE_LAST]);
+
+        assert_eq!(card.export_save_bytes("BASLUS-00594GT").unwrap(), multi);
+        assert_eq!(card.export_save_bytes("BISLPS-00001SAVE").unwrap(), single);
+        assert!(matches!(
+            card.export_save_bytes("BESLES-00000"),
+            Err(MemoryCardError::SaveNotFound(_))
+        ));
+        assert!(matches!(
+            card.import_save_bytes(&single),
+            Err(MemoryCardError::SaveExists(_))
+        ));
+        assert!(matches!(
+            card.import_save_bytes(&build_mcs("BISLPS-00002BIG", 12)),
+            Err(MemoryCardError::NotEnoughSpace {
+                needed: 12,
+                free: 11
+            })
+        ));
+        assert!(matches!(
+            card.import_save_bytes(&single[..FRAME_SIZE + 0x100]),
+            Err(MemoryCardError::InvalidSave(_))
+        ));
+    }
+
+    #[test]
+    fn corrupted_entries() {
+        let mut card = MemoryCard::formatted();
+        card.import_save_bytes(&build_mcs("BISLPS-00001A", 1))
+            .unwrap();
+        card.import_save_bytes(&build_mcs("BISLPS-00001B", 2))
+            .unwrap();
+        card.import_save_bytes(&build_mcs("BISLPS-00001C", 1))
+            .unwrap();
+
+        // bad checksum in the first save
+        card.dir_frame_mut(1)[0x20] = 1;
+        // the second save points to a free block
+        let frame = card.dir_frame_mut(2);
+        LittleEndian::write_u16(&mut frame[8..], 9);
+        frame[FRAME_SIZE - 1] = checksum(frame);
+        // the third save lost its title header
+        card.block_mut(4)[0] = 0;
+
+        assert!(card.list_saves().is_empty());
+        let errors = card.directory_errors();
+        let blocks = errors
+            .iter()
+            .map(|e| match e {
+                MemoryCardError::CorruptedEntry { block, .. } => *block,
+                e => panic!("unexpected error {:?}", e),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(blocks, [1, 2, 4]);
+    }
+
+    #[test]
+    fn shift_jis_titles() {
+        // full width "Ａｂ１！", hiragana "あ", katakana "ア", and a kanji
+        let title = b"\x82\x60\x82\x82\x82\x50\x81\x49\x82\xA0\x83\x41\x88\x9F\x81\x40\x81\x40";
+        assert_eq!(decode_shift_jis(title), "Ab1!あア\u{FFFD}");
+        assert_eq!(decode_shift_jis(b"ASCII\0garbage"), "ASCII");
+        // the trail byte is missing
+        assert_eq!(decode_shift_jis(b"A\x82"), "A\u{FFFD}");
+    }
+}
@@ -0,0 +1,530 @@
+//! Lockstep netplay between two emulators.
+//!
+//! Both peers run the same BIOS, disk and config, each one controls a controller
+//! port. Before a frame is emulated, the local input for it is sent to the peer,
+//! and the frame only runs when the peer input for the same frame arrived, so both
+//! emulators see the same input at the same frames and stay in the same state
+//! (see [`Psx::state_hash`]).
+//!
+//! Every [`NetplaySession::set_hash_interval`] frames the state hashes are exchanged,
+//! and a mismatch is reported as [`NetplayError::Desync`].
+//!
+//! There is no rollback, so every frame waits for a round trip to the peer.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+};
+
+use crossbeam::channel::{Receiver, Sender, TryRecvError};
+
+use crate::{cpu::CpuState, DigitalControllerKey, Psx};
+
+const DEFAULT_HASH_INTERVAL: u64 = 60;
+
+const PACKET_INPUT: u8 = 0;
+const PACKET_STATE_HASH: u8 = 1;
+
+#[derive(Debug)]
+pub enum NetplayError {
+    Io(io::Error),
+    /// The peer closed the connection
+    Disconnected,
+    /// The peer sent something that is not a netplay packet
+    InvalidPacket(Vec<u8>),
+    /// The emulators are not in the same state anymore, from `frame` or before it
+    Desync {
+        frame: u64,
+        local_hash: u64,
+        remote_hash: u64,
+    },
+}
+
+impl std::error::Error for NetplayError {}
+impl std::fmt::Display for NetplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NetplayError::Io(e) => write!(f, "Netplay connection error: {}", e),
+            NetplayError::Disconnected => write!(f, "The netplay peer disconnected"),
+            NetplayError::InvalidPacket(p) => write!(f, "Invalid netplay packet: {:02X?}", p),
+            NetplayError::Desync {
+                frame,
+                local_hash,
+                remote_hash,
+            } => write!(
+                f,
+                "Netplay desync at frame {}: local state {:016X}, remote state {:016X}",
+                frame, local_hash, remote_hash
+            ),
+        }
+    }
+}
+
+impl From<io::Error> for NetplayError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted => NetplayError::Disconnected,
+            _ => NetplayError::Io(e),
+        }
+    }
+}
+
+/// Carries the packets between the peers, packets must arrive whole and in order.
+pub trait NetplayTransport {
+    fn send(&mut self, packet: &[u8]) -> io::Result<()>;
+
+    /// Return the next packet if one arrived, without blocking.
+    ///
+    /// When the peer is gone, return an error of kind `UnexpectedEof`.
+    fn try_recv(&mut self) -> io::Result<Option<Vec<u8>>>;
+}
+
+/// A transport over TCP, every packet is prefixed with its length (one byte)
+pub struct TcpTransport {
+    stream: TcpStream,
+    recv_buffer: Vec<u8>,
+    closed: bool,
+}
+
+impl TcpTransport {
+    /// Use an already connected stream, for example from `TcpListener::accept`
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        // the packets are tiny, and each frame waits for them
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        Ok(Self {
+            stream,
+            recv_buffer: Vec::new(),
+            closed: false,
+        })
+    }
+
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Self::new(TcpStream::connect(addr)?)
+    }
+
+    fn take_packet(&mut self) -> Option<Vec<u8>> {
+        let len = *self.recv_buffer.first()? as usize;
+        if self.recv_buffer.len() <= len {
+            return None;
+        }
+        let packet = self.recv_buffer[1..=len].to_vec();
+        self.recv_buffer.drain(..=len);
+        Some(packet)
+    }
+}
+
+impl NetplayTransport for TcpTransport {
+    fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+        assert!(packet.len() <= u8::MAX as usize, "netplay packet too large");
+        let mut data = Vec::with_capacity(packet.len() + 1);
+        data.push(packet.len() as u8);
+        data.extend_from_slice(packet);
+
+        // the socket is non-blocking for `try_recv`, but the packets are small
+        // so this will not spin for long
+        let mut written = 0;
+        while written < data.len() {
+            match self.stream.write(&data[written..]) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => written += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => std::thread::yield_now(),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn try_recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut buf = [0; 256];
+        while !self.closed {
+            match self.stream.read(&mut buf) {
+                Ok(0) => self.closed = true,
+                Ok(n) => self.recv_buffer.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        // the packets received before closing are still delivered
+        match self.take_packet() {
+            Some(packet) => Ok(Some(packet)),
+            None if self.closed => Err(io::ErrorKind::UnexpectedEof.into()),
+            None => Ok(None),
+        }
+    }
+}
+
+/// A transport between two sessions in the same process, for tests and
+/// local multiplayer through the netplay path
+pub struct MemoryTransport {
+    sender: Sender<Vec<u8>>,
+    receiver: Receiver<Vec<u8>>,
+}
+
+impl MemoryTransport {
+    /// Two connected ends, what is sent from one is received by the other
+    pub fn pair() -> (Self, Self) {
+        let (a_sender, b_receiver) = crossbeam::channel::unbounded();
+        let (b_sender, a_receiver) = crossbeam::channel::unbounded();
+        (
+            Self {
+                sender: a_sender,
+                receiver: a_receiver,
+            },
+            Self {
+                sender: b_sender,
+                receiver: b_receiver,
+            },
+        )
+    }
+}
+
+impl NetplayTransport for MemoryTransport {
+    fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+        self.sender
+            .send(packet.to_vec())
+            .map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn try_recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        match self.receiver.try_recv() {
+            Ok(packet) => Ok(Some(packet)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Packet {
+    /// The pressed keys of the sender, bits from [`DigitalControllerKey::mask`]
+    Input { frame: u64, keys: u16 },
+    /// [`Psx::state_hash`] after running `frame` frames
+    StateHash { frame: u64, hash: u64 },
+}
+
+impl Packet {
+    fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(17);
+        match *self {
+            Packet::Input { frame, keys } => {
+                data.push(PACKET_INPUT);
+                data.extend_from_slice(&frame.to_le_bytes());
+                data.extend_from_slice(&keys.to_le_bytes());
+            }
+            Packet::StateHash { frame, hash } => {
+                data.push(PACKET_STATE_HASH);
+                data.extend_from_slice(&frame.to_le_bytes());
+                data.extend_from_slice(&hash.to_le_bytes());
+            }
+        }
+        data
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        let (&ty, rest) = data.split_first()?;
+        let frame = u64::from_le_bytes(rest.get(..8)?.try_into().unwrap());
+        let payload = &rest[8..];
+        match (ty, payload.len()) {
+            (PACKET_INPUT, 2) => Some(Packet::Input {
+                frame,
+                keys: u16::from_le_bytes(payload.try_into().unwrap()),
+            }),
+            (PACKET_STATE_HASH, 8) => Some(Packet::StateHash {
+                frame,
+                hash: u64::from_le_bytes(payload.try_into().unwrap()),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// The result of [`NetplaySession::poll_frame`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetplayStatus {
+    /// The peer input for the frame did not arrive yet, nothing was emulated
+    Waiting,
+    /// A frame was emulated
+    FrameDone,
+}
+
+/// A [`Psx`] that runs in lockstep with a peer, see the [module docs](self)
+pub struct NetplaySession<T: NetplayTransport> {
+    psx: Psx,
+    transport: T,
+    local_port: usize,
+    /// The frames emulated so far, which is also the next frame to run
+    frame: u64,
+    /// The local input of `frame`, once it's sent it can't change
+    sent_local_keys: Option<u16>,
+    remote_inputs: BTreeMap<u64, u16>,
+    /// The keys pressed in each port as given to the emulator
+    port_keys: [u16; 2],
+    hash_interval: u64,
+    local_hashes: BTreeMap<u64, u64>,
+    remote_hashes: BTreeMap<u64, u64>,
+}
+
+impl<T: NetplayTransport> NetplaySession<T> {
+    /// `local_port` (`0` or `1`) is controlled by this side, the other port by the peer,
+    /// the controllers in both ports are connected.
+    ///
+    /// `psx` should be freshly created (or reset) on both sides with the same BIOS,
+    /// disk and config.
+    ///
+    /// # Panics
+    ///
+    /// If `local_port` is not `0` or `1`.
+    pub fn new(mut psx: Psx, transport: T, local_port: usize) -> Self {
+        assert!(local_port < 2, "invalid controller port {}", local_port);
+        psx.set_controller_connected(0, true);
+        psx.set_controller_connected(1, true);
+        Self {
+            psx,
+            transport,
+            local_port,
+            frame: 0,
+            sent_local_keys: None,
+            remote_inputs: BTreeMap::new(),
+            port_keys: [0; 2],
+            hash_interval: DEFAULT_HASH_INTERVAL,
+            local_hashes: BTreeMap::new(),
+            remote_hashes: BTreeMap::new(),
+        }
+    }
+
+    /// Exchange the state hashes every `frames` frames (`60` by default), `0` disables
+    /// the desync check. Both peers must use the same interval.
+    pub fn set_hash_interval(&mut self, frames: u64) {
+        self.hash_interval = frames;
+    }
+
+    /// The frames emulated so far
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn local_port(&self) -> usize {
+        self.local_port
+    }
+
+    /// For the video and audio output, the emulation state must only be changed
+    /// through the session, or the peers will desync
+    pub fn psx(&mut self) -> &mut Psx {
+        &mut self.psx
+    }
+
+    pub fn into_psx(self) -> Psx {
+        self.psx
+    }
+
+    /// Try to run the next frame with the local pressed keys `local_keys` (bits from
+    /// [`DigitalControllerKey::mask`]), without blocking.
+    ///
+    /// The local input is sent on the first call for a frame, while the peer input
+    /// didn't arrive the result is [`NetplayStatus::Waiting`] and `local_keys` is ignored
+    /// until the frame runs, so this should be called again (with any keys) until
+    /// the frame is done.
+    pub fn poll_frame(&mut self, local_keys: u16) -> Result<NetplayStatus, NetplayError> {
+        let local_keys = match self.sent_local_keys {
+            Some(keys) => keys,
+            None => {
+                self.send(Packet::Input {
+                    frame: self.frame,
+                    keys: local_keys,
+                })?;
+                self.sent_local_keys = Some(local_keys);
+                local_keys
+            }
+        };
+
+        self.receive_packets()?;
+
+        let Some(remote_keys) = self.remote_inputs.remove(&self.frame) else {
+            return Ok(NetplayStatus::Waiting);
+        };
+        self.apply_keys(self.local_port, local_keys);
+        self.apply_keys(1 - self.local_port, remote_keys);
+        self.run_frame();
+        self.frame += 1;
+        self.sent_local_keys = None;
+
+        if self.hash_interval != 0 && self.frame % self.hash_interval == 0 {
+            let hash = self.psx.state_hash();
+            self.send(Packet::StateHash {
+                frame: self.frame,
+                hash,
+            })?;
+            self.local_hashes.insert(self.frame, hash);
+            self.check_hashes()?;
+        }
+
+        Ok(NetplayStatus::FrameDone)
+    }
+
+    /// Like [`NetplaySession::poll_frame`], but wait for the peer input
+    pub fn run_frame_blocking(&mut self, local_keys: u16) -> Result<(), NetplayError> {
+        while self.poll_frame(local_keys)? == NetplayStatus::Waiting {
+            std::thread::yield_now();
+        }
+        Ok(())
+    }
+}
+
+impl<T: NetplayTransport> NetplaySession<T> {
+    fn send(&mut self, packet: Packet) -> Result<(), NetplayError> {
+        Ok(self.transport.send(&packet.encode())?)
+    }
+
+    fn receive_packets(&mut self) -> Result<(), NetplayError> {
+        while let Some(data) = self.transport.try_recv()? {
+            match Packet::decode(&data) {
+                Some(Packet::Input { frame, keys }) => {
+                    self.remote_inputs.insert(frame, keys);
+                }
+                Some(Packet::StateHash { frame, hash }) => {
+                    self.remote_hashes.insert(frame, hash);
+                }
+                None => return Err(NetplayError::InvalidPacket(data)),
+            }
+        }
+        self.check_hashes()
+    }
+
+    /// Compare the hashes of the frames that both sides reached
+    fn check_hashes(&mut self) -> Result<(), NetplayError> {
+        while let Some((&frame, &remote_hash)) = self.remote_hashes.first_key_value() {
+            let Some(local_hash) = self.local_hashes.remove(&frame) else {
+                break;
+            };
+            self.remote_hashes.remove(&frame);
+            if local_hash != remote_hash {
+                return Err(NetplayError::Desync {
+                    frame,
+                    local_hash,
+                    remote_hash,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_keys(&mut self, port: usize, keys: u16) {
+        let changed = self.port_keys[port] ^ keys;
+        for key in DigitalControllerKey::ALL {
+            if changed & key.mask() != 0 {
+                self.psx
+                    .change_port_controller_key_state(port, key, keys & key.mask() != 0);
+            }
+        }
+        self.port_keys[port] = keys;
+    }
+
+    fn run_frame(&mut self) {
+        loop {
+            let (frame_done, cpu_state) = self.psx.clock_based_on_video(u32::MAX);
+            if frame_done {
+                break;
+            }
+            // breakpoints are not supported in netplay, the frame must finish
+            if cpu_state != CpuState::Normal {
+                log::warn!("netplay: ignoring CPU state {:?}", cpu_state);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::TcpListener;
+
+    #[test]
+    fn packet_round_trip() {
+        for packet in [
+            Packet::Input {
+                frame: 0x0102030405060708,
+                keys: 0xA55A,
+            },
+            Packet::StateHash {
+                frame: 60,
+                hash: 0xCBF29CE484222325,
+            },
+        ] {
+            assert_eq!(Packet::decode(&packet.encode()), Some(packet));
+        }
+
+        assert_eq!(Packet::decode(&[]), None);
+        assert_eq!(Packet::decode(&[PACKET_INPUT, 0, 0]), None);
+        // wrong payload size for the type
+        let mut data = Packet::Input { frame: 1, keys: 2 }.encode();
+        data[0] = PACKET_STATE_HASH;
+        assert_eq!(Packet::decode(&data), None);
+    }
+
+    #[test]
+    fn memory_transport() {
+        let (mut a, mut b) = MemoryTransport::pair();
+        assert_eq!(b.try_recv().unwrap(), None);
+        a.send(&[1, 2, 3]).unwrap();
+        a.send(&[4]).unwrap();
+        assert_eq!(b.try_recv().unwrap(), Some(vec![1, 2, 3]));
+        assert_eq!(b.try_recv().unwrap(), Some(vec![4]));
+        assert_eq!(b.try_recv().unwrap(), None);
+
+        drop(a);
+        assert_eq!(
+            b.try_recv().unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn tcp_transport() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpTransport::connect(listener.local_addr().unwrap()).unwrap();
+        let mut server = TcpTransport::new(listener.accept().unwrap().0).unwrap();
+
+        let packets = [vec![1, 2, 3], vec![], vec![0xFF; 17]];
+        for packet in &packets {
+            client.send(packet).unwrap();
+        }
+
+        let mut received = Vec::new();
+        while received.len() < packets.len() {
+            if let Some(packet) = server.try_recv().unwrap() {
+                received.push(packet);
+            }
+        }
+        assert_eq!(received, packets);
+
+        // the packets sent before closing are received first
+        server.send(&[7]).unwrap();
+        drop(server);
+        let packet = loop {
+            if let Some(packet) = client.try_recv().unwrap() {
+                break packet;
+            }
+        };
+        assert_eq!(packet, [7]);
+        let err = loop {
+            match client.try_recv() {
+                Ok(None) => {}
+                Ok(Some(p)) => panic!("unexpected packet {:?}", p),
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(
+            NetplayError::from(err).to_string(),
+            "The netplay peer disconnected"
+        );
+    }
+}
@@ -0,0 +1,88 @@
+//! Counters of the work done by the emulated CPU and DMA, to find out where the
+//! time of a frame goes, see [`Psx::perf_frame_report`](crate::Psx::perf_frame_report).
+//!
+//! The counters are plain increments, with the `no-perf-counters` feature, they
+//! are compiled out and the reports are all zeros.
+
+/// The number of exception codes, the `ExcCode` field of the `cause` register
+pub const EXCEPTION_CAUSES: usize = 0x0D;
+
+/// The work done by the emulated hardware in one video frame, from one vblank to
+/// the next.
+///
+/// There is no instruction cache emulation, so cache misses are not counted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PerfFrameReport {
+    /// Executed instructions, the calls handled by the HLE BIOS are not counted
+    pub instructions_retired: u64,
+    /// Exceptions taken, indexed by the exception code, `0x00` is interrupts
+    /// and `0x08` is `syscall`
+    pub exceptions: [u32; EXCEPTION_CAUSES],
+    /// CPU cycles stalled by DMA transfers, by channel
+    pub dma_cycles: [u32; 7],
+}
+
+impl PerfFrameReport {
+    pub fn total_exceptions(&self) -> u32 {
+        self.exceptions.iter().sum()
+    }
+
+    pub fn total_dma_cycles(&self) -> u32 {
+        self.dma_cycles.iter().sum()
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct CpuCounters {
+    instructions_retired: u64,
+    exceptions: [u32; EXCEPTION_CAUSES],
+}
+
+impl CpuCounters {
+    #[inline(always)]
+    pub fn instruction_retired(&mut self) {
+        #[cfg(not(feature = "no-perf-counters"))]
+        {
+            self.instructions_retired += 1;
+        }
+    }
+
+    #[inline(always)]
+    #[cfg_attr(feature = "no-perf-counters", allow(unused_variables))]
+    pub fn exception(&mut self, cause_code: u8) {
+        #[cfg(not(feature = "no-perf-counters"))]
+        {
+            self.exceptions[cause_code as usize] += 1;
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct DmaCounters {
+    cycles: [u32; 7],
+}
+
+impl DmaCounters {
+    #[inline(always)]
+    #[cfg_attr(feature = "no-perf-counters", allow(unused_variables))]
+    pub fn transfer(&mut self, channel: usize, cycles: u32) {
+        #[cfg(not(feature = "no-perf-counters"))]
+        {
+            self.cycles[channel] += cycles;
+        }
+    }
+}
+
+impl PerfFrameReport {
+    /// Build the report from the counters and reset them for the next frame
+    pub(crate) fn take(cpu: &mut CpuCounters, dma: &mut DmaCounters) -> Self {
+        let cpu = std::mem::take(cpu);
+        let dma = std::mem::take(dma);
+        Self {
+            instructions_retired: cpu.instructions_retired,
+            exceptions: cpu.exceptions,
+            dma_cycles: dma.cycles,
+        }
+    }
+}
@@ -0,0 +1,31 @@
+//! Console regions, shared by the BIOS and disc detection.
+
+/// The region of a BIOS or a disc
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Region {
+    /// NTSC-J
+    Japan,
+    /// NTSC-U
+    NorthAmerica,
+    /// PAL
+    Europe,
+}
+
+impl Region {
+    /// Whether consoles of this region output PAL video (`50Hz`), otherwise NTSC (`60Hz`)
+    pub fn is_pal(self) -> bool {
+        matches!(self, Region::Europe)
+    }
+}
+
+/// Force the video standard, regardless of what the BIOS and the game set in `GPUSTAT`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RegionOverride {
+    /// Use the mode the game sets
+    #[default]
+    Auto,
+    /// `60Hz`, also useful for PAL games that run fine at NTSC speed (PAL60)
+    Ntsc,
+    /// `50Hz`
+    Pal,
+}
@@ -0,0 +1,113 @@
+//! Hooks for scripts to automate the emulator, for tool-assisted runs, bots or
+//! research.
+//!
+//! The core doesn't depend on a scripting language, the embedding crate
+//! implements [`ScriptEngine`] for the language it wants, and calls into
+//! the emulator with the [`ScriptHandle`] it gets in the callbacks.
+
+use std::path::Path;
+
+use crate::cpu::{RegisterType, Registers};
+use crate::memory::{BusError, BusLine, Result};
+use crate::{DigitalControllerKey, Psx};
+
+/// The callbacks of a script, attached with [`Psx::attach_script_engine`]
+///
+/// They run on the emulation thread, in the middle of the emulation, so they
+/// should be quick.
+pub trait ScriptEngine: Send {
+    /// Called once when attached, e.g. to add breakpoints
+    fn on_attach(&mut self, _psx: &mut ScriptHandle) {}
+
+    /// Called at the start of every vblank
+    fn on_frame(&mut self, _psx: &mut ScriptHandle) {}
+
+    /// Called before the instruction at `addr` executes, only for the breakpoints
+    /// added with [`ScriptHandle::add_breakpoint`]
+    fn on_breakpoint(&mut self, _psx: &mut ScriptHandle, _addr: u32) {}
+}
+
+/// What a script can do with the emulator, only available inside the callbacks
+/// of [`ScriptEngine`]
+pub struct ScriptHandle<'a> {
+    psx: &'a mut Psx,
+}
+
+impl<'a> ScriptHandle<'a> {
+    pub(crate) fn new(psx: &'a mut Psx) -> Self {
+        Self { psx }
+    }
+
+    pub fn bus_read_u32(&mut self, addr: u32) -> Result<u32> {
+        self.psx.bus_read_u32(addr)
+    }
+
+    pub fn bus_read_u16(&mut self, addr: u32) -> Result<u16> {
+        self.psx.bus_read_u16(addr)
+    }
+
+    pub fn bus_read_u8(&mut self, addr: u32) -> Result<u8> {
+        self.psx.bus_read_u8(addr)
+    }
+
+    /// Write the same way the CPU would, a write to a hardware register
+    /// has the same effect as if the game did it
+    pub fn bus_write_u32(&mut self, addr: u32, data: u32) -> Result<()> {
+        if addr % 4 != 0 {
+            return Err(BusError::UnalignedAccess { addr, size: 32 });
+        }
+        self.psx.bus.write_u32(addr, data)
+    }
+
+    pub fn bus_write_u16(&mut self, addr: u32, data: u16) -> Result<()> {
+        if addr % 2 != 0 {
+            return Err(BusError::UnalignedAccess { addr, size: 16 });
+        }
+        self.psx.bus.write_u16(addr, data)
+    }
+
+    pub fn bus_write_u8(&mut self, addr: u32, data: u8) -> Result<()> {
+        self.psx.bus.write_u8(addr, data)
+    }
+
+    pub fn registers(&self) -> &Registers {
+        self.psx.cpu.registers()
+    }
+
+    pub fn set_register(&mut self, ty: RegisterType, value: u32) {
+        self.psx.cpu.registers_mut().write(ty, value);
+    }
+
+    /// Change the key state of the controller in `port`, the game sees it
+    /// in its next poll
+    pub fn set_key_state(&mut self, port: usize, key: DigitalControllerKey, pressed: bool) {
+        self.psx
+            .change_port_controller_key_state(port, key, pressed);
+    }
+
+    /// The number of frames emulated so far
+    pub fn frame(&self) -> u64 {
+        self.psx.elapsed_frames()
+    }
+
+    /// See [`Psx::capture_screenshot`]
+    pub fn capture_screenshot<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<()> {
+        self.psx.capture_screenshot(path)
+    }
+
+    /// Call [`ScriptEngine::on_breakpoint`] before the instruction at `addr`
+    /// executes, the emulation continues after the callback.
+    ///
+    /// These are shared with the debugger breakpoints, so a debugger breakpoint
+    /// at the same address won't pause the emulation.
+    pub fn add_breakpoint(&mut self, addr: u32) {
+        self.psx.cpu.debugger().add_breakpoint(addr);
+        self.psx.script_breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u32) {
+        if self.psx.script_breakpoints.remove(&addr) {
+            self.psx.cpu.debugger().remove_breakpoint(addr);
+        }
+    }
+}
@@ -1,13 +1,20 @@
-use std::{
-    cell::Cell,
-    collections::VecDeque,
-    ops::{Index, IndexMut, Range},
-};
+use std::collections::VecDeque;
 
-use crate::memory::{interrupts::InterruptRequester, BusLine, Result};
+use crate::memory::{interrupts::InterruptRequester, BusError, BusLine, Result};
+use crate::state_hash::StateHasher;
+use crate::trace::{TraceEvent, Tracer};
+use crate::warnings::Warnings;
 
 const CPU_CLOCKS_PER_SPU: u32 = 0x300;
-
+/// Half a second of CD audio, the CD-ROM only delivers a sector ahead
+const MAX_CDROM_AUDIO_SAMPLES: usize = 44100 / 2;
+/// Half a second of external audio, the host should push about a frame ahead
+const MAX_EXTERNAL_AUDIO_SAMPLES: usize = 44100 / 2;
+/// The steps of the ADSR and the volume sweeps, from the 2 step bits
+const STEPS_POS: &[i16; 4] = &[7, 6, 5, 4];
+const STEPS_NEG: &[i16; 4] = &[-8, -7, -6, -5];
+
+#[derive(Debug)]
 enum RamTransferMode {
     Stop,
     ManualWrite,
@@ -60,6 +67,88 @@ bitflags::bitflags! {
     }
 }
 
+/// The weights of the 4-tap gaussian interpolation of the voices, the same table as
+/// the hardware. The 4 weights of a position are `0x0FF - i`, `0x1FF - i`, `0x100 + i`
+/// and `i`, from the oldest sample to the newest.
+#[rustfmt::skip]
+const GAUSS_TABLE: &[i32; 512] = &[
+    -0x001, -0x001, -0x001, -0x001, -0x001, -0x001, -0x001, -0x001,
+    -0x001, -0x001, -0x001, -0x001, -0x001, -0x001, -0x001, -0x001,
+    0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0001,
+    0x0001, 0x0001, 0x0001, 0x0002, 0x0002, 0x0002, 0x0003, 0x0003,
+    0x0003, 0x0004, 0x0004, 0x0005, 0x0005, 0x0006, 0x0007, 0x0007,
+    0x0008, 0x0009, 0x0009, 0x000A, 0x000B, 0x000C, 0x000D, 0x000E,
+    0x000F, 0x0010, 0x0011, 0x0012, 0x0013, 0x0015, 0x0016, 0x0018,
+    0x0019, 0x001B, 0x001C, 0x001E, 0x0020, 0x0021, 0x0023, 0x0025,
+    0x0027, 0x0029, 0x002C, 0x002E, 0x0030, 0x0033, 0x0035, 0x0038,
+    0x003A, 0x003D, 0x0040, 0x0043, 0x0046, 0x0049, 0x004D, 0x0050,
+    0x0054, 0x0057, 0x005B, 0x005F, 0x0063, 0x0067, 0x006B, 0x006F,
+    0x0074, 0x0078, 0x007D, 0x0082, 0x0087, 0x008C, 0x0091, 0x0096,
+    0x009C, 0x00A1, 0x00A7, 0x00AD, 0x00B3, 0x00BA, 0x00C0, 0x00C7,
+    0x00CD, 0x00D4, 0x00DB, 0x00E3, 0x00EA, 0x00F2, 0x00FA, 0x0101,
+    0x010A, 0x0112, 0x011B, 0x0123, 0x012C, 0x0135, 0x013F, 0x0148,
+    0x0152, 0x015C, 0x0166, 0x0171, 0x017B, 0x0186, 0x0191, 0x019C,
+    0x01A8, 0x01B4, 0x01C0, 0x01CC, 0x01D9, 0x01E5, 0x01F2, 0x0200,
+    0x020D, 0x021B, 0x0229, 0x0237, 0x0246, 0x0255, 0x0264, 0x0273,
+    0x0283, 0x0293, 0x02A3, 0x02B4, 0x02C4, 0x02D6, 0x02E7, 0x02F9,
+    0x030B, 0x031D, 0x0330, 0x0343, 0x0356, 0x036A, 0x037E, 0x0392,
+    0x03A7, 0x03BC, 0x03D1, 0x03E7, 0x03FC, 0x0413, 0x042A, 0x0441,
+    0x0458, 0x0470, 0x0488, 0x04A0, 0x04B9, 0x04D2, 0x04EC, 0x0506,
+    0x0520, 0x053B, 0x0556, 0x0572, 0x058E, 0x05AA, 0x05C7, 0x05E4,
+    0x0601, 0x061F, 0x063E, 0x065C, 0x067C, 0x069B, 0x06BB, 0x06DC,
+    0x06FD, 0x071E, 0x0740, 0x0762, 0x0784, 0x07A7, 0x07CB, 0x07EF,
+    0x0813, 0x0838, 0x085D, 0x0883, 0x08A9, 0x08D0, 0x08F7, 0x091E,
+    0x0946, 0x096F, 0x0998, 0x09C1, 0x09EB, 0x0A16, 0x0A40, 0x0A6C,
+    0x0A98, 0x0AC4, 0x0AF1, 0x0B1E, 0x0B4C, 0x0B7A, 0x0BA9, 0x0BD8,
+    0x0C07, 0x0C38, 0x0C68, 0x0C99, 0x0CCB, 0x0CFD, 0x0D30, 0x0D63,
+    0x0D97, 0x0DCB, 0x0E00, 0x0E35, 0x0E6B, 0x0EA1, 0x0ED7, 0x0F0F,
+    0x0F46, 0x0F7F, 0x0FB7, 0x0FF1, 0x102A, 0x1065, 0x109F, 0x10DB,
+    0x1116, 0x1153, 0x118F, 0x11CD, 0x120B, 0x1249, 0x1288, 0x12C7,
+    0x1307, 0x1347, 0x1388, 0x13C9, 0x140B, 0x144D, 0x1490, 0x14D4,
+    0x1517, 0x155C, 0x15A0, 0x15E6, 0x162C, 0x1672, 0x16B9, 0x1700,
+    0x1747, 0x1790, 0x17D8, 0x1821, 0x186B, 0x18B5, 0x1900, 0x194B,
+    0x1996, 0x19E2, 0x1A2E, 0x1A7B, 0x1AC8, 0x1B16, 0x1B64, 0x1BB3,
+    0x1C02, 0x1C51, 0x1CA1, 0x1CF1, 0x1D42, 0x1D93, 0x1DE5, 0x1E37,
+    0x1E89, 0x1EDC, 0x1F2F, 0x1F82, 0x1FD6, 0x202A, 0x207F, 0x20D4,
+    0x2129, 0x217F, 0x21D5, 0x222C, 0x2282, 0x22DA, 0x2331, 0x2389,
+    0x23E1, 0x2439, 0x2492, 0x24EB, 0x2545, 0x259E, 0x25F8, 0x2653,
+    0x26AD, 0x2708, 0x2763, 0x27BE, 0x281A, 0x2876, 0x28D2, 0x292E,
+    0x298B, 0x29E7, 0x2A44, 0x2AA1, 0x2AFF, 0x2B5C, 0x2BBA, 0x2C18,
+    0x2C76, 0x2CD4, 0x2D33, 0x2D91, 0x2DF0, 0x2E4F, 0x2EAE, 0x2F0D,
+    0x2F6C, 0x2FCC, 0x302B, 0x308B, 0x30EA, 0x314A, 0x31AA, 0x3209,
+    0x3269, 0x32C9, 0x3329, 0x3389, 0x33E9, 0x3449, 0x34A9, 0x3509,
+    0x3569, 0x35C9, 0x3629, 0x3689, 0x36E8, 0x3748, 0x37A8, 0x3807,
+    0x3867, 0x38C6, 0x3926, 0x3985, 0x39E4, 0x3A43, 0x3AA2, 0x3B00,
+    0x3B5F, 0x3BBD, 0x3C1B, 0x3C79, 0x3CD7, 0x3D34, 0x3D92, 0x3DEF,
+    0x3E4C, 0x3EA8, 0x3F05, 0x3F61, 0x3FBD, 0x4018, 0x4074, 0x40CF,
+    0x4129, 0x4184, 0x41DE, 0x4237, 0x4291, 0x42EA, 0x4342, 0x439B,
+    0x43F3, 0x444A, 0x44A1, 0x44F8, 0x454F, 0x45A5, 0x45FA, 0x4650,
+    0x46A5, 0x46F9, 0x474D, 0x47A1, 0x47F4, 0x4846, 0x4899, 0x48EA,
+    0x493C, 0x498D, 0x49DD, 0x4A2D, 0x4A7D, 0x4ACC, 0x4B1A, 0x4B68,
+    0x4BB6, 0x4C03, 0x4C50, 0x4C9C, 0x4CE8, 0x4D33, 0x4D7E, 0x4DC8,
+    0x4E12, 0x4E5B, 0x4EA3, 0x4EEC, 0x4F33, 0x4F7A, 0x4FC1, 0x5007,
+    0x504D, 0x5092, 0x50D6, 0x511A, 0x515D, 0x51A0, 0x51E2, 0x5224,
+    0x5265, 0x52A5, 0x52E5, 0x5325, 0x5363, 0x53A2, 0x53DF, 0x541C,
+    0x5459, 0x5495, 0x54D0, 0x550B, 0x5545, 0x557F, 0x55B8, 0x55F0,
+    0x5628, 0x565F, 0x5696, 0x56CC, 0x5701, 0x5736, 0x576A, 0x579D,
+    0x57D0, 0x5802, 0x5833, 0x5864, 0x5894, 0x58C4, 0x58F3, 0x5921,
+    0x594F, 0x597C, 0x59A8, 0x59D4, 0x59FF, 0x5A29, 0x5A53, 0x5A7C,
+    0x5AA4, 0x5ACC, 0x5AF3, 0x5B19, 0x5B3F, 0x5B64, 0x5B88, 0x5BAC,
+    0x5BCF, 0x5BF1, 0x5C13, 0x5C34, 0x5C54, 0x5C74, 0x5C93, 0x5CB1,
+    0x5CCE, 0x5CEB, 0x5D07, 0x5D23, 0x5D3E, 0x5D58, 0x5D71, 0x5D8A,
+];
+
+/// How the voices resample their ADPCM samples to the output rate, see
+/// [`PsxConfig::spu_interpolation`](crate::PsxConfig::spu_interpolation)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SpuInterpolation {
+    /// The 4-tap gaussian filter of the hardware
+    #[default]
+    Gaussian,
+    /// The nearest sample, harsher and aliased, but a bit faster
+    Nearest,
+}
+
 const ADPCM_TABLE_POS: &[i32; 5] = &[0, 60, 115, 98, 122];
 const ADPCM_TABLE_NEG: &[i32; 5] = &[0, 0, -52, -55, -60];
 
@@ -169,9 +258,6 @@ bitflags::bitflags! {
 
 impl std::fmt::Debug for ADSRConfig {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        const STEPS_POS: &[i16; 4] = &[7, 6, 5, 4];
-        const STEPS_NEG: &[i16; 4] = &[-8, -7, -6, -5];
-
         let attack_mode_exp = self.contains(ADSRConfig::ATTACK_MODE);
         let attack_shift = ((self.bits() >> 10) & 0b11111) as u8;
         let step_i = (self.bits() >> 8) & 0b11;
@@ -211,8 +297,8 @@ impl std::fmt::Debug for ADSRConfig {
     }
 }
 
-#[derive(Debug, Default, Clone, Copy, PartialEq)]
-enum ADSRState {
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ADSRState {
     Attack,
     Decay,
     Sustain,
@@ -221,13 +307,126 @@ enum ADSRState {
     Stopped,
 }
 
+/// One step of the envelope of the ADSR and the volume sweeps, `level` is
+/// `0..=0x7FFF`. Returns the new level, and the number of samples until the
+/// next step.
+///
+///  AdsrCycles = 1 SHL Max(0,ShiftValue-11)
+///  AdsrStep = StepValue SHL Max(0,11-ShiftValue)
+///  IF exponential AND increase AND AdsrLevel>6000h THEN AdsrCycles=AdsrCycles*4
+///  IF exponential AND decrease THEN AdsrStep=AdsrStep*AdsrLevel/8000h
+///  Wait(AdsrCycles)              ;cycles counted at 44.1kHz clock
+///  AdsrLevel=AdsrLevel+AdsrStep  ;saturated to 0..+7FFFh
+fn envelope_step(
+    level: u16,
+    mode_exponential: bool,
+    direction_decrease: bool,
+    shift: u8,
+    step: i16,
+) -> (u16, u32) {
+    let mut cycles = 1 << shift.saturating_sub(11);
+    let mut step = (step as i32) << (11u8).saturating_sub(shift);
+
+    // fake exponential
+    if mode_exponential {
+        if direction_decrease {
+            // rounds down, so the step is never `0` before reaching level `0`
+            step = (step * level as i32) >> 15;
+        } else if level > 0x6000 {
+            // 4 times slower, the cycles can't go below 1, so the step is
+            // made smaller instead
+            if shift < 10 {
+                step /= 4;
+            } else if shift >= 11 {
+                cycles *= 4;
+            } else {
+                step /= 2;
+                cycles *= 2;
+            }
+        }
+    }
+
+    let level = (level as i32 + step).clamp(0, 0x7FFF) as u16;
+    (level, cycles)
+}
+
+/// A volume register of the voices or the main volume, and the current
+/// volume it produces, which changes over time in sweep mode
 #[derive(Default, Clone, Copy)]
-struct Voice {
-    volume_left: u16,
-    volume_right: u16,
+struct SweepVolume {
+    /// In fixed mode, bits 0-14 are the volume / 2.
+    /// In sweep mode (bit 15):
+    ///  14    Sweep Mode      (0=Linear, 1=Exponential)
+    ///  13    Sweep Direction (0=Increase, 1=Decrease)
+    ///  12    Sweep Phase     (0=Positive, 1=Negative)
+    ///  2-6   Sweep Shift     (0..1Fh = Fast..Slow)
+    ///  0-1   Sweep Step      (0..3 = "+7,+6,+5,+4" or "-8,-7,-6,-5") (inc/dec)
+    register: u16,
+    /// The current volume register
+    current: i16,
+    i_cycle_counter: u32,
+}
+
+impl SweepVolume {
+    const SWEEP_MODE: u16 = 0x8000;
+    const SWEEP_EXPONENTIAL: u16 = 0x4000;
+    const SWEEP_DECREASE: u16 = 0x2000;
+    const SWEEP_NEGATIVE_PHASE: u16 = 0x1000;
+
+    fn write(&mut self, data: u16) {
+        self.register = data;
+        self.i_cycle_counter = 0;
+        if data & Self::SWEEP_MODE == 0 {
+            self.current = (data << 1) as i16;
+        }
+    }
+
+    /// The current volume is writable, a sweep continues from the written
+    /// volume, but in fixed mode it's replaced on the next sample
+    fn write_current(&mut self, data: u16) {
+        self.current = data as i16;
+    }
 
-    current_vol_left: i16,
-    current_vol_right: i16,
+    /// Clocked every sample, at 44100Hz
+    fn clock(&mut self) {
+        if self.register & Self::SWEEP_MODE == 0 {
+            self.current = (self.register << 1) as i16;
+            return;
+        }
+
+        if self.i_cycle_counter > 0 {
+            self.i_cycle_counter -= 1;
+            return;
+        }
+
+        let mode_exponential = self.register & Self::SWEEP_EXPONENTIAL != 0;
+        let direction_decrease = self.register & Self::SWEEP_DECREASE != 0;
+        let shift = ((self.register >> 2) & 0x1F) as u8;
+        let step_i = (self.register & 0b11) as usize;
+        let step = if direction_decrease {
+            STEPS_NEG[step_i]
+        } else {
+            STEPS_POS[step_i]
+        };
+
+        // the envelope works on the magnitude, the phase is the sign
+        let level = self.current.unsigned_abs().min(0x7FFF);
+        let (level, cycles) =
+            envelope_step(level, mode_exponential, direction_decrease, shift, step);
+        // this sample is the first of the `cycles`
+        self.i_cycle_counter = cycles - 1;
+        self.current = if self.register & Self::SWEEP_NEGATIVE_PHASE != 0 {
+            -(level as i16)
+        } else {
+            level as i16
+        };
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+struct Voice {
+    volume_left: SweepVolume,
+    volume_right: SweepVolume,
 
     /// pitch
     adpcm_sample_rate: u16,
@@ -249,23 +448,32 @@ struct Voice {
 
     i_adsr_state: ADSRState,
 
-    /// This is also refer to `ADSRLevel`
+    /// The `ADSRLevel` register, a copy of `i_adsr_level` after every step
     ///
     /// DOCS: The register is read/writeable, writing allows to let the
     /// ADSR generator to "jump" to a specific volume level. But, ACTUALLY,
     /// the ADSR generator does overwrite the setting (from another internal
     /// register) whenever applying a new Step?!
-    ///
-    /// FIXME: currently, we are directly using this, we should use internal register
     adsr_current_vol: u16,
+    /// The level the envelope works on, and the voice volume
+    i_adsr_level: u16,
+    /// Written to the register, loaded into `i_adsr_level` by the next step
+    i_adsr_written_level: Option<u16>,
 
     i_cached_28_samples_block: [i16; 28],
+    /// The last 3 samples of the previous block, the gaussian interpolation
+    /// uses the 3 samples before the current one
+    i_previous_samples: [i16; 3],
     // 0 means that there is no cached block, so we must fetch,decode,cache it
     // and use the first sample
     i_cached_sample_index: usize,
 
     i_adsr_cycle_counter: u32,
 
+    /// The outputs of the last `clock_voice`, after the voice volume
+    i_last_output_left: i16,
+    i_last_output_right: i16,
+
     is_on: bool,
     is_off: bool,
 }
@@ -274,11 +482,15 @@ impl Voice {
     fn key_on(&mut self) {
         self.i_adpcm_current_address = self.adpcm_start_address as usize * 4;
         self.i_cached_sample_index = 28;
+        // no history for the interpolation of the first samples
+        self.i_cached_28_samples_block = [0; 28];
         self.i_adpcm_decoder.old = 0;
         self.i_adpcm_decoder.older = 0;
         self.adpcm_repeat_address = self.adpcm_start_address;
         self.set_adsr_state(ADSRState::Attack);
         self.adsr_current_vol = 0;
+        self.i_adsr_level = 0;
+        self.i_adsr_written_level = None;
         self.is_on = true;
         self.is_off = false;
     }
@@ -288,6 +500,11 @@ impl Voice {
         self.is_off = true;
     }
 
+    fn write_adsr_level(&mut self, level: u16) {
+        self.adsr_current_vol = level;
+        self.i_adsr_written_level = Some(level);
+    }
+
     fn set_adsr_state(&mut self, state: ADSRState) {
         self.i_adsr_state = state;
         self.i_adsr_cycle_counter = 0;
@@ -300,9 +517,6 @@ impl Voice {
     /// - `step`
     /// - `target_level`
     fn get_adsr_current_info(&self) -> (bool, bool, u8, i16, u16) {
-        const STEPS_POS: &[i16; 4] = &[7, 6, 5, 4];
-        const STEPS_NEG: &[i16; 4] = &[-8, -7, -6, -5];
-
         let mode;
         let direction;
         let shift;
@@ -327,7 +541,7 @@ impl Voice {
                 let sustain_level_mul = (self.adsr_config.bits() & 0b1111) as u16 + 1;
                 // the max level will be 0x8000, which is outside the range
                 // so clamp it.
-                target_level = (sustain_level_mul * 0x800).max(0x7FFF);
+                target_level = (sustain_level_mul * 0x800).min(0x7FFF);
             }
             ADSRState::Sustain => {
                 mode = self.adsr_config.contains(ADSRConfig::SUSTAIN_MODE);
@@ -362,14 +576,8 @@ impl Voice {
     }
 
     fn clock_adsr(&mut self) {
-        // ADSR operation
+        // ADSR operation, see `envelope_step`
         //
-        //  AdsrCycles = 1 SHL Max(0,ShiftValue-11)
-        //  AdsrStep = StepValue SHL Max(0,11-ShiftValue)
-        //  IF exponential AND increase AND AdsrLevel>6000h THEN AdsrCycles=AdsrCycles*4
-        //  IF exponential AND decrease THEN AdsrStep=AdsrStep*AdsrLevel/8000h
-        //  Wait(AdsrCycles)              ;cycles counted at 44.1kHz clock
-        //  AdsrLevel=AdsrLevel+AdsrStep  ;saturated to 0..+7FFFh
         // FIXME: we are waiting first, then adding the step together with the
         //        rest, not sure if this is correct, but for now its simpler
         if self.i_adsr_cycle_counter > 0 {
@@ -385,37 +593,28 @@ impl Voice {
             return;
         }
 
-        let mut adsr_cycles = 1 << shift.saturating_sub(11);
-        let mut adsr_step = step << (11u8).saturating_sub(shift);
-
-        // fake exponential
-        if mode_exponential {
-            if direction_decrease {
-                adsr_step = (adsr_step as i32 * self.adsr_current_vol as i32 / 0x8000)
-                    .clamp(-0x8000, 0x7FFF) as i16;
-                if adsr_step == 0 {
-                    adsr_step = -1;
-                }
-            } else if self.adsr_current_vol > 0x6000 {
-                if shift < 10 {
-                    adsr_step /= 4;
-                } else if shift >= 11 {
-                    adsr_cycles *= 4;
-                } else {
-                    adsr_step /= 4;
-                    adsr_cycles *= 4;
-                }
-            }
+        // the register was written, the envelope continues from there
+        if let Some(level) = self.i_adsr_written_level.take() {
+            self.i_adsr_level = level;
         }
 
-        self.i_adsr_cycle_counter = adsr_cycles.max(1);
+        let (level, adsr_cycles) = envelope_step(
+            self.i_adsr_level,
+            mode_exponential,
+            direction_decrease,
+            shift,
+            step,
+        );
+
+        // this sample is the first of the `adsr_cycles`
+        self.i_adsr_cycle_counter = adsr_cycles - 1;
 
         // should wait here
-        self.adsr_current_vol =
-            ((self.adsr_current_vol as i16).saturating_add(adsr_step)).clamp(0, 0x7FFF) as u16;
+        self.i_adsr_level = level;
+        self.adsr_current_vol = self.i_adsr_level;
 
-        if (direction_decrease && self.adsr_current_vol <= target_level)
-            || (!direction_decrease && self.adsr_current_vol >= target_level)
+        if (direction_decrease && self.i_adsr_level <= target_level)
+            || (!direction_decrease && self.i_adsr_level >= target_level)
         {
             match self.i_adsr_state {
                 ADSRState::Attack => {
@@ -440,11 +639,11 @@ impl Voice {
     /// returns `true` if `ENDX` should be set
     ///
     /// This may set ADSR mode to `Release` when encountering the flags `End+Mute`
-    fn fetch_and_decode_next_sample_block(&mut self, ram: &SpuRam) -> bool {
+    fn fetch_and_decode_next_sample_block(&mut self, ram: &mut SpuRam) -> bool {
         let mut endx_set = false;
 
         // 16 bytes block
-        let adpcm_block = &ram[self.i_adpcm_current_address..self.i_adpcm_current_address + 8];
+        let adpcm_block = ram.fetch_voice_block(self.i_adpcm_current_address);
         // move to next block
         self.i_adpcm_current_address += 8;
         self.i_adpcm_current_address &= 0x3FFFF;
@@ -473,19 +672,44 @@ impl Voice {
             }
         }
 
+        self.i_previous_samples
+            .copy_from_slice(&self.i_cached_28_samples_block[25..]);
         self.i_adpcm_decoder
-            .decode_block(adpcm_block, &mut self.i_cached_28_samples_block);
+            .decode_block(&adpcm_block, &mut self.i_cached_28_samples_block);
 
         endx_set
     }
 
+    /// The sample at `index` in the block, filtered with the 3 samples before it,
+    /// `i` is the position between the samples
+    fn gaussian_sample(&self, index: usize, i: usize) -> i16 {
+        // the samples before the start of the block are from the previous one
+        let sample = |back: usize| match index.checked_sub(back) {
+            Some(index) => self.i_cached_28_samples_block[index] as i32,
+            None => self.i_previous_samples[3 + index - back] as i32,
+        };
+
+        let out = ((GAUSS_TABLE[0x0FF - i] * sample(3)) >> 15)
+            + ((GAUSS_TABLE[0x1FF - i] * sample(2)) >> 15)
+            + ((GAUSS_TABLE[0x100 + i] * sample(1)) >> 15)
+            + ((GAUSS_TABLE[i] * sample(0)) >> 15);
+        // the weights add up to a bit more than 1.0 for some positions
+        out.clamp(-0x8000, 0x7FFF) as i16
+    }
+
     /// returns
     /// - `true` if `ENDX` should be set
     /// - `mono_output` can be used for capture
     /// - `left_output`
     /// - `right_output`
-    fn clock_voice(&mut self, ram: &SpuRam) -> (bool, i16, i32, i32) {
+    fn clock_voice(
+        &mut self,
+        ram: &mut SpuRam,
+        interpolation: SpuInterpolation,
+    ) -> (bool, i16, i32, i32) {
         self.clock_adsr();
+        self.volume_left.clock();
+        self.volume_right.clock();
 
         let mut endx_set = false;
 
@@ -498,6 +722,8 @@ impl Voice {
         }
 
         let current_index = self.i_cached_sample_index;
+        // Counter.Bit4..11 are used as 8bit gaussian interpolation index
+        let interpolation_index = ((self.i_adpcm_pitch_counter >> 4) & 0xFF) as usize;
 
         let mut step = self.adpcm_sample_rate;
 
@@ -511,30 +737,97 @@ impl Voice {
         // Counter.Bit12 and up indicates the current sample (within a ADPCM block).
         let next_sample = self.i_adpcm_pitch_counter >> 12;
         // TODO: add pitch modulation
-        // Counter.Bit3..11 are used as 8bit gaussian interpolation index
 
         self.i_cached_sample_index = next_sample as usize;
 
-        let current_sample = self.i_cached_28_samples_block[current_index];
+        let current_sample = match interpolation {
+            SpuInterpolation::Gaussian => self.gaussian_sample(current_index, interpolation_index),
+            SpuInterpolation::Nearest => self.i_cached_28_samples_block[current_index],
+        };
 
         // This `mono output` can be used in the capture buffer, the remaining
         // volume control and sweep are not included in the capture buffer data.
         let mono_output =
-            (current_sample as i32 * self.adsr_current_vol as i32 / 0x8000).clamp(-0x8000, 0x7FFF);
-
-        // TODO: implement sweep
-        #[allow(unused)]
-        let sweep_mode = self.volume_left & 0x8000 == 0x8000;
+            (current_sample as i32 * self.i_adsr_level as i32 / 0x8000).clamp(-0x8000, 0x7FFF);
 
         let left_output =
-            (mono_output * self.current_vol_left as i32 / 0x8000).clamp(-0x8000, 0x7FFF);
+            (mono_output * self.volume_left.current as i32 / 0x8000).clamp(-0x8000, 0x7FFF);
         let right_output =
-            (mono_output * self.current_vol_right as i32 / 0x8000).clamp(-0x8000, 0x7FFF);
+            (mono_output * self.volume_right.current as i32 / 0x8000).clamp(-0x8000, 0x7FFF);
+
+        self.i_last_output_left = left_output as i16;
+        self.i_last_output_right = right_output as i16;
 
         (endx_set, mono_output as i16, left_output, right_output)
     }
 }
 
+/// The state of a voice, see [`SpuState`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct VoiceState {
+    pub key_on: bool,
+    pub key_off: bool,
+    pub pitch_modulation: bool,
+    pub noise_mode: bool,
+    pub reverb_mode: bool,
+    pub endx: bool,
+    /// The volume registers, bit 15 is the sweep mode
+    pub volume_left: u16,
+    pub volume_right: u16,
+    /// The pitch, `0x1000` is 44100Hz
+    pub sample_rate: u16,
+    /// The addresses are in 8 bytes units, same as the registers
+    pub start_address: u16,
+    pub repeat_address: u16,
+    pub current_address: u16,
+    pub adsr_config: u32,
+    pub adsr_state: ADSRState,
+    pub adsr_level: u16,
+    /// The last sample produced by the voice (before the main volume), for level meters
+    pub last_output_left: i16,
+    pub last_output_right: i16,
+}
+
+/// A snapshot of the SPU registers and voices, for debuggers and visualizers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SpuState {
+    pub main_volume_left: u16,
+    pub main_volume_right: u16,
+    pub reverb_volume_left: u16,
+    pub reverb_volume_right: u16,
+    pub cd_volume_left: u16,
+    pub cd_volume_right: u16,
+    pub external_volume_left: u16,
+    pub external_volume_right: u16,
+    /// `SPUCNT`
+    pub control: u16,
+    /// `SPUSTAT`
+    pub stat: u16,
+    pub ram_transfer_control: u16,
+    /// In 8 bytes units, same as the register
+    pub ram_transfer_address: u16,
+    /// In 8 bytes units, same as the register
+    pub irq_address: u16,
+    pub voices: [VoiceState; 24],
+}
+
+/// Muting of the final mix for debugging, this is not visible to the emulated
+/// hardware (the capture buffers and the IRQs are not affected)
+#[derive(Default, Clone, Copy)]
+struct HostMix {
+    muted_voices: u32,
+    solo_voice: Option<usize>,
+    cd_muted: bool,
+}
+
+impl HostMix {
+    fn voice_audible(&self, voice: usize) -> bool {
+        self.muted_voices & (1 << voice) == 0 && self.solo_voice.map_or(true, |v| v == voice)
+    }
+}
+
 // 1KB of RAM (16bit)
 const CAPTURE_MEMORY_REGION_SIZE: usize = 0x200;
 
@@ -542,10 +835,9 @@ struct SpuRam {
     data: Box<[u16; 0x40000]>,
     /// The address from the ram, when read/written to it should trigger interrupt
     irq_address: usize,
-    /// will store whether the IRQ was triggered
-    /// Handling and signaling interrupt to the other hardware is done by the `Spu` itself.
-    /// must be cleared by the handler before the next IRQ can be triggered
-    irq_flag: Cell<bool>,
+    /// Set when `irq_address` is accessed by a voice, the capture buffers
+    /// or a transfer. The `Spu` decides if it should be latched into `SPUSTAT`
+    irq_triggered: bool,
 
     /// The saved location of the pointer to store the next sample for the cd left audio
     /// in the ram (this goes from 0 to 0x1FF)
@@ -564,21 +856,55 @@ struct SpuRam {
     voice_3_mono_capture_index: usize,
 }
 
+/// Only the accesses the hardware does go through the methods here, and can
+/// trigger the IRQ, each at its own granularity:
+/// - voices fetch a whole 16 bytes ADPCM block at once
+/// - transfers read/write a halfword at a time
+/// - the capture buffers write a halfword per buffer each sample
+///
+/// The reverb is not emulated, its work area accesses would be per halfword as well.
+/// Anything else the emulator does with the RAM (reset, hashing, debugging)
+/// uses `data` directly so it never touches the IRQ.
 impl SpuRam {
-    pub fn reset_irq(&self) {
-        self.irq_flag.set(false);
+    /// Returns `true` if `irq_address` was accessed since the last call
+    pub fn take_irq(&mut self) -> bool {
+        std::mem::take(&mut self.irq_triggered)
     }
 
-    pub fn push_cd_capture_samples(&mut self, left: i16, right: i16) {
-        {
-            let i = self.cd_left_capture_index;
-            self[i] = left as u16;
+    fn check_irq(&mut self, index: usize) {
+        if index == self.irq_address {
+            self.irq_triggered = true;
         }
-        {
-            let i = 0x200 + self.cd_right_capture_index;
-            // offset by 1KB
-            self[i] = right as u16;
+    }
+
+    pub fn transfer_read(&mut self, index: usize) -> u16 {
+        self.check_irq(index);
+        self.data[index]
+    }
+
+    pub fn transfer_write(&mut self, index: usize, value: u16) {
+        self.check_irq(index);
+        self.data[index] = value;
+    }
+
+    fn capture_write(&mut self, index: usize, value: u16) {
+        self.check_irq(index);
+        self.data[index] = value;
+    }
+
+    /// Fetch the 8 halfwords ADPCM block at `address`, triggers the IRQ if
+    /// `irq_address` is anywhere inside it, wraps at the end of the RAM
+    pub fn fetch_voice_block(&mut self, address: usize) -> [u16; 8] {
+        if (self.irq_address.wrapping_sub(address) & 0x3FFFF) < 8 {
+            self.irq_triggered = true;
         }
+        std::array::from_fn(|i| self.data[(address + i) & 0x3FFFF])
+    }
+
+    pub fn push_cd_capture_samples(&mut self, left: i16, right: i16) {
+        self.capture_write(self.cd_left_capture_index, left as u16);
+        // offset by 1KB
+        self.capture_write(0x200 + self.cd_right_capture_index, right as u16);
 
         self.cd_left_capture_index = (self.cd_left_capture_index + 1) % CAPTURE_MEMORY_REGION_SIZE;
         self.cd_right_capture_index =
@@ -586,57 +912,24 @@ impl SpuRam {
     }
 
     pub fn push_voice_1_sample(&mut self, sample: i16) {
-        let i = 0x400 + self.voice_1_mono_capture_index;
-        self[i] = sample as u16;
+        self.capture_write(0x400 + self.voice_1_mono_capture_index, sample as u16);
         self.voice_1_mono_capture_index =
             (self.voice_1_mono_capture_index + 1) % CAPTURE_MEMORY_REGION_SIZE;
     }
 
     pub fn push_voice_3_sample(&mut self, sample: i16) {
-        let i = 0x600 + self.voice_3_mono_capture_index;
-        self[i] = sample as u16;
+        self.capture_write(0x600 + self.voice_3_mono_capture_index, sample as u16);
         self.voice_3_mono_capture_index =
             (self.voice_3_mono_capture_index + 1) % CAPTURE_MEMORY_REGION_SIZE;
     }
 }
 
-impl Index<Range<usize>> for SpuRam {
-    type Output = [u16];
-
-    fn index(&self, index: Range<usize>) -> &Self::Output {
-        if index.contains(&self.irq_address) {
-            self.irq_flag.set(true);
-        }
-        self.data.index(index)
-    }
-}
-
-impl Index<usize> for SpuRam {
-    type Output = u16;
-
-    fn index(&self, index: usize) -> &Self::Output {
-        if index == self.irq_address {
-            self.irq_flag.set(true);
-        }
-        &self.data[index]
-    }
-}
-
-impl IndexMut<usize> for SpuRam {
-    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-        if index == self.irq_address {
-            self.irq_flag.set(true);
-        }
-        &mut self.data[index]
-    }
-}
-
 impl Default for SpuRam {
     fn default() -> Self {
         Self {
             data: Box::new([0; 0x40000]),
             irq_address: 0x0,
-            irq_flag: Cell::new(false),
+            irq_triggered: false,
             cd_left_capture_index: 0,
             cd_right_capture_index: 0,
             voice_1_mono_capture_index: 0,
@@ -647,8 +940,8 @@ impl Default for SpuRam {
 
 #[derive(Default)]
 pub struct Spu {
-    main_vol_left: u16,
-    main_vol_right: u16,
+    main_vol_left: SweepVolume,
+    main_vol_right: SweepVolume,
 
     reverb_out_vol_left: u16,
     reverb_out_vol_right: u16,
@@ -660,9 +953,6 @@ pub struct Spu {
     external_vol_left: u16,
     external_vol_right: u16,
 
-    current_main_vol_left: i16,
-    current_main_vol_right: i16,
-
     ram_transfer_control: u16,
     ram_transfer_address: u16,
     i_ram_transfer_address: usize,
@@ -691,8 +981,14 @@ pub struct Spu {
 
     spu_ram: SpuRam,
 
-    cdrom_audio_buffer_left: VecDeque<i16>,
-    cdrom_audio_buffer_right: VecDeque<i16>,
+    // (left, right) samples
+    cdrom_audio_buffer: VecDeque<(i16, i16)>,
+    /// Samples were dropped from `cdrom_audio_buffer` the last time it was filled
+    cdrom_audio_overflow: bool,
+    // (left, right) samples, from the host
+    external_audio_buffer: VecDeque<(i16, i16)>,
+    /// Samples were dropped from `external_audio_buffer` the last time it was filled
+    external_audio_overflow: bool,
 
     /// internal timer to know when to run the SPU.
     /// The SPU runs at 44100Hz, which is CPU_CLOCK / 0x300
@@ -703,21 +999,82 @@ pub struct Spu {
     out_audio_buffer: Vec<f32>,
 
     in_dma_transfer: bool,
+
+    host_mix: HostMix,
+    interpolation: SpuInterpolation,
+
+    tracer: Tracer,
+    warnings: Warnings,
 }
 
 impl Spu {
+    /// Reset the emulated state (the registers, the voices and the reverb),
+    /// the host mix settings, the interpolation, the tracer and the warnings are kept.
+    ///
+    /// The reset button doesn't clear the SPU RAM on hardware, `keep_ram` does
+    /// the same, otherwise it's cleared like at power-on.
+    pub fn reset(&mut self, keep_ram: bool) {
+        let old = std::mem::take(self);
+        self.host_mix = old.host_mix;
+        self.interpolation = old.interpolation;
+        self.tracer = old.tracer;
+        self.warnings = old.warnings;
+        if keep_ram {
+            self.spu_ram.data = old.spu_ram.data;
+        }
+    }
+
+    pub fn set_voice_muted(&mut self, voice: usize, muted: bool) {
+        assert!(voice < 24, "invalid voice {}", voice);
+        self.host_mix.muted_voices &= !(1 << voice);
+        self.host_mix.muted_voices |= (muted as u32) << voice;
+    }
+
+    /// Only output `voice` (and the CD audio) if `Some`
+    pub fn solo_voice(&mut self, voice: Option<usize>) {
+        if let Some(voice) = voice {
+            assert!(voice < 24, "invalid voice {}", voice);
+        }
+        self.host_mix.solo_voice = voice;
+    }
+
+    pub fn set_cd_audio_muted(&mut self, muted: bool) {
+        self.host_mix.cd_muted = muted;
+    }
+
+    pub(crate) fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = tracer;
+    }
+
+    pub(crate) fn set_warnings(&mut self, warnings: Warnings) {
+        self.warnings = warnings;
+    }
+
+    /// Pitch modulation and noise are not emulated, the voices with `flag` set
+    /// play their samples as if it was off
+    fn warn_unsupported_voice_mode(&self, flag: &VoicesFlag, mode: &str) {
+        if flag.get_all() != 0 {
+            self.warnings
+                .warn("SPU", format!("{} is not supported", mode));
+        }
+    }
+
+    pub(crate) fn set_interpolation(&mut self, interpolation: SpuInterpolation) {
+        self.interpolation = interpolation;
+    }
+
     pub fn clock(&mut self, interrupt_requester: &mut impl InterruptRequester, cycles: u32) {
         self.cpu_clock_timer += cycles;
 
+        // transfers from the CPU and DMA happen between clocks
+        self.handle_irq(interrupt_requester);
+
         loop {
             if self.cpu_clock_timer < CPU_CLOCKS_PER_SPU {
                 break;
             }
             self.cpu_clock_timer -= CPU_CLOCKS_PER_SPU;
 
-            // clear internal irq flag
-            self.spu_ram.reset_irq();
-
             // the order of SPU handling is
             // - voice1
             // - write cd left
@@ -750,18 +1107,17 @@ impl Spu {
                     } else {
                         self.stat.insert(SpuStat::DATA_TRANSFER_BUSY_FLAG);
 
-                        for d in self.write_data_fifo.drain(..) {
-                            self.spu_ram[self.i_ram_transfer_address] = d;
-                            self.i_ram_transfer_address += 1;
-                            self.i_ram_transfer_address &= 0x3FFFF
-                        }
+                        self.flush_write_fifo();
                         // reset the busy flag on the next round
                     }
                 }
+                // only request the current direction, the mode could have changed
+                // while the other was requested
                 RamTransferMode::DmaWrite => {
                     self.stat
                         .set(SpuStat::DATA_TRANSFER_BUSY_FLAG, self.in_dma_transfer);
 
+                    self.stat.remove(SpuStat::DATA_TRANSFER_DMA_READ_REQ);
                     self.stat.insert(
                         SpuStat::DATA_TRANSFER_USING_DMA | SpuStat::DATA_TRANSFER_DMA_WRITE_REQ,
                     );
@@ -769,37 +1125,53 @@ impl Spu {
                 RamTransferMode::DmaRead => {
                     self.stat
                         .set(SpuStat::DATA_TRANSFER_BUSY_FLAG, self.in_dma_transfer);
+                    self.stat.remove(SpuStat::DATA_TRANSFER_DMA_WRITE_REQ);
                     self.stat.insert(
                         SpuStat::DATA_TRANSFER_USING_DMA | SpuStat::DATA_TRANSFER_DMA_READ_REQ,
                     );
                 }
             }
 
+            self.main_vol_left.clock();
+            self.main_vol_right.clock();
+
             let mut mixed_audio_left = 0;
             let mut mixed_audio_right = 0;
 
-            let cd_left = self.cdrom_audio_buffer_left.pop_front().unwrap_or(0);
-            let cd_right = self.cdrom_audio_buffer_right.pop_front().unwrap_or(0);
+            let (cd_left, cd_right) = self.cdrom_audio_buffer.pop_front().unwrap_or((0, 0));
             self.spu_ram.push_cd_capture_samples(cd_left, cd_right);
+            let (cd_left, cd_right) = if self.host_mix.cd_muted {
+                (0, 0)
+            } else {
+                (cd_left, cd_right)
+            };
 
             mixed_audio_left +=
                 ((cd_left as i32 * self.cd_vol_left as i32) / 0x8000).clamp(-0x8000, 0x7FFF);
             mixed_audio_right +=
                 ((cd_right as i32 * self.cd_vol_right as i32) / 0x8000).clamp(-0x8000, 0x7FFF);
 
+            // the input keeps flowing while disabled, it's just not mixed
+            // TODO: `EXTERNAL_AUDIO_REVERB` (and `CD_AUDIO_REVERB`) when the reverb is emulated
+            let (external_left, external_right) =
+                self.external_audio_buffer.pop_front().unwrap_or((0, 0));
+            if self.control.intersects(SpuControl::EXTERNAL_AUDIO_ENABLE) {
+                mixed_audio_left += ((external_left as i32 * self.external_vol_left as i32)
+                    / 0x8000)
+                    .clamp(-0x8000, 0x7FFF);
+                mixed_audio_right += ((external_right as i32 * self.external_vol_right as i32)
+                    / 0x8000)
+                    .clamp(-0x8000, 0x7FFF);
+            }
+
             // TODO: implement correct order of handling voices (refer to above)
             for i in 0..24 {
-                let pitch_mod = self.pitch_mod_channel_flag.get(i);
-                let noise_mode = self.noise_channel_mode_flag.get(i);
+                // pitch modulation and noise are ignored, see `warn_unsupported_voice_mode`
                 let _reverb_mode = self.reverb_channel_mode_flag.get(i);
 
-                assert!(!pitch_mod);
-                assert!(!noise_mode);
-                //assert!(!_reverb_mode);
-
                 // handle voices
                 let (reached_endx, mono_output, left_output, right_output) =
-                    self.voices[i].clock_voice(&self.spu_ram);
+                    self.voices[i].clock_voice(&mut self.spu_ram, self.interpolation);
 
                 // push the voice output to the capture buffer
                 match i {
@@ -808,13 +1180,16 @@ impl Spu {
                     _ => {}
                 }
 
-                let final_left_output = (left_output * self.current_main_vol_left as i32 / 0x8000)
-                    .clamp(-0x8000, 0x7FFF);
-                mixed_audio_left += final_left_output;
-                let final_right_output = (right_output * self.current_main_vol_right as i32
-                    / 0x8000)
-                    .clamp(-0x8000, 0x7FFF);
-                mixed_audio_right += final_right_output;
+                if self.host_mix.voice_audible(i) {
+                    let final_left_output = (left_output * self.main_vol_left.current as i32
+                        / 0x8000)
+                        .clamp(-0x8000, 0x7FFF);
+                    mixed_audio_left += final_left_output;
+                    let final_right_output = (right_output * self.main_vol_right.current as i32
+                        / 0x8000)
+                        .clamp(-0x8000, 0x7FFF);
+                    mixed_audio_right += final_right_output;
+                }
 
                 if reached_endx {
                     self.endx_flag.set(i, true);
@@ -837,36 +1212,185 @@ impl Spu {
             self.out_audio_buffer.push(left);
             self.out_audio_buffer.push(right);
 
-            if self
+            self.handle_irq(interrupt_requester);
+        }
+    }
+
+    /// Latch the IRQ if `irq_address` was accessed, accesses while `IRQ9_ENABLE`
+    /// is off are lost, and once latched, it must be acknowledged by clearing
+    /// `IRQ9_ENABLE` before it can be raised again.
+    fn handle_irq(&mut self, interrupt_requester: &mut impl InterruptRequester) {
+        if self.spu_ram.take_irq()
+            && self
                 .control
                 .contains(SpuControl::SPU_ENABLE | SpuControl::IRQ9_ENABLE)
-                && self.spu_ram.irq_flag.get()
-            {
-                self.stat.insert(SpuStat::IRQ_FLAG);
-                interrupt_requester.request_spu();
+            && !self.stat.intersects(SpuStat::IRQ_FLAG)
+        {
+            self.stat.insert(SpuStat::IRQ_FLAG);
+            interrupt_requester.request_spu();
+        }
+    }
+
+    /// Queue `(left, right)` CD-ROM samples, already at 44100Hz.
+    ///
+    /// The CD-ROM delivers a bit faster than the SPU plays, so if the samples are not
+    /// consumed, the oldest are dropped after [`MAX_CDROM_AUDIO_SAMPLES`]
+    pub(crate) fn add_cdrom_audio(&mut self, samples: impl Iterator<Item = (i16, i16)>) {
+        self.cdrom_audio_buffer.extend(samples);
+
+        let excess = self
+            .cdrom_audio_buffer
+            .len()
+            .saturating_sub(MAX_CDROM_AUDIO_SAMPLES);
+        if excess > 0 {
+            // warn once until it catches up
+            if !self.cdrom_audio_overflow {
+                log::warn!("CD audio is not consumed fast enough, dropping the oldest samples");
             }
+            self.cdrom_audio_buffer.drain(..excess);
         }
+        self.cdrom_audio_overflow = excess > 0;
     }
 
-    pub(crate) fn add_cdrom_audio(&mut self, left: &[i16], right: &[i16]) {
-        assert_eq!(left.len(), right.len());
+    /// Queue `(left, right)` samples of the external audio input, at 44100Hz.
+    ///
+    /// One sample is played every SPU cycle, even when `EXTERNAL_AUDIO_ENABLE` is off,
+    /// the oldest are dropped after [`MAX_EXTERNAL_AUDIO_SAMPLES`]
+    pub(crate) fn add_external_audio(&mut self, samples: &[(i16, i16)]) {
+        self.external_audio_buffer.extend(samples);
+
+        let excess = self
+            .external_audio_buffer
+            .len()
+            .saturating_sub(MAX_EXTERNAL_AUDIO_SAMPLES);
+        if excess > 0 {
+            if !self.external_audio_overflow {
+                log::warn!("External audio is pushed too fast, dropping the oldest samples");
+            }
+            self.external_audio_buffer.drain(..excess);
+        }
+        self.external_audio_overflow = excess > 0;
+    }
 
-        self.cdrom_audio_buffer_left.extend(left);
-        self.cdrom_audio_buffer_right.extend(right);
+    #[cfg(test)]
+    pub(crate) fn take_cdrom_audio(&mut self) -> Vec<(i16, i16)> {
+        self.cdrom_audio_buffer.drain(..).collect()
     }
 
-    pub fn take_audio_buffer(&mut self) -> Vec<f32> {
-        let mut out = Vec::with_capacity(self.out_audio_buffer.len());
-        out.extend_from_slice(&self.out_audio_buffer);
-        self.out_audio_buffer.clear();
-        out
+    /// Move the produced audio to the end of `out`, the internal buffer keeps its
+    /// capacity for the next frame
+    pub fn take_audio_buffer(&mut self, out: &mut Vec<f32>) {
+        out.append(&mut self.out_audio_buffer);
+    }
+
+    /// The values in the audio buffer, 2 for each stereo sample
+    pub fn audio_buffer_len(&self) -> usize {
+        self.out_audio_buffer.len()
+    }
+
+    pub fn voices_state(&self) -> [VoiceState; 24] {
+        std::array::from_fn(|i| {
+            let voice = &self.voices[i];
+            VoiceState {
+                key_on: voice.is_on,
+                key_off: voice.is_off,
+                pitch_modulation: self.pitch_mod_channel_flag.get(i),
+                noise_mode: self.noise_channel_mode_flag.get(i),
+                reverb_mode: self.reverb_channel_mode_flag.get(i),
+                endx: self.endx_flag.get(i),
+                volume_left: voice.volume_left.register,
+                volume_right: voice.volume_right.register,
+                sample_rate: voice.adpcm_sample_rate,
+                start_address: voice.adpcm_start_address,
+                repeat_address: voice.adpcm_repeat_address,
+                current_address: (voice.i_adpcm_current_address / 4) as u16,
+                adsr_config: voice.adsr_config.bits(),
+                adsr_state: voice.i_adsr_state,
+                adsr_level: voice.adsr_current_vol,
+                last_output_left: voice.i_last_output_left,
+                last_output_right: voice.i_last_output_right,
+            }
+        })
+    }
+
+    pub fn state(&self) -> SpuState {
+        SpuState {
+            main_volume_left: self.main_vol_left.register,
+            main_volume_right: self.main_vol_right.register,
+            reverb_volume_left: self.reverb_out_vol_left,
+            reverb_volume_right: self.reverb_out_vol_right,
+            cd_volume_left: self.cd_vol_left,
+            cd_volume_right: self.cd_vol_right,
+            external_volume_left: self.external_vol_left,
+            external_volume_right: self.external_vol_right,
+            control: self.control.bits(),
+            stat: self.stat.bits(),
+            ram_transfer_control: self.ram_transfer_control,
+            ram_transfer_address: self.ram_transfer_address,
+            irq_address: (self.spu_ram.irq_address / 4) as u16,
+            voices: self.voices_state(),
+        }
+    }
+
+    /// The SPU RAM and the registers, the host mix and the audio output are not
+    /// part of the emulated state
+    pub(crate) fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_u16_slice(&self.spu_ram.data[..]);
+
+        let state = self.state();
+        for reg in [
+            state.main_volume_left,
+            state.main_volume_right,
+            state.reverb_volume_left,
+            state.reverb_volume_right,
+            state.cd_volume_left,
+            state.cd_volume_right,
+            state.external_volume_left,
+            state.external_volume_right,
+            state.control,
+            state.stat,
+            state.ram_transfer_control,
+            state.ram_transfer_address,
+            state.irq_address,
+        ] {
+            hasher.write_u16(reg);
+        }
+        hasher.write_u16_slice(&self.reverb_config);
+        hasher.write_u32(self.cpu_clock_timer);
+        // these change over time with the sweeps
+        hasher.write_u16(self.main_vol_left.current as u16);
+        hasher.write_u16(self.main_vol_right.current as u16);
+        for voice in &self.voices {
+            hasher.write_u16(voice.volume_left.current as u16);
+            hasher.write_u16(voice.volume_right.current as u16);
+        }
+
+        for voice in state.voices {
+            hasher.write_u8(
+                voice.key_on as u8
+                    | (voice.key_off as u8) << 1
+                    | (voice.pitch_modulation as u8) << 2
+                    | (voice.noise_mode as u8) << 3
+                    | (voice.reverb_mode as u8) << 4
+                    | (voice.endx as u8) << 5,
+            );
+            hasher.write_u16(voice.volume_left);
+            hasher.write_u16(voice.volume_right);
+            hasher.write_u16(voice.sample_rate);
+            hasher.write_u16(voice.start_address);
+            hasher.write_u16(voice.repeat_address);
+            hasher.write_u16(voice.current_address);
+            hasher.write_u32(voice.adsr_config);
+            hasher.write_u8(voice.adsr_state as u8);
+            hasher.write_u16(voice.adsr_level);
+        }
     }
 
     pub fn print_state(&self) {
         println!("SPU State:");
         println!(
             "  Main Volume: Left: {:04X}, Right: {:04X}",
-            self.main_vol_left, self.main_vol_right
+            self.main_vol_left.register, self.main_vol_right.register
         );
         println!(
             "  Reverb Volume: Left: {:04X}, Right: {:04X}",
@@ -890,7 +1414,7 @@ impl Spu {
         println!(
             "  IRQ Address: {:X}, IRQ Flag: {}",
             self.spu_ram.irq_address / 4,
-            self.spu_ram.irq_flag.get()
+            self.stat.intersects(SpuStat::IRQ_FLAG)
         );
         println!();
         println!("  | {:^2} | {:^6} | {:^7} | {:^9} | {:^10} | {:^11} | {:^5} | {:^8} | {:^9} | {:^11} | {:^10} | {:^11} | {:^12} | {:^11} | {:^8} | {:^10} | {:^12} | {:^13} |", 
@@ -907,8 +1431,8 @@ impl Spu {
                 self.noise_channel_mode_flag.get(i),
                 self.reverb_channel_mode_flag.get(i),
                 self.endx_flag.get(i),
-                self.voices[i].volume_left,
-                self.voices[i].volume_right,
+                self.voices[i].volume_left.register,
+                self.voices[i].volume_right.register,
                 self.voices[i].adpcm_sample_rate,
                 self.voices[i].adpcm_start_address,
                 self.voices[i].adpcm_repeat_address,
@@ -925,10 +1449,8 @@ impl Spu {
 
 // DMA transfer
 impl Spu {
-    pub fn is_ready_for_dma(&mut self, write: bool) -> bool {
-        self.in_dma_transfer = true;
-        self.stat.insert(SpuStat::DATA_TRANSFER_BUSY_FLAG);
-
+    /// Whether the SPU requests a DMA transfer in this direction, `write` is to the SPU
+    pub fn is_ready_for_dma(&self, write: bool) -> bool {
         if self.stat.intersects(SpuStat::DATA_TRANSFER_USING_DMA) {
             if write {
                 self.stat.intersects(SpuStat::DATA_TRANSFER_DMA_WRITE_REQ)
@@ -940,35 +1462,80 @@ impl Spu {
         }
     }
 
+    /// Write the words to the RAM at the transfer address, the request is raised
+    /// again on the next clock, so the DMA waits between the blocks
     pub fn dma_write_buf(&mut self, buf: &[u32]) {
         self.in_dma_transfer = true;
         self.stat.insert(SpuStat::DATA_TRANSFER_BUSY_FLAG);
 
         // finish this first
-        if !self.write_data_fifo.is_empty() {
-            for d in self.write_data_fifo.drain(..) {
-                self.spu_ram[self.i_ram_transfer_address] = d;
-                self.i_ram_transfer_address += 1;
-                self.i_ram_transfer_address &= 0x3FFFF;
-            }
+        self.flush_write_fifo();
+
+        self.write_transfer_block(buf.len() * 2, |i| (buf[i / 2] >> ((i & 1) * 16)) as u16);
+
+        self.stat
+            .remove(SpuStat::DATA_TRANSFER_DMA_WRITE_REQ | SpuStat::DATA_TRANSFER_USING_DMA);
+    }
+
+    fn flush_write_fifo(&mut self) {
+        if self.write_data_fifo.is_empty() {
+            return;
         }
+        let mut fifo = std::mem::take(&mut self.write_data_fifo);
+        let fifo_data = fifo.make_contiguous();
+        self.write_transfer_block(fifo_data.len(), |i| fifo_data[i]);
+        fifo.clear();
+        // keep the allocation
+        self.write_data_fifo = fifo;
+    }
 
-        for d in buf {
-            let low = *d as u16;
-            let high = (*d >> 16) as u16;
-            self.spu_ram[self.i_ram_transfer_address] = low;
+    /// Write `len` halfwords to the RAM at the transfer address, rearranged
+    /// based on the transfer type in `ram_transfer_control`, for data `A,B,C,...,X`:
+    /// - `2`: normal, `A,B,C,D,E,F,G,H,...`
+    /// - `3`: `A,A,C,C,E,E,G,G,...`
+    /// - `4`: `A,A,A,A,E,E,E,E,...`
+    /// - `5`: `H,H,H,H,H,H,H,H,...`
+    /// - `0,1,6,7`: fill, `X,X,X,X,X,X,X,X,...`
+    fn write_transfer_block(&mut self, len: usize, halfword: impl Fn(usize) -> u16) {
+        if len == 0 {
+            return;
+        }
+        let transfer_type = (self.ram_transfer_control >> 1) & 7;
+
+        for i in 0..len {
+            let source = match transfer_type {
+                2 => i,
+                3 => i & !1,
+                4 => i & !3,
+                // the last of each 8, or the last one if the block is not complete
+                5 => (i | 7).min(len - 1),
+                _ => len - 1,
+            };
+            self.spu_ram
+                .transfer_write(self.i_ram_transfer_address, halfword(source));
             self.i_ram_transfer_address += 1;
             self.i_ram_transfer_address &= 0x3FFFF;
+        }
+    }
 
-            self.spu_ram[self.i_ram_transfer_address] = high;
-            self.i_ram_transfer_address += 1;
-            self.i_ram_transfer_address &= 0x3FFFF;
+    /// Read the data port, only works when the transfer mode is `DmaRead`,
+    /// the RAM is read directly, so the transfer type doesn't affect it
+    fn read_data_port(&mut self) -> u16 {
+        if !matches!(self.control.ram_transfer_mode(), RamTransferMode::DmaRead) {
+            log::warn!(
+                "sound ram data transfer fifo read in mode {:?}, returning 0...",
+                self.control.ram_transfer_mode()
+            );
+            return 0;
         }
 
-        self.stat
-            .remove(SpuStat::DATA_TRANSFER_DMA_WRITE_REQ | SpuStat::DATA_TRANSFER_USING_DMA);
+        let data = self.spu_ram.transfer_read(self.i_ram_transfer_address);
+        self.i_ram_transfer_address += 1;
+        self.i_ram_transfer_address &= 0x3FFFF;
+        data
     }
 
+    /// Read `size` words from the RAM at the transfer address
     pub fn dma_read_buf(&mut self, size: usize) -> Vec<u32> {
         self.in_dma_transfer = true;
         self.stat.insert(SpuStat::DATA_TRANSFER_BUSY_FLAG);
@@ -976,11 +1543,11 @@ impl Spu {
         let mut buf = Vec::with_capacity(size);
 
         for _ in 0..size {
-            let low = self.spu_ram[self.i_ram_transfer_address];
+            let low = self.spu_ram.transfer_read(self.i_ram_transfer_address);
             self.i_ram_transfer_address += 1;
             self.i_ram_transfer_address &= 0x3FFFF;
 
-            let high = self.spu_ram[self.i_ram_transfer_address];
+            let high = self.spu_ram.transfer_read(self.i_ram_transfer_address);
             self.i_ram_transfer_address += 1;
             self.i_ram_transfer_address &= 0x3FFFF;
 
@@ -988,7 +1555,7 @@ impl Spu {
         }
 
         self.stat
-            .remove(SpuStat::DATA_TRANSFER_DMA_WRITE_REQ | SpuStat::DATA_TRANSFER_USING_DMA);
+            .remove(SpuStat::DATA_TRANSFER_DMA_READ_REQ | SpuStat::DATA_TRANSFER_USING_DMA);
         buf
     }
 
@@ -997,29 +1564,48 @@ impl Spu {
     }
 }
 
+fn spu_error(detail: String) -> BusError {
+    BusError::DeviceError {
+        device: "SPU",
+        detail: detail.into(),
+    }
+}
+
 impl BusLine for Spu {
     fn read_u32(&mut self, addr: u32) -> Result<u32> {
         match addr {
-            0x000..=0x17F => Err(format!("SPU u32 read voice register {:03X}", addr)),
-            0x180..=0x187 => Err(format!("SPU u32 read spu control {:03X}", addr)),
-            0x188..=0x19F => Err(format!("SPU u32 read voice flags {:03X}", addr)),
-            0x1A0..=0x1BF => Err(format!("SPU u32 read spu  control {:03X}", addr)),
-            0x1C0..=0x1FF => Err(format!("SPU u32 read reverb configuration {:03X}", addr)),
-            0x200..=0x25F => Err(format!("SPU u32 read voice internal reg {:03X}", addr)),
-            0x260..=0x2FF => unreachable!("u32 read unknown {:03X}", addr),
+            0x000..=0x17F => Err(spu_error(format!("u32 read voice register {:03X}", addr))),
+            0x180..=0x187 => Err(spu_error(format!("u32 read spu control {:03X}", addr))),
+            0x188..=0x19F => Err(spu_error(format!("u32 read voice flags {:03X}", addr))),
+            0x1A0..=0x1BF => Err(spu_error(format!("u32 read spu  control {:03X}", addr))),
+            0x1C0..=0x1FF => Err(spu_error(format!(
+                "u32 read reverb configuration {:03X}",
+                addr
+            ))),
+            0x200..=0x25F => Err(spu_error(format!(
+                "u32 read voice internal reg {:03X}",
+                addr
+            ))),
+            0x260..=0x2FF => Err(spu_error(format!("u32 read unknown {:03X}", addr))),
             _ => unreachable!(),
         }
     }
 
     fn write_u32(&mut self, addr: u32, _data: u32) -> Result<()> {
         match addr {
-            0x000..=0x17F => Err(format!("SPU u32 write voice register {:03X}", addr)),
-            0x180..=0x187 => Err(format!("SPU u32 write spu control {:03X}", addr)),
-            0x188..=0x19F => Err(format!("SPU u32 write voice flags {:03X}", addr)),
-            0x1A0..=0x1BF => Err(format!("SPU u32 write spu  control {:03X}", addr)),
-            0x1C0..=0x1FF => Err(format!("SPU u32 write reverb configuration {:03X}", addr)),
-            0x200..=0x25F => Err(format!("SPU u32 write voice internal reg {:03X}", addr)),
-            0x260..=0x2FF => unreachable!("u32 write unknown {:03X}", addr),
+            0x000..=0x17F => Err(spu_error(format!("u32 write voice register {:03X}", addr))),
+            0x180..=0x187 => Err(spu_error(format!("u32 write spu control {:03X}", addr))),
+            0x188..=0x19F => Err(spu_error(format!("u32 write voice flags {:03X}", addr))),
+            0x1A0..=0x1BF => Err(spu_error(format!("u32 write spu  control {:03X}", addr))),
+            0x1C0..=0x1FF => Err(spu_error(format!(
+                "u32 write reverb configuration {:03X}",
+                addr
+            ))),
+            0x200..=0x25F => Err(spu_error(format!(
+                "u32 write voice internal reg {:03X}",
+                addr
+            ))),
+            0x260..=0x2FF => Err(spu_error(format!("u32 write unknown {:03X}", addr))),
             _ => unreachable!(),
         }
     }
@@ -1030,8 +1616,8 @@ impl BusLine for Spu {
                 let reg = addr & 0xF;
                 let voice_idx = (addr >> 4) as usize;
                 match reg {
-                    0x0 => self.voices[voice_idx].volume_left,
-                    0x2 => self.voices[voice_idx].volume_right,
+                    0x0 => self.voices[voice_idx].volume_left.register,
+                    0x2 => self.voices[voice_idx].volume_right.register,
                     0x4 => self.voices[voice_idx].adpcm_sample_rate,
                     0x6 => self.voices[voice_idx].adpcm_start_address,
                     0x8 => self.voices[voice_idx].adsr_config.bits() as u16,
@@ -1041,8 +1627,8 @@ impl BusLine for Spu {
                     _ => unreachable!(),
                 }
             }
-            0x180 => self.main_vol_left,
-            0x182 => self.main_vol_right,
+            0x180 => self.main_vol_left.register,
+            0x182 => self.main_vol_right.register,
             0x184 => self.reverb_out_vol_left,
             0x186 => self.reverb_out_vol_right,
             // key on and key off should be treated as write only, reading
@@ -1062,6 +1648,7 @@ impl BusLine for Spu {
             0x1A2 => self.reverb_work_base,
             0x1A4 => (self.spu_ram.irq_address / 4) as u16,
             0x1A6 => self.ram_transfer_address,
+            0x1A8 => self.read_data_port(),
             0x1AA => self.control.bits(),
             0x1AC => self.ram_transfer_control,
             0x1AE => self.stat.bits(),
@@ -1069,15 +1656,15 @@ impl BusLine for Spu {
             0x1B2 => self.cd_vol_right,
             0x1B4 => self.external_vol_left,
             0x1B6 => self.external_vol_right,
-            0x1B8 => self.current_main_vol_left as u16,
-            0x1BA => self.current_main_vol_right as u16,
+            0x1B8 => self.main_vol_left.current as u16,
+            0x1BA => self.main_vol_right.current as u16,
             0x1C0..=0x1FE => self.reverb_config[(addr - 0x1C0) as usize / 2],
             0x200..=0x25E => {
-                let voice_idx = ((addr >> 2) & 23) as usize;
+                let voice_idx = ((addr - 0x200) >> 2) as usize;
                 if addr & 0x2 == 0 {
-                    self.voices[voice_idx].current_vol_left as u16
+                    self.voices[voice_idx].volume_left.current as u16
                 } else {
-                    self.voices[voice_idx].current_vol_right as u16
+                    self.voices[voice_idx].volume_right.current as u16
                 }
             }
             0x1A0 | 0x1BC..=0x1BF | 0x260..=0x2FF => {
@@ -1096,20 +1683,8 @@ impl BusLine for Spu {
                 let voice_idx = (addr >> 4) as usize;
                 log::info!("voice {}, reg {:01X} = {:04X}", voice_idx, reg, data);
                 match reg {
-                    0x0 => {
-                        self.voices[voice_idx].volume_left = data;
-                        // volume mode
-                        if data & 0x8000 == 0 {
-                            self.voices[voice_idx].current_vol_left = (data * 2) as i16;
-                        }
-                    }
-                    0x2 => {
-                        self.voices[voice_idx].volume_right = data;
-                        // volume mode
-                        if data & 0x8000 == 0 {
-                            self.voices[voice_idx].current_vol_right = (data * 2) as i16;
-                        }
-                    }
+                    0x0 => self.voices[voice_idx].volume_left.write(data),
+                    0x2 => self.voices[voice_idx].volume_right.write(data),
                     0x4 => self.voices[voice_idx].adpcm_sample_rate = data,
                     0x6 => self.voices[voice_idx].adpcm_start_address = data,
                     0x8 => {
@@ -1122,26 +1697,18 @@ impl BusLine for Spu {
                         self.voices[voice_idx].adsr_config =
                             ADSRConfig::from_bits_retain((f & 0xFFFF) | ((data as u32) << 16));
                     }
-                    0xC => self.voices[voice_idx].adsr_current_vol = data,
+                    0xC => self.voices[voice_idx].write_adsr_level(data),
                     0xE => self.voices[voice_idx].adpcm_repeat_address = data,
                     _ => unreachable!(),
                 }
             }
             0x180 => {
                 log::info!("main vol left = {:04X}", data);
-                self.main_vol_left = data;
-                // volume mode
-                if data & 0x8000 == 0 {
-                    self.current_main_vol_left = (data * 2) as i16;
-                }
+                self.main_vol_left.write(data);
             }
             0x182 => {
                 log::info!("main vol right = {:04X}", data);
-                self.main_vol_right = data;
-                // volume mode
-                if data & 0x8000 == 0 {
-                    self.current_main_vol_right = (data * 2) as i16;
-                }
+                self.main_vol_right.write(data);
             }
             0x184 => {
                 log::info!("reverb vol left = {:04X}", data);
@@ -1160,6 +1727,8 @@ impl BusLine for Spu {
                     if self.key_on_flag.get(i) {
                         self.endx_flag.set(i, false);
                         self.voices[i].key_on();
+                        self.tracer
+                            .trace(|| TraceEvent::SpuKeyOn { voice: i as u8 });
                     }
                 }
             }
@@ -1173,6 +1742,8 @@ impl BusLine for Spu {
                     if self.key_on_flag.get(i) {
                         self.endx_flag.set(i, false);
                         self.voices[i].key_on();
+                        self.tracer
+                            .trace(|| TraceEvent::SpuKeyOn { voice: i as u8 });
                     }
                 }
             }
@@ -1209,6 +1780,7 @@ impl BusLine for Spu {
                     "pitch mod flag = {:08X}",
                     self.pitch_mod_channel_flag.get_all()
                 );
+                self.warn_unsupported_voice_mode(&self.pitch_mod_channel_flag, "pitch modulation");
             }
             0x192 => {
                 let f = self.pitch_mod_channel_flag.get_all();
@@ -1218,6 +1790,7 @@ impl BusLine for Spu {
                     "pitch mod flag = {:08X}",
                     self.pitch_mod_channel_flag.get_all()
                 );
+                self.warn_unsupported_voice_mode(&self.pitch_mod_channel_flag, "pitch modulation");
             }
             0x194 => {
                 let f = self.noise_channel_mode_flag.get_all();
@@ -1227,6 +1800,7 @@ impl BusLine for Spu {
                     "noise channel mode flag = {:08X}",
                     self.noise_channel_mode_flag.get_all()
                 );
+                self.warn_unsupported_voice_mode(&self.noise_channel_mode_flag, "noise mode");
             }
             0x196 => {
                 let f = self.noise_channel_mode_flag.get_all();
@@ -1236,6 +1810,7 @@ impl BusLine for Spu {
                     "noise channel mode flag = {:08X}",
                     self.noise_channel_mode_flag.get_all()
                 );
+                self.warn_unsupported_voice_mode(&self.noise_channel_mode_flag, "noise mode");
             }
             0x198 => {
                 let f = self.reverb_channel_mode_flag.get_all();
@@ -1300,10 +1875,8 @@ impl BusLine for Spu {
                 log::info!("spu control {:04X}", data);
             }
             0x1AC => {
+                log::info!("sound ram data transfer control {:04X}", data);
                 self.ram_transfer_control = data;
-                // TODO: support more control modes
-                //let control_mode = (data >> 1) & 7;
-                //assert!(control_mode == 2);
             }
             0x1AE => log::warn!("u16 write SpuStat is not supported, ignoring..."),
             0x1B0 => {
@@ -1316,11 +1889,17 @@ impl BusLine for Spu {
             }
             0x1B4 => self.external_vol_left = data,
             0x1B6 => self.external_vol_right = data,
-            0x1B8 => self.current_main_vol_left = data as i16,
-            0x1BA => self.current_main_vol_right = data as i16,
+            0x1B8 => self.main_vol_left.write_current(data),
+            0x1BA => self.main_vol_right.write_current(data),
             0x1C0..=0x1FE => self.reverb_config[(addr - 0x1C0) as usize / 2] = data,
-            // TODO: not sure if this is writable, since its internal current vol
-            0x200..=0x25F => todo!("u16 write voice internal reg {:03X}", addr),
+            0x200..=0x25F => {
+                let voice_idx = ((addr - 0x200) >> 2) as usize;
+                if addr & 0x2 == 0 {
+                    self.voices[voice_idx].volume_left.write_current(data);
+                } else {
+                    self.voices[voice_idx].volume_right.write_current(data);
+                }
+            }
             0x1A0 | 0x1BC..=0x1BF | 0x260..=0x2FF => {
                 log::warn!(
                     "Writing value {:04X} to unknown register {:03X}, ignoring...",
@@ -1333,21 +1912,816 @@ impl BusLine for Spu {
         Ok(())
     }
 
-    fn read_u8(&mut self, _addr: u32) -> Result<u8> {
-        todo!()
+    fn read_u8(&mut self, addr: u32) -> Result<u8> {
+        Err(spu_error(format!("u8 read {:03X}", addr)))
+    }
+
+    fn write_u8(&mut self, addr: u32, data: u8) -> Result<()> {
+        // only the CPU can do 8bit writes, and it gives us the whole word,
+        // so this is from somewhere else, treat the rest as zeros
+        self.write_u8_from_word(addr, data as u32)
     }
 
-    fn write_u8(&mut self, addr: u32, _data: u8) -> Result<()> {
+    fn write_u8_from_word(&mut self, addr: u32, word: u32) -> Result<()> {
         // The SPU is connected to a 16bit databus.
         // 8bit/16bit/32bit reads and 16bit/32bit writes are implemented.
         // However, 8bit writes are NOT implemented: 8bit writes to
         // ODD addresses are simply ignored (without causing any exceptions),
         // 8bit writes to EVEN addresses are executed as 16bit writes
         // (eg. "movp r1,12345678h, movb [spu_port],r1" will write 5678h instead of 78h).
-        //
-        // TODO: implement this behavior of 8bit writes, we need to get access
-        //       to the whole 32bit word from the CPU
+        if addr & 1 == 1 {
+            log::warn!("spu 8bit write to odd address {:03X}, ignoring", addr);
+            return Ok(());
+        }
+        self.write_u16(addr, word as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::interrupts::Interrupts;
+
+    const SPU_IRQ: u16 = 1 << 9;
+
+    fn spu_with_irq(irq_address: u16) -> Spu {
+        let mut spu = Spu::default();
+        spu.write_u16(0x1A4, irq_address).unwrap();
+        spu.write_u16(
+            0x1AA,
+            (SpuControl::SPU_ENABLE | SpuControl::IRQ9_ENABLE).bits(),
+        )
+        .unwrap();
+        spu
+    }
+
+    /// Run one SPU sample, returns `true` if the IRQ was requested
+    fn clock_sample(spu: &mut Spu, interrupts: &mut Interrupts) -> bool {
+        spu.clock(interrupts, CPU_CLOCKS_PER_SPU);
+        let requested = interrupts.read_u16(0).unwrap() & SPU_IRQ != 0;
+        interrupts.write_u32(0, 0).unwrap();
+        requested
+    }
+
+    fn acknowledge(spu: &mut Spu) {
+        spu.write_u16(0x1AA, SpuControl::SPU_ENABLE.bits()).unwrap();
+        spu.write_u16(
+            0x1AA,
+            (SpuControl::SPU_ENABLE | SpuControl::IRQ9_ENABLE).bits(),
+        )
+        .unwrap();
+    }
+
+    /// Clock the envelope of `voice` `samples` times, returns the level register
+    /// and the state after each sample
+    fn adsr_curve(voice: &mut Voice, samples: usize) -> Vec<(u16, ADSRState)> {
+        (0..samples)
+            .map(|_| {
+                voice.clock_adsr();
+                (voice.adsr_current_vol, voice.i_adsr_state)
+            })
+            .collect()
+    }
+
+    /// Play a block alternating between `0` and `0x7000`, then a block of `-0x7000`,
+    /// at a quarter of the rate, returns the voice output before the volume
+    fn play_low_pitch(interpolation: SpuInterpolation) -> Vec<i16> {
+        let mut ram = SpuRam::default();
+        // shift 0 and filter 0, every nibble is a sample `<< 12`
+        for i in 1..8 {
+            ram.data[0x800 + i] = 0x7070;
+            ram.data[0x808 + i] = 0x9999;
+        }
+        let mut voice = Voice {
+            adpcm_sample_rate: 0x400,
+            adpcm_start_address: 0x200,
+            ..Default::default()
+        };
+        voice.key_on();
+        // keep the envelope at the max level
+        voice.set_adsr_state(ADSRState::Stopped);
+        voice.i_adsr_level = 0x7FFF;
+
+        (0..2 * 28 * 4)
+            .map(|_| voice.clock_voice(&mut ram, interpolation).1)
+            .collect()
+    }
+
+    #[test]
+    fn gaussian_interpolation() {
+        let output = play_low_pitch(SpuInterpolation::Gaussian);
+        // computed from `GAUSS_TABLE`, there is no history for the first samples
+        assert_eq!(
+            output[..12],
+            [0, 0, 0, 0, 0, 33, 370, 1564, 4261, 8662, 13953, 18455]
+        );
+        // the end of the first block, and the start of the second, which still
+        // uses the last 3 samples of the first
+        assert_eq!(
+            output[104..120],
+            [
+                8467, 10198, 14313, 18487, 20950, 18432, 14243, 10148, 8467, 10163, 13942, 16921,
+                16688, 9699, -452, -11438
+            ]
+        );
+
+        // the filter smooths the square wave, nearest keeps it
+        let nearest = play_low_pitch(SpuInterpolation::Nearest);
+        assert_eq!(nearest[4..12], [28671, 28671, 28671, 28671, 0, 0, 0, 0]);
+        assert_eq!(nearest[112], -28671);
+        let peak = |samples: &[i16]| samples[..112].iter().map(|&s| (s as i32).abs()).max();
+        assert!(peak(&output).unwrap() < peak(&nearest).unwrap() * 3 / 4);
+    }
+
+    #[test]
+    fn adsr_level_write_during_sustain() {
+        let mut voice = Voice {
+            // linear attack (+7 << 11), decay to level 0x4000, linear sustain
+            // decrease (-8 every 4 samples)
+            adsr_config: ADSRConfig::from_bits_retain(0x4D0C0007),
+            ..Default::default()
+        };
+        voice.key_on();
+        assert_eq!(
+            adsr_curve(&mut voice, 5),
+            [
+                (0x3800, ADSRState::Attack),
+                (0x7000, ADSRState::Attack),
+                (0x7FFF, ADSRState::Decay),
+                (0x3FFF, ADSRState::Sustain),
+                (0x3FF7, ADSRState::Sustain),
+            ]
+        );
+
+        // the register keeps the written value until the next step, which
+        // continues from it
+        voice.write_adsr_level(0x3000);
+        assert_eq!(voice.adsr_current_vol, 0x3000);
+        let levels: Vec<u16> = adsr_curve(&mut voice, 11).iter().map(|(l, _)| *l).collect();
+        assert_eq!(
+            levels,
+            [
+                0x3000, 0x3000, 0x2FF8, 0x2FF8, 0x2FF8, 0x2FF8, 0x2FF0, 0x2FF0, 0x2FF0, 0x2FF0,
+                0x2FE8
+            ]
+        );
+        assert_eq!(voice.i_adsr_state, ADSRState::Sustain);
+    }
+
+    #[test]
+    fn adsr_full_curve() {
+        let mut voice = Voice {
+            // exponential attack (shift 10, +7), decay shift 4 to level 0x5000,
+            // exponential sustain decrease (shift 14, -8), exponential release (shift 9)
+            adsr_config: ADSRConfig::from_bits_retain(0xCE29A849),
+            ..Default::default()
+        };
+        voice.key_on();
+        let curve = adsr_curve(&mut voice, 8000);
+
+        // computed from the formula of the documentation
+        let expected = [
+            (0, 0x000E, ADSRState::Attack),
+            (1, 0x001C, ADSRState::Attack),
+            (1000, 0x36BE, ADSRState::Attack),
+            (1754, 0x5FFA, ADSRState::Attack),
+            // above 0x6000, 4 times slower
+            (1755, 0x6008, ADSRState::Attack),
+            (2000, 0x6365, ADSRState::Attack),
+            (2500, 0x6A3B, ADSRState::Attack),
+            (4091, 0x7FF8, ADSRState::Attack),
+            (4092, 0x7FFF, ADSRState::Decay),
+            (4107, 0x520C, ADSRState::Decay),
+            (4108, 0x4F7B, ADSRState::Sustain),
+            (6000, 0x4ADA, ADSRState::Sustain),
+            (7999, 0x45F8, ADSRState::Sustain),
+        ];
+        for (sample, level, state) in expected {
+            assert_eq!(curve[sample], (level, state), "sample {}", sample);
+        }
+
+        voice.key_off();
+        let release = adsr_curve(&mut voice, 3549);
+        let expected = [
+            (0, 0x45E6),
+            (1, 0x45D4),
+            (10, 0x4532),
+            (100, 0x3F33),
+            (500, 0x2A21),
+            (1000, 0x1917),
+            // the exponential decrease never stops before reaching 0
+            (3547, 0x0001),
+            (3548, 0x0000),
+        ];
+        for (sample, level) in expected {
+            assert_eq!(release[sample].0, level, "release sample {}", sample);
+        }
+        assert_eq!(release[3547].1, ADSRState::Release);
+        assert_eq!(release[3548].1, ADSRState::Stopped);
+
+        voice.clock_adsr();
+        assert!(!voice.is_on);
+    }
+
+    #[test]
+    fn u32_access_error() {
+        let mut spu = Spu::default();
+        assert_eq!(
+            spu.read_u32(0x1C0).unwrap_err(),
+            BusError::DeviceError {
+                device: "SPU",
+                detail: "u32 read reverb configuration 1C0".into()
+            }
+        );
+        assert!(matches!(
+            spu.write_u32(0x180, 0),
+            Err(BusError::DeviceError { device: "SPU", .. })
+        ));
+    }
+
+    #[test]
+    fn unsupported_access_errors() {
+        let mut spu = Spu::default();
+        assert_eq!(
+            spu.read_u8(0x1AE).unwrap_err(),
+            BusError::DeviceError {
+                device: "SPU",
+                detail: "u8 read 1AE".into()
+            }
+        );
+        assert!(spu.write_u16(0x200, 0).is_err());
+        assert!(spu.read_u32(0x280).is_err());
+        // still usable after that
+        spu.write_u16(0x180, 0x1234).unwrap();
+        assert_eq!(spu.read_u16(0x180).unwrap(), 0x1234);
+    }
+
+    #[test]
+    fn unsupported_voice_modes() {
+        let mut spu = Spu::default();
+        let warnings = Warnings::default();
+        warnings.set_strict(false);
+        spu.set_warnings(warnings.clone());
+        let mut interrupts = Interrupts::default();
+
+        // clearing them is fine
+        spu.write_u16(0x190, 0).unwrap();
+        spu.write_u16(0x196, 0).unwrap();
+        assert_eq!(warnings.count(), 0);
+
+        spu.write_u16(0x192, 0x0001).unwrap();
+        spu.write_u16(0x194, 0x0004).unwrap();
+        let list = warnings.list();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].message, "pitch modulation is not supported");
+        assert_eq!(list[1].message, "noise mode is not supported");
+
+        // the voices still play
+        spu.write_u16(0x1AA, 0x8000).unwrap();
+        spu.write_u16(0x188, 0x0004).unwrap();
+        spu.clock(&mut interrupts, 0x300 * 10);
+        assert!(spu.state().voices[2].key_on);
+    }
+
+    #[test]
+    fn u8_write_even_address() {
+        let mut spu = Spu::default();
+        // `sb` of `0x12345678` to the main volume left
+        spu.write_u8_from_word(0x180, 0x1234_5678).unwrap();
+        assert_eq!(spu.read_u16(0x180).unwrap(), 0x5678);
+
+        // cd volume right
+        spu.write_u8_from_word(0x1B2, 0xABCD).unwrap();
+        assert_eq!(spu.read_u16(0x1B2).unwrap(), 0xABCD);
+    }
+
+    #[test]
+    fn u8_write_odd_address() {
+        let mut spu = Spu::default();
+        spu.write_u16(0x180, 0x1111).unwrap();
+        spu.write_u8_from_word(0x181, 0x1234_5678).unwrap();
+        assert_eq!(spu.read_u16(0x180).unwrap(), 0x1111);
+    }
+
+    /// Write 16 halfwords with the transfer `control`, and read them back
+    fn transfer_roundtrip(control: u16) -> Vec<u16> {
+        let mut interrupts = Interrupts::default();
+        let mut spu = Spu::default();
+        spu.write_u16(0x1AC, control).unwrap();
+        spu.write_u16(0x1A6, 0x1000 / 4).unwrap();
+        for i in 0..16 {
+            spu.write_u16(0x1A8, 0x100 + i).unwrap();
+        }
+        // manual write
+        spu.write_u16(0x1AA, SpuControl::SPU_ENABLE.bits() | 0x10)
+            .unwrap();
+        spu.clock(&mut interrupts, CPU_CLOCKS_PER_SPU);
+
+        // dma read
+        spu.write_u16(0x1AA, SpuControl::SPU_ENABLE.bits() | 0x30)
+            .unwrap();
+        spu.write_u16(0x1A6, 0x1000 / 4).unwrap();
+        (0..16).map(|_| spu.read_u16(0x1A8).unwrap()).collect()
+    }
+
+    #[test]
+    fn transfer_types() {
+        let expected = |f: fn(u16) -> u16| (0..16).map(|i| 0x100 + f(i)).collect::<Vec<_>>();
+
+        assert_eq!(transfer_roundtrip(2 << 1), expected(|i| i));
+        assert_eq!(transfer_roundtrip(3 << 1), expected(|i| i & !1));
+        assert_eq!(transfer_roundtrip(4 << 1), expected(|i| i & !3));
+        assert_eq!(transfer_roundtrip(5 << 1), expected(|i| i | 7));
+        for fill in [0, 1, 6, 7] {
+            assert_eq!(transfer_roundtrip(fill << 1), expected(|_| 15));
+        }
+    }
+
+    #[test]
+    fn transfer_dma_write() {
+        let mut spu = Spu::default();
+        spu.write_u16(0x1AC, 2 << 1).unwrap();
+        spu.write_u16(0x1A6, 0x1000 / 4).unwrap();
+        spu.dma_write_buf(&[0x2222_1111, 0x4444_3333]);
+
+        spu.write_u16(0x1AA, SpuControl::SPU_ENABLE.bits() | 0x30)
+            .unwrap();
+        spu.write_u16(0x1A6, 0x1000 / 4).unwrap();
+        assert_eq!(spu.dma_read_buf(2), [0x2222_1111, 0x4444_3333]);
+    }
+
+    #[test]
+    fn data_port_read_outside_dma_read() {
+        let mut spu = Spu::default();
+        spu.write_u16(0x1AA, SpuControl::SPU_ENABLE.bits() | 0x10)
+            .unwrap();
+        assert_eq!(spu.read_u16(0x1A8).unwrap(), 0);
+    }
+
+    #[test]
+    fn irq_in_capture_buffer() {
+        let mut interrupts = Interrupts::default();
+        // 0x40th sample of the cd left capture buffer
+        let mut spu = spu_with_irq(0x40 / 4);
+
+        for _ in 0..0x40 {
+            assert!(!clock_sample(&mut spu, &mut interrupts));
+        }
+        assert!(clock_sample(&mut spu, &mut interrupts));
+        assert!(spu.stat.intersects(SpuStat::IRQ_FLAG));
+
+        // not raised again on the next pass without acknowledging
+        for _ in 0..CAPTURE_MEMORY_REGION_SIZE {
+            assert!(!clock_sample(&mut spu, &mut interrupts));
+        }
+
+        acknowledge(&mut spu);
+        assert!(!spu.stat.intersects(SpuStat::IRQ_FLAG));
+        for _ in 0..CAPTURE_MEMORY_REGION_SIZE - 1 {
+            assert!(!clock_sample(&mut spu, &mut interrupts));
+        }
+        assert!(clock_sample(&mut spu, &mut interrupts));
+    }
+
+    #[test]
+    fn irq_in_voice_samples() {
+        let mut interrupts = Interrupts::default();
+        // the second block of the voice
+        let mut spu = spu_with_irq(0x202);
+        spu.write_u16(0x4, 0x1000).unwrap();
+        spu.write_u16(0x6, 0x200).unwrap();
+        spu.write_u16(0x188, 1).unwrap();
+
+        // 1 sample per clock, the first block is fetched on the first clock
+        for _ in 0..28 {
+            assert!(!clock_sample(&mut spu, &mut interrupts));
+        }
+        assert!(clock_sample(&mut spu, &mut interrupts));
+    }
+
+    #[test]
+    fn irq_in_capture_region_only_from_hardware_accesses() {
+        let mut interrupts = Interrupts::default();
+        // the 0x10th sample of the voice 1 capture buffer
+        let mut spu = spu_with_irq((0x400 + 0x10) / 4);
+
+        // the emulator looking at the RAM doesn't count
+        spu.state();
+        spu.hash_state(&mut crate::state_hash::StateHasher::default());
+        // reading the halfwords just before it
+        spu.write_u16(0x1A6, (0x400 + 0x0C) / 4).unwrap();
+        spu.dma_read_buf(2);
+        spu.clock(&mut interrupts, 0);
+        assert_eq!(interrupts.read_u16(0).unwrap() & SPU_IRQ, 0);
+
+        // the other capture buffers write the same position on the same sample
+        for _ in 0..0x10 {
+            assert!(!clock_sample(&mut spu, &mut interrupts));
+        }
+        assert!(clock_sample(&mut spu, &mut interrupts));
+
+        // the transfer continues at the irq address
+        acknowledge(&mut spu);
+        spu.dma_read_buf(1);
+        spu.clock(&mut interrupts, 0);
+        assert_eq!(interrupts.read_u16(0).unwrap() & SPU_IRQ, SPU_IRQ);
+    }
+
+    #[test]
+    fn irq_in_streamed_voice_buffer() {
+        // (irq address, the samples where the IRQ is requested)
+        // a block is fetched every 28 samples, the first on the first sample
+        for (irq_address, expected) in [
+            // the start and the middle of the third block
+            (0x1020 / 8, [57, 169, 281]),
+            (0x1028 / 8, [57, 169, 281]),
+            // the fourth block
+            (0x1030 / 8, [85, 197, 309]),
+        ] {
+            let mut interrupts = Interrupts::default();
+            let mut spu = spu_with_irq(irq_address);
+            // a ring buffer of 4 blocks at `0x1000`, loop start on the first block,
+            // `End+Repeat` on the last
+            spu.spu_ram.data[0x800] = 0x0400;
+            spu.spu_ram.data[0x818] = 0x0300;
+            // normal transfers
+            spu.write_u16(0x1AC, 0x0004).unwrap();
+
+            spu.write_u16(0x4, 0x1000).unwrap();
+            spu.write_u16(0x6, 0x1000 / 8).unwrap();
+            spu.write_u16(0x188, 1).unwrap();
+
+            let mut requested = Vec::new();
+            for sample in 1..=320 {
+                if clock_sample(&mut spu, &mut interrupts) {
+                    requested.push(sample);
+                    acknowledge(&mut spu);
+                    // refill the first half, like a game streaming audio
+                    let mut words = [0x7777_7777; 2 * 4];
+                    words[0] = 0x7777_0400;
+                    words[4] = 0x7777_0000;
+                    spu.write_u16(0x1A6, 0x1000 / 8).unwrap();
+                    spu.dma_write_buf(&words);
+                }
+            }
+            assert_eq!(requested, expected, "irq address {:04X}", irq_address);
+            // it kept looping
+            assert!(spu.endx_flag.get(0));
+        }
+    }
+
+    #[test]
+    fn voice_state_key_on() {
+        let mut interrupts = Interrupts::default();
+        let mut spu = Spu::default();
+        spu.write_u16(
+            0x1AA,
+            (SpuControl::SPU_ENABLE | SpuControl::UNMUTE_SPU).bits(),
+        )
+        .unwrap();
+        // voice 2, max volume, fast attack
+        spu.write_u16(0x20, 0x3FFF).unwrap();
+        spu.write_u16(0x22, 0x1000).unwrap();
+        spu.write_u16(0x24, 0x1000).unwrap();
+        spu.write_u16(0x26, 0x200).unwrap();
+        spu.write_u16(0x28, 0x00FF).unwrap();
+        // a block with a constant sample (shift 0, filter 0, no flags, all nibbles 7)
+        spu.spu_ram.data[0x200 * 4] = 0x0000;
+        for i in 1..8 {
+            spu.spu_ram.data[0x200 * 4 + i] = 0x7777;
+        }
+        spu.write_u16(0x188, 1 << 2).unwrap();
+
+        for _ in 0..10 {
+            clock_sample(&mut spu, &mut interrupts);
+        }
+
+        let state = spu.state();
+        assert_eq!(
+            state.control,
+            (SpuControl::SPU_ENABLE | SpuControl::UNMUTE_SPU).bits()
+        );
+        let voice = state.voices[2];
+        assert!(voice.key_on && !voice.key_off && !voice.endx);
+        assert_eq!((voice.volume_left, voice.volume_right), (0x3FFF, 0x1000));
+        assert_eq!(voice.sample_rate, 0x1000);
+        assert_eq!(voice.start_address, 0x200);
+        // the first block is fetched
+        assert_eq!(voice.current_address, 0x202);
+        assert_eq!(voice.adsr_config, 0x00FF);
+        assert_ne!(voice.adsr_state, ADSRState::Stopped);
+        assert!(voice.adsr_level > 0);
+        assert!(voice.last_output_left > 0);
+        // the right volume is lower
+        assert!(voice.last_output_right > 0 && voice.last_output_right < voice.last_output_left);
+
+        // the other voices are silent
+        for (i, voice) in state.voices.iter().enumerate() {
+            if i != 2 {
+                assert!(!voice.key_on, "voice {}", i);
+                assert_eq!((voice.last_output_left, voice.last_output_right), (0, 0));
+            }
+        }
+    }
+
+    #[test]
+    fn reset_while_playing() {
+        let mut interrupts = Interrupts::default();
+        for keep_ram in [false, true] {
+            let mut spu = Spu::default();
+            spu.set_voice_muted(5, true);
+            spu.write_u16(
+                0x1AA,
+                (SpuControl::SPU_ENABLE | SpuControl::UNMUTE_SPU).bits(),
+            )
+            .unwrap();
+            spu.spu_ram.data[0x1000] = 0x1234;
+            spu.write_u16(0x20, 0x3FFF).unwrap();
+            spu.write_u16(0x188, 1 << 2).unwrap();
+            spu.add_cdrom_audio((0..20).map(|i| (i * 100, -i * 100)));
+            for _ in 0..10 {
+                clock_sample(&mut spu, &mut interrupts);
+            }
+
+            spu.reset(keep_ram);
+            let state = spu.state();
+            assert_eq!(state.control, 0);
+            assert!(state
+                .voices
+                .iter()
+                .all(|v| !v.key_on && v.last_output_left == 0));
+            assert_eq!(spu.spu_ram.data[0x1000], if keep_ram { 0x1234 } else { 0 });
+            // the host setting is kept
+            assert_eq!(spu.host_mix.muted_voices, 1 << 5);
+        }
+    }
+
+    /// Run 2 voices with different samples, returns the output audio
+    /// and the capture buffers
+    fn run_two_voices(mute: impl FnOnce(&mut Spu)) -> (Vec<f32>, Vec<u16>) {
+        let mut interrupts = Interrupts::default();
+        let mut spu = Spu::default();
+        spu.write_u16(
+            0x1AA,
+            (SpuControl::SPU_ENABLE | SpuControl::UNMUTE_SPU).bits(),
+        )
+        .unwrap();
+        spu.write_u16(0x180, 0x3FFF).unwrap();
+        spu.write_u16(0x182, 0x3FFF).unwrap();
+        spu.write_u16(0x1B0, 0x3FFF).unwrap();
+        spu.write_u16(0x1B2, 0x3FFF).unwrap();
+        // voices 1 and 3, they are both in the capture buffers
+        for (voice, address, nibbles) in [(1, 0x200, 0x7777), (3, 0x300, 0x3333)] {
+            let base = voice * 0x10;
+            spu.write_u16(base, 0x3FFF).unwrap();
+            spu.write_u16(base + 0x2, 0x3FFF).unwrap();
+            spu.write_u16(base + 0x4, 0x1000).unwrap();
+            spu.write_u16(base + 0x6, address).unwrap();
+            spu.write_u16(base + 0x8, 0x00FF).unwrap();
+            for i in 1..8 {
+                spu.spu_ram.data[address as usize * 4 + i] = nibbles;
+            }
+        }
+        spu.add_cdrom_audio((0..20).map(|i| (i * 100, -i * 100)));
+        mute(&mut spu);
+        spu.write_u16(0x188, (1 << 1) | (1 << 3)).unwrap();
+
+        for _ in 0..20 {
+            clock_sample(&mut spu, &mut interrupts);
+        }
+        let mut audio = Vec::new();
+        spu.take_audio_buffer(&mut audio);
+        (
+            audio,
+            spu.spu_ram.data[..CAPTURE_MEMORY_REGION_SIZE * 4].to_vec(),
+        )
+    }
+
+    #[test]
+    fn host_mix_muting() {
+        let (audio, capture) = run_two_voices(|_| {});
+        assert!(audio.iter().any(|&s| s != 0.0));
+
+        let mutes: [fn(&mut Spu); 4] = [
+            |spu| spu.set_voice_muted(1, true),
+            |spu| spu.solo_voice(Some(3)),
+            |spu| spu.set_cd_audio_muted(true),
+            |spu| {
+                spu.set_voice_muted(1, true);
+                spu.set_voice_muted(3, true);
+                spu.set_cd_audio_muted(true);
+            },
+        ];
+        for (i, mute) in mutes.into_iter().enumerate() {
+            let (muted_audio, muted_capture) = run_two_voices(mute);
+            assert_ne!(muted_audio, audio, "{}", i);
+            // the game can't tell
+            assert_eq!(muted_capture, capture, "{}", i);
+        }
+
+        // everything muted
+        let (muted_audio, _) = run_two_voices(mutes[3]);
+        assert!(muted_audio.iter().all(|&s| s == 0.0));
+
+        // unmuting and solo of a muted voice
+        let (unmuted_audio, _) = run_two_voices(|spu| {
+            spu.set_voice_muted(1, true);
+            spu.set_voice_muted(1, false);
+            spu.solo_voice(Some(3));
+            spu.solo_voice(None);
+        });
+        assert_eq!(unmuted_audio, audio);
+    }
+
+    #[test]
+    fn irq_disabled_is_not_latched() {
+        let mut interrupts = Interrupts::default();
+        let mut spu = spu_with_irq(0);
+        spu.write_u16(0x1AA, SpuControl::SPU_ENABLE.bits()).unwrap();
+
+        // the capture buffer writes to address 0 on the first clock
+        assert!(!clock_sample(&mut spu, &mut interrupts));
+        spu.write_u16(
+            0x1AA,
+            (SpuControl::SPU_ENABLE | SpuControl::IRQ9_ENABLE).bits(),
+        )
+        .unwrap();
+        assert!(!clock_sample(&mut spu, &mut interrupts));
+    }
+
+    #[test]
+    fn irq_on_transfer() {
+        let mut interrupts = Interrupts::default();
+        let mut spu = spu_with_irq(0x1000 / 4);
+        spu.write_u16(0x1A6, 0x1000 / 4).unwrap();
+
+        spu.dma_write_buf(&[0x1234_5678]);
+        // raised on the next clock, even before the next sample
+        spu.clock(&mut interrupts, 0);
+        assert_eq!(interrupts.read_u16(0).unwrap() & SPU_IRQ, SPU_IRQ);
+    }
+
+    /// Play `samples` of external audio, returns the output
+    fn run_external_audio(control: SpuControl, samples: &[(i16, i16)]) -> Vec<f32> {
+        let mut interrupts = Interrupts::default();
+        let mut spu = Spu::default();
+        spu.write_u16(0x1AA, control.bits()).unwrap();
+        // half volume on the left, a quarter on the right
+        spu.write_u16(0x1B4, 0x4000).unwrap();
+        spu.write_u16(0x1B6, 0x2000).unwrap();
+        spu.add_external_audio(samples);
+        for _ in 0..samples.len() {
+            clock_sample(&mut spu, &mut interrupts);
+        }
+        let mut out = Vec::new();
+        spu.take_audio_buffer(&mut out);
+        out
+    }
+
+    #[test]
+    fn external_audio() {
+        let tone = (0..64)
+            .map(|i| {
+                let s = if i % 8 < 4 { 0x4000 } else { -0x4000 };
+                (s, s / 2)
+            })
+            .collect::<Vec<(i16, i16)>>();
+        let enabled = SpuControl::SPU_ENABLE | SpuControl::UNMUTE_SPU;
+
+        let out = run_external_audio(enabled | SpuControl::EXTERNAL_AUDIO_ENABLE, &tone);
+        let expected = tone
+            .iter()
+            .flat_map(|&(left, right)| [left as i32 / 2, right as i32 / 4])
+            .map(|s| s as f32 / 0x8000 as f32)
+            .collect::<Vec<_>>();
+        assert_eq!(out, expected);
+
+        let out = run_external_audio(enabled, &tone);
+        assert!(out.iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn external_audio_bounded() {
+        let mut spu = Spu::default();
+        for _ in 0..40 {
+            spu.add_external_audio(&[(1, 1); 735]);
+        }
+        assert_eq!(spu.external_audio_buffer.len(), MAX_EXTERNAL_AUDIO_SAMPLES);
+        assert!(spu.external_audio_overflow);
+    }
+
+    #[test]
+    fn bounded_buffers_with_spu_disabled() {
+        let mut spu = Spu::default();
+        let mut interrupts = Interrupts::default();
+        let mut out = Vec::new();
+
+        // the CD keeps delivering a bit more than what is played
+        for frame in 0..600 {
+            spu.add_cdrom_audio((0..800).map(|i| (i, -i)));
+            for _ in 0..735 {
+                clock_sample(&mut spu, &mut interrupts);
+            }
+            out.clear();
+            spu.take_audio_buffer(&mut out);
+            assert_eq!(out.len(), 735 * 2);
+            assert!(spu.cdrom_audio_buffer.len() <= MAX_CDROM_AUDIO_SAMPLES);
+            if frame == 0 {
+                assert!(!spu.cdrom_audio_overflow);
+            }
+        }
+        assert!(spu.cdrom_audio_overflow);
+        // the output buffer is reused
+        assert!(spu.out_audio_buffer.capacity() <= 735 * 2 * 2);
+    }
+
+    #[test]
+    fn main_volume_exponential_sweep() {
+        let mut interrupts = Interrupts::default();
+        let mut spu = Spu::default();
+        spu.write_u16(
+            0x1AA,
+            (SpuControl::SPU_ENABLE | SpuControl::UNMUTE_SPU).bits(),
+        )
+        .unwrap();
+        spu.write_u16(0x180, 0x3FFF).unwrap();
+        spu.write_u16(0x182, 0x3FFF).unwrap();
+        // voice 0, max volume, fast attack
+        spu.write_u16(0x0, 0x3FFF).unwrap();
+        spu.write_u16(0x2, 0x3FFF).unwrap();
+        spu.write_u16(0x4, 0x1000).unwrap();
+        spu.write_u16(0x6, 0x200).unwrap();
+        spu.write_u16(0x8, 0x00FF).unwrap();
+        // a block with a constant sample, `End+Repeat` back to itself
+        spu.spu_ram.data[0x200 * 4] = 0x0300;
+        for i in 1..8 {
+            spu.spu_ram.data[0x200 * 4 + i] = 0x7777;
+        }
+        spu.write_u16(0x188, 1).unwrap();
+        for _ in 0..10 {
+            clock_sample(&mut spu, &mut interrupts);
+        }
+        assert_eq!(spu.read_u16(0x1B8).unwrap(), 0x7FFE);
+
+        // exponential decrease, shift 8, step -8
+        spu.write_u16(0x180, 0xE000 | (8 << 2)).unwrap();
+        assert_eq!(spu.read_u16(0x180).unwrap(), 0xE020);
+        // starts from the current volume
+        assert_eq!(spu.read_u16(0x1B8).unwrap(), 0x7FFE);
+
+        let mut audio = Vec::new();
+        spu.take_audio_buffer(&mut audio);
+        let mut levels = Vec::new();
+        for _ in 0..3000 {
+            clock_sample(&mut spu, &mut interrupts);
+            let level = spu.read_u16(0x1B8).unwrap();
+            levels.push(level);
+
+            audio.clear();
+            spu.take_audio_buffer(&mut audio);
+            let voice_output = spu.voices[0].i_last_output_left as i32;
+            assert!(voice_output > 0);
+            let expected_left = voice_output * level as i32 / 0x8000;
+            assert_eq!(audio[0], expected_left as f32 / 0x8000 as f32);
+            // the right side is not affected
+            assert_eq!(spu.read_u16(0x1BA).unwrap(), 0x7FFE);
+        }
+
+        // every sample, the step is -8 << 3 scaled by the level
+        assert_eq!(levels[..4], [0x7FBE, 0x7F7E, 0x7F3E, 0x7EFE]);
+        assert!(levels.windows(2).all(|w| w[1] < w[0] || w[1] == 0));
+        // halved after about 512 * ln(2) samples, the end is linear since the
+        // step is rounded down to -1
+        assert_eq!(levels.iter().position(|&l| l < 0x4000), Some(350));
+        assert_eq!(levels.iter().position(|&l| l == 0), Some(2426));
+    }
 
-        todo!("spu corrupt 8bit write addr: {:03X}", addr);
+    #[test]
+    fn current_volume_writes() {
+        let mut interrupts = Interrupts::default();
+        let mut spu = Spu::default();
+
+        // in fixed mode, the current volume is replaced on the next sample
+        spu.write_u16(0x180, 0x2000).unwrap();
+        assert_eq!(spu.read_u16(0x1B8).unwrap(), 0x4000);
+        spu.write_u16(0x1B8, 0x1234).unwrap();
+        assert_eq!(spu.read_u16(0x1B8).unwrap(), 0x1234);
+        clock_sample(&mut spu, &mut interrupts);
+        assert_eq!(spu.read_u16(0x1B8).unwrap(), 0x4000);
+
+        // voice 9 right, linear increase, shift 0, step +7
+        spu.write_u16(0x92, 0x8000).unwrap();
+        spu.write_u16(0x226, 0x1000).unwrap();
+        clock_sample(&mut spu, &mut interrupts);
+        // the sweep continues from the written volume
+        assert_eq!(spu.read_u16(0x226).unwrap(), 0x4800);
+        // the left side and the other voices are separate
+        assert_eq!(spu.read_u16(0x224).unwrap(), 0);
+        assert_eq!(spu.read_u16(0x22A).unwrap(), 0);
+
+        // negative phase, saturates at 0x7FFF
+        spu.write_u16(0x92, 0x9000).unwrap();
+        clock_sample(&mut spu, &mut interrupts);
+        assert_eq!(spu.read_u16(0x226).unwrap() as i16, -0x7FFF);
     }
 }
@@ -0,0 +1,77 @@
+//! A checksum of the emulation state, see [`Psx::state_hash`](crate::Psx::state_hash).
+//!
+//! This uses `FNV-1a` instead of `std`'s `DefaultHasher`, since its algorithm can
+//! change between Rust releases, and two builds of the emulator (e.g. netplay peers)
+//! must agree on the hash. Values are always hashed as little endian.
+
+const FNV_OFFSET_BASIS: u64 = 0xCBF29CE484222325;
+const FNV_PRIME: u64 = 0x100000001B3;
+
+pub(crate) struct StateHasher {
+    hash: u64,
+}
+
+impl Default for StateHasher {
+    fn default() -> Self {
+        Self {
+            hash: FNV_OFFSET_BASIS,
+        }
+    }
+}
+
+impl StateHasher {
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.hash ^= b as u64;
+            self.hash = self.hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.write(&[value]);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.write(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.write(&value.to_le_bytes());
+    }
+
+    pub fn write_u16_slice(&mut self, values: &[u16]) {
+        for &v in values {
+            self.write_u16(v);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv1a_reference() {
+        let mut hasher = StateHasher::default();
+        assert_eq!(hasher.finish(), 0xCBF29CE484222325);
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xAF63DC4C8601EC8C);
+
+        let mut hasher = StateHasher::default();
+        hasher.write(b"foobar");
+        assert_eq!(hasher.finish(), 0x85944171F73967E8);
+    }
+
+    #[test]
+    fn little_endian() {
+        let mut a = StateHasher::default();
+        a.write_u32(0x11223344);
+        let mut b = StateHasher::default();
+        b.write(&[0x44, 0x33, 0x22, 0x11]);
+        assert_eq!(a.finish(), b.finish());
+    }
+}
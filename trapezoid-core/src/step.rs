@@ -0,0 +1,36 @@
+//! The results of [`Psx::step`](crate::Psx::step) and
+//! [`Psx::step_instruction`](crate::Psx::step_instruction), for embeddings that
+//! schedule the emulator themselves instead of running it a frame at a time.
+
+use crate::cpu::CpuState;
+
+/// Returned from [`Psx::step`](crate::Psx::step)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct StepResult {
+    /// The CPU cycles the emulation advanced, this is what
+    /// [`Psx::elapsed_cycles`](crate::Psx::elapsed_cycles) grew by
+    pub cycles_executed: u32,
+    pub cpu_state: CpuState,
+    /// A vblank started, this is where the frame based functions stop
+    pub vblank_crossed: bool,
+    /// The stereo samples waiting to be taken with
+    /// [`Psx::take_audio_buffer_into`](crate::Psx::take_audio_buffer_into),
+    /// before resampling for the speed multiplier
+    pub audio_samples_ready: usize,
+}
+
+/// Returned from [`Psx::step_instruction`](crate::Psx::step_instruction)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct InstructionStep {
+    /// The address of the next instruction to execute
+    pub pc: u32,
+    /// The CPU cycles of the instruction, with the DMA it started. With
+    /// [`PsxConfig::cpu_overclock`](crate::PsxConfig::cpu_overclock), these are
+    /// the stock cycles, and can be `0` for a fast instruction
+    pub cycles: u32,
+    pub cpu_state: CpuState,
+    /// A vblank started during the instruction
+    pub vblank_crossed: bool,
+}
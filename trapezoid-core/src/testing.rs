@@ -0,0 +1,213 @@
+//! Drive parts of the emulator directly without running the CPU, this is
+//! used by the integration tests and is not part of the stable API.
+
+#[cfg(feature = "vulkan")]
+use std::sync::Arc;
+use std::{io, path::Path};
+
+#[cfg(feature = "vulkan")]
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage},
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, CommandBufferUsage,
+        CopyImageToBufferInfo, PrimaryCommandBufferAbstract,
+    },
+    device::{Device, Queue},
+    format::Format,
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    sync::GpuFuture,
+};
+
+use crate::gpu::{Gpu, GpuCaptureReader, GpuError, GpuRenderOptions, GpuStats};
+use crate::memory::{interrupts::Interrupts, BusLine};
+use crate::region::RegionOverride;
+#[cfg(feature = "vulkan")]
+use crate::PsxError;
+
+/// A GPU without the rest of the system, commands are written to it
+/// the same way as the CPU and DMA would write to `GP0` and `GP1`.
+pub struct GpuHarness {
+    gpu: Gpu,
+    interrupts: Interrupts,
+    #[cfg(feature = "vulkan")]
+    device: Arc<Device>,
+    #[cfg(feature = "vulkan")]
+    queue: Arc<Queue>,
+}
+
+impl GpuHarness {
+    #[cfg(feature = "vulkan")]
+    pub fn new(device: Arc<Device>, queue: Arc<Queue>) -> Self {
+        Self {
+            gpu: Gpu::new(device.clone(), queue.clone()),
+            interrupts: Interrupts::default(),
+            device,
+            queue,
+        }
+    }
+
+    /// Without Vulkan, the VRAM is kept in memory, and nothing is drawn
+    #[cfg(not(feature = "vulkan"))]
+    pub fn new() -> Self {
+        Self {
+            gpu: Gpu::new(),
+            interrupts: Interrupts::default(),
+        }
+    }
+
+    pub fn gp0_write(&mut self, word: u32) {
+        self.gpu.write_u32(0, word).unwrap();
+    }
+
+    pub fn gp1_write(&mut self, word: u32) {
+        self.gpu.write_u32(4, word).unwrap();
+    }
+
+    pub fn set_render_options(&mut self, render_options: GpuRenderOptions) {
+        self.gpu.set_render_options(render_options);
+    }
+
+    pub fn gpu_stat(&mut self) -> u32 {
+        self.gpu.read_u32(4).unwrap()
+    }
+
+    /// Read `GPUREAD`, the same as a CPU read
+    pub fn gpu_read(&mut self) -> u32 {
+        self.gpu.read_u32(0).unwrap()
+    }
+
+    pub fn set_region_override(&mut self, region_override: RegionOverride) {
+        self.gpu.set_region_override(region_override);
+    }
+
+    /// `I_STAT` of the interrupts requested by the GPU, they are never acknowledged
+    pub fn interrupt_stat(&mut self) -> u16 {
+        self.interrupts.read_u16(0).unwrap()
+    }
+
+    /// Run the video timing for `cpu_cycles`
+    pub fn clock(&mut self, cpu_cycles: u32) {
+        self.gpu.clock(&mut self.interrupts, cpu_cycles);
+    }
+
+    /// Run the video timing until the start of the next vblank, returns the
+    /// CPU cycles it took, in steps of `1000`
+    pub fn run_frame(&mut self) -> u32 {
+        let mut cpu_cycles = 1000;
+        while !self.gpu.clock(&mut self.interrupts, 1000).vblank_started {
+            cpu_cycles += 1000;
+        }
+        cpu_cycles
+    }
+
+    /// Request the front image, the same as the frontend does at the end of a frame.
+    /// The stats of the frame ending here are only available after the next call.
+    #[cfg(feature = "vulkan")]
+    pub fn end_frame(&mut self) {
+        self.gpu.sync_and_take_front_image(false);
+    }
+
+    /// Like [`end_frame`](Self::end_frame), and read back the front image requested
+    /// by the previous call, as `(width, height, pixels)` in the output format
+    /// (`BGRA8` by default), `None` on the first call
+    #[cfg(feature = "vulkan")]
+    pub fn take_front_image(&mut self) -> Option<(u32, u32, Vec<u8>)> {
+        let image = self.gpu.sync_and_take_front_image(false)?;
+        let [width, height, _] = image.extent();
+        let pixel_size = image.format().block_size() as u32;
+
+        let buffer = Buffer::new_slice::<u8>(
+            Arc::new(StandardMemoryAllocator::new_default(self.device.clone())),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..Default::default()
+            },
+            (width * height * pixel_size) as u64,
+        )
+        .unwrap();
+
+        let command_buffer_allocator =
+            StandardCommandBufferAllocator::new(self.device.clone(), Default::default());
+        let mut builder = AutoCommandBufferBuilder::primary(
+            &command_buffer_allocator,
+            self.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        builder
+            .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(image, buffer.clone()))
+            .unwrap();
+        builder
+            .build()
+            .unwrap()
+            .execute(self.queue.clone())
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+
+        let pixels = buffer.read().unwrap().to_vec();
+        Some((width, height, pixels))
+    }
+
+    /// The display area in `RGB888`, the same as [`Psx::read_display_rgba`](crate::Psx::read_display_rgba)
+    pub fn read_display_rgb(&mut self) -> Vec<u8> {
+        self.gpu.read_display_frame().rgb
+    }
+
+    /// The stats of the frame before the last [`end_frame`](Self::end_frame)
+    pub fn gpu_stats(&self) -> GpuStats {
+        self.gpu.stats()
+    }
+
+    /// The format of the front images requested after this, see
+    /// [`Psx::set_output_format`](crate::Psx::set_output_format)
+    #[cfg(feature = "vulkan")]
+    pub fn set_output_format(&mut self, format: Format) -> Result<(), PsxError> {
+        self.gpu.set_output_format(format)
+    }
+
+    pub fn start_capture<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.gpu.start_command_capture(path)
+    }
+
+    pub fn stop_capture(&mut self) -> io::Result<()> {
+        self.gpu.stop_command_capture()
+    }
+
+    /// Returns the number of frames replayed
+    pub fn replay_capture<P: AsRef<Path>>(&mut self, path: P) -> io::Result<u64> {
+        self.gpu
+            .replay_command_capture(GpuCaptureReader::open(path)?)
+    }
+
+    /// Read a block of VRAM in `RGB555` row by row, after all the previous
+    /// commands are done.
+    pub fn read_vram_block(&mut self, left: u32, top: u32, width: u32, height: u32) -> Vec<u16> {
+        self.gpu.read_vram_block(left, top, width, height)
+    }
+
+    /// The next vertex buffer allocation of the backend fails with `error`
+    #[cfg(feature = "vulkan")]
+    pub fn inject_error(&mut self, error: GpuError) {
+        self.gpu.inject_error(error);
+    }
+
+    /// The next error of the backend, and whether it recovered from it
+    pub fn take_error(&mut self) -> Option<(GpuError, bool)> {
+        self.gpu.take_error()
+    }
+}
+
+#[cfg(not(feature = "vulkan"))]
+impl Default for GpuHarness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
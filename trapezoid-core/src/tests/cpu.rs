@@ -0,0 +1,746 @@
+use super::{asm, run, setup_cpu, PROGRAM_START};
+use crate::cpu::IdleSkip;
+use crate::cpu::RegisterType::*;
+use crate::memory::BusLine;
+
+const CAUSE_ADEL: u32 = 0x04;
+const CAUSE_ADES: u32 = 0x05;
+const CAUSE_IBE: u32 = 0x06;
+const CAUSE_SYSCALL: u32 = 0x08;
+const CAUSE_BREAK: u32 = 0x09;
+const CAUSE_OVERFLOW: u32 = 0x0C;
+
+/// Run the program until it reaches the exception handler loop, and return
+/// `(cause_code, bd, epc, bad_vaddr)`, and the cpu
+fn run_till_exception(program: &[u32]) -> ((u32, bool, u32, u32), crate::cpu::Cpu) {
+    let (mut cpu, mut bus) = setup_cpu(program);
+    run(&mut cpu, &mut bus, program.len() + 10);
+
+    let regs = cpu.registers();
+    let cause = regs.read(K0);
+    let result = (
+        (cause >> 2) & 0x1F,
+        cause & 0x80000000 != 0,
+        regs.read(K1),
+        regs.read(T9),
+    );
+    (result, cpu)
+}
+
+#[test]
+fn address_error_load() {
+    let ((cause, bd, epc, bad_vaddr), cpu) = run_till_exception(&[
+        asm::lui(T0, 0x8002),
+        asm::ori(T0, T0, 0x0001),
+        asm::lw(T1, T0, 0),
+    ]);
+
+    assert_eq!(cause, CAUSE_ADEL);
+    assert!(!bd);
+    assert_eq!(epc, PROGRAM_START + 8);
+    assert_eq!(bad_vaddr, 0x80020001);
+    // the load must not happen
+    assert_eq!(cpu.registers().read(T1), 0);
+
+    let ((cause, _, epc, bad_vaddr), _) =
+        run_till_exception(&[asm::lui(T0, 0x8002), asm::lh(T1, T0, 3)]);
+    assert_eq!(cause, CAUSE_ADEL);
+    assert_eq!(epc, PROGRAM_START + 4);
+    assert_eq!(bad_vaddr, 0x80020003);
+}
+
+/// The scratchpad is only a data cache, running code from it is a bus error
+#[test]
+fn scratchpad_instruction_fetch() {
+    for target in [0x1F800000, 0x9F800100] {
+        let ((cause, bd, epc, _), cpu) = run_till_exception(&[
+            asm::lui(T0, (target >> 16) as u16),
+            asm::ori(T0, T0, target as u16),
+            asm::jr(T0),
+            asm::addiu(T1, Zero, 1),
+        ]);
+
+        assert_eq!(cause, CAUSE_IBE);
+        assert!(!bd);
+        assert_eq!(epc, target);
+        // the delay slot is still executed
+        assert_eq!(cpu.registers().read(T1), 1);
+    }
+}
+
+#[test]
+fn address_error_store() {
+    let program = [
+        asm::lui(T0, 0x8002),
+        asm::addiu(T1, Zero, 0x1234),
+        asm::sw(T1, T0, 2),
+        asm::sh(T1, T0, 5),
+    ];
+
+    let (mut cpu, mut bus) = setup_cpu(&program);
+    run(&mut cpu, &mut bus, program.len() + 10);
+    let regs = cpu.registers();
+
+    assert_eq!((regs.read(K0) >> 2) & 0x1F, CAUSE_ADES);
+    assert_eq!(regs.read(K1), PROGRAM_START + 8);
+    assert_eq!(regs.read(T9), 0x80020002);
+    // nothing is written
+    assert_eq!(bus.read_u32(0x80020000).unwrap(), 0);
+    assert_eq!(bus.read_u32(0x80020004).unwrap(), 0);
+}
+
+#[test]
+fn arithmetic_overflow() {
+    // addi
+    let ((cause, _, epc, _), cpu) = run_till_exception(&[
+        asm::lui(T0, 0x7FFF),
+        asm::ori(T0, T0, 0xFFFF),
+        asm::addi(T1, T0, 1),
+    ]);
+    assert_eq!(cause, CAUSE_OVERFLOW);
+    assert_eq!(epc, PROGRAM_START + 8);
+    assert_eq!(cpu.registers().read(T1), 0);
+
+    // add
+    let ((cause, _, epc, _), cpu) =
+        run_till_exception(&[asm::lui(T0, 0x4000), asm::add(T1, T0, T0)]);
+    assert_eq!(cause, CAUSE_OVERFLOW);
+    assert_eq!(epc, PROGRAM_START + 4);
+    assert_eq!(cpu.registers().read(T1), 0);
+
+    // sub
+    let ((cause, _, epc, _), cpu) = run_till_exception(&[
+        asm::lui(T0, 0x8000),
+        asm::addiu(T1, Zero, 1),
+        asm::sub(T2, T0, T1),
+    ]);
+    assert_eq!(cause, CAUSE_OVERFLOW);
+    assert_eq!(epc, PROGRAM_START + 8);
+    assert_eq!(cpu.registers().read(T2), 0);
+
+    // the unsigned versions don't trap
+    let program = [
+        asm::lui(T0, 0x7FFF),
+        asm::ori(T0, T0, 0xFFFF),
+        asm::addiu(T1, T0, 1),
+        asm::addu(T2, T0, T0),
+    ];
+    let (mut cpu, mut bus) = setup_cpu(&program);
+    run(&mut cpu, &mut bus, program.len());
+    assert_eq!(cpu.registers().read(T1), 0x80000000);
+    assert_eq!(cpu.registers().read(T2), 0xFFFFFFFE);
+    assert_eq!(cpu.registers().read(Pc), PROGRAM_START + 16);
+}
+
+#[test]
+fn syscall_and_break() {
+    let ((cause, bd, epc, _), _) = run_till_exception(&[asm::NOP, asm::syscall()]);
+    assert_eq!(cause, CAUSE_SYSCALL);
+    assert!(!bd);
+    assert_eq!(epc, PROGRAM_START + 4);
+
+    let ((cause, bd, epc, _), _) = run_till_exception(&[asm::break_()]);
+    assert_eq!(cause, CAUSE_BREAK);
+    assert!(!bd);
+    assert_eq!(epc, PROGRAM_START);
+}
+
+#[test]
+fn exceptions_in_delay_slot() {
+    // branch delay slot
+    let ((cause, bd, epc, _), _) =
+        run_till_exception(&[asm::NOP, asm::beq(Zero, Zero, 4), asm::break_()]);
+    assert_eq!(cause, CAUSE_BREAK);
+    assert!(bd);
+    // points to the branch
+    assert_eq!(epc, PROGRAM_START + 4);
+
+    // jump delay slot
+    let ((cause, bd, epc, _), _) =
+        run_till_exception(&[asm::lui(T0, 0x8002), asm::jr(T0), asm::syscall()]);
+    assert_eq!(cause, CAUSE_SYSCALL);
+    assert!(bd);
+    assert_eq!(epc, PROGRAM_START + 4);
+
+    // overflow in delay slot
+    let ((cause, bd, epc, _), _) = run_till_exception(&[
+        asm::lui(T0, 0x4000),
+        asm::beq(Zero, Zero, 4),
+        asm::add(T1, T0, T0),
+    ]);
+    assert_eq!(cause, CAUSE_OVERFLOW);
+    assert!(bd);
+    assert_eq!(epc, PROGRAM_START + 4);
+}
+
+#[test]
+fn address_error_instruction_fetch() {
+    let ((cause, bd, epc, bad_vaddr), _) = run_till_exception(&[
+        asm::lui(T0, 0x8002),
+        asm::ori(T0, T0, 0x0002),
+        asm::jr(T0),
+        asm::NOP,
+    ]);
+
+    assert_eq!(cause, CAUSE_ADEL);
+    assert!(!bd);
+    assert_eq!(epc, 0x80020002);
+    assert_eq!(bad_vaddr, 0x80020002);
+}
+
+/// Run the program with a handler at the debug vector, which stores `cause` in
+/// `k0`, `epc` in `k1`, and `DCIC` in `t9` then loops forever
+fn run_till_debug_exception(program: &[u32]) -> (crate::cpu::Cpu, super::TestBus) {
+    let (mut cpu, mut bus) = setup_cpu(program);
+    bus.write_program(
+        0x80000040,
+        &[
+            asm::mfc0(K0, 13),
+            asm::mfc0(K1, 14),
+            asm::mfc0(T9, 7),
+            // loop
+            asm::j(0x8000004C),
+            asm::NOP,
+        ],
+    );
+    run(&mut cpu, &mut bus, program.len() + 10);
+    (cpu, bus)
+}
+
+/// Setup `BPC` on `PROGRAM_START + 0x28` and write `dcic`, then count in `t3`
+fn code_breakpoint_program(dcic: u16) -> Vec<u32> {
+    vec![
+        asm::lui(T0, 0x8001),
+        asm::ori(T0, T0, 0x0028),
+        asm::mtc0(T0, 3),
+        asm::addiu(T1, Zero, -1),
+        asm::mtc0(T1, 11),
+        asm::lui(T2, dcic),
+        asm::mtc0(T2, 7),
+        asm::NOP,
+        asm::addiu(T3, Zero, 1),
+        asm::addiu(T3, T3, 1),
+        // breakpoint
+        asm::addiu(T3, T3, 1),
+        asm::addiu(T3, T3, 1),
+    ]
+}
+
+#[test]
+fn cop0_code_breakpoint() {
+    let (cpu, _) = run_till_debug_exception(&code_breakpoint_program(0xC180));
+    let regs = cpu.registers();
+
+    assert_eq!((regs.read(K0) >> 2) & 0x1F, CAUSE_BREAK);
+    assert_eq!(regs.read(K1), PROGRAM_START + 0x28);
+    // any break and code break status bits
+    assert_eq!(regs.read(T9), 0xC1800003);
+    // happens before the execution
+    assert_eq!(regs.read(T3), 2);
+    assert!((0x80000040..0x80000054).contains(&regs.read(Pc)));
+
+    // without the super-master enables, nothing happens
+    let (cpu, _) = run_till_debug_exception(&code_breakpoint_program(0x4100));
+    let regs = cpu.registers();
+    assert_eq!(regs.read(T3), 4);
+    assert_eq!(regs.read(T9), 0);
+}
+
+#[test]
+fn cop0_data_breakpoint() {
+    let (cpu, mut bus) = run_till_debug_exception(&[
+        // BDA=0x80020004, BDAM=0xFFFFFFFC, break on writes only
+        asm::lui(T0, 0x8002),
+        asm::ori(T1, T0, 0x0004),
+        asm::mtc0(T1, 5),
+        asm::addiu(T1, Zero, -4),
+        asm::mtc0(T1, 9),
+        asm::lui(T1, 0xCA80),
+        asm::mtc0(T1, 7),
+        asm::addiu(T1, Zero, 0x1234),
+        // reads and other addresses don't break
+        asm::lw(T2, T0, 4),
+        asm::sw(T1, T0, 0),
+        asm::sh(T1, T0, 6),
+    ]);
+    let regs = cpu.registers();
+
+    assert_eq!((regs.read(K0) >> 2) & 0x1F, CAUSE_BREAK);
+    assert_eq!(regs.read(K1), PROGRAM_START + 0x28);
+    // any break, data break and data write break status bits
+    assert_eq!(regs.read(T9), 0xCA800015);
+    assert_eq!(bus.read_u32(0x80020000).unwrap(), 0x1234);
+    // the store must not happen
+    assert_eq!(bus.read_u32(0x80020004).unwrap(), 0);
+}
+
+/// `t0` points to data with the bytes `00 01 02 03 04 05 06 07`
+fn setup_load_test(program: &[u32]) -> (crate::cpu::Cpu, super::TestBus) {
+    let mut full_program = vec![asm::lui(T0, 0x8002)];
+    full_program.extend_from_slice(program);
+
+    let (mut cpu, mut bus) = setup_cpu(&full_program);
+    bus.write_u32(0x80020000, 0x03020100).unwrap();
+    bus.write_u32(0x80020004, 0x07060504).unwrap();
+    // run the `lui`
+    run(&mut cpu, &mut bus, 1);
+    (cpu, bus)
+}
+
+#[test]
+fn load_delay_slot() {
+    let (mut cpu, mut bus) = setup_load_test(&[
+        asm::addiu(T1, Zero, 0x55),
+        asm::lw(T1, T0, 0),
+        // still the old value
+        asm::addu(T2, T1, Zero),
+        // loaded now
+        asm::addu(T3, T1, Zero),
+    ]);
+    run(&mut cpu, &mut bus, 4);
+    let regs = cpu.registers();
+    assert_eq!(regs.read(T2), 0x55);
+    assert_eq!(regs.read(T3), 0x03020100);
+
+    // writing to the same register in the delay slot cancels the load
+    let (mut cpu, mut bus) =
+        setup_load_test(&[asm::lw(T1, T0, 0), asm::addiu(T1, Zero, 5), asm::NOP]);
+    run(&mut cpu, &mut bus, 3);
+    assert_eq!(cpu.registers().read(T1), 5);
+
+    // two loads to the same register, the last one wins
+    let (mut cpu, mut bus) = setup_load_test(&[asm::lw(T1, T0, 0), asm::lw(T1, T0, 4), asm::NOP]);
+    run(&mut cpu, &mut bus, 3);
+    assert_eq!(cpu.registers().read(T1), 0x07060504);
+
+    // coprocessor reads are delayed as well
+    let (mut cpu, mut bus) = setup_load_test(&[
+        asm::ori(T1, Zero, 0x0400),
+        asm::mtc0(T1, 12),
+        asm::mfc0(T2, 12),
+        asm::addu(T3, T2, Zero),
+        asm::addu(T4, T2, Zero),
+    ]);
+    run(&mut cpu, &mut bus, 5);
+    let regs = cpu.registers();
+    assert_eq!(regs.read(T3), 0);
+    assert_eq!(regs.read(T4), 0x0400);
+}
+
+#[test]
+fn lwl_lwr_pairs() {
+    for offset in 0..4 {
+        // garbage in the register to make sure all of it is replaced
+        let (mut cpu, mut bus) = setup_load_test(&[
+            asm::lui(T1, 0xAABB),
+            asm::lwl(T1, T0, offset + 3),
+            asm::lwr(T1, T0, offset),
+            asm::NOP,
+        ]);
+        run(&mut cpu, &mut bus, 4);
+
+        let expected = (0x0706050403020100u64 >> (offset * 8)) as u32;
+        assert_eq!(cpu.registers().read(T1), expected, "offset {}", offset);
+    }
+
+    // the other order works as well
+    let (mut cpu, mut bus) = setup_load_test(&[asm::lwr(T1, T0, 1), asm::lwl(T1, T0, 4), asm::NOP]);
+    run(&mut cpu, &mut bus, 3);
+    assert_eq!(cpu.registers().read(T1), 0x04030201);
+}
+
+#[test]
+fn lwl_lwr_partial_merge() {
+    let cases = [
+        // (lwl?, offset, result)
+        (true, 0, 0x00CCDDEE),
+        (true, 1, 0x0100DDEE),
+        (true, 2, 0x020100EE),
+        (true, 3, 0x03020100),
+        (false, 0, 0x03020100),
+        (false, 1, 0xBB030201),
+        (false, 2, 0xBBCC0302),
+        (false, 3, 0xBBCCDD03),
+    ];
+
+    for (is_lwl, offset, expected) in cases {
+        let load = if is_lwl {
+            asm::lwl(T1, T0, offset)
+        } else {
+            asm::lwr(T1, T0, offset)
+        };
+        let (mut cpu, mut bus) = setup_load_test(&[
+            asm::lui(T1, 0xBBCC),
+            asm::ori(T1, T1, 0xDDEE),
+            load,
+            asm::NOP,
+        ]);
+        run(&mut cpu, &mut bus, 4);
+        assert_eq!(
+            cpu.registers().read(T1),
+            expected,
+            "lwl: {}, offset {}",
+            is_lwl,
+            offset
+        );
+    }
+}
+
+#[test]
+fn swl_swr_pairs() {
+    for offset in 0..4 {
+        let (mut cpu, mut bus) = setup_load_test(&[
+            asm::lui(T1, 0xAABB),
+            asm::ori(T1, T1, 0xCCDD),
+            asm::swl(T1, T0, offset + 3),
+            asm::swr(T1, T0, offset),
+        ]);
+        run(&mut cpu, &mut bus, 4);
+
+        let data = (bus.read_u32(0x80020000).unwrap() as u64)
+            | ((bus.read_u32(0x80020004).unwrap() as u64) << 32);
+        let mut expected = 0x0706050403020100u64;
+        expected &= !(0xFFFFFFFFu64 << (offset * 8));
+        expected |= 0xAABBCCDDu64 << (offset * 8);
+        assert_eq!(data, expected, "offset {}", offset);
+    }
+}
+
+#[test]
+fn lwl_lwr_pair_interrupted() {
+    let (mut cpu, mut bus) = setup_load_test(&[
+        // enable interrupts
+        asm::ori(T2, Zero, 0x0401),
+        asm::mtc0(T2, 12),
+        asm::lui(T1, 0xAABB),
+        asm::lwl(T1, T0, 5),
+        asm::lwr(T1, T0, 2),
+        asm::NOP,
+        // end
+        asm::j(PROGRAM_START + 0x1C),
+        asm::NOP,
+    ]);
+    // return from the interrupt without touching anything else
+    bus.write_program(
+        0x80000080,
+        &[
+            asm::mfc0(K0, 14),
+            asm::addiu(K1, K1, 1),
+            asm::jr(K0),
+            asm::rfe(),
+        ],
+    );
+
+    // until `lwl`
+    run(&mut cpu, &mut bus, 4);
+    bus.interrupt_pending = true;
+    run(&mut cpu, &mut bus, 1);
+    bus.interrupt_pending = false;
+    run(&mut cpu, &mut bus, 10);
+
+    let regs = cpu.registers();
+    // the interrupt happened once, and returned to `lwr`
+    assert_eq!(regs.read(K1), 1);
+    assert_eq!(regs.read(K0), PROGRAM_START + 0x14);
+    assert_eq!(regs.read(T1), 0x05040302);
+}
+
+#[test]
+fn unwind_nested_calls() {
+    let main = PROGRAM_START;
+    let func_a = PROGRAM_START + 0x20;
+    let func_b = PROGRAM_START + 0x40;
+    let (mut cpu, mut bus) = setup_cpu(&[
+        // main
+        asm::addiu(Sp, Sp, -24),
+        asm::sw(Ra, Sp, 20),
+        asm::jal(func_a),
+        asm::NOP,
+        asm::j(main + 0x10),
+        asm::NOP,
+        asm::NOP,
+        asm::NOP,
+        // func_a
+        asm::addiu(Sp, Sp, -32),
+        asm::sw(Ra, Sp, 28),
+        asm::jal(func_b),
+        asm::NOP,
+        asm::lw(Ra, Sp, 28),
+        asm::addiu(Sp, Sp, 32),
+        asm::jr(Ra),
+        asm::NOP,
+        // func_b, a leaf without a frame
+        asm::j(func_b),
+        asm::NOP,
+    ]);
+    cpu.registers_mut().write(Sp, 0x801FFF00);
+    cpu.registers_mut().write(Ra, 0);
+    run(&mut cpu, &mut bus, 20);
+
+    let regs = cpu.registers();
+    let (pc, sp, ra) = (regs.read(Pc), regs.read(Sp), regs.read(Ra));
+    assert_eq!(pc, func_b);
+
+    let frames = crate::cpu::unwind_stack(pc, sp, ra, 16, |addr| bus.read_u32(addr).ok());
+    let frames = frames
+        .iter()
+        .map(|f| (f.pc, f.sp, f.function))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        frames,
+        [
+            (func_b, 0x801FFEC8, None),
+            (func_a + 0x10, 0x801FFEC8, Some(func_a)),
+            (main + 0x10, 0x801FFEE8, Some(main)),
+        ]
+    );
+
+    let frames = crate::cpu::unwind_stack(pc, sp, ra, 2, |addr| bus.read_u32(addr).ok());
+    assert_eq!(frames.len(), 2);
+}
+
+#[cfg(feature = "debugger")]
+#[test]
+fn execution_trace() {
+    use crate::cpu::TraceConfig;
+
+    let program = [
+        asm::addiu(T0, Zero, 1),
+        asm::addiu(T1, T0, 2),
+        asm::j(PROGRAM_START + 0x10),
+        asm::NOP,
+        // loop
+        asm::addiu(T0, T0, 1),
+        asm::j(PROGRAM_START + 0x10),
+        asm::NOP,
+    ];
+    let path = std::env::temp_dir().join(format!("trapezoid_trace_{}.log", std::process::id()));
+
+    let (mut cpu, mut bus) = setup_cpu(&program);
+    cpu.set_trace(Some(TraceConfig {
+        path: path.clone(),
+        start_pc: None,
+        end_pc: None,
+        max_instructions: Some(10),
+        include_registers: true,
+    }))
+    .unwrap();
+    run(&mut cpu, &mut bus, 20);
+    // stopped by itself
+    assert!(!cpu.tracing());
+
+    let trace = std::fs::read_to_string(&path).unwrap();
+    let lines = trace.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 10);
+    assert_eq!(
+        lines[0],
+        "80010000: 24080001 addiu t0, zero, 0x0001 ; t0=00000001"
+    );
+    assert_eq!(
+        lines[1],
+        "80010004: 25090002 addiu t1, t0, 0x0002 ; t1=00000003"
+    );
+    assert_eq!(lines[2], "80010008: 08004004 j 0x0004004 => 0x80010010");
+    assert_eq!(lines[3], "8001000C: 00000000 _nop");
+    assert_eq!(
+        lines[4],
+        "80010010: 25080001 addiu t0, t0, 0x0001 ; t0=00000002"
+    );
+
+    // only the loop
+    let (mut cpu, mut bus) = setup_cpu(&program);
+    cpu.set_trace(Some(TraceConfig {
+        path: path.clone(),
+        start_pc: Some(PROGRAM_START + 0x10),
+        end_pc: Some(PROGRAM_START + 0x14),
+        max_instructions: None,
+        include_registers: false,
+    }))
+    .unwrap();
+    run(&mut cpu, &mut bus, 20);
+    assert!(!cpu.tracing());
+
+    let trace = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(trace, "80010010: 25080001 addiu t0, t0, 0x0001\n");
+    std::fs::remove_file(&path).ok();
+}
+
+#[test]
+fn interrupt_pending_bit_follows_acknowledge() {
+    let (mut cpu, mut bus) = setup_cpu(&[
+        asm::lui(T1, 0x1F80),
+        asm::mfc0(T0, 13),
+        // acknowledge
+        asm::sw(Zero, T1, 0x1070),
+        asm::mfc0(T2, 13),
+        asm::NOP,
+        asm::j(PROGRAM_START + 0x14),
+        asm::NOP,
+    ]);
+    // interrupts are disabled in `SR`, so only `cause.10` changes
+    bus.interrupt_pending = true;
+    // all in one block, the acknowledge must be visible to the next instruction
+    cpu.clock(&mut bus, 5);
+
+    let regs = cpu.registers();
+    assert_eq!(regs.read(T0) & 0x400, 0x400);
+    assert_eq!(regs.read(T2) & 0x400, 0);
+    assert_eq!(regs.read(Pc), PROGRAM_START + 0x14);
+}
+
+/// Clock the cpu the same as the emulator, returns the cycles of every `clock` call
+fn run_blocks(cpu: &mut crate::cpu::Cpu, bus: &mut super::TestBus, blocks: usize) -> Vec<u32> {
+    (0..blocks).map(|_| cpu.clock(bus, 56).1).collect()
+}
+
+#[test]
+fn idle_skip_branch_to_self() {
+    let (mut cpu, mut bus) = setup_cpu(&[asm::beq(Zero, Zero, -1), asm::NOP]);
+    cpu.set_idle_skip(IdleSkip::On);
+
+    let cycles = run_blocks(&mut cpu, &mut bus, 4);
+    // detected after two iterations, then nothing runs
+    assert!(cycles[0] < 56 * 4);
+    assert_eq!(cycles[1..], [1000; 3]);
+    assert_eq!(cpu.registers().read(Pc), PROGRAM_START);
+
+    // any pending interrupt wakes the CPU, even if `SR` masks it
+    bus.interrupt_pending = true;
+    let cycles = run_blocks(&mut cpu, &mut bus, 1);
+    assert_ne!(cycles[0], 1000);
+}
+
+#[test]
+fn idle_skip_polling_loop() {
+    let program = [
+        asm::lui(T0, 0x8002),
+        // loop until the value is set
+        asm::lw(T1, T0, 0),
+        asm::NOP,
+        asm::beq(T1, Zero, -3),
+        asm::NOP,
+        asm::addiu(T2, Zero, 1),
+        asm::j(PROGRAM_START + 0x18),
+        asm::NOP,
+    ];
+    let (mut cpu, mut bus) = setup_cpu(&program);
+    cpu.set_idle_skip(IdleSkip::On);
+
+    let cycles = run_blocks(&mut cpu, &mut bus, 3);
+    assert_eq!(cycles[1..], [1000; 2]);
+    assert_eq!(cpu.registers().read(Pc), PROGRAM_START + 4);
+
+    // something else (DMA) changed the memory
+    bus.write_u32(0x80020000, 5).unwrap();
+    run_blocks(&mut cpu, &mut bus, 1);
+    let regs = cpu.registers();
+    assert_eq!(regs.read(T1), 5);
+    assert_eq!(regs.read(T2), 1);
+}
+
+#[test]
+fn idle_skip_ignores_busy_loops() {
+    let programs: [&[u32]; 3] = [
+        // counting
+        &[asm::addiu(T1, T1, 1), asm::bne(T1, Zero, -2), asm::NOP],
+        // storing
+        &[
+            asm::lui(T0, 0x8002),
+            asm::sw(T1, T0, 0),
+            asm::beq(Zero, Zero, -2),
+            asm::NOP,
+        ],
+        // reading hardware registers, which can have side effects
+        &[
+            asm::lui(T0, 0x1F80),
+            asm::lw(T1, T0, 0x1800),
+            asm::beq(Zero, Zero, -2),
+            asm::NOP,
+        ],
+    ];
+
+    for program in programs {
+        let (mut cpu, mut bus) = setup_cpu(program);
+        cpu.set_idle_skip(IdleSkip::On);
+        let cycles = run_blocks(&mut cpu, &mut bus, 10);
+        assert!(cycles.iter().all(|&c| c != 1000), "{:?}", cycles);
+    }
+}
+
+#[test]
+fn idle_skip_verify() {
+    let program = [
+        asm::lui(T0, 0x8002),
+        asm::lw(T1, T0, 0),
+        asm::NOP,
+        asm::beq(T1, Zero, -3),
+        asm::NOP,
+        asm::addiu(T2, Zero, 1),
+        asm::j(PROGRAM_START + 0x18),
+        asm::NOP,
+    ];
+    let (mut cpu, mut bus) = setup_cpu(&program);
+    cpu.set_idle_skip(IdleSkip::Verify);
+
+    // keeps executing, and the checks pass on every iteration
+    let cycles = run_blocks(&mut cpu, &mut bus, 20);
+    assert!(cycles.iter().all(|&c| c != 1000));
+
+    bus.write_u32(0x80020000, 1).unwrap();
+    run_blocks(&mut cpu, &mut bus, 1);
+    assert_eq!(cpu.registers().read(T2), 1);
+}
+
+#[test]
+fn exe_loading() {
+    use crate::memory::{build_exe, PsxExe, RamSize};
+
+    // store `gp`, `sp` and the first bss word, then loop
+    let program = [
+        asm::lui(T0, 0x8002),
+        asm::sw(Gp, T0, 0),
+        asm::sw(Sp, T0, 4),
+        asm::lui(T1, 0x8003),
+        asm::lw(T2, T1, 0),
+        asm::NOP,
+        asm::sw(T2, T0, 8),
+        asm::j(PROGRAM_START + 7 * 4),
+        asm::NOP,
+    ];
+    let text: Vec<u8> = program.iter().flat_map(|i| i.to_le_bytes()).collect();
+    // the header size is padded to 0x800, the file is not
+    let exe = build_exe(
+        [
+            PROGRAM_START,
+            0x8001F000,
+            PROGRAM_START,
+            0x800,
+            0,
+            0,
+            0x80030000,
+            0x100,
+            0x801FFF00,
+            0xF0,
+        ],
+        &text,
+    );
+    let exe = PsxExe::parse(&exe, RamSize::Std2MB).unwrap();
+
+    let (mut cpu, mut bus) = setup_cpu(&[]);
+    // garbage to be cleared
+    bus.write_u32(0x80030000, 0xFFFFFFFF).unwrap();
+    bus.write_u32(0x80030100, 0xFFFFFFFF).unwrap();
+    exe.load(&mut bus);
+    exe.apply_registers(cpu.registers_mut());
+    run(&mut cpu, &mut bus, program.len() + 10);
+
+    let regs = cpu.registers();
+    assert_eq!(regs.read(Fp), 0x801FFFF0);
+    assert_eq!(bus.read_u32(0x80020000).unwrap(), 0x8001F000);
+    assert_eq!(bus.read_u32(0x80020004).unwrap(), 0x801FFFF0);
+    assert_eq!(bus.read_u32(0x80020008).unwrap(), 0);
+    // only the bss is cleared
+    assert_eq!(bus.read_u32(0x80030100).unwrap(), 0xFFFFFFFF);
+    // reached the final loop
+    assert!((PROGRAM_START + 7 * 4..=PROGRAM_START + 8 * 4).contains(&regs.read(Pc)));
+}
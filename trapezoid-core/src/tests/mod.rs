@@ -1,4 +1,285 @@
+mod cpu;
+
+use crate::cpu::{Cpu, CpuBusProvider, IdleSkip, RegisterType};
+use crate::memory::{BusLine, Result};
+use crate::{PsxConfig, CPU_OVERCLOCK_RANGE};
+
 #[test]
 fn test() {
     assert_eq!(1 + 1, 2)
 }
+
+#[test]
+fn overclock_is_clamped() {
+    let clamped = |overclock| {
+        PsxConfig::builder()
+            .cpu_overclock(overclock)
+            .build()
+            .cpu_overclock
+    };
+    assert_eq!(clamped(1.5), 1.5);
+    assert_eq!(clamped(100.0), *CPU_OVERCLOCK_RANGE.end());
+    assert_eq!(clamped(0.0), *CPU_OVERCLOCK_RANGE.start());
+    assert_eq!(clamped(f32::NAN), 1.0);
+}
+
+/// Boot the BIOS at `TRAPEZOID_TEST_BIOS` with the GPU that keeps the VRAM in memory,
+/// the same way a wasm build does, CI sets it to the extracted BIOS
+#[test]
+#[cfg(not(feature = "vulkan"))]
+fn boot_bios_without_vulkan() {
+    let Ok(path) = std::env::var("TRAPEZOID_TEST_BIOS") else {
+        eprintln!("TRAPEZOID_TEST_BIOS is not set, skipping");
+        return;
+    };
+    let bios = std::fs::read(&path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+
+    let mut psx = crate::Psx::new_headless_from_bytes(bios, None, PsxConfig::default()).unwrap();
+    for _ in 0..60 {
+        psx.clock_full_video_frame();
+    }
+    assert!(
+        psx.tty_output().contains("PS-X Realtime Kernel"),
+        "{:?}",
+        psx.tty_output()
+    );
+}
+
+/// A minimal bus with only RAM, used to run small programs on the [`Cpu`]
+/// without the rest of the hardware.
+///
+/// Writing to `I_STAT` acknowledges the pending interrupt.
+struct TestBus {
+    ram: Vec<u8>,
+    interrupt_pending: bool,
+}
+
+impl TestBus {
+    const RAM_SIZE: usize = 0x200000;
+    const I_STAT: u32 = 0x1F801070;
+
+    fn new() -> Self {
+        Self {
+            ram: vec![0; Self::RAM_SIZE],
+            interrupt_pending: false,
+        }
+    }
+
+    fn index(addr: u32) -> usize {
+        (addr & 0x1FFFFFFF) as usize % Self::RAM_SIZE
+    }
+
+    fn write_program(&mut self, addr: u32, program: &[u32]) {
+        for (i, instr) in program.iter().enumerate() {
+            self.write_u32(addr + i as u32 * 4, *instr).unwrap();
+        }
+    }
+}
+
+impl BusLine for TestBus {
+    fn read_u32(&mut self, addr: u32) -> Result<u32> {
+        let i = Self::index(addr);
+        Ok(u32::from_le_bytes(self.ram[i..i + 4].try_into().unwrap()))
+    }
+
+    fn write_u32(&mut self, addr: u32, data: u32) -> Result<()> {
+        if addr & 0x1FFFFFFF == Self::I_STAT {
+            self.interrupt_pending = false;
+            return Ok(());
+        }
+        let i = Self::index(addr);
+        self.ram[i..i + 4].copy_from_slice(&data.to_le_bytes());
+        Ok(())
+    }
+
+    fn read_u16(&mut self, addr: u32) -> Result<u16> {
+        let i = Self::index(addr);
+        Ok(u16::from_le_bytes(self.ram[i..i + 2].try_into().unwrap()))
+    }
+
+    fn write_u16(&mut self, addr: u32, data: u16) -> Result<()> {
+        let i = Self::index(addr);
+        self.ram[i..i + 2].copy_from_slice(&data.to_le_bytes());
+        Ok(())
+    }
+
+    fn read_u8(&mut self, addr: u32) -> Result<u8> {
+        Ok(self.ram[Self::index(addr)])
+    }
+
+    fn write_u8(&mut self, addr: u32, data: u8) -> Result<()> {
+        self.ram[Self::index(addr)] = data;
+        Ok(())
+    }
+}
+
+impl CpuBusProvider for TestBus {
+    fn pending_interrupts(&self) -> bool {
+        self.interrupt_pending
+    }
+
+    fn should_run_dma(&self) -> bool {
+        false
+    }
+}
+
+const PROGRAM_START: u32 = 0x80010000;
+
+/// The idle skip mode of [`setup_cpu`], from `TRAPEZOID_TEST_IDLE_SKIP` (`off`, `on`
+/// or `verify`), CI runs the CPU tests in all of them
+fn idle_skip_from_env() -> IdleSkip {
+    match std::env::var("TRAPEZOID_TEST_IDLE_SKIP").as_deref() {
+        Err(_) | Ok("off") => IdleSkip::Off,
+        Ok("on") => IdleSkip::On,
+        Ok("verify") => IdleSkip::Verify,
+        Ok(mode) => panic!("invalid TRAPEZOID_TEST_IDLE_SKIP {:?}", mode),
+    }
+}
+
+/// Load the `program` at [`PROGRAM_START`] and setup the CPU to execute it.
+///
+/// An exception handler is placed at the exception vector, which stores
+/// `cause` in `k0`, `epc` in `k1`, and `bad_vaddr` in `t9` then loops forever.
+fn setup_cpu(program: &[u32]) -> (Cpu, TestBus) {
+    let mut bus = TestBus::new();
+    bus.write_program(
+        0x80000080,
+        &[
+            asm::mfc0(RegisterType::K0, 13),
+            asm::mfc0(RegisterType::K1, 14),
+            asm::mfc0(RegisterType::T9, 8),
+            // loop
+            asm::j(0x8000008C),
+            asm::NOP,
+        ],
+    );
+    bus.write_program(PROGRAM_START, program);
+
+    let mut cpu = Cpu::new();
+    cpu.registers_mut().write(RegisterType::Pc, PROGRAM_START);
+    cpu.set_idle_skip(idle_skip_from_env());
+
+    (cpu, bus)
+}
+
+/// Clock the cpu one instruction at a time
+fn run(cpu: &mut Cpu, bus: &mut TestBus, instructions: usize) {
+    for _ in 0..instructions {
+        cpu.clock(bus, 1);
+    }
+}
+
+/// Small assembler to write test programs
+mod asm {
+    use crate::cpu::RegisterType;
+
+    pub const NOP: u32 = 0;
+
+    fn r_type(rs: RegisterType, rt: RegisterType, rd: RegisterType, funct: u32) -> u32 {
+        ((rs as u32) << 21) | ((rt as u32) << 16) | ((rd as u32) << 11) | funct
+    }
+
+    fn i_type(op: u32, rs: RegisterType, rt: RegisterType, imm: u16) -> u32 {
+        (op << 26) | ((rs as u32) << 21) | ((rt as u32) << 16) | imm as u32
+    }
+
+    pub fn add(rd: RegisterType, rs: RegisterType, rt: RegisterType) -> u32 {
+        r_type(rs, rt, rd, 0x20)
+    }
+
+    pub fn addu(rd: RegisterType, rs: RegisterType, rt: RegisterType) -> u32 {
+        r_type(rs, rt, rd, 0x21)
+    }
+
+    pub fn sub(rd: RegisterType, rs: RegisterType, rt: RegisterType) -> u32 {
+        r_type(rs, rt, rd, 0x22)
+    }
+
+    pub fn syscall() -> u32 {
+        0x0C
+    }
+
+    pub fn break_() -> u32 {
+        0x0D
+    }
+
+    pub fn jr(rs: RegisterType) -> u32 {
+        r_type(rs, RegisterType::Zero, RegisterType::Zero, 0x08)
+    }
+
+    pub fn j(target: u32) -> u32 {
+        (0x02 << 26) | ((target >> 2) & 0x3FFFFFF)
+    }
+
+    pub fn jal(target: u32) -> u32 {
+        (0x03 << 26) | ((target >> 2) & 0x3FFFFFF)
+    }
+
+    pub fn beq(rs: RegisterType, rt: RegisterType, offset: i16) -> u32 {
+        i_type(0x04, rs, rt, offset as u16)
+    }
+
+    pub fn bne(rs: RegisterType, rt: RegisterType, offset: i16) -> u32 {
+        i_type(0x05, rs, rt, offset as u16)
+    }
+
+    pub fn addi(rt: RegisterType, rs: RegisterType, imm: i16) -> u32 {
+        i_type(0x08, rs, rt, imm as u16)
+    }
+
+    pub fn addiu(rt: RegisterType, rs: RegisterType, imm: i16) -> u32 {
+        i_type(0x09, rs, rt, imm as u16)
+    }
+
+    pub fn ori(rt: RegisterType, rs: RegisterType, imm: u16) -> u32 {
+        i_type(0x0D, rs, rt, imm)
+    }
+
+    pub fn lui(rt: RegisterType, imm: u16) -> u32 {
+        i_type(0x0F, RegisterType::Zero, rt, imm)
+    }
+
+    pub fn lh(rt: RegisterType, base: RegisterType, offset: i16) -> u32 {
+        i_type(0x21, base, rt, offset as u16)
+    }
+
+    pub fn lwl(rt: RegisterType, base: RegisterType, offset: i16) -> u32 {
+        i_type(0x22, base, rt, offset as u16)
+    }
+
+    pub fn lw(rt: RegisterType, base: RegisterType, offset: i16) -> u32 {
+        i_type(0x23, base, rt, offset as u16)
+    }
+
+    pub fn lwr(rt: RegisterType, base: RegisterType, offset: i16) -> u32 {
+        i_type(0x26, base, rt, offset as u16)
+    }
+
+    pub fn sh(rt: RegisterType, base: RegisterType, offset: i16) -> u32 {
+        i_type(0x29, base, rt, offset as u16)
+    }
+
+    pub fn swl(rt: RegisterType, base: RegisterType, offset: i16) -> u32 {
+        i_type(0x2A, base, rt, offset as u16)
+    }
+
+    pub fn sw(rt: RegisterType, base: RegisterType, offset: i16) -> u32 {
+        i_type(0x2B, base, rt, offset as u16)
+    }
+
+    pub fn swr(rt: RegisterType, base: RegisterType, offset: i16) -> u32 {
+        i_type(0x2E, base, rt, offset as u16)
+    }
+
+    pub fn mfc0(rt: RegisterType, rd: u8) -> u32 {
+        (0x10 << 26) | ((rt as u32) << 16) | ((rd as u32) << 11)
+    }
+
+    pub fn mtc0(rt: RegisterType, rd: u8) -> u32 {
+        (0x10 << 26) | (4 << 21) | ((rt as u32) << 16) | ((rd as u32) << 11)
+    }
+
+    pub fn rfe() -> u32 {
+        0x42000010
+    }
+}
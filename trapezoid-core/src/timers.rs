@@ -1,4 +1,5 @@
 use crate::memory::{interrupts::InterruptRequester, BusLine, Result};
+use crate::state_hash::StateHasher;
 use bitflags::bitflags;
 
 bitflags! {
@@ -116,10 +117,9 @@ impl TimerBase {
                 self.one_shot_suppress_irqs = false;
 
                 let mode = CounterMode::from_bits_retain(data & 0x3FF);
-                if data & 0x400 != 0 {
-                    // reset IRQ request
-                    self.mode.insert(CounterMode::NOT_IRQ_REQUEST);
-                }
+                // writing the mode always resets the IRQ request (sets bit 10),
+                // otherwise the first IRQ after power on would not be a transition
+                self.mode.insert(CounterMode::NOT_IRQ_REQUEST);
 
                 self.mode &= CounterMode::from_bits_retain(!0x3FF);
                 self.mode |= mode;
@@ -147,10 +147,13 @@ impl TimerBase {
         let old_irq = self.mode.irq();
 
         assert!(cycles <= 0xFFFF);
-        let (new_counter, overflow) = self.counter.overflowing_add(cycles as u16);
+        // computed in 32 bits, so that a target after a wrap is not missed
+        let old_counter = self.counter as u32;
+        let target = self.target as u32;
+        let mut counter = old_counter + cycles;
 
-        let reached_target = self.counter < self.target && new_counter >= self.target;
-        self.counter = new_counter;
+        let reached_target = (old_counter < target && counter >= target)
+            || (counter > 0xFFFF && counter - 0x10000 >= target);
 
         let mut irq = false;
         let is_one_shot_mode = !self.mode.irq_repeat_mode();
@@ -163,16 +166,19 @@ impl TimerBase {
                 irq = true;
             }
             if self.mode.reset_after_target() {
-                self.counter -= self.target;
+                // the target can be passed more than once if `cycles` is large
+                counter = if target == 0 { 0 } else { counter % target };
             }
         }
 
-        if overflow {
+        if counter > 0xFFFF {
             self.mode.set_reached_ffff();
             if self.mode.irq_on_ffff() {
                 irq = true;
             }
+            counter &= 0xFFFF;
         }
+        self.counter = counter as u16;
 
         if irq && !one_shot_mode_irq_supressed {
             if is_one_shot_mode {
@@ -200,6 +206,17 @@ impl TimerBase {
         }
     }
 
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_u16(self.mode.bits());
+        hasher.write_u16(self.counter);
+        hasher.write_u16(self.target);
+        hasher.write_u8(
+            self.paused as u8
+                | (self.one_shot_suppress_irqs as u8) << 1
+                | (self.should_request_interrupt as u8) << 2,
+        );
+    }
+
     /// This is so that the base timer knows that it can set the irq line
     ///  in pulse mode
     fn get_irq_requested(&mut self) -> bool {
@@ -255,6 +272,9 @@ impl Timer0 {
 #[derive(Default)]
 struct Timer1 {
     base: TimerBase,
+    in_vblank: bool,
+    /// For sync mode 3, a vblank happened since the mode was written
+    vblank_seen: bool,
 }
 
 impl Timer1 {
@@ -268,6 +288,10 @@ impl Timer1 {
 
     fn write(&mut self, index: u32, data: u16) {
         self.base.write(index, data);
+        if index == 1 {
+            self.vblank_seen = false;
+            self.update_paused();
+        }
     }
 
     fn get_irq_requested(&mut self) -> bool {
@@ -277,20 +301,40 @@ impl Timer1 {
 
 impl Timer1 {
     fn increment_counter(&mut self, cycles: u32) {
+        // `paused` is updated by the sync mode
+        self.base.increment_counter(cycles);
+    }
+
+    /// The sync modes of timer 1 are based on vblank
+    fn set_in_vblank(&mut self, in_vblank: bool) {
+        let vblank_started = in_vblank && !self.in_vblank;
+        self.in_vblank = in_vblank;
+
         let sync_mode = self.mode().sync_mode();
-        if self.mode().sync_enable() {
-            // TODO: fix sync modes
-            self.base.increment_counter(cycles);
+        if vblank_started && self.mode().sync_enable() {
             match sync_mode {
-                0 => {}
-                1 => {}
-                2 => {}
-                3 => {}
-                _ => unreachable!(),
+                1 | 2 => self.base.counter = 0,
+                3 => self.vblank_seen = true,
+                _ => {}
             }
-        } else {
-            self.base.increment_counter(cycles);
         }
+        self.update_paused();
+    }
+
+    fn update_paused(&mut self) {
+        let sync_mode = self.mode().sync_mode();
+        self.base.paused = self.mode().sync_enable()
+            && match sync_mode {
+                // pause during vblank
+                0 => self.in_vblank,
+                // reset at vblank
+                1 => false,
+                // reset at vblank, and pause outside it
+                2 => !self.in_vblank,
+                // pause until the first vblank, then free run
+                3 => !self.vblank_seen,
+                _ => unreachable!(),
+            };
     }
 }
 
@@ -370,12 +414,26 @@ impl Timers {
         }
     }
 
-    pub fn clock_from_hblank(&mut self) {
-        if self.timer1.mode().clk_source() & 1 == 1 {
-            self.timer1.increment_counter(1);
+    /// Timer 1 is gated by vblank in sync mode, this should be called before
+    /// the clocks of each step
+    pub fn set_in_vblank(&mut self, in_vblank: bool) {
+        self.timer1.set_in_vblank(in_vblank);
+    }
+
+    pub fn clock_from_hblank(&mut self, hblanks: u32) {
+        if self.timer1.mode().clk_source() & 1 == 1 && hblanks > 0 {
+            self.timer1.increment_counter(hblanks);
         }
     }
 
+    pub(crate) fn hash_state(&self, hasher: &mut StateHasher) {
+        self.timer0.base.hash_state(hasher);
+        self.timer1.base.hash_state(hasher);
+        self.timer2.base.hash_state(hasher);
+        hasher.write_u8(self.timer1.in_vblank as u8 | (self.timer1.vblank_seen as u8) << 1);
+        hasher.write_u32(self.timer2.divider_counter);
+    }
+
     /// Request interrupts if any are queued from the previous clocking
     pub fn handle_interrupts(&mut self, interrupt_requester: &mut impl InterruptRequester) {
         if self.timer0.get_irq_requested() {
@@ -465,3 +523,179 @@ impl BusLine for Timers {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// GPU cycles in an NTSC scanline
+    const DOTS_PER_SCANLINE: u32 = 3413;
+    const SCANLINES: u32 = 263;
+    const VBLANK_START: u32 = 240;
+    /// The CPU cycles of an NTSC scanline, rounded
+    const CPU_CYCLES_PER_SCANLINE: u32 = 2153;
+
+    /// Clock source 1, this is the dot clock for timer 0 and hblank for timer 1
+    const CLK_SOURCE_1: u32 = 0x100;
+    const TARGET_MODE: u32 = CLK_SOURCE_1 | 0x08 | 0x10 | 0x40;
+
+    #[derive(Default)]
+    struct IrqCounter {
+        timers: [u32; 3],
+    }
+
+    impl InterruptRequester for IrqCounter {
+        fn request_vblank(&mut self) {}
+        fn request_gpu(&mut self) {}
+        fn request_cdrom(&mut self) {}
+        fn request_dma(&mut self) {}
+        fn request_timer0(&mut self) {
+            self.timers[0] += 1;
+        }
+        fn request_timer1(&mut self) {
+            self.timers[1] += 1;
+        }
+        fn request_timer2(&mut self) {
+            self.timers[2] += 1;
+        }
+        fn request_controller_mem_card(&mut self) {}
+        fn request_spu(&mut self) {}
+    }
+
+    /// Clock `timers` one scanline at a time for `frames` NTSC frames, in the same
+    /// order as the bus, `dots_divider` depends on the horizontal resolution
+    fn run_frames(timers: &mut Timers, irqs: &mut IrqCounter, dots_divider: u32, frames: u32) {
+        let mut dot_remainder = 0;
+        for _ in 0..frames {
+            for scanline in 0..SCANLINES {
+                // the hblank at the end of this scanline starts the next one
+                let next_scanline = (scanline + 1) % SCANLINES;
+                dot_remainder += DOTS_PER_SCANLINE;
+                let dots = dot_remainder / dots_divider;
+                dot_remainder %= dots_divider;
+
+                timers.set_in_vblank(next_scanline >= VBLANK_START);
+                timers.clock_from_system(CPU_CYCLES_PER_SCANLINE);
+                timers.clock_from_hblank(1);
+                timers.clock_from_gpu_dot(dots);
+                timers.handle_interrupts(irqs);
+            }
+        }
+    }
+
+    fn setup(timer: u32, mode: u32, target: u32) -> (Timers, IrqCounter) {
+        let mut timers = Timers::default();
+        timers.write_u32(timer * 0x10 + 8, target).unwrap();
+        timers.write_u32(timer * 0x10 + 4, mode).unwrap();
+        (timers, IrqCounter::default())
+    }
+
+    fn counter(timers: &mut Timers, timer: u32) -> u32 {
+        timers.read_u32(timer * 0x10).unwrap()
+    }
+
+    fn mode(timers: &mut Timers, timer: u32) -> CounterMode {
+        CounterMode::from_bits_retain(timers.read_u32(timer * 0x10 + 4).unwrap() as u16)
+    }
+
+    #[test]
+    fn timer1_hblank_repeat() {
+        let (mut timers, mut irqs) = setup(1, TARGET_MODE, 100);
+        run_frames(&mut timers, &mut irqs, 10, 3);
+
+        // 789 hblanks
+        assert_eq!(irqs.timers, [0, 7, 0]);
+        assert_eq!(counter(&mut timers, 1), 89);
+        assert!(mode(&mut timers, 1).contains(CounterMode::REACHED_TARGET));
+        // cleared after the read
+        assert!(!mode(&mut timers, 1).contains(CounterMode::REACHED_TARGET));
+    }
+
+    #[test]
+    fn timer1_hblank_one_shot() {
+        let one_shot = TARGET_MODE & !0x40;
+        let (mut timers, mut irqs) = setup(1, one_shot, 100);
+        run_frames(&mut timers, &mut irqs, 10, 3);
+
+        // still resets at the target, but only one IRQ
+        assert_eq!(irqs.timers, [0, 1, 0]);
+        assert_eq!(counter(&mut timers, 1), 89);
+        assert!(mode(&mut timers, 1).contains(CounterMode::REACHED_TARGET));
+
+        // writing the mode allows the next one
+        timers.write_u32(0x14, one_shot).unwrap();
+        run_frames(&mut timers, &mut irqs, 10, 1);
+        assert_eq!(irqs.timers, [0, 2, 0]);
+        assert_eq!(counter(&mut timers, 1), 263 % 100);
+    }
+
+    #[test]
+    fn timer1_hblank_toggle() {
+        let (mut timers, mut irqs) = setup(1, TARGET_MODE | 0x80, 100);
+        run_frames(&mut timers, &mut irqs, 10, 3);
+
+        // 7 matches, only the ones that change bit 10 to `0` request an IRQ
+        assert_eq!(irqs.timers, [0, 4, 0]);
+        assert!(!mode(&mut timers, 1).contains(CounterMode::NOT_IRQ_REQUEST));
+    }
+
+    #[test]
+    fn timer1_hblank_overflow() {
+        // IRQ on target and on 0xFFFF, without resetting at the target
+        let (mut timers, mut irqs) = setup(1, CLK_SOURCE_1 | 0x10 | 0x20 | 0x40, 100);
+        run_frames(&mut timers, &mut irqs, 10, 250);
+
+        // 65750 hblanks, the target is matched again after the wrap
+        assert_eq!(irqs.timers, [0, 3, 0]);
+        assert_eq!(counter(&mut timers, 1), 65750 - 0x10000);
+        let mode = mode(&mut timers, 1);
+        assert!(mode.contains(CounterMode::REACHED_TARGET | CounterMode::REACHED_FFFF));
+    }
+
+    #[test]
+    fn timer1_vblank_sync_modes() {
+        // the vblank starts when scanline 239 ends, and it lasts 23 scanlines
+        for (sync_mode, expected) in [
+            // pause during vblank
+            (0, 2 * (SCANLINES - 23)),
+            // reset at vblank
+            (1, 24),
+            // reset at vblank, and pause outside
+            (2, 23),
+            // pause until the first vblank
+            (3, 2 * SCANLINES - 239),
+        ] {
+            let (mut timers, mut irqs) = setup(1, CLK_SOURCE_1 | 1 | (sync_mode << 1), 0);
+            run_frames(&mut timers, &mut irqs, 10, 2);
+            assert_eq!(counter(&mut timers, 1), expected, "sync mode {}", sync_mode);
+        }
+    }
+
+    #[test]
+    fn timer0_dotclock_resolutions() {
+        // 256, 320, 368, 512 and 640 pixels
+        for dots_divider in [10, 8, 7, 5, 4] {
+            let total_dots = 2 * SCANLINES * DOTS_PER_SCANLINE / dots_divider;
+
+            let (mut timers, mut irqs) = setup(0, TARGET_MODE, 1000);
+            run_frames(&mut timers, &mut irqs, dots_divider, 2);
+            assert_eq!(irqs.timers, [total_dots / 1000, 0, 0], "{}", dots_divider);
+            assert_eq!(
+                counter(&mut timers, 0),
+                total_dots % 1000,
+                "{}",
+                dots_divider
+            );
+
+            // the target is passed more than once in a scanline
+            let (mut timers, mut irqs) = setup(0, TARGET_MODE, 100);
+            run_frames(&mut timers, &mut irqs, dots_divider, 2);
+            assert_eq!(
+                counter(&mut timers, 0),
+                total_dots % 100,
+                "{}",
+                dots_divider
+            );
+        }
+    }
+}
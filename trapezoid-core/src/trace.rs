@@ -0,0 +1,202 @@
+//! Instrumentation events emitted by the hardware components.
+//!
+//! A [`TraceSink`] can be installed with [`Psx::set_trace_sink`](crate::Psx::set_trace_sink)
+//! to receive typed events as they happen, which is useful for timeline viewers
+//! and automated analysis. When no sink is installed, the events are not even
+//! constructed.
+
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+/// The source of an interrupt request, matches the bits of `I_STAT`.
+///
+/// `SIO` and the lightpen `Controller` interrupts are not included, since
+/// nothing emulated raises them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqSource {
+    Vblank,
+    Gpu,
+    Cdrom,
+    Dma,
+    Timer0,
+    Timer1,
+    Timer2,
+    ControllerAndMemCard,
+    Spu,
+}
+
+/// The type of primitive rendered by a GPU draw call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpuPrimitive {
+    Polygon,
+    Line,
+    Rectangle,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// A command started executing, with the parameters it was given
+    CdromCommand {
+        cmd: u8,
+        params: Vec<u8>,
+    },
+    /// A response was pushed together with the interrupt `INT1`-`INT5`
+    CdromResponse {
+        interrupt: u8,
+        response: Vec<u8>,
+    },
+    SpuKeyOn {
+        voice: u8,
+    },
+    /// A draw command was sent to the GPU backend
+    GpuDrawCall {
+        prim: GpuPrimitive,
+        vertices: u32,
+    },
+    /// A single run of a DMA channel, `words` is the number of words transferred
+    DmaTransfer {
+        channel: u8,
+        words: u32,
+    },
+    /// An interrupt was raised, only fired when the bit in `I_STAT` goes from 0 to 1
+    IrqRaised {
+        source: IrqSource,
+    },
+}
+
+pub trait TraceSink: Send {
+    fn trace(&mut self, event: &TraceEvent);
+}
+
+/// A handle to the installed sink that is shared by all the components.
+///
+/// Cloning it is cheap, and when no sink is installed [`Tracer::trace`]
+/// doesn't call the event constructor.
+#[derive(Clone, Default)]
+pub(crate) struct Tracer {
+    sink: Option<Arc<Mutex<Box<dyn TraceSink>>>>,
+}
+
+impl Tracer {
+    pub fn new(sink: Box<dyn TraceSink>) -> Self {
+        Self {
+            sink: Some(Arc::new(Mutex::new(sink))),
+        }
+    }
+
+    #[inline]
+    pub fn trace(&self, event: impl FnOnce() -> TraceEvent) {
+        if let Some(sink) = &self.sink {
+            sink.lock().unwrap().trace(&event());
+        }
+    }
+}
+
+/// A [`TraceSink`] writing every event as a JSON object on its own line.
+pub struct JsonLinesTraceSink<W: Write + Send> {
+    writer: W,
+}
+
+impl<W: Write + Send> JsonLinesTraceSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_event(&mut self, event: &TraceEvent) -> io::Result<()> {
+        let w = &mut self.writer;
+        match event {
+            TraceEvent::CdromCommand { cmd, params } => write!(
+                w,
+                r#"{{"event":"CdromCommand","cmd":{},"params":{:?}}}"#,
+                cmd, params
+            ),
+            TraceEvent::CdromResponse {
+                interrupt,
+                response,
+            } => write!(
+                w,
+                r#"{{"event":"CdromResponse","interrupt":{},"response":{:?}}}"#,
+                interrupt, response
+            ),
+            TraceEvent::SpuKeyOn { voice } => {
+                write!(w, r#"{{"event":"SpuKeyOn","voice":{}}}"#, voice)
+            }
+            TraceEvent::GpuDrawCall { prim, vertices } => write!(
+                w,
+                r#"{{"event":"GpuDrawCall","prim":"{:?}","vertices":{}}}"#,
+                prim, vertices
+            ),
+            TraceEvent::DmaTransfer { channel, words } => write!(
+                w,
+                r#"{{"event":"DmaTransfer","channel":{},"words":{}}}"#,
+                channel, words
+            ),
+            TraceEvent::IrqRaised { source } => {
+                write!(w, r#"{{"event":"IrqRaised","source":"{:?}"}}"#, source)
+            }
+        }?;
+        writeln!(w)
+    }
+}
+
+impl<W: Write + Send> TraceSink for JsonLinesTraceSink<W> {
+    fn trace(&mut self, event: &TraceEvent) {
+        if let Err(e) = self.write_event(event) {
+            log::error!("failed to write trace event: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Collects all the events, so they can be checked later
+    #[derive(Clone, Default)]
+    pub(crate) struct CollectSink(pub Arc<Mutex<Vec<TraceEvent>>>);
+
+    impl TraceSink for CollectSink {
+        fn trace(&mut self, event: &TraceEvent) {
+            self.0.lock().unwrap().push(event.clone());
+        }
+    }
+
+    #[test]
+    fn tracer_skips_events_without_sink() {
+        let tracer = Tracer::default();
+        tracer.trace(|| unreachable!("event should not be constructed"));
+    }
+
+    #[test]
+    fn json_lines_output() {
+        let mut sink = JsonLinesTraceSink::new(Vec::new());
+        sink.trace(&TraceEvent::CdromCommand {
+            cmd: 0x19,
+            params: vec![0x20],
+        });
+        sink.trace(&TraceEvent::GpuDrawCall {
+            prim: GpuPrimitive::Polygon,
+            vertices: 3,
+        });
+        sink.trace(&TraceEvent::IrqRaised {
+            source: IrqSource::Vblank,
+        });
+
+        let out = String::from_utf8(sink.into_inner()).unwrap();
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                r#"{"event":"CdromCommand","cmd":25,"params":[32]}"#,
+                r#"{"event":"GpuDrawCall","prim":"Polygon","vertices":3}"#,
+                r#"{"event":"IrqRaised","source":"Vblank"}"#,
+            ]
+        );
+    }
+}
@@ -0,0 +1,143 @@
+//! Guest behavior that is not emulated.
+//!
+//! Instead of aborting, the devices log an error, continue with a plausible
+//! default (open bus, ignoring the command...) and record a warning here, so the
+//! frontend can tell the user why a game misbehaves.
+
+use std::sync::{Arc, Mutex};
+
+use crate::memory::BusError;
+
+/// The distinct warnings kept, after that only [`Warnings::count`] grows
+const MAX_WARNINGS: usize = 256;
+
+/// An unsupported access or command, `count` is how many times it happened
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmulationWarning {
+    pub device: &'static str,
+    pub message: String,
+    pub count: u64,
+}
+
+struct WarningLog {
+    warnings: Vec<EmulationWarning>,
+    count: u64,
+    /// Panic instead of recording, so tests don't pass over unsupported paths
+    strict: bool,
+}
+
+impl WarningLog {
+    fn record(&mut self, device: &'static str, message: String) {
+        self.count += 1;
+        if let Some(warning) = self
+            .warnings
+            .iter_mut()
+            .find(|w| w.device == device && w.message == message)
+        {
+            warning.count += 1;
+        } else if self.warnings.len() < MAX_WARNINGS {
+            self.warnings.push(EmulationWarning {
+                device,
+                message,
+                count: 1,
+            });
+        }
+    }
+}
+
+/// A handle to the warnings, shared by the CPU and the devices
+#[derive(Clone)]
+pub(crate) struct Warnings {
+    log: Arc<Mutex<WarningLog>>,
+}
+
+impl Default for Warnings {
+    fn default() -> Self {
+        Self {
+            log: Arc::new(Mutex::new(WarningLog {
+                warnings: Vec::new(),
+                count: 0,
+                strict: cfg!(test),
+            })),
+        }
+    }
+}
+
+impl Warnings {
+    pub fn warn(&self, device: &'static str, message: String) {
+        log::error!("{}: {}", device, message);
+
+        let mut log = self.log.lock().unwrap();
+        if log.strict {
+            drop(log);
+            panic!("{}: {}", device, message);
+        }
+        log.record(device, message);
+    }
+
+    /// Bus errors are already logged by the CPU, and were never fatal, so they
+    /// don't panic in strict mode
+    pub fn bus_error(&self, error: &BusError) {
+        let device = match error {
+            BusError::DeviceError { device, .. } => *device,
+            _ => "bus",
+        };
+        self.log.lock().unwrap().record(device, error.to_string());
+    }
+
+    /// The total number of warnings, including repeated ones
+    pub fn count(&self) -> u64 {
+        self.log.lock().unwrap().count
+    }
+
+    pub fn list(&self) -> Vec<EmulationWarning> {
+        self.log.lock().unwrap().warnings.clone()
+    }
+
+    pub fn clear(&self) {
+        let mut log = self.log.lock().unwrap();
+        log.warnings.clear();
+        log.count = 0;
+    }
+
+    pub fn set_strict(&self, strict: bool) {
+        self.log.lock().unwrap().strict = strict;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_warnings() {
+        let warnings = Warnings::default();
+        warnings.set_strict(false);
+        warnings.warn("CDROM", "unknown command 1F".to_string());
+        warnings.warn("CDROM", "unknown command 1F".to_string());
+        warnings.warn("SPU", "u8 read".to_string());
+        // not a panic even in strict mode
+        warnings.set_strict(true);
+        warnings.bus_error(&BusError::UnmappedRead {
+            addr: 0x1F802100,
+            size: 8,
+        });
+
+        assert_eq!(warnings.count(), 4);
+        let list = warnings.list();
+        assert_eq!(list.len(), 3);
+        assert_eq!(list[0].count, 2);
+        assert_eq!(list[1].device, "SPU");
+        assert_eq!(list[2].message, "u8 read from 1F802100");
+
+        warnings.clear();
+        assert_eq!(warnings.count(), 0);
+        assert!(warnings.list().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "SPU: u8 read")]
+    fn strict_panics() {
+        Warnings::default().warn("SPU", "u8 read".to_string());
+    }
+}
@@ -0,0 +1,119 @@
+//! Helpers shared by the integration tests.
+//!
+//! These need a Vulkan device, so the tests only run with the `gpu-tests` feature.
+
+use std::sync::Arc;
+
+use trapezoid_core::{DiskImage, Psx, PsxConfigBuilder};
+use vulkano::device::{Device, Queue};
+
+/// Create a headless device with a graphics queue, the first available one is used
+pub fn create_device() -> (Arc<Device>, Arc<Queue>) {
+    trapezoid_core::create_headless_device().unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// A [`Psx`] running `exe` with the HLE BIOS, on top of `config`
+#[allow(dead_code)]
+pub fn hle_psx(exe: Vec<u8>, config: PsxConfigBuilder) -> Psx {
+    let (device, queue) = create_device();
+    Psx::new_from_bytes(
+        Vec::new(),
+        Some(DiskImage::Exe(exe)),
+        config.hle_bios(true).build(),
+        device,
+        queue,
+    )
+    .unwrap()
+}
+
+/// The entry point of the EXEs made by [`exe_from_program`]
+const EXE_ENTRY: u32 = 0x80010000;
+
+/// A `PS-X EXE` that runs `program` at [`EXE_ENTRY`]
+#[allow(dead_code)]
+pub fn exe_from_program(program: &[u32]) -> Vec<u8> {
+    let mut exe = vec![0; 0x800];
+    exe[..8].copy_from_slice(b"PS-X EXE");
+    let mut header_word = |offset: usize, value: u32| {
+        exe[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    };
+    header_word(0x10, EXE_ENTRY);
+    header_word(0x18, EXE_ENTRY);
+    header_word(0x1C, program.len() as u32 * 4);
+    header_word(0x30, 0x801FFF00);
+    exe.extend(program.iter().flat_map(|w| w.to_le_bytes()));
+    exe
+}
+
+/// Send `byte` to the controller and wait for the reply in `t3`
+fn pad_exchange(byte: u8) -> [u32; 9] {
+    [
+        0x34090000 | byte as u32, // ori   t1, zero, byte
+        0xA1091040,               // sb    t1, 0x1040(t0)    ; JOY_TX_DATA
+        0x950A1044,               // lhu   t2, 0x1044(t0)    ; JOY_STAT
+        0x00000000,               // nop
+        0x314A0002,               // andi  t2, t2, 2         ; RX not empty
+        0x1140FFFC,               // beq   t2, zero, -4
+        0x00000000,               // nop
+        0x910B1040,               // lbu   t3, 0x1040(t0)    ; JOY_RX_DATA
+        0x00000000,               // nop
+    ]
+}
+
+/// Read the buttons of the controller selected by `joy_ctrl`, and add them, mixed
+/// with the timer 2 counter, to the next word in the buffer at `s0`
+fn pad_poll_and_mix(joy_ctrl: u16) -> Vec<u32> {
+    let mut program = vec![
+        0x34090000 | joy_ctrl as u32, // ori   t1, zero, joy_ctrl
+        0xA509104A,                   // sh    t1, 0x104A(t0)    ; JOY_CTRL
+    ];
+    // `ReadButtons`
+    program.extend(pad_exchange(0x01));
+    program.extend(pad_exchange(0x42));
+    program.extend(pad_exchange(0x00));
+    program.extend(pad_exchange(0x00));
+    program.push(0x01608825); // or    s1, t3, zero
+    program.extend(pad_exchange(0x00));
+    program.extend([
+        0x000B5A00, // sll   t3, t3, 8
+        0x022B8825, // or    s1, s1, t3
+        0xA500104A, // sh    zero, 0x104A(t0)  ; JOY_CTRL, deselect
+        0x950A1120, // lhu   t2, 0x1120(t0)    ; timer 2 counter
+        0x00000000, // nop
+        0x01515026, // xor   t2, t2, s1
+        0x02126021, // addu  t4, s0, s2
+        0x8D8D0000, // lw    t5, 0(t4)
+        0x00000000, // nop
+        0x01AA6821, // addu  t5, t5, t2
+        0xAD8D0000, // sw    t5, 0(t4)
+        0x26520004, // addiu s2, s2, 4
+        0x32520FFF, // andi  s2, s2, 0xFFF
+    ]);
+    program
+}
+
+/// An EXE that polls the controllers in both ports in a loop, and mixes the buttons
+/// and the timer 2 counter into a buffer in RAM, so any difference in the timing or
+/// the input changes the state
+#[allow(dead_code)]
+pub fn pad_poll_exe() -> Vec<u8> {
+    let mut program = vec![
+        0x3C081F80, // lui   t0, 0x1F80
+        0x3C108010, // lui   s0, 0x8010
+        0x00009025, // or    s2, zero, zero
+    ];
+    // loop:
+    program.extend(pad_poll_and_mix(0x0003)); // TX enable, select the first port
+    program.extend(pad_poll_and_mix(0x2003)); // and the second port
+    program.extend([
+        0x08004003, // j     loop
+        0x00000000, // nop
+    ]);
+    exe_from_program(&program)
+}
+
+/// A [`Psx`] running [`pad_poll_exe`] with the HLE BIOS
+#[allow(dead_code)]
+pub fn pad_poll_psx(config: PsxConfigBuilder) -> Psx {
+    hle_psx(pad_poll_exe(), config)
+}
@@ -0,0 +1,103 @@
+//! A controller poll that waits for the `/ACK` interrupt of each byte in a tight
+//! loop, and gives up after a fixed number of iterations, like the games that decide
+//! that no controller is connected when the `/ACK` comes too late.
+#![cfg(feature = "gpu-tests")]
+
+mod common;
+
+use trapezoid_core::{cpu::CpuState, AckTiming, PsxConfig};
+
+/// The iterations of the wait loop, around 7 instructions each
+const TIMEOUT: u32 = 500;
+const POLL: [u8; 5] = [0x01, 0x42, 0x00, 0x00, 0x00];
+/// The iterations left when each `/ACK` came are stored here
+const REMAINING_ADDR: u32 = 0x80100000;
+/// And the received bytes here
+const RECEIVED_ADDR: u32 = 0x80100100;
+
+/// Send `byte`, and wait for the controller interrupt in `I_STAT`, then acknowledge it
+/// and store the received byte
+fn exchange_with_timeout(byte: u8) -> [u32; 18] {
+    [
+        0x34090000 | byte as u32, // ori   t1, zero, byte
+        0xA1091040,               // sb    t1, 0x1040(t0)    ; JOY_TX_DATA
+        0x340C0000 | TIMEOUT,     // ori   t4, zero, TIMEOUT
+        // wait:
+        0x950A1070, // lhu   t2, 0x1070(t0)    ; I_STAT
+        0x258CFFFF, // addiu t4, t4, -1
+        0x314A0080, // andi  t2, t2, 0x80      ; controller and memory card
+        0x15400003, // bne   t2, zero, done
+        0x00000000, // nop
+        0x1D80FFFA, // bgtz  t4, wait
+        0x00000000, // nop
+        // done:
+        0xAE0C0000, // sw    t4, 0(s0)
+        0x26100004, // addiu s0, s0, 4
+        0xA50E104A, // sh    t6, 0x104A(t0)    ; JOY_CTRL, acknowledge
+        0xA50D1070, // sh    t5, 0x1070(t0)    ; I_STAT, acknowledge
+        0x910B1040, // lbu   t3, 0x1040(t0)    ; JOY_RX_DATA
+        0x00000000, // nop
+        0xA22B0000, // sb    t3, 0(s1)
+        0x26310001, // addiu s1, s1, 1
+    ]
+}
+
+fn poll_with_timeout_exe() -> Vec<u8> {
+    let mut program = vec![
+        0x3C081F80, // lui   t0, 0x1F80
+        0x3C108010, // lui   s0, 0x8010
+        0x3C118010, // lui   s1, 0x8010
+        0x36310100, // ori   s1, s1, 0x100
+        0x340DFF7F, // ori   t5, zero, 0xFF7F
+        0x340E1013, // ori   t6, zero, 0x1013
+        0xA50D1070, // sh    t5, 0x1070(t0)    ; I_STAT, acknowledge
+        0x34091003, // ori   t1, zero, 0x1003
+        0xA509104A, // sh    t1, 0x104A(t0)    ; JOY_CTRL, TX and ACK interrupt enable
+    ];
+    for byte in POLL {
+        program.extend(exchange_with_timeout(byte));
+    }
+    program.extend([
+        0x1000FFFF, // b     .
+        0x00000000, // nop
+    ]);
+    common::exe_from_program(&program)
+}
+
+/// The iterations left when each `/ACK` came, and the received bytes
+fn run_poll(ack_timing: AckTiming) -> (Vec<u32>, Vec<u8>) {
+    let mut psx = common::hle_psx(
+        poll_with_timeout_exe(),
+        PsxConfig::builder().controller_ack_timing(ack_timing),
+    );
+    for _ in 0..2 {
+        assert_eq!(psx.clock_full_video_frame(), CpuState::Normal);
+    }
+
+    let remaining = (0..POLL.len() as u32)
+        .map(|i| psx.bus_read_u32(REMAINING_ADDR + i * 4).unwrap())
+        .collect();
+    let received = (0..POLL.len() as u32)
+        .map(|i| psx.bus_read_u8(RECEIVED_ADDR + i).unwrap())
+        .collect();
+    (remaining, received)
+}
+
+#[test]
+fn pad_answers_before_timeout() {
+    let (fast, fast_received) = run_poll(AckTiming::Fast);
+    let (accurate, accurate_received) = run_poll(AckTiming::Accurate);
+
+    for received in [fast_received, accurate_received] {
+        assert_eq!(received, [0x00, 0x41, 0x5A, 0xFF, 0xFF]);
+    }
+    for remaining in [&fast, &accurate] {
+        // all the bytes are acknowledged in time, except the last one
+        assert!(remaining[..4].iter().all(|&r| r > 0), "{:?}", remaining);
+        assert_eq!(remaining[4], 0);
+    }
+    // the delay is after the transfer
+    for (fast, accurate) in fast.iter().zip(&accurate).take(4) {
+        assert!(accurate < fast, "{:?} {:?}", fast, accurate);
+    }
+}
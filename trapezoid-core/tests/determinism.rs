@@ -0,0 +1,103 @@
+//! Two emulators given the same EXE and the same input must stay in the same state.
+//!
+//! The EXE polls the controllers in a loop and mixes the buttons and the timer 2
+//! counter into a buffer in RAM, so any difference in the timing or the input
+//! shows up in [`Psx::state_hash`].
+#![cfg(feature = "gpu-tests")]
+
+mod common;
+
+use trapezoid_core::{cpu::CpuState, DigitalControllerKey, Psx, PsxConfig};
+
+const FRAMES: u64 = 600;
+const CHECK_INTERVAL: u64 = 60;
+
+/// The keys pressed in `frame`, each key is held for a different number of frames
+fn scripted_input(frame: u64) -> impl Iterator<Item = (DigitalControllerKey, bool)> {
+    const KEYS: [DigitalControllerKey; 4] = [
+        DigitalControllerKey::X,
+        DigitalControllerKey::Up,
+        DigitalControllerKey::Start,
+        DigitalControllerKey::R1,
+    ];
+    KEYS.into_iter()
+        .enumerate()
+        .map(move |(i, key)| (key, (frame / (i as u64 * 3 + 5)) % 2 == 1))
+}
+
+fn run_frame(psx: &mut Psx) {
+    loop {
+        let (frame_done, cpu_state) = psx.clock_based_on_video(u32::MAX);
+        assert_eq!(cpu_state, CpuState::Normal);
+        if frame_done {
+            break;
+        }
+    }
+}
+
+/// Run `FRAMES` frames, and return the state hash every `CHECK_INTERVAL` frames
+fn run(with_input: bool) -> Vec<u64> {
+    let mut psx = common::pad_poll_psx(PsxConfig::builder());
+    let mut hashes = Vec::new();
+
+    for frame in 0..FRAMES {
+        if with_input {
+            for (key, pressed) in scripted_input(frame) {
+                psx.change_controller_key_state(key, pressed);
+            }
+        }
+        run_frame(&mut psx);
+        if (frame + 1) % CHECK_INTERVAL == 0 {
+            hashes.push(psx.state_hash());
+        }
+    }
+    hashes
+}
+
+#[test]
+fn same_input_same_state() {
+    let first = run(true);
+    let second = run(true);
+    for (i, (a, b)) in first.iter().zip(&second).enumerate() {
+        assert_eq!(
+            a,
+            b,
+            "state diverged by frame {}",
+            (i as u64 + 1) * CHECK_INTERVAL
+        );
+    }
+
+    // the program is running and writing to RAM
+    for pair in first.windows(2) {
+        assert_ne!(pair[0], pair[1]);
+    }
+    // the input is part of the state, so this test would catch input being lost
+    assert_ne!(run(false).last(), first.last());
+}
+
+#[test]
+fn reset_same_as_new() {
+    let mut psx = common::pad_poll_psx(PsxConfig::builder());
+    for frame in 0..FRAMES / 2 {
+        for (key, pressed) in scripted_input(frame) {
+            psx.change_controller_key_state(key, pressed);
+        }
+        run_frame(&mut psx);
+    }
+    psx.reset().unwrap();
+
+    let mut new = common::pad_poll_psx(PsxConfig::builder());
+    assert_eq!(psx.state_hash(), new.state_hash());
+    for frame in 0..FRAMES / 2 {
+        run_frame(&mut psx);
+        run_frame(&mut new);
+        if (frame + 1) % CHECK_INTERVAL == 0 {
+            assert_eq!(
+                psx.state_hash(),
+                new.state_hash(),
+                "diverged by frame {} after the reset",
+                frame + 1
+            );
+        }
+    }
+}
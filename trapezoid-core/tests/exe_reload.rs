@@ -0,0 +1,66 @@
+//! [`Psx::reload_exe`] replaces the running EXE, and keeps the old one if the
+//! new file is broken.
+#![cfg(feature = "gpu-tests")]
+
+mod common;
+
+use std::path::PathBuf;
+
+use trapezoid_core::{cpu::CpuState, Psx, PsxConfig, PsxError};
+
+/// An EXE that writes `value` to `0x80030000`, then loops
+fn store_exe(value: u16) -> Vec<u8> {
+    common::exe_from_program(&[
+        0x34090000 | value as u32, // ori   t1, zero, value
+        0x3C088003,                // lui   t0, 0x8003
+        0xAD090000,                // sw    t1, 0(t0)
+        0x08004003,                // j     0x8001000C
+        0x00000000,                // nop
+    ])
+}
+
+fn run_frames(psx: &mut Psx, frames: u64) {
+    for _ in 0..frames {
+        assert_eq!(psx.clock_full_video_frame(), CpuState::Normal);
+    }
+}
+
+fn temp_exe(name: &str, data: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "trapezoid_reload_{}_{}.exe",
+        name,
+        std::process::id()
+    ));
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+#[test]
+fn reload_exe() {
+    let mut psx = common::hle_psx(store_exe(1), PsxConfig::builder());
+    run_frames(&mut psx, 2);
+    assert_eq!(psx.bus_read_u32(0x80030000).unwrap(), 1);
+
+    let rebuilt = temp_exe("rebuilt", &store_exe(2));
+    psx.reload_exe(&rebuilt).unwrap();
+    // the RAM is cleared by the reset
+    assert_eq!(psx.bus_read_u32(0x80030000).unwrap(), 0);
+    run_frames(&mut psx, 2);
+    assert_eq!(psx.bus_read_u32(0x80030000).unwrap(), 2);
+
+    // a broken EXE keeps the old one running, without a reset
+    let broken = temp_exe("broken", b"not an exe");
+    assert!(matches!(
+        psx.reload_exe(&broken),
+        Err(PsxError::InvalidExe(_))
+    ));
+    assert!(matches!(
+        psx.reload_exe(&std::env::temp_dir().join("trapezoid_reload_missing.exe")),
+        Err(PsxError::CouldNotLoadDisk(_))
+    ));
+    assert_eq!(psx.bus_read_u32(0x80030000).unwrap(), 2);
+    run_frames(&mut psx, 2);
+
+    std::fs::remove_file(rebuilt).ok();
+    std::fs::remove_file(broken).ok();
+}
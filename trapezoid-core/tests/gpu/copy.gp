@@ -0,0 +1,8 @@
+# GP0(A0h) CPU to VRAM, and GP0(80h) VRAM to VRAM copies
+area 0 0 32 16
+gp0 02000000 00000000 00100020
+# 4x2 block at (0, 0)
+gp0 A0000000 00000000 00020004
+gp0 7C1F001F 03E07FFF 00007C00 7FFF03E0
+# copy it to (8, 4)
+gp0 80000000 00000000 00040008 00020004
@@ -0,0 +1,7 @@
+# GP0(02h) fill rectangle, ignores the drawing area and offset
+area 0 0 64 32
+gp0 02000000 00000000 00200040
+# red at (16, 8), 32x16
+gp0 020000FF 00080010 00100020
+# green at (32, 16), 16x8
+gp0 0200FF00 00100020 00080010
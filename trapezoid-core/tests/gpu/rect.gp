@@ -0,0 +1,15 @@
+# GP0(60h) monochrome rectangles, with drawing offset and drawing area clipping
+area 0 0 64 32
+gp0 02000000 00000000 00200040
+# drawing area (0, 0) - (47, 31)
+gp0 E3000000
+gp0 E4007C2F
+gp0 E5000000
+# blue 8x8 at (4, 4)
+gp0 60FF0000 00040004 00080008
+# yellow 16x8 at (0, 0) with offset (16, 8)
+gp0 E5004010
+gp0 6000FFFF 00000000 00080010
+gp0 E5000000
+# white 16x16 at (40, 20), clipped to the drawing area
+gp0 60FFFFFF 00140028 00100010
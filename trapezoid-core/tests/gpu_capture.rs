@@ -0,0 +1,46 @@
+//! Capturing the GPU command stream and replaying it.
+#![cfg(feature = "gpu-tests")]
+
+mod common;
+
+use trapezoid_core::{testing::GpuHarness, GpuCaptureReader, GpuCaptureRecord};
+
+#[test]
+fn capture_replay_roundtrip() {
+    let (device, queue) = common::create_device();
+    let path = std::env::temp_dir().join("trapezoid_gpu_capture_roundtrip.bin");
+
+    let mut gpu = GpuHarness::new(device.clone(), queue.clone());
+    gpu.start_capture(&path).unwrap();
+    // frame 1: clear and setup the drawing area
+    gpu.gp1_write(0x00000000);
+    for word in [0x02000000, 0x00000000, 0x00400040, 0xE3000000, 0xE407FFFF] {
+        gpu.gp0_write(word);
+    }
+    gpu.run_frame();
+    // frame 2: a triangle and a rectangle
+    for word in [0x2000FF00, 0x00000000, 0x00000020, 0x00200000] {
+        gpu.gp0_write(word);
+    }
+    for word in [0x60FF0000, 0x00200020, 0x00100010] {
+        gpu.gp0_write(word);
+    }
+    gpu.run_frame();
+    gpu.stop_capture().unwrap();
+    let expected = gpu.read_vram_block(0, 0, 64, 64);
+
+    let records = GpuCaptureReader::open(&path)
+        .unwrap()
+        .collect::<std::io::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(records.len(), 1 + 5 + 1 + 4 + 3 + 1);
+    assert_eq!(records[0], GpuCaptureRecord::Gp1(0));
+    assert_eq!(records[6], GpuCaptureRecord::FrameEnd);
+    assert_eq!(records[14], GpuCaptureRecord::FrameEnd);
+
+    let mut replay = GpuHarness::new(device, queue);
+    assert_eq!(replay.replay_capture(&path).unwrap(), 2);
+    assert_eq!(replay.read_vram_block(0, 0, 64, 64), expected);
+
+    std::fs::remove_file(path).unwrap();
+}
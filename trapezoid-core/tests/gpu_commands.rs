@@ -0,0 +1,704 @@
+//! Drawing with raw GP0/GP1 commands, without the CPU.
+#![cfg(feature = "gpu-tests")]
+
+mod common;
+
+use trapezoid_core::{
+    testing::GpuHarness, DitherMode, GpuError, GpuRenderOptions, PsxConfig, PsxError,
+    RegionOverride,
+};
+use vulkano::format::Format;
+
+const RED: u16 = 0x001F;
+
+fn gpu_with_drawing_area() -> GpuHarness {
+    let (device, queue) = common::create_device();
+    let mut gpu = GpuHarness::new(device, queue);
+    // clear, and use the whole VRAM as drawing area
+    gpu.gp0_write(0x02000000);
+    gpu.gp0_write(0x00000000);
+    gpu.gp0_write(0x00400040);
+    gpu.gp0_write(0xE3000000);
+    gpu.gp0_write(0xE407FFFF);
+    gpu.gp0_write(0xE5000000);
+    gpu
+}
+
+fn pixel(block: &[u16], x: usize, y: usize) -> u16 {
+    block[y * 64 + x] & 0x7FFF
+}
+
+#[test]
+fn flat_triangle() {
+    let mut gpu = gpu_with_drawing_area();
+    // red triangle (0, 0), (32, 0), (0, 32)
+    gpu.gp0_write(0x200000FF);
+    gpu.gp0_write(0x00000000);
+    gpu.gp0_write(0x00000020);
+    gpu.gp0_write(0x00200000);
+
+    let block = gpu.read_vram_block(0, 0, 64, 64);
+    // away from the edges, so it doesn't depend on the rasterization rules
+    for (x, y) in [(2, 2), (20, 4), (4, 20), (12, 12)] {
+        assert_eq!(pixel(&block, x, y), RED, "({}, {})", x, y);
+    }
+    for (x, y) in [(20, 20), (40, 4), (4, 40), (60, 60)] {
+        assert_eq!(pixel(&block, x, y), 0, "({}, {})", x, y);
+    }
+}
+
+#[test]
+fn invalid_commands_are_ignored() {
+    let mut gpu = gpu_with_drawing_area();
+    // not used environment command, and unknown GP1 command
+    gpu.gp0_write(0xE8000000);
+    gpu.gp1_write(0x3F000000);
+
+    // still decoding commands normally
+    gpu.gp0_write(0x600000FF);
+    gpu.gp0_write(0x00000000);
+    gpu.gp0_write(0x00080008);
+
+    let block = gpu.read_vram_block(0, 0, 64, 64);
+    assert_eq!(pixel(&block, 4, 4), RED);
+    assert_eq!(pixel(&block, 10, 10), 0);
+}
+
+/// A 16x16 15-bit texture at (512, 0), the texel at `(u, v)` has `u + 1` in red
+/// and `v + 1` in green, so it's never `0` (transparent)
+fn texel(u: u32, v: u32) -> u16 {
+    ((u + 1) | ((v + 1) << 5)) as u16
+}
+
+fn upload_texture(gpu: &mut GpuHarness) {
+    gpu.gp0_write(0xA0000000);
+    gpu.gp0_write(0x00000200);
+    gpu.gp0_write(0x00100010);
+    for v in 0..16 {
+        for u in (0..16).step_by(2) {
+            gpu.gp0_write(texel(u, v) as u32 | (texel(u + 1, v) as u32) << 16);
+        }
+    }
+}
+
+/// Draw a raw textured `size`x`size` sprite at (0, 0) with the texcoord `(u, v)`,
+/// from the texture page at (512, 0) in 15-bit mode
+fn draw_sprite(gpu: &mut GpuHarness, size: u32, (u, v): (u32, u32), flip: (bool, bool)) {
+    gpu.gp0_write(0xE1000000 | 8 | (2 << 7) | (flip.0 as u32) << 12 | (flip.1 as u32) << 13);
+    gpu.gp0_write(match size {
+        8 => 0x75000000,
+        16 => 0x7D000000,
+        _ => unreachable!(),
+    });
+    gpu.gp0_write(0x00000000);
+    gpu.gp0_write(v << 8 | u);
+}
+
+#[test]
+fn sprites_texcoords_and_flip() {
+    for (size, start) in [(8, 4), (16, 0)] {
+        for flip in [(false, false), (true, false), (false, true), (true, true)] {
+            let mut gpu = gpu_with_drawing_area();
+            upload_texture(&mut gpu);
+
+            // flipped axes start from the other end, and decrement
+            let first = |flipped: bool| if flipped { start + size - 1 } else { start };
+            draw_sprite(&mut gpu, size, (first(flip.0), first(flip.1)), flip);
+
+            let block = gpu.read_vram_block(0, 0, 64, 64);
+            for y in 0..size {
+                for x in 0..size {
+                    let u = if flip.0 {
+                        start + size - 1 - x
+                    } else {
+                        start + x
+                    };
+                    let v = if flip.1 {
+                        start + size - 1 - y
+                    } else {
+                        start + y
+                    };
+                    assert_eq!(
+                        pixel(&block, x as usize, y as usize),
+                        texel(u, v),
+                        "{0}x{0} flip {1:?} at ({2}, {3})",
+                        size,
+                        flip,
+                        x,
+                        y
+                    );
+                }
+            }
+            // and nothing outside
+            assert_eq!(pixel(&block, size as usize, 0), 0);
+            assert_eq!(pixel(&block, 0, size as usize), 0);
+        }
+    }
+}
+
+#[test]
+fn texture_window() {
+    // (mask, offset), in units of 8 texels, the offset bits outside the mask are ignored
+    let windows = [
+        ((1, 0), (1, 0)),
+        ((0, 1), (0, 1)),
+        ((1, 1), (0, 1)),
+        ((1, 1), (2, 3)),
+    ];
+    for (mask, offset) in windows {
+        let mut gpu = gpu_with_drawing_area();
+        upload_texture(&mut gpu);
+        gpu.gp0_write(0xE2000000 | mask.0 | mask.1 << 5 | offset.0 << 10 | offset.1 << 15);
+        draw_sprite(&mut gpu, 16, (0, 0), (false, false));
+
+        let window =
+            |coord: u32, mask: u32, offset: u32| (coord & !(mask * 8)) | ((offset & mask) * 8);
+        let block = gpu.read_vram_block(0, 0, 64, 64);
+        for y in 0..16 {
+            for x in 0..16 {
+                assert_eq!(
+                    pixel(&block, x as usize, y as usize),
+                    texel(window(x, mask.0, offset.0), window(y, mask.1, offset.1)),
+                    "mask {:?} offset {:?} at ({}, {})",
+                    mask,
+                    offset,
+                    x,
+                    y
+                );
+            }
+        }
+    }
+}
+
+/// Draw a raw textured 16x16 rectangle at `(x, 0)`, from a 4-bit texture page
+/// at (512, 0) and the CLUT at (0, 256)
+fn draw_4bit_rect(gpu: &mut GpuHarness, x: u32) {
+    gpu.gp0_write(0xE1000008);
+    gpu.gp0_write(0x65000000);
+    gpu.gp0_write(x);
+    gpu.gp0_write(0x40000000);
+    gpu.gp0_write(0x00100010);
+}
+
+#[test]
+fn clut_drawn_before_textured_draw() {
+    let mut gpu = gpu_with_drawing_area();
+    // 4-bit texture at (512, 0), all the texels use index 1
+    gpu.gp0_write(0xA0000000);
+    gpu.gp0_write(0x00000200);
+    gpu.gp0_write(0x00100004);
+    for _ in 0..4 * 16 / 2 {
+        gpu.gp0_write(0x11111111);
+    }
+
+    const GREEN: u16 = 0x03E0;
+    // draw the CLUT, the draws are still buffered when the textured draws come
+    for (x, color, expected) in [(0, 0x0000FF, RED), (32, 0x00FF00, GREEN)] {
+        gpu.gp0_write(0x60000000 | color);
+        gpu.gp0_write(0x01000000);
+        gpu.gp0_write(0x00010010);
+        draw_4bit_rect(&mut gpu, x);
+
+        let block = gpu.read_vram_block(0, 0, 64, 64);
+        for (dx, y) in [(0, 0), (15, 0), (8, 8), (0, 15), (15, 15)] {
+            assert_eq!(
+                pixel(&block, x as usize + dx, y),
+                expected,
+                "({}, {})",
+                x + dx as u32,
+                y
+            );
+        }
+    }
+}
+
+#[test]
+fn rectangles_are_not_dithered() {
+    let mut gpu = gpu_with_drawing_area();
+    // dithering enabled
+    gpu.gp0_write(0xE1000200);
+    // grey 16x16 rectangle, each channel is 8 in 5-bit with no dithering
+    gpu.gp0_write(0x60404040);
+    gpu.gp0_write(0x00000000);
+    gpu.gp0_write(0x00100010);
+
+    let block = gpu.read_vram_block(0, 0, 64, 64);
+    let grey = 8 | (8 << 5) | (8 << 10);
+    for y in 0..16 {
+        for x in 0..16 {
+            assert_eq!(pixel(&block, x, y), grey, "({}, {})", x, y);
+        }
+    }
+}
+
+/// The documented 4x4 dither matrix, added to the 8-bit colors
+const DITHER_MATRIX: [[i32; 4]; 4] = [
+    [-4, 0, -3, 1],
+    [2, -2, 3, -1],
+    [-3, 1, -4, 0],
+    [3, -1, 2, -2],
+];
+
+/// Gouraud triangle (0, 0), (64, 0), (0, 64), red goes from 0 to 128 with x,
+/// so the pixel `x` has red `2x + 1` at its center, green is 255 everywhere
+fn draw_gradient_triangle(gpu: &mut GpuHarness, dither: bool) {
+    gpu.gp0_write(0xE1000000 | (dither as u32) << 9);
+    gpu.gp0_write(0x3000FF00);
+    gpu.gp0_write(0x00000000);
+    gpu.gp0_write(0x0000FF80);
+    gpu.gp0_write(0x00000040);
+    gpu.gp0_write(0x0000FF00);
+    gpu.gp0_write(0x00400000);
+}
+
+/// The pixels inside the triangle, away from the edges
+fn gradient_triangle_pixels() -> impl Iterator<Item = (usize, usize)> {
+    (0..56).flat_map(|y| (0..56 - y).map(move |x| (x, y)))
+}
+
+#[test]
+fn gouraud_dithering() {
+    let dithered = |x: usize, y: usize| {
+        let offset = DITHER_MATRIX[y % 4][x % 4];
+        // clamped before truncating to 5-bit
+        let channel = |c: i32| ((c + offset).clamp(0, 255) >> 3) as u16;
+        channel(2 * x as i32 + 1) | channel(255) << 5 | channel(0) << 10
+    };
+
+    let mut gpu = gpu_with_drawing_area();
+    draw_gradient_triangle(&mut gpu, true);
+    let block = gpu.read_vram_block(0, 0, 64, 64);
+    for (x, y) in gradient_triangle_pixels() {
+        assert_eq!(pixel(&block, x, y), dithered(x, y), "({}, {})", x, y);
+    }
+
+    // forced on, even if the game disables it
+    let mut gpu = gpu_with_drawing_area();
+    let mut options = GpuRenderOptions::default();
+    options.dithering = DitherMode::ForceOn;
+    gpu.set_render_options(options);
+    draw_gradient_triangle(&mut gpu, false);
+    let block = gpu.read_vram_block(0, 0, 64, 64);
+    for (x, y) in gradient_triangle_pixels() {
+        assert_eq!(pixel(&block, x, y), dithered(x, y), "({}, {})", x, y);
+    }
+
+    // turned off, the same as when the game disables it
+    let mut gpu = gpu_with_drawing_area();
+    draw_gradient_triangle(&mut gpu, false);
+    let not_dithered = gpu.read_vram_block(0, 0, 64, 64);
+
+    let mut gpu = gpu_with_drawing_area();
+    options.dithering = DitherMode::Off;
+    gpu.set_render_options(options);
+    draw_gradient_triangle(&mut gpu, true);
+    let block = gpu.read_vram_block(0, 0, 64, 64);
+    for (x, y) in gradient_triangle_pixels() {
+        assert_eq!(
+            pixel(&block, x, y),
+            pixel(&not_dithered, x, y),
+            "({}, {})",
+            x,
+            y
+        );
+    }
+}
+
+#[test]
+fn region_override_frame_duration() {
+    // `3413 * 263` and `3406 * 314` GPU cycles, in CPU cycles
+    const NTSC_FRAME_CYCLES: u32 = 566204;
+    const PAL_FRAME_CYCLES: u32 = 680823;
+    const GPUSTAT_PAL: u32 = 1 << 20;
+
+    let (device, queue) = common::create_device();
+    for (game_pal, region_override, pal) in [
+        (false, RegionOverride::Auto, false),
+        (true, RegionOverride::Auto, true),
+        (false, RegionOverride::Ntsc, false),
+        (true, RegionOverride::Ntsc, false),
+        (false, RegionOverride::Pal, true),
+        (true, RegionOverride::Pal, true),
+    ] {
+        let mut gpu = GpuHarness::new(device.clone(), queue.clone());
+        gpu.set_region_override(region_override);
+        // display mode, bit 3 is the video mode
+        gpu.gp1_write(0x08000000 | ((game_pal as u32) << 3));
+
+        // start from a vblank
+        gpu.run_frame();
+        let cpu_cycles = gpu.run_frame();
+        let expected = if pal {
+            PAL_FRAME_CYCLES
+        } else {
+            NTSC_FRAME_CYCLES
+        };
+        assert!(
+            cpu_cycles.abs_diff(expected) <= 1000,
+            "{:?} (game PAL: {}): {} != {}",
+            region_override,
+            game_pal,
+            cpu_cycles,
+            expected
+        );
+        assert_eq!(gpu.gpu_stat() & GPUSTAT_PAL != 0, pal);
+    }
+}
+
+#[test]
+fn frame_stats() {
+    let mut gpu = gpu_with_drawing_area();
+    // the setup is in its own frame
+    gpu.end_frame();
+
+    // 3 opaque triangles, batched into one draw
+    for _ in 0..3 {
+        gpu.gp0_write(0x200000FF);
+        gpu.gp0_write(0x00000000);
+        gpu.gp0_write(0x00000020);
+        gpu.gp0_write(0x00200000);
+    }
+    // a semi-transparent triangle needs another pipeline
+    gpu.gp0_write(0x220000FF);
+    gpu.gp0_write(0x00000000);
+    gpu.gp0_write(0x00000020);
+    gpu.gp0_write(0x00200000);
+    // 2x2 `CPU to VRAM` at (256, 0)
+    gpu.gp0_write(0xA0000000);
+    gpu.gp0_write(0x00000100);
+    gpu.gp0_write(0x00020002);
+    gpu.gp0_write(0x7FFF7FFF);
+    gpu.gp0_write(0x7FFF7FFF);
+    // textured 8x8 rectangle, samples the written block
+    gpu.gp0_write(0x64808080);
+    gpu.gp0_write(0x00100010);
+    gpu.gp0_write(0x00000000);
+    gpu.gp0_write(0x00080008);
+
+    gpu.read_vram_block(0, 0, 2, 2);
+    gpu.end_frame();
+    // these are still the stats of the setup frame
+    assert_eq!(gpu.gpu_stats().draw_calls, 0);
+    gpu.end_frame();
+
+    let stats = gpu.gpu_stats();
+    assert_eq!(stats.draw_calls, 3);
+    assert_eq!(stats.vertices, 3 * 3 + 3 + 6);
+    assert_eq!(stats.vram_writes, 1);
+    assert_eq!(stats.vram_write_bytes, 2 * 2 * 2);
+    assert_eq!(stats.vram_reads, 1);
+    assert_eq!(stats.vram_read_bytes, 2 * 2 * 2);
+    assert_eq!(stats.back_image_updates, 1);
+    assert!(stats.command_buffer_flushes >= 1);
+    assert!(!stats.fence_wait_time.is_zero());
+}
+
+#[test]
+fn drawing_offset_changes_are_batched() {
+    let mut gpu = gpu_with_drawing_area();
+    gpu.end_frame();
+
+    // split-screen like, the offset changes for every triangle, before
+    // it was part of the draw state, so this took a draw call per triangle
+    for _ in 0..4 {
+        for offset in [0xE5000000, 0xE5000040] {
+            gpu.gp0_write(offset);
+            // red triangle (0, 0), (16, 0), (0, 16)
+            gpu.gp0_write(0x200000FF);
+            gpu.gp0_write(0x00000000);
+            gpu.gp0_write(0x00000010);
+            gpu.gp0_write(0x00100000);
+        }
+    }
+    gpu.gp0_write(0xE5000000);
+
+    let block = gpu.read_vram_block(0, 0, 128, 32);
+    let pixel = |x: usize, y: usize| block[y * 128 + x] & 0x7FFF;
+    for (x, y) in [(2, 2), (8, 4), (66, 2), (72, 4)] {
+        assert_eq!(pixel(x, y), RED, "({}, {})", x, y);
+    }
+    for (x, y) in [(20, 2), (40, 2), (84, 2), (2, 20)] {
+        assert_eq!(pixel(x, y), 0, "({}, {})", x, y);
+    }
+
+    gpu.end_frame();
+    gpu.end_frame();
+    let stats = gpu.gpu_stats();
+    assert_eq!(stats.draw_calls, 1);
+    assert_eq!(stats.vertices, 8 * 3);
+}
+
+fn draw_red_triangle(gpu: &mut GpuHarness) {
+    gpu.gp0_write(0x200000FF);
+    gpu.gp0_write(0x00000000);
+    gpu.gp0_write(0x00000020);
+    gpu.gp0_write(0x00200000);
+}
+
+#[test]
+fn out_of_memory_is_retried() {
+    let mut gpu = gpu_with_drawing_area();
+    gpu.inject_error(GpuError::OutOfMemory);
+    draw_red_triangle(&mut gpu);
+
+    // retried after waiting for the GPU, nothing is lost
+    let block = gpu.read_vram_block(0, 0, 64, 64);
+    assert_eq!(pixel(&block, 2, 2), RED);
+    assert_eq!(gpu.take_error(), None);
+}
+
+#[test]
+fn device_lost_is_recovered() {
+    let mut gpu = gpu_with_drawing_area();
+    // drawn before the error, lost with the VRAM
+    gpu.gp0_write(0x600000FF);
+    gpu.gp0_write(0x00200020);
+    gpu.gp0_write(0x00080008);
+    gpu.read_vram_block(0, 0, 1, 1);
+
+    gpu.inject_error(GpuError::DeviceLost);
+    draw_red_triangle(&mut gpu);
+    let block = gpu.read_vram_block(0, 0, 64, 64);
+    assert_eq!(gpu.take_error(), Some((GpuError::DeviceLost, true)));
+    assert_eq!(pixel(&block, 2, 2), 0);
+    assert_eq!(pixel(&block, 36, 36), 0);
+
+    // the new context keeps drawing
+    draw_red_triangle(&mut gpu);
+    let block = gpu.read_vram_block(0, 0, 64, 64);
+    assert_eq!(pixel(&block, 2, 2), RED);
+    assert_eq!(gpu.take_error(), None);
+}
+
+const GPUSTAT_DISPLAY_DISABLED: u32 = 1 << 23;
+const GPUSTAT_IRQ: u32 = 1 << 24;
+const I_STAT_GPU: u16 = 1 << 1;
+
+#[test]
+fn interrupt_request() {
+    let mut gpu = gpu_with_drawing_area();
+    gpu.clock(100);
+    assert_eq!(gpu.gpu_stat() & GPUSTAT_IRQ, 0);
+    assert_eq!(gpu.interrupt_stat() & I_STAT_GPU, 0);
+
+    gpu.gp0_write(0x1F000000);
+    assert_ne!(gpu.gpu_stat() & GPUSTAT_IRQ, 0);
+    gpu.clock(100);
+    assert_ne!(gpu.interrupt_stat() & I_STAT_GPU, 0);
+
+    // acknowledge
+    gpu.gp1_write(0x02000000);
+    assert_eq!(gpu.gpu_stat() & GPUSTAT_IRQ, 0);
+    gpu.clock(100);
+    assert_eq!(gpu.gpu_stat() & GPUSTAT_IRQ, 0);
+}
+
+/// The `(R, G, B)` of the pixel at `(x, y)` of a `BGRA8` image
+fn front_pixel(image: &(u32, u32, Vec<u8>), x: u32, y: u32) -> (u8, u8, u8) {
+    let i = ((y * image.0 + x) * 4) as usize;
+    (image.2[i + 2], image.2[i + 1], image.2[i])
+}
+
+#[test]
+fn display_disable() {
+    let mut gpu = gpu_with_drawing_area();
+    draw_red_triangle(&mut gpu);
+    // display enable, the display area is at (0, 0)
+    gpu.gp1_write(0x03000000);
+    gpu.gp1_write(0x05000000);
+    assert_eq!(gpu.gpu_stat() & GPUSTAT_DISPLAY_DISABLED, 0);
+    assert_eq!(gpu.take_front_image(), None);
+
+    gpu.gp1_write(0x03000001);
+    assert_ne!(gpu.gpu_stat() & GPUSTAT_DISPLAY_DISABLED, 0);
+    // requested before disabling
+    let enabled = gpu.take_front_image().unwrap();
+    assert_eq!(front_pixel(&enabled, 2, 2), (0xFF, 0, 0));
+    assert_eq!(&gpu.read_display_rgb()[..3], [0, 0, 0]);
+
+    // drawing continues while disabled
+    gpu.gp0_write(0x6000FF00);
+    gpu.gp0_write(0x00200020);
+    gpu.gp0_write(0x00080008);
+    let disabled = gpu.take_front_image().unwrap();
+    assert_eq!((disabled.0, disabled.1), (enabled.0, enabled.1));
+    assert!(disabled.2.chunks_exact(4).all(|p| p[..3] == [0, 0, 0]));
+    let block = gpu.read_vram_block(0, 0, 64, 64);
+    assert_eq!(pixel(&block, 2, 2), RED);
+    assert_eq!(pixel(&block, 36, 36), 0x03E0);
+
+    // and shown again when enabled
+    gpu.gp1_write(0x03000000);
+    gpu.take_front_image();
+    let enabled = gpu.take_front_image().unwrap();
+    assert_eq!(front_pixel(&enabled, 36, 36), (0, 0xFF, 0));
+}
+
+#[test]
+fn output_format() {
+    let mut gpu = gpu_with_drawing_area();
+    draw_red_triangle(&mut gpu);
+    gpu.gp1_write(0x03000000);
+    gpu.gp1_write(0x05000000);
+    gpu.end_frame();
+    let bgra = gpu.take_front_image().unwrap();
+    assert_eq!(bgra.2[(2 * bgra.0 as usize + 2) * 4..][..3], [0, 0, 0xFF]);
+
+    // the frame requested before still has the old format
+    gpu.set_output_format(Format::R8G8B8A8_UNORM).unwrap();
+    gpu.take_front_image();
+    let rgba = gpu.take_front_image().unwrap();
+    assert_eq!((rgba.0, rgba.1), (bgra.0, bgra.1));
+    assert_eq!(rgba.2[(2 * rgba.0 as usize + 2) * 4..][..3], [0xFF, 0, 0]);
+
+    for format in [Format::D32_SFLOAT, Format::R8_UNORM, Format::R8G8B8A8_UINT] {
+        assert!(matches!(
+            gpu.set_output_format(format),
+            Err(PsxError::UnsupportedOutputFormat(f)) if f == format
+        ));
+    }
+}
+
+#[test]
+fn front_images_are_reused() {
+    let mut gpu = gpu_with_drawing_area();
+    draw_red_triangle(&mut gpu);
+    gpu.gp1_write(0x05000000);
+    for _ in 0..4 {
+        gpu.end_frame();
+    }
+    for _ in 0..10 {
+        gpu.end_frame();
+        assert_eq!(gpu.gpu_stats().front_image_allocations, 0);
+    }
+
+    // a new size needs new images, then they are reused again
+    gpu.gp1_write(0x08000001);
+    gpu.end_frame();
+    gpu.end_frame();
+    assert_eq!(gpu.gpu_stats().front_image_allocations, 1);
+    for _ in 0..4 {
+        gpu.end_frame();
+    }
+    assert_eq!(gpu.gpu_stats().front_image_allocations, 0);
+}
+
+const GPUSTAT_READY_TO_SEND_VRAM: u32 = 1 << 27;
+
+/// The pixels of a `width`x`height` pattern, none of them is `0`
+fn vram_pattern(width: u32, height: u32) -> Vec<u16> {
+    (0..width * height)
+        .map(|i| 0x8000 | (i as u16 + 1) * 0x111)
+        .collect()
+}
+
+/// Pixels packed 2 per word, the first in the low half, the last word of an
+/// odd number of pixels is padded with `0`
+fn pack_pixels(pixels: &[u16]) -> Vec<u32> {
+    pixels
+        .chunks(2)
+        .map(|p| p[0] as u32 | (p.get(1).copied().unwrap_or(0) as u32) << 16)
+        .collect()
+}
+
+/// The odd sizes need a padding word
+const VRAM_TRANSFER_SIZES: [(u32, u32); 4] = [(4, 2), (3, 3), (5, 1), (1, 1)];
+const VRAM_TRANSFER_POSITION: u32 = 50 << 16 | 100;
+
+#[test]
+fn vram_to_cpu_through_gpuread() {
+    for (width, height) in VRAM_TRANSFER_SIZES {
+        let mut gpu = gpu_with_drawing_area();
+        let pattern = vram_pattern(width, height);
+        let words = pack_pixels(&pattern);
+
+        // `CPU to VRAM`, then `VRAM to CPU` of the same rectangle
+        gpu.gp0_write(0xA0000000);
+        gpu.gp0_write(VRAM_TRANSFER_POSITION);
+        gpu.gp0_write(height << 16 | width);
+        for &word in &words {
+            gpu.gp0_write(word);
+        }
+        assert_eq!(gpu.gpu_stat() & GPUSTAT_READY_TO_SEND_VRAM, 0);
+        gpu.gp0_write(0xC0000000);
+        gpu.gp0_write(VRAM_TRANSFER_POSITION);
+        gpu.gp0_write(height << 16 | width);
+
+        let mut read = Vec::new();
+        for _ in 0..words.len() {
+            assert_ne!(
+                gpu.gpu_stat() & GPUSTAT_READY_TO_SEND_VRAM,
+                0,
+                "{}x{}",
+                width,
+                height
+            );
+            read.push(gpu.gpu_read());
+        }
+        // cleared after the padding word, not before it
+        assert_eq!(
+            gpu.gpu_stat() & GPUSTAT_READY_TO_SEND_VRAM,
+            0,
+            "{}x{}",
+            width,
+            height
+        );
+        assert_eq!(read, words, "{}x{}", width, height);
+    }
+}
+
+/// Run DMA2 from the GPU to RAM at `0x100000`, with one block of `words`
+fn dma_gpu_to_ram_exe(words: u32) -> Vec<u8> {
+    common::exe_from_program(&[
+        0x3C081F80,         // lui   t0, 0x1F80
+        0x34090800,         // ori   t1, zero, 0x800
+        0xAD0910F0,         // sw    t1, 0x10F0(t0)    ; DPCR, enable DMA2
+        0x3C090010,         // lui   t1, 0x0010
+        0xAD0910A0,         // sw    t1, 0x10A0(t0)    ; D2_MADR
+        0x3C090001,         // lui   t1, 1
+        0x35290000 | words, // ori   t1, t1, words
+        0xAD0910A4,         // sw    t1, 0x10A4(t0)    ; D2_BCR, 1 block
+        0x3C090100,         // lui   t1, 0x0100
+        0x35290200,         // ori   t1, t1, 0x200
+        0xAD0910A8,         // sw    t1, 0x10A8(t0)    ; D2_CHCR, to RAM, sync mode 1, start
+        // loop:
+        0x0800400B, // j     loop
+        0x00000000, // nop
+    ])
+}
+
+#[test]
+fn vram_to_cpu_through_dma() {
+    for (width, height) in VRAM_TRANSFER_SIZES {
+        let pattern = vram_pattern(width, height);
+        let words = pack_pixels(&pattern);
+        let mut psx = common::hle_psx(dma_gpu_to_ram_exe(words.len() as u32), PsxConfig::builder());
+
+        psx.gpu_gp0_write(0xA0000000);
+        psx.gpu_gp0_write(VRAM_TRANSFER_POSITION);
+        psx.gpu_gp0_write(height << 16 | width);
+        for &word in &words {
+            psx.gpu_gp0_write(word);
+        }
+        psx.gpu_gp0_write(0xC0000000);
+        psx.gpu_gp0_write(VRAM_TRANSFER_POSITION);
+        psx.gpu_gp0_write(height << 16 | width);
+        // DMA direction `GPUREAD to CPU`
+        psx.gpu_gp1_write(0x04000003);
+        let gpu_stat = psx.bus_read_u32(0x1F801814).unwrap();
+        assert_ne!(gpu_stat & GPUSTAT_READY_TO_SEND_VRAM, 0);
+
+        psx.clock_full_video_frame();
+
+        let gpu_stat = psx.bus_read_u32(0x1F801814).unwrap();
+        assert_eq!(gpu_stat & GPUSTAT_READY_TO_SEND_VRAM, 0);
+        // the DMA is done
+        assert_eq!(psx.bus_read_u32(0x1F8010A8).unwrap() & (1 << 24), 0);
+        // one word more, nothing is written after the padding word
+        let read = (0..=words.len() as u32)
+            .map(|i| psx.bus_read_u32(0x80100000 + i * 4).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(read[..words.len()], words, "{}x{}", width, height);
+        assert_eq!(read[words.len()], 0);
+    }
+}
@@ -0,0 +1,187 @@
+//! Screenshot regression tests for the GPU.
+//!
+//! Every `tests/gpu/*.gp` script is a list of GP0/GP1 commands, which are
+//! written directly to the GPU (no BIOS or CPU), then the VRAM `area` of the
+//! script is read and compared with the reference image next to it
+//! (`tests/gpu/<name>.ppm`).
+//!
+//! Script format, one command per line, `#` starts a comment:
+//! - `area <x> <y> <width> <height>`: the VRAM area to compare
+//! - `gp0 <word> <word>...`: words written to GP0, in hex
+//! - `gp1 <word>...`: words written to GP1, in hex
+//!
+//! Run with `TRAPEZOID_BLESS=1` to write the references from the current output.
+#![cfg(feature = "gpu-tests")]
+
+mod common;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use trapezoid_core::testing::GpuHarness;
+use vulkano::device::{Device, Queue};
+
+/// Maximum difference in a color channel for a pixel to be considered the same
+const MAX_CHANNEL_DIFF: u8 = 8;
+/// Ratio of pixels (of the area) that can be different
+const MAX_DIFFERENT_PIXELS_RATIO: f64 = 0.001;
+
+const BLESS_ENV: &str = "TRAPEZOID_BLESS";
+
+struct Image {
+    width: u32,
+    height: u32,
+    rgb: Vec<u8>,
+}
+
+impl Image {
+    fn from_vram(width: u32, height: u32, block: &[u16]) -> Self {
+        let rgb = block
+            .iter()
+            .flat_map(|p| {
+                [p & 0x1F, (p >> 5) & 0x1F, (p >> 10) & 0x1F].map(|c| ((c << 3) | (c >> 2)) as u8)
+            })
+            .collect();
+        Self { width, height, rgb }
+    }
+
+    /// Parse binary `ppm` (`P6`) with `255` max value
+    fn load_ppm(path: &Path) -> Option<Self> {
+        let data = fs::read(path).ok()?;
+        let mut parts = data.splitn(4, |b| b.is_ascii_whitespace());
+        let mut next = || std::str::from_utf8(parts.next()?).ok();
+        if next()? != "P6" {
+            return None;
+        }
+        let width = next()?.parse().ok()?;
+        let height = next()?.parse().ok()?;
+        let rest = parts.next()?;
+        let rest = rest.strip_prefix(b"255\n")?;
+
+        (rest.len() == (width * height * 3) as usize).then(|| Self {
+            width,
+            height,
+            rgb: rest.to_vec(),
+        })
+    }
+
+    fn save_ppm(&self, path: &Path) {
+        let mut data = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        data.extend_from_slice(&self.rgb);
+        fs::write(path, data).unwrap();
+    }
+
+    /// Returns the number of different pixels
+    fn compare(&self, other: &Self) -> usize {
+        assert_eq!((self.width, self.height), (other.width, other.height));
+        self.rgb
+            .chunks_exact(3)
+            .zip(other.rgb.chunks_exact(3))
+            .filter(|(a, b)| {
+                a.iter()
+                    .zip(b.iter())
+                    .any(|(a, b)| a.abs_diff(*b) > MAX_CHANNEL_DIFF)
+            })
+            .count()
+    }
+}
+
+/// Run the script, returns the image of its area
+fn run_script(path: &Path, device: Arc<Device>, queue: Arc<Queue>) -> Image {
+    let script = fs::read_to_string(path).unwrap();
+    let mut gpu = GpuHarness::new(device, queue);
+    let mut area = None;
+
+    for (i, line) in script.lines().enumerate() {
+        let line = line.split('#').next().unwrap();
+        let mut parts = line.split_whitespace();
+        let Some(command) = parts.next() else {
+            continue;
+        };
+        let error = || format!("{}:{}: invalid line {:?}", path.display(), i + 1, line);
+
+        match command {
+            "area" => {
+                let values = parts
+                    .map(|v| v.parse::<u32>().unwrap_or_else(|_| panic!("{}", error())))
+                    .collect::<Vec<_>>();
+                assert_eq!(values.len(), 4, "{}", error());
+                area = Some((values[0], values[1], values[2], values[3]));
+            }
+            "gp0" | "gp1" => {
+                for word in parts {
+                    let word =
+                        u32::from_str_radix(word, 16).unwrap_or_else(|_| panic!("{}", error()));
+                    if command == "gp0" {
+                        gpu.gp0_write(word);
+                    } else {
+                        gpu.gp1_write(word);
+                    }
+                }
+            }
+            _ => panic!("{}", error()),
+        }
+    }
+
+    let (x, y, width, height) =
+        area.unwrap_or_else(|| panic!("{}: missing `area`", path.display()));
+    Image::from_vram(width, height, &gpu.read_vram_block(x, y, width, height))
+}
+
+#[test]
+fn gpu_screenshots() {
+    let bless = std::env::var_os(BLESS_ENV).is_some();
+    let (device, queue) = common::create_device();
+
+    let scripts_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/gpu");
+    let mut scripts = fs::read_dir(&scripts_dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().map(|e| e == "gp").unwrap_or(false))
+        .collect::<Vec<PathBuf>>();
+    scripts.sort();
+    assert!(!scripts.is_empty());
+
+    let mut failures = Vec::new();
+    for script in scripts {
+        let image = run_script(&script, device.clone(), queue.clone());
+        let reference_path = script.with_extension("ppm");
+
+        if bless {
+            image.save_ppm(&reference_path);
+            continue;
+        }
+
+        let Some(reference) = Image::load_ppm(&reference_path) else {
+            failures.push(format!(
+                "{}: missing reference, run with `{}=1` to create it",
+                script.display(),
+                BLESS_ENV
+            ));
+            continue;
+        };
+
+        if (reference.width, reference.height) != (image.width, image.height) {
+            failures.push(format!("{}: size mismatch", script.display()));
+            continue;
+        }
+
+        let different = image.compare(&reference);
+        let max_different = (image.width * image.height) as f64 * MAX_DIFFERENT_PIXELS_RATIO;
+        if different as f64 > max_different {
+            let output_path = script.with_extension("out.ppm");
+            image.save_ppm(&output_path);
+            failures.push(format!(
+                "{}: {} pixels are different, output saved to {}",
+                script.display(),
+                different,
+                output_path.display()
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
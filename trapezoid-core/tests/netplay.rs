@@ -0,0 +1,101 @@
+//! Two netplay sessions connected in memory, running an EXE that reads both
+//! controllers into RAM.
+#![cfg(feature = "gpu-tests")]
+
+mod common;
+
+use trapezoid_core::{
+    netplay::{MemoryTransport, NetplayError, NetplaySession, NetplayStatus},
+    DigitalControllerKey, Psx, PsxConfig,
+};
+
+const FRAMES: u64 = 300;
+const HASH_INTERVAL: u64 = 30;
+
+/// A different pattern for each port, so swapped inputs would desync
+fn scripted_keys(port: usize, frame: u64) -> u16 {
+    let (key, hold) = match port {
+        0 => (DigitalControllerKey::X, 7),
+        _ => (DigitalControllerKey::Circle, 11),
+    };
+    let mut keys = 0;
+    if (frame / hold) % 2 == 1 {
+        keys |= key.mask();
+    }
+    if frame % 50 < 3 {
+        keys |= DigitalControllerKey::Start.mask();
+    }
+    keys
+}
+
+fn new_sessions(
+    psx_a: Psx,
+    psx_b: Psx,
+) -> (
+    NetplaySession<MemoryTransport>,
+    NetplaySession<MemoryTransport>,
+) {
+    let (transport_a, transport_b) = MemoryTransport::pair();
+    let mut a = NetplaySession::new(psx_a, transport_a, 0);
+    let mut b = NetplaySession::new(psx_b, transport_b, 1);
+    a.set_hash_interval(HASH_INTERVAL);
+    b.set_hash_interval(HASH_INTERVAL);
+    (a, b)
+}
+
+/// Poll both sides until they finish `frame`
+fn run_both(
+    a: &mut NetplaySession<MemoryTransport>,
+    b: &mut NetplaySession<MemoryTransport>,
+    frame: u64,
+) -> Result<(), NetplayError> {
+    let mut a_done = false;
+    let mut b_done = false;
+    while !a_done || !b_done {
+        if !a_done {
+            a_done = a.poll_frame(scripted_keys(0, frame))? == NetplayStatus::FrameDone;
+        }
+        if !b_done {
+            b_done = b.poll_frame(scripted_keys(1, frame))? == NetplayStatus::FrameDone;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn lockstep_same_state() {
+    let (mut a, mut b) = new_sessions(
+        common::pad_poll_psx(PsxConfig::builder()),
+        common::pad_poll_psx(PsxConfig::builder()),
+    );
+
+    // `b` didn't send its input yet
+    assert_eq!(
+        a.poll_frame(scripted_keys(0, 0)).unwrap(),
+        NetplayStatus::Waiting
+    );
+    assert_eq!(a.frame(), 0);
+
+    for frame in 0..FRAMES {
+        run_both(&mut a, &mut b, frame).unwrap();
+    }
+    assert_eq!(a.frame(), FRAMES);
+    assert_eq!(b.frame(), FRAMES);
+    assert_eq!(a.psx().state_hash(), b.psx().state_hash());
+}
+
+#[test]
+fn desync_is_detected() {
+    let mut psx_b = common::pad_poll_psx(PsxConfig::builder());
+    // one frame ahead of `a`
+    psx_b.clock_full_video_frame();
+    let (mut a, mut b) = new_sessions(common::pad_poll_psx(PsxConfig::builder()), psx_b);
+
+    let err = (0..FRAMES)
+        .find_map(|frame| run_both(&mut a, &mut b, frame).err())
+        .expect("the desync was not detected");
+    match err {
+        NetplayError::Desync { frame, .. } => assert_eq!(frame, HASH_INTERVAL),
+        err => panic!("unexpected error: {}", err),
+    }
+}
@@ -0,0 +1,41 @@
+//! [`PsxConfig::cpu_overclock`] runs more instructions in the same emulated time,
+//! without changing the video timing.
+#![cfg(all(feature = "gpu-tests", not(feature = "no-perf-counters")))]
+
+mod common;
+
+use trapezoid_core::{cpu::CpuState, PsxConfig};
+
+const FRAMES: u64 = 60;
+
+/// Returns the frames per emulated second, and the instructions executed
+fn run(cpu_overclock: f32) -> (f64, u64) {
+    let mut psx = common::pad_poll_psx(PsxConfig::builder().cpu_overclock(cpu_overclock));
+
+    let mut instructions = 0;
+    for _ in 0..FRAMES {
+        assert_eq!(psx.clock_full_video_frame(), CpuState::Normal);
+        instructions += psx.perf_frame_report().instructions_retired;
+    }
+    let frame_rate = psx.elapsed_frames() as f64 / psx.emulated_time().as_secs_f64();
+    (frame_rate, instructions)
+}
+
+#[test]
+fn overclock_keeps_frame_rate() {
+    let (stock_frame_rate, stock_instructions) = run(1.0);
+    let (frame_rate, instructions) = run(2.0);
+
+    assert!(
+        (frame_rate - stock_frame_rate).abs() < 0.1,
+        "{} != {}",
+        frame_rate,
+        stock_frame_rate
+    );
+    let ratio = instructions as f64 / stock_instructions as f64;
+    assert!(
+        (1.9..2.1).contains(&ratio),
+        "{} times the instructions",
+        ratio
+    );
+}
@@ -0,0 +1,51 @@
+//! The counters of [`Psx::perf_frame_report`] while running a small EXE.
+#![cfg(all(feature = "gpu-tests", not(feature = "no-perf-counters")))]
+
+mod common;
+
+use trapezoid_core::{cpu::CpuState, PerfFrameReport, Psx, PsxConfig};
+
+const CAUSE_SYSCALL: usize = 0x08;
+
+/// Calls `EnterCriticalSection` in a loop
+fn syscall_exe() -> Vec<u8> {
+    common::exe_from_program(&[
+        0x34040001, // ori   a0, zero, 1
+        0x0000000C, // syscall
+        0x08004000, // j     0x80010000
+        0x00000000, // nop
+    ])
+}
+
+fn run_frame(psx: &mut Psx) {
+    loop {
+        let (frame_done, cpu_state) = psx.clock_based_on_video(u32::MAX);
+        assert_eq!(cpu_state, CpuState::Normal);
+        if frame_done {
+            break;
+        }
+    }
+}
+
+#[test]
+fn frame_report() {
+    let mut psx = common::hle_psx(syscall_exe(), PsxConfig::builder());
+
+    let mut total_instructions = 0;
+    for _ in 0..10 {
+        run_frame(&mut psx);
+        let report = psx.perf_frame_report();
+        assert!(report.instructions_retired > 0);
+        assert!(report.exceptions[CAUSE_SYSCALL] > 0);
+        // every loop is one `syscall`
+        assert!(report.exceptions[CAUSE_SYSCALL] as u64 <= report.instructions_retired / 4 + 1);
+
+        // the counts are per frame, so the total keeps growing
+        let new_total = total_instructions + report.instructions_retired;
+        assert!(new_total > total_instructions);
+        total_instructions = new_total;
+
+        // reset on read
+        assert_eq!(psx.perf_frame_report(), PerfFrameReport::default());
+    }
+}
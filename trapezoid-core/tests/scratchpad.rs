@@ -0,0 +1,53 @@
+//! Code that uses the scratchpad as fast RAM, the way games do.
+#![cfg(feature = "gpu-tests")]
+
+mod common;
+
+use trapezoid_core::{cpu::CpuState, PsxConfig};
+
+/// Copies the words after the program into the scratchpad, then copies it byte
+/// by byte through `KSEG0` to `0x80030000`
+const PROGRAM: [u32; 21] = [
+    0x3C088001, // lui   t0, 0x8001
+    0x35080100, // ori   t0, t0, 0x0100    ; the data after the program
+    0x3C091F80, // lui   t1, 0x1F80        ; scratchpad
+    0x240A0100, // addiu t2, zero, 0x100
+    0x8D0B0000, // lw    t3, 0(t0)
+    0x25080004, // addiu t0, t0, 4
+    0xAD2B0000, // sw    t3, 0(t1)
+    0x254AFFFC, // addiu t2, t2, -4
+    0x1540FFFB, // bne   t2, zero, -5
+    0x25290004, // addiu t1, t1, 4
+    0x3C099F80, // lui   t1, 0x9F80        ; scratchpad through KSEG0
+    0x3C088003, // lui   t0, 0x8003
+    0x240A0100, // addiu t2, zero, 0x100
+    0x812B0000, // lb    t3, 0(t1)
+    0x25290001, // addiu t1, t1, 1
+    0xA10B0000, // sb    t3, 0(t0)
+    0x254AFFFF, // addiu t2, t2, -1
+    0x1540FFFB, // bne   t2, zero, -5
+    0x25080001, // addiu t0, t0, 1
+    0x08004013, // j     0x8001004C
+    0x00000000, // nop
+];
+
+#[test]
+fn copy_through_scratchpad() {
+    let data = (0..64u32)
+        .map(|i| i.wrapping_mul(0x9E3779B9))
+        .collect::<Vec<_>>();
+    let mut program = PROGRAM.to_vec();
+    program.resize(0x40, 0);
+    program.extend(&data);
+
+    let mut psx = common::hle_psx(common::exe_from_program(&program), PsxConfig::builder());
+    for _ in 0..2 {
+        assert_eq!(psx.clock_full_video_frame(), CpuState::Normal);
+    }
+
+    for (i, &word) in data.iter().enumerate() {
+        let offset = i as u32 * 4;
+        assert_eq!(psx.bus_read_u32(0x1F800000 + offset).unwrap(), word);
+        assert_eq!(psx.bus_read_u32(0x80030000 + offset).unwrap(), word);
+    }
+}
@@ -0,0 +1,87 @@
+//! A script attached to the emulator running a small EXE in HLE mode.
+#![cfg(all(feature = "gpu-tests", feature = "scripting"))]
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use trapezoid_core::{
+    cpu::{CpuState, RegisterType},
+    scripting::{ScriptEngine, ScriptHandle},
+    Psx, PsxConfig,
+};
+
+const ENTRY: u32 = 0x80010000;
+const COUNTER: u32 = 0x80100000;
+
+/// A `nop`, then loop forever after it
+fn idle_exe() -> Vec<u8> {
+    common::exe_from_program(&[
+        0x00000000, // nop
+        0x08004001, // j ENTRY + 4
+        0x00000000, // nop
+    ])
+}
+
+fn run_frame(psx: &mut Psx) {
+    loop {
+        let (frame_done, cpu_state) = psx.clock_based_on_video(u32::MAX);
+        assert_eq!(cpu_state, CpuState::Normal);
+        if frame_done {
+            break;
+        }
+    }
+}
+
+#[derive(Default)]
+struct Recorded {
+    counter_values: Vec<u32>,
+    breakpoints: Vec<u32>,
+}
+
+/// Increments `COUNTER` every frame
+struct PokeEveryFrame {
+    recorded: Arc<Mutex<Recorded>>,
+}
+
+impl ScriptEngine for PokeEveryFrame {
+    fn on_attach(&mut self, psx: &mut ScriptHandle) {
+        psx.add_breakpoint(ENTRY);
+    }
+
+    fn on_frame(&mut self, psx: &mut ScriptHandle) {
+        let value = psx.bus_read_u32(COUNTER).unwrap();
+        psx.bus_write_u32(COUNTER, value + 1).unwrap();
+        self.recorded.lock().unwrap().counter_values.push(value);
+    }
+
+    fn on_breakpoint(&mut self, psx: &mut ScriptHandle, addr: u32) {
+        assert_eq!(psx.registers().read(RegisterType::Pc), addr);
+        self.recorded.lock().unwrap().breakpoints.push(addr);
+    }
+}
+
+#[test]
+fn poke_every_frame() {
+    let mut psx = common::hle_psx(idle_exe(), PsxConfig::builder());
+
+    let recorded = Arc::new(Mutex::new(Recorded::default()));
+    psx.attach_script_engine(Box::new(PokeEveryFrame {
+        recorded: recorded.clone(),
+    }));
+
+    for _ in 0..5 {
+        run_frame(&mut psx);
+    }
+    assert_eq!(psx.bus_read_u32(COUNTER).unwrap(), 5);
+    {
+        let recorded = recorded.lock().unwrap();
+        assert_eq!(recorded.counter_values, [0, 1, 2, 3, 4]);
+        // the loop is after the breakpoint
+        assert_eq!(recorded.breakpoints, [ENTRY]);
+    }
+
+    assert!(psx.detach_script_engine().is_some());
+    run_frame(&mut psx);
+    assert_eq!(psx.bus_read_u32(COUNTER).unwrap(), 5);
+}
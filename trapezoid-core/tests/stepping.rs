@@ -0,0 +1,125 @@
+//! [`Psx::step`] and [`Psx::step_instruction`] must account for the cycles the
+//! same way as the frame based functions.
+//!
+//! The EXE mixes the timer 2 counter into RAM, so any difference in the timing
+//! shows up in [`Psx::state_hash`].
+#![cfg(feature = "gpu-tests")]
+
+mod common;
+
+use trapezoid_core::{
+    cpu::{CpuState, RegisterType},
+    IdleSkip, Psx, PsxConfig, PsxError,
+};
+
+const FRAMES: u64 = 60;
+
+fn run_frames(psx: &mut Psx, frames: u64) {
+    for _ in 0..frames {
+        assert_eq!(psx.clock_full_video_frame(), CpuState::Normal);
+    }
+}
+
+#[test]
+fn step_unlimited_same_as_frames() {
+    let mut frames_psx = common::pad_poll_psx(PsxConfig::builder());
+    let mut step_psx = common::pad_poll_psx(PsxConfig::builder());
+
+    for frame in 1..=FRAMES {
+        run_frames(&mut frames_psx, 1);
+
+        let result = step_psx.step(u32::MAX);
+        assert_eq!(result.cpu_state, CpuState::Normal);
+        assert!(result.vblank_crossed);
+
+        assert_eq!(step_psx.elapsed_frames(), frame);
+        assert_eq!(step_psx.elapsed_cycles(), frames_psx.elapsed_cycles());
+        assert_eq!(step_psx.state_hash(), frames_psx.state_hash());
+    }
+}
+
+#[test]
+fn step_cycle_accounting() {
+    const MAX_CYCLES: u32 = 1000;
+
+    let mut frames_psx = common::pad_poll_psx(PsxConfig::builder());
+    let mut step_psx = common::pad_poll_psx(PsxConfig::builder());
+
+    run_frames(&mut frames_psx, FRAMES);
+
+    let mut total_cycles = 0;
+    let mut vblanks = 0;
+    let mut audio_samples = 0;
+    while vblanks < FRAMES {
+        let result = step_psx.step(MAX_CYCLES);
+        assert_eq!(result.cpu_state, CpuState::Normal);
+        assert!(result.cycles_executed <= MAX_CYCLES);
+        // only stops early on a vblank
+        assert!(result.cycles_executed == MAX_CYCLES || result.vblank_crossed);
+
+        total_cycles += result.cycles_executed as u64;
+        assert_eq!(step_psx.elapsed_cycles(), total_cycles);
+        if result.vblank_crossed {
+            vblanks += 1;
+        }
+
+        assert!(result.audio_samples_ready >= audio_samples);
+        audio_samples = result.audio_samples_ready;
+    }
+
+    assert_eq!(step_psx.elapsed_frames(), frames_psx.elapsed_frames());
+    // both stop when the vblank starts, but the frames only check between the
+    // chunks of CPU cycles
+    let difference = frames_psx
+        .elapsed_cycles()
+        .abs_diff(step_psx.elapsed_cycles());
+    assert!(difference < 2000, "{} cycles apart", difference);
+
+    // 44100Hz
+    let expected_samples = step_psx.emulated_time().as_secs_f64() * 44100.;
+    assert!((audio_samples as f64 - expected_samples).abs() < 100.);
+    let mut audio = Vec::new();
+    step_psx.take_audio_buffer_into(&mut audio);
+    assert_eq!(audio.len(), audio_samples * 2);
+    assert_eq!(step_psx.step(0).audio_samples_ready, 0);
+}
+
+#[test]
+fn step_instruction_cycle_accounting() {
+    const STEP_FRAMES: u64 = 3;
+
+    let mut frames_psx = common::pad_poll_psx(PsxConfig::builder());
+    let mut step_psx = common::pad_poll_psx(PsxConfig::builder());
+
+    run_frames(&mut frames_psx, STEP_FRAMES);
+
+    let mut total_cycles = 0;
+    let mut vblanks = 0;
+    while vblanks < STEP_FRAMES {
+        let step = step_psx.step_instruction().unwrap();
+        assert_eq!(step.cpu_state, CpuState::Normal);
+        assert!(step.cycles > 0);
+        assert_eq!(step.pc, step_psx.cpu().registers().read(RegisterType::Pc));
+
+        total_cycles += step.cycles as u64;
+        assert_eq!(step_psx.elapsed_cycles(), total_cycles);
+        if step.vblank_crossed {
+            vblanks += 1;
+        }
+    }
+
+    assert_eq!(step_psx.elapsed_frames(), frames_psx.elapsed_frames());
+    let difference = frames_psx
+        .elapsed_cycles()
+        .abs_diff(step_psx.elapsed_cycles());
+    assert!(difference < 2000, "{} cycles apart", difference);
+}
+
+#[test]
+fn step_instruction_needs_idle_skip_off() {
+    let mut psx = common::pad_poll_psx(PsxConfig::builder().idle_skip(IdleSkip::On));
+    assert!(matches!(
+        psx.step_instruction(),
+        Err(PsxError::IdleSkipEnabled)
+    ));
+}
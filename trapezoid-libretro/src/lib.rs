@@ -0,0 +1,559 @@
+//! libretro core for the trapezoid PSX emulator.
+//!
+//! The core renders with Vulkan internally, and gives the frontend the display area
+//! as a software framebuffer (`XRGB8888`). The BIOS is searched in the frontend
+//! system directory, see [`BIOS_FILES`].
+//!
+//! libretro only has one instance of a core, so the emulator and the callbacks are
+//! kept in globals.
+
+mod libretro;
+mod logger;
+
+use std::{
+    ffi::{c_char, c_uint, c_void, CStr},
+    path::{Path, PathBuf},
+    ptr,
+    sync::Mutex,
+};
+
+use libretro::*;
+use trapezoid_core::{DigitalControllerKey, DitherMode, Psx, PsxConfig, Region};
+
+/// A nul terminated string literal, as a C string pointer
+macro_rules! cstr {
+    ($s:expr) => {
+        concat!($s, "\0").as_ptr() as *const c_char
+    };
+}
+
+/// The BIOS files looked for in the system directory, in order
+const BIOS_FILES: &[&str] = &[
+    "scph5501.bin",
+    "scph1001.bin",
+    "scph7001.bin",
+    "scph7501.bin",
+    "scph5500.bin",
+    "scph5502.bin",
+    "scph7502.bin",
+];
+
+const OPTION_FAST_BOOT: *const c_char = cstr!("trapezoid_fast_boot");
+const OPTION_VRAM_VIEW: *const c_char = cstr!("trapezoid_vram_view");
+const OPTION_DITHERING: *const c_char = cstr!("trapezoid_dithering");
+
+const VRAM_WIDTH: u32 = 1024;
+const VRAM_HEIGHT: u32 = 512;
+const SAMPLE_RATE: f64 = 44100.;
+
+/// libretro joypad buttons, with the PSX layout (`B` is the bottom button)
+const KEY_MAP: &[(c_uint, DigitalControllerKey)] = &[
+    (RETRO_DEVICE_ID_JOYPAD_B, DigitalControllerKey::X),
+    (RETRO_DEVICE_ID_JOYPAD_A, DigitalControllerKey::Circle),
+    (RETRO_DEVICE_ID_JOYPAD_Y, DigitalControllerKey::Square),
+    (RETRO_DEVICE_ID_JOYPAD_X, DigitalControllerKey::Triangle),
+    (RETRO_DEVICE_ID_JOYPAD_SELECT, DigitalControllerKey::Select),
+    (RETRO_DEVICE_ID_JOYPAD_START, DigitalControllerKey::Start),
+    (RETRO_DEVICE_ID_JOYPAD_UP, DigitalControllerKey::Up),
+    (RETRO_DEVICE_ID_JOYPAD_DOWN, DigitalControllerKey::Down),
+    (RETRO_DEVICE_ID_JOYPAD_LEFT, DigitalControllerKey::Left),
+    (RETRO_DEVICE_ID_JOYPAD_RIGHT, DigitalControllerKey::Right),
+    (RETRO_DEVICE_ID_JOYPAD_L, DigitalControllerKey::L1),
+    (RETRO_DEVICE_ID_JOYPAD_R, DigitalControllerKey::R1),
+    (RETRO_DEVICE_ID_JOYPAD_L2, DigitalControllerKey::L2),
+    (RETRO_DEVICE_ID_JOYPAD_R2, DigitalControllerKey::R2),
+    (RETRO_DEVICE_ID_JOYPAD_L3, DigitalControllerKey::L3),
+    (RETRO_DEVICE_ID_JOYPAD_R3, DigitalControllerKey::R3),
+];
+
+#[derive(Clone, Copy)]
+struct Callbacks {
+    environment: Option<RetroEnvironmentFn>,
+    video_refresh: Option<RetroVideoRefreshFn>,
+    audio_sample_batch: Option<RetroAudioSampleBatchFn>,
+    input_poll: Option<RetroInputPollFn>,
+    input_state: Option<RetroInputStateFn>,
+    log: Option<RetroLogPrintfFn>,
+}
+
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+    log: None,
+});
+
+struct Core {
+    psx: Psx,
+    vram_view: bool,
+    /// The size last reported to the frontend
+    geometry: (u32, u32),
+    /// The frame rate last reported to the frontend
+    frame_rate: f64,
+    frame: Vec<u32>,
+    /// The output of the emulator, converted into `audio`
+    samples: Vec<f32>,
+    audio: Vec<i16>,
+}
+
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+fn callbacks() -> Callbacks {
+    *CALLBACKS.lock().unwrap()
+}
+
+/// # Safety
+/// `data` must be what the frontend expects for `cmd`
+unsafe fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    match callbacks().environment {
+        Some(environment) => environment(cmd, data),
+        None => false,
+    }
+}
+
+fn get_variable(key: *const c_char) -> Option<String> {
+    let mut variable = RetroVariable {
+        key,
+        value: ptr::null(),
+    };
+    unsafe {
+        if !environment(
+            RETRO_ENVIRONMENT_GET_VARIABLE,
+            &mut variable as *mut _ as *mut c_void,
+        ) || variable.value.is_null()
+        {
+            return None;
+        }
+        CStr::from_ptr(variable.value)
+            .to_str()
+            .ok()
+            .map(str::to_owned)
+    }
+}
+
+fn option_enabled(key: *const c_char) -> bool {
+    get_variable(key).as_deref() == Some("enabled")
+}
+
+fn apply_dithering_option(psx: &mut Psx) {
+    let mut render_options = psx.gpu_render_options();
+    render_options.dithering = match get_variable(OPTION_DITHERING).as_deref() {
+        Some("off") => DitherMode::Off,
+        Some("force on") => DitherMode::ForceOn,
+        _ => DitherMode::Hardware,
+    };
+    psx.set_gpu_render_options(render_options);
+}
+
+fn system_directory() -> Option<PathBuf> {
+    let mut dir: *const c_char = ptr::null();
+    unsafe {
+        if !environment(
+            RETRO_ENVIRONMENT_GET_SYSTEM_DIRECTORY,
+            &mut dir as *mut _ as *mut c_void,
+        ) || dir.is_null()
+        {
+            return None;
+        }
+        CStr::from_ptr(dir).to_str().ok().map(PathBuf::from)
+    }
+}
+
+fn find_bios(system_dir: &Path) -> Option<PathBuf> {
+    BIOS_FILES
+        .iter()
+        .map(|name| system_dir.join(name))
+        .find(|path| path.is_file())
+}
+
+impl Core {
+    fn region(&self) -> Option<Region> {
+        self.psx.disk_region().or(self.psx.bios_info().region)
+    }
+
+    fn is_pal(&self) -> bool {
+        self.region().is_some_and(Region::is_pal)
+    }
+
+    /// Fill `frame` with the image to show, and return its size
+    fn render_frame(&mut self) -> (u32, u32) {
+        self.frame.clear();
+        if self.vram_view {
+            let vram = self.psx.read_vram_block(0, 0, VRAM_WIDTH, VRAM_HEIGHT);
+            self.frame.extend(vram.iter().map(|&c| {
+                // 5 bits to 8 bits
+                let channel = |shift: u16| {
+                    let c = ((c >> shift) & 0x1F) as u32;
+                    (c << 3) | (c >> 2)
+                };
+                (channel(0) << 16) | (channel(5) << 8) | channel(10)
+            }));
+            (VRAM_WIDTH, VRAM_HEIGHT)
+        } else {
+            let (width, height, rgba) = self.psx.read_display_rgba();
+            self.frame.extend(
+                rgba.chunks_exact(4)
+                    .map(|p| ((p[0] as u32) << 16) | ((p[1] as u32) << 8) | p[2] as u32),
+            );
+            (width, height)
+        }
+    }
+
+    fn update_geometry(&mut self, width: u32, height: u32) {
+        if self.geometry == (width, height) {
+            return;
+        }
+        self.geometry = (width, height);
+        let mut info = geometry(width, height);
+        unsafe {
+            environment(
+                RETRO_ENVIRONMENT_SET_GEOMETRY,
+                &mut info as *mut _ as *mut c_void,
+            );
+        }
+    }
+
+    /// The video mode can change while running, e.g. when the BIOS switches to PAL
+    fn update_frame_rate(&mut self) {
+        let frame_rate = self.psx.frame_rate();
+        if self.frame_rate == frame_rate {
+            return;
+        }
+        self.frame_rate = frame_rate;
+        let mut info = self.av_info();
+        unsafe {
+            environment(
+                RETRO_ENVIRONMENT_SET_SYSTEM_AV_INFO,
+                &mut info as *mut _ as *mut c_void,
+            );
+        }
+    }
+
+    fn av_info(&self) -> RetroSystemAvInfo {
+        RetroSystemAvInfo {
+            geometry: geometry(self.geometry.0, self.geometry.1),
+            timing: RetroSystemTiming {
+                fps: self.frame_rate,
+                sample_rate: SAMPLE_RATE,
+            },
+        }
+    }
+
+    fn send_audio(&mut self, batch: RetroAudioSampleBatchFn) {
+        self.samples.clear();
+        self.psx.take_audio_buffer_into(&mut self.samples);
+        self.audio.clear();
+        self.audio.extend(
+            self.samples
+                .iter()
+                .map(|&s| (s.clamp(-1., 1.) * i16::MAX as f32) as i16),
+        );
+
+        let mut frames = &self.audio[..self.audio.len() & !1];
+        while !frames.is_empty() {
+            let written = unsafe { batch(frames.as_ptr(), frames.len() / 2) };
+            if written == 0 {
+                break;
+            }
+            frames = &frames[(written * 2).min(frames.len())..];
+        }
+    }
+}
+
+fn geometry(width: u32, height: u32) -> RetroGameGeometry {
+    RetroGameGeometry {
+        base_width: width,
+        base_height: height,
+        max_width: VRAM_WIDTH,
+        max_height: VRAM_HEIGHT,
+        aspect_ratio: 4. / 3.,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(cb: RetroEnvironmentFn) {
+    CALLBACKS.lock().unwrap().environment = Some(cb);
+
+    let variables = [
+        RetroVariable {
+            key: OPTION_FAST_BOOT,
+            value: cstr!("Fast boot (restart); disabled|enabled"),
+        },
+        RetroVariable {
+            key: OPTION_VRAM_VIEW,
+            value: cstr!("Show full VRAM; disabled|enabled"),
+        },
+        RetroVariable {
+            key: OPTION_DITHERING,
+            value: cstr!("Dithering; hardware|off|force on"),
+        },
+        RetroVariable {
+            key: ptr::null(),
+            value: ptr::null(),
+        },
+    ];
+    // without content, the BIOS shell is booted
+    let mut no_game = true;
+    unsafe {
+        cb(
+            RETRO_ENVIRONMENT_SET_VARIABLES,
+            variables.as_ptr() as *mut c_void,
+        );
+        cb(
+            RETRO_ENVIRONMENT_SET_SUPPORT_NO_GAME,
+            &mut no_game as *mut _ as *mut c_void,
+        );
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(cb: RetroVideoRefreshFn) {
+    CALLBACKS.lock().unwrap().video_refresh = Some(cb);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_cb: RetroAudioSampleFn) {
+    // only the batch callback is used
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(cb: RetroAudioSampleBatchFn) {
+    CALLBACKS.lock().unwrap().audio_sample_batch = Some(cb);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(cb: RetroInputPollFn) {
+    CALLBACKS.lock().unwrap().input_poll = Some(cb);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(cb: RetroInputStateFn) {
+    CALLBACKS.lock().unwrap().input_state = Some(cb);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {
+    let mut log_callback = RetroLogCallback { log: None };
+    unsafe {
+        environment(
+            RETRO_ENVIRONMENT_GET_LOG_INTERFACE,
+            &mut log_callback as *mut _ as *mut c_void,
+        );
+    }
+    CALLBACKS.lock().unwrap().log = log_callback.log;
+    logger::init();
+}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    *CORE.lock().unwrap() = None;
+}
+
+/// # Safety
+/// `info` must be a valid pointer
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    *info = RetroSystemInfo {
+        library_name: cstr!("Trapezoid"),
+        library_version: cstr!(env!("CARGO_PKG_VERSION")),
+        valid_extensions: cstr!("cue|exe"),
+        need_fullpath: true,
+        block_extract: false,
+    };
+}
+
+/// # Safety
+/// `info` must be a valid pointer
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    // only called after `retro_load_game`, which always creates the core
+    if let Some(core) = CORE.lock().unwrap().as_ref() {
+        *info = core.av_info();
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(port: c_uint, device: c_uint) {
+    if port >= 2 {
+        return;
+    }
+    if let Some(core) = CORE.lock().unwrap().as_mut() {
+        core.psx
+            .set_controller_connected(port as usize, device == RETRO_DEVICE_JOYPAD);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    if let Some(core) = CORE.lock().unwrap().as_mut() {
+        if let Err(e) = core.psx.reset() {
+            log::error!("Could not load the disk after the reset: {}", e);
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let callbacks = callbacks();
+    let mut core = CORE.lock().unwrap();
+    let Some(core) = core.as_mut() else {
+        return;
+    };
+
+    let mut updated = false;
+    unsafe {
+        environment(
+            RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE,
+            &mut updated as *mut _ as *mut c_void,
+        );
+    }
+    if updated {
+        core.vram_view = option_enabled(OPTION_VRAM_VIEW);
+        apply_dithering_option(&mut core.psx);
+    }
+
+    if let (Some(poll), Some(state)) = (callbacks.input_poll, callbacks.input_state) {
+        unsafe { poll() };
+        for port in 0..2 {
+            for &(id, key) in KEY_MAP {
+                let pressed = unsafe { state(port, RETRO_DEVICE_JOYPAD, 0, id) } != 0;
+                core.psx
+                    .change_port_controller_key_state(port as usize, key, pressed);
+            }
+        }
+    }
+
+    // the other states are only for the debugger, which is not enabled
+    core.psx.clock_full_video_frame();
+
+    let (width, height) = core.render_frame();
+    core.update_geometry(width, height);
+    core.update_frame_rate();
+    if let Some(video_refresh) = callbacks.video_refresh {
+        unsafe {
+            video_refresh(
+                core.frame.as_ptr() as *const c_void,
+                width,
+                height,
+                width as usize * 4,
+            )
+        };
+    }
+
+    if let Some(batch) = callbacks.audio_sample_batch {
+        core.send_audio(batch);
+    }
+}
+
+// TODO: implement when the core supports save states
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize(_data: *mut c_void, _size: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unserialize(_data: *const c_void, _size: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+/// # Safety
+/// `game` must be `NULL` or a valid pointer
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    let mut pixel_format = RETRO_PIXEL_FORMAT_XRGB8888;
+    if !environment(
+        RETRO_ENVIRONMENT_SET_PIXEL_FORMAT,
+        &mut pixel_format as *mut _ as *mut c_void,
+    ) {
+        log::error!("XRGB8888 is not supported by the frontend");
+        return false;
+    }
+
+    let Some(bios) = system_directory().as_deref().and_then(find_bios) else {
+        log::error!(
+            "No BIOS found in the system directory, expected one of: {}",
+            BIOS_FILES.join(", ")
+        );
+        return false;
+    };
+
+    let disk = game
+        .as_ref()
+        .filter(|game| !game.path.is_null())
+        .and_then(|game| CStr::from_ptr(game.path).to_str().ok())
+        .map(PathBuf::from);
+
+    let config = PsxConfig::builder()
+        .fast_boot(option_enabled(OPTION_FAST_BOOT))
+        .build();
+    let mut psx = match Psx::new_headless(Some(bios), disk, config) {
+        Ok(psx) => psx,
+        Err(e) => {
+            log::error!("{}", e);
+            return false;
+        }
+    };
+    apply_dithering_option(&mut psx);
+
+    *CORE.lock().unwrap() = Some(Core {
+        frame_rate: psx.frame_rate(),
+        psx,
+        vram_view: option_enabled(OPTION_VRAM_VIEW),
+        geometry: (640, 480),
+        frame: Vec::new(),
+        samples: Vec::new(),
+        audio: Vec::new(),
+    });
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const RetroGameInfo,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    *CORE.lock().unwrap() = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    if CORE.lock().unwrap().as_ref().is_some_and(Core::is_pal) {
+        RETRO_REGION_PAL
+    } else {
+        RETRO_REGION_NTSC
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(_id: c_uint) -> *mut c_void {
+    ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(_id: c_uint) -> usize {
+    0
+}
@@ -0,0 +1,48 @@
+//! Forward the `log` messages of the emulator to the frontend log interface
+
+use std::ffi::{c_char, CString};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::{
+    callbacks,
+    libretro::{RETRO_LOG_DEBUG, RETRO_LOG_ERROR, RETRO_LOG_INFO, RETRO_LOG_WARN},
+};
+
+struct RetroLogger;
+
+static LOGGER: RetroLogger = RetroLogger;
+
+impl Log for RetroLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Info
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let Some(printf) = callbacks().log else {
+            return;
+        };
+        let level = match record.level() {
+            Level::Error => RETRO_LOG_ERROR,
+            Level::Warn => RETRO_LOG_WARN,
+            Level::Info => RETRO_LOG_INFO,
+            Level::Debug | Level::Trace => RETRO_LOG_DEBUG,
+        };
+        let Ok(message) = CString::new(format!("[trapezoid] {}\n", record.args())) else {
+            return;
+        };
+        unsafe { printf(level, "%s\0".as_ptr() as *const c_char, message.as_ptr()) };
+    }
+
+    fn flush(&self) {}
+}
+
+pub fn init() {
+    // fails if already set, by a previous `retro_init`
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(LevelFilter::Info);
+    }
+}